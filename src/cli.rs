@@ -1,8 +1,30 @@
-use clap::{Parser, Subcommand};
+use crate::diff::LineRange;
+use clap::{ArgGroup, Args, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(name = "gh-agent", about = "Agent-friendly GitHub CLI for PR reviews")]
 pub struct Cli {
+    /// Log every GitHub API request (method, URL, status, size, timing) to stderr
+    #[arg(long, global = true)]
+    pub verbose: bool,
+    /// Cap the number of GitHub API requests; fetches past the cap are skipped
+    /// (partial results) and writes fail before posting anything
+    #[arg(long, global = true, value_name = "N")]
+    pub max_requests: Option<usize>,
+    /// Page text output even when stdout isn't a terminal
+    #[arg(long, global = true, conflicts_with = "no_pager")]
+    pub paginate: bool,
+    /// Never page output (by default text output goes through $PAGER on a terminal)
+    #[arg(long, global = true)]
+    pub no_pager: bool,
+    /// In JSON output, replace code (file contents, patches, match text, suggestion
+    /// lines) with a hash and length; paths, line numbers and counts are kept
+    #[arg(long, global = true)]
+    pub redact_content: bool,
+    /// Color diffs, stat tables and search matches: auto (on a terminal, unless
+    /// NO_COLOR is set), always, or never. JSON output is never colored.
+    #[arg(long, global = true, value_name = "WHEN", default_value = "auto", value_parser = ["auto", "always", "never"])]
+    pub color: String,
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -14,10 +36,122 @@ pub enum Commands {
         #[command(subcommand)]
         command: PrCommands,
     },
+    /// Persistent per-repo default flags
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+/// A pending review: invisible to others until submitted, so comments can be
+/// added one at a time as files are read
+#[derive(Subcommand)]
+pub enum ReviewCommands {
+    /// Open a pending review on the PR head
+    Start {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Start even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Add a comment to the pending review
+    Add {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// File path
+        #[arg(short, long)]
+        file: String,
+        /// Line in the head version of the file (the last line for a range)
+        #[arg(long)]
+        line: u64,
+        /// First line of a multi-line comment
+        #[arg(long)]
+        start_line: Option<u64>,
+        /// Comment text
+        #[arg(short, long)]
+        body: String,
+        /// Add even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Submit the pending review
+    Submit {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        #[arg(long, default_value = "COMMENT", value_parser = ["COMMENT", "APPROVE", "REQUEST_CHANGES"])]
+        event: String,
+        /// Review summary
+        #[arg(short, long)]
+        body: Option<String>,
+        /// Submit even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Delete the pending review and its comments
+    Abandon {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Set a default flag for a repo (e.g. `diff.all true`, `grep.context 3`)
+    Set {
+        #[arg(short, long)]
+        repo: String,
+        /// Key in <command>.<flag> form
+        key: String,
+        /// Value (true/false for switches)
+        value: String,
+    },
+    /// Print a repo default
+    Get {
+        #[arg(short, long)]
+        repo: String,
+        /// Key in <command>.<flag> form
+        key: String,
+    },
+    /// Remove a repo default
+    Unset {
+        #[arg(short, long)]
+        repo: String,
+        /// Key in <command>.<flag> form
+        key: String,
+    },
+    /// List repo defaults (all repos unless --repo is given)
+    List {
+        #[arg(short, long)]
+        repo: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
 pub enum PrCommands {
+    /// Run one pr subcommand for several PRs at once, printing a JSON line per PR
+    /// (`pr each -r R --numbers 12,15 -- view --json`)
+    Each {
+        #[arg(short, long)]
+        repo: String,
+        /// PR numbers, comma-separated; without it they're read from stdin
+        #[arg(long, value_delimiter = ',')]
+        numbers: Vec<u64>,
+        /// How many PRs to work on at once
+        #[arg(long, value_name = "N", default_value_t = 4)]
+        concurrency: usize,
+        /// The subcommand and its flags, after `--`, without the PR number or --repo
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        args: Vec<String>,
+    },
     /// One-stop PR overview: metadata, file stats, optional semantic summary
     View {
         /// PR number
@@ -31,128 +165,1060 @@ pub enum PrCommands {
         /// Smart categorized review guide (uses sem beforeContent/afterContent)
         #[arg(long)]
         smart: bool,
+        /// Only changed files CODEOWNERS assigns to this owner (@me, @user, or org/team)
+        #[arg(long, value_name = "OWNER")]
+        owned_by: Option<String>,
+        /// Order the stat table (and JSON files) by path, or by line counts, largest first
+        #[arg(long, value_name = "KEY", value_parser = ["path", "additions", "deletions", "total"])]
+        sort: Option<String>,
+        /// Append a +++--- bar to each file in the stat table
+        #[arg(long, conflicts_with = "json")]
+        graph: bool,
         /// Output as JSON
         #[arg(long)]
         json: bool,
     },
-    /// Line-numbered unified diff
-    Diff {
+    /// Changed file paths, one per line
+    Files {
         /// PR number
         number: u64,
         #[arg(short, long)]
         repo: String,
-        /// Filter to specific files (substring match, repeatable)
+        /// Include lock/generated/minified files
+        #[arg(long)]
+        all: bool,
+        /// Only files with this status (repeatable)
+        #[arg(long, value_parser = ["added", "modified", "removed", "renamed", "copied"])]
+        status: Vec<String>,
+        /// Output as JSON (path, status, additions, deletions)
+        #[arg(long)]
+        json: bool,
+    },
+    /// List PRs, most recently updated first
+    List {
         #[arg(short, long)]
-        file: Vec<String>,
-        /// Only show diffs for files with meaningful changes (auto-skips mechanical)
+        repo: String,
+        /// PR state
+        #[arg(long, default_value = "open", value_parser = ["open", "closed", "all"])]
+        state: String,
+        /// Filter by author login
         #[arg(long)]
-        smart_files: bool,
-        /// Include lock files, generated files, and other noise (excluded by default)
+        author: Option<String>,
+        /// Filter by label (repeatable, all must match)
         #[arg(long)]
-        all: bool,
-        /// Only show the stat table (no diff content)
+        label: Vec<String>,
+        /// Filter by base branch
+        #[arg(long)]
+        base: Option<String>,
+        /// Maximum number of PRs to list
+        #[arg(short = 'L', long, default_value = "30")]
+        limit: usize,
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Line-numbered unified diff
+    Diff(Box<DiffArgs>),
+    /// CI status for the PR head commit
+    Checks {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Only show checks that haven't passed
+        #[arg(long)]
+        failed: bool,
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the commits in the PR, oldest first
+    Commits {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Also fetch per-commit file counts and line totals
         #[arg(long)]
         stat: bool,
-        /// Output JSON with commentable lines map
+        /// Mark commit pairs where a later commit exactly undoes an earlier one
+        #[arg(long)]
+        detect_reverts: bool,
+        /// Output as JSON (full sha and message)
         #[arg(long)]
         json: bool,
     },
-    /// Read a file at the PR branch state
-    File {
+    /// List the PR commits that changed one file, or diff it between two of them
+    FileHistory {
         /// PR number
         number: u64,
         #[arg(short, long)]
         repo: String,
-        /// File path within the repo
+        /// File path
         #[arg(short, long)]
         path: String,
+        /// Diff the file between revisions I and J (numbers from the listing)
+        #[arg(long, value_name = "I..J")]
+        diff: Option<String>,
+        /// Output as JSON (full shas; with --diff, the raw patch)
+        #[arg(long)]
+        json: bool,
     },
-    /// Post batch review comments from a JSON file
-    Review {
+    /// List inline review comments, grouped by file and thread
+    Comments {
         /// PR number
         number: u64,
         #[arg(short, long)]
         repo: String,
-        /// Path to JSON file with comments array
+        /// Filter to specific files (substring match, repeatable)
         #[arg(short, long)]
-        comments_file: String,
+        file: Vec<String>,
+        /// Only show unresolved threads
+        #[arg(long)]
+        unresolved: bool,
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
     },
-    /// Text search across PR files (or full repo at PR branch)
-    Grep {
+    /// List submitted reviews and their states (APPROVED, CHANGES_REQUESTED, ...)
+    Reviews {
         /// PR number
         number: u64,
         #[arg(short, long)]
         repo: String,
-        /// Search pattern (text)
+        /// Only each reviewer's most recent review (what the review decision is based on)
+        #[arg(long)]
+        latest_per_user: bool,
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Export metadata, checks, smart analysis and review threads as one markdown document
+    Report {
+        /// PR number
+        number: u64,
         #[arg(short, long)]
-        pattern: String,
-        /// Filter to specific files (substring match, repeatable)
+        repo: String,
+        /// Write the report to this file instead of stdout
         #[arg(short, long)]
-        file: Vec<String>,
-        /// Search the entire repo via GitHub Code Search + PR changed files
+        out: Option<String>,
+        /// Embed each file's diff in a collapsed section (cut at 20 KB per file)
         #[arg(long)]
-        repo_wide: bool,
-        /// Optional path prefix to narrow --repo-wide results (e.g. "src/")
+        include_diff: bool,
+    },
+    /// Files ranked by where to look first, with the factors behind each score
+    Priorities {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Output as JSON
         #[arg(long)]
-        path: Option<String>,
-        /// Search base branch instead of head
+        json: bool,
+    },
+    /// What changed in the smart analysis since the last recorded revision of this PR
+    SmartDiff {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Output as JSON
         #[arg(long)]
-        base: bool,
-        /// Case-sensitive search
+        json: bool,
+    },
+    /// Merge readiness: mergeability, review decision, requested reviewers, checks
+    Status {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Re-query up to N times while GitHub is still computing mergeability
+        #[arg(long, value_name = "N", default_value_t = 3)]
+        poll: u32,
+        /// Exit nonzero unless the PR is ready to merge
         #[arg(long)]
-        case_sensitive: bool,
-        /// Lines of context around matches (like grep -C)
-        #[arg(short = 'C', long, default_value = "0")]
-        context: usize,
-        /// Include lock/generated/minified files
+        fail_if_blocked: bool,
+        /// Output as JSON
         #[arg(long)]
-        all: bool,
+        json: bool,
     },
-    /// AST structural search across PR files (or full repo via Code Search)
-    AstGrep {
+    /// Files changed both in the PR and on base since the merge base; exits nonzero on conflicts
+    Conflicts {
         /// PR number
         number: u64,
         #[arg(short, long)]
         repo: String,
-        /// AST pattern (e.g. "console.log($$$)")
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Approve the PR (a review with no inline comments)
+    Approve {
+        /// PR number
+        number: u64,
         #[arg(short, long)]
-        pattern: String,
-        /// Filter to specific files (substring match, repeatable)
+        repo: String,
+        /// Review body
         #[arg(short, long)]
-        file: Vec<String>,
-        /// Search the entire repo via GitHub Code Search + PR changed files
+        body: Option<String>,
+        /// Post even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Request changes on the PR (a review with no inline comments)
+    RequestChanges {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Review body
+        #[arg(short, long)]
+        body: Option<String>,
+        /// Post even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Reply in an existing review thread
+    Reply {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Any comment in the thread (a reply is followed up to the thread's first comment)
+        #[arg(long, required_unless_present = "file", conflicts_with = "file")]
+        comment_id: Option<u64>,
+        /// Find the thread on this file (with --line)
+        #[arg(long, requires = "line")]
+        file: Option<String>,
+        /// Find the thread on this line (with --file)
+        #[arg(long, requires = "file")]
+        line: Option<u64>,
+        /// Reply text
+        #[arg(short, long)]
+        body: String,
+        /// Post even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Dismiss a submitted review, e.g. a stale request for changes that blocks merging
+    DismissReview {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Review to dismiss (ids are in `pr reviews --json`)
+        #[arg(long, required_unless_present = "mine", conflicts_with = "mine")]
+        review_id: Option<u64>,
+        /// Dismiss your own latest CHANGES_REQUESTED review
         #[arg(long)]
-        repo_wide: bool,
-        /// Optional path prefix to narrow --repo-wide results (e.g. "src/")
+        mine: bool,
+        /// Why it's dismissed (GitHub requires a message)
+        #[arg(short, long)]
+        message: String,
+        /// Dismiss even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Resolve a review thread (or unresolve it with --undo); prints the thread's state as JSON
+    Resolve {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Thread node id (as `pr resolve` lists them when a line is ambiguous)
+        #[arg(long, required_unless_present = "file", conflicts_with = "file")]
+        thread_id: Option<String>,
+        /// Find the thread on this file (with --line)
+        #[arg(long, requires = "line")]
+        file: Option<String>,
+        /// Find the thread on this line (with --file)
+        #[arg(long, requires = "file")]
+        line: Option<u64>,
+        /// Unresolve instead
         #[arg(long)]
-        path: Option<String>,
-        /// Search base branch instead of head
+        undo: bool,
+        /// Resolve even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Add or remove labels, or list them with their colors
+    Label {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Label to add (repeatable)
+        #[arg(long, value_name = "LABEL")]
+        add: Vec<String>,
+        /// Label to remove (repeatable)
+        #[arg(long, value_name = "LABEL")]
+        remove: Vec<String>,
+        /// Print the PR's labels (after any changes)
         #[arg(long)]
-        base: bool,
-        /// Language override (auto-detected from extension by default)
+        list: bool,
+        /// Change labels even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Assign or unassign people and set the milestone
+    Assign {
+        /// PR number
+        number: u64,
         #[arg(short, long)]
-        lang: Option<String>,
-        /// Include lock/generated/minified files
+        repo: String,
+        /// Login to assign (repeatable)
+        #[arg(long, value_name = "LOGIN")]
+        add: Vec<String>,
+        /// Login to unassign (repeatable)
+        #[arg(long, value_name = "LOGIN")]
+        remove: Vec<String>,
+        /// Milestone title to set; "" clears it
+        #[arg(long, value_name = "TITLE")]
+        milestone: Option<String>,
+        /// Output as JSON
         #[arg(long)]
-        all: bool,
+        json: bool,
+        /// Assign even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
     },
-    /// Post a suggestion comment (GitHub suggestion block)
-    Suggest {
+    /// Request or remove reviewers, and list who is requested or has reviewed
+    Reviewers {
         /// PR number
         number: u64,
         #[arg(short, long)]
         repo: String,
-        /// File path
+        /// User to request a review from (repeatable)
+        #[arg(long, value_name = "USER")]
+        add: Vec<String>,
+        /// Team to request a review from, as org/team (repeatable)
+        #[arg(long, value_name = "ORG/TEAM")]
+        add_team: Vec<String>,
+        /// User or org/team whose review request to withdraw (repeatable)
+        #[arg(long, value_name = "REVIEWER")]
+        remove: Vec<String>,
+        /// Print requested reviewers and each reviewer's latest verdict (after any changes)
+        #[arg(long)]
+        list: bool,
+        /// Change requests even if the PR is already merged or closed
+        #[arg(long, visible_alias = "force")]
+        allow_closed: bool,
+    },
+    /// Fetch the PR head (fork PRs too) and check it out locally
+    Checkout {
+        /// PR number
+        number: u64,
         #[arg(short, long)]
-        file: String,
-        /// Start line
+        repo: String,
+        /// Local branch to create or reset (default: the PR's head branch name)
+        #[arg(short, long)]
+        branch_name: Option<String>,
+        /// Check out the head commit without a branch
+        #[arg(long, conflicts_with = "branch_name")]
+        detach: bool,
+        /// Discard uncommitted changes and reset the branch even if it has other commits
+        #[arg(long)]
+        force: bool,
+    },
+    /// Mark a draft PR ready for review
+    Ready {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+    },
+    /// Convert the PR back to a draft
+    Draft {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+    },
+    /// Close the PR, optionally explaining why in a comment
+    Close {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Conversation comment to post before closing
+        #[arg(short, long)]
+        comment: Option<String>,
+        /// Delete the head branch after closing
+        #[arg(long)]
+        delete_branch: bool,
+    },
+    /// Reopen a closed PR
+    Reopen {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+    },
+    /// Bring the PR's branch up to date with its base ("Update branch")
+    UpdateBranch {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Rebase onto the base instead of merging it in
+        #[arg(long)]
+        rebase: bool,
+    },
+    /// Merge the PR and print the merge commit SHA
+    Merge {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// Merge strategy
+        #[arg(long, default_value = "merge", value_parser = ["merge", "squash", "rebase"])]
+        method: String,
+        /// Commit title (defaults to GitHub's)
+        #[arg(long)]
+        subject: Option<String>,
+        /// Commit message body (defaults to GitHub's)
         #[arg(long)]
-        line_start: u64,
-        /// End line (same as start for single-line)
+        body: Option<String>,
+        /// Delete the head branch after merging
         #[arg(long)]
-        line_end: u64,
-        /// Replacement code
+        delete_branch: bool,
+        /// Merge even if the PR is a draft or conflicts with its base
         #[arg(long)]
-        replacement: String,
+        force: bool,
     },
+    /// Read a file at the PR branch state
+    File {
+        /// PR number
+        number: u64,
+        #[arg(short, long)]
+        repo: String,
+        /// File path within the repo
+        #[arg(short, long)]
+        path: String,
+        /// `markdown` prints the file in a code block fenced for its language,
+        /// instead of JSON
+        #[arg(long, value_name = "FORMAT", default_value = "json", value_parser = ["json", "markdown"])]
+        format: String,
+        /// Truncate output to about N bytes (on a character boundary)
+        #[arg(long, value_name = "N", conflicts_with = "max_tokens")]
+        max_bytes: Option<usize>,
+        /// Truncate output to about N estimated LLM tokens
+        #[arg(long, value_name = "N")]
+        max_tokens: Option<usize>,
+    },
+    /// Post batch review comments from a JSON file, or build a review up over time
+    /// with the start/add/submit subcommands
+    Review(Box<ReviewArgs>),
+    /// Text search across PR files (or full repo at PR branch)
+    Grep(Box<GrepArgs>),
+    /// AST structural search across PR files (or full repo via Code Search)
+    AstGrep(Box<AstGrepArgs>),
+    /// Post a suggestion comment (GitHub suggestion block)
+    Suggest(Box<SuggestArgs>),
+}
+
+impl PrCommands {
+    /// Whether the command prints JSON, by flag or always, so it's never paged
+    /// or colored
+    pub fn prints_json(&self) -> bool {
+        match self {
+            PrCommands::Each { .. }
+            | PrCommands::Approve { .. }
+            | PrCommands::RequestChanges { .. }
+            | PrCommands::Resolve { .. }
+            | PrCommands::Close { .. }
+            | PrCommands::Reopen { .. }
+            | PrCommands::Merge { .. }
+            | PrCommands::UpdateBranch { .. } => true,
+            PrCommands::View { json, .. }
+            | PrCommands::Files { json, .. }
+            | PrCommands::List { json, .. }
+            | PrCommands::Checks { json, .. }
+            | PrCommands::Commits { json, .. }
+            | PrCommands::FileHistory { json, .. }
+            | PrCommands::Comments { json, .. }
+            | PrCommands::Reviews { json, .. }
+            | PrCommands::Priorities { json, .. }
+            | PrCommands::SmartDiff { json, .. }
+            | PrCommands::Status { json, .. }
+            | PrCommands::Conflicts { json, .. }
+            | PrCommands::Assign { json, .. } => *json,
+            PrCommands::Diff(diff) => diff.json || diff.json_full,
+            PrCommands::File { format, .. } => format == "json",
+            // Posting prints the review as JSON; only a plain --dry-run lists it as text
+            PrCommands::Review(review) => review.post.as_ref().is_some_and(|post| post.json || !post.dry_run),
+            // --suggest prints the posted review, or the refusal, as JSON
+            PrCommands::AstGrep(ast_grep) => ast_grep.json || ast_grep.suggest,
+            PrCommands::Suggest(suggest) => suggest.json || !suggest.preview,
+            PrCommands::Report { .. }
+            | PrCommands::Reply { .. }
+            | PrCommands::DismissReview { .. }
+            | PrCommands::Label { .. }
+            | PrCommands::Reviewers { .. }
+            | PrCommands::Checkout { .. }
+            | PrCommands::Ready { .. }
+            | PrCommands::Draft { .. }
+            | PrCommands::Grep(_) => false,
+        }
+    }
+}
+
+/// `pr diff`'s flags
+#[derive(Args)]
+pub struct DiffArgs {
+    /// PR number
+    pub number: u64,
+    #[arg(short, long)]
+    pub repo: String,
+    /// Filter to specific files (repeatable). A plain word matches anywhere in the
+    /// path; one with *, ? or / is a glob over the whole path ("src/**/*.ts"),
+    /// and one ending in / takes everything under that directory
+    #[arg(short, long)]
+    pub file: Vec<String>,
+    /// Filter to paths this regex matches anywhere in (repeatable; combines with --file)
+    #[arg(long, value_name = "REGEX")]
+    pub file_regex: Vec<String>,
+    /// Leave out paths matching this glob, after every other filter (e.g.
+    /// "**/*.snap", "tests/**"; repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+    /// Only show diffs for files with meaningful changes (auto-skips mechanical)
+    #[arg(long)]
+    pub smart_files: bool,
+    /// Only changed files CODEOWNERS assigns to this owner (@me, @user, or org/team)
+    #[arg(long, value_name = "OWNER")]
+    pub owned_by: Option<String>,
+    /// Include lock files, generated files, and other noise (excluded by default)
+    #[arg(long)]
+    pub all: bool,
+    /// Also treat as noise files whose large, one-sided change reads like machine
+    /// output (minified, snapshots, hash lists)
+    #[arg(long)]
+    pub auto_noise: bool,
+    /// Only show the stat table (no diff content)
+    #[arg(long)]
+    pub stat: bool,
+    /// Order the stat table by path, or by line counts, largest first
+    #[arg(long, value_name = "KEY", value_parser = ["path", "additions", "deletions", "total"], requires = "stat")]
+    pub sort: Option<String>,
+    /// Append a +++--- bar to each file in the stat table
+    #[arg(long, requires = "stat")]
+    pub graph: bool,
+    /// Output JSON with commentable lines map
+    #[arg(long)]
+    pub json: bool,
+    /// `patch` prints a plain unified diff that `git apply` takes, and
+    /// `markdown` a stat table and a collapsed section per file, instead of
+    /// the line-numbered view
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "numbered",
+        value_parser = ["numbered", "patch", "markdown"],
+        conflicts_with_all = ["stat", "json", "json_full", "name_only", "name_status", "word_diff", "ignore_whitespace", "max_lines_per_file", "max_total_lines", "max_bytes", "max_tokens"]
+    )]
+    pub format: String,
+    /// Output JSON with every file's status, line counts and hunks, line by
+    /// line, plus the commentable lines map
+    #[arg(long, conflicts_with_all = ["json", "stat"])]
+    pub json_full: bool,
+    /// Only list the paths the diff would show, one per line (an array of
+    /// {path, status} with --json)
+    #[arg(long, conflicts_with_all = ["stat", "json_full", "name_status"])]
+    pub name_only: bool,
+    /// Only list the paths the diff would show with a status letter (A, M, D, R)
+    #[arg(long, conflicts_with_all = ["stat", "json_full"])]
+    pub name_status: bool,
+    /// Only this hunk, by the id shown in hunk headers (repeatable; overrides file filters)
+    #[arg(long, value_name = "ID")]
+    pub hunk: Vec<String>,
+    /// Only the changes at these lines of the new file, with two lines of context
+    /// around them: N, N-M or N- for N to the end (repeatable; needs a --file
+    /// that picks exactly one file)
+    #[arg(long, value_name = "RANGE", value_parser = parse_line_range, conflicts_with_all = ["stat", "name_only", "name_status"])]
+    pub lines: Vec<LineRange>,
+    /// Shortest run of deleted lines re-added elsewhere in the file to mark as moved (0 = off)
+    #[arg(long, value_name = "N", default_value = "5")]
+    pub min_move_lines: usize,
+    /// Hide the added copy of moved blocks behind a one-line marker
+    #[arg(long)]
+    pub collapse_moves: bool,
+    /// Mark the words that changed between a deleted line and the added line
+    /// replacing it, as [-old-]/{+new+} (plain) or in red/green (color)
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        default_missing_value = "plain",
+        value_parser = ["plain", "color"],
+        conflicts_with_all = ["json", "json_full", "stat"]
+    )]
+    pub word_diff: Option<String>,
+    /// For hunks whose header names no enclosing function, find the nearest
+    /// fn/def/function/func line above them in the file (one request per file)
+    #[arg(long, conflicts_with = "stat")]
+    pub resolve_context: bool,
+    /// Recompute modified files' diffs ignoring indentation, spacing and blank
+    /// lines, and hide files that only changed whitespace (two requests per file)
+    #[arg(long, conflicts_with = "hunk")]
+    pub ignore_whitespace: bool,
+    /// Diff from this commit instead of the merge base (e.g. the commit you
+    /// last reviewed); only the PR's changed files are shown
+    #[arg(long, value_name = "SHA")]
+    pub from: Option<String>,
+    /// Diff to this commit instead of the PR head
+    #[arg(long, value_name = "SHA")]
+    pub to: Option<String>,
+    /// Only what changed since your last submitted review of the PR (the
+    /// full diff, with a warning, if you haven't reviewed it)
+    #[arg(long, conflicts_with_all = ["from", "to", "since"])]
+    pub since_review: bool,
+    /// Only what changed since the last PR commit authored before TIME
+    /// (YYYY-MM-DD or YYYY-MM-DDTHH:MM[:SS]Z, UTC)
+    #[arg(long, value_name = "TIME", conflicts_with_all = ["from", "to"])]
+    pub since: Option<String>,
+    /// Also list the commit pairs that cancel out and so aren't in the diff
+    /// (one request per commit)
+    #[arg(long, conflicts_with_all = ["from", "to", "since_review", "since"])]
+    pub net_only: bool,
+    /// Write each file's diff to its own file in DIR (created if missing), with
+    /// an index.json listing them, instead of printing
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["stat", "json", "json_full", "name_only", "name_status", "max_total_lines", "max_bytes", "max_tokens"])]
+    pub out_dir: Option<String>,
+    /// With --out-dir, first remove the files an earlier run's index.json there lists
+    #[arg(long, requires = "out_dir")]
+    pub clean: bool,
+    /// Show at most N lines of each file's diff, cut between hunks where possible
+    #[arg(long, value_name = "N", conflicts_with_all = ["json", "json_full", "stat"])]
+    pub max_lines_per_file: Option<usize>,
+    /// Show at most N diff lines in all; later files are only counted
+    #[arg(long, value_name = "N", conflicts_with_all = ["json", "json_full", "stat"])]
+    pub max_total_lines: Option<usize>,
+    /// Truncate output to about N bytes (on a character boundary)
+    #[arg(long, value_name = "N", conflicts_with = "max_tokens")]
+    pub max_bytes: Option<usize>,
+    /// Truncate output to about N estimated LLM tokens
+    #[arg(long, value_name = "N")]
+    pub max_tokens: Option<usize>,
+}
+
+/// `pr review`'s flags
+#[derive(Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct ReviewArgs {
+    #[command(subcommand)]
+    pub action: Option<ReviewCommands>,
+    /// Set exactly when there's no subcommand
+    #[command(flatten)]
+    pub post: Option<PostReviewArgs>,
+}
+
+/// `pr review`'s flags for posting a comments file
+#[derive(Args)]
+pub struct PostReviewArgs {
+    /// PR number
+    pub number: u64,
+    #[arg(short, long)]
+    pub repo: String,
+    /// JSON review file (an object with comments, or a bare comments array); - reads stdin
+    #[arg(short, long)]
+    pub comments_file: String,
+    /// Review body, replacing the comments file's
+    #[arg(short, long, conflicts_with = "body_file")]
+    pub body: Option<String>,
+    /// Read the review body (markdown) from a file, replacing the comments file's;
+    /// - reads stdin
+    #[arg(long, value_name = "FILE")]
+    pub body_file: Option<String>,
+    /// Build the review body from a template file, filling in {{pr_title}},
+    /// {{changed_files}}, {{comment_count}}, {{severity_counts}} and {{smart_summary}}
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["body", "body_file"])]
+    pub template: Option<String>,
+    /// Extra template placeholder (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", requires = "template")]
+    pub template_vars: Vec<String>,
+    /// Write suggestion blocks to a git-applyable patch file instead of posting
+    #[arg(long, value_name = "FILE")]
+    pub emit_patch: Option<String>,
+    /// If GitHub rejects specific comments (422), drop them and post the rest
+    #[arg(long)]
+    pub retry_without_invalid: bool,
+    /// Split reviews with more comments than this into several submissions
+    #[arg(long, visible_alias = "batch-size", value_name = "N", default_value_t = 50)]
+    pub chunk_size: usize,
+    /// Longest comment or review body to send, in characters (GitHub rejects the
+    /// whole review over 65536)
+    #[arg(long, value_name = "N", default_value_t = crate::commands::MAX_BODY_CHARS)]
+    pub max_comment_chars: usize,
+    /// Cut over-long comments and review bodies down to --max-comment-chars instead
+    /// of failing (comments with a suggestion are never cut)
+    #[arg(long)]
+    pub truncate_long_comments: bool,
+    /// Move a comment whose line isn't in the diff to the nearest commentable line
+    /// at most N lines away (ties go to the later line)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub fuzz: u64,
+    /// Cut a multi-line comment that spans hunks down to the part in its last line's
+    /// hunk, instead of skipping it (suggestions are still skipped)
+    #[arg(long)]
+    pub split_ranges: bool,
+    /// Post through REST, or through GraphQL review threads, which can sit on any
+    /// line of a changed file, not just lines in the diff
+    #[arg(long, default_value = "rest", value_parser = ["rest", "graphql"])]
+    pub via: String,
+    /// Add the comments to your latest review of the PR (as single comments on
+    /// its commit) instead of opening a new review
+    #[arg(long, conflicts_with_all = ["body", "body_file", "template", "emit_patch", "idempotency_key", "json"])]
+    pub amend: bool,
+    /// Post comments on lock, generated and vendored files too (skipped by default)
+    #[arg(long)]
+    pub allow_noise_comments: bool,
+    /// Post comments even where the same comment already exists on that line
+    #[arg(long)]
+    pub allow_duplicates: bool,
+    /// Fail instead of warning when a suggestion changes nothing or targets lines
+    /// outside the diff
+    #[arg(long)]
+    pub strict: bool,
+    /// Leave out comments whose `severity` is below this (comments without one are kept)
+    #[arg(long, value_name = "SEVERITY", value_parser = ["blocker", "warning", "nit", "praise"])]
+    pub min_severity: Option<String>,
+    /// Check the comments file strictly and report every problem, without contacting GitHub
+    #[arg(long)]
+    pub validate_only: bool,
+    /// Fetch the diff and check every comment against it, then show what would be posted
+    /// instead of posting; exits nonzero if any comment would be skipped
+    #[arg(long, conflicts_with = "emit_patch")]
+    pub dry_run: bool,
+    /// Output {review_id, url, posted, skipped} as JSON, each skipped comment with a
+    /// reason code (with --dry-run: {valid, skipped}). Exits 2 when every comment
+    /// was skipped
+    #[arg(long, conflicts_with = "emit_patch")]
+    pub json: bool,
+    /// Show a summary of what will be posted and ask before posting (declining
+    /// exits 4; without a terminal to ask on, it declines)
+    #[arg(long, conflicts_with_all = ["emit_patch", "dry_run"])]
+    pub confirm: bool,
+    /// Answer yes to --confirm without asking (the summary is still shown)
+    #[arg(long, requires = "confirm")]
+    pub yes: bool,
+    /// Skip posting if a review carrying this key already exists (safe retries)
+    #[arg(long, value_name = "KEY")]
+    pub idempotency_key: Option<String>,
+    /// Post even if the PR is already merged or closed
+    #[arg(long, visible_alias = "force")]
+    pub allow_closed: bool,
+}
+
+/// `pr grep`'s flags
+#[derive(Args)]
+pub struct GrepArgs {
+    /// PR number
+    pub number: u64,
+    #[arg(short, long)]
+    pub repo: String,
+    /// Search pattern (text)
+    #[arg(short, long)]
+    pub pattern: String,
+    /// Filter to specific files (repeatable). A plain word matches anywhere in the
+    /// path; one with *, ? or / is a glob over the whole path ("src/**/*.ts"),
+    /// and one ending in / takes everything under that directory
+    #[arg(short, long)]
+    pub file: Vec<String>,
+    /// Filter to paths this regex matches anywhere in (repeatable; combines with --file)
+    #[arg(long, value_name = "REGEX")]
+    pub file_regex: Vec<String>,
+    /// Leave out paths matching this glob, after every other filter (e.g.
+    /// "**/*.snap", "tests/**"; repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+    /// Search the entire repo via GitHub Code Search + PR changed files
+    #[arg(long)]
+    pub repo_wide: bool,
+    /// Path prefix to narrow --repo-wide results (e.g. "src/"; repeatable)
+    #[arg(long)]
+    pub path: Vec<String>,
+    /// Path prefix to leave out of --repo-wide results (repeatable)
+    #[arg(long, value_name = "PREFIX")]
+    pub exclude_path: Vec<String>,
+    /// Only --repo-wide results in this language (Code Search's language:)
+    #[arg(long)]
+    pub language: Option<String>,
+    /// Extra Code Search qualifier for --repo-wide, e.g. "-filename:mock.go" (repeatable)
+    #[arg(long, value_name = "KEY:VALUE")]
+    pub search_qualifier: Vec<String>,
+    /// Search base branch instead of head
+    #[arg(long, conflicts_with = "git_ref")]
+    pub base: bool,
+    /// Read files at this branch, tag or SHA instead of the PR head
+    #[arg(long = "ref", value_name = "REF")]
+    pub git_ref: Option<String>,
+    /// Search these paths (one per line; "-" reads stdin) instead of the PR's changed files
+    #[arg(long, value_name = "PATH")]
+    pub files_from: Option<String>,
+    /// --files-from entries are NUL-separated (as from find -print0)
+    #[arg(long, requires = "files_from")]
+    pub null: bool,
+    /// Case-sensitive search
+    #[arg(long)]
+    pub case_sensitive: bool,
+    /// Lines of context around matches (like grep -C)
+    #[arg(short = 'C', long, default_value = "0")]
+    pub context: usize,
+    /// Only changed files CODEOWNERS assigns to this owner (@me, @user, or org/team)
+    #[arg(long, value_name = "OWNER")]
+    pub owned_by: Option<String>,
+    /// Include lock/generated/minified files
+    #[arg(long)]
+    pub all: bool,
+    /// Truncate output to about N bytes (on a character boundary)
+    #[arg(long, value_name = "N", conflicts_with = "max_tokens")]
+    pub max_bytes: Option<usize>,
+    /// Truncate output to about N estimated LLM tokens
+    #[arg(long, value_name = "N")]
+    pub max_tokens: Option<usize>,
+}
+
+/// `pr ast-grep`'s flags
+#[derive(Args)]
+pub struct AstGrepArgs {
+    /// PR number
+    pub number: u64,
+    #[arg(short, long)]
+    pub repo: String,
+    /// AST pattern (e.g. "console.log($$$)")
+    #[arg(short, long)]
+    pub pattern: String,
+    /// Filter to specific files (repeatable). A plain word matches anywhere in the
+    /// path; one with *, ? or / is a glob over the whole path ("src/**/*.ts"),
+    /// and one ending in / takes everything under that directory
+    #[arg(short, long)]
+    pub file: Vec<String>,
+    /// Filter to paths this regex matches anywhere in (repeatable; combines with --file)
+    #[arg(long, value_name = "REGEX")]
+    pub file_regex: Vec<String>,
+    /// Leave out paths matching this glob, after every other filter (e.g.
+    /// "**/*.snap", "tests/**"; repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+    /// Search the entire repo via GitHub Code Search + PR changed files
+    #[arg(long)]
+    pub repo_wide: bool,
+    /// Path prefix to narrow --repo-wide results (e.g. "src/"; repeatable)
+    #[arg(long)]
+    pub path: Vec<String>,
+    /// Path prefix to leave out of --repo-wide results (repeatable)
+    #[arg(long, value_name = "PREFIX")]
+    pub exclude_path: Vec<String>,
+    /// Only --repo-wide results in this language (Code Search's language:)
+    #[arg(long)]
+    pub language: Option<String>,
+    /// Extra Code Search qualifier for --repo-wide, e.g. "-filename:mock.go" (repeatable)
+    #[arg(long, value_name = "KEY:VALUE")]
+    pub search_qualifier: Vec<String>,
+    /// Search base branch instead of head
+    #[arg(long, conflicts_with = "git_ref")]
+    pub base: bool,
+    /// Read files at this branch, tag or SHA instead of the PR head
+    #[arg(long = "ref", value_name = "REF")]
+    pub git_ref: Option<String>,
+    /// Search these paths (one per line; "-" reads stdin) instead of the PR's changed files
+    #[arg(long, value_name = "PATH")]
+    pub files_from: Option<String>,
+    /// --files-from entries are NUL-separated (as from find -print0)
+    #[arg(long, requires = "files_from")]
+    pub null: bool,
+    /// Language override (auto-detected from extension by default)
+    #[arg(short, long)]
+    pub lang: Option<String>,
+    /// Only changed files CODEOWNERS assigns to this owner (@me, @user, or org/team)
+    #[arg(long, value_name = "OWNER")]
+    pub owned_by: Option<String>,
+    /// Include lock/generated/minified files
+    #[arg(long)]
+    pub all: bool,
+    /// Show at most N lines of each multi-line match
+    #[arg(long, value_name = "N", default_value_t = crate::search::DEFAULT_MAX_MATCH_LINES)]
+    pub max_match_lines: usize,
+    /// Output matches as JSON, with each match's exact text and its dedented display text
+    #[arg(long)]
+    pub json: bool,
+    /// Rewrite each match with this template ($VAR for what a metavariable
+    /// matched) and show it before and after
+    #[arg(long, value_name = "TEMPLATE")]
+    pub rewrite: Option<String>,
+    /// Post the rewrites of matches on the PR's diff lines as suggestions, in one
+    /// review (files the PR doesn't change are never posted to)
+    #[arg(long, requires = "rewrite", conflicts_with_all = ["base", "git_ref", "json"])]
+    pub suggest: bool,
+    /// With --suggest, post even if the PR is already merged or closed
+    #[arg(long, visible_alias = "force", requires = "suggest")]
+    pub allow_closed: bool,
+}
+
+/// `pr suggest`'s flags
+#[derive(Args)]
+#[command(group(ArgGroup::new("batch").args(["from_local", "sed"])))]
+pub struct SuggestArgs {
+    /// PR number
+    pub number: u64,
+    #[arg(short, long)]
+    pub repo: String,
+    /// File path
+    #[arg(short, long)]
+    pub file: String,
+    /// Set exactly when neither --from-local nor --sed is
+    #[command(flatten)]
+    pub lines: Option<SuggestLines>,
+    /// Replacement code
+    #[arg(
+        long,
+        required_unless_present_any = ["replacement_file", "delete", "from_local", "sed"],
+        conflicts_with_all = ["replacement_file", "delete"]
+    )]
+    pub replacement: Option<String>,
+    /// Read the replacement code from a file (- for stdin), verbatim apart from
+    /// one trailing newline
+    #[arg(long, value_name = "FILE", conflicts_with = "delete")]
+    pub replacement_file: Option<String>,
+    /// Suggest deleting the lines (same as an empty --replacement)
+    #[arg(long, conflicts_with = "match_indent")]
+    pub delete: bool,
+    /// Why the change is suggested, posted above the suggestion block
+    #[arg(long, conflicts_with = "comment_file")]
+    pub comment: Option<String>,
+    /// Read the --comment text from a file (- for stdin)
+    #[arg(long, value_name = "FILE")]
+    pub comment_file: Option<String>,
+    /// Review body (default: "Suggestion from gh-agent")
+    #[arg(long)]
+    pub review_body: Option<String>,
+    /// Write the suggestion to a git-applyable patch file instead of posting
+    #[arg(long, value_name = "FILE")]
+    pub emit_patch: Option<String>,
+    /// Quote the surrounding diff lines in a collapsed block above the suggestion
+    #[arg(long)]
+    pub include_context: bool,
+    /// Fail instead of warning when the suggestion changes nothing or targets lines
+    /// outside the diff
+    #[arg(long)]
+    pub strict: bool,
+    /// Skip posting if a review carrying this key already exists (safe retries)
+    #[arg(long, value_name = "KEY")]
+    pub idempotency_key: Option<String>,
+    /// Post even if the PR is already merged or closed
+    #[arg(long)]
+    pub allow_closed: bool,
+    /// Post even if the lines aren't all in the diff within one hunk, or the PR is
+    /// merged or closed
+    #[arg(long)]
+    pub force: bool,
+    /// Re-indent the replacement to the replaced lines' indentation
+    #[arg(long)]
+    pub match_indent: bool,
+    /// Show the change "Apply suggestion" would make to the file at the PR head, as
+    /// a diff, instead of posting
+    #[arg(long, conflicts_with_all = ["emit_patch", "idempotency_key"])]
+    pub preview: bool,
+    /// With --preview, output {before, after, diff} as JSON
+    #[arg(long, requires = "preview")]
+    pub json: bool,
+    /// Suggest the changes that turn --file at the PR head into this local copy,
+    /// one suggestion per changed run of lines, posted as one review
+    #[arg(
+        long,
+        value_name = "LOCAL_PATH",
+        conflicts_with_all = [
+            "line_start", "line_end", "replacement", "replacement_file", "delete",
+            "match_indent", "include_context", "emit_patch", "preview", "idempotency_key",
+        ]
+    )]
+    pub from_local: Option<String>,
+    /// Make a sed-style s/PATTERN/REPLACEMENT/[gi] substitution (\1 for a group,
+    /// & for the match) on the lines of --file in the PR's diff, one suggestion per
+    /// run of changed lines, posted as one review
+    #[arg(
+        long,
+        value_name = "EXPR",
+        conflicts_with_all = [
+            "line_start", "line_end", "replacement", "replacement_file", "delete",
+            "match_indent", "include_context", "emit_patch", "preview", "idempotency_key", "from_local",
+        ]
+    )]
+    pub sed: Option<String>,
+    /// With --from-local or --sed, list the suggestions (and the changes that
+    /// can't be suggested) instead of posting
+    #[arg(long, requires = "batch")]
+    pub dry_run: bool,
+}
+
+/// The lines `pr suggest` replaces
+#[derive(Args)]
+pub struct SuggestLines {
+    /// Start line
+    #[arg(long, required = false, required_unless_present_any = ["from_local", "sed"])]
+    pub line_start: u64,
+    /// End line (same as start for single-line)
+    #[arg(long, required = false, required_unless_present_any = ["from_local", "sed"])]
+    pub line_end: u64,
+}
+
+/// A `--lines` range: `N`, `N-M` or `N-`, lines counted from 1
+fn parse_line_range(s: &str) -> Result<LineRange, String> {
+    let line = |n: &str| match n.trim().parse::<u64>() {
+        Ok(0) | Err(_) => Err(format!("expected N, N-M or N- with line numbers from 1, got {s:?}")),
+        Ok(n) => Ok(n),
+    };
+    let range = match s.split_once('-') {
+        None => LineRange { start: line(s)?, end: Some(line(s)?) },
+        Some((start, "")) => LineRange { start: line(start)?, end: None },
+        Some((start, end)) => LineRange { start: line(start)?, end: Some(line(end)?) },
+    };
+    if range.end.is_some_and(|end| end < range.start) {
+        return Err(format!("{s:?} ends before it starts"));
+    }
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prints_json(args: &[&str]) -> bool {
+        let cli = Cli::try_parse_from([&["gh-agent", "pr"], args].concat()).unwrap();
+        let Commands::Pr { command } = cli.command else {
+            unreachable!()
+        };
+        command.prints_json()
+    }
+
+    #[test]
+    fn test_prints_json() {
+        // By flag
+        assert!(prints_json(&["view", "1", "-r", "o/r", "--json"]));
+        assert!(!prints_json(&["view", "1", "-r", "o/r"]));
+        assert!(prints_json(&["diff", "1", "-r", "o/r", "--json-full"]));
+        // Always, or by default
+        assert!(prints_json(&["file", "1", "-r", "o/r", "-p", "a.rs"]));
+        assert!(!prints_json(&["file", "1", "-r", "o/r", "-p", "a.rs", "--format", "markdown"]));
+        assert!(prints_json(&["resolve", "1", "-r", "o/r", "--thread-id", "T"]));
+        assert!(prints_json(&["merge", "1", "-r", "o/r"]));
+        assert!(prints_json(&["ast-grep", "1", "-r", "o/r", "-p", "$A", "--rewrite", "$A", "--suggest"]));
+        assert!(!prints_json(&["ast-grep", "1", "-r", "o/r", "-p", "$A"]));
+        assert!(prints_json(&["review", "1", "-r", "o/r", "-c", "c.json"]));
+        assert!(!prints_json(&["review", "1", "-r", "o/r", "-c", "c.json", "--dry-run"]));
+        assert!(!prints_json(&["review", "submit", "1", "-r", "o/r"]));
+        assert!(!prints_json(&["grep", "1", "-r", "o/r", "--pattern", "x"]));
+    }
+
+    #[test]
+    fn test_parse_line_range() {
+        assert_eq!(parse_line_range("480-520"), Ok(LineRange { start: 480, end: Some(520) }));
+        assert_eq!(parse_line_range("12"), Ok(LineRange { start: 12, end: Some(12) }));
+        assert_eq!(parse_line_range("480-"), Ok(LineRange { start: 480, end: None }));
+        for bad in ["", "0", "-5", "a-b", "5-x", "1-2-3"] {
+            assert!(parse_line_range(bad).is_err(), "{bad}");
+        }
+        assert_eq!(parse_line_range("9-3").unwrap_err(), "\"9-3\" ends before it starts");
+    }
 }
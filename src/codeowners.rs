@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::HashSet;
+
+/// Where GitHub looks for a CODEOWNERS file, in priority order
+pub const CODEOWNERS_PATHS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+struct Rule {
+    matchers: Vec<GlobMatcher>,
+    owners: Vec<String>,
+}
+
+/// Parsed CODEOWNERS file. Rules use gitignore-style patterns; the last
+/// matching rule wins, so later rules override earlier owners.
+pub struct Codeowners {
+    rules: Vec<Rule>,
+}
+
+impl Codeowners {
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (i, raw) in content.lines().enumerate() {
+            let line = raw.split_once(" #").map_or(raw, |(l, _)| l).trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let pattern = parts.next().unwrap_or_default();
+            let owners = parts.map(normalize_owner).collect();
+            let matchers = pattern_globs(pattern)
+                .into_iter()
+                .map(|g| {
+                    GlobBuilder::new(&g)
+                        .literal_separator(true)
+                        .build()
+                        .map(|g| g.compile_matcher())
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("CODEOWNERS line {}: invalid pattern {pattern}", i + 1))?;
+            rules.push(Rule { matchers, owners });
+        }
+        Ok(Self { rules })
+    }
+
+    /// Owners of `path` (normalized, see `normalize_owner`). Empty if unowned.
+    pub fn owners_of(&self, path: &str) -> &[String] {
+        self.rules
+            .iter()
+            .rev()
+            .find(|r| r.matchers.iter().any(|m| m.is_match(path)))
+            .map(|r| r.owners.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Whether any of `owners` owns `path`
+    pub fn is_owned_by(&self, path: &str, owners: &HashSet<String>) -> bool {
+        self.owners_of(path).iter().any(|o| owners.contains(o))
+    }
+}
+
+/// Translate a CODEOWNERS pattern into equivalent globs over repo-relative paths
+fn pattern_globs(pattern: &str) -> Vec<String> {
+    let dir_only = pattern.ends_with('/');
+    let trimmed = pattern.trim_end_matches('/');
+    // A leading or interior slash anchors the pattern to the repo root
+    let anchored = trimmed.contains('/');
+    let base = trimmed.trim_start_matches('/');
+    let base = if anchored || base.starts_with("**") {
+        base.to_string()
+    } else {
+        format!("**/{base}")
+    };
+
+    if dir_only {
+        vec![format!("{base}/**")]
+    } else if base.rsplit('/').next().is_some_and(|last| last.contains(['*', '?', '['])) {
+        // A wildcard names files at its own level only: `docs/*` owns docs/a.md
+        // but not docs/a/b.md
+        vec![base]
+    } else {
+        // Matches the path itself, or everything under it when it names a directory
+        vec![base.clone(), format!("{base}/**")]
+    }
+}
+
+/// Canonical owner form for comparisons: lowercase, `@`-prefixed unless it's an email
+pub fn normalize_owner(owner: &str) -> String {
+    let owner = owner.to_lowercase();
+    if owner.starts_with('@') || owner.contains('@') {
+        owner
+    } else {
+        format!("@{owner}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "\
+# Default owners
+*                       @org/core
+*.md                    @org/docs  # docs team
+
+/src/payments/          @org/payments
+/src/payments/legacy/   @alice
+docs/                   @org/docs
+build                   @org/infra
+";
+
+    #[test]
+    fn test_later_rule_overrides() {
+        let co = Codeowners::parse(FILE).unwrap();
+        assert_eq!(co.owners_of("src/main.rs"), ["@org/core"]);
+        assert_eq!(co.owners_of("src/payments/api.rs"), ["@org/payments"]);
+        assert_eq!(co.owners_of("src/payments/legacy/v1.rs"), ["@alice"]);
+        // *.md is earlier than the payments rule, so payments wins inside it
+        assert_eq!(co.owners_of("src/payments/README.md"), ["@org/payments"]);
+        assert_eq!(co.owners_of("README.md"), ["@org/docs"]);
+    }
+
+    #[test]
+    fn test_anchoring() {
+        let co = Codeowners::parse(FILE).unwrap();
+        // `docs/` has a trailing slash only, so it matches at any depth
+        assert_eq!(co.owners_of("pkg/docs/guide.txt"), ["@org/docs"]);
+        // `/src/payments/` is anchored to the root
+        assert_eq!(co.owners_of("vendor/src/payments/x.rs"), ["@org/core"]);
+        // `build` matches a file or directory anywhere
+        assert_eq!(co.owners_of("tools/build/run.sh"), ["@org/infra"]);
+        assert_eq!(co.owners_of("build"), ["@org/infra"]);
+    }
+
+    #[test]
+    fn test_wildcards_stay_at_their_level() {
+        let co = Codeowners::parse("*  @org/core\ndocs/*  @org/docs\n/apps/*.js  @org/apps\nlogs/  @org/ops\n").unwrap();
+        // `*` alone still matches at any depth
+        assert_eq!(co.owners_of("src/deep/main.rs"), ["@org/core"]);
+        assert_eq!(co.owners_of("docs/a.md"), ["@org/docs"]);
+        assert_eq!(co.owners_of("docs/guide/b.md"), ["@org/core"]);
+        assert_eq!(co.owners_of("apps/x.js"), ["@org/apps"]);
+        assert_eq!(co.owners_of("apps/web/x.js"), ["@org/core"]);
+        // A trailing slash takes everything under the directory, wherever it is
+        assert_eq!(co.owners_of("logs/a/b.txt"), ["@org/ops"]);
+        assert_eq!(co.owners_of("svc/logs/today.txt"), ["@org/ops"]);
+        // ...but not a file with the directory's name
+        assert_eq!(co.owners_of("logs"), ["@org/core"]);
+    }
+
+    #[test]
+    fn test_is_owned_by() {
+        let co = Codeowners::parse(FILE).unwrap();
+        let me: HashSet<String> = ["@Alice", "@org/Payments"].iter().map(|o| normalize_owner(o)).collect();
+        assert!(co.is_owned_by("src/payments/api.rs", &me));
+        assert!(co.is_owned_by("src/payments/legacy/v1.rs", &me));
+        assert!(!co.is_owned_by("src/main.rs", &me));
+        assert_eq!(normalize_owner("team/payments"), "@team/payments");
+        assert_eq!(normalize_owner("Dev@Example.com"), "dev@example.com");
+    }
+}
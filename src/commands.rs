@@ -1,12 +1,32 @@
 use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::diff::{commentable_lines, parse_patch};
+use crate::budget::{self, Limit};
+use crate::codeowners::{normalize_owner, Codeowners, CODEOWNERS_PATHS};
+use crate::config::{self, Config};
+use crate::diff::{self, commentable_lines, hunk_comment_range, identified_hunks, parse_patch, DiffHunk};
 use crate::format;
+use crate::generated;
+use crate::git;
 use crate::github::{self, CreateReview, ReviewCommentInput};
+use crate::history::{self, SmartRecord};
+use crate::owners::{self, OwnersFile, OwnersTree, Ownership};
+use crate::pager::{self, out, outln};
+use crate::patch::{self, Suggestion};
+use crate::pending;
+use crate::priority;
+use crate::redact;
+use crate::report;
+use crate::review_input;
 use crate::search;
+use crate::sed;
 use crate::sem;
+use crate::severity::Severity;
 
 // --- Output types for JSON ---
 
@@ -23,606 +43,9755 @@ struct PrViewJson {
     deletions: u64,
     changed_files: u64,
     files: Vec<FileStatJson>,
+    /// Sums over `files`
+    totals: TotalsJson,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    smart: Option<Vec<sem::SmartChange>>,
 }
 
 #[derive(Serialize)]
-struct FileStatJson {
-    path: String,
-    status: String,
+struct PrListJson {
+    number: u64,
+    title: String,
+    body: Option<String>,
+    state: String,
+    author: String,
+    head_sha: String,
+    head_ref: String,
+    base_ref: String,
     additions: u64,
     deletions: u64,
+    changed_files: u64,
+    updated_at: String,
 }
 
+/// `pr comments --json`
 #[derive(Serialize)]
-struct DiffJson {
-    files: HashMap<String, Vec<u64>>,
+struct CommentsJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    comments: Vec<ReviewCommentJson>,
 }
 
 #[derive(Serialize)]
-struct FileOut {
+struct ReviewCommentJson {
+    id: u64,
+    thread_id: u64,
     path: String,
-    content: String,
-    lines: usize,
+    line: Option<u64>,
+    outdated: bool,
+    author: String,
+    created_at: String,
+    body: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    suggestions: Vec<SuggestionJson>,
 }
 
 #[derive(Serialize)]
-struct ReviewOut {
+struct SuggestionJson {
+    /// The lines the suggestion replaces; null when they couldn't be read
+    original_lines: Option<Vec<String>>,
+    suggested_lines: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct AstMatchJson {
+    file: String,
+    line: usize,
+    end_line: usize,
+    column: usize,
+    /// The matched node's source, exactly
+    text: String,
+    /// `text` re-indented as in the file and dedented, as the text output shows it
+    display: String,
+    /// What `--rewrite` turns `text` into
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rewrite: Option<String>,
+}
+
+impl From<&search::SearchMatch> for AstMatchJson {
+    fn from(m: &search::SearchMatch) -> Self {
+        AstMatchJson {
+            file: m.file.clone(),
+            line: m.line,
+            end_line: search::end_line(m),
+            column: m.column,
+            text: m.text.clone(),
+            display: search::dedent_match(&m.text, &m.lead).join("\n"),
+            rewrite: m.rewrite.as_ref().map(|r| r.text.clone()),
+        }
+    }
+}
+
+/// `pr reviews --json`
+#[derive(Serialize)]
+struct ReviewsJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    reviews: Vec<ReviewJson>,
+}
+
+#[derive(Serialize)]
+struct ReviewJson {
     id: u64,
+    author: String,
+    state: String,
+    submitted_at: Option<String>,
+    body: String,
     url: String,
 }
 
-#[derive(Deserialize)]
-struct CommentInput {
-    path: String,
-    line: u64,
-    body: String,
-    #[serde(default)]
-    start_line: Option<u64>,
+#[derive(Serialize)]
+struct SmartDiffJson<'a> {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: &'a str,
+    from: &'a str,
+    to: &'a str,
+    #[serde(flatten)]
+    diff: &'a history::SmartDiff,
 }
 
-#[derive(Deserialize)]
-struct ReviewInput {
-    #[serde(default = "default_body")]
-    body: String,
-    comments: Vec<CommentInput>,
+#[derive(Serialize)]
+struct StatusJson {
+    number: u64,
+    title: String,
+    state: String,
+    is_draft: bool,
+    mergeable: String,
+    merge_state_status: String,
+    review_decision: Option<String>,
+    requested_reviewers: Vec<String>,
+    head_sha: String,
+    checks: StatusChecksJson,
+    ready: bool,
+    blockers: Vec<String>,
 }
 
-fn default_body() -> String {
-    "Review from gh-agent".to_string()
+#[derive(Serialize)]
+struct StatusChecksJson {
+    state: Option<String>,
+    passed: usize,
+    failed: usize,
+    pending: usize,
+    failing: Vec<String>,
 }
 
-fn print_json<T: Serialize>(value: &T) -> Result<()> {
-    println!("{}", serde_json::to_string_pretty(value)?);
-    Ok(())
+#[derive(Serialize)]
+struct ConflictsJson {
+    number: u64,
+    state: String,
+    base: String,
+    head: String,
+    merge_base: String,
+    mergeable: String,
+    merge_state_status: String,
+    conflicting: bool,
+    files: Vec<ConflictFileJson>,
 }
 
-// --- Noise file filtering ---
+/// A path changed both in the PR and on base since the merge base
+#[derive(Serialize)]
+struct ConflictFileJson {
+    path: String,
+    /// Who last changed the path on base; None when not found within the lookup cap
+    last_author: Option<String>,
+    last_sha: Option<String>,
+}
 
-/// Files that are never useful in a code review diff.
-/// Excluded by default; pass --all to include them.
-const NOISE_EXACT: &[&str] = &[
-    // JS/TS
-    "pnpm-lock.yaml",
-    "package-lock.json",
-    "yarn.lock",
-    "npm-shrinkwrap.json",
-    "bun.lockb",
-    // Rust
-    "Cargo.lock",
-    // Ruby
-    "Gemfile.lock",
-    // Python
-    "poetry.lock",
-    "Pipfile.lock",
-    "uv.lock",
-    // Go
-    "go.sum",
-    // PHP
-    "composer.lock",
-    // .NET
-    "packages.lock.json",
-    // Dart/Flutter
-    "pubspec.lock",
-    // Swift
-    "Package.resolved",
-    // Elixir
-    "mix.lock",
-    // Misc
-    ".DS_Store",
-];
+/// `pr commits --json`
+#[derive(Serialize)]
+struct CommitsJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    commits: Vec<CommitJson>,
+}
 
-const NOISE_EXTENSIONS: &[&str] = &[
-    ".min.js",
-    ".min.css",
-    ".map",
-    ".chunk.js",
-    ".bundle.js",
-];
+#[derive(Serialize)]
+struct CommitJson {
+    sha: String,
+    author: String,
+    date: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    additions: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deletions: Option<u64>,
+    /// With --detect-reverts: the later commit in this PR that exactly undoes this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverted_by: Option<String>,
+    /// With --detect-reverts: the earlier commit in this PR this one exactly undoes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverts: Option<String>,
+}
 
-const NOISE_PREFIXES: &[&str] = &[
-    "dist/",
-    ".next/",
-    "build/",
-    "__generated__/",
-    ".turbo/",
-];
+#[derive(Serialize)]
+struct FileHistoryJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    path: String,
+    revisions: Vec<FileRevisionJson>,
+    /// With --diff: the file's change between the two revisions, absent if it didn't change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<FileRevisionDiffJson>,
+}
 
-pub(crate) fn is_noise_file(path: &str) -> bool {
-    let filename = path.rsplit('/').next().unwrap_or(path);
+#[derive(Serialize)]
+struct FileRevisionJson {
+    /// 1-based, as `--diff` takes it
+    revision: usize,
+    sha: String,
+    subject: String,
+    status: String,
+    additions: u64,
+    deletions: u64,
+}
 
-    if NOISE_EXACT.iter().any(|n| filename == *n) {
-        return true;
-    }
+#[derive(Serialize)]
+struct FileRevisionDiffJson {
+    from: String,
+    to: String,
+    status: String,
+    additions: u64,
+    deletions: u64,
+    patch: Option<String>,
+}
 
-    if NOISE_EXTENSIONS.iter().any(|ext| path.ends_with(ext)) {
-        return true;
-    }
+#[derive(Serialize)]
+struct ChecksJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    head_sha: String,
+    passed: usize,
+    failed: usize,
+    pending: usize,
+    checks: Vec<CheckJson>,
+}
 
-    if NOISE_PREFIXES.iter().any(|prefix| path.starts_with(prefix)) {
-        return true;
-    }
+#[derive(Serialize)]
+struct CheckJson {
+    name: String,
+    outcome: String,
+    status: String,
+    conclusion: Option<String>,
+    details_url: Option<String>,
+}
 
-    false
+/// `pr files --json`
+#[derive(Serialize)]
+struct FilesJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    files: Vec<FileStatJson>,
 }
 
-// --- Commands ---
+/// `pr priorities --json`
+#[derive(Serialize)]
+struct PrioritiesJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    /// Most pressing first
+    files: Vec<priority::Ranked>,
+}
 
-pub async fn pr_view(
-    client: &github::Client,
-    repo: &str,
-    number: u64,
-    use_sem: bool,
-    use_smart: bool,
-    json: bool,
-) -> Result<()> {
-    let pr = client.get_pr(repo, number).await?;
+#[derive(Serialize)]
+struct FileStatJson {
+    path: String,
+    status: String,
+    additions: u64,
+    deletions: u64,
+}
 
-    if json {
-        let out = PrViewJson {
-            number: pr.number,
-            title: pr.title.clone(),
-            body: pr.body.clone(),
-            state: pr.state.clone(),
-            head_sha: pr.head_sha.clone(),
-            head_ref: pr.head_ref.clone(),
-            base_ref: pr.base_ref.clone(),
-            additions: pr.additions,
-            deletions: pr.deletions,
-            changed_files: pr.changed_files,
-            files: pr
-                .files
-                .iter()
-                .map(|f| FileStatJson {
-                    path: f.filename.clone(),
-                    status: f.status.clone(),
-                    additions: f.additions,
-                    deletions: f.deletions,
-                })
-                .collect(),
-        };
-        return print_json(&out);
-    }
+/// `pr diff --name-only --json` / `--name-status --json`
+#[derive(Serialize)]
+struct FileNamesJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    files: Vec<FileNameJson>,
+}
 
-    let noise_count = pr.files.iter().filter(|f| is_noise_file(&f.filename)).count();
-    let visible_files: Vec<github::PrFile> = pr
-        .files
-        .iter()
-        .filter(|f| !is_noise_file(&f.filename))
-        .cloned()
-        .collect();
+#[derive(Serialize)]
+struct FileNameJson {
+    path: String,
+    status: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    binary: bool,
+}
 
-    println!("{}", format::format_metadata(&pr));
-    println!();
-    println!("{}", format::format_stat_table(&visible_files));
-    if noise_count > 0 {
-        eprintln!("({} noise files hidden: lock/generated/minified)", noise_count);
+/// One entry of `pr diff --out-dir`'s index.json
+#[derive(Serialize, Deserialize)]
+struct OutFileJson {
+    path: String,
+    status: String,
+    additions: u64,
+    deletions: u64,
+    /// Relative to the directory; null when the file had nothing to write
+    output_file: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TotalsJson {
+    files: usize,
+    additions: u64,
+    deletions: u64,
+}
+
+impl TotalsJson {
+    fn of<'a>(files: impl IntoIterator<Item = &'a github::PrFile>) -> Self {
+        let mut totals = TotalsJson {
+            files: 0,
+            additions: 0,
+            deletions: 0,
+        };
+        for f in files {
+            totals.files += 1;
+            totals.additions += f.additions;
+            totals.deletions += f.deletions;
+        }
+        totals
     }
+}
 
-    if use_smart {
-        println!();
-        eprintln!("smart: fetching file contents from GitHub API...");
-        let pairs = client
-            .get_file_pairs(repo, &visible_files, &pr.base_ref, &pr.head_ref)
-            .await;
-        let smart_output = sem::run_sem_smart_from_pairs(&pairs)?;
-        println!("{smart_output}");
-    } else if use_sem {
-        println!();
-        let sem_output = sem::run_sem(&pr.base_ref, &pr.head_ref)?;
-        println!("{sem_output}");
+impl From<&github::PrFile> for FileStatJson {
+    fn from(f: &github::PrFile) -> Self {
+        FileStatJson {
+            path: f.filename.clone(),
+            status: f.status.clone(),
+            additions: f.additions,
+            deletions: f.deletions,
+        }
     }
+}
 
-    Ok(())
+#[derive(Serialize)]
+struct DiffJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    files: HashMap<String, Vec<u64>>,
+    /// Sums over `files`
+    totals: TotalsJson,
+    /// Each file's hunks with their stable ids
+    hunks: HashMap<String, Vec<HunkJson>>,
+    /// Binary files, which have no lines to comment on
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    binary: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<TruncatedJson>,
 }
 
-pub async fn pr_diff(
-    client: &github::Client,
-    repo: &str,
-    number: u64,
-    file_filters: &[String],
-    smart_files: bool,
-    include_all: bool,
-    stat_only: bool,
-    json: bool,
-) -> Result<()> {
-    let pr = client.get_pr_with_patches(repo, number).await?;
+#[derive(Serialize)]
+struct HunkJson {
+    id: String,
+    old_start: u64,
+    old_count: u64,
+    new_start: u64,
+    new_count: u64,
+    /// The enclosing function from the hunk header (or found with --resolve-context)
+    context: String,
+}
 
-    // Build the file filter list: --smart-files fetches contents from API, runs sem, filters
-    let smart_list = if smart_files {
-        eprintln!("smart: fetching file contents from GitHub API...");
-        let pairs = client
-            .get_file_pairs(repo, &pr.files, &pr.base_ref, &pr.head_ref)
-            .await;
-        match sem::get_smart_files_from_pairs(&pairs) {
-            Some(sf) => {
-                eprintln!("smart: filtering to {} files (skipped mechanical)", sf.len());
-                sf
-            }
-            None => {
-                eprintln!("smart: sem analysis failed, showing all files");
-                vec![]
-            }
-        }
-    } else {
-        vec![]
-    };
+/// `pr diff --json-full`
+#[derive(Serialize)]
+struct DiffFullJson {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    files: Vec<FileDiffJson>,
+    /// Sums over `files`
+    totals: TotalsJson,
+    /// Each file's commentable lines, the `files` map of `pr diff --json`
+    commentable: HashMap<String, Vec<u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<TruncatedJson>,
+}
 
-    let files: Vec<&github::PrFile> = if !file_filters.is_empty() {
-        // Explicit --file flags: substring match
-        pr.files
-            .iter()
-            .filter(|f| file_filters.iter().any(|filter| f.filename.contains(filter.as_str())))
-            .collect()
-    } else if smart_files && !smart_list.is_empty() {
-        // --smart-files with successful sem: exact path match
-        pr.files
-            .iter()
-            .filter(|f| smart_list.iter().any(|sf| f.filename == *sf))
-            .collect()
+#[derive(Serialize)]
+struct FileDiffJson {
+    path: String,
+    /// added, modified, removed or renamed
+    status: String,
+    additions: u64,
+    deletions: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    binary: bool,
+    hunks: Vec<FullHunkJson>,
+}
+
+/// A hunk as `parse_patch` gives it: `header`, `old_start`, `old_count`,
+/// `new_start`, `new_count`, `context`, and `lines`, each with `kind` (add,
+/// delete or context), `old_line`, `new_line` (null on the side it's missing
+/// from), `content` and `commentable`
+#[derive(Serialize)]
+struct FullHunkJson {
+    id: String,
+    #[serde(flatten)]
+    hunk: DiffHunk,
+}
+
+fn file_diff_json(f: &github::PrFile, ids: Vec<String>, hunks: Vec<DiffHunk>) -> FileDiffJson {
+    FileDiffJson {
+        path: f.filename.clone(),
+        status: f.status.clone(),
+        additions: f.additions,
+        deletions: f.deletions,
+        binary: f.binary,
+        hunks: ids.into_iter().zip(hunks).map(|(id, hunk)| FullHunkJson { id, hunk }).collect(),
+    }
+}
+
+/// Present on JSON output that was cut by `--max-bytes` / `--max-tokens`
+#[derive(Serialize)]
+struct TruncatedJson {
+    emitted_tokens: usize,
+    omitted_tokens: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    omitted_files: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct FileOut {
+    /// The PR's state: OPEN, CLOSED or MERGED
+    state: String,
+    path: String,
+    content: String,
+    /// Line count of the whole file, even when `content` is truncated
+    lines: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<TruncatedJson>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReviewOut {
+    id: u64,
+    url: String,
+    /// Every review created, when the comments were split across several
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reviews: Vec<ReviewRef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    dropped: Vec<DroppedComment>,
+    /// Nothing was posted: a review with the same `--idempotency-key` already exists
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deduplicated: bool,
+    /// Comments left out because validation failed
+    #[serde(skip_serializing_if = "is_zero")]
+    skipped: usize,
+    /// Comments left out because they target noise files
+    #[serde(skip_serializing_if = "is_zero")]
+    skipped_noise: usize,
+    /// Posted comments per `severity`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    severities: BTreeMap<Severity, usize>,
+    /// `--via graphql`: comments GraphQL turned down that went through REST instead
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fell_back: Vec<String>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+#[derive(Debug, Serialize)]
+struct ReviewRef {
+    id: u64,
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DroppedComment {
+    path: String,
+    line: u64,
+    reason: String,
+}
+
+/// `pr review --amend` result
+#[derive(Serialize)]
+struct AmendOut {
+    /// Your latest review, whose commit the comments were posted on
+    review_id: u64,
+    review_url: String,
+    commit_id: String,
+    comments: Vec<AmendedComment>,
+    /// Comments left out because validation failed
+    #[serde(skip_serializing_if = "is_zero")]
+    skipped: usize,
+}
+
+#[derive(Serialize)]
+struct AmendedComment {
+    path: String,
+    line: u64,
+    url: String,
+}
+
+/// `pr review --json` result. `review_id` and `url` are null when every comment
+/// was skipped and nothing was posted.
+#[derive(Serialize)]
+struct ReviewResult {
+    review_id: Option<u64>,
+    url: Option<String>,
+    /// Every review created, when the comments were split across several
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reviews: Vec<ReviewRef>,
+    posted: Vec<PostedComment>,
+    skipped: Vec<Skipped>,
+    /// Nothing was posted: a review with the same `--idempotency-key` already exists
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deduplicated: bool,
+}
+
+#[derive(Serialize)]
+struct PostedComment {
+    path: String,
+    line: u64,
+    /// Cut down by `--truncate-long-comments`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+}
+
+/// A comment `pr review` left out: the warning shown on stderr, and the reason
+/// `--json` reports
+#[derive(Debug, PartialEq, Serialize)]
+struct Skipped {
+    path: String,
+    /// None for a hunk comment whose hunk couldn't be resolved
+    line: Option<u64>,
+    reason: SkipReason,
+    #[serde(skip)]
+    message: String,
+}
+
+impl Skipped {
+    fn new(path: &str, line: Option<u64>, reason: SkipReason, message: String) -> Skipped {
+        Skipped {
+            path: path.to_string(),
+            line,
+            reason,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SkipReason {
+    /// The line isn't in the diff (even after `--fuzz`)
+    NotInDiff,
+    FileNotChanged,
+    /// On a binary file, which has no lines to comment on
+    BinaryFile,
+    /// The same comment is already on that line
+    Duplicate,
+    RangeCrossesHunk,
+    /// On a lock, generated or vendored file, without `--allow-noise-comments`
+    NoiseFile,
+    EmptyBody,
+    /// No path and line, or a `hunk` that doesn't resolve or doesn't hold the line
+    BadTarget,
+    /// GitHub turned it down (422) and `--retry-without-invalid` dropped it
+    Rejected,
+}
+
+/// `pr resolve` result: the thread's state after the mutation
+#[derive(Serialize)]
+struct ResolveOut {
+    thread_id: String,
+    path: String,
+    line: Option<u64>,
+    resolved: bool,
+}
+
+/// `pr close` / `pr reopen` result
+#[derive(Serialize)]
+struct StateOut {
+    number: u64,
+    state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_branch: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AssignOut {
+    number: u64,
+    assignees: Vec<String>,
+    milestone: Option<String>,
+    /// `--add` logins that can't be assigned in the repo, left out
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unassignable: Vec<String>,
+}
+
+/// `pr update-branch` result. A merge update is accepted and runs in the
+/// background, so only a rebase has the new head SHA.
+#[derive(Serialize)]
+struct UpdateBranchOut {
+    number: u64,
+    method: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    head_sha: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PatchOut {
+    /// Where the patch was written
+    path: String,
+    suggestions: usize,
+    files: usize,
+}
+
+#[derive(Serialize)]
+struct DryRunOut {
+    valid: Vec<DryRunComment>,
+    /// Why each skipped comment wouldn't be posted
+    skipped: Vec<String>,
+    /// Comments on noise files, left out without `--allow-noise-comments`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped_noise: Vec<String>,
+    /// Comments moved to a nearby line by `--fuzz`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    adjusted: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    severities: BTreeMap<Severity, usize>,
+}
+
+#[derive(Serialize)]
+struct DryRunComment {
+    path: String,
+    line: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_line: Option<u64>,
+    body: String,
+}
+
+#[derive(Deserialize)]
+struct CommentInput {
+    /// With `hunk`, may be left out
+    #[serde(default)]
+    path: Option<String>,
+    /// With `hunk`, defaults to the hunk's added lines
+    #[serde(default)]
+    line: Option<u64>,
+    /// May be empty when `suggestion` is given
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    start_line: Option<u64>,
+    /// Replacement for lines `start_line..=line`, posted as a suggestion block after the body
+    #[serde(default)]
+    suggestion: Option<String>,
+    /// Stable hunk id from `pr diff` (e.g. "auth-7f3a") in place of path/line
+    #[serde(default)]
+    hunk: Option<String>,
+    /// Quote the commented hunk lines in a collapsed block above the body
+    #[serde(default)]
+    include_context: bool,
+    /// Shown as a prefix on the body (e.g. "💡 nit:")
+    #[serde(default)]
+    severity: Option<Severity>,
+    /// LEFT comments on the base's lines (`line` is an old line number)
+    #[serde(default)]
+    side: Option<Side>,
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+enum Side {
+    Left,
+    Right,
+}
+
+struct ReviewInput {
+    body: String,
+    /// COMMENT, APPROVE or REQUEST_CHANGES
+    event: String,
+    comments: Vec<CommentInput>,
+    /// Unknown fields that were ignored
+    warnings: Vec<String>,
+}
+
+fn default_body() -> String {
+    "Review from gh-agent".to_string()
+}
+
+/// The review object, or just its comments array with the default body. Each
+/// comment is deserialized on its own, and every problem is reported together
+/// under the entry it's in (`comments[17]: invalid type: …`).
+fn parse_review_input(raw: &str) -> Result<ReviewInput> {
+    let entries = review_input::entries(raw).map_err(|problems| anyhow::anyhow!(problems.join("\n")))?;
+    let mut problems = Vec::new();
+    let mut comments = Vec::new();
+    for (at, c) in entries.comments {
+        match serde_json::from_value::<CommentInput>(c) {
+            Ok(c) => comments.push(c),
+            Err(e) => problems.push(format!("{at}: {e}")),
+        }
+    }
+    if !problems.is_empty() {
+        anyhow::bail!(problems.join("\n"));
+    }
+    Ok(ReviewInput {
+        body: entries.body.unwrap_or_else(default_body),
+        event: entries.event.unwrap_or_else(|| "COMMENT".to_string()),
+        comments,
+        warnings: entries.warnings,
+    })
+}
+
+/// Read `--comments-file`, where `-` means stdin. Returns the contents and
+/// the name to use in messages.
+fn read_comments_file(spec: &str) -> Result<(String, &str)> {
+    if spec == "-" {
+        let mut raw = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut raw).context("Failed to read comments from stdin")?;
+        return Ok((raw, "stdin"));
+    }
+    let raw = std::fs::read_to_string(spec).with_context(|| format!("Failed to read {spec}"))?;
+    Ok((raw, spec))
+}
+
+/// The review body given on the command line, which replaces the comments file's:
+/// `--body`, or `--body-file` (- for stdin). An empty body file gives the default body.
+fn read_review_body(body: Option<&str>, body_file: Option<&str>, comments_file: &str) -> Result<Option<String>> {
+    if body.is_some() && body_file.is_some() {
+        anyhow::bail!("Pass --body or --body-file, not both");
+    }
+    if let Some(body) = body {
+        return Ok(Some(body.to_string()));
+    }
+    let Some(spec) = body_file else {
+        return Ok(None);
+    };
+    let raw = if spec == "-" {
+        if comments_file == "-" {
+            anyhow::bail!("--body-file - needs the comments in a file (--comments-file), since stdin can only be read once");
+        }
+        let mut raw = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut raw).context("Failed to read the review body from stdin")?;
+        raw
     } else {
-        // No filter or sem fallback: all files
-        pr.files.iter().collect()
+        std::fs::read_to_string(spec).with_context(|| format!("Failed to read {spec}"))?
     };
+    Ok(Some(if raw.trim().is_empty() { default_body() } else { raw }))
+}
+
+/// Placeholders a `--template` can use besides its `--template-vars` names
+const TEMPLATE_PLACEHOLDERS: &[&str] = &["pr_title", "changed_files", "comment_count", "severity_counts", "smart_summary"];
+
+/// Names of the `{{name}}` placeholders in a review template, in order
+fn template_placeholders(template: &str) -> Vec<&str> {
+    template
+        .split("{{")
+        .skip(1)
+        .filter_map(|rest| rest.split_once("}}"))
+        .map(|(name, _)| name.trim())
+        .collect()
+}
+
+/// `--template-vars key=value` pairs
+fn parse_template_vars(vars: &[String]) -> Result<BTreeMap<String, String>> {
+    vars.iter()
+        .map(|kv| match kv.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+            _ => anyhow::bail!("Invalid --template-vars {kv:?}: expected KEY=VALUE"),
+        })
+        .collect()
+}
+
+/// Read a `--template` file, failing on any placeholder that can't be filled in
+fn read_template(path: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let template = std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+    let mut unknown: Vec<&str> = Vec::new();
+    for name in template_placeholders(&template) {
+        if !TEMPLATE_PLACEHOLDERS.contains(&name) && !vars.contains_key(name) && !unknown.contains(&name) {
+            unknown.push(name);
+        }
+    }
+    if !unknown.is_empty() {
+        let names: Vec<String> = unknown.iter().map(|n| format!("{{{{{n}}}}}")).collect();
+        anyhow::bail!(
+            "Unknown placeholder{} in {path}: {}; supported: {}, or a name given with --template-vars",
+            if unknown.len() == 1 { "" } else { "s" },
+            names.join(", "),
+            TEMPLATE_PLACEHOLDERS.join(", ")
+        );
+    }
+    Ok(template)
+}
+
+/// Replace each `{{name}}` that has a value
+fn fill_template(template: &str, values: &BTreeMap<String, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find("{{") {
+        let Some(close) = rest[open + 2..].find("}}").map(|i| open + 2 + i) else {
+            break;
+        };
+        // The innermost `{{` before the braces close, as `template_placeholders` reads it
+        let at = open + rest[open..close].rfind("{{").unwrap_or(0);
+        out.push_str(&rest[..at]);
+        match values.get(rest[at + 2..close].trim()) {
+            Some(value) => out.push_str(value),
+            None => out.push_str(&rest[at..close + 2]),
+        }
+        rest = &rest[close + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    outln!("{}", render_json(value, redact::enabled())?);
+    Ok(())
+}
+
+/// Pretty JSON for output, with code swapped for hashes under `--redact-content`
+fn render_json<T: Serialize>(value: &T, redact_content: bool) -> Result<String> {
+    if !redact_content {
+        return Ok(serde_json::to_string_pretty(value)?);
+    }
+    let mut value = serde_json::to_value(value)?;
+    redact::redact(&mut value);
+    Ok(serde_json::to_string_pretty(&value)?)
+}
+
+// --- Noise file filtering ---
+
+/// Files that are never useful in a code review diff.
+/// Excluded by default; pass --all to include them.
+const NOISE_EXACT: &[&str] = &[
+    // JS/TS
+    "pnpm-lock.yaml",
+    "package-lock.json",
+    "yarn.lock",
+    "npm-shrinkwrap.json",
+    "bun.lockb",
+    // Rust
+    "Cargo.lock",
+    // Ruby
+    "Gemfile.lock",
+    // Python
+    "poetry.lock",
+    "Pipfile.lock",
+    "uv.lock",
+    // Go
+    "go.sum",
+    // PHP
+    "composer.lock",
+    // .NET
+    "packages.lock.json",
+    // Dart/Flutter
+    "pubspec.lock",
+    // Swift
+    "Package.resolved",
+    // Elixir
+    "mix.lock",
+    // Misc
+    ".DS_Store",
+];
+
+const NOISE_EXTENSIONS: &[&str] = &[
+    ".min.js",
+    ".min.css",
+    ".map",
+    ".chunk.js",
+    ".bundle.js",
+];
+
+const NOISE_PREFIXES: &[&str] = &[
+    "dist/",
+    ".next/",
+    "build/",
+    "__generated__/",
+    ".turbo/",
+];
+
+/// Vendored or installed-dependency directories, matched as a whole path segment at
+/// any depth (`services/api/node_modules/...`), never as a substring of a name
+const NOISE_SEGMENTS: &[&str] = &["vendor", "third_party", "node_modules", ".yarn"];
+
+/// Extra segments from `[noise] segments = [...]` in the config file
+static EXTRA_NOISE_SEGMENTS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Register the config file's `noise.segments`; call once at startup
+pub fn set_noise_segments(segments: Vec<String>) {
+    let _ = EXTRA_NOISE_SEGMENTS.set(segments);
+}
+
+/// The first of `segments` that is a directory in `path` (not the file name itself)
+fn noise_dir<'a>(path: &str, segments: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let dirs: Vec<&str> = path.split('/').rev().skip(1).collect();
+    segments.map(|seg| seg.trim_matches('/')).find(|seg| dirs.contains(seg))
+}
+
+/// Which noise rule a path matched
+#[derive(Debug, PartialEq)]
+pub(crate) enum NoiseReason {
+    /// A lock file or other known file name
+    FileName(&'static str),
+    Extension(&'static str),
+    Prefix(&'static str),
+    /// A vendored or dependency directory, built in or from `[noise] segments`
+    Dir(String),
+    /// `--auto-noise`: a large one-sided change that reads like machine output
+    BulkGenerated,
+}
+
+impl std::fmt::Display for NoiseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoiseReason::FileName(name) => write!(f, "file name {name}"),
+            NoiseReason::Extension(ext) => write!(f, "extension {ext}"),
+            NoiseReason::Prefix(prefix) => write!(f, "path prefix {prefix}"),
+            NoiseReason::Dir(dir) => write!(f, "directory {dir}/"),
+            NoiseReason::BulkGenerated => write!(f, "heuristic:bulk-generated"),
+        }
+    }
+}
+
+pub(crate) fn noise_reason(path: &str) -> Option<NoiseReason> {
+    let filename = path.rsplit('/').next().unwrap_or(path);
+
+    if let Some(name) = NOISE_EXACT.iter().find(|n| filename == **n) {
+        return Some(NoiseReason::FileName(name));
+    }
+
+    if let Some(ext) = NOISE_EXTENSIONS.iter().find(|ext| path.ends_with(*ext)) {
+        return Some(NoiseReason::Extension(ext));
+    }
+
+    if let Some(prefix) = NOISE_PREFIXES.iter().find(|prefix| path.starts_with(*prefix)) {
+        return Some(NoiseReason::Prefix(prefix));
+    }
+
+    let extra = EXTRA_NOISE_SEGMENTS.get().into_iter().flatten().map(String::as_str);
+    noise_dir(path, NOISE_SEGMENTS.iter().copied().chain(extra)).map(|dir| NoiseReason::Dir(dir.to_string()))
+}
+
+pub(crate) fn is_noise_file(path: &str) -> bool {
+    noise_reason(path).is_some()
+}
+
+/// The path rules, then with `auto_noise` the bulk-generated heuristic over the
+/// file's patch (files without one are judged by path alone)
+fn file_noise_reason(f: &github::PrFile, auto_noise: bool) -> Option<NoiseReason> {
+    noise_reason(&f.filename).or_else(|| {
+        let patch = f.patch.as_deref().filter(|_| auto_noise)?;
+        generated::Shape::of(f.additions, f.deletions, patch)
+            .looks_generated()
+            .then_some(NoiseReason::BulkGenerated)
+    })
+}
+
+// --- File selection ---
+
+/// How long the `--owned-by @me` identity (login + teams) stays cached on disk
+const IDENTITY_CACHE_SECS: u64 = 60 * 60;
+
+#[derive(Serialize, Deserialize)]
+struct IdentityCache {
+    login: String,
+    teams: Vec<String>,
+    fetched_at: u64,
+}
 
-    // Apply noise filter unless --all is set
-    let (files, skipped) = if include_all {
-        (files, 0usize)
+/// `--owned-by` filter: the repo's ownership rules and the owner handles to keep
+pub(crate) struct OwnedBy {
+    rules: Ownership,
+    owners: HashSet<String>,
+}
+
+impl OwnedBy {
+    fn owns(&self, path: &str) -> bool {
+        self.rules.is_owned_by(path, &self.owners)
+    }
+}
+
+/// Resolve an `--owned-by` spec against the PR's ownership rules for `paths`
+/// (see `fetch_ownership`). `@me` expands to the authenticated user's login
+/// plus their team memberships.
+async fn resolve_owned_by(
+    client: &github::Client,
+    repo: &str,
+    pr: &github::PullRequest,
+    paths: &[&str],
+    spec: Option<&str>,
+) -> Result<Option<OwnedBy>> {
+    let Some(spec) = spec else {
+        return Ok(None);
+    };
+    let owners = if spec.eq_ignore_ascii_case("@me") {
+        my_owner_handles(client).await?
     } else {
-        let before = files.len();
-        let filtered: Vec<&github::PrFile> = files
+        HashSet::from([normalize_owner(spec)])
+    };
+    let rules = fetch_ownership(client, repo, pr, paths).await?;
+    Ok(Some(OwnedBy { rules, owners }))
+}
+
+fn changed_paths(pr: &github::PullRequest) -> Vec<&str> {
+    pr.files.iter().map(|f| f.filename.as_str()).collect()
+}
+
+/// `[owners]` settings from the config file
+static OWNERS_CONFIG: OnceLock<config::OwnersConfig> = OnceLock::new();
+
+/// Register the config file's `[owners]` section; call once at startup
+pub fn set_owners_config(settings: config::OwnersConfig) {
+    let _ = OWNERS_CONFIG.set(settings);
+}
+
+/// CODEOWNERS and, with `[owners] enabled`, the OWNERS files governing `paths`,
+/// both read at the PR's base commit: at the head, a PR could rewrite them to
+/// name its own author as owner. With OWNERS enabled a missing CODEOWNERS is
+/// fine; it's an error only when neither system has any rules.
+async fn fetch_ownership(client: &github::Client, repo: &str, pr: &github::PullRequest, paths: &[&str]) -> Result<Ownership> {
+    let settings = OWNERS_CONFIG.get().cloned().unwrap_or_default();
+    fetch_ownership_with(client, repo, pr, paths, settings).await
+}
+
+async fn fetch_ownership_with(
+    client: &github::Client,
+    repo: &str,
+    pr: &github::PullRequest,
+    paths: &[&str],
+    settings: config::OwnersConfig,
+) -> Result<Ownership> {
+    if !settings.enabled {
+        return Ok(Ownership {
+            codeowners: Some(fetch_codeowners(client, repo, &pr.base_sha).await?),
+            owners_files: None,
+            precedence: settings.precedence,
+        });
+    }
+    let codeowners = match fetch_codeowners(client, repo, &pr.base_sha).await {
+        Ok(rules) => Some(rules),
+        Err(e) if github::is_budget_exhausted(&e) => return Err(e),
+        Err(_) => None,
+    };
+    // At the base, like CODEOWNERS, so a PR can't name its own owners
+    let owners_files = fetch_owners_tree(client, repo, &pr.base_sha, paths).await?;
+    if codeowners.is_none() && owners_files.is_empty() {
+        anyhow::bail!(
+            "No CODEOWNERS or OWNERS files cover these paths in {repo} (looked in {} and each changed directory)",
+            CODEOWNERS_PATHS.join(", ")
+        );
+    }
+    Ok(Ownership {
+        codeowners,
+        owners_files: Some(owners_files),
+        precedence: settings.precedence,
+    })
+}
+
+/// OWNERS files fetched at once
+const OWNERS_FETCH_BATCH: usize = 8;
+
+/// OWNERS discovery at one commit. Commits are immutable, so this never expires.
+#[derive(Default, Serialize, Deserialize)]
+struct OwnersCache {
+    /// Every OWNERS file path in the tree, once listed
+    listing: Option<Vec<String>>,
+    /// Directory -> OWNERS contents, for the directories fetched so far
+    contents: BTreeMap<String, String>,
+}
+
+/// OWNERS rules for `paths` at `sha`. The tree is listed once to find which
+/// directories have an OWNERS file; only those on the paths' ancestor chains
+/// are fetched, a batch at a time. Both are cached on disk per sha.
+async fn fetch_owners_tree(client: &github::Client, repo: &str, sha: &str, paths: &[&str]) -> Result<OwnersTree> {
+    let cache_path = config::cache_dir()
+        .ok()
+        .map(|d| d.join("owners").join(config::repo_cache_name(repo)).join(format!("{sha}.json")));
+    let mut cache: OwnersCache = cache_path
+        .as_ref()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default();
+    let mut dirty = false;
+
+    if cache.listing.is_none() {
+        let (all, truncated) = client.get_tree_paths(repo, sha).await?;
+        if truncated {
+            eprintln!("warning: GitHub truncated the file listing of {repo}; some OWNERS files may be missed");
+        }
+        let listing = all.into_iter().filter(|p| p == "OWNERS" || p.ends_with("/OWNERS")).collect();
+        cache.listing = Some(listing);
+        dirty = true;
+    }
+    let dirs = owners::needed_dirs(cache.listing.iter().flatten().map(String::as_str), paths);
+
+    let missing: Vec<&String> = dirs.iter().filter(|d| !cache.contents.contains_key(*d)).collect();
+    for batch in missing.chunks(OWNERS_FETCH_BATCH) {
+        let files: Vec<String> = batch.iter().map(|dir| owners::owners_path(dir)).collect();
+        let futs = files.iter().map(|path| client.get_file_content(repo, path, sha));
+        for (dir, result) in batch.iter().zip(futures::future::join_all(futs).await) {
+            cache.contents.insert((*dir).clone(), result?);
+            dirty = true;
+        }
+    }
+
+    let mut tree = OwnersTree::default();
+    for dir in &dirs {
+        let file = OwnersFile::parse(&cache.contents[dir]).with_context(|| format!("Parsing {}", owners::owners_path(dir)))?;
+        tree.insert(dir.clone(), file);
+    }
+
+    // Best effort, like the churn cache
+    if let (Some(p), true) = (&cache_path, dirty) {
+        if let (Some(dir), Ok(raw)) = (p.parent(), serde_json::to_string(&cache)) {
+            let _ = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(p, raw));
+        }
+    }
+    Ok(tree)
+}
+
+async fn fetch_codeowners(client: &github::Client, repo: &str, git_ref: &str) -> Result<Codeowners> {
+    for path in CODEOWNERS_PATHS {
+        match client.get_file_content(repo, path, git_ref).await {
+            Ok(content) => return Codeowners::parse(&content).with_context(|| format!("Parsing {path}")),
+            Err(e) if github::is_budget_exhausted(&e) => return Err(e),
+            Err(_) => continue,
+        }
+    }
+    anyhow::bail!(
+        "No CODEOWNERS file in {repo} at {git_ref} (looked in {})",
+        CODEOWNERS_PATHS.join(", ")
+    )
+}
+
+/// Owner handles for `@me`, cached on disk to avoid refetching teams on every call
+async fn my_owner_handles(client: &github::Client) -> Result<HashSet<String>> {
+    let cache_path = config::cache_dir().ok().map(|d| d.join("identity.json"));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let cached = cache_path
+        .as_ref()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|raw| serde_json::from_str::<IdentityCache>(&raw).ok())
+        .filter(|c| now.saturating_sub(c.fetched_at) < IDENTITY_CACHE_SECS);
+
+    let identity = match cached {
+        Some(c) => c,
+        None => {
+            let (login, teams) =
+                tokio::try_join!(client.get_authenticated_user(), client.get_user_teams())
+                    .context("Resolving @me (token needs read:org to list teams)")?;
+            let fresh = IdentityCache { login, teams, fetched_at: now };
+            // Best effort: a missing cache only costs two requests next time
+            if let Some(p) = &cache_path {
+                if let (Some(dir), Ok(raw)) = (p.parent(), serde_json::to_string(&fresh)) {
+                    let _ = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(p, raw));
+                }
+            }
+            fresh
+        }
+    };
+
+    Ok(std::iter::once(&identity.login)
+        .chain(&identity.teams)
+        .map(|o| normalize_owner(o))
+        .collect())
+}
+
+/// `--file` and `--file-regex` for diff/grep/ast-grep: a path is kept when any of
+/// them matches it, or when there are none. A `--file` with `*`, `?` or `/` is a
+/// glob over the whole path, and one ending in `/` takes everything under that
+/// directory. A plain word matches anywhere in the path.
+#[derive(Default, Clone)]
+pub struct FileFilter {
+    words: Vec<String>,
+    globs: Vec<GlobMatcher>,
+    regexes: Vec<Regex>,
+}
+
+impl FileFilter {
+    pub fn new(files: &[String], regexes: &[String]) -> Result<Self> {
+        let mut filter = FileFilter::default();
+        for file in files {
+            if !file.contains(['*', '?', '/']) {
+                filter.words.push(file.clone());
+                continue;
+            }
+            let glob = match file.strip_suffix('/') {
+                Some(dir) => format!("{dir}/**"),
+                None => file.clone(),
+            };
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid --file glob {file:?}"))?;
+            filter.globs.push(glob.compile_matcher());
+        }
+        for pattern in regexes {
+            let regex = Regex::new(pattern).with_context(|| format!("Invalid --file-regex {pattern:?}"))?;
+            filter.regexes.push(regex);
+        }
+        Ok(filter)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.globs.is_empty() && self.regexes.is_empty()
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        self.is_empty()
+            || self.words.iter().any(|w| path.contains(w.as_str()))
+            || self.globs.iter().any(|g| g.is_match(path))
+            || self.regexes.iter().any(|r| r.is_match(path))
+    }
+}
+
+/// Shared file selection for view/diff/grep/ast-grep: the `--file` filter, the
+/// `--owned-by` CODEOWNERS filter, then the noise filter unless `include_all`
+/// (with the bulk-generated heuristic when `auto_noise`).
+/// Returns the selected files and how many noise files were dropped.
+fn select_files<'a>(
+    files: impl IntoIterator<Item = &'a github::PrFile>,
+    file_filter: &FileFilter,
+    owned_by: Option<&OwnedBy>,
+    include_all: bool,
+    auto_noise: bool,
+) -> (Vec<&'a github::PrFile>, usize) {
+    let mut noise = 0;
+    let selected = files
+        .into_iter()
+        .filter(|f| file_filter.matches(&f.filename))
+        .filter(|f| owned_by.is_none_or(|o| o.owns(&f.filename)))
+        .filter(|f| {
+            let keep = include_all || file_noise_reason(f, auto_noise).is_none();
+            noise += usize::from(!keep);
+            keep
+        })
+        .collect();
+    (selected, noise)
+}
+
+/// `--exclude` globs over repo-relative paths. `*`, `?` and `[...]` match within
+/// one path segment and `**` across any number of them, so `*.snap` is only a
+/// top-level file while `**/*.snap` is any.
+fn exclude_globs(patterns: &[String]) -> Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("Invalid --exclude glob {pattern:?}"))?;
+        set.add(glob);
+    }
+    Ok(set.build()?)
+}
+
+/// Drop the paths `--exclude` matches, saying how many on stderr
+fn exclude_paths<T>(items: Vec<T>, excludes: &GlobSet, path: impl Fn(&T) -> &str) -> Vec<T> {
+    let before = items.len();
+    let kept: Vec<T> = items.into_iter().filter(|item| !excludes.is_match(path(item))).collect();
+    if kept.len() < before {
+        eprintln!("exclude: left out {} files", before - kept.len());
+    }
+    kept
+}
+
+fn report_owned_by(spec: Option<&str>, kept: usize) {
+    if let Some(spec) = spec {
+        eprintln!("owned-by {spec}: {kept} changed files");
+    }
+}
+
+// --- PR state ---
+
+/// Exit status when a write is refused because the PR is merged or closed
+pub const EXIT_PR_NOT_OPEN: i32 = 4;
+
+/// A write aimed at a merged or closed PR, refused before anything was fetched
+/// beyond the PR's metadata
+#[derive(Debug)]
+pub struct PrNotOpen {
+    number: u64,
+    /// "merged" or "closed"
+    state: String,
+    /// Whether `--allow-closed` would let the write through
+    overridable: bool,
+}
+
+impl std::fmt::Display for PrNotOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PR #{} is {}; nothing was written", self.number, self.state)?;
+        if self.overridable {
+            write!(f, " (pass --allow-closed or --force to write anyway)")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PrNotOpen {}
+
+pub fn is_pr_not_open(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<PrNotOpen>().is_some()
+}
+
+/// Refuse to write to a merged or closed PR. `allow_closed` is None for writes
+/// that can't apply to one at all (merging, updating the branch, draft toggles).
+fn ensure_open(number: u64, state: &str, allow_closed: Option<bool>) -> Result<()> {
+    if state == "OPEN" || allow_closed == Some(true) {
+        return Ok(());
+    }
+    Err(PrNotOpen {
+        number,
+        state: state.to_lowercase(),
+        overridable: allow_closed.is_some(),
+    }
+    .into())
+}
+
+/// `ensure_open` for commands whose output is JSON: a refusal is also printed as
+/// `{"error": "pr_not_open", "number", "state"}`, so agents can branch on the state
+/// rather than on the message
+fn ensure_open_json(number: u64, state: &str, allow_closed: Option<bool>) -> Result<()> {
+    let result = ensure_open(number, state, allow_closed);
+    if let Some(refused) = result.as_ref().err().and_then(|e| e.downcast_ref::<PrNotOpen>()) {
+        print_json(&serde_json::json!({
+            "error": "pr_not_open",
+            "number": refused.number,
+            "state": refused.state,
+        }))?;
+    }
+    result
+}
+
+/// Fetch the PR's state and refuse a write to it the way `ensure_open` does, for
+/// writes that otherwise never read the PR
+async fn ensure_pr_open(client: &github::Client, repo: &str, number: u64, allow_closed: bool, json: bool) -> Result<()> {
+    let pr = client.get_pr(repo, number).await?;
+    if json {
+        ensure_open_json(number, &pr.state, Some(allow_closed))
+    } else {
+        ensure_open(number, &pr.state, Some(allow_closed))
+    }
+}
+
+/// The PR's state, for the JSON of read commands that don't otherwise fetch the
+/// PR; noted on stderr like `note_state`
+async fn json_state(client: &github::Client, repo: &str, number: u64) -> Result<String> {
+    let pr = client.get_pr(repo, number).await?;
+    note_state(&pr);
+    Ok(pr.state)
+}
+
+/// `[MERGED]`/`[CLOSED]` note on stderr for read commands, whose output
+/// otherwise looks the same for a PR that's no longer open
+fn note_state(pr: &github::PullRequest) {
+    if pr.state != "OPEN" {
+        eprintln!("[{}] PR #{} is {}", pr.state, pr.number, pr.state.to_lowercase());
+    }
+}
+
+// --- Commands ---
+
+/// What `pr view` shows, from its flags
+#[derive(Default)]
+pub struct ViewOptions<'a> {
+    pub use_sem: bool,
+    pub use_smart: bool,
+    pub owned_by: Option<&'a str>,
+    /// --sort and --graph for the stat table
+    pub sort: Option<format::StatSort>,
+    pub graph: bool,
+    pub json: bool,
+}
+
+pub async fn pr_view(client: &github::Client, repo: &str, number: u64, options: ViewOptions<'_>) -> Result<()> {
+    let ViewOptions {
+        use_sem,
+        use_smart,
+        owned_by,
+        sort,
+        graph,
+        json,
+    } = options;
+    let pr = client.get_pr(repo, number).await?;
+    let owned = resolve_owned_by(client, repo, &pr, &changed_paths(&pr), owned_by).await?;
+
+    if json {
+        let smart = if use_smart {
+            let visible: Vec<github::PrFile> =
+                select_files(&pr.files, &FileFilter::default(), owned.as_ref(), false, false).0.into_iter().cloned().collect();
+            let pairs = client.get_file_pairs(repo, &visible, &pr.base_ref, &pr.head_ref).await;
+            let changes = sem::run_sem_smart_json(&pairs);
+            if owned.is_none() {
+                record_smart(repo, number, &pr.head_sha, &changes);
+            }
+            Some(changes)
+        } else {
+            None
+        };
+        let mut files = select_files(&pr.files, &FileFilter::default(), owned.as_ref(), true, false).0;
+        if let Some(sort) = sort {
+            sort.sort(&mut files);
+        }
+        let out = PrViewJson {
+            number: pr.number,
+            title: pr.title.clone(),
+            body: pr.body.clone(),
+            state: pr.state.clone(),
+            head_sha: pr.head_sha.clone(),
+            head_ref: pr.head_ref.clone(),
+            base_ref: pr.base_ref.clone(),
+            additions: pr.additions,
+            deletions: pr.deletions,
+            changed_files: pr.changed_files,
+            totals: TotalsJson::of(files.iter().copied()),
+            files: files.into_iter().map(FileStatJson::from).collect(),
+            smart,
+        };
+        return print_json(&out);
+    }
+
+    let (visible, noise_count) = select_files(&pr.files, &FileFilter::default(), owned.as_ref(), false, false);
+    let visible_files: Vec<github::PrFile> = visible.into_iter().cloned().collect();
+    report_owned_by(owned_by, visible_files.len() + noise_count);
+
+    outln!("{}", format::format_metadata(&pr));
+    outln!();
+    let mut table = visible_files.clone();
+    if let Some(sort) = sort {
+        sort.sort(&mut table);
+    }
+    outln!("{}", format::format_stat_table(&table, graph));
+    if noise_count > 0 {
+        eprintln!("({} noise files hidden: lock/generated/minified)", noise_count);
+    }
+
+    if use_smart {
+        outln!();
+        eprintln!("smart: fetching file contents from GitHub API...");
+        let pairs = client
+            .get_file_pairs(repo, &visible_files, &pr.base_ref, &pr.head_ref)
+            .await;
+        let (smart_output, changes) = sem::run_sem_smart_from_pairs(&pairs)?;
+        outln!("{smart_output}");
+        if owned.is_none() {
+            record_smart(repo, number, &pr.head_sha, &changes);
+        }
+    } else if use_sem {
+        outln!();
+        let sem_output = sem::run_sem(&pr.base_ref, &pr.head_ref)?;
+        outln!("{sem_output}");
+    }
+
+    Ok(())
+}
+
+pub async fn pr_ready(client: &github::Client, repo: &str, number: u64) -> Result<()> {
+    set_draft_state(client, repo, number, false).await
+}
+
+pub async fn pr_draft(client: &github::Client, repo: &str, number: u64) -> Result<()> {
+    set_draft_state(client, repo, number, true).await
+}
+
+async fn set_draft_state(client: &github::Client, repo: &str, number: u64, draft: bool) -> Result<()> {
+    let describe = |draft: bool| if draft { "a draft" } else { "ready for review" };
+    // metadata + the mutation
+    client.check_budget(2)?;
+    let pr = client.get_pr(repo, number).await?;
+    ensure_open(number, &pr.state, None)?;
+    if pr.is_draft == draft {
+        anyhow::bail!("PR #{number} is already {}", describe(draft));
+    }
+    let is_draft = client.set_pr_draft(&pr.node_id, draft).await?;
+    outln!("PR #{number} is now {}", describe(is_draft));
+    Ok(())
+}
+
+/// Close a PR, first posting `comment` to explain why. Closing a closed PR only warns;
+/// a merged PR is refused.
+pub async fn pr_close(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    comment: Option<&str>,
+    delete_branch: bool,
+) -> Result<()> {
+    let pr = client.get_pr(repo, number).await?;
+    if pr.state == "MERGED" {
+        ensure_open_json(number, &pr.state, None)?;
+    }
+    let mut out = StateOut {
+        number,
+        state: pr.state.to_lowercase(),
+        comment_url: None,
+        deleted_branch: None,
+    };
+    if pr.state != "OPEN" {
+        eprintln!("warning: PR #{number} is already {}; nothing to do", out.state);
+        return print_json(&out);
+    }
+
+    // The state PATCH, plus the comment POST and ref DELETE when asked for
+    client.check_budget(1 + usize::from(comment.is_some()) + usize::from(delete_branch))?;
+    if let Some(body) = comment {
+        out.comment_url = Some(client.add_issue_comment(repo, number, body).await?.html_url);
+    }
+    out.state = client.set_pr_state(repo, number, "closed").await?.state;
+    if delete_branch && delete_head_branch(client, repo, &pr.head_ref, pr.is_cross_repository).await {
+        out.deleted_branch = Some(pr.head_ref);
+    }
+    print_json(&out)
+}
+
+/// Reopen a closed PR. Reopening an open PR only warns; a merged PR can't be reopened.
+pub async fn pr_reopen(client: &github::Client, repo: &str, number: u64) -> Result<()> {
+    let pr = client.get_pr(repo, number).await?;
+    let mut out = StateOut {
+        number,
+        state: pr.state.to_lowercase(),
+        comment_url: None,
+        deleted_branch: None,
+    };
+    match pr.state.as_str() {
+        "MERGED" => anyhow::bail!("PR #{number} is merged and can't be reopened"),
+        "OPEN" => eprintln!("warning: PR #{number} is already open; nothing to do"),
+        _ => {
+            client.check_budget(1)?;
+            out.state = client.set_pr_state(repo, number, "open").await?.state;
+        }
+    }
+    print_json(&out)
+}
+
+/// Bring the PR up to date with its base by merging it in, or with `rebase`, by
+/// rebasing onto it. Pins the head SHA read first so a concurrent push isn't lost.
+pub async fn pr_update_branch(client: &github::Client, repo: &str, number: u64, rebase: bool) -> Result<()> {
+    // metadata + the update
+    client.check_budget(2)?;
+    let pr = client.get_pr(repo, number).await?;
+    ensure_open(number, &pr.state, None)?;
+    let conflict = || {
+        anyhow::anyhow!(
+            "PR #{number} can't be updated automatically: {} conflicts with {}; resolve it locally and push",
+            pr.head_ref,
+            pr.base_ref
+        )
+    };
+
+    let out = if rebase {
+        let head_sha = match client.rebase_branch(&pr.node_id, &pr.head_sha).await {
+            Ok(sha) => sha,
+            Err(err) if format!("{err:#}").to_lowercase().contains("conflict") => return Err(conflict()),
+            Err(err) => return Err(err),
+        };
+        UpdateBranchOut {
+            number,
+            method: "rebase",
+            head_sha: Some(head_sha),
+            message: None,
+        }
+    } else {
+        let accepted = match client.update_branch(repo, number, &pr.head_sha).await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                let body = github::api_error(&err)
+                    .filter(|a| a.status == reqwest::StatusCode::UNPROCESSABLE_ENTITY)
+                    .map(|a| a.body.to_lowercase());
+                match body {
+                    Some(body) if body.contains("merge conflict") => return Err(conflict()),
+                    Some(body) if body.contains("expected head sha") => anyhow::bail!(
+                        "PR #{number}'s head moved past {} while updating; run the command again",
+                        format::short_sha(&pr.head_sha)
+                    ),
+                    _ => return Err(err),
+                }
+            }
+        };
+        UpdateBranchOut {
+            number,
+            method: "merge",
+            head_sha: None,
+            message: Some(accepted.message),
+        }
+    };
+    print_json(&out)
+}
+
+/// Check out the PR locally so `pr view --sem` and local tools see its head
+pub async fn pr_checkout(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    branch_name: Option<&str>,
+    detach: bool,
+    force: bool,
+) -> Result<()> {
+    let pr = client.get_pr(repo, number).await?;
+    // A fork's head is often named after the base ("main"); don't reset that one
+    let default_branch = if pr.head_ref == pr.base_ref {
+        format!("pr-{number}")
+    } else {
+        pr.head_ref.clone()
+    };
+    let branch = (!detach).then(|| branch_name.unwrap_or(&default_branch));
+
+    let checkout = git::checkout_pr(&std::env::current_dir()?, number, &pr.base_ref, branch, force)?;
+    if checkout.head_sha != pr.head_sha {
+        eprintln!(
+            "warning: checked out {}, but GitHub reports head {}; the PR may have just been pushed to",
+            format::short_sha(&checkout.head_sha),
+            format::short_sha(&pr.head_sha)
+        );
+    }
+    outln!(
+        "{} {}",
+        checkout.branch.as_deref().unwrap_or("(detached)"),
+        checkout.head_sha
+    );
+    Ok(())
+}
+
+/// Label edits split by whether they change anything. GitHub compares label
+/// names case-insensitively.
+#[derive(Debug, Default, PartialEq)]
+struct LabelPlan {
+    add: Vec<String>,
+    remove: Vec<String>,
+    already_present: Vec<String>,
+    already_absent: Vec<String>,
+}
+
+fn plan_labels(current: &[github::Label], add: &[String], remove: &[String]) -> LabelPlan {
+    let has = |name: &str| current.iter().any(|l| l.name.eq_ignore_ascii_case(name));
+    let mut plan = LabelPlan::default();
+    for name in add {
+        if has(name) {
+            plan.already_present.push(name.clone());
+        } else if !plan.add.iter().any(|a| a.eq_ignore_ascii_case(name)) {
+            plan.add.push(name.clone());
+        }
+    }
+    for name in remove {
+        match current.iter().find(|l| l.name.eq_ignore_ascii_case(name)) {
+            // Delete by the stored spelling
+            Some(label) if !plan.remove.contains(&label.name) => plan.remove.push(label.name.clone()),
+            Some(_) => {}
+            None => plan.already_absent.push(name.clone()),
+        }
+    }
+    plan
+}
+
+pub async fn pr_label(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    add: &[String],
+    remove: &[String],
+    list: bool,
+    allow_closed: bool,
+) -> Result<()> {
+    if add.is_empty() && remove.is_empty() && !list {
+        anyhow::bail!("Nothing to do: pass --add, --remove, or --list");
+    }
+    if !add.is_empty() || !remove.is_empty() {
+        ensure_pr_open(client, repo, number, allow_closed, false).await?;
+    }
+    let mut labels = client.get_labels(repo, number).await?;
+    let plan = plan_labels(&labels, add, remove);
+
+    client.check_budget(usize::from(!plan.add.is_empty()) + plan.remove.len())?;
+    if !plan.add.is_empty() {
+        labels = client.add_labels(repo, number, &plan.add).await?;
+    }
+    for name in &plan.remove {
+        client.remove_label(repo, number, name).await?;
+        labels.retain(|l| &l.name != name);
+    }
+
+    for name in &plan.add {
+        eprintln!("+ {name}");
+    }
+    for name in &plan.remove {
+        eprintln!("- {name}");
+    }
+    for name in &plan.already_present {
+        eprintln!("= {name} (already present)");
+    }
+    for name in &plan.already_absent {
+        eprintln!("= {name} (already absent)");
+    }
+    if list {
+        outln!("{}", format::format_labels(&labels));
+    }
+    Ok(())
+}
+
+/// What `pr assign` changes, from its flags
+#[derive(Default)]
+pub struct AssignOptions<'a> {
+    pub add: &'a [String],
+    pub remove: &'a [String],
+    /// Milestone title; "" clears it
+    pub milestone: Option<&'a str>,
+    pub json: bool,
+    pub allow_closed: bool,
+}
+
+/// Add or remove assignees and set the milestone by title ("" clears it), then
+/// show the resulting assignment. Logins that can't be assigned are reported
+/// one by one while the rest are still assigned.
+pub async fn pr_assign(client: &github::Client, repo: &str, number: u64, options: AssignOptions<'_>) -> Result<()> {
+    let AssignOptions {
+        add,
+        remove,
+        milestone,
+        json,
+        allow_closed,
+    } = options;
+    if !add.is_empty() || !remove.is_empty() || milestone.is_some() {
+        ensure_pr_open(client, repo, number, allow_closed, json).await?;
+    }
+    let trim = |logins: &[String]| -> Vec<String> { logins.iter().map(|l| l.trim_start_matches('@').to_string()).collect() };
+    let (add, remove) = (trim(add), trim(remove));
+
+    // Resolve everything before writing, so a bad milestone title changes nothing
+    let milestone = match milestone {
+        None => None,
+        Some("") => Some(None),
+        Some(title) => {
+            let milestones = client.get_milestones(repo).await?;
+            let found = milestones
+                .iter()
+                .find(|m| m.title == title)
+                .or_else(|| milestones.iter().find(|m| m.title.eq_ignore_ascii_case(title)));
+            match found {
+                Some(m) => Some(Some(m.number)),
+                None => anyhow::bail!(
+                    "No milestone titled \"{title}\" in {repo} (found: {})",
+                    if milestones.is_empty() {
+                        "none".to_string()
+                    } else {
+                        milestones.iter().map(|m| m.title.as_str()).collect::<Vec<_>>().join(", ")
+                    }
+                ),
+            }
+        }
+    };
+    let checks = futures::future::join_all(add.iter().map(|login| client.is_assignable(repo, login))).await;
+    let mut assignable = Vec::new();
+    let mut unassignable = Vec::new();
+    for (login, ok) in add.into_iter().zip(checks) {
+        if ok? {
+            assignable.push(login);
+        } else {
+            unassignable.push(login);
+        }
+    }
+
+    client.check_budget(
+        usize::from(!assignable.is_empty()) + usize::from(!remove.is_empty()) + usize::from(milestone.is_some()),
+    )?;
+    let mut assignment = None;
+    if !assignable.is_empty() {
+        assignment = Some(client.add_assignees(repo, number, &assignable).await?);
+    }
+    if !remove.is_empty() {
+        assignment = Some(client.remove_assignees(repo, number, &remove).await?);
+    }
+    if let Some(m) = milestone {
+        assignment = Some(client.set_milestone(repo, number, m).await?);
+    }
+    let assignment = match assignment {
+        Some(a) => a,
+        None => client.get_assignment(repo, number).await?,
+    };
+
+    for login in &assignable {
+        eprintln!("+ {login}");
+    }
+    for login in &remove {
+        eprintln!("- {login}");
+    }
+    for login in &unassignable {
+        eprintln!("? {login} (can't be assigned in {repo})");
+    }
+    if json {
+        return print_json(&AssignOut {
+            number,
+            assignees: assignment.assignees,
+            milestone: assignment.milestone.map(|m| m.title),
+            unassignable,
+        });
+    }
+    outln!("{}", format::format_assignment(&assignment));
+    Ok(())
+}
+
+/// What `pr reviewers` changes and shows, from its flags
+#[derive(Default)]
+pub struct ReviewersOptions<'a> {
+    pub add: &'a [String],
+    /// Teams as org/team
+    pub add_team: &'a [String],
+    pub remove: &'a [String],
+    pub list: bool,
+    pub allow_closed: bool,
+}
+
+/// Request or withdraw review requests, then optionally show who is requested
+/// and who has already reviewed
+pub async fn pr_reviewers(client: &github::Client, repo: &str, number: u64, options: ReviewersOptions<'_>) -> Result<()> {
+    let ReviewersOptions {
+        add,
+        add_team,
+        remove,
+        list,
+        allow_closed,
+    } = options;
+    if add.is_empty() && add_team.is_empty() && remove.is_empty() && !list {
+        anyhow::bail!("Nothing to do: pass --add, --add-team, --remove, or --list");
+    }
+    if let Some(team) = add_team.iter().find(|t| !t.contains('/')) {
+        anyhow::bail!("--add-team expects org/team, got: {team}");
+    }
+    let requests: Vec<String> = add.iter().chain(add_team).cloned().collect();
+    if !requests.is_empty() || !remove.is_empty() {
+        ensure_pr_open(client, repo, number, allow_closed, false).await?;
+    }
+
+    client.check_budget(usize::from(!requests.is_empty()) + usize::from(!remove.is_empty()))?;
+    if !requests.is_empty() {
+        if let Err(err) = client.request_reviewers(repo, number, &requests).await {
+            // GitHub refuses to ask a PR's author to review it with a 422
+            let author = github::api_error(&err).is_some_and(|a| {
+                a.status == reqwest::StatusCode::UNPROCESSABLE_ENTITY
+                    && a.body.to_lowercase().contains("pull request author")
+            });
+            if author {
+                anyhow::bail!(
+                    "GitHub can't request a review from the author of PR #{number}; drop them from --add"
+                );
+            }
+            return Err(err);
+        }
+    }
+    if !remove.is_empty() {
+        client.remove_requested_reviewers(repo, number, remove).await?;
+    }
+
+    for reviewer in &requests {
+        eprintln!("+ {reviewer}");
+    }
+    for reviewer in remove {
+        eprintln!("- {reviewer}");
+    }
+    if list {
+        let (requested, reviews) = tokio::try_join!(
+            client.get_requested_reviewers(repo, number),
+            client.get_reviews(repo, number),
+        )?;
+        outln!("{}", format::format_reviewers(&requested, &latest_review_per_user(&reviews)));
+    }
+    Ok(())
+}
+
+/// Remember this revision's smart analysis for `pr smart-diff`. Best effort: a
+/// missing record only means there's nothing to compare against later.
+fn record_smart(repo: &str, number: u64, head_sha: &str, changes: &[sem::SmartChange]) {
+    let record = SmartRecord {
+        head_sha: head_sha.to_string(),
+        recorded_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        changes: changes.to_vec(),
+    };
+    if let Err(e) = history::record_dir(repo, number).and_then(|dir| history::store(&dir, &record)) {
+        eprintln!("warning: could not record smart analysis: {e:#}");
+    }
+}
+
+/// Smart analysis of the PR's head: the recorded one, else computed (over
+/// non-noise files) and recorded
+async fn head_smart_changes(
+    client: &github::Client,
+    repo: &str,
+    pr: &github::PullRequest,
+    dir: &std::path::Path,
+) -> Vec<sem::SmartChange> {
+    if let Some(record) = history::load(dir, &pr.head_sha) {
+        return record.changes;
+    }
+    let (visible, _) = select_files(&pr.files, &FileFilter::default(), None, false, false);
+    let visible: Vec<github::PrFile> = visible.into_iter().cloned().collect();
+    eprintln!("smart: fetching file contents from GitHub API...");
+    let pairs = client.get_file_pairs(repo, &visible, &pr.base_ref, &pr.head_ref).await;
+    let changes = sem::run_sem_smart_json(&pairs);
+    record_smart(repo, pr.number, &pr.head_sha, &changes);
+    changes
+}
+
+/// Compare the smart analysis of the PR's current head with the last one recorded
+/// for an earlier head (by `pr view --smart` or a previous `pr smart-diff`)
+pub async fn pr_smart_diff(client: &github::Client, repo: &str, number: u64, json: bool) -> Result<()> {
+    let pr = client.get_pr(repo, number).await?;
+    note_state(&pr);
+    let dir = history::record_dir(repo, number)?;
+
+    let current = head_smart_changes(client, repo, &pr, &dir).await;
+
+    let Some(prev) = history::previous(&dir, &pr.head_sha) else {
+        anyhow::bail!(
+            "No earlier analysis of PR #{number} to compare against; recorded this one ({}) for next time",
+            format::short_sha(&pr.head_sha)
+        );
+    };
+    let diff = history::compare(&prev.changes, &current);
+
+    if json {
+        return print_json(&SmartDiffJson {
+            state: &pr.state,
+            from: &prev.head_sha,
+            to: &pr.head_sha,
+            diff: &diff,
+        });
+    }
+    outln!(
+        "{}",
+        format::format_smart_diff(&diff, &prev.head_sha, &pr.head_sha)
+    );
+    Ok(())
+}
+
+/// How far back `pr priorities` counts base commits per path
+const CHURN_WINDOW_DAYS: u64 = 90;
+/// Only the files ranked highest before churn get a churn lookup
+const CHURN_FILE_CAP: usize = 25;
+/// Churn lookups in flight at once
+const CHURN_BATCH: usize = 5;
+/// How long a path's churn count stays cached on disk
+const CHURN_CACHE_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Default, Serialize, Deserialize)]
+struct ChurnCache {
+    /// "owner/repo@base:path" -> count
+    entries: HashMap<String, ChurnEntry>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct ChurnEntry {
+    commits: usize,
+    fetched_at: u64,
+}
+
+/// Base-branch commit counts per path over the churn window, for the paths
+/// in `paths` (already capped). Served from the disk cache when fresh;
+/// misses are fetched a batch at a time, and a failed lookup leaves the path out.
+async fn path_churn(client: &github::Client, repo: &str, base: &str, paths: &[&str]) -> HashMap<String, usize> {
+    let cache_path = config::cache_dir().ok().map(|d| d.join("churn.json"));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mut cache: ChurnCache = cache_path
+        .as_ref()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default();
+    cache.entries.retain(|_, e| now.saturating_sub(e.fetched_at) < CHURN_CACHE_SECS);
+
+    let key = |path: &str| format!("{repo}@{base}:{path}");
+    let mut churn = HashMap::new();
+    let mut missing = Vec::new();
+    for &path in paths {
+        match cache.entries.get(&key(path)) {
+            Some(e) => {
+                churn.insert(path.to_string(), e.commits);
+            }
+            None => missing.push(path),
+        }
+    }
+
+    let since = priority::iso_date_days_ago(now, CHURN_WINDOW_DAYS);
+    for batch in missing.chunks(CHURN_BATCH) {
+        let futs = batch.iter().map(|path| client.count_path_commits(repo, path, base, &since));
+        for (path, result) in batch.iter().zip(futures::future::join_all(futs).await) {
+            if let Ok(commits) = result {
+                churn.insert(path.to_string(), commits);
+                cache.entries.insert(key(path), ChurnEntry { commits, fetched_at: now });
+            }
+        }
+    }
+
+    // Best effort: a missing cache only costs the lookups again next time
+    if let (Some(p), false) = (&cache_path, missing.is_empty()) {
+        if let (Some(dir), Ok(raw)) = (p.parent(), serde_json::to_string(&cache)) {
+            let _ = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(p, raw));
+        }
+    }
+    churn
+}
+
+/// Rank the PR's files by review priority, showing each score's factors
+pub async fn pr_priorities(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    settings: &config::PriorityConfig,
+    json: bool,
+) -> Result<()> {
+    let weights = priority::Weights::with_overrides(&settings.weights).context("Invalid [priority.weights] in config")?;
+    let pr = client.get_pr(repo, number).await?;
+    note_state(&pr);
+    let (comments, unresolved) = tokio::try_join!(
+        client.get_review_comments(repo, number),
+        client.get_unresolved_thread_ids(repo, number),
+    )?;
+    let smart = head_smart_changes(client, repo, &pr, &history::record_dir(repo, number)?).await;
+    // Ownership is optional; without any rules the factor just never applies
+    let owners = match fetch_ownership(client, repo, &pr, &changed_paths(&pr)).await {
+        Ok(rules) => Some(rules),
+        Err(e) if github::is_budget_exhausted(&e) => return Err(e),
+        Err(_) => None,
+    };
+
+    let (files, skipped) = select_files(&pr.files, &FileFilter::default(), None, false, false);
+    if skipped > 0 {
+        eprintln!("skipped {} noise files (lock/generated/minified)", skipped);
+    }
+    let mut signals: Vec<priority::FileSignals> = files
+        .iter()
+        .map(|f| {
+            let changes = smart.iter().filter(|c| c.file == f.filename);
+            let count = |category: &str| changes.clone().filter(|c| c.category == category).count();
+            priority::FileSignals {
+                path: f.filename.clone(),
+                behavioral: count("behavioral"),
+                new_logic: count("new_logic"),
+                mechanical: count("mechanical"),
+                code_additions: if priority::is_test_path(&f.filename) { 0 } else { f.additions },
+                sensitive: priority::sensitive_match(&f.filename, &settings.sensitive),
+                owners: owners.as_ref().map(|o| o.owners_of(&f.filename)).unwrap_or_default(),
+                unresolved: comments
+                    .iter()
+                    .filter(|c| c.path == f.filename && c.id == c.thread_id && unresolved.contains(&c.id))
+                    .count(),
+                churn: None,
+            }
+        })
+        .collect();
+
+    if weights.churn != 0.0 {
+        let ranked = priority::rank(&signals, &weights, repo, number);
+        let top: Vec<&str> = ranked.iter().take(CHURN_FILE_CAP).map(|r| r.path.as_str()).collect();
+        if ranked.len() > top.len() {
+            eprintln!("churn: looked up the top {} of {} files", top.len(), ranked.len());
+        }
+        let churn = path_churn(client, repo, &pr.base_ref, &top).await;
+        for s in &mut signals {
+            s.churn = churn.get(&s.path).copied();
+        }
+    }
+
+    let ranked = priority::rank(&signals, &weights, repo, number);
+    if json {
+        return print_json(&PrioritiesJson { state: pr.state, files: ranked });
+    }
+    outln!("{}", priority::format_ranked(&ranked));
+    Ok(())
+}
+
+/// Per-file cap on diffs embedded by `pr report --include-diff`
+const REPORT_DIFF_CAP_BYTES: usize = 20_000;
+
+/// Render the whole PR review as one markdown document, to `out` or stdout
+pub async fn pr_report(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    out: Option<&str>,
+    include_diff: bool,
+) -> Result<()> {
+    let fetch_pr = async {
+        if include_diff {
+            client.get_pr_with_patches(repo, number).await
+        } else {
+            client.get_pr(repo, number).await
+        }
+    };
+    let (pr, status, comments, unresolved) = tokio::try_join!(
+        fetch_pr,
+        client.get_pr_status(repo, number),
+        client.get_review_comments(repo, number),
+        client.get_unresolved_thread_ids(repo, number),
+    )?;
+    let smart = history::record_dir(repo, number)
+        .ok()
+        .and_then(|dir| history::load(&dir, &pr.head_sha));
+
+    let report = report::render(&report::ReportInput {
+        repo,
+        pr: &pr,
+        status: &status,
+        smart: smart.as_ref().map(|r| r.changes.as_slice()),
+        comments: &comments,
+        unresolved: &unresolved,
+        diff_cap: include_diff.then_some(REPORT_DIFF_CAP_BYTES),
+    });
+    match out {
+        Some(path) => {
+            std::fs::write(path, &report).with_context(|| format!("Failed to write {path}"))?;
+            eprintln!("Wrote report for PR #{number} to {path}");
+        }
+        None => out!("{report}"),
+    }
+    Ok(())
+}
+
+/// `pr files` selection: the noise filter unless `include_all`, then `statuses`
+/// (any status when empty). Also returns how many noise files were skipped.
+fn listed_files<'a>(
+    files: &'a [github::PrFile],
+    include_all: bool,
+    statuses: &[String],
+) -> (Vec<&'a github::PrFile>, usize) {
+    let (files, skipped) = select_files(files, &FileFilter::default(), None, include_all, false);
+    let files = files
+        .into_iter()
+        .filter(|f| statuses.is_empty() || statuses.contains(&f.status))
+        .collect();
+    (files, skipped)
+}
+
+/// Just the changed paths, one per line, for piping into other tools
+pub async fn pr_files(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    include_all: bool,
+    statuses: &[String],
+    json: bool,
+) -> Result<()> {
+    let pr = client.get_pr(repo, number).await?;
+    note_state(&pr);
+    let (files, skipped) = listed_files(&pr.files, include_all, statuses);
+    if skipped > 0 {
+        eprintln!("skipped {} noise files (lock/generated/minified). Use --all to include.", skipped);
+    }
+
+    if json {
+        return print_json(&FilesJson {
+            state: pr.state.clone(),
+            files: files.into_iter().map(FileStatJson::from).collect(),
+        });
+    }
+    for f in files {
+        outln!("{}", f.filename);
+    }
+    Ok(())
+}
+
+/// Which PRs `pr list` asks for
+pub struct ListFilters<'a> {
+    pub state: &'a str,
+    pub author: Option<&'a str>,
+    pub labels: &'a [String],
+    pub base: Option<&'a str>,
+}
+
+pub async fn pr_list(
+    client: &github::Client,
+    repo: &str,
+    filters: &ListFilters<'_>,
+    limit: usize,
+    json: bool,
+) -> Result<()> {
+    let ListFilters {
+        state,
+        author,
+        labels,
+        base,
+    } = *filters;
+    let prs = client.list_prs(repo, state, author, labels, base, limit).await?;
+
+    if json {
+        let out: Vec<PrListJson> = prs
+            .into_iter()
+            .map(|pr| PrListJson {
+                number: pr.number,
+                title: pr.title,
+                body: pr.body,
+                state: pr.state,
+                author: pr.author,
+                head_sha: pr.head_sha,
+                head_ref: pr.head_ref,
+                base_ref: pr.base_ref,
+                additions: pr.additions,
+                deletions: pr.deletions,
+                changed_files: pr.changed_files,
+                updated_at: pr.updated_at,
+            })
+            .collect();
+        return print_json(&out);
+    }
+
+    if prs.is_empty() {
+        outln!("No pull requests found.");
+        return Ok(());
+    }
+    outln!("{}", format::format_pr_list(&prs));
+    Ok(())
+}
+
+/// `pr diff --name-only` / `--name-status`
+#[derive(Clone, Copy, PartialEq)]
+pub enum NameMode {
+    /// Paths, one per line
+    Only,
+    /// `M\tpath`, as `git diff --name-status`
+    Status,
+}
+
+/// `pr diff --format`
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum DiffFormat {
+    /// Line-numbered hunks with stable ids, for reading and commenting
+    #[default]
+    Numbered,
+    /// Plain unified diff text for `git apply`
+    Patch,
+    /// A markdown stat table and a collapsed section per file, for pasting
+    Markdown,
+}
+
+/// `git diff --name-status` letter for a GitHub file status
+fn status_letter(status: &str) -> char {
+    match status {
+        "added" => 'A',
+        "removed" => 'D',
+        "renamed" => 'R',
+        "copied" => 'C',
+        _ => 'M',
+    }
+}
+
+/// Which changes `pr diff` shows
+#[derive(Default)]
+pub enum DiffRange<'a> {
+    /// The whole PR
+    #[default]
+    Full,
+    /// `--from`/`--to`: from a commit (else the merge base) to a commit (else the head)
+    Commits { from: Option<&'a str>, to: Option<&'a str> },
+    /// `--since-review`: from the commit of your last submitted review
+    SinceReview,
+    /// `--since`: from the last PR commit authored before this time
+    Since(&'a str),
+}
+
+/// The commit `range` starts from, or None for the full diff (with a warning when
+/// there's no such commit)
+async fn range_start(client: &github::Client, repo: &str, number: u64, range: &DiffRange<'_>) -> Result<Option<String>> {
+    match range {
+        DiffRange::Full => Ok(None),
+        DiffRange::Commits { from, .. } => Ok(from.map(str::to_string)),
+        DiffRange::SinceReview => {
+            let (login, reviews) = tokio::try_join!(client.get_authenticated_user(), client.get_reviews(repo, number))?;
+            let last = reviews
+                .into_iter()
+                .rev()
+                .find(|r| r.author == login && r.submitted_at.is_some() && r.commit_id.is_some());
+            let Some(review) = last else {
+                eprintln!("warning: {login} has not reviewed PR #{number} yet; showing the full diff");
+                return Ok(None);
+            };
+            let sha = review.commit_id.unwrap_or_default();
+            eprintln!(
+                "since-review: your last review ({}) was at {}",
+                review.submitted_at.unwrap_or_default(),
+                format::short_sha(&sha)
+            );
+            Ok(Some(sha))
+        }
+        DiffRange::Since(time) => {
+            let since = since_timestamp(time)?;
+            let commits = client.get_pr_commits(repo, number).await?;
+            match commits.into_iter().rev().find(|c| c.date < since) {
+                Some(c) => {
+                    eprintln!("since: {} ({}) is the last commit before {since}", format::short_sha(&c.sha), c.date);
+                    Ok(Some(c.sha))
+                }
+                None => {
+                    eprintln!("warning: no commit in PR #{number} is older than {since}; showing the full diff");
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+/// `--since` as GitHub writes timestamps, so the two compare as strings:
+/// `YYYY-MM-DD` is midnight UTC, and a time must be UTC (`Z`)
+fn since_timestamp(time: &str) -> Result<String> {
+    // 9 stands for any digit
+    let fits = |shape: &str| {
+        time.len() == shape.len()
+            && time.bytes().zip(shape.bytes()).all(|(c, s)| if s == b'9' { c.is_ascii_digit() } else { c == s })
+    };
+    if fits("9999-99-99") {
+        Ok(format!("{time}T00:00:00Z"))
+    } else if fits("9999-99-99T99:99Z") {
+        Ok(format!("{}:00Z", &time[..16]))
+    } else if fits("9999-99-99T99:99:99Z") {
+        Ok(time.to_string())
+    } else {
+        anyhow::bail!("Invalid --since {time:?}: expected YYYY-MM-DD or YYYY-MM-DDTHH:MM[:SS]Z (UTC)")
+    }
+}
+
+/// What `pr diff` shows and how, from its flags
+#[derive(Default)]
+pub struct DiffOptions<'a> {
+    /// --file and --file-regex
+    pub file_filter: FileFilter,
+    pub smart_files: bool,
+    pub owned_by: Option<&'a str>,
+    /// --all: keep noise files
+    pub include_all: bool,
+    pub auto_noise: bool,
+    /// --stat, with --sort and --graph
+    pub stat_only: bool,
+    pub stat_sort: Option<format::StatSort>,
+    pub graph: bool,
+    pub json: bool,
+    pub json_full: bool,
+    /// --hunk ids
+    pub hunk_filters: &'a [String],
+    /// --lines
+    pub line_ranges: &'a [diff::LineRange],
+    pub min_move_lines: usize,
+    pub collapse_moves: bool,
+    pub word_diff: Option<format::WordDiff>,
+    pub resolve_context: bool,
+    pub ignore_whitespace: bool,
+    pub exclude: &'a [String],
+    /// --name-only or --name-status
+    pub names: Option<NameMode>,
+    /// --format
+    pub output: DiffFormat,
+    pub out_dir: Option<&'a str>,
+    pub clean: bool,
+    pub range: DiffRange<'a>,
+    /// --net-only: note the commit pairs that cancel out
+    pub net_only: bool,
+    pub max_lines_per_file: Option<usize>,
+    pub max_total_lines: Option<usize>,
+    /// --max-bytes or --max-tokens
+    pub limit: Option<Limit>,
+}
+
+pub async fn pr_diff(client: &github::Client, repo: &str, number: u64, options: DiffOptions<'_>) -> Result<()> {
+    let DiffOptions {
+        file_filter,
+        smart_files,
+        owned_by,
+        include_all,
+        auto_noise,
+        stat_only,
+        stat_sort,
+        graph,
+        json,
+        json_full,
+        hunk_filters,
+        line_ranges,
+        min_move_lines,
+        collapse_moves,
+        word_diff,
+        resolve_context,
+        ignore_whitespace,
+        exclude,
+        names,
+        output,
+        out_dir,
+        clean,
+        range,
+        net_only,
+        max_lines_per_file,
+        max_total_lines,
+        limit,
+    } = options;
+    let file_filter = &file_filter;
+    let excludes = exclude_globs(exclude)?;
+    // A range: how the PR's files changed between two of its commits. The
+    // three-dot compare of the base branch diffs from the merge base.
+    let from = range_start(client, repo, number, &range).await?;
+    let to = match range {
+        DiffRange::Commits { to, .. } => to,
+        _ => None,
+    };
+    let (pr, base, head) = if from.is_some() || to.is_some() {
+        let pr = client.get_pr(repo, number).await?;
+        let base = from.as_deref().unwrap_or(&pr.base_ref).to_string();
+        let head = to.unwrap_or(&pr.head_sha).to_string();
+        eprintln!(
+            "range: {}..{}",
+            from.as_deref().map_or("merge base", format::short_sha),
+            to.map_or("head", format::short_sha)
+        );
+        (client.with_range_patches(repo, pr, &base, &head).await?, base, head)
+    } else {
+        // Listing names needs no patches, unless --hunk or --auto-noise reads them
+        let pr = if names.is_some() && hunk_filters.is_empty() && !auto_noise {
+            client.get_pr(repo, number).await?
+        } else {
+            client.get_pr_with_patches(repo, number).await?
+        };
+        let (base, head) = (pr.base_ref.clone(), pr.head_sha.clone());
+        (pr, base, head)
+    };
+    note_state(&pr);
+    if net_only {
+        // The full diff is the net change by construction; name what netted out
+        let commits = client.get_pr_commits(repo, number).await?;
+        let details = futures::future::join_all(commits.iter().map(|c| client.get_commit_detail(repo, &c.sha))).await;
+        let details: Vec<_> = details.into_iter().map(|r| r.ok()).collect();
+        eprint!("{}", format_net_note(&commits, &revert_pairs(&details)));
+    }
+    // --hunk names hunks directly and takes precedence over the file filters
+    let hunk_selection = select_hunks(&pr.files, hunk_filters)?;
+    let owned = resolve_owned_by(client, repo, &pr, &changed_paths(&pr), owned_by).await?;
+
+    // Build the file filter list: --smart-files fetches contents from API, runs sem, filters
+    let smart_list = if smart_files && hunk_selection.is_empty() {
+        eprintln!("smart: fetching file contents from GitHub API...");
+        let pairs = client
+            .get_file_pairs(repo, &pr.files, &base, &head)
+            .await;
+        match sem::get_smart_files_from_pairs(&pairs) {
+            Some(sf) => {
+                eprintln!("smart: filtering to {} files (skipped mechanical)", sf.len());
+                sf
+            }
+            None => {
+                eprintln!("smart: sem analysis failed, showing all files");
+                vec![]
+            }
+        }
+    } else {
+        vec![]
+    };
+
+    let candidates: Vec<&github::PrFile> = if file_filter.is_empty() && smart_files && !smart_list.is_empty() {
+        // --smart-files with successful sem: exact path match
+        pr.files
+            .iter()
+            .filter(|f| smart_list.iter().any(|sf| f.filename == *sf))
+            .collect()
+    } else {
+        // Explicit --file flags (substrings or globs, applied below), no filter, or sem fallback
+        pr.files.iter().collect()
+    };
+
+    if auto_noise && !include_all {
+        for f in &candidates {
+            if file_noise_reason(f, true) == Some(NoiseReason::BulkGenerated) {
+                eprintln!("auto-noise: {} looks generated ({}); --all shows it", f.filename, NoiseReason::BulkGenerated);
+            }
+        }
+    }
+
+    // --file, --owned-by, then the noise filter unless --all is set
+    let (files, skipped) = if hunk_selection.is_empty() {
+        select_files(candidates, file_filter, owned.as_ref(), include_all, auto_noise)
+    } else {
+        (pr.files.iter().filter(|f| hunk_selection.contains_key(&f.filename)).collect(), 0)
+    };
+    report_owned_by(owned_by, files.len() + skipped);
+    // --exclude goes last, after --smart-files and the noise filter
+    let files = exclude_paths(files, &excludes, |f| f.filename.as_str());
+    let only_hunks = |f: &github::PrFile| hunk_selection.get(&f.filename).map_or(&[][..], Vec::as_slice);
+
+    if skipped > 0 {
+        eprintln!("skipped {} noise files (lock/generated/minified). Use --all to include.", skipped);
+    }
+
+    // --ignore-whitespace: modified files' diffs recomputed from both versions,
+    // empty for those that only changed whitespace
+    let recomputed: HashMap<String, github::PrFile> = if ignore_whitespace {
+        let old_ref = match &from {
+            Some(sha) => sha.clone(),
+            None => client.compare(repo, &pr.base_ref, &head).await?.merge_base_sha,
+        };
+        let modified: Vec<github::PrFile> = files.iter().filter(|f| f.status == "modified").map(|f| (*f).clone()).collect();
+        let pairs = client.get_file_pairs(repo, &modified, &old_ref, &head).await;
+        modified
             .into_iter()
-            .filter(|f| !is_noise_file(&f.filename))
+            .zip(pairs)
+            .filter_map(|(f, (_, _, before, after))| {
+                let patch = diff::whitespace_insensitive_patch(&before?, &after?);
+                Some((f.filename.clone(), github::PrFile { patch: Some(patch), ..f }))
+            })
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    let whitespace_only = |f: &github::PrFile| f.patch.as_deref() == Some("") && recomputed.contains_key(&f.filename);
+    let stat_files: Vec<&github::PrFile> = files.iter().map(|f| recomputed.get(&f.filename).unwrap_or(f)).collect();
+    let files: Vec<&github::PrFile> = stat_files.iter().copied().filter(|f| !whitespace_only(f)).collect();
+    if stat_files.len() > files.len() {
+        eprintln!("ignore-whitespace: {} files changed only in whitespace", stat_files.len() - files.len());
+    }
+
+    // --lines: the one file's patch cut down to the hunks (and parts of hunks) at
+    // those lines, each part keeping its hunk's id. --hunk picks the hunks first.
+    let trimmed = if line_ranges.is_empty() {
+        None
+    } else {
+        Some(trim_to_lines(&files, line_ranges, &hunk_selection)?)
+    };
+    let (files, trimmed_ids) = match &trimmed {
+        Some((f, ids)) => (vec![f], Some(ids.as_slice())),
+        None => (files, None),
+    };
+    let only_hunks = |f: &github::PrFile| if trimmed.is_some() { &[][..] } else { only_hunks(f) };
+
+    if let Some(mode) = names {
+        if json {
+            let files = files
+                .iter()
+                .map(|f| FileNameJson {
+                    path: f.filename.clone(),
+                    status: f.status.clone(),
+                    binary: f.binary,
+                })
+                .collect();
+            return print_json(&FileNamesJson { state: pr.state.clone(), files });
+        }
+        for f in &files {
+            match mode {
+                NameMode::Only => outln!("{}", f.filename),
+                NameMode::Status => outln!("{}\t{}", status_letter(&f.status), f.filename),
+            }
+        }
+        return Ok(());
+    }
+
+    // --format patch: the diff as `git apply` takes it
+    if output == DiffFormat::Patch {
+        if let Some(dir) = out_dir {
+            let texts = files.iter().map(|f| format::format_git_patch(f, only_hunks(f))).collect();
+            return write_out_dir(dir, clean, &files, texts, "diff");
+        }
+        let mut patches = Vec::new();
+        let mut left_out = Vec::new();
+        for f in &files {
+            match format::format_git_patch(f, only_hunks(f)) {
+                Some(patch) => patches.push(patch),
+                None => left_out.push(f.filename.as_str()),
+            }
+        }
+        if !left_out.is_empty() {
+            eprintln!("patch: left out {} files with no text patch (binary or too large): {}", left_out.len(), left_out.join(", "));
+        }
+        if !patches.is_empty() {
+            outln!("{}", patches.join("\n"));
+        }
+        return Ok(());
+    }
+
+    if output == DiffFormat::Markdown {
+        if let Some(dir) = out_dir {
+            let texts = files.iter().map(|f| Some(format::format_markdown_file(f, only_hunks(f)))).collect();
+            return write_out_dir(dir, clean, &files, texts, "md");
+        }
+        let mut out = vec![format::format_markdown_stat_table(&files)];
+        out.extend(files.iter().map(|f| format::format_markdown_file(f, only_hunks(f))));
+        outln!("{}", out.join("\n\n"));
+        return Ok(());
+    }
+
+    // --resolve-context: the head (or --to) version of each file with a hunk header naming no function
+    let sources: HashMap<String, String> = if resolve_context {
+        let paths: Vec<String> = files
+            .iter()
+            .filter(|f| f.status != "removed")
+            .filter(|f| f.patch.as_deref().is_some_and(|p| parse_patch(p).iter().any(|h| h.context.is_empty())))
+            .map(|f| f.filename.clone())
+            .collect();
+        fetch_file_contents(client, repo, &paths, &head).await.0.into_iter().collect()
+    } else {
+        HashMap::new()
+    };
+
+    if json || json_full {
+        let mut map = HashMap::new();
+        let mut hunk_map = HashMap::new();
+        let mut full_files = Vec::new();
+        let mut used = 0;
+        let mut truncated = limit.map(|_| TruncatedJson {
+            emitted_tokens: 0,
+            omitted_tokens: 0,
+            omitted_files: vec![],
+        });
+        for f in &files {
+            let (ids, mut hunks): (Vec<String>, Vec<DiffHunk>) = match (trimmed_ids, f.patch.as_deref()) {
+                (Some(ids), Some(p)) => (ids.to_vec(), parse_patch(p)),
+                (_, patch) => patch
+                    .map(|p| identified_hunks(&f.filename, p, only_hunks(f)))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .unzip(),
+            };
+            if let Some(content) = sources.get(&f.filename) {
+                diff::resolve_contexts(&mut hunks, content);
+            }
+            let cl = commentable_lines(&hunks);
+            let hunk_list: Vec<HunkJson> = ids
+                .iter()
+                .cloned()
+                .zip(&hunks)
+                .map(|(id, h)| HunkJson {
+                    id,
+                    old_start: h.old_start,
+                    old_count: h.old_count,
+                    new_start: h.new_start,
+                    new_count: h.new_count,
+                    context: h.context.clone(),
+                })
+                .collect();
+            let full = json_full.then(|| file_diff_json(f, ids, hunks));
+            // Whole files only: a partial line list would look commentable-complete
+            if let (Some(limit), Some(t)) = (limit, truncated.as_mut()) {
+                let entry = match &full {
+                    Some(full) => serde_json::to_string(&(full, &cl))?,
+                    None => serde_json::to_string(&(&f.filename, &cl, &hunk_list))?,
+                };
+                used += limit.measure(&entry);
+                if used > limit.max() || !t.omitted_files.is_empty() {
+                    t.omitted_tokens += budget::estimate_tokens(&entry);
+                    t.omitted_files.push(f.filename.clone());
+                    continue;
+                }
+                t.emitted_tokens += budget::estimate_tokens(&entry);
+            }
+            map.insert(f.filename.clone(), cl);
+            hunk_map.insert(f.filename.clone(), hunk_list);
+            full_files.extend(full);
+        }
+        let truncated = truncated.filter(|t| !t.omitted_files.is_empty());
+        let totals = TotalsJson::of(files.iter().copied().filter(|f| map.contains_key(&f.filename)));
+        if json_full {
+            return print_json(&DiffFullJson {
+                state: pr.state.clone(),
+                totals,
+                files: full_files,
+                commentable: map,
+                truncated,
+            });
+        }
+        let mut binary: Vec<String> =
+            files.iter().filter(|f| f.binary && map.contains_key(&f.filename)).map(|f| f.filename.clone()).collect();
+        binary.sort();
+        return print_json(&DiffJson {
+            state: pr.state.clone(),
+            files: map,
+            totals,
+            hunks: hunk_map,
+            binary,
+            truncated,
+        });
+    }
+
+    if stat_only {
+        let mut borrowed: Vec<github::PrFile> = stat_files
+            .iter()
+            .map(|f| {
+                let mut f = (*f).clone();
+                if whitespace_only(&f) {
+                    f.filename.push_str(" (whitespace-only)");
+                }
+                f
+            })
+            .collect();
+        if let Some(sort) = stat_sort {
+            sort.sort(&mut borrowed);
+        }
+        outln!("{}", format::format_stat_table(&borrowed, graph));
+        return Ok(());
+    }
+
+    let sizes = binary_sizes(client, repo, &files, &base, &head).await;
+    if let Some(dir) = out_dir {
+        // Files get plain text, even when the terminal gets color
+        let _plain = format::override_color(false);
+        let texts = files
+            .iter()
+            .map(|f| {
+                let view = format::DiffView {
+                    only_hunks: only_hunks(f),
+                    hunk_ids: trimmed_ids,
+                    min_move_lines,
+                    collapse_moves,
+                    word_diff,
+                    context_source: sources.get(&f.filename).map(String::as_str),
+                    max_lines: max_lines_per_file,
+                    binary_sizes: sizes.get(&f.filename).copied().unwrap_or_default(),
+                };
+                Some(format::format_line_numbered_diff(f, &view))
+            })
+            .collect();
+        return write_out_dir(dir, clean, &files, texts, "diff");
+    }
+    let mut out = Vec::new();
+    // --max-total-lines: what's left of it, and the files and lines past it
+    let mut remaining = max_total_lines;
+    let (mut omitted_files, mut omitted_lines) = (0, 0);
+    for f in &files {
+        let mut view = format::DiffView {
+            only_hunks: only_hunks(f),
+            hunk_ids: trimmed_ids,
+            min_move_lines,
+            collapse_moves,
+            word_diff,
+            context_source: sources.get(&f.filename).map(String::as_str),
+            max_lines: None,
+            binary_sizes: sizes.get(&f.filename).copied().unwrap_or_default(),
+        };
+        if remaining == Some(0) {
+            omitted_files += 1;
+            omitted_lines += format::format_line_numbered_diff(f, &view).lines().count();
+            continue;
+        }
+        view.max_lines = match (max_lines_per_file, remaining) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let text = format::format_line_numbered_diff(f, &view);
+        if let Some(r) = remaining.as_mut() {
+            *r = r.saturating_sub(text.lines().count());
+        }
+        out.push(text);
+    }
+    if omitted_files > 0 {
+        let s = if omitted_files == 1 { "" } else { "s" };
+        out.push(format!(
+            "… ({omitted_files} more file{s}, {} lines, past --max-total-lines)",
+            format::thousands(omitted_lines)
+        ));
+    }
+    print_limited(&out.join("\n\n"), limit);
+    Ok(())
+}
+
+/// `pr diff --out-dir`: write each file's text (None: nothing to write) to its own
+/// file in `dir`, plus an index.json listing them. `clean` first removes the
+/// files an earlier run's index.json lists, and that index.
+fn write_out_dir(dir: &str, clean: bool, files: &[&github::PrFile], texts: Vec<Option<String>>, ext: &str) -> Result<()> {
+    let root = std::path::Path::new(dir);
+    std::fs::create_dir_all(root).with_context(|| format!("Failed to create {dir}"))?;
+    if clean {
+        clean_out_dir(root)?;
+    }
+    let mut taken = HashSet::new();
+    let mut index = Vec::new();
+    for (f, text) in files.iter().zip(texts) {
+        let output_file = match text {
+            Some(text) => {
+                let name = out_file_name(&f.filename, ext, &mut taken);
+                let path = root.join(&name);
+                std::fs::write(&path, text + "\n").with_context(|| format!("Failed to write {}", path.display()))?;
+                Some(name)
+            }
+            None => None,
+        };
+        index.push(OutFileJson {
+            path: f.filename.clone(),
+            status: f.status.clone(),
+            additions: f.additions,
+            deletions: f.deletions,
+            output_file,
+        });
+    }
+    let written = index.iter().filter(|e| e.output_file.is_some()).count();
+    let path = root.join("index.json");
+    std::fs::write(&path, serde_json::to_string_pretty(&index)? + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    let s = if written == 1 { "" } else { "s" };
+    eprintln!("Wrote {written} file diff{s} and index.json to {dir}");
+    Ok(())
+}
+
+/// Remove what the last `--out-dir` run into `root` wrote, going by its
+/// index.json; anything else there (a README, notes) is left alone
+fn clean_out_dir(root: &std::path::Path) -> Result<()> {
+    let index = root.join("index.json");
+    let Ok(text) = std::fs::read_to_string(&index) else {
+        return Ok(());
+    };
+    let listed: Vec<OutFileJson> = serde_json::from_str(&text)
+        .with_context(|| format!("{} isn't an index --out-dir wrote; not cleaning", index.display()))?;
+    // Only bare names, as they're written; never a path out of the directory
+    let names = listed.iter().filter_map(|e| e.output_file.as_deref());
+    for name in names.filter(|n| std::path::Path::new(n).file_name() == Some(std::ffi::OsStr::new(n))) {
+        let path = root.join(name);
+        if path.is_file() {
+            std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    std::fs::remove_file(&index).with_context(|| format!("Failed to remove {}", index.display()))
+}
+
+/// A file name in one directory for the diff of `path`: `/` becomes `__`,
+/// characters some filesystems refuse become `_`, and a name already in `taken`
+/// (compared without case, for case-insensitive filesystems) gets `~2`, `~3`, …
+fn out_file_name(path: &str, ext: &str, taken: &mut HashSet<String>) -> String {
+    let stem: String = path
+        .replace('/', "__")
+        .chars()
+        .map(|c| if c.is_control() || "\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    let mut name = format!("{stem}.{ext}");
+    let mut n = 1;
+    while !taken.insert(name.to_lowercase()) {
+        n += 1;
+        name = format!("{stem}~{n}.{ext}");
+    }
+    name
+}
+
+/// Each binary file's size in bytes before and after the change, where the
+/// contents API has it
+async fn binary_sizes(
+    client: &github::Client,
+    repo: &str,
+    files: &[&github::PrFile],
+    base: &str,
+    head: &str,
+) -> HashMap<String, (Option<u64>, Option<u64>)> {
+    let futs = files.iter().filter(|f| f.binary).map(|f| async move {
+        let old_path = f.previous_filename.as_deref().unwrap_or(&f.filename);
+        let before = match f.status.as_str() {
+            "added" => None,
+            _ => client.get_file_size(repo, old_path, base).await.ok(),
+        };
+        let after = match f.status.as_str() {
+            "removed" => None,
+            _ => client.get_file_size(repo, &f.filename, head).await.ok(),
+        };
+        (f.filename.clone(), (before, after))
+    });
+    futures::future::join_all(futs).await.into_iter().collect()
+}
+
+/// The one file in `files` with its patch cut down to `ranges`, for `pr diff
+/// --lines`, and the id of the hunk each remaining part was cut from. Only the
+/// hunks in `selection` (from --hunk) are cut from, when it names the file.
+fn trim_to_lines(
+    files: &[&github::PrFile],
+    ranges: &[diff::LineRange],
+    selection: &HashMap<String, Vec<String>>,
+) -> Result<(github::PrFile, Vec<String>)> {
+    let shown = ranges.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+    let file = match files {
+        [file] => file,
+        [] => anyhow::bail!("--lines {shown}: no file in the diff matches; pick one with --file"),
+        _ => anyhow::bail!(
+            "--lines {shown}: {} files match, but it needs exactly one; narrow --file down to one of: {}",
+            files.len(),
+            files.iter().map(|f| f.filename.as_str()).collect::<Vec<_>>().join(", ")
+        ),
+    };
+    let Some(patch) = file.patch.as_deref() else {
+        anyhow::bail!("--lines {shown}: {} has no text diff (binary or too large)", file.filename);
+    };
+    let ids = diff::hunk_ids(&file.filename, &parse_patch(patch));
+    let only = selection.get(&file.filename).map_or(&[][..], Vec::as_slice);
+    let (ids, pieces): (Vec<String>, Vec<String>) = diff::trim_patch_to_lines(patch, ranges, diff::LINES_CONTEXT)
+        .into_iter()
+        .map(|(i, piece)| (ids[i].clone(), piece))
+        .filter(|(id, _)| only.is_empty() || only.contains(id))
+        .unzip();
+    if pieces.is_empty() {
+        anyhow::bail!("--lines {shown}: no changes in {} at those lines", file.filename);
+    }
+    let file = github::PrFile {
+        patch: Some(pieces.join("\n")),
+        ..(*file).clone()
+    };
+    Ok((file, ids))
+}
+
+/// Resolve `--hunk` ids to the ids to show per file. Empty when no ids are given.
+fn select_hunks(files: &[github::PrFile], ids: &[String]) -> Result<HashMap<String, Vec<String>>> {
+    let mut selection: HashMap<String, Vec<String>> = HashMap::new();
+    if ids.is_empty() {
+        return Ok(selection);
+    }
+    let index: Vec<(&str, Vec<String>)> = files
+        .iter()
+        .map(|f| {
+            let ids = f.patch.as_deref().map(|p| diff::hunk_ids(&f.filename, &parse_patch(p)));
+            (f.filename.as_str(), ids.unwrap_or_default())
+        })
+        .collect();
+    for id in ids {
+        let (path, i) = diff::resolve_hunk(id, index.iter().map(|(p, ids)| (*p, ids.as_slice())))?;
+        let ids = &index.iter().find(|(p, _)| *p == path).expect("resolved path is indexed").1;
+        selection.entry(path.to_string()).or_default().push(ids[i].clone());
+    }
+    Ok(selection)
+}
+
+pub async fn pr_comments(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    file_filters: &[String],
+    unresolved: bool,
+    json: bool,
+) -> Result<()> {
+    let (mut comments, unresolved_ids) = if unresolved {
+        let (comments, ids) = tokio::try_join!(
+            client.get_review_comments(repo, number),
+            client.get_unresolved_thread_ids(repo, number),
+        )?;
+        (comments, Some(ids))
+    } else {
+        (client.get_review_comments(repo, number).await?, None)
+    };
+
+    comments.retain(|c| {
+        (file_filters.is_empty() || file_filters.iter().any(|f| c.path.contains(f.as_str())))
+            && unresolved_ids.as_ref().is_none_or(|ids| ids.contains(&c.thread_id))
+    });
+    let threads = format::group_threads(&comments);
+    let originals = suggestion_originals(client, repo, &comments).await?;
+
+    if json {
+        let comments = threads
+            .iter()
+            .flatten()
+            .map(|c| ReviewCommentJson {
+                id: c.id,
+                thread_id: c.thread_id,
+                path: c.path.clone(),
+                line: c.line,
+                outdated: c.outdated,
+                author: c.author.clone(),
+                created_at: c.created_at.clone(),
+                body: c.body.clone(),
+                suggestions: format::parse_suggestions(&c.body)
+                    .into_iter()
+                    .map(|b| SuggestionJson {
+                        original_lines: originals.get(&c.id).cloned(),
+                        suggested_lines: b.lines.iter().map(|l| l.to_string()).collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        return print_json(&CommentsJson { state: json_state(client, repo, number).await?, comments });
+    }
+
+    outln!("{}", format::format_review_threads(&threads, &originals));
+    Ok(())
+}
+
+/// Most distinct files `pr comments` reads to show what suggestions replace
+const SUGGESTION_FILE_LIMIT: usize = 20;
+
+/// The lines each suggestion comment would replace, keyed by comment id, read at
+/// the commit the comment points to. Each file is fetched once, for at most
+/// `SUGGESTION_FILE_LIMIT` files; comments whose file is past the limit or
+/// can't be read are left out.
+async fn suggestion_originals(
+    client: &github::Client,
+    repo: &str,
+    comments: &[github::ReviewComment],
+) -> Result<HashMap<u64, Vec<String>>> {
+    let wanted: Vec<&github::ReviewComment> = comments
+        .iter()
+        .filter(|c| c.line.is_some() && !c.commit_id.is_empty() && !format::parse_suggestions(&c.body).is_empty())
+        .collect();
+    let files: Vec<(&str, &str)> = wanted
+        .iter()
+        .map(|c| (c.path.as_str(), c.commit_id.as_str()))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .take(SUGGESTION_FILE_LIMIT)
+        .collect();
+    let futs = files.iter().map(|(path, sha)| client.get_file_content(repo, path, sha));
+    let mut contents = HashMap::new();
+    for (file, result) in files.iter().zip(futures::future::join_all(futs).await) {
+        match result {
+            Ok(content) => {
+                contents.insert(*file, content);
+            }
+            Err(e) if github::is_budget_exhausted(&e) => return Err(e),
+            // Deleted or unreadable: the suggestion is shown without its original
+            Err(_) => {}
+        }
+    }
+
+    Ok(wanted
+        .iter()
+        .filter_map(|c| {
+            let content = contents.get(&(c.path.as_str(), c.commit_id.as_str()))?;
+            let end = c.line? as usize;
+            let start = c.start_line.map_or(end, |s| s as usize);
+            let lines: Vec<&str> = content.lines().collect();
+            let range = lines.get(start.checked_sub(1)?..end)?;
+            Some((c.id, range.iter().map(|l| l.to_string()).collect()))
+        })
+        .collect())
+}
+
+pub async fn pr_reviews(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    latest_per_user: bool,
+    json: bool,
+) -> Result<()> {
+    let reviews = client.get_reviews(repo, number).await?;
+    let shown: Vec<&github::Review> = if latest_per_user {
+        latest_review_per_user(&reviews)
+    } else {
+        reviews.iter().collect()
+    };
+
+    if json {
+        let reviews = shown
+            .iter()
+            .map(|r| ReviewJson {
+                id: r.id,
+                author: r.author.clone(),
+                state: r.state.clone(),
+                submitted_at: r.submitted_at.clone(),
+                body: r.body.clone(),
+                url: r.html_url.clone(),
+            })
+            .collect();
+        return print_json(&ReviewsJson { state: json_state(client, repo, number).await?, reviews });
+    }
+
+    if shown.is_empty() {
+        outln!("No reviews.");
+        return Ok(());
+    }
+    outln!("{}", format::format_reviews(&shown));
+    Ok(())
+}
+
+/// `pr dismiss-review`: dismiss `review_id`, or with `mine` the authenticated
+/// user's latest CHANGES_REQUESTED review
+pub async fn pr_dismiss_review(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    review_id: Option<u64>,
+    mine: bool,
+    message: &str,
+    allow_closed: bool,
+) -> Result<()> {
+    if message.trim().is_empty() {
+        anyhow::bail!("A dismissal message is required");
+    }
+    ensure_pr_open(client, repo, number, allow_closed, false).await?;
+    let review_id = match review_id {
+        Some(id) => id,
+        None if mine => {
+            let (login, reviews) =
+                tokio::try_join!(client.get_authenticated_user(), client.get_reviews(repo, number))?;
+            latest_changes_requested(&reviews, &login)
+                .with_context(|| format!("@{login} has no CHANGES_REQUESTED review on #{number} to dismiss"))?
+        }
+        None => anyhow::bail!("Pass --review-id or --mine"),
+    };
+    let review = client.dismiss_review(repo, number, review_id, message).await?;
+    outln!("Review {} by @{}: {}", review.id, review.author, review.state);
+    Ok(())
+}
+
+/// The id of `login`'s newest review that still requests changes
+fn latest_changes_requested(reviews: &[github::Review], login: &str) -> Option<u64> {
+    reviews
+        .iter()
+        .rev()
+        .find(|r| r.author.eq_ignore_ascii_case(login) && r.state == "CHANGES_REQUESTED")
+        .map(|r| r.id)
+}
+
+pub async fn pr_approve(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    body: Option<&str>,
+    allow_closed: bool,
+) -> Result<()> {
+    submit_verdict(client, repo, number, "APPROVE", body, allow_closed).await
+}
+
+pub async fn pr_request_changes(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    body: Option<&str>,
+    allow_closed: bool,
+) -> Result<()> {
+    submit_verdict(client, repo, number, "REQUEST_CHANGES", body, allow_closed).await
+}
+
+/// How many `in_reply_to_id` links `pr reply` follows to find a thread's first comment
+const MAX_REPLY_HOPS: usize = 5;
+
+/// Reply in a review thread, named by any of its comments or found by `file:line`
+pub async fn pr_reply(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    comment_id: Option<u64>,
+    at: Option<(&str, u64)>,
+    body: &str,
+    allow_closed: bool,
+) -> Result<()> {
+    // metadata + the comment lookup (up to MAX_REPLY_HOPS for a reply's id) + the
+    // reply POST
+    client.check_budget(2 + if comment_id.is_some() { MAX_REPLY_HOPS } else { 1 })?;
+    let pr = client.get_pr(repo, number).await?;
+    ensure_open(number, &pr.state, Some(allow_closed))?;
+
+    let root = match (comment_id, at) {
+        (Some(id), _) => thread_root(client, repo, id).await?,
+        (None, Some((file, line))) => {
+            let comments = client.get_review_comments(repo, number).await?;
+            find_thread(&comments, file, line)?
+        }
+        (None, None) => anyhow::bail!("Pass --comment-id, or --file and --line"),
+    };
+    // The comment list may have taken more than one page
+    client.check_budget(1)?;
+    let reply = client.reply_to_review_comment(repo, number, root, body).await?;
+    outln!("{}", reply.html_url);
+    Ok(())
+}
+
+/// GitHub only accepts replies to a thread's first comment, so follow
+/// `in_reply_to_id` up from a reply
+async fn thread_root(client: &github::Client, repo: &str, comment_id: u64) -> Result<u64> {
+    let mut id = comment_id;
+    for _ in 0..MAX_REPLY_HOPS {
+        let comment = client
+            .get_review_comment(repo, id)
+            .await
+            .with_context(|| format!("Looking up review comment {id}"))?;
+        if comment.thread_id == comment.id {
+            return Ok(id);
+        }
+        id = comment.thread_id;
+    }
+    anyhow::bail!("Review comment {comment_id} is more than {MAX_REPLY_HOPS} replies deep; pass its thread's first comment")
+}
+
+/// The id of the one thread on `file:line`
+fn find_thread(comments: &[github::ReviewComment], file: &str, line: u64) -> Result<u64> {
+    let file = review_input::normalize_path(file);
+    let roots: Vec<&github::ReviewComment> = comments
+        .iter()
+        .filter(|c| c.id == c.thread_id && c.path == file && c.line == Some(line))
+        .collect();
+    match roots.as_slice() {
+        [] => anyhow::bail!("No review thread on {file}:{line}"),
+        [root] => Ok(root.id),
+        _ => {
+            let ids: Vec<String> = roots
+                .iter()
+                .map(|c| format!("  {} @{}: {}", c.id, c.author, c.body.lines().next().unwrap_or_default()))
+                .collect();
+            anyhow::bail!(
+                "{} threads on {file}:{line}; pick one with --comment-id:\n{}",
+                roots.len(),
+                ids.join("\n")
+            )
+        }
+    }
+}
+
+/// `pr review start`: open a pending review on the PR head and remember it for
+/// `add`, `submit` and `abandon`
+pub async fn pr_review_start(client: &github::Client, repo: &str, number: u64, allow_closed: bool) -> Result<()> {
+    if let Some(open) = pending::load(repo, number)? {
+        anyhow::bail!(
+            "Pending review {} is already open on #{number} ({} comments); submit or abandon it first",
+            open.id,
+            open.comments
+        );
+    }
+    // metadata + the review POST
+    client.check_budget(2)?;
+    let pr = client.get_pr(repo, number).await?;
+    ensure_open(number, &pr.state, Some(allow_closed))?;
+    let review = client.create_pending_review(repo, number, &pr.head_sha).await?;
+    pending::store(
+        repo,
+        number,
+        &pending::PendingReview {
+            id: review.id,
+            node_id: review.node_id,
+            commit_id: pr.head_sha,
+            comments: 0,
+        },
+    )?;
+    outln!("Started pending review {} on #{number}", review.id);
+    Ok(())
+}
+
+/// `pr review add`: one comment on `file:line` in the pending review, on the head
+/// side of the diff
+pub async fn pr_review_add(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    (file, line): (&str, u64),
+    start_line: Option<u64>,
+    body: &str,
+    allow_closed: bool,
+) -> Result<()> {
+    if let Some(start) = start_line.filter(|&start| start > line) {
+        anyhow::bail!("--start-line {start} is after --line {line}");
+    }
+    let mut review = open_pending_review(repo, number)?;
+    ensure_pr_open(client, repo, number, allow_closed, false).await?;
+    let file = review_input::normalize_path(file);
+    client
+        .add_pending_review_comment(&review.node_id, file, line, start_line.filter(|&s| s != line), "RIGHT", body)
+        .await?;
+    review.comments += 1;
+    pending::store(repo, number, &review)?;
+    outln!("Added {file}:{line} to pending review {} ({} comments)", review.id, review.comments);
+    Ok(())
+}
+
+/// `pr review submit`: publish the pending review with `event`
+pub async fn pr_review_submit(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    event: &str,
+    body: Option<&str>,
+    allow_closed: bool,
+) -> Result<()> {
+    let review = open_pending_review(repo, number)?;
+    ensure_pr_open(client, repo, number, allow_closed, false).await?;
+    let submitted = client.submit_pending_review(repo, number, review.id, event, body).await?;
+    pending::clear(repo, number)?;
+    outln!("{}", submitted.html_url);
+    Ok(())
+}
+
+/// `pr review abandon`: delete the pending review and everything added to it
+pub async fn pr_review_abandon(client: &github::Client, repo: &str, number: u64) -> Result<()> {
+    let review = open_pending_review(repo, number)?;
+    match client.delete_pending_review(repo, number, review.id).await {
+        Ok(()) => {}
+        // Already submitted or deleted elsewhere; nothing left to abandon
+        Err(e) if github::api_error(&e).is_some_and(|a| a.status == reqwest::StatusCode::NOT_FOUND) => {
+            eprintln!("Pending review {} no longer exists on GitHub", review.id);
+        }
+        Err(e) => return Err(e),
+    }
+    pending::clear(repo, number)?;
+    outln!("Abandoned pending review {} ({} comments discarded)", review.id, review.comments);
+    Ok(())
+}
+
+fn open_pending_review(repo: &str, number: u64) -> Result<pending::PendingReview> {
+    pending::load(repo, number)?.with_context(|| {
+        format!("No pending review on {repo}#{number}; start one with `gh-agent pr review start --repo {repo} {number}`")
+    })
+}
+
+/// `pr resolve`: resolve the review thread `thread_id`, or the one open thread on
+/// `file:line`. With `undo`, unresolve instead.
+pub async fn pr_resolve(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    thread_id: Option<&str>,
+    at: Option<(&str, u64)>,
+    undo: bool,
+    allow_closed: bool,
+) -> Result<()> {
+    ensure_pr_open(client, repo, number, allow_closed, true).await?;
+    let thread_id = match (thread_id, at) {
+        (Some(id), _) => id.to_string(),
+        (None, Some((file, line))) => {
+            let threads = client.get_review_threads(repo, number).await?;
+            find_thread_to_resolve(&threads, file, line, undo)?
+        }
+        (None, None) => anyhow::bail!("Pass --thread-id, or --file and --line"),
+    };
+    let thread = client.set_thread_resolved(&thread_id, !undo).await?;
+    print_json(&ResolveOut {
+        thread_id: thread.id,
+        path: thread.path,
+        line: thread.line,
+        resolved: thread.is_resolved,
+    })
+}
+
+/// The node id of the one thread on `file:line` that resolving (or, with `undo`,
+/// unresolving) would change
+fn find_thread_to_resolve(threads: &[github::ReviewThread], file: &str, line: u64, undo: bool) -> Result<String> {
+    let file = review_input::normalize_path(file);
+    let want = if undo { "resolved" } else { "unresolved" };
+    let matching: Vec<&github::ReviewThread> = threads
+        .iter()
+        .filter(|t| t.path == file && t.line == Some(line) && t.resolved == undo)
+        .collect();
+    match matching.as_slice() {
+        [] => anyhow::bail!("No {want} review thread on {file}:{line}"),
+        [thread] => Ok(thread.id.clone()),
+        _ => {
+            let ids: Vec<String> = matching
+                .iter()
+                .map(|t| format!("  {} @{}: {}", t.id, t.author, t.body.lines().next().unwrap_or_default()))
+                .collect();
+            anyhow::bail!(
+                "{} {want} threads on {file}:{line}; pick one with --thread-id:\n{}",
+                matching.len(),
+                ids.join("\n")
+            )
+        }
+    }
+}
+
+/// Post a review with no inline comments, just the verdict `event` and an optional body
+async fn submit_verdict(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    event: &str,
+    body: Option<&str>,
+    allow_closed: bool,
+) -> Result<()> {
+    // metadata + the review POST
+    client.check_budget(2)?;
+    let pr = client.get_pr(repo, number).await?;
+    ensure_open_json(number, &pr.state, Some(allow_closed))?;
+    let review = CreateReview {
+        commit_id: pr.head_sha,
+        event: event.to_string(),
+        body: body.unwrap_or_default().to_string(),
+        comments: vec![],
+    };
+
+    let resp = match client.create_review(repo, number, &review).await {
+        Ok(resp) => resp,
+        Err(err) => {
+            // GitHub answers a verdict on your own PR with a bare 422
+            let own_pr = github::api_error(&err).is_some_and(|a| {
+                a.status == reqwest::StatusCode::UNPROCESSABLE_ENTITY
+                    && a.body.to_lowercase().contains("your own pull request")
+            });
+            if own_pr {
+                let verdict = if event == "APPROVE" { "approve" } else { "request changes on" };
+                anyhow::bail!(
+                    "GitHub doesn't let you {verdict} your own pull request (#{number} was opened by the authenticated user)"
+                );
+            }
+            return Err(err);
+        }
+    };
+    print_json(&ReviewOut {
+        id: resp.id,
+        url: resp.html_url,
+        reviews: vec![],
+        dropped: vec![],
+        deduplicated: false,
+        skipped: 0,
+        skipped_noise: 0,
+        severities: BTreeMap::new(),
+        fell_back: vec![],
+    })
+}
+
+/// Each reviewer's most recent review, the way GitHub's review decision sees it:
+/// a later COMMENTED review doesn't replace an earlier approval, change request or
+/// dismissal, and PENDING (unsubmitted) reviews are ignored. Keeps chronological order.
+fn latest_review_per_user(reviews: &[github::Review]) -> Vec<&github::Review> {
+    let mut latest: HashMap<&str, usize> = HashMap::new();
+    for (i, r) in reviews.iter().enumerate() {
+        if r.state == "PENDING" {
+            continue;
+        }
+        let supersedes = match latest.get(r.author.as_str()) {
+            Some(&prev) => r.state != "COMMENTED" || reviews[prev].state == "COMMENTED",
+            None => true,
+        };
+        if supersedes {
+            latest.insert(&r.author, i);
+        }
+    }
+    let mut keep: Vec<usize> = latest.into_values().collect();
+    keep.sort_unstable();
+    keep.into_iter().map(|i| &reviews[i]).collect()
+}
+
+/// Delay between `pr status` polls while mergeability is UNKNOWN
+const MERGEABLE_POLL_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+pub async fn pr_status(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    poll: u32,
+    fail_if_blocked: bool,
+    json: bool,
+) -> Result<()> {
+    let mut status = client.get_pr_status(repo, number).await?;
+    for attempt in 1..=poll {
+        if status.mergeable != "UNKNOWN" {
+            break;
+        }
+        eprintln!("mergeable: UNKNOWN (GitHub is still computing it), retrying {attempt}/{poll}...");
+        tokio::time::sleep(MERGEABLE_POLL_DELAY).await;
+        status = client.get_pr_status(repo, number).await?;
+    }
+    let blockers = merge_blockers(&status);
+
+    if json {
+        let count = |outcome: &str| status.checks.iter().filter(|c| format::check_outcome(c) == outcome).count();
+        let out = StatusJson {
+            number: status.number,
+            title: status.title.clone(),
+            state: status.state.clone(),
+            is_draft: status.is_draft,
+            mergeable: status.mergeable.clone(),
+            merge_state_status: status.merge_state_status.clone(),
+            review_decision: status.review_decision.clone(),
+            requested_reviewers: status.requested_reviewers.clone(),
+            head_sha: status.head_sha.clone(),
+            checks: StatusChecksJson {
+                state: status.checks_state.clone(),
+                passed: count("pass"),
+                failed: count("fail"),
+                pending: count("pending"),
+                failing: status
+                    .checks
+                    .iter()
+                    .filter(|c| format::check_outcome(c) == "fail")
+                    .map(|c| c.name.clone())
+                    .collect(),
+            },
+            ready: blockers.is_empty(),
+            blockers: blockers.clone(),
+        };
+        print_json(&out)?;
+    } else {
+        outln!("{}", format::format_status(&status, &blockers));
+    }
+
+    if fail_if_blocked && !blockers.is_empty() {
+        anyhow::bail!("PR #{number} is not ready to merge: {}", blockers.join("; "));
+    }
+    Ok(())
+}
+
+/// Reasons a PR can't be merged yet; empty means ready
+fn merge_blockers(status: &github::PrStatus) -> Vec<String> {
+    let mut blockers = Vec::new();
+    if status.state != "OPEN" {
+        blockers.push(format!("PR is {}", status.state.to_lowercase()));
+    }
+    if status.is_draft {
+        blockers.push("draft".to_string());
+    }
+    match status.mergeable.as_str() {
+        "CONFLICTING" => blockers.push("merge conflicts".to_string()),
+        "UNKNOWN" => blockers.push("mergeability not computed yet".to_string()),
+        _ => {}
+    }
+    match status.review_decision.as_deref() {
+        Some("CHANGES_REQUESTED") => blockers.push("changes requested".to_string()),
+        Some("REVIEW_REQUIRED") => blockers.push("review required".to_string()),
+        _ => {}
+    }
+    let plural = |n: usize, what: &str| format!("{n} {what} check{}", if n == 1 { "" } else { "s" });
+    let failed = status.checks.iter().filter(|c| format::check_outcome(c) == "fail").count();
+    let pending = status.checks.iter().filter(|c| format::check_outcome(c) == "pending").count();
+    if failed > 0 {
+        blockers.push(plural(failed, "failing"));
+    }
+    if pending > 0 {
+        blockers.push(plural(pending, "pending"));
+    }
+    match status.merge_state_status.as_str() {
+        "BEHIND" => blockers.push("branch is behind base".to_string()),
+        // Branch protection we couldn't attribute to reviews or checks above
+        "BLOCKED" if blockers.is_empty() => blockers.push("blocked by branch protection".to_string()),
+        _ => {}
+    }
+    blockers
+}
+
+/// Newest base commits to inspect when attributing conflict candidates
+const CONFLICT_AUTHOR_LOOKUPS: usize = 20;
+
+/// PR paths that base also changed since the merge base, in PR order
+fn overlapping_paths(pr_files: &[github::PrFile], base_files: &[String]) -> Vec<String> {
+    pr_files
+        .iter()
+        .filter(|f| base_files.contains(&f.filename))
+        .map(|f| f.filename.clone())
+        .collect()
+}
+
+/// Likely conflicts: files changed on both sides since the merge base, next to
+/// GitHub's own verdict. Fails when GitHub reports a conflict, or while it's
+/// still computing mergeability and any path overlaps, so CI can gate on it.
+pub async fn pr_conflicts(client: &github::Client, repo: &str, number: u64, json: bool) -> Result<()> {
+    let pr = client.get_pr(repo, number).await?;
+    note_state(&pr);
+    // head...base: what base gained since the PR branched off
+    let base = client.compare(repo, &pr.head_sha, &pr.base_ref).await?;
+    let status = client.get_pr_status(repo, number).await?;
+
+    let mut files: Vec<ConflictFileJson> = overlapping_paths(&pr.files, &base.files)
+        .into_iter()
+        .map(|path| ConflictFileJson {
+            path,
+            last_author: None,
+            last_sha: None,
+        })
+        .collect();
+
+    // Best-effort attribution, newest base commit first; a failed fetch stops the walk
+    let mut unattributed = files.len();
+    for commit in base.commits.iter().rev().take(CONFLICT_AUTHOR_LOOKUPS) {
+        if unattributed == 0 {
+            break;
+        }
+        let Ok(touched) = client.get_commit_files(repo, &commit.sha).await else {
+            break;
+        };
+        for f in files.iter_mut().filter(|f| f.last_author.is_none() && touched.contains(&f.path)) {
+            f.last_author = Some(commit.author.clone());
+            f.last_sha = Some(commit.sha.clone());
+            unattributed -= 1;
+        }
+    }
+
+    let conflicting = match status.mergeable.as_str() {
+        "CONFLICTING" => true,
+        "UNKNOWN" => !files.is_empty(),
+        _ => false,
+    };
+
+    if json {
+        print_json(&ConflictsJson {
+            number,
+            state: pr.state.clone(),
+            base: pr.base_ref.clone(),
+            head: pr.head_ref.clone(),
+            merge_base: base.merge_base_sha.clone(),
+            mergeable: status.mergeable.clone(),
+            merge_state_status: status.merge_state_status.clone(),
+            conflicting,
+            files,
+        })?;
+    } else {
+        outln!(
+            "PR #{number} {} → {}: mergeable {} ({})",
+            pr.head_ref, pr.base_ref, status.mergeable, status.merge_state_status
+        );
+        let merge_base = format::short_sha(&base.merge_base_sha);
+        if files.is_empty() {
+            outln!("No files changed on {} since merge base {merge_base} overlap this PR", pr.base_ref);
+        } else {
+            outln!(
+                "{} file{} also changed on {} since merge base {merge_base}:",
+                files.len(),
+                if files.len() == 1 { "" } else { "s" },
+                pr.base_ref
+            );
+            for f in &files {
+                match (&f.last_author, &f.last_sha) {
+                    (Some(author), Some(sha)) => outln!("  {}  (last by {author} in {})", f.path, format::short_sha(sha)),
+                    _ => outln!("  {}", f.path),
+                }
+            }
+        }
+    }
+
+    if conflicting {
+        anyhow::bail!("PR #{number} conflicts with {}", pr.base_ref);
+    }
+    Ok(())
+}
+
+/// How `pr merge` merges, from its flags
+pub struct MergeOptions<'a> {
+    /// merge, squash or rebase
+    pub method: &'a str,
+    /// The merge commit's subject and body; GitHub's defaults when unset
+    pub subject: Option<&'a str>,
+    pub body: Option<&'a str>,
+    pub delete_branch: bool,
+    pub force: bool,
+}
+
+pub async fn pr_merge(client: &github::Client, repo: &str, number: u64, options: MergeOptions<'_>) -> Result<()> {
+    let MergeOptions {
+        method,
+        subject,
+        body,
+        delete_branch,
+        force,
+    } = options;
+    let status = client.get_pr_status(repo, number).await?;
+    ensure_open_json(number, &status.state, None)?;
+    if let Some(reason) = merge_refusal(&status).filter(|_| !force) {
+        anyhow::bail!("refusing to merge PR #{number}: {reason} (pass --force to try anyway)");
+    }
+
+    // The merge PUT, plus the ref DELETE for --delete-branch
+    client.check_budget(1 + usize::from(delete_branch))?;
+    let merged = match client.merge_pr(repo, number, method, subject, body).await {
+        Ok(merged) => merged,
+        Err(err) => {
+            let readable = github::api_error(&err).and_then(|a| merge_error_message(a.status, &a.body));
+            match readable {
+                Some(msg) => anyhow::bail!("could not merge PR #{number}: {msg}"),
+                None => return Err(err),
+            }
+        }
+    };
+    if !merged.merged {
+        anyhow::bail!("could not merge PR #{number}: {}", merged.message);
+    }
+    eprintln!("Merged PR #{number} ({method}): {}", merged.message);
+    outln!("{}", merged.sha);
+
+    if delete_branch && delete_head_branch(client, repo, &status.head_ref, status.is_cross_repository).await {
+        eprintln!("Deleted branch {}", status.head_ref);
+    }
+    Ok(())
+}
+
+/// `--delete-branch`: delete the PR's head branch once the PR is merged or
+/// closed. A fork's branch is left alone, as a branch of that name in `repo` is
+/// a different one. Failures only warn, since the PR is already done with.
+/// Whether the branch was deleted.
+async fn delete_head_branch(client: &github::Client, repo: &str, head_ref: &str, cross_repository: bool) -> bool {
+    if cross_repository {
+        eprintln!("warning: not deleting branch {head_ref}: it is in the PR author's fork, not in {repo}");
+        return false;
+    }
+    match client.delete_branch(repo, head_ref).await {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("warning: could not delete branch {head_ref}: {e}");
+            false
+        }
+    }
+}
+
+/// Why `pr merge` won't try without --force
+fn merge_refusal(status: &github::PrStatus) -> Option<&'static str> {
+    if status.is_draft {
+        Some("it is a draft")
+    } else if status.merge_state_status == "DIRTY" {
+        Some("it conflicts with the base branch (mergeable_state: dirty)")
+    } else {
+        None
+    }
+}
+
+/// Readable reason for a refused merge (405) or a head that moved mid-merge (409)
+fn merge_error_message(status: reqwest::StatusCode, body: &str) -> Option<String> {
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|json| json["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| body.trim().to_string());
+    let message = message.trim_end_matches('.');
+
+    match status.as_u16() {
+        405 => {
+            let approvals = message
+                .strip_prefix("At least ")
+                .and_then(|rest| rest.split_whitespace().next())
+                .and_then(|n| n.parse::<u32>().ok());
+            Some(if let Some(n) = approvals {
+                format!("branch protection requires {n} approval{}", if n == 1 { "" } else { "s" })
+            } else if message.contains("status check") {
+                format!("branch protection requires passing status checks ({message})")
+            } else if message.contains("code owner") {
+                "branch protection requires a code owner review".to_string()
+            } else if message.contains("not allowed") {
+                format!("{message}; try a different --method")
+            } else {
+                message.to_string()
+            })
+        }
+        409 => Some(format!(
+            "the head branch changed during the merge ({message}); review the new commits and retry"
+        )),
+        _ => None,
+    }
+}
+
+/// List the PR's commits that touched `path`, or diff it between two of them
+pub async fn pr_file_history(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    path: &str,
+    diff_range: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let path = review_input::normalize_path(path);
+    let commits = client.get_pr_commits(repo, number).await?;
+    // commit list + one detail per commit (+ the compare, + the PR's state for JSON)
+    client.check_budget(1 + commits.len() + usize::from(diff_range.is_some()) + usize::from(json))?;
+    let futs = commits.iter().map(|c| client.get_commit_detail(repo, &c.sha));
+    let details = futures::future::try_join_all(futs).await?;
+
+    let revisions = file_revisions(&commits, details, path);
+    if revisions.is_empty() {
+        anyhow::bail!("No commit in PR #{number} touches {path}");
+    }
+
+    let diff = match diff_range {
+        Some(spec) => {
+            let (from, to) = parse_revision_range(spec, revisions.len())?;
+            let (from, to) = (revisions[from - 1].0, revisions[to - 1].0);
+            Some((from, to, client.compare_path(repo, &from.sha, &to.sha, path).await?))
+        }
+        None => None,
+    };
+
+    if json {
+        return print_json(&FileHistoryJson {
+            state: json_state(client, repo, number).await?,
+            path: path.to_string(),
+            revisions: revisions
+                .iter()
+                .enumerate()
+                .map(|(i, (c, f))| FileRevisionJson {
+                    revision: i + 1,
+                    sha: c.sha.clone(),
+                    subject: c.subject().to_string(),
+                    status: f.status.clone(),
+                    additions: f.additions,
+                    deletions: f.deletions,
+                })
+                .collect(),
+            diff: diff.and_then(|(from, to, f)| {
+                let f = f?;
+                Some(FileRevisionDiffJson {
+                    from: from.sha.clone(),
+                    to: to.sha.clone(),
+                    status: f.status,
+                    additions: f.additions,
+                    deletions: f.deletions,
+                    patch: f.patch,
+                })
+            }),
+        });
+    }
+
+    let rows: Vec<(&github::PrCommit, &github::PrFile)> = revisions.iter().map(|(c, f)| (*c, f)).collect();
+    outln!("{}", format::format_file_history(path, &rows, commits.len()));
+    if let Some((from, to, file)) = diff {
+        outln!();
+        match file {
+            Some(f) => {
+                outln!("{} → {}  {} +{} -{}", from.short_sha(), to.short_sha(), f.status, f.additions, f.deletions);
+                outln!("{}", format::format_line_numbered_diff(&f, &format::DiffView::default()));
+            }
+            None => outln!("{path} is the same at {} and {}", from.short_sha(), to.short_sha()),
+        }
+    }
+    Ok(())
+}
+
+/// The commits that changed `path`, each with its change to it
+fn file_revisions<'a>(
+    commits: &'a [github::PrCommit],
+    details: Vec<github::CommitDetail>,
+    path: &str,
+) -> Vec<(&'a github::PrCommit, github::PrFile)> {
+    commits
+        .iter()
+        .zip(details)
+        .filter_map(|(c, d)| Some((c, d.files.into_iter().find(|f| f.filename == path)?)))
+        .collect()
+}
+
+/// Parse `--diff I..J` into 1-based revision numbers, I before J, both within `count`
+fn parse_revision_range(spec: &str, count: usize) -> Result<(usize, usize)> {
+    let parsed = spec
+        .split_once("..")
+        .and_then(|(from, to)| Some((from.trim().parse::<usize>().ok()?, to.trim().parse::<usize>().ok()?)));
+    match parsed {
+        Some((from, to)) if from >= 1 && from < to && to <= count => Ok((from, to)),
+        Some(_) => anyhow::bail!("--diff {spec}: need 1 <= I < J <= {count} (the number of revisions)"),
+        None => anyhow::bail!("--diff {spec}: expected I..J, e.g. 1..{count}"),
+    }
+}
+
+/// (original, revert) index pairs of commits that exactly undo each other,
+/// from their details in commit order; a failed fetch pairs with nothing
+fn revert_pairs(details: &[Option<github::CommitDetail>]) -> Vec<(usize, usize)> {
+    let fingerprints: Vec<Option<(u64, u64)>> = details
+        .iter()
+        .map(|d| {
+            let patches: Vec<(String, Option<String>)> =
+                d.as_ref()?.files.iter().map(|f| (f.filename.clone(), f.patch.clone())).collect();
+            Some((diff::change_fingerprint(&patches, false)?, diff::change_fingerprint(&patches, true)?))
+        })
+        .collect();
+    diff::pair_reverts(&fingerprints)
+}
+
+/// `pr diff --net-only`'s note on the commit pairs left out of the net diff
+fn format_net_note(commits: &[github::PrCommit], pairs: &[(usize, usize)]) -> String {
+    if pairs.is_empty() {
+        return "net: no commits cancel out\n".to_string();
+    }
+    pairs
+        .iter()
+        .map(|&(original, revert)| {
+            format!(
+                "net: {} reverts {} ({}); neither is in the diff\n",
+                commits[revert].short_sha(),
+                commits[original].short_sha(),
+                commits[original].subject()
+            )
+        })
+        .collect()
+}
+
+pub async fn pr_commits(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    with_stat: bool,
+    detect_reverts: bool,
+    json: bool,
+) -> Result<()> {
+    let commits = client.get_pr_commits(repo, number).await?;
+
+    // Per-commit details are best-effort: a failed fetch leaves that row without
+    // totals, and out of revert pairing
+    let details: Option<Vec<Option<github::CommitDetail>>> = if with_stat || detect_reverts {
+        let futs = commits.iter().map(|c| client.get_commit_detail(repo, &c.sha));
+        Some(
+            futures::future::join_all(futs)
+                .await
+                .into_iter()
+                .map(|r| r.ok())
+                .collect(),
+        )
+    } else {
+        None
+    };
+    let stats: Option<Vec<Option<github::CommitStat>>> = details
+        .as_ref()
+        .filter(|_| with_stat)
+        .map(|d| d.iter().map(|d| d.as_ref().map(|d| d.stat.clone())).collect());
+    let pairs = match details.as_ref().filter(|_| detect_reverts) {
+        Some(details) => revert_pairs(details),
+        None => vec![],
+    };
+    if !pairs.is_empty() {
+        eprintln!(
+            "{} commit pair{} cancel out; the PR diff doesn't include them",
+            pairs.len(),
+            if pairs.len() == 1 { "" } else { "s" }
+        );
+    }
+    let reverted_by = |i: usize| pairs.iter().find(|p| p.0 == i).map(|p| commits[p.1].sha.clone());
+    let reverts = |i: usize| pairs.iter().find(|p| p.1 == i).map(|p| commits[p.0].sha.clone());
+
+    if json {
+        let commits = commits
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let st = stats.as_ref().and_then(|s| s[i].as_ref());
+                CommitJson {
+                    sha: c.sha.clone(),
+                    author: c.author.clone(),
+                    date: c.date.clone(),
+                    message: c.message.clone(),
+                    files: st.map(|s| s.files),
+                    additions: st.map(|s| s.additions),
+                    deletions: st.map(|s| s.deletions),
+                    reverted_by: reverted_by(i),
+                    reverts: reverts(i),
+                }
+            })
+            .collect();
+        return print_json(&CommitsJson { state: json_state(client, repo, number).await?, commits });
+    }
+
+    if commits.is_empty() {
+        outln!("No commits.");
+        return Ok(());
+    }
+    outln!("{}", format::format_commits(&commits, stats.as_deref(), &pairs));
+    Ok(())
+}
+
+pub async fn pr_checks(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    failed_only: bool,
+    json: bool,
+) -> Result<()> {
+    let pr = client.get_pr(repo, number).await?;
+    note_state(&pr);
+    let checks = client.get_check_runs(repo, &pr.head_sha).await?;
+
+    let count = |outcome: &str| checks.iter().filter(|c| format::check_outcome(c) == outcome).count();
+    let (passed, failed, pending) = (count("pass"), count("fail"), count("pending"));
+    let shown: Vec<&github::CheckRun> = checks
+        .iter()
+        .filter(|c| !failed_only || format::check_outcome(c) != "pass")
+        .collect();
+
+    if json {
+        let out = ChecksJson {
+            state: pr.state,
+            head_sha: pr.head_sha,
+            passed,
+            failed,
+            pending,
+            checks: shown
+                .iter()
+                .map(|c| CheckJson {
+                    name: c.name.clone(),
+                    outcome: format::check_outcome(c).to_string(),
+                    status: c.status.clone(),
+                    conclusion: c.conclusion.clone(),
+                    details_url: c.details_url.clone(),
+                })
+                .collect(),
+        };
+        return print_json(&out);
+    }
+
+    if checks.is_empty() {
+        outln!("No checks reported for {}.", &pr.head_sha[..pr.head_sha.len().min(7)]);
+        return Ok(());
+    }
+    if !shown.is_empty() {
+        outln!("{}", format::format_checks(&shown));
+        outln!();
+    }
+    outln!("{passed} passed, {failed} failed, {pending} pending");
+    Ok(())
+}
+
+pub async fn pr_file(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    path: &str,
+    markdown: bool,
+    limit: Option<Limit>,
+) -> Result<()> {
+    let pr = client.get_pr(repo, number).await?;
+    note_state(&pr);
+    let content = client
+        .get_file_content(repo, path, &pr.head_ref)
+        .await?;
+    let lines = content.lines().count();
+
+    let (content, truncated) = match limit.map(|l| budget::truncate(&content, l)) {
+        Some(t) if t.is_truncated() => (
+            t.kept.to_string(),
+            Some(TruncatedJson {
+                emitted_tokens: budget::estimate_tokens(t.kept),
+                omitted_tokens: budget::estimate_tokens(t.omitted),
+                omitted_files: vec![],
+            }),
+        ),
+        _ => (content, None),
+    };
+
+    if markdown {
+        outln!("{}", format::fenced(format::fence_lang(path), content.trim_end_matches('\n')));
+        if let Some(t) = truncated {
+            outln!("\n_About {} more tokens not shown._", t.omitted_tokens);
+        }
+        return Ok(());
+    }
+    let out = FileOut {
+        state: pr.state,
+        path: path.to_string(),
+        content,
+        lines,
+        truncated,
+    };
+    print_json(&out)
+}
+
+/// Print `text`, cut to `limit` with a trailer saying how much was left out
+fn print_limited(text: &str, limit: Option<Limit>) {
+    match limit.map(|l| budget::truncate(text, l)) {
+        Some(t) if t.is_truncated() => {
+            out!("{}", t.kept);
+            if !t.kept.ends_with('\n') {
+                outln!();
+            }
+            outln!("{}", t.notice());
+        }
+        _ => outln!("{text}"),
+    }
+}
+
+/// `pr review --validate-only`: list every problem in the comments file, or
+/// confirm it's well-formed. Fails when there are problems.
+pub fn pr_review_validate(comments_file: &str) -> Result<()> {
+    let (raw, comments_file) = read_comments_file(comments_file)?;
+    let problems = review_input::validate(&raw);
+    if problems.is_empty() {
+        outln!("{comments_file}: OK");
+        return Ok(());
+    }
+    for p in &problems {
+        outln!("{p}");
+    }
+    anyhow::bail!(
+        "{comments_file} has {} problem{}",
+        problems.len(),
+        if problems.len() == 1 { "" } else { "s" }
+    )
+}
+
+/// How `pr review` posts the comments file, from its flags
+#[derive(Default)]
+pub struct ReviewOptions<'a> {
+    /// The review JSON file; - reads stdin
+    pub comments_file: &'a str,
+    /// --body, or --body-file to read it from
+    pub body: Option<&'a str>,
+    pub body_file: Option<&'a str>,
+    pub template: Option<&'a str>,
+    /// --var NAME=VALUE pairs for the template
+    pub template_vars: &'a [String],
+    /// Write suggestion blocks to this patch file instead of posting
+    pub emit_patch: Option<&'a str>,
+    pub retry_without_invalid: bool,
+    pub chunk_size: usize,
+    pub max_comment_chars: usize,
+    pub truncate_long_comments: bool,
+    pub fuzz: u64,
+    pub split_ranges: bool,
+    /// --via graphql
+    pub via_graphql: bool,
+    pub amend: bool,
+    pub allow_noise_comments: bool,
+    pub allow_duplicates: bool,
+    pub strict: bool,
+    pub min_severity: Option<Severity>,
+    pub idempotency_key: Option<&'a str>,
+    pub allow_closed: bool,
+    pub dry_run: bool,
+    pub json: bool,
+    pub confirm: bool,
+    /// Answer yes to --confirm without asking
+    pub yes: bool,
+}
+
+pub async fn pr_review(client: &github::Client, repo: &str, number: u64, options: ReviewOptions<'_>) -> Result<()> {
+    let ReviewOptions {
+        comments_file,
+        body,
+        body_file,
+        template,
+        template_vars,
+        emit_patch,
+        retry_without_invalid,
+        chunk_size,
+        max_comment_chars,
+        truncate_long_comments,
+        fuzz,
+        split_ranges,
+        via_graphql,
+        amend,
+        allow_noise_comments,
+        allow_duplicates,
+        strict,
+        min_severity,
+        idempotency_key,
+        allow_closed,
+        dry_run,
+        json,
+        confirm,
+        yes,
+    } = options;
+    if let Some(key) = idempotency_key {
+        check_idempotency_key(key)?;
+    }
+    let body = read_review_body(body, body_file, comments_file)?;
+    let template_vars = parse_template_vars(template_vars)?;
+    let template = template.map(|path| read_template(path, &template_vars)).transpose()?;
+    let dedupe = !allow_duplicates && emit_patch.is_none();
+    // metadata + raw diff (+ the key lookup, existing comments) + the (first) review POST
+    client.check_budget(3 + usize::from(idempotency_key.is_some()) + usize::from(dedupe))?;
+    let pr = client.get_pr(repo, number).await?;
+    if emit_patch.is_none() && !dry_run {
+        if json {
+            ensure_open_json(number, &pr.state, Some(allow_closed))?;
+        } else {
+            ensure_open(number, &pr.state, Some(allow_closed))?;
+        }
+    }
+    let quiet = dry_run && json;
+    let (raw, comments_file) = read_comments_file(comments_file)?;
+    let input = parse_review_input(&raw).with_context(|| format!("Failed to parse {comments_file}"))?;
+    if !quiet {
+        for w in &input.warnings {
+            eprintln!("{comments_file}: {w}");
+        }
+    }
+    if amend && input.event != "COMMENT" {
+        eprintln!("amend: ignoring event {}; the new comments join the existing review as they are", input.event);
+    }
+    let event = input.event;
+    let pr = client.add_patches(repo, pr).await?;
+
+    let file_hunks: HashMap<String, Vec<DiffHunk>> = pr
+        .files
+        .iter()
+        .map(|f| {
+            let hunks = f.patch.as_deref().map(parse_patch).unwrap_or_default();
+            (f.filename.clone(), hunks)
+        })
+        .collect();
+    let file_commentable: HashMap<&str, Vec<u64>> = file_hunks
+        .iter()
+        .map(|(path, hunks)| (path.as_str(), commentable_lines(hunks)))
+        .collect();
+    let binaries: HashSet<&str> = pr.files.iter().filter(|f| f.binary).map(|f| f.filename.as_str()).collect();
+
+    let review_body = body.unwrap_or(input.body);
+
+    let hunk_index: Vec<(&str, Vec<String>)> = file_hunks
+        .iter()
+        .map(|(path, hunks)| (path.as_str(), diff::hunk_ids(path, hunks)))
+        .collect();
+
+    let (comments, below): (Vec<_>, Vec<_>) = input
+        .comments
+        .iter()
+        .partition(|c| match (c.severity, min_severity) {
+            (Some(severity), Some(min)) => severity.at_least(min),
+            _ => true,
+        });
+    if let (false, Some(min)) = (below.is_empty() || quiet, min_severity) {
+        eprintln!("min-severity: left out {} comments below {}", below.len(), min.name());
+    }
+
+    let mut warnings = Vec::new();
+    let mut noise = Vec::new();
+    let mut adjusted = Vec::new();
+    let mut valid_comments = Vec::new();
+
+    for c in comments {
+        match review_comment(c, &file_hunks, &file_commentable, &hunk_index, fuzz, split_ranges, via_graphql) {
+            Ok((comment, _)) if !allow_noise_comments && is_noise_file(&comment.path) => {
+                let reason = noise_reason(&comment.path).map(|r| r.to_string()).unwrap_or_default();
+                let message = format!(
+                    "NOISE: commenting on a lock/generated file: {}:{} ({reason}); pass --allow-noise-comments to post it",
+                    comment.path, comment.line
+                );
+                noise.push(Skipped::new(&comment.path, Some(comment.line), SkipReason::NoiseFile, message));
+            }
+            Ok((comment, note)) => {
+                adjusted.extend(note);
+                valid_comments.push((comment, c.severity));
+            }
+            Err(warning) if warning.reason == SkipReason::NotInDiff && binaries.contains(warning.path.as_str()) => {
+                let message = format!("SKIP: {} is a binary file, with no lines to comment on", warning.path);
+                warnings.push(Skipped::new(&warning.path, warning.line, SkipReason::BinaryFile, message));
+            }
+            Err(warning) => warnings.push(warning),
+        }
+    }
+
+    if dedupe && !valid_comments.is_empty() {
+        let existing = client.get_review_comments(repo, number).await?;
+        valid_comments.retain(|(c, _)| match duplicate_of(c, &existing) {
+            Some(prior) => {
+                let message =
+                    format!("DUPLICATE: {}:{} (same as @{}'s comment {})", c.path, c.line, prior.author, prior.id);
+                warnings.push(Skipped::new(&c.path, Some(c.line), SkipReason::Duplicate, message));
+                false
+            }
+            None => true,
+        });
+    }
+
+    let mut severities = BTreeMap::new();
+    for severity in valid_comments.iter().filter_map(|(_, s)| *s) {
+        *severities.entry(severity).or_insert(0) += 1;
+    }
+    let mut valid_comments: Vec<ReviewCommentInput> = valid_comments.into_iter().map(|(c, _)| c).collect();
+
+    // Measured as posted: with severity prefixes, suggestion blocks and context
+    let mut truncated = Vec::new();
+    let mut too_long = Vec::new();
+    for c in &mut valid_comments {
+        let len = c.body.chars().count();
+        if len <= max_comment_chars {
+            continue;
+        }
+        let cuttable = truncate_long_comments && patch::extract_suggestion(&c.body).is_none();
+        match cuttable.then(|| truncate_body(&c.body, max_comment_chars)).flatten() {
+            Some(body) => {
+                adjusted.push(format!("TRUNCATED: {}:{} from {len} to {max_comment_chars} chars", c.path, c.line));
+                truncated.push((c.path.clone(), c.line));
+                c.body = body;
+            }
+            None if truncate_long_comments => {
+                too_long.push(format!("{}:{} ({len} chars; a suggestion can't be cut short)", c.path, c.line));
+            }
+            None => too_long.push(format!("{}:{} ({len} chars)", c.path, c.line)),
+        }
+    }
+
+    let noted = !warnings.is_empty() || !noise.is_empty() || !adjusted.is_empty();
+    if noted && !quiet {
+        eprintln!("⚠️  Validation warnings:");
+        for w in adjusted.iter().chain(noise.iter().chain(&warnings).map(|s| &s.message)) {
+            eprintln!("  {w}");
+        }
+    }
+
+    if !too_long.is_empty() {
+        anyhow::bail!(
+            "{} comments are over the {max_comment_chars}-character limit, and GitHub would reject the whole review:\n  {}\nShorten them, or pass --truncate-long-comments",
+            too_long.len(),
+            too_long.join("\n  ")
+        );
+    }
+
+    if emit_patch.is_none() {
+        let suggestions = suggestions_in(&valid_comments);
+        // GraphQL threads may sit outside the diff
+        let commentable = (!via_graphql).then_some(&file_commentable);
+        verify_suggestions(client, repo, &pr.head_sha, &suggestions, commentable, strict, quiet).await?;
+    }
+
+    if dry_run {
+        return review_dry_run(&valid_comments, &warnings, &noise, &adjusted, severities, json);
+    }
+
+    if valid_comments.is_empty() {
+        if json {
+            print_json(&ReviewResult {
+                review_id: None,
+                url: None,
+                reviews: vec![],
+                posted: vec![],
+                skipped: noise.into_iter().chain(warnings).collect(),
+                deduplicated: false,
+            })?;
+        }
+        return Err(AllSkipped.into());
+    }
+
+    if let Some(out_path) = emit_patch {
+        let suggestions = suggestions_in(&valid_comments);
+        let skipped = valid_comments.len() - suggestions.len();
+        if skipped > 0 {
+            eprintln!("emit-patch: ignoring {skipped} comments without a suggestion block");
+        }
+        return write_patch(client, repo, &pr.head_sha, &suggestions, out_path).await;
+    }
+
+    let review_body = match &template {
+        Some(template) => {
+            render_review_template(client, repo, &pr, template, valid_comments.len(), &severities, template_vars).await?
+        }
+        None => review_body,
+    };
+    // Leave room for the hidden markers added when posting
+    let body_limit = max_comment_chars.saturating_sub(BODY_MARKER_ROOM);
+    let review_body = match truncate_body(&review_body, body_limit) {
+        Some(cut) if truncate_long_comments => {
+            eprintln!("TRUNCATED: the review body, from {} to {body_limit} chars", review_body.chars().count());
+            cut
+        }
+        Some(_) => anyhow::bail!(
+            "The review body is {} chars, over the {body_limit} that fit; shorten it, or pass --truncate-long-comments",
+            review_body.chars().count()
+        ),
+        None => review_body,
+    };
+
+    if confirm {
+        let event = if amend { "(amend: added to your latest review)" } else { event.as_str() };
+        eprint!("{}", confirm_summary(&valid_comments, event, &review_body));
+        // Comments read from stdin leave only the terminal to ask on
+        if !yes && !ask_yes_no("Post this review?", comments_file == "stdin") {
+            return Err(Declined.into());
+        }
+    }
+
+    if amend {
+        let mut out = amend_review(client, repo, number, &pr.head_sha, valid_comments).await?;
+        out.skipped = warnings.len() + noise.len();
+        return print_json(&out);
+    }
+
+    let targets: Vec<PostedComment> = valid_comments
+        .iter()
+        .map(|c| PostedComment {
+            path: c.path.clone(),
+            line: c.line,
+            truncated: truncated.contains(&(c.path.clone(), c.line)),
+        })
+        .collect();
+    if via_graphql {
+        let mut body = review_body;
+        if let Some(key) = idempotency_key {
+            let marker = idempotency_marker(key, 1, 1);
+            let recent = client.get_recent_reviews(repo, number, IDEMPOTENCY_LOOKBACK).await?;
+            if let Some(found) = recent.iter().find(|r| r.body.contains(&marker)) {
+                eprintln!("Already posted under this key: {}", found.html_url);
+                return print_review(deduplicated_review(found), targets, noise.into_iter().chain(warnings).collect(), json);
+            }
+            body = with_marker(&body, &marker);
+        }
+        let review = CreateReview {
+            commit_id: pr.head_sha.clone(),
+            event: event.clone(),
+            body,
+            comments: valid_comments,
+        };
+        let mut out =
+            post_review_graphql(client, repo, number, &pr.node_id, review, &file_commentable, retry_without_invalid).await?;
+        out.skipped = warnings.len();
+        out.skipped_noise = noise.len();
+        out.severities = severities;
+        return print_review(out, targets, noise.into_iter().chain(warnings).collect(), json);
+    }
+
+    let sent = valid_comments.len();
+    let chunks = chunk_review_comments(valid_comments, chunk_size);
+    let total = chunks.len();
+    // Don't start posting a chunked review that can't be finished
+    client.check_budget(total)?;
+
+    let recent = match idempotency_key {
+        Some(_) => client.get_recent_reviews(repo, number, IDEMPOTENCY_LOOKBACK).await?,
+        None => vec![],
+    };
+
+    let mut posted: Vec<ReviewOut> = Vec::new();
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        // Only the first part carries the verdict
+        let (mut body, event) = if i == 0 {
+            (review_body.clone(), event.as_str())
+        } else {
+            (format!("Continued review (part {}/{total})", i + 1), "COMMENT")
+        };
+        if let Some(key) = idempotency_key {
+            let marker = idempotency_marker(key, i + 1, total);
+            if let Some(found) = recent.iter().find(|r| r.body.contains(&marker)) {
+                eprintln!("Part {}/{total} was already posted under this key: {}", i + 1, found.html_url);
+                posted.push(deduplicated_review(found));
+                continue;
+            }
+            body = with_marker(&body, &marker);
+        }
+        let review = CreateReview {
+            commit_id: pr.head_sha.clone(),
+            event: event.to_string(),
+            body,
+            comments: chunk,
+        };
+        match post_review(client, repo, number, review, retry_without_invalid).await {
+            Ok(out) => {
+                if total > 1 {
+                    eprintln!("Posted part {}/{total}: {}", i + 1, out.url);
+                }
+                posted.push(out);
+            }
+            Err(e) if posted.is_empty() => return Err(e),
+            Err(e) => {
+                let done: Vec<String> = posted
+                    .iter()
+                    .enumerate()
+                    .map(|(j, r)| format!("  part {}/{total}: review {} {}", j + 1, r.id, r.url))
+                    .collect();
+                let resume = match idempotency_key {
+                    Some(key) => format!("\nRe-run with --idempotency-key {key} to post only the missing parts"),
+                    None => String::new(),
+                };
+                return Err(e.context(format!(
+                    "Review part {}/{total} failed; these parts were posted:\n{}{resume}",
+                    i + 1,
+                    done.join("\n")
+                )));
+            }
+        }
+    }
+
+    let reviews = if total > 1 {
+        posted
+            .iter()
+            .map(|r| ReviewRef {
+                id: r.id,
+                url: r.url.clone(),
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    let dropped: Vec<DroppedComment> = posted.iter_mut().flat_map(|r| r.dropped.drain(..)).collect();
+    if total > 1 {
+        eprintln!("Posted {} comments across {total} reviews", sent - dropped.len());
+    }
+    let deduplicated = posted.iter().all(|r| r.deduplicated);
+    let first = posted.swap_remove(0);
+    let out = ReviewOut {
+        id: first.id,
+        url: first.url,
+        reviews,
+        dropped,
+        deduplicated,
+        skipped: warnings.len(),
+        skipped_noise: noise.len(),
+        severities,
+        fell_back: vec![],
+    };
+    print_review(out, targets, noise.into_iter().chain(warnings).collect(), json)
+}
+
+/// Print a posted review: as `ReviewOut`, or with `json` as a `ReviewResult` that
+/// lists each comment. `targets` are all the comments sent, and `skipped` those
+/// left out before sending. Fails with `AllSkipped` when GitHub dropped every
+/// comment that was sent.
+fn print_review(out: ReviewOut, targets: Vec<PostedComment>, skipped: Vec<Skipped>, json: bool) -> Result<()> {
+    if !json {
+        return print_json(&out);
+    }
+    let dropped: Vec<Skipped> = out
+        .dropped
+        .into_iter()
+        .map(|d| Skipped::new(&d.path, Some(d.line), SkipReason::Rejected, d.reason))
+        .collect();
+    let posted: Vec<PostedComment> = targets
+        .into_iter()
+        .filter(|c| !dropped.iter().any(|d| d.path == c.path && d.line == Some(c.line)))
+        .collect();
+    let nothing_posted = posted.is_empty();
+    print_json(&ReviewResult {
+        review_id: Some(out.id),
+        url: Some(out.url),
+        reviews: out.reviews,
+        posted,
+        skipped: skipped.into_iter().chain(dropped).collect(),
+        deduplicated: out.deduplicated,
+    })?;
+    if nothing_posted {
+        return Err(AllSkipped.into());
+    }
+    Ok(())
+}
+
+/// Exit status when `pr review` posted no comment because every one was skipped
+pub const EXIT_ALL_SKIPPED: i32 = 2;
+
+#[derive(Debug)]
+struct AllSkipped;
+
+impl std::fmt::Display for AllSkipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No valid comments to post after validation")
+    }
+}
+
+impl std::error::Error for AllSkipped {}
+
+pub fn is_all_skipped(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<AllSkipped>().is_some()
+}
+
+/// Exit status when `pr review --confirm` was answered no (or couldn't ask)
+pub const EXIT_DECLINED: i32 = 3;
+
+#[derive(Debug)]
+struct Declined;
+
+impl std::fmt::Display for Declined {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Review not posted: declined at the confirmation prompt")
+    }
+}
+
+impl std::error::Error for Declined {}
+
+pub fn is_declined(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Declined>().is_some()
+}
+
+/// What `--confirm` shows before asking: comments per file with their line span,
+/// the event, and the start of the body
+fn confirm_summary(comments: &[ReviewCommentInput], event: &str, body: &str) -> String {
+    let mut files: Vec<(&str, usize, u64, u64)> = Vec::new();
+    for c in comments {
+        let first = c.start_line.unwrap_or(c.line);
+        match files.iter_mut().find(|(path, ..)| *path == c.path) {
+            Some((_, n, min, max)) => {
+                *n += 1;
+                *min = (*min).min(first);
+                *max = (*max).max(c.line);
+            }
+            None => files.push((&c.path, 1, first, c.line)),
+        }
+    }
+    let width = files.iter().map(|(path, ..)| path.len()).max().unwrap_or(0).max("file".len());
+    let mut out = format!("{:<width$}  comments  lines\n", "file");
+    for (path, n, min, max) in &files {
+        out += &format!("{path:<width$}  {n:>8}  {min}-{max}\n");
+    }
+    out += &format!("{} comments in {} files\nevent: {event}\n", comments.len(), files.len());
+    let preview: Vec<&str> = body.lines().take(CONFIRM_BODY_LINES).collect();
+    out += "body:\n";
+    for line in &preview {
+        out += &format!("  {line}\n");
+    }
+    if body.lines().count() > preview.len() {
+        out += "  …\n";
+    }
+    out
+}
+
+/// Lines of the review body shown by `--confirm`
+const CONFIRM_BODY_LINES: usize = 5;
+
+/// Ask `question` with a y/N prompt on stderr. The answer comes from stdin when it's
+/// a terminal, or from /dev/tty when stdin was used for input; otherwise there's
+/// no one to ask, and the answer is no.
+fn ask_yes_no(question: &str, stdin_used: bool) -> bool {
+    use std::io::{BufRead, IsTerminal};
+    let mut reader: Box<dyn BufRead> = if !stdin_used && std::io::stdin().is_terminal() {
+        Box::new(std::io::stdin().lock())
+    } else if let (true, Ok(tty)) = (stdin_used, std::fs::File::open("/dev/tty")) {
+        Box::new(std::io::BufReader::new(tty))
+    } else {
+        eprintln!("{question} [y/N] no (no terminal to ask on; pass --yes to post anyway)");
+        return false;
+    };
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    reader.read_line(&mut answer).is_ok() && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// GitHub's limit on a comment or review body, in characters
+pub const MAX_BODY_CHARS: usize = 65536;
+
+/// Room kept free in a review body for the markers `pr review` appends
+const BODY_MARKER_ROOM: usize = 200;
+
+/// `body` cut to at most `max` characters, ending in a note of how many were
+/// left out; None when it fits already
+fn truncate_body(body: &str, max: usize) -> Option<String> {
+    let len = body.chars().count();
+    if len <= max {
+        return None;
+    }
+    let mut keep = max;
+    loop {
+        let suffix = format!("… (truncated, {} chars omitted)", len - keep);
+        let fits = max.saturating_sub(suffix.chars().count());
+        if keep <= fits || keep == 0 {
+            let kept: String = body.chars().take(keep).collect();
+            return Some(format!("{kept}{suffix}"));
+        }
+        keep = fits;
+    }
+}
+
+/// `pr review --amend`: add the comments to the PR one by one, on the commit of
+/// your latest submitted review, instead of opening another review. Refused once
+/// the PR has moved past that commit, since the lines were checked at the head.
+async fn amend_review(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    head_sha: &str,
+    comments: Vec<ReviewCommentInput>,
+) -> Result<AmendOut> {
+    // The login, the reviews, then one POST per comment
+    client.check_budget(2 + comments.len())?;
+    let me = client.get_authenticated_user().await?;
+    let reviews = client.get_reviews(repo, number).await?;
+    // The reviews may have taken more than one page
+    client.check_budget(comments.len())?;
+    let Some(review) = reviews
+        .into_iter()
+        .rev()
+        .find(|r| r.author == me && r.state != "PENDING" && r.state != "DISMISSED")
+    else {
+        anyhow::bail!("@{me} has no submitted review on PR #{number} to amend; post one without --amend");
+    };
+    let commit_id = review.commit_id.unwrap_or_else(|| head_sha.to_string());
+    if commit_id != head_sha {
+        anyhow::bail!(
+            "Review {} was made on {}, but PR #{number} is now at {}; post a new review without --amend",
+            review.id,
+            format::short_sha(&commit_id),
+            format::short_sha(head_sha)
+        );
+    }
+
+    let mut posted: Vec<AmendedComment> = Vec::new();
+    for c in comments {
+        match client.create_review_comment(repo, number, &commit_id, &c).await {
+            Ok(created) => posted.push(AmendedComment {
+                path: c.path,
+                line: c.line,
+                url: created.html_url,
+            }),
+            Err(e) if posted.is_empty() => return Err(e),
+            Err(e) => {
+                let done: Vec<String> = posted.iter().map(|p| format!("  {}:{} {}", p.path, p.line, p.url)).collect();
+                return Err(e.context(format!(
+                    "Comment on {}:{} failed; these were posted:\n{}",
+                    c.path,
+                    c.line,
+                    done.join("\n")
+                )));
+            }
+        }
+    }
+    eprintln!("Added {} comments alongside review {} ({})", posted.len(), review.id, review.html_url);
+    Ok(AmendOut {
+        review_id: review.id,
+        review_url: review.html_url,
+        commit_id,
+        comments: posted,
+        skipped: 0,
+    })
+}
+
+/// Fill a `--template` with the PR's details and the comments about to be posted.
+/// The smart analysis only runs when the template asks for `{{smart_summary}}`.
+async fn render_review_template(
+    client: &github::Client,
+    repo: &str,
+    pr: &github::PullRequest,
+    template: &str,
+    comment_count: usize,
+    severities: &BTreeMap<Severity, usize>,
+    vars: BTreeMap<String, String>,
+) -> Result<String> {
+    let mut values = BTreeMap::from([
+        ("pr_title".to_string(), pr.title.clone()),
+        ("changed_files".to_string(), pr.changed_files.to_string()),
+        ("comment_count".to_string(), comment_count.to_string()),
+        ("severity_counts".to_string(), severity_counts(severities).unwrap_or_else(|| "none".to_string())),
+    ]);
+    if template_placeholders(template).contains(&"smart_summary") {
+        let changes = head_smart_changes(client, repo, pr, &history::record_dir(repo, pr.number)?).await;
+        values.insert("smart_summary".to_string(), smart_summary(&changes));
+    }
+    values.extend(vars);
+    Ok(fill_template(template, &values))
+}
+
+/// "2 blocker, 1 nit", or None without severities
+fn severity_counts(severities: &BTreeMap<Severity, usize>) -> Option<String> {
+    let counts: Vec<String> = severities.iter().map(|(s, n)| format!("{n} {}", s.name())).collect();
+    (!counts.is_empty()).then(|| counts.join(", "))
+}
+
+/// Markdown for `{{smart_summary}}`: the count per category, then each change
+/// worth reading
+fn smart_summary(changes: &[sem::SmartChange]) -> String {
+    if changes.is_empty() {
+        return "No semantic changes found.".to_string();
+    }
+    let count = |category: &str| changes.iter().filter(|c| c.category == category).count();
+    let mut lines = vec![format!(
+        "{} behavioral, {} new logic, {} mechanical",
+        count("behavioral"),
+        count("new_logic"),
+        count("mechanical")
+    )];
+    for c in changes.iter().filter(|c| c.category != "mechanical") {
+        let entity = match &c.parent {
+            Some(parent) => format!("{parent} › {} {}", c.entity_type, c.entity_name),
+            None => format!("{} {}", c.entity_type, c.entity_name),
+        };
+        lines.push(format!("- {}: `{entity}` in {}", c.category.replace('_', " "), c.file));
+    }
+    lines.join("\n")
+}
+
+/// `pr review --via graphql`: start a pending review, add each comment as a thread
+/// (GraphQL anchors them on any line of a changed file), then submit it. A comment
+/// GraphQL turns down is posted through REST afterwards, as a second review, when
+/// its lines are in the diff, and dropped otherwise.
+async fn post_review_graphql(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    node_id: &str,
+    review: CreateReview,
+    file_commentable: &HashMap<&str, Vec<u64>>,
+    retry_without_invalid: bool,
+) -> Result<ReviewOut> {
+    let CreateReview {
+        commit_id,
+        event,
+        body,
+        comments,
+    } = review;
+    // Start, one call per thread, submit
+    client.check_budget(2 + comments.len())?;
+    let pending = client.start_review_graphql(node_id, &commit_id).await?;
+    let mut rest = Vec::new();
+    let mut dropped = Vec::new();
+    for c in comments {
+        let Err(e) = client
+            .add_pending_review_comment(
+                &pending.node_id,
+                &c.path,
+                c.line,
+                c.start_line,
+                c.side.as_deref().unwrap_or("RIGHT"),
+                &c.body,
+            )
+            .await
+        else {
+            continue;
+        };
+        // LEFT comments were checked against the base's lines already
+        let in_diff = c.side.is_some()
+            || file_commentable
+                .get(c.path.as_str())
+                .is_some_and(|cl| (c.start_line.unwrap_or(c.line)..=c.line).all(|l| cl.contains(&l)));
+        if in_diff {
+            eprintln!("GraphQL turned down {}:{} ({e:#}); posting it through REST", c.path, c.line);
+            rest.push(c);
+        } else {
+            eprintln!("GraphQL turned down {}:{} ({e:#}); it's outside the diff, so REST can't post it either", c.path, c.line);
+            dropped.push(DroppedComment {
+                path: c.path,
+                line: c.line,
+                reason: format!("{e:#}"),
+            });
+        }
+    }
+    let submitted = client
+        .submit_review_graphql(&pending.node_id, &event, &body)
+        .await
+        .with_context(|| format!("Review {} was left pending; submit or delete it on GitHub", pending.id))?;
+
+    let mut out = ReviewOut {
+        id: submitted.id,
+        url: submitted.html_url,
+        reviews: vec![],
+        dropped,
+        deduplicated: false,
+        skipped: 0,
+        skipped_noise: 0,
+        severities: BTreeMap::new(),
+        fell_back: rest.iter().map(|c| format!("{}:{}", c.path, c.line)).collect(),
+    };
+    if !rest.is_empty() {
+        let second = CreateReview {
+            commit_id,
+            event: "COMMENT".to_string(),
+            body: default_body(),
+            comments: rest,
+        };
+        let second = post_review(client, repo, number, second, retry_without_invalid).await?;
+        out.reviews = vec![
+            ReviewRef {
+                id: out.id,
+                url: out.url.clone(),
+            },
+            ReviewRef {
+                id: second.id,
+                url: second.url,
+            },
+        ];
+        out.dropped.extend(second.dropped);
+    }
+    Ok(out)
+}
+
+/// The suggestion block in each comment that has one
+fn suggestions_in(comments: &[ReviewCommentInput]) -> Vec<Suggestion> {
+    comments
+        .iter()
+        .filter_map(|c| {
+            Some(Suggestion {
+                path: c.path.clone(),
+                start_line: c.start_line.unwrap_or(c.line),
+                end_line: c.line,
+                replacement: patch::extract_suggestion(&c.body)?,
+            })
+        })
+        .collect()
+}
+
+/// Hold each suggestion against its file at the PR head before posting: show the
+/// lines it replaces above the replacement on stderr, and warn about no-ops and
+/// lines outside the diff (fail with `strict`). Files are read at the head commit,
+/// which the base repo serves even when the head branch lives on a fork.
+async fn verify_suggestions(
+    client: &github::Client,
+    repo: &str,
+    head_sha: &str,
+    suggestions: &[Suggestion],
+    commentable: Option<&HashMap<&str, Vec<u64>>>,
+    strict: bool,
+    quiet: bool,
+) -> Result<()> {
+    let paths: BTreeSet<&str> = suggestions.iter().map(|s| s.path.as_str()).collect();
+    client.check_budget(paths.len())?;
+    let mut contents = HashMap::new();
+    for path in paths {
+        contents.insert(path, client.get_file_content(repo, path, head_sha).await);
+    }
+
+    let mut problems = Vec::new();
+    for s in suggestions {
+        let check = match &contents[s.path.as_str()] {
+            Ok(content) => {
+                let lines = commentable.map(|c| c.get(s.path.as_str()).map_or(&[][..], Vec::as_slice));
+                patch::check_suggestion(content, s, lines)
+            }
+            Err(e) => patch::SuggestionCheck {
+                original: None,
+                problems: vec![format!("{}: couldn't read it at the PR head to check the suggestion: {e:#}", s.path)],
+            },
+        };
+        if !quiet {
+            eprint!("{}", patch::render_check(s, &check));
+            for p in &check.problems {
+                eprintln!("  ⚠️  {p}");
+            }
+        }
+        problems.extend(check.problems);
+    }
+    if strict && !problems.is_empty() {
+        anyhow::bail!("{} suggestion problems (--strict):\n  {}", problems.len(), problems.join("\n  "));
+    }
+    Ok(())
+}
+
+/// How many commentable ranges a `check_comment_range` error offers instead
+const NEAREST_RANGES: usize = 5;
+
+/// Fail unless lines `start..=end` of `path` can all take a comment and sit in one
+/// hunk, the checks `pr review` skips a comment over. The error offers the file's
+/// commentable ranges nearest `start`.
+fn check_comment_range(path: &str, hunks: &[DiffHunk], start: u64, end: u64) -> Result<()> {
+    if hunks.is_empty() {
+        anyhow::bail!("{path} is not a changed file in this PR (pass --force to post anyway)");
+    }
+    let Some(problem) = comment_range_problem(path, hunks, start, end) else {
+        return Ok(());
+    };
+    let mut ranges = diff::commentable_ranges(hunks);
+    let distance = |&(first, last): &(u64, u64)| if start < first { first - start } else { start.saturating_sub(last) };
+    ranges.sort_by_key(distance);
+    ranges.truncate(NEAREST_RANGES);
+    ranges.sort();
+    let ranges: Vec<String> = ranges.iter().map(|(first, last)| format!("{first}-{last}")).collect();
+    anyhow::bail!("{problem}\nNearest commentable ranges in {path}: {}\nPass --force to post anyway", ranges.join(", "))
+}
+
+/// Why lines `start..=end` of `path` can't take one comment: a line outside the diff,
+/// or a range across hunks
+fn comment_range_problem(path: &str, hunks: &[DiffHunk], start: u64, end: u64) -> Option<String> {
+    let cl = commentable_lines(hunks);
+    let problem = match (start..=end).find(|l| !cl.contains(l)) {
+        Some(line) => RangeProblem::NotInDiff { line },
+        None => RangeProblem::CrossesHunk { start, end, boundary: diff::range_hunk_boundary(hunks, start, end)? },
+    };
+    Some(problem.message(path))
+}
+
+/// A comment target GitHub would reject, worded the same for `pr comment` and `pr review`
+enum RangeProblem {
+    NotInDiff { line: u64 },
+    CrossesHunk { start: u64, end: u64, boundary: u64 },
+}
+
+impl RangeProblem {
+    fn message(&self, path: &str) -> String {
+        match self {
+            RangeProblem::NotInDiff { line } => format!("{path}:{line} is not a commentable line (not in diff)"),
+            RangeProblem::CrossesHunk { start, end, boundary } => {
+                format!("{path}: range {start}-{end} crosses hunk boundary at {boundary}")
+            }
+        }
+    }
+}
+
+/// The parsed patch of `file` in the PR (empty when it isn't changed)
+fn pr_file_hunks(pr: &github::PullRequest, file: &str) -> Vec<DiffHunk> {
+    pr.files
+        .iter()
+        .find(|f| f.filename == file)
+        .and_then(|f| f.patch.as_deref())
+        .map(parse_patch)
+        .unwrap_or_default()
+}
+
+/// How many of the newest reviews are searched for an `--idempotency-key` marker.
+/// A keyed review older than that isn't found, and would be posted again.
+const IDEMPOTENCY_LOOKBACK: usize = 100;
+
+/// Keys end up inside an HTML comment, so they can't contain `--` or `>`
+fn check_idempotency_key(key: &str) -> Result<()> {
+    let ok = !key.is_empty()
+        && key.len() <= 128
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || "._:/-".contains(c))
+        && !key.contains("--");
+    if !ok {
+        anyhow::bail!(
+            "Invalid --idempotency-key {key:?}: use 1-128 letters, digits, '.', '_', ':', '/' or single '-'"
+        );
+    }
+    Ok(())
+}
+
+/// Hidden marker tying a posted review (or one part of a chunked review) to its
+/// `--idempotency-key`. Markdown renders HTML comments as nothing, and GitHub
+/// keeps them in the stored body.
+fn idempotency_marker(key: &str, part: usize, total: usize) -> String {
+    if total > 1 {
+        format!("<!-- gh-agent-key:{key} part {part}/{total} -->")
+    } else {
+        format!("<!-- gh-agent-key:{key} -->")
+    }
+}
+
+fn with_marker(body: &str, marker: &str) -> String {
+    if body.is_empty() {
+        marker.to_string()
+    } else {
+        format!("{body}\n\n{marker}")
+    }
+}
+
+fn deduplicated_review(found: &github::Review) -> ReviewOut {
+    ReviewOut {
+        id: found.id,
+        url: found.html_url.clone(),
+        reviews: vec![],
+        dropped: vec![],
+        deduplicated: true,
+        skipped: 0,
+        skipped_noise: 0,
+        severities: BTreeMap::new(),
+        fell_back: vec![],
+    }
+}
+
+/// Length of the body excerpt shown per comment by `pr review --dry-run`
+const DRY_RUN_BODY_CHARS: usize = 80;
+
+/// Report what `pr review` would post; fails if any comment would be skipped
+fn review_dry_run(
+    valid: &[ReviewCommentInput],
+    warnings: &[Skipped],
+    noise: &[Skipped],
+    adjusted: &[String],
+    severities: BTreeMap<Severity, usize>,
+    json: bool,
+) -> Result<()> {
+    if json {
+        print_json(&DryRunOut {
+            valid: valid
+                .iter()
+                .map(|c| DryRunComment {
+                    path: c.path.clone(),
+                    line: c.line,
+                    start_line: c.start_line,
+                    body: c.body.clone(),
+                })
+                .collect(),
+            skipped: warnings.iter().map(|w| w.message.trim_start_matches("SKIP: ").to_string()).collect(),
+            skipped_noise: noise.iter().map(|w| w.message.trim_start_matches("NOISE: ").to_string()).collect(),
+            adjusted: adjusted
+                .iter()
+                .map(|w| w.trim_start_matches("MOVED: ").trim_start_matches("SPLIT: ").trim_start_matches("TRUNCATED: ").to_string())
+                .collect(),
+            severities,
+        })?;
+    } else {
+        outln!("Would post {} comment{}:", valid.len(), if valid.len() == 1 { "" } else { "s" });
+        for c in valid {
+            let lines = match c.start_line {
+                Some(start) => format!("{start}-{}", c.line),
+                None => c.line.to_string(),
+            };
+            let flat = c.body.split_whitespace().collect::<Vec<_>>().join(" ");
+            let excerpt: String = flat.chars().take(DRY_RUN_BODY_CHARS).collect();
+            let more = if flat.chars().count() > DRY_RUN_BODY_CHARS { "…" } else { "" };
+            outln!("  {}:{lines}  {excerpt}{more}", c.path);
+        }
+        if let Some(counts) = severity_counts(&severities) {
+            outln!("Severities: {counts}");
+        }
+    }
+    let skipped = warnings.len() + noise.len();
+    if skipped > 0 {
+        anyhow::bail!("{skipped} of {} comments would be skipped", skipped + valid.len());
+    }
+    Ok(())
+}
+
+/// Check one comments-file entry against the diff and build what gets posted.
+/// Err is why it's skipped instead.
+fn review_comment(
+    c: &CommentInput,
+    file_hunks: &HashMap<String, Vec<DiffHunk>>,
+    file_commentable: &HashMap<&str, Vec<u64>>,
+    hunk_index: &[(&str, Vec<String>)],
+    fuzz: u64,
+    split_ranges: bool,
+    any_line: bool,
+) -> std::result::Result<(ReviewCommentInput, Option<String>), Skipped> {
+    if c.side == Some(Side::Left) && c.hunk.is_some() {
+        let path = c.path.as_deref().map(review_input::normalize_path).unwrap_or_default();
+        let message = "SKIP: a LEFT comment needs a path and line (hunk ids name head lines)".to_string();
+        return Err(Skipped::new(path, c.line, SkipReason::BadTarget, message));
+    }
+    let (path, mut line, mut start_line) = comment_target(c, file_hunks, hunk_index)?;
+    let skip = |line, reason, message| Err(Skipped::new(&path, Some(line), reason, message));
+    let Some(cl) = file_commentable.get(path.as_str()) else {
+        return skip(line, SkipReason::FileNotChanged, format!("SKIP: {path} is not a changed file in this PR"));
+    };
+    if c.side == Some(Side::Left) {
+        return left_comment(c, &file_hunks[&path], path.clone(), line, start_line).map(|comment| (comment, None));
+    }
+    let mut note = None;
+    // `any_line`: GraphQL threads may sit on any line of a changed file
+    if !cl.contains(&line) && !any_line {
+        let snapped = snap_line(cl, line, fuzz).filter(|&to| {
+            // A range moves as a whole, and its start must land in the diff too
+            start_line.is_none_or(|s| (s + to).checked_sub(line).is_some_and(|s| cl.contains(&s)))
+        });
+        let Some(to) = snapped else {
+            return skip(line, SkipReason::NotInDiff, format!("SKIP: {}", RangeProblem::NotInDiff { line }.message(&path)));
+        };
+        note = Some(format!("MOVED: {path}:{line} → {to}, the nearest commentable line"));
+        start_line = start_line.map(|s| s + to - line);
+        line = to;
+    }
+    if c.body.trim().is_empty() && c.suggestion.is_none() {
+        return skip(line, SkipReason::EmptyBody, format!("SKIP: {path}:{line} has neither a body nor a suggestion"));
+    }
+    // GitHub rejects a range whose lines span hunks
+    if let Some(start) = start_line.filter(|_| !any_line) {
+        if let Some(boundary) = diff::range_hunk_boundary(&file_hunks[&path], start, line) {
+            if c.suggestion.is_some() {
+                // Cutting it short would change what the suggestion replaces
+                return skip(
+                    line,
+                    SkipReason::RangeCrossesHunk,
+                    format!("SKIP: suggestion for {path}:{start}-{line} spans more than one hunk (crosses hunk boundary at {boundary})"),
+                );
+            }
+            if !split_ranges {
+                let problem = RangeProblem::CrossesHunk { start, end: line, boundary };
+                return skip(line, SkipReason::RangeCrossesHunk, format!("SKIP: {}", problem.message(&path)));
+            }
+            let split = format!("SPLIT: {path}:{start}-{line} → {boundary}-{line}, the part within one hunk");
+            note = Some(note.map_or(split.clone(), |moved| format!("{moved}; {split}")));
+            start_line = Some(boundary).filter(|&b| b != line);
+        }
+    }
+
+    let text = match c.severity {
+        Some(severity) if c.body.trim().is_empty() => severity.prefix().to_string(),
+        Some(severity) => format!("{} {}", severity.prefix(), c.body),
+        None => c.body.clone(),
+    };
+    let body = match &c.suggestion {
+        Some(replacement) => format::suggestion_comment(&text, replacement),
+        None => text,
+    };
+    let body = if c.include_context {
+        let ctx = format::format_comment_context(&file_hunks[&path], &path, start_line.unwrap_or(line), line);
+        format::with_comment_context(&body, ctx)
+    } else {
+        body
+    };
+    Ok((
+        ReviewCommentInput {
+            path,
+            line,
+            body,
+            start_line,
+            side: None,
+            start_side: None,
+        },
+        note,
+    ))
+}
+
+/// A comment on the base side of the diff: `line` (and `start_line`) are old line
+/// numbers of deleted or context lines, all in one hunk. These are never moved or
+/// split, and can't carry a suggestion, which would replace head lines.
+fn left_comment(
+    c: &CommentInput,
+    hunks: &[DiffHunk],
+    path: String,
+    line: u64,
+    start_line: Option<u64>,
+) -> std::result::Result<ReviewCommentInput, Skipped> {
+    let skip = |reason, message| Err(Skipped::new(&path, Some(line), reason, message));
+    if c.suggestion.is_some() {
+        return skip(SkipReason::BadTarget, format!("SKIP: {path}:{line} is a LEFT comment, which can't carry a suggestion"));
+    }
+    if c.body.trim().is_empty() {
+        return skip(SkipReason::EmptyBody, format!("SKIP: {path}:{line} has neither a body nor a suggestion"));
+    }
+    let hunk_of = |n: u64| hunks.iter().position(|h| h.lines.iter().any(|l| l.kind != "add" && l.old_line == Some(n)));
+    let Some(hunk) = hunk_of(line) else {
+        return skip(SkipReason::NotInDiff, format!("SKIP: {path}:{line} (LEFT) is not a line of the base in the diff"));
+    };
+    if let Some(start) = start_line {
+        if hunk_of(start) != Some(hunk) {
+            return skip(
+                SkipReason::RangeCrossesHunk,
+                format!("SKIP: {path}: LEFT range {start}-{line} is not within one hunk"),
+            );
+        }
+    }
+    let body = match c.severity {
+        Some(severity) => format!("{} {}", severity.prefix(), c.body),
+        None => c.body.clone(),
+    };
+    Ok(ReviewCommentInput {
+        path: path.clone(),
+        line,
+        body,
+        start_line,
+        side: Some("LEFT".to_string()),
+        start_side: start_line.map(|_| "LEFT".to_string()),
+    })
+}
+
+/// An existing, not outdated comment on the same line as `c` with the same body,
+/// ignoring differences in whitespace
+fn duplicate_of<'a>(c: &ReviewCommentInput, existing: &'a [github::ReviewComment]) -> Option<&'a github::ReviewComment> {
+    let normalize = |body: &str| body.split_whitespace().collect::<Vec<_>>().join(" ");
+    let body = normalize(&c.body);
+    existing
+        .iter()
+        .find(|e| !e.outdated && e.path == c.path && e.line == Some(c.line) && normalize(&e.body) == body)
+}
+
+/// The commentable line nearest `line`, at most `fuzz` lines away; ties go to
+/// the later line
+fn snap_line(commentable: &[u64], line: u64, fuzz: u64) -> Option<u64> {
+    commentable
+        .iter()
+        .copied()
+        .filter(|n| n.abs_diff(line) <= fuzz)
+        .min_by_key(|&n| (n.abs_diff(line), std::cmp::Reverse(n)))
+}
+
+/// Where a comment goes, as (path, line, start_line): its own path and line, or
+/// the hunk it names. Err is why it's skipped instead.
+fn comment_target(
+    c: &CommentInput,
+    file_hunks: &HashMap<String, Vec<DiffHunk>>,
+    hunk_index: &[(&str, Vec<String>)],
+) -> std::result::Result<(String, u64, Option<u64>), Skipped> {
+    let given_path = c.path.as_deref().map(review_input::normalize_path);
+    let bad_target = |path: &str, message| Skipped::new(path, c.line, SkipReason::BadTarget, message);
+    let Some(id) = &c.hunk else {
+        return match (given_path, c.line) {
+            (Some(path), Some(line)) => Ok((path.to_string(), line, c.start_line)),
+            _ => Err(bad_target(given_path.unwrap_or_default(), "SKIP: comment needs a path and line, or a hunk".to_string())),
+        };
+    };
+    let (path, i) = diff::resolve_hunk(id, hunk_index.iter().map(|(p, ids)| (*p, ids.as_slice())))
+        .map_err(|e| bad_target(given_path.unwrap_or_default(), format!("SKIP: {e}")))?;
+    if let Some(given) = given_path.filter(|p| *p != path) {
+        return Err(bad_target(given, format!("SKIP: hunk {id} is in {path}, not {given}")));
+    }
+    let hunk = &file_hunks[path][i];
+    match c.line {
+        Some(line) => {
+            let in_hunk = |n: u64| hunk.lines.iter().any(|l| l.kind != "delete" && l.new_line == Some(n));
+            if !in_hunk(line) || c.start_line.is_some_and(|s| !in_hunk(s)) {
+                return Err(bad_target(path, format!("SKIP: {path}:{line} is outside hunk {id}")));
+            }
+            Ok((path.to_string(), line, c.start_line))
+        }
+        None => hunk_comment_range(hunk)
+            .map(|(start, line)| (path.to_string(), line, start))
+            .ok_or_else(|| bad_target(path, format!("SKIP: hunk {id} has no commentable lines"))),
+    }
+}
+
+/// Split comments into review-sized chunks of at most `max`. Comments are grouped by
+/// file (files in first-mentioned order, comments in input order within a file), and a
+/// file only spans chunks when it alone has more than `max` comments.
+fn chunk_review_comments(comments: Vec<ReviewCommentInput>, max: usize) -> Vec<Vec<ReviewCommentInput>> {
+    let max = max.max(1);
+    let mut by_file: Vec<Vec<ReviewCommentInput>> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for c in comments {
+        match index.get(&c.path) {
+            Some(&i) => by_file[i].push(c),
+            None => {
+                index.insert(c.path.clone(), by_file.len());
+                by_file.push(vec![c]);
+            }
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let mut current: Vec<ReviewCommentInput> = Vec::new();
+    for file in by_file {
+        if !current.is_empty() && current.len() + file.len() > max {
+            chunks.push(std::mem::take(&mut current));
+        }
+        for c in file {
+            if current.len() == max {
+                chunks.push(std::mem::take(&mut current));
+            }
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Hidden marker appended to review bodies. It's derived from the review payload, so a
+/// POST that failed ambiguously can be matched against the reviews that actually landed.
+fn review_marker(review: &CreateReview) -> String {
+    let payload = serde_json::to_string(review).unwrap_or_default();
+    let hash = diff::fnv1a_64(payload.as_bytes());
+    format!("<!-- gh-agent-review:{hash:016x} -->")
+}
+
+/// Comments a 422 response blames, as (index, reason). GitHub names the offender either
+/// by `comments[N]` or by path (and sometimes line) in the error messages.
+fn invalid_comment_indices(error_body: &str, comments: &[ReviewCommentInput]) -> Vec<(usize, String)> {
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(error_body) else {
+        return vec![];
+    };
+    let messages: Vec<String> = json["errors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|e| match e {
+            serde_json::Value::String(m) => Some(m.clone()),
+            serde_json::Value::Object(o) => {
+                let parts: Vec<&str> = ["field", "message"]
+                    .iter()
+                    .filter_map(|k| o.get(*k).and_then(|v| v.as_str()))
+                    .collect();
+                (!parts.is_empty()).then(|| parts.join(": "))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut blamed: Vec<(usize, String)> = Vec::new();
+    for msg in &messages {
+        let by_index = msg.match_indices("comments[").filter_map(|(at, prefix)| {
+            let digits: String = msg[at + prefix.len()..].chars().take_while(char::is_ascii_digit).collect();
+            digits.parse::<usize>().ok().filter(|&i| i < comments.len())
+        });
+        let mut hits: Vec<usize> = by_index.collect();
+        if hits.is_empty() {
+            let line = msg
+                .split_once("line ")
+                .and_then(|(_, rest)| rest.split(|c: char| !c.is_ascii_digit()).next()?.parse::<u64>().ok());
+            hits = comments
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| msg.contains(c.path.as_str()) && line.is_none_or(|l| l == c.line))
+                .map(|(i, _)| i)
+                .collect();
+        }
+        for i in hits {
+            if !blamed.iter().any(|(b, _)| *b == i) {
+                blamed.push((i, msg.clone()));
+            }
+        }
+    }
+    blamed.sort_by_key(|(i, _)| *i);
+    blamed
+}
+
+/// Most pending reviews `post_review` opens to find the comments behind a 422 that
+/// doesn't name them. Each costs two requests.
+const MAX_BISECT_PROBES: usize = 8;
+
+/// Find the comments GitHub rejects when its 422 doesn't name them, as (index,
+/// reason) like `invalid_comment_indices`. Halves of a rejected group are tried as
+/// pending reviews, invisible to others and deleted right away; when the first half
+/// goes through, the fault is taken to be in the second. A group still rejected
+/// when `probes` runs out is blamed as a whole.
+async fn bisect_invalid_comments(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    head_sha: &str,
+    comments: &[ReviewCommentInput],
+    message: &str,
+    probes: &mut usize,
+) -> Result<Vec<(usize, String)>> {
+    let mut blamed = Vec::new();
+    // (indices, whether GitHub is known to reject them together)
+    let mut groups: Vec<(Vec<usize>, bool)> = vec![((0..comments.len()).collect(), true)];
+    while let Some((group, rejected)) = groups.pop() {
+        if !rejected {
+            // Without probes left, it's kept and the repost decides
+            if *probes == 0 {
+                continue;
+            }
+            *probes -= 1;
+            if probe_review(client, repo, number, head_sha, comments, &group).await? {
+                continue;
+            }
+        }
+        if group.len() == 1 {
+            blamed.push((group[0], format!("{message} (found by bisection)")));
+            continue;
+        }
+        if *probes == 0 {
+            let reason = format!("{message} (somewhere in a group of {} comments; out of bisection probes)", group.len());
+            blamed.extend(group.into_iter().map(|i| (i, reason.clone())));
+            continue;
+        }
+        let (first, second) = group.split_at(group.len() / 2);
+        *probes -= 1;
+        if probe_review(client, repo, number, head_sha, comments, first).await? {
+            groups.push((second.to_vec(), true));
+        } else {
+            groups.push((second.to_vec(), false));
+            groups.push((first.to_vec(), true));
+        }
+    }
+    blamed.sort_by_key(|(i, _)| *i);
+    Ok(blamed)
+}
+
+/// Whether GitHub takes the comments at `indices`, tried as a pending review that
+/// is deleted straight after
+async fn probe_review(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    head_sha: &str,
+    comments: &[ReviewCommentInput],
+    indices: &[usize],
+) -> Result<bool> {
+    client.check_budget(2)?;
+    let review = CreateReview {
+        commit_id: head_sha.to_string(),
+        event: String::new(),
+        body: String::new(),
+        comments: indices.iter().map(|&i| comments[i].clone()).collect(),
+    };
+    match client.create_review(repo, number, &review).await {
+        Ok(pending) => {
+            client
+                .delete_pending_review(repo, number, pending.id)
+                .await
+                .with_context(|| format!("Probe review {} was left pending; delete it on GitHub", pending.id))?;
+            Ok(true)
+        }
+        Err(e) => match github::api_error(&e) {
+            Some(a) if a.status == reqwest::StatusCode::UNPROCESSABLE_ENTITY => {
+                // GitHub allows one pending review per user and PR
+                if a.body.contains("pending review") {
+                    return Err(e.context("Can't bisect the rejected review while you have a pending review on this PR (`pr review abandon` discards it)"));
+                }
+                Ok(false)
+            }
+            _ => Err(e),
+        },
+    }
+}
+
+/// POST the review, recovering from the two ways GitHub fails midway: a 5xx after the
+/// review was actually created (found again by its marker), and a 422 over specific
+/// comments (dropped and reposted when `retry_without_invalid` is set; when the 422
+/// doesn't name them, they are found by bisection).
+async fn post_review(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    review: CreateReview,
+    retry_without_invalid: bool,
+) -> Result<ReviewOut> {
+    let CreateReview {
+        commit_id: head_sha,
+        event,
+        body,
+        mut comments,
+    } = review;
+    let mut dropped: Vec<DroppedComment> = Vec::new();
+    let mut probes = MAX_BISECT_PROBES;
+    loop {
+        let mut review = CreateReview {
+            commit_id: head_sha.clone(),
+            event: event.clone(),
+            body: body.clone(),
+            comments,
+        };
+        let marker = review_marker(&review);
+        review.body = with_marker(&body, &marker);
+
+        client.check_budget(1)?;
+        let err = match client.create_review(repo, number, &review).await {
+            Ok(resp) => {
+                return Ok(ReviewOut {
+                    id: resp.id,
+                    url: resp.html_url,
+                    reviews: vec![],
+                    dropped,
+                    deduplicated: false,
+                    skipped: 0,
+                    skipped_noise: 0,
+                    severities: BTreeMap::new(),
+                    fell_back: vec![],
+                })
+            }
+            Err(e) => e,
+        };
+
+        let status = github::api_error(&err).map(|a| a.status);
+        if status == Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY) {
+            let error_body = github::api_error(&err).map(|a| a.body.as_str()).unwrap_or_default();
+            let mut invalid = invalid_comment_indices(error_body, &review.comments);
+            if invalid.is_empty() {
+                if !retry_without_invalid {
+                    return Err(err.context(
+                        "GitHub didn't say which comment it rejected; if one did cause it, --retry-without-invalid finds and drops it",
+                    ));
+                }
+                eprintln!("GitHub rejected the review without naming a comment; bisecting to find it...");
+                let message = serde_json::from_str::<serde_json::Value>(error_body)
+                    .ok()
+                    .and_then(|v| v["message"].as_str().map(str::to_string))
+                    .unwrap_or_else(|| error_body.to_string());
+                invalid =
+                    bisect_invalid_comments(client, repo, number, &head_sha, &review.comments, &message, &mut probes).await?;
+            }
+            eprintln!("GitHub rejected {} comment(s):", invalid.len());
+            for (i, reason) in &invalid {
+                let c = &review.comments[*i];
+                eprintln!("  {}:{} — {reason}", c.path, c.line);
+            }
+            if !retry_without_invalid {
+                anyhow::bail!("{err}\nRe-run with --retry-without-invalid to drop them and post the rest");
+            }
+            if invalid.len() == review.comments.len() {
+                anyhow::bail!("{err}\nEvery comment was rejected; nothing left to post");
+            }
+            comments = Vec::new();
+            for (i, c) in review.comments.into_iter().enumerate() {
+                match invalid.iter().find(|(b, _)| *b == i) {
+                    Some((_, reason)) => dropped.push(DroppedComment {
+                        path: c.path,
+                        line: c.line,
+                        reason: reason.clone(),
+                    }),
+                    None => comments.push(c),
+                }
+            }
+            eprintln!("Retrying with {} comment(s)...", comments.len());
+            continue;
+        }
+
+        // Client errors and budget exhaustion are definitive; anything else
+        // (5xx, timeouts, dropped connections) may have created the review anyway
+        if github::is_budget_exhausted(&err) || status.is_some_and(|s| s.is_client_error()) {
+            return Err(err);
+        }
+        eprintln!("Review POST failed; checking whether it was created anyway...");
+        let reviews = match client.get_reviews(repo, number).await {
+            Ok(r) => r,
+            Err(check) => anyhow::bail!(
+                "{err}\nCould not verify whether the review was created ({check}); check the PR before retrying"
+            ),
+        };
+        return match reviews.into_iter().rev().find(|r| r.body.contains(&marker)) {
+            Some(found) => {
+                eprintln!("The review was created despite the error");
+                Ok(ReviewOut {
+                    id: found.id,
+                    url: found.html_url,
+                    reviews: vec![],
+                    dropped,
+                    deduplicated: false,
+                    skipped: 0,
+                    skipped_noise: 0,
+                    severities: BTreeMap::new(),
+                    fell_back: vec![],
+                })
+            }
+            None => Err(err.context("The review was not created; it is safe to retry")),
+        };
+    }
+}
+
+/// The `pr suggest` replacement: `--replacement`, or `--replacement-file` (- for stdin)
+/// read verbatim, less one trailing newline so the block doesn't end in an empty line
+pub fn read_replacement(replacement: Option<String>, replacement_file: Option<&str>) -> Result<String> {
+    let raw = match (replacement, replacement_file) {
+        (Some(_), Some(_)) => anyhow::bail!("Pass --replacement or --replacement-file, not both"),
+        (Some(replacement), None) => return Ok(replacement),
+        (None, None) => anyhow::bail!("Pass --replacement or --replacement-file"),
+        (None, Some("-")) => {
+            let mut raw = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut raw).context("Failed to read the replacement from stdin")?;
+            raw
+        }
+        (None, Some(path)) => std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?,
+    };
+    let raw = match raw.strip_suffix('\n') {
+        Some(rest) => rest.strip_suffix('\r').unwrap_or(rest).to_string(),
+        None => raw,
+    };
+    Ok(raw)
+}
+
+/// The `pr suggest --comment` text, or `--comment-file` (- for stdin), without
+/// trailing whitespace
+pub fn read_suggestion_comment(
+    comment: Option<String>,
+    comment_file: Option<&str>,
+    replacement_file: Option<&str>,
+) -> Result<Option<String>> {
+    let raw = match (comment, comment_file) {
+        (Some(_), Some(_)) => anyhow::bail!("Pass --comment or --comment-file, not both"),
+        (comment, None) => return Ok(comment),
+        (None, Some("-")) => {
+            if replacement_file == Some("-") {
+                anyhow::bail!("--comment-file - and --replacement-file - can't both read stdin");
+            }
+            let mut raw = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut raw).context("Failed to read the comment from stdin")?;
+            raw
+        }
+        (None, Some(path)) => std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?,
+    };
+    Ok(Some(raw.trim_end().to_string()))
+}
+
+/// `pr suggest --from-local`: suggest the changes that turn `file` at the PR head into
+/// the local copy at `local_path`, all in one review. Changes on lines outside the
+/// PR's diff can't be suggested; they're listed on stderr as UNPOSTABLE.
+pub async fn pr_suggest_from_local(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    file: &str,
+    local_path: &str,
+    options: &SuggestOptions<'_>,
+) -> Result<()> {
+    let SuggestOptions {
+        dry_run,
+        comment,
+        review_body,
+        ..
+    } = *options;
+    let allow_closed = options.allow_closed || options.force;
+    let local = std::fs::read_to_string(local_path).with_context(|| format!("Failed to read {local_path}"))?;
+    // metadata + the file + raw diff + the review POST
+    client.check_budget(4)?;
+    let pr = client.get_pr(repo, number).await?;
+    if !dry_run {
+        ensure_open_json(number, &pr.state, Some(allow_closed))?;
+    }
+    let head = client
+        .get_file_content(repo, file, &pr.head_sha)
+        .await
+        .with_context(|| format!("Failed to fetch {file} at {}", pr.head_sha))?;
+    let suggestions = patch::suggestions_from_edit(file, &head, &local);
+    if suggestions.is_empty() {
+        anyhow::bail!("{local_path} has no line changes from {file} at the PR head; nothing to suggest");
+    }
+
+    let pr = client.add_patches(repo, pr).await?;
+    let hunks = pr_file_hunks(&pr, file);
+    let mut postable = Vec::new();
+    for s in suggestions {
+        let problem = if hunks.is_empty() {
+            Some(format!("{file} is not a changed file in this PR"))
+        } else {
+            comment_range_problem(file, &hunks, s.start_line, s.end_line)
+        };
+        match problem {
+            Some(problem) => eprintln!("UNPOSTABLE: {file}:{}-{}: {problem}", s.start_line, s.end_line),
+            None => postable.push(s),
+        }
+    }
+    if postable.is_empty() {
+        anyhow::bail!("None of the changes in {local_path} fall on lines of the PR's diff; nothing to suggest");
+    }
+    if dry_run {
+        return list_suggestions(&head, &postable);
+    }
+    post_suggestions(client, repo, number, &pr.head_sha, &postable, comment, review_body).await
+}
+
+/// `pr suggest --sed`: the substitution made on the commentable lines of `file` at
+/// the PR head, one suggestion per run of changed lines, posted as one review
+pub async fn pr_suggest_sed(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    file: &str,
+    expr: &str,
+    options: &SuggestOptions<'_>,
+) -> Result<()> {
+    let SuggestOptions {
+        dry_run,
+        comment,
+        review_body,
+        ..
+    } = *options;
+    let allow_closed = options.allow_closed || options.force;
+    let sub = sed::Substitution::parse(expr)?;
+    // metadata + the file + raw diff + the review POST
+    client.check_budget(4)?;
+    let pr = client.get_pr(repo, number).await?;
+    if !dry_run {
+        ensure_open_json(number, &pr.state, Some(allow_closed))?;
+    }
+    let head = client
+        .get_file_content(repo, file, &pr.head_sha)
+        .await
+        .with_context(|| format!("Failed to fetch {file} at {}", pr.head_sha))?;
+    let matching: Vec<u64> = (1..)
+        .zip(head.lines())
+        .filter(|(_, line)| sub.apply(line).is_some())
+        .map(|(n, _)| n)
+        .collect();
+    if matching.is_empty() {
+        anyhow::bail!("{expr} matches nothing in {file} at the PR head; nothing to suggest");
+    }
+
+    let pr = client.add_patches(repo, pr).await?;
+    let hunks = pr_file_hunks(&pr, file);
+    if hunks.is_empty() {
+        anyhow::bail!("{file} is not a changed file in this PR");
+    }
+    let ranges = diff::commentable_ranges(&hunks);
+    if !matching.iter().any(|&n| ranges.iter().any(|&(first, last)| (first..=last).contains(&n))) {
+        let lines: Vec<String> = matching.iter().map(u64::to_string).collect();
+        anyhow::bail!(
+            "{expr} matches nothing on the lines of the PR's diff in {file} (only outside it, on line {})",
+            lines.join(", ")
+        );
+    }
+    let suggestions = patch::suggestions_from_substitution(file, &head, &sub, &ranges);
+    if suggestions.is_empty() {
+        anyhow::bail!("{expr} changes nothing on the lines of the PR's diff in {file}; nothing to suggest");
+    }
+    if dry_run {
+        return list_suggestions(&head, &suggestions);
+    }
+    post_suggestions(client, repo, number, &pr.head_sha, &suggestions, comment, review_body).await
+}
+
+/// `--dry-run`: `suggestions` to `head` (the file at the PR head), each under the
+/// lines it replaces
+fn list_suggestions(head: &str, suggestions: &[Suggestion]) -> Result<()> {
+    for s in suggestions {
+        out!("{}", patch::render_check(s, &patch::check_suggestion(head, s, None)));
+    }
+    let n = suggestions.len();
+    outln!("{n} suggestion{} would be posted", if n == 1 { "" } else { "s" });
+    Ok(())
+}
+
+/// Post `suggestions` as one review, each with `comment` above its block
+async fn post_suggestions(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    head_sha: &str,
+    suggestions: &[Suggestion],
+    comment: Option<&str>,
+    review_body: Option<&str>,
+) -> Result<()> {
+    let comments = suggestions
+        .iter()
+        .map(|s| ReviewCommentInput {
+            path: s.path.clone(),
+            line: s.end_line,
+            body: format::suggestion_comment(comment.unwrap_or_default(), &s.replacement),
+            start_line: Some(s.start_line).filter(|&start| start != s.end_line),
+            side: None,
+            start_side: None,
+        })
+        .collect();
+    let review = CreateReview {
+        commit_id: head_sha.to_string(),
+        event: "COMMENT".to_string(),
+        body: review_body.unwrap_or("Suggestions from gh-agent").to_string(),
+        comments,
+    };
+    let out = post_review(client, repo, number, review, false).await?;
+    print_json(&out)
+}
+
+/// `pr suggest --preview`: the change applying the suggestion would make to the file
+/// at the PR head, as a diff (or `{before, after, diff}`). Nothing is posted.
+pub async fn pr_suggest_preview(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    mut suggestion: Suggestion,
+    match_indent: bool,
+    json: bool,
+) -> Result<()> {
+    let file = suggestion.path.as_str();
+    // metadata + the file
+    client.check_budget(2)?;
+    let pr = client.get_pr(repo, number).await?;
+    let content = client
+        .get_file_content(repo, file, &pr.head_sha)
+        .await
+        .with_context(|| format!("Failed to fetch {file} at {}", pr.head_sha))?;
+    if match_indent {
+        suggestion.replacement =
+            indent_like(&content, file, suggestion.start_line, suggestion.end_line, &suggestion.replacement)?;
+    }
+    let preview = patch::preview_suggestion(&content, &suggestion)?;
+    if json {
+        return print_json(&preview);
+    }
+    out!("{}", preview.diff);
+    Ok(())
+}
+
+/// `--match-indent`: `replacement` re-indented like lines `start..=end` of `content`
+fn indent_like(content: &str, path: &str, start: u64, end: u64, replacement: &str) -> Result<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    if start == 0 || start > end || end as usize > lines.len() {
+        anyhow::bail!("{path}:{start}-{end} is outside the file ({} lines)", lines.len());
+    }
+    Ok(patch::match_indent(&lines[start as usize - 1..end as usize].join("\n"), replacement))
+}
+
+/// How `pr suggest` checks and posts, from its flags; `--from-local` and `--sed` use
+/// only `dry_run`, `allow_closed`/`force`, `comment` and `review_body`
+#[derive(Default)]
+pub struct SuggestOptions<'a> {
+    /// --emit-patch: write a patch here instead of posting
+    pub emit_patch: Option<&'a str>,
+    pub include_context: bool,
+    pub strict: bool,
+    pub idempotency_key: Option<&'a str>,
+    pub allow_closed: bool,
+    pub force: bool,
+    pub match_indent: bool,
+    pub dry_run: bool,
+    /// --comment or --comment-file: text above the suggestion block
+    pub comment: Option<&'a str>,
+    pub review_body: Option<&'a str>,
+}
+
+pub async fn pr_suggest(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    suggestion: Suggestion,
+    options: &SuggestOptions<'_>,
+) -> Result<()> {
+    let SuggestOptions {
+        emit_patch,
+        include_context,
+        strict,
+        idempotency_key,
+        allow_closed,
+        force,
+        match_indent,
+        comment,
+        review_body,
+        ..
+    } = *options;
+    let Suggestion {
+        path,
+        start_line: line_start,
+        end_line: line_end,
+        replacement,
+    } = suggestion;
+    let file = path.as_str();
+    if let Some(key) = idempotency_key {
+        check_idempotency_key(key)?;
+    }
+    // metadata (+ the key lookup) (+ the file to indent like) + raw diff + the file
+    // + the review POST (or metadata + the file for --emit-patch)
+    client.check_budget(4 + usize::from(idempotency_key.is_some()) + usize::from(match_indent))?;
+    let pr = client.get_pr(repo, number).await?;
+    let replacement = if match_indent {
+        let content = client
+            .get_file_content(repo, file, &pr.head_sha)
+            .await
+            .with_context(|| format!("Failed to fetch {file} at {}", pr.head_sha))?;
+        indent_like(&content, file, line_start, line_end, &replacement)?
+    } else {
+        replacement
+    };
+    let replacement = replacement.as_str();
+    let suggestion = Suggestion {
+        path: file.to_string(),
+        start_line: line_start,
+        end_line: line_end,
+        replacement: replacement.to_string(),
+    };
+    if let Some(out_path) = emit_patch {
+        return write_patch(client, repo, &pr.head_sha, &[suggestion], out_path).await;
+    }
+    ensure_open_json(number, &pr.state, Some(allow_closed || force))?;
+
+    let mut review_body = review_body.unwrap_or("Suggestion from gh-agent").to_string();
+    if let Some(key) = idempotency_key {
+        let marker = idempotency_marker(key, 1, 1);
+        let recent = client.get_recent_reviews(repo, number, IDEMPOTENCY_LOOKBACK).await?;
+        if let Some(found) = recent.iter().find(|r| r.body.contains(&marker)) {
+            eprintln!("Already posted under this key: {}", found.html_url);
+            return print_json(&deduplicated_review(found));
+        }
+        review_body = with_marker(&review_body, &marker);
+    }
+
+    let pr = client.add_patches(repo, pr).await?;
+    let hunks = pr_file_hunks(&pr, file);
+    if !force {
+        check_comment_range(file, &hunks, line_start, line_end)?;
+    }
+    let commentable = HashMap::from([(file, commentable_lines(&hunks))]);
+    let commentable = (!force).then_some(&commentable);
+    verify_suggestions(client, repo, &pr.head_sha, &[suggestion], commentable, strict, false).await?;
+
+    let body = format::suggestion_comment(comment.unwrap_or_default(), replacement);
+    let body = if include_context {
+        format::with_comment_context(&body, format::format_comment_context(&hunks, file, line_start, line_end))
+    } else {
+        body
+    };
+
+    let start_line = if line_start == line_end {
+        None
+    } else {
+        Some(line_start)
+    };
+
+    let review = CreateReview {
+        commit_id: pr.head_sha,
+        event: "COMMENT".to_string(),
+        body: review_body,
+        comments: vec![ReviewCommentInput {
+            path: file.to_string(),
+            line: line_end,
+            body,
+            start_line,
+            side: None,
+            start_side: None,
+        }],
+    };
+
+    client.check_budget(1)?;
+    let resp = client.create_review(repo, number, &review).await?;
+    let out = ReviewOut {
+        id: resp.id,
+        url: resp.html_url,
+        reviews: vec![],
+        dropped: vec![],
+        deduplicated: false,
+        skipped: 0,
+        skipped_noise: 0,
+        severities: BTreeMap::new(),
+        fell_back: vec![],
+    };
+    print_json(&out)
+}
+
+// --- Config commands ---
+
+pub fn config_set(repo: &str, key: &str, value: &str) -> Result<()> {
+    let key = config::normalize_key(key)?;
+    let value = config::parse_value(&key, value)?;
+    let mut cfg = Config::load()?;
+    cfg.set(repo, &key, value);
+    cfg.save()?;
+    eprintln!("{repo}: {key} set ({})", config::config_path()?.display());
+    Ok(())
+}
+
+pub fn config_get(repo: &str, key: &str) -> Result<()> {
+    let key = config::normalize_key(key)?;
+    let cfg = Config::load()?;
+    match cfg.get(repo, &key) {
+        Some(value) => {
+            outln!("{value}");
+            Ok(())
+        }
+        None => anyhow::bail!("{key} is not set for {repo}"),
+    }
+}
+
+pub fn config_unset(repo: &str, key: &str) -> Result<()> {
+    let key = config::normalize_key(key)?;
+    let mut cfg = Config::load()?;
+    if !cfg.unset(repo, &key) {
+        anyhow::bail!("{key} is not set for {repo}");
+    }
+    cfg.save()
+}
+
+pub fn config_list(repo: Option<&str>) -> Result<()> {
+    let cfg = Config::load()?;
+    let entries: Vec<_> = cfg
+        .entries()
+        .into_iter()
+        .filter(|(r, _, _)| repo.is_none_or(|want| *r == want))
+        .collect();
+    if entries.is_empty() {
+        outln!("No repo defaults set.");
+        return Ok(());
+    }
+    for (r, key, value) in entries {
+        outln!("{r}  {key} = {value}");
+    }
+    Ok(())
+}
+
+/// Fetch head contents for the suggested files and write them out as one patch
+async fn write_patch(
+    client: &github::Client,
+    repo: &str,
+    head_sha: &str,
+    suggestions: &[Suggestion],
+    out_path: &str,
+) -> Result<()> {
+    if suggestions.is_empty() {
+        anyhow::bail!("No suggestions to write");
+    }
+
+    let mut paths: Vec<String> = suggestions.iter().map(|s| s.path.clone()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut contents = HashMap::new();
+    for path in &paths {
+        let content = client
+            .get_file_content(repo, path, head_sha)
+            .await
+            .with_context(|| format!("Failed to fetch {path} at {head_sha}"))?;
+        contents.insert(path.clone(), content);
+    }
+
+    let diff = patch::build_patch(&contents, suggestions)?;
+    std::fs::write(out_path, &diff).with_context(|| format!("Failed to write {out_path}"))?;
+
+    print_json(&PatchOut {
+        path: out_path.to_string(),
+        suggestions: suggestions.len(),
+        files: paths.len(),
+    })
+}
+
+/// Extract a text keyword from an ast-grep pattern for pre-filtering via code search.
+/// Takes everything before the first meta-variable ($) or opening paren with $.
+/// Falls back to the whole pattern if no good keyword found.
+fn extract_search_keyword(pattern: &str) -> &str {
+    let end = pattern.find('$').unwrap_or(pattern.len());
+    let keyword = pattern[..end].trim().trim_end_matches('(');
+    if keyword.is_empty() {
+        pattern.split_whitespace().next().unwrap_or(pattern)
+    } else {
+        keyword
+    }
+}
+
+/// Which files `pr grep` and `pr ast-grep` search, from their shared flags
+#[derive(Default)]
+pub struct SearchFiles<'a> {
+    /// --file and --file-regex
+    pub file_filter: FileFilter,
+    /// --repo-wide, narrowed by `scope`
+    pub repo_wide: bool,
+    pub scope: search::CodeSearchScope,
+    pub targets: search::SearchTargets,
+    pub owned_by: Option<&'a str>,
+    /// --all: keep noise files
+    pub include_all: bool,
+}
+
+/// `pr grep`'s own flags
+pub struct GrepOptions<'a> {
+    pub pattern: &'a str,
+    pub case_sensitive: bool,
+    /// -C lines around each match
+    pub context_lines: usize,
+    /// --max-bytes or --max-tokens
+    pub limit: Option<Limit>,
+}
+
+pub async fn pr_grep(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    files: &SearchFiles<'_>,
+    options: GrepOptions<'_>,
+) -> Result<()> {
+    let GrepOptions {
+        pattern,
+        case_sensitive,
+        context_lines,
+        limit,
+    } = options;
+    let include_all = files.include_all;
+    let repo_wide = files.repo_wide;
+    let targets = &files.targets;
+    let queries = if repo_wide { search::code_search_queries(pattern, repo, &files.scope)? } else { vec![] };
+    let excludes = exclude_globs(&targets.exclude)?;
+    let (pr_file_paths, git_ref) = resolve_search_targets(client, repo, number, files, &excludes).await?;
+
+    eprintln!("Fetching {} files at {}...", pr_file_paths.len(), git_ref);
+    let pr_files = fetch_targets(client, repo, &pr_file_paths, &git_ref, targets).await;
+    let mut pr_matches = search::grep_files(&pr_files, pattern, case_sensitive, context_lines);
+
+    if repo_wide {
+        // Search the broader codebase via GitHub Code Search (default branch)
+        eprintln!("Searching codebase via GitHub Code Search...");
+        let search_results = search_within_budget(client, &queries).await?;
+        eprintln!("Code Search: {} results from default branch", search_results.total_count);
+
+        // Convert code search results to SearchMatch, but skip files already in PR
+        let pr_file_set: std::collections::HashSet<&str> = pr_file_paths.iter().map(|s| s.as_str()).collect();
+
+        for item in &search_results.items {
+            if pr_file_set.contains(item.path.as_str()) {
+                continue; // PR version takes priority
+            }
+            if (!include_all && is_noise_file(&item.path)) || excludes.is_match(&item.path) {
+                continue;
+            }
+            if let Some(text_matches) = &item.text_matches {
+                for tm in text_matches {
+                    for (line_idx, line) in tm.fragment.lines().enumerate() {
+                        let haystack = if case_sensitive { line.to_string() } else { line.to_lowercase() };
+                        let pat = if case_sensitive { pattern.to_string() } else { pattern.to_lowercase() };
+                        if let Some(at) = haystack.find(&pat) {
+                            pr_matches.push(search::SearchMatch {
+                                file: item.path.clone(),
+                                line: line_idx + 1,
+                                column: at + 1,
+                                text: line.to_string(),
+                                lead: String::new(),
+                                context_before: vec![],
+                                context_after: vec![],
+                                rewrite: None,
+                                highlight: search::highlight(line, &haystack, at, pat.len()),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    print_limited(&search::format_matches(&pr_matches, search::DEFAULT_MAX_MATCH_LINES), limit);
+    Ok(())
+}
+
+/// `pr ast-grep`'s own flags
+#[derive(Default)]
+pub struct AstGrepOptions<'a> {
+    pub pattern: &'a str,
+    /// --lang, instead of guessing from each file's extension
+    pub lang_override: Option<&'a str>,
+    pub max_match_lines: usize,
+    pub json: bool,
+    pub rewrite: Option<&'a str>,
+    /// --suggest: post the rewrites as suggestions
+    pub suggest: bool,
+    pub allow_closed: bool,
+}
+
+pub async fn pr_ast_grep(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    files: &SearchFiles<'_>,
+    options: AstGrepOptions<'_>,
+) -> Result<()> {
+    let AstGrepOptions {
+        pattern,
+        lang_override,
+        max_match_lines,
+        json,
+        rewrite,
+        suggest,
+        allow_closed,
+    } = options;
+    let include_all = files.include_all;
+    let repo_wide = files.repo_wide;
+    let targets = &files.targets;
+    // Use text keyword from AST pattern to pre-filter via Code Search
+    let keyword = extract_search_keyword(pattern);
+    let queries = if repo_wide { search::code_search_queries(keyword, repo, &files.scope)? } else { vec![] };
+
+    let lang: Option<ast_grep_language::SupportLang> = lang_override
+        .map(|l| l.parse())
+        .transpose()
+        .map_err(|e: ast_grep_language::SupportLangErr| anyhow::anyhow!("{e}"))
+        .context("Invalid language. Use: ts, tsx, js, jsx, py, rs, go, java, etc.")?;
+    let excludes = exclude_globs(&targets.exclude)?;
+
+    let (pr_file_paths, git_ref) = resolve_search_targets(client, repo, number, files, &excludes).await?;
+
+    let mut all_file_paths = pr_file_paths.clone();
+
+    if repo_wide {
+        eprintln!("Searching codebase for '{}' via GitHub Code Search...", keyword);
+
+        let search_results = search_within_budget(client, &queries).await?;
+        eprintln!("Code Search: {} candidate files from default branch", search_results.total_count);
+
+        let pr_file_set: std::collections::HashSet<String> = pr_file_paths.iter().cloned().collect();
+
+        for item in &search_results.items {
+            if !pr_file_set.contains(&item.path) {
+                if (include_all || !is_noise_file(&item.path)) && !excludes.is_match(&item.path) {
+                    all_file_paths.push(item.path.clone());
+                }
+            }
+        }
+
+        // Dedup
+        all_file_paths.sort();
+        all_file_paths.dedup();
+    }
+
+    if all_file_paths.is_empty() {
+        outln!("No files to search.");
+        return Ok(());
+    }
+
+    eprintln!("Fetching {} files at {}...", all_file_paths.len(), git_ref);
+    let files = fetch_targets(client, repo, &all_file_paths, &git_ref, targets).await;
+
+    if files.is_empty() {
+        outln!("No readable files found.");
+        return Ok(());
+    }
+
+    let matches = search::ast_grep_files(&files, pattern, lang, rewrite)?;
+    if suggest {
+        return suggest_rewrites(client, repo, number, &files, &matches, allow_closed).await;
+    }
+    if json {
+        return print_json(&matches.iter().map(AstMatchJson::from).collect::<Vec<_>>());
+    }
+    if rewrite.is_some() {
+        outln!("{}", search::format_rewrites(&matches));
+        return Ok(());
+    }
+    outln!("{}", search::format_matches(&matches, max_match_lines));
+    Ok(())
+}
+
+/// `pr ast-grep --rewrite --suggest`: post the rewrites of matches on the PR's diff
+/// lines as suggestions, in one review. Files the PR doesn't change (from
+/// `--repo-wide` or `--files-from`) are never posted to.
+async fn suggest_rewrites(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    files: &[(String, String)],
+    matches: &[search::SearchMatch],
+    allow_closed: bool,
+) -> Result<()> {
+    // metadata + raw diff + the review POST
+    client.check_budget(3)?;
+    let pr = client.get_pr(repo, number).await?;
+    ensure_open_json(number, &pr.state, Some(allow_closed))?;
+    let pr = client.add_patches(repo, pr).await?;
+    let mut suggestions = Vec::new();
+    for (path, content) in files {
+        let in_file: Vec<&search::SearchMatch> = matches.iter().filter(|m| &m.file == path).collect();
+        if in_file.is_empty() {
+            continue;
+        }
+        let hunks = pr_file_hunks(&pr, path);
+        if hunks.is_empty() {
+            eprintln!("SKIPPED: {path}: not changed in this PR, {} matches shown only", in_file.len());
+            continue;
+        }
+        suggestions.extend(rewrite_suggestions(path, content, &hunks, &in_file));
+    }
+    if suggestions.is_empty() {
+        anyhow::bail!("No match falls on lines of the PR's diff; nothing to suggest");
+    }
+    post_suggestions(client, repo, number, &pr.head_sha, &suggestions, None, Some("ast-grep rewrites from gh-agent")).await
+}
+
+/// Suggestions making the rewrites of `matches` whose lines touch the diff of
+/// `path`. A rewrite also changing lines outside it can't be posted, and is
+/// reported as `UNPOSTABLE: …` instead.
+fn rewrite_suggestions(path: &str, content: &str, hunks: &[DiffHunk], matches: &[&search::SearchMatch]) -> Vec<Suggestion> {
+    let cl = commentable_lines(hunks);
+    let touching: Vec<&search::SearchMatch> = matches
+        .iter()
+        .filter(|m| (m.line..=search::end_line(m)).any(|l| cl.contains(&(l as u64))))
+        .copied()
+        .collect();
+    search::rewritten_lines(content, &touching)
+        .into_iter()
+        .map(|(first, last, replacement)| Suggestion {
+            path: path.to_string(),
+            start_line: first as u64,
+            end_line: last as u64,
+            replacement,
+        })
+        .filter(|s| match comment_range_problem(path, hunks, s.start_line, s.end_line) {
+            Some(problem) => {
+                eprintln!("UNPOSTABLE: {path}:{}-{}: {problem}", s.start_line, s.end_line);
+                false
+            }
+            None => true,
+        })
+        .collect()
+}
+
+/// One `pr each` output line
+#[derive(Serialize)]
+struct EachRecord {
+    number: u64,
+    ok: bool,
+    /// What the command printed: parsed when it's JSON, else the text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// `pr each`: run every `(number, job)`, at most `concurrency` at once. Each PR's
+/// output is collected and printed as one JSON line, in the order of `jobs` whatever
+/// order they finish in. A PR that fails gets an `error` in its line and the rest
+/// carry on; the run fails at the end if any did.
+pub async fn pr_each<T, F, Fut>(jobs: Vec<(u64, T)>, concurrency: usize, run: F) -> Result<()>
+where
+    F: Fn(T) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    use futures::StreamExt;
+
+    let total = jobs.len();
+    let mut results = futures::stream::iter(jobs)
+        .map(|(number, job)| {
+            let fut = run(job);
+            async move { (number, pager::capture(fut).await) }
+        })
+        .buffered(concurrency.max(1));
+    let mut failed = 0;
+    while let Some((number, (output, result))) = results.next().await {
+        let output = (!output.trim().is_empty()).then(|| {
+            serde_json::from_str(&output).unwrap_or_else(|_| serde_json::Value::String(output.trim_end().to_string()))
+        });
+        let error = result.err().map(|e| format!("{e:#}"));
+        failed += usize::from(error.is_some());
+        let record = EachRecord {
+            number,
+            ok: error.is_none(),
+            output,
+            error,
+        };
+        outln!("{}", serde_json::to_string(&record)?);
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {total} PRs failed");
+    }
+    Ok(())
+}
+
+/// `pr each` PR numbers from stdin, separated by whitespace or commas (`#12` works too)
+pub fn read_pr_numbers() -> Result<Vec<u64>> {
+    let mut raw = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut raw).context("Failed to read PR numbers from stdin")?;
+    parse_pr_numbers(&raw)
+}
+
+fn parse_pr_numbers(raw: &str) -> Result<Vec<u64>> {
+    let numbers = raw
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.trim_start_matches('#')
+                .parse()
+                .with_context(|| format!("Not a PR number: {s}"))
+        })
+        .collect::<Result<Vec<u64>>>()?;
+    if numbers.is_empty() {
+        anyhow::bail!("No PR numbers given (pass --numbers or pipe them in)");
+    }
+    Ok(numbers)
+}
+
+/// Read a `--files-from` list from a file, or stdin for "-"
+pub fn read_files_from(spec: &str, nul: bool) -> Result<Vec<String>> {
+    let raw = if spec == "-" {
+        let mut raw = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut raw).context("Failed to read paths from stdin")?;
+        raw
+    } else {
+        std::fs::read_to_string(spec).with_context(|| format!("Failed to read {spec}"))?
+    };
+    Ok(search::parse_path_list(&raw, nul))
+}
+
+/// The paths grep/ast-grep search and the ref to read them at. `--files-from`
+/// paths replace the PR's changed files (`--file` and `--owned-by` still apply,
+/// the noise filter doesn't). The PR is only fetched when the ref, the file
+/// list or CODEOWNERS needs it.
+async fn resolve_search_targets(
+    client: &github::Client,
+    repo: &str,
+    number: u64,
+    files: &SearchFiles<'_>,
+    excludes: &GlobSet,
+) -> Result<(Vec<String>, String)> {
+    let &SearchFiles {
+        ref file_filter,
+        ref targets,
+        owned_by,
+        include_all,
+        ..
+    } = files;
+    let pr = match (&targets.files_from, &targets.git_ref, owned_by) {
+        (Some(_), Some(_), None) => None,
+        _ => Some(client.get_pr(repo, number).await?),
+    };
+    if let Some(pr) = &pr {
+        note_state(pr);
+    }
+    let git_ref = match (&targets.git_ref, &pr) {
+        (Some(r), _) => r.clone(),
+        (None, Some(pr)) if targets.base => pr.base_ref.clone(),
+        (None, Some(pr)) => pr.head_ref.clone(),
+        (None, None) => unreachable!("the PR is fetched whenever no --ref is given"),
+    };
+    let owned = match &pr {
+        Some(pr) => {
+            let paths = match &targets.files_from {
+                Some(listed) => listed.iter().map(String::as_str).collect(),
+                None => changed_paths(pr),
+            };
+            resolve_owned_by(client, repo, pr, &paths, owned_by).await?
+        }
+        None => None,
+    };
+
+    let paths: Vec<String> = match (&targets.files_from, &pr) {
+        (Some(listed), _) => listed
+            .iter()
+            .filter(|p| file_filter.matches(p))
+            .filter(|p| owned.as_ref().is_none_or(|o| o.owns(p)))
+            .cloned()
+            .collect(),
+        (None, Some(pr)) => {
+            let (selected, skipped) = select_files(&pr.files, file_filter, owned.as_ref(), include_all, false);
+            report_owned_by(owned_by, selected.len() + skipped);
+            let paths = selected.iter().map(|f| f.filename.clone()).collect();
+            return Ok((exclude_paths(paths, excludes, String::as_str), git_ref));
+        }
+        (None, None) => unreachable!("the PR is fetched whenever there's no --files-from"),
+    };
+    report_owned_by(owned_by, paths.len());
+    Ok((exclude_paths(paths, excludes, String::as_str), git_ref))
+}
+
+/// Fetch the search targets, warning about `--files-from` paths missing at the ref
+async fn fetch_targets(
+    client: &github::Client,
+    repo: &str,
+    paths: &[String],
+    git_ref: &str,
+    targets: &search::SearchTargets,
+) -> Vec<(String, String)> {
+    let (files, not_found) = fetch_file_contents(client, repo, paths, git_ref).await;
+    if targets.files_from.is_some() {
+        for path in not_found {
+            eprintln!("warning: {path} does not exist at {git_ref}");
+        }
+    }
+    files
+}
+
+/// Run each code search query and merge the results, dropping repeated paths.
+/// Degrades to what was found so far (with a warning) once the request budget is spent.
+async fn search_within_budget(client: &github::Client, queries: &[String]) -> Result<github::CodeSearchResponse> {
+    let mut merged = github::CodeSearchResponse { total_count: 0, items: vec![] };
+    let mut seen = HashSet::new();
+    for q in queries {
+        match client.search_code(q).await {
+            Ok(resp) => {
+                merged.total_count += resp.total_count;
+                merged.items.extend(resp.items.into_iter().filter(|item| seen.insert(item.path.clone())));
+            }
+            Err(e) if github::is_budget_exhausted(&e) => {
+                eprintln!("warning: {e}; skipping repo-wide search (results are partial)");
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(merged)
+}
+
+/// Why a file fetch produced nothing
+enum FetchSkip {
+    /// The request budget ran out first
+    Budget,
+    NotFound(String),
+    /// Binary, too large, or another API error
+    Unreadable,
+}
+
+/// Fetch file contents concurrently, skipping failures. Returns the files read
+/// and the paths that don't exist at `git_ref`. Warns when the request budget
+/// cut the fetch short.
+async fn fetch_file_contents(
+    client: &github::Client,
+    repo: &str,
+    paths: &[String],
+    git_ref: &str,
+) -> (Vec<(String, String)>, Vec<String>) {
+    let futs: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            let path = path.clone();
+            let repo = repo.to_string();
+            let git_ref = git_ref.to_string();
+            async move {
+                client
+                    .get_file_content(&repo, &path, &git_ref)
+                    .await
+                    .map(|content| (path.clone(), content))
+                    .map_err(|e| {
+                        if github::is_budget_exhausted(&e) {
+                            FetchSkip::Budget
+                        } else if github::api_error(&e).is_some_and(|a| a.status == reqwest::StatusCode::NOT_FOUND) {
+                            FetchSkip::NotFound(path)
+                        } else {
+                            FetchSkip::Unreadable
+                        }
+                    })
+            }
+        })
+        .collect();
+
+    let results = futures::future::join_all(futs).await;
+    let skipped = results.iter().filter(|r| matches!(r, Err(FetchSkip::Budget))).count();
+    if skipped > 0 {
+        eprintln!(
+            "warning: request budget exhausted; fetched {} of {} files (results are partial)",
+            paths.len() - skipped,
+            paths.len()
+        );
+    }
+    let mut files = Vec::new();
+    let mut not_found = Vec::new();
+    for r in results {
+        match r {
+            Ok(file) => files.push(file),
+            Err(FetchSkip::NotFound(path)) => not_found.push(path),
+            Err(_) => {}
+        }
+    }
+    (files, not_found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{contents_response, pr_response, MockServer};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_fetch_stops_at_request_budget() {
+        let server = MockServer::start(|_, _, _| (200, contents_response("fn main() {}\n")));
+        let client = github::Client::for_test(&server.url, Some(3));
+        let paths: Vec<String> = (0..5).map(|i| format!("src/f{i}.rs")).collect();
+
+        let (files, _) = fetch_file_contents(&client, "o/r", &paths, "main").await;
+
+        assert_eq!(files.len(), 3);
+        assert!(files.iter().all(|(_, content)| content == "fn main() {}\n"));
+        assert_eq!(server.hits(), 3);
+        assert_eq!(client.requests_used(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_file_pairs_read_a_renamed_base_at_its_old_name() {
+        let server = MockServer::start(|method, path, _| match path {
+            "/repos/o/r/contents/src/old.rs?ref=base" => (200, contents_response("old\n")),
+            "/repos/o/r/contents/src/new.rs?ref=head" => (200, contents_response("new\n")),
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let files = [github::PrFile {
+            filename: "src/new.rs".to_string(),
+            status: "renamed".to_string(),
+            additions: 1,
+            deletions: 1,
+            previous_filename: Some("src/old.rs".to_string()),
+            ..Default::default()
+        }];
+
+        let pairs = client.get_file_pairs("o/r", &files, "base", "head").await;
+        assert_eq!(pairs[0].2.as_deref(), Some("old\n"));
+        assert_eq!(pairs[0].3.as_deref(), Some("new\n"));
+    }
+
+    #[tokio::test]
+    async fn test_files_from_with_ref_skips_the_pr_fetch() {
+        let server = MockServer::start(|method, path, _| match path {
+            "/repos/o/r/contents/src/a.rs?ref=v1" => (200, contents_response("// TODO\n")),
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let files = SearchFiles {
+            file_filter: FileFilter::new(&["src/".to_string()], &[]).unwrap(),
+            targets: search::SearchTargets {
+                files_from: Some(vec!["src/a.rs".to_string(), "src/gone.rs".to_string(), "docs/x.md".to_string()]),
+                git_ref: Some("v1".to_string()),
+                base: false,
+                exclude: vec![],
+            },
+            ..Default::default()
+        };
+
+        let (paths, git_ref) = resolve_search_targets(&client, "o/r", 1, &files, &GlobSet::empty())
+            .await
+            .unwrap();
+        assert_eq!((paths.as_slice(), git_ref.as_str()), (&["src/a.rs".to_string(), "src/gone.rs".to_string()][..], "v1"));
+        let (files, not_found) = fetch_file_contents(&client, "o/r", &paths, &git_ref).await;
+        assert_eq!(files, [("src/a.rs".to_string(), "// TODO\n".to_string())]);
+        assert_eq!(not_found, ["src/gone.rs"]);
+        assert_eq!(server.hits(), 2, "contents only, no PR metadata");
+    }
+
+    #[tokio::test]
+    async fn test_assign_skips_unassignable_logins_and_resolves_milestone() {
+        let server = MockServer::start(|method, path, body| match (method, path) {
+            ("POST", "/graphql") => (200, pr_response(1, "abc")),
+            ("GET", "/repos/o/r/assignees/alice") => (204, String::new()),
+            ("GET", "/repos/o/r/assignees/ghost") => (404, r#"{"message": "Not Found"}"#.to_string()),
+            ("GET", "/repos/o/r/milestones?state=all&per_page=100&page=1") => {
+                (200, r#"[{"number": 3, "title": "v1.0"}, {"number": 4, "title": "v1.1"}]"#.to_string())
+            }
+            ("GET", _) if path.starts_with("/repos/o/r/milestones") => (200, "[]".to_string()),
+            ("POST", "/repos/o/r/issues/1/assignees") => {
+                assert_eq!(body, r#"{"assignees":["alice"]}"#);
+                (200, r#"{"assignees": [{"login": "alice"}], "milestone": null}"#.to_string())
+            }
+            ("PATCH", "/repos/o/r/issues/1") => {
+                assert_eq!(body, r#"{"milestone":4}"#);
+                (200, r#"{"assignees": [{"login": "alice"}], "milestone": {"number": 4, "title": "v1.1"}}"#.to_string())
+            }
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let add = ["@alice".to_string(), "ghost".to_string()];
+        pr_assign(&client, "o/r", 1, AssignOptions { add: &add, milestone: Some("V1.1"), json: true, ..Default::default() }).await.unwrap();
+        assert_eq!(server.hits(), 7, "PR state, milestones (2 pages), 2 login checks, assign, milestone");
+
+        let err = pr_assign(&client, "o/r", 1, AssignOptions { milestone: Some("v2"), json: true, ..Default::default() }).await.unwrap_err();
+        assert_eq!(err.to_string(), "No milestone titled \"v2\" in o/r (found: v1.0, v1.1)");
+    }
+
+    #[tokio::test]
+    async fn test_write_fails_before_posting_when_over_budget() {
+        let server = MockServer::start(|_, _, _| (200, "{}".to_string()));
+        let client = github::Client::for_test(&server.url, Some(1));
+
+        let err = pr_suggest(&client, "o/r", 1, suggestion("a.rs", 1, 1, "x"), &SuggestOptions::default()).await.unwrap_err();
+        assert!(err.to_string().contains("--max-requests 1"), "{err}");
+        assert_eq!(server.hits(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_owned_by_filters_selection() {
+        const RULES: &str = "* @org/core\n/src/payments/ @org/payments\n/src/payments/legacy/ @alice\n";
+        let server = MockServer::start(|_, path, _| {
+            if path.starts_with("/repos/o/r/contents/CODEOWNERS") {
+                (200, contents_response(RULES))
+            } else {
+                (404, r#"{"message":"Not Found"}"#.to_string())
+            }
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let file = |name: &str| github::PrFile {
+            filename: name.to_string(),
+            status: "modified".to_string(),
+            additions: 1,
+            ..Default::default()
+        };
+        let files = [
+            file("src/main.rs"),
+            file("src/payments/api.rs"),
+            file("src/payments/legacy/v1.rs"),
+            file("src/payments/package-lock.json"),
+        ];
+
+        let pr = github::PullRequest {
+            node_id: "PR_node".to_string(),
+            number: 1,
+            title: "t".to_string(),
+            body: None,
+            state: "OPEN".to_string(),
+            additions: 4,
+            deletions: 0,
+            changed_files: 4,
+            is_draft: false,
+            head_ref: "feature".to_string(),
+            base_ref: "main".to_string(),
+            head_sha: "abc".to_string(),
+            base_sha: "def".to_string(),
+            is_cross_repository: false,
+            files: files.to_vec(),
+        };
+
+        let owned = resolve_owned_by(&client, "o/r", &pr, &changed_paths(&pr), Some("org/Payments")).await.unwrap();
+        // .github/CODEOWNERS misses, root CODEOWNERS is used
+        assert_eq!(server.hits(), 2);
+
+        // legacy/ is reassigned to @alice by a later rule
+        let (selected, noise) = select_files(&files, &FileFilter::default(), owned.as_ref(), false, false);
+        let names: Vec<&str> = selected.iter().map(|f| f.filename.as_str()).collect();
+        assert_eq!(names, ["src/payments/api.rs"]);
+        assert_eq!(noise, 1);
+
+        let (selected, _) = select_files(&files, &FileFilter::new(&["legacy".to_string()], &[]).unwrap(), None, false, false);
+        assert_eq!(selected.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_owners_files_fetched_for_ancestor_dirs_and_cached() {
+        config::use_cache_dir_for_test("owners-tree");
+        let sha = "owners-test";
+        let listing = r#"{"truncated": false, "tree": [
+            {"path": "OWNERS", "type": "blob"},
+            {"path": "src", "type": "tree"},
+            {"path": "src/OWNERS", "type": "blob"},
+            {"path": "src/a.rs", "type": "blob"},
+            {"path": "docs/OWNERS", "type": "blob"}
+        ]}"#;
+        let server = MockServer::start(move |_, path, _| {
+            if path.starts_with("/repos/o/r/git/trees/") {
+                (200, listing.to_string())
+            } else if path.starts_with("/repos/o/r/contents/OWNERS?") {
+                (200, contents_response("root\n"))
+            } else if path.starts_with("/repos/o/r/contents/src/OWNERS?") {
+                (200, contents_response("set noparent\nsrc-owner\n"))
+            } else {
+                (404, r#"{"message":"Not Found"}"#.to_string())
+            }
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let tree = fetch_owners_tree(&client, "o/r", sha, &["src/a.rs", "README.md"]).await.unwrap();
+        // tree listing plus the two OWNERS on the paths' ancestor chains; docs/ is never fetched
+        assert_eq!(server.hits(), 3);
+        assert_eq!(tree.owners_of("src/a.rs"), ["@src-owner"]);
+        assert_eq!(tree.owners_of("README.md"), ["@root"]);
+
+        fetch_owners_tree(&client, "o/r", sha, &["src/a.rs"]).await.unwrap();
+        assert_eq!(server.hits(), 3, "served from the per-sha cache");
+        let _ = std::fs::remove_dir_all(config::cache_dir().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_ownership_read_at_the_base_commit() {
+        config::use_cache_dir_for_test("owners-base");
+        // The head and the base branch's tip name the PR's author; only the
+        // base commit counts
+        let server = MockServer::start(|_, path, _| match path {
+            "/repos/o/r/contents/CODEOWNERS?ref=base-sha" => (200, contents_response("/docs/ @docs\n")),
+            "/repos/o/r/contents/CODEOWNERS?ref=main" | "/repos/o/r/contents/CODEOWNERS?ref=head-sha" => {
+                (200, contents_response("* @author\n"))
+            }
+            "/repos/o/r/git/trees/base-sha?recursive=1" => {
+                (200, r#"{"truncated": false, "tree": [{"path": "OWNERS", "type": "blob"}]}"#.to_string())
+            }
+            "/repos/o/r/contents/OWNERS?ref=base-sha" => (200, contents_response("maintainer\n")),
+            "/repos/o/r/contents/OWNERS?ref=head-sha" => (200, contents_response("author\n")),
+            _ => (404, r#"{"message":"Not Found"}"#.to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let pr = github::PullRequest {
+            node_id: "PR_node".to_string(),
+            number: 1,
+            title: "t".to_string(),
+            body: None,
+            state: "OPEN".to_string(),
+            additions: 1,
+            deletions: 0,
+            changed_files: 1,
+            is_draft: false,
+            head_ref: "feature".to_string(),
+            base_ref: "main".to_string(),
+            head_sha: "head-sha".to_string(),
+            base_sha: "base-sha".to_string(),
+            is_cross_repository: false,
+            files: vec![],
+        };
+        let settings = config::OwnersConfig {
+            enabled: true,
+            ..Default::default()
+        };
+
+        let rules = fetch_ownership_with(&client, "o/r", &pr, &["src/a.rs"], settings).await.unwrap();
+        assert_eq!(rules.owners_of("src/a.rs"), ["@maintainer"]);
+        assert_eq!(rules.owners_of("docs/a.md"), ["@docs"]);
+        let _ = std::fs::remove_dir_all(config::cache_dir().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_me_identity_is_cached() {
+        let dir = config::use_cache_dir_for_test("identity");
+        let server = MockServer::start(|method, path, _| match path {
+            "/user" => (200, r#"{"login":"Alice"}"#.to_string()),
+            "/user/teams?per_page=100&page=1" => (200, r#"[{"slug":"payments","organization":{"login":"org"}}]"#.to_string()),
+            "/user/teams?per_page=100&page=2" => (200, "[]".to_string()),
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let me = my_owner_handles(&client).await.unwrap();
+        assert_eq!(me, HashSet::from(["@alice".to_string(), "@org/payments".to_string()]));
+        assert_eq!(server.hits(), 3);
+        assert!(dir.join("identity.json").exists());
+
+        assert_eq!(my_owner_handles(&client).await.unwrap(), me);
+        assert_eq!(server.hits(), 3, "served from the cache");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    fn comment(path: &str, line: u64) -> ReviewCommentInput {
+        ReviewCommentInput {
+            path: path.to_string(),
+            line,
+            body: "nit".to_string(),
+            start_line: None,
+            side: None,
+            start_side: None,
+        }
+    }
+
+    fn suggestion(path: &str, start_line: u64, end_line: u64, replacement: &str) -> Suggestion {
+        Suggestion {
+            path: path.to_string(),
+            start_line,
+            end_line,
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_review_input_shapes() {
+        let full = parse_review_input(r#"{"body": "LGTM", "comments": []}"#).unwrap();
+        assert_eq!((full.body.as_str(), full.comments.len()), ("LGTM", 0));
+        let bare = parse_review_input(r#" [{"path": "a.rs", "line": 1, "body": "nit"}]"#).unwrap();
+        assert_eq!((bare.body.as_str(), bare.comments.len()), ("Review from gh-agent", 1));
+        assert!(parse_review_input(r#"[{"line": "x"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_review_input_points_at_bad_entries() {
+        let raw = r#"{"event": "MERGE", "comments": [
+            {"path": "a.rs", "line": 1, "body": "ok"},
+            {"path": "a.rs", "line": "two", "body": "x"},
+            "nit",
+            {"path": "a.rs", "line": 3, "body": "x", "side": "MIDDLE"}
+        ]}"#;
+        let err = parse_review_input(raw).err().unwrap().to_string();
+        assert_eq!(
+            err,
+            "event: unknown \"MERGE\" (expected one of COMMENT, APPROVE, REQUEST_CHANGES)\ncomments[2]: expected object, got string \"nit\""
+        );
+
+        let raw = r#"{"comments": [{"path": "a.rs", "line": "two", "body": "x"}, {"path": "a.rs", "line": 3, "side": "MIDDLE"}]}"#;
+        let err = parse_review_input(raw).err().unwrap().to_string();
+        let lines: Vec<&str> = err.lines().collect();
+        assert_eq!(lines.len(), 2, "{err}");
+        assert!(lines[0].starts_with("comments[0]: invalid type: string \"two\""), "{err}");
+        assert!(lines[1].starts_with("comments[1]: unknown variant `MIDDLE`"), "{err}");
+
+        let input = parse_review_input(r#"{"event": "APPROVE", "comments": [{"path": "a.rs", "line": 1, "body": "x", "why": 1}]}"#).unwrap();
+        assert_eq!(input.event, "APPROVE");
+        assert_eq!(input.warnings, ["comments[0].why: unknown field, ignored"]);
+    }
+
+    #[test]
+    fn test_confirm_summary() {
+        let mut range = comment("src/b.rs", 9);
+        range.start_line = Some(4);
+        let comments = [comment("a.rs", 12), range, comment("a.rs", 3), comment("src/b.rs", 20)];
+        let body = "1\n2\n3\n4\n5\n6";
+        assert_eq!(
+            confirm_summary(&comments, "APPROVE", body),
+            "file      comments  lines\n\
+             a.rs             2  3-12\n\
+             src/b.rs         2  4-20\n\
+             4 comments in 2 files\nevent: APPROVE\nbody:\n  1\n  2\n  3\n  4\n  5\n  …\n"
+        );
+        assert!(confirm_summary(&[], "COMMENT", "LGTM").ends_with("body:\n  LGTM\n"));
+    }
+
+    #[test]
+    fn test_exit_statuses() {
+        assert_eq!(EXIT_ALL_SKIPPED, 2);
+        assert_eq!(EXIT_DECLINED, 3);
+        assert_eq!(EXIT_PR_NOT_OPEN, 4);
+        assert!(is_declined(&anyhow::Error::from(Declined)));
+        assert!(!is_pr_not_open(&anyhow::Error::from(Declined)));
+    }
+
+    #[test]
+    fn test_read_replacement_keeps_the_file_verbatim() {
+        let path = std::env::temp_dir().join(format!("gh-agent-replacement-{}.rs", std::process::id()));
+        let file = path.to_str().unwrap();
+        std::fs::write(&path, "\tif x {\n\t\ty()\n\t}\n\n").unwrap();
+        assert_eq!(read_replacement(None, Some(file)).unwrap(), "\tif x {\n\t\ty()\n\t}\n");
+        std::fs::write(&path, "a\r\n").unwrap();
+        assert_eq!(read_replacement(None, Some(file)).unwrap(), "a");
+        assert!(read_replacement(Some("b".to_string()), Some(file)).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_replacement(Some("b\n".to_string()), None).unwrap(), "b\n");
+        assert!(read_replacement(None, None).is_err());
+    }
+
+    #[test]
+    fn test_read_review_body() {
+        let path = std::env::temp_dir().join(format!("gh-agent-body-{}.md", std::process::id()));
+        let file = path.to_str().unwrap();
+        std::fs::write(&path, "## Summary\n\nLooks good.\n").unwrap();
+        assert_eq!(read_review_body(None, Some(file), "-").unwrap().as_deref(), Some("## Summary\n\nLooks good.\n"));
+        std::fs::write(&path, " \n").unwrap();
+        assert_eq!(read_review_body(None, Some(file), "r.json").unwrap().as_deref(), Some("Review from gh-agent"));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_review_body(Some("short"), None, "-").unwrap().as_deref(), Some("short"));
+        assert_eq!(read_review_body(None, None, "r.json").unwrap(), None);
+        assert!(read_review_body(Some("a"), Some(file), "r.json").is_err());
+        // Both from stdin: refused before reading anything
+        assert!(read_review_body(None, Some("-"), "-").is_err());
+    }
+
+    #[test]
+    fn test_review_templates() {
+        let path = std::env::temp_dir().join(format!("gh-agent-template-{}.md", std::process::id()));
+        let file = path.to_str().unwrap();
+        std::fs::write(&path, "## {{ pr_title }}\n{{risk}} / {{owner}} / {{risk}}").unwrap();
+        let err = read_template(file, &BTreeMap::new()).unwrap_err().to_string();
+        assert!(err.contains("Unknown placeholders in"), "{err}");
+        assert!(err.contains("{{risk}}, {{owner}}; supported: pr_title, changed_files"), "{err}");
+
+        let vars = parse_template_vars(&["risk=low".to_string(), "owner=a=b".to_string()]).unwrap();
+        let template = read_template(file, &vars).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut values = vars.clone();
+        values.insert("pr_title".to_string(), "Fix the cache".to_string());
+        assert_eq!(fill_template(&template, &values), "## Fix the cache\nlow / a=b / low");
+        // Nothing to fill, or no closing braces: left as written
+        assert_eq!(fill_template("{{x}} and {{", &values), "{{x}} and {{");
+        assert_eq!(fill_template("{{a {{risk}}, {{{risk}}}", &values), "{{a low, {low}");
+        assert!(parse_template_vars(&["novalue".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_review_comment_suggestions() {
+        let patch = "@@ -1,3 +1,4 @@\n a\n-b\n+b2\n+b3\n c\n@@ -20,2 +21,3 @@\n x\n+y\n z";
+        let file_hunks = HashMap::from([("a.rs".to_string(), parse_patch(patch))]);
+        let file_commentable: HashMap<&str, Vec<u64>> =
+            file_hunks.iter().map(|(p, h)| (p.as_str(), commentable_lines(h))).collect();
+        let entry = |raw: &str| -> CommentInput { serde_json::from_str(raw).unwrap() };
+        let build = |raw: &str| review_comment(&entry(raw), &file_hunks, &file_commentable, &[], 0, false, false).map(|(c, _)| c);
+
+        let plain = build(r#"{"path": "a.rs", "line": 2, "body": "why?"}"#).unwrap();
+        assert_eq!(plain.body, "why?");
+        let alone = build(r#"{"path": "./a.rs", "line": 2, "suggestion": "b4\n"}"#).unwrap();
+        assert_eq!(alone.body, "```suggestion\nb4\n```");
+        let both = build(r#"{"path": "a.rs", "line": 3, "start_line": 2, "body": "Simpler:", "suggestion": "b"}"#).unwrap();
+        assert_eq!((both.start_line, both.line), (Some(2), 3));
+        assert_eq!(both.body, "Simpler:\n\n```suggestion\nb\n```");
+
+        let err = build(r#"{"path": "a.rs", "line": 22, "start_line": 3, "suggestion": "x"}"#).unwrap_err();
+        assert!(err.message.contains("spans more than one hunk"), "{err:?}");
+        assert_eq!(err.reason, SkipReason::RangeCrossesHunk);
+        let err = build(r#"{"path": "a.rs", "line": 10, "suggestion": "x"}"#).unwrap_err();
+        assert!(err.message.contains("not a commentable line"), "{err:?}");
+        assert_eq!((err.reason, err.line), (SkipReason::NotInDiff, Some(10)));
+        let err = build(r#"{"path": "a.rs", "line": 2, "body": " "}"#).unwrap_err();
+        assert!(err.message.contains("neither a body nor a suggestion"), "{err:?}");
+        assert_eq!(err.reason, SkipReason::EmptyBody);
+        let err = build(r#"{"path": "b.rs", "line": 2, "body": "x"}"#).unwrap_err();
+        assert_eq!((err.path.as_str(), err.reason), ("b.rs", SkipReason::FileNotChanged));
+    }
+
+    /// (path, body) of each comment in each POSTed review
+    type PostedComments = std::sync::Arc<std::sync::Mutex<Vec<Vec<(String, String)>>>>;
+
+    /// PR #1 changing line 2 of each of `files`, with `existing` review comments
+    /// (path, line, body)
+    fn review_server(
+        files: &'static [&'static str],
+        existing: &'static [(&'static str, u64, &'static str)],
+    ) -> (MockServer, PostedComments) {
+        let posted = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = posted.clone();
+        let server = MockServer::start(move |method, path, body| match (method, path.split('?').next().unwrap_or_default()) {
+            ("POST", "/graphql") => {
+                let file = |p: &&str| serde_json::json!({ "path": p, "additions": 1, "deletions": 0, "changeType": "MODIFIED" });
+                let files = serde_json::Value::from_iter(files.iter().map(file));
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            ("GET", "/repos/o/r/pulls/1") => {
+                let patch = |p: &&str| format!("diff --git a/{p} b/{p}\n--- a/{p}\n+++ b/{p}\n@@ -1,1 +1,2 @@\n a\n+b\n");
+                (200, files.iter().map(patch).collect())
+            }
+            ("GET", "/repos/o/r/pulls/1/comments") if path.ends_with("&page=1") => {
+                let comment = |(i, (p, line, body)): (usize, &(&str, u64, &str))| serde_json::json!({
+                    "id": i + 1, "path": p, "line": line, "original_line": line, "commit_id": "abc",
+                    "user": { "login": "bot" }, "created_at": "2024-05-01T10:00:00Z", "body": body,
+                });
+                (200, serde_json::Value::from_iter(existing.iter().enumerate().map(comment)).to_string())
+            }
+            ("GET", "/repos/o/r/pulls/1/comments") => (200, "[]".to_string()),
+            ("POST", "/repos/o/r/pulls/1/reviews") => {
+                let review: serde_json::Value = serde_json::from_str(body).unwrap();
+                let comments = review["comments"].as_array().unwrap().iter().map(|c| {
+                    (c["path"].as_str().unwrap().to_string(), c["body"].as_str().unwrap().to_string())
+                });
+                seen.lock().unwrap().push(comments.collect());
+                (200, r#"{"id":10,"html_url":"u10"}"#.to_string())
+            }
+            _ => (404, "{}".to_string()),
+        });
+        (server, posted)
+    }
+
+    /// Run `pr_review` on a temp comments file holding `comments`
+    async fn review_with(client: &github::Client, comments: &str, allow_noise: bool, allow_duplicates: bool) -> Result<()> {
+        let path = std::env::temp_dir().join(format!("gh-agent-review-{}-{allow_noise}-{allow_duplicates}.json", std::process::id()));
+        std::fs::write(&path, comments).unwrap();
+        let file = path.to_str().unwrap();
+        let result =
+            pr_review(client, "o/r", 1, ReviewOptions { comments_file: file, chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, allow_noise_comments: allow_noise, allow_duplicates, ..Default::default() }).await;
+        std::fs::remove_file(&path).ok();
+        result
+    }
+
+    #[tokio::test]
+    async fn test_review_skips_noise_comments_unless_allowed() {
+        let (server, posted) = review_server(&["a.rs", "package-lock.json"], &[]);
+        let client = github::Client::for_test(&server.url, None);
+        let comments = r#"[{"path": "a.rs", "line": 2, "body": "x"}, {"path": "package-lock.json", "line": 2, "body": "y"}]"#;
+
+        review_with(&client, comments, false, false).await.unwrap();
+        review_with(&client, comments, true, false).await.unwrap();
+        let posted = posted.lock().unwrap();
+        let paths = |i: usize| posted[i].iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths(0), ["a.rs"]);
+        assert_eq!(paths(1), ["a.rs", "package-lock.json"]);
+    }
+
+    #[tokio::test]
+    async fn test_review_severity_prefixes_and_min_severity() {
+        let (server, posted) = review_server(&["a.rs", "b.rs", "c.rs"], &[]);
+        let client = github::Client::for_test(&server.url, None);
+        let path = std::env::temp_dir().join(format!("gh-agent-severity-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"[
+                {"path": "a.rs", "line": 2, "body": "Unchecked unwrap", "severity": "blocker"},
+                {"path": "b.rs", "line": 2, "body": "Rename this", "severity": "nit"},
+                {"path": "c.rs", "line": 2, "body": "Why?"}
+            ]"#,
+        )
+        .unwrap();
+        let file = path.to_str().unwrap();
+        let run = pr_review(&client, "o/r", 1, ReviewOptions { comments_file: file, chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, min_severity: Some(Severity::Warning), ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        std::fs::remove_file(&path).ok();
+        result.unwrap();
+
+        let posted = posted.lock().unwrap();
+        let bodies: Vec<&str> = posted[0].iter().map(|(_, b)| b.as_str()).collect();
+        assert_eq!(bodies, ["🛑 blocker: Unchecked unwrap", "Why?"]);
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out["severities"], serde_json::json!({ "blocker": 1 }));
+
+        let err = parse_review_input(r#"[{"path": "a.rs", "line": 2, "body": "x", "severity": "high"}]"#).err().unwrap();
+        assert!(err.to_string().contains("expected one of `blocker`, `warning`, `nit`, `praise`"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_review_json_reports_posted_and_skipped_comments() {
+        let (server, _) = review_server(&["a.rs", "b.rs", "yarn.lock"], &[("b.rs", 2, "Same")]);
+        let client = &github::Client::for_test(&server.url, None);
+        let review = |comments: &str| {
+            let path = std::env::temp_dir().join(format!("gh-agent-review-json-{}.json", std::process::id()));
+            std::fs::write(&path, comments).unwrap();
+            async move {
+                let run = pr_review(client, "o/r", 1, ReviewOptions { comments_file: path.to_str().unwrap(), chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, json: true, ..Default::default() });
+                let captured = pager::capture(run).await;
+                std::fs::remove_file(&path).ok();
+                captured
+            }
+        };
+
+        let (out, result) = review(
+            r#"[
+                {"path": "a.rs", "line": 2, "body": "x"},
+                {"path": "a.rs", "line": 9, "body": "x"},
+                {"path": "c.rs", "line": 2, "body": "x"},
+                {"path": "yarn.lock", "line": 2, "body": "x"},
+                {"path": "b.rs", "line": 2, "body": "Same"}
+            ]"#,
+        )
+        .await;
+        result.unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!((out["review_id"].as_u64(), out["url"].as_str()), (Some(10), Some("u10")));
+        assert_eq!(out["posted"], serde_json::json!([{ "path": "a.rs", "line": 2 }]));
+        let reasons: Vec<(&str, &str)> = out["skipped"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["path"].as_str().unwrap(), s["reason"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            reasons,
+            [("yarn.lock", "noise_file"), ("a.rs", "not_in_diff"), ("c.rs", "file_not_changed"), ("b.rs", "duplicate")]
+        );
+
+        // Nothing left to post: the result still comes out, and the error maps to exit 2
+        let (out, result) = review(r#"[{"path": "a.rs", "line": 9, "body": "x"}]"#).await;
+        assert!(is_all_skipped(&result.unwrap_err()));
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert!(out["review_id"].is_null());
+        assert_eq!(out["skipped"], serde_json::json!([{ "path": "a.rs", "line": 9, "reason": "not_in_diff" }]));
+    }
+
+    #[test]
+    fn test_truncate_body() {
+        assert_eq!(truncate_body("short", 10), None);
+        let cut = truncate_body(&"é".repeat(200), 50).unwrap();
+        assert_eq!(cut.chars().count(), 50);
+        assert!(cut.ends_with("é… (truncated, 182 chars omitted)"), "{cut}");
+    }
+
+    #[tokio::test]
+    async fn test_review_long_comments_fail_or_get_truncated() {
+        let (server, posted) = review_server(&["a.rs", "b.rs"], &[]);
+        let client = &github::Client::for_test(&server.url, None);
+        let path = std::env::temp_dir().join(format!("gh-agent-long-{}.json", std::process::id()));
+        let long = "x".repeat(500);
+        let comments = serde_json::json!([
+            { "path": "a.rs", "line": 2, "body": long },
+            { "path": "b.rs", "line": 2, "body": "fine" },
+        ]);
+        std::fs::write(&path, comments.to_string()).unwrap();
+        let file = path.to_str().unwrap();
+        let review = |truncate: bool| {
+            pager::capture(pr_review(client, "o/r", 1, ReviewOptions { comments_file: file, chunk_size: 50, max_comment_chars: 300, truncate_long_comments: truncate, json: true, ..Default::default() }))
+        };
+
+        let (_, result) = review(false).await;
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("1 comments are over the 300-character limit"), "{err}");
+        assert!(err.contains("a.rs:2 (500 chars)"), "{err}");
+        assert!(posted.lock().unwrap().is_empty());
+
+        let (out, result) = review(true).await;
+        std::fs::remove_file(&path).ok();
+        result.unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out["posted"], serde_json::json!([{ "path": "a.rs", "line": 2, "truncated": true }, { "path": "b.rs", "line": 2 }]));
+        let posted = posted.lock().unwrap();
+        assert_eq!(posted[0][0].1.chars().count(), 300);
+        assert!(posted[0][0].1.ends_with("… (truncated, 232 chars omitted)"));
+    }
+
+    #[tokio::test]
+    async fn test_review_via_graphql_anchors_outside_the_diff_and_falls_back() {
+        let threads = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let rest_posted = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (seen_threads, seen_rest) = (threads.clone(), rest_posted.clone());
+        let server = MockServer::start(move |method, path, body| match (method, path.split('?').next().unwrap_or_default()) {
+            ("POST", "/graphql") if body.contains("addPullRequestReviewThread") => {
+                let input: serde_json::Value = serde_json::from_str(body).unwrap();
+                let input = &input["variables"]["input"];
+                let at = format!("{}:{}", input["path"].as_str().unwrap(), input["line"]);
+                seen_threads.lock().unwrap().push(at.clone());
+                if at.starts_with("b.rs") {
+                    return (200, r#"{"errors":[{"message":"pull_request_review_thread.line is invalid"}]}"#.to_string());
+                }
+                (200, r#"{"data":{"result":{"thread":{"id":"T1"}}}}"#.to_string())
+            }
+            ("POST", "/graphql") if body.contains("addPullRequestReview(") || body.contains("submitPullRequestReview") => {
+                (200, r#"{"data":{"result":{"pullRequestReview":{"id":"R1","databaseId":10,"url":"u10"}}}}"#.to_string())
+            }
+            ("POST", "/graphql") => {
+                let files = serde_json::json!([
+                    { "path": "a.rs", "additions": 1, "deletions": 0, "changeType": "MODIFIED" },
+                    { "path": "b.rs", "additions": 1, "deletions": 0, "changeType": "MODIFIED" },
+                ]);
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            ("GET", "/repos/o/r/pulls/1") => {
+                let patch = |p: &str| format!("diff --git a/{p} b/{p}\n--- a/{p}\n+++ b/{p}\n@@ -1,1 +1,2 @@\n a\n+b\n");
+                (200, patch("a.rs") + &patch("b.rs"))
+            }
+            ("GET", "/repos/o/r/pulls/1/comments") => (200, "[]".to_string()),
+            ("POST", "/repos/o/r/pulls/1/reviews") => {
+                let review: serde_json::Value = serde_json::from_str(body).unwrap();
+                seen_rest.lock().unwrap().push(review["comments"].to_string());
+                (200, r#"{"id":11,"html_url":"u11"}"#.to_string())
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let path = std::env::temp_dir().join(format!("gh-agent-graphql-review-{}.json", std::process::id()));
+        // a.rs:40 isn't in the diff: only GraphQL can anchor it
+        std::fs::write(&path, r#"[{"path": "a.rs", "line": 40, "body": "x"}, {"path": "b.rs", "line": 2, "body": "y"}]"#).unwrap();
+        let file = path.to_str().unwrap();
+        let run = pr_review(&client, "o/r", 1, ReviewOptions { comments_file: file, chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, via_graphql: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        std::fs::remove_file(&path).ok();
+        result.unwrap();
+
+        assert_eq!(*threads.lock().unwrap(), ["a.rs:40", "b.rs:2"]);
+        let rest_posted = rest_posted.lock().unwrap();
+        assert_eq!(rest_posted.len(), 1);
+        assert!(rest_posted[0].contains("b.rs") && !rest_posted[0].contains("a.rs"), "{}", rest_posted[0]);
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out["id"], 10);
+        assert_eq!(out["fell_back"], serde_json::json!(["b.rs:2"]));
+        assert_eq!(out["reviews"][1]["id"], 11);
+    }
+
+    #[tokio::test]
+    async fn test_review_skips_duplicate_comments_unless_allowed() {
+        let existing = &[("a.rs", 2, "Handle   the\nerror "), ("b.rs", 1, "Handle the error")];
+        let (server, posted) = review_server(&["a.rs", "b.rs"], existing);
+        let client = github::Client::for_test(&server.url, None);
+        // Same line and body up to whitespace; a different body; same body on another line
+        let comments = r#"[
+            {"path": "a.rs", "line": 2, "body": "Handle the error"},
+            {"path": "a.rs", "line": 2, "body": "Also log it"},
+            {"path": "b.rs", "line": 2, "body": "Handle the error"}
+        ]"#;
+
+        review_with(&client, comments, false, false).await.unwrap();
+        review_with(&client, comments, false, true).await.unwrap();
+        let posted = posted.lock().unwrap();
+        let bodies = |i: usize| posted[i].iter().map(|(p, b)| format!("{p}: {b}")).collect::<Vec<_>>();
+        assert_eq!(bodies(0), ["a.rs: Also log it", "b.rs: Handle the error"]);
+        assert_eq!(bodies(1).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_reply_targets_thread_root() {
+        let comment = |id: u64, reply_to: Option<u64>, path: &str, line: u64| serde_json::json!({
+            "id": id, "in_reply_to_id": reply_to, "path": path, "line": line, "original_line": line,
+            "user": { "login": "rev" }, "created_at": "2024-05-01T10:00:00Z", "body": format!("comment {id}"),
+        });
+        let replied = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = replied.clone();
+        let server = MockServer::start(move |method, path, body| match (method, path) {
+            ("POST", "/graphql") => (200, pr_response(1, "abc")),
+            ("GET", "/repos/o/r/pulls/comments/7") => (200, comment(7, Some(5), "a.rs", 3).to_string()),
+            ("GET", "/repos/o/r/pulls/comments/5") => (200, comment(5, None, "a.rs", 3).to_string()),
+            ("GET", p) if p.starts_with("/repos/o/r/pulls/1/comments") && p.ends_with("&page=1") => {
+                let all = [comment(5, None, "a.rs", 3), comment(7, Some(5), "a.rs", 3), comment(8, None, "b.rs", 1), comment(9, None, "b.rs", 1)];
+                (200, serde_json::Value::from_iter(all).to_string())
+            }
+            ("GET", p) if p.starts_with("/repos/o/r/pulls/1/comments") => (200, "[]".to_string()),
+            ("POST", p) if p.ends_with("/replies") => {
+                assert_eq!(serde_json::from_str::<serde_json::Value>(body).unwrap()["body"], "Fixed");
+                seen.lock().unwrap().push(p.to_string());
+                (201, r#"{"html_url":"u"}"#.to_string())
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        // A reply is followed up to its thread's first comment
+        pr_reply(&client, "o/r", 1, Some(7), None, "Fixed", false).await.unwrap();
+        pr_reply(&client, "o/r", 1, None, Some(("./a.rs", 3)), "Fixed", false).await.unwrap();
+        assert_eq!(*replied.lock().unwrap(), ["/repos/o/r/pulls/1/comments/5/replies"; 2]);
+
+        let err = pr_reply(&client, "o/r", 1, None, Some(("b.rs", 1)), "Fixed", false).await.unwrap_err();
+        assert!(err.to_string().starts_with("2 threads on b.rs:1; pick one with --comment-id"), "{err}");
+        let err = pr_reply(&client, "o/r", 1, None, Some(("b.rs", 2)), "Fixed", false).await.unwrap_err();
+        assert_eq!(err.to_string(), "No review thread on b.rs:2");
+
+        // Two pages of comments leave no room for the reply, so it isn't sent;
+        // following a reply's id is budgeted for every hop up front
+        let capped = github::Client::for_test(&server.url, Some(3));
+        let err = pr_reply(&capped, "o/r", 1, None, Some(("a.rs", 3)), "Fixed", false).await.unwrap_err();
+        assert!(err.to_string().contains("only 0 of --max-requests 3 remain"), "{err}");
+        let capped = github::Client::for_test(&server.url, Some(6));
+        let err = pr_reply(&capped, "o/r", 1, Some(7), None, "Fixed", false).await.unwrap_err();
+        assert!(err.to_string().starts_with("needs 7 more requests"), "{err}");
+        assert_eq!(replied.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pending_review_start_add_submit() {
+        let cache = config::use_cache_dir_for_test("pending");
+        let number = 7;
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let seen = calls.clone();
+        let reviews = format!("/repos/o/r/pulls/{number}/reviews");
+        let review_path = reviews.clone();
+        let server = MockServer::start(move |method, path, body| {
+            let reviews = &review_path;
+            let req: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+            let query = req["query"].as_str().unwrap_or_default();
+            seen.lock().unwrap().push(format!("{method} {path}"));
+            match (method, path) {
+                ("POST", "/graphql") if query.contains("addPullRequestReviewThread") => {
+                    let input = &req["variables"]["input"];
+                    assert_eq!(input["pullRequestReviewId"], "PRR_1");
+                    seen.lock().unwrap().push(format!("thread {} {} {}", input["path"], input["line"], input["startLine"]));
+                    (200, r#"{"data":{"result":{"thread":{"id":"T1"}}}}"#.to_string())
+                }
+                ("POST", "/graphql") => (200, pr_response(number, "abc")),
+                ("POST", p) if p == reviews.as_str() => {
+                    assert_eq!(req, serde_json::json!({ "commit_id": "abc" }));
+                    (200, r#"{"id":77,"node_id":"PRR_1","html_url":""}"#.to_string())
+                }
+                ("POST", p) if p == format!("{reviews}/77/events") => {
+                    assert_eq!(req["event"], "APPROVE");
+                    (200, r#"{"id":77,"html_url":"https://example.com/review"}"#.to_string())
+                }
+                ("DELETE", p) if p == format!("{reviews}/77") => (200, "{}".to_string()),
+                _ => (404, "{}".to_string()),
+            }
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_review_start(&client, "o/r", number, false).await.unwrap();
+        let err = pr_review_start(&client, "o/r", number, false).await.unwrap_err();
+        assert!(err.to_string().starts_with("Pending review 77 is already open"), "{err}");
+        pr_review_add(&client, "o/r", number, ("./src/a.rs", 12), None, "nit", false).await.unwrap();
+        pr_review_add(&client, "o/r", number, ("src/b.rs", 9), Some(4), "range", false).await.unwrap();
+        assert_eq!(pending::load("o/r", number).unwrap().unwrap().comments, 2);
+        assert!(cache.join("pending").join("o__r").join("7.json").exists());
+        pr_review_submit(&client, "o/r", number, "APPROVE", None, false).await.unwrap();
+        assert!(pending::load("o/r", number).unwrap().is_none());
+        let err = pr_review_add(&client, "o/r", number, ("src/a.rs", 1), None, "late", false).await.unwrap_err();
+        assert!(err.to_string().starts_with("No pending review on o/r#"), "{err}");
+
+        pr_review_start(&client, "o/r", number, false).await.unwrap();
+        pr_review_abandon(&client, "o/r", number).await.unwrap();
+        assert!(pending::load("o/r", number).unwrap().is_none());
+
+        let calls = calls.lock().unwrap();
+        assert!(calls.contains(&r#"thread "src/a.rs" 12 null"#.to_string()), "{calls:?}");
+        assert!(calls.contains(&r#"thread "src/b.rs" 9 4"#.to_string()), "{calls:?}");
+        assert_eq!(calls.iter().filter(|c| *c == &format!("DELETE {reviews}/77")).count(), 1);
+        let _ = std::fs::remove_dir_all(cache);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_matches_thread_by_line() {
+        let thread = |id: &str, path: &str, line: Option<u64>, original: u64, resolved: bool| serde_json::json!({
+            "id": id, "isResolved": resolved, "isOutdated": line.is_none(), "path": path,
+            "line": line, "originalLine": original,
+            "comments": { "nodes": [{ "databaseId": 1, "author": { "login": "rev" }, "body": format!("about {id}") }] },
+        });
+        let threads = serde_json::json!({ "data": { "repository": { "pullRequest": { "reviewThreads": {
+            "pageInfo": { "hasNextPage": false, "endCursor": null },
+            "nodes": [
+                thread("T1", "a.rs", Some(3), 3, false),
+                thread("T2", "a.rs", Some(3), 3, true),
+                thread("T3", "b.rs", Some(1), 1, false),
+                thread("T4", "b.rs", Some(1), 1, false),
+                thread("T5", "c.rs", None, 9, false),
+            ],
+        } } } } })
+        .to_string();
+        let mutated = Arc::new(Mutex::new(Vec::new()));
+        let seen = mutated.clone();
+        let server = MockServer::start(move |_, _, body| {
+            let req: serde_json::Value = serde_json::from_str(body).unwrap();
+            let query = req["query"].as_str().unwrap_or_default();
+            if query.contains("changedFiles") {
+                return (200, pr_response(1, "abc"));
+            }
+            if query.contains("reviewThreads") {
+                return (200, threads.clone());
+            }
+            let id = req["variables"]["input"]["threadId"].as_str().unwrap().to_string();
+            let resolved = query.contains("result: resolveReviewThread");
+            seen.lock().unwrap().push((id.clone(), resolved));
+            let state = serde_json::json!({ "id": id, "isResolved": resolved, "path": "a.rs", "line": 3 });
+            (200, serde_json::json!({ "data": { "result": { "thread": state } } }).to_string())
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_resolve(&client, "o/r", 1, None, Some(("./a.rs", 3)), false, false).await.unwrap();
+        pr_resolve(&client, "o/r", 1, None, Some(("a.rs", 3)), true, false).await.unwrap();
+        // Outdated threads match on their original line
+        pr_resolve(&client, "o/r", 1, None, Some(("c.rs", 9)), false, false).await.unwrap();
+        pr_resolve(&client, "o/r", 1, Some("T3"), None, false, false).await.unwrap();
+        assert_eq!(
+            *mutated.lock().unwrap(),
+            [("T1".to_string(), true), ("T2".to_string(), false), ("T5".to_string(), true), ("T3".to_string(), true)]
+        );
+
+        let err = pr_resolve(&client, "o/r", 1, None, Some(("b.rs", 1)), false, false).await.unwrap_err();
+        assert!(err.to_string().starts_with("2 unresolved threads on b.rs:1; pick one with --thread-id"), "{err}");
+        assert!(err.to_string().contains("T4 @rev: about T4"), "{err}");
+        let err = pr_resolve(&client, "o/r", 1, None, Some(("b.rs", 1)), true, false).await.unwrap_err();
+        assert_eq!(err.to_string(), "No resolved review thread on b.rs:1");
+    }
+
+    fn history_fixture() -> (Vec<github::PrCommit>, Vec<github::CommitDetail>) {
+        let file = |path: &str, status: &str, additions: u64, deletions: u64| github::PrFile {
+            filename: path.to_string(),
+            status: status.to_string(),
+            additions,
+            deletions,
+            ..Default::default()
+        };
+        let commit = |sha: &str| github::PrCommit {
+            sha: format!("{sha}0000000"),
+            author: "alice".to_string(),
+            date: "2024-05-01T10:00:00Z".to_string(),
+            message: format!("Commit {sha}\n\nbody"),
+        };
+        let detail = |files: Vec<github::PrFile>| github::CommitDetail {
+            stat: github::CommitStat {
+                files: files.len(),
+                additions: 0,
+                deletions: 0,
+            },
+            files,
+        };
+        let commits = ["c1", "c2", "c3", "c4"].map(commit).to_vec();
+        let details = vec![
+            detail(vec![file("old.rs", "modified", 1, 1)]),
+            detail(vec![file("auth.rs", "added", 40, 0), file("old.rs", "removed", 0, 12)]),
+            detail(vec![file("auth.rs", "modified", 3, 1)]),
+            detail(vec![file("old.rs", "added", 10, 0)]),
+        ];
+        (commits, details)
+    }
+
+    #[test]
+    fn test_file_revisions() {
+        // Added mid-PR
+        let (commits, details) = history_fixture();
+        let revisions = file_revisions(&commits, details, "auth.rs");
+        let rows: Vec<(&github::PrCommit, &github::PrFile)> = revisions.iter().map(|(c, f)| (*c, f)).collect();
+        assert_eq!(
+            format::format_file_history("auth.rs", &rows, commits.len()),
+            "\
+auth.rs: 2 revisions in 4 commits
+  1  c200000  added       +40    -0  Commit c2
+  2  c300000  modified     +3    -1  Commit c3"
+        );
+
+        // Deleted, then re-added
+        let (commits, details) = history_fixture();
+        let revisions = file_revisions(&commits, details, "old.rs");
+        let steps: Vec<(&str, &str)> = revisions.iter().map(|(c, f)| (c.short_sha(), f.status.as_str())).collect();
+        assert_eq!(steps, [("c100000", "modified"), ("c200000", "removed"), ("c400000", "added")]);
+
+        assert_eq!(parse_revision_range("1..3", 3).unwrap(), (1, 3));
+        assert_eq!(parse_revision_range(" 2 .. 3", 3).unwrap(), (2, 3));
+        for bad in ["0..2", "2..2", "3..1", "1..4"] {
+            assert!(parse_revision_range(bad, 3).unwrap_err().to_string().contains("need 1 <= I < J <= 3"), "{bad}");
+        }
+        assert!(parse_revision_range("1-3", 3).unwrap_err().to_string().contains("expected I..J"));
+    }
+
+    #[tokio::test]
+    async fn test_file_history_diffs_via_compare() {
+        let server = MockServer::start(|_, path, _| match path.split('?').next().unwrap_or_default() {
+            "/repos/o/r/pulls/1/commits" if path.ends_with("&page=1") => {
+                let commit = |sha: &str| serde_json::json!({
+                    "sha": sha, "author": { "login": "alice" },
+                    "commit": { "message": format!("Commit {sha}"), "author": { "name": "alice", "date": "2024-01-01T00:00:00Z" } },
+                });
+                (200, serde_json::json!([commit("c1"), commit("c2"), commit("c3")]).to_string())
+            }
+            "/repos/o/r/pulls/1/commits" => (200, "[]".to_string()),
+            "/repos/o/r/commits/c2" => (200, r#"{"sha":"c2","commit":{"message":"m"},"files":[{"filename":"b.rs"}]}"#.to_string()),
+            p if p.starts_with("/repos/o/r/commits/") => {
+                let files = serde_json::json!([{ "filename": "a.rs", "status": "modified", "additions": 1, "deletions": 1 }]);
+                (200, serde_json::json!({ "sha": "x", "commit": { "message": "m" }, "files": files }).to_string())
+            }
+            "/repos/o/r/compare/c1...c3" => {
+                let files = serde_json::json!([
+                    { "filename": "b.rs", "status": "added" },
+                    { "filename": "a.rs", "status": "modified", "additions": 2, "deletions": 2, "patch": "@@ -1 +1 @@\n-x\n+y" },
+                ]);
+                (200, serde_json::json!({ "merge_base_commit": { "sha": "c1" }, "commits": [], "files": files }).to_string())
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_file_history(&client, "o/r", 1, "./a.rs", Some("1..2"), false).await.unwrap();
+        // Two commit-list pages, three commit details, one compare
+        assert_eq!(server.hits(), 6);
+        let err = pr_file_history(&client, "o/r", 1, "c.rs", None, false).await.unwrap_err();
+        assert_eq!(err.to_string(), "No commit in PR #1 touches c.rs");
+    }
+
+    #[tokio::test]
+    async fn test_diff_range_keeps_pr_files_changed_in_it() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/user" => (200, r#"{"login":"alice"}"#.to_string()),
+            "/repos/o/r/pulls/1/reviews?per_page=100&page=1" => {
+                let review = |id: u64, login: &str, sha: &str| serde_json::json!({
+                    "id": id, "user": { "login": login }, "state": "COMMENTED", "submitted_at": "2024-02-02T00:00:00Z",
+                    "html_url": "u", "commit_id": sha,
+                });
+                (200, serde_json::json!([review(1, "alice", "c0"), review(2, "alice", "c1"), review(3, "bob", "c2")]).to_string())
+            }
+            "/repos/o/r/pulls/1/commits?per_page=100&page=1" => {
+                let commit = |sha: &str, date: &str| serde_json::json!({
+                    "sha": sha, "commit": { "message": "m", "author": { "name": "alice", "date": date } },
+                });
+                let commits = [commit("c0", "2024-01-01T00:00:00Z"), commit("c1", "2024-02-01T00:00:00Z"), commit("c2", "2024-03-01T00:00:00Z")];
+                (200, serde_json::json!(commits).to_string())
+            }
+            p if p.ends_with("&page=2") => (200, "[]".to_string()),
+            "/graphql" => {
+                let files = r#"[{"path":"a.rs","additions":3,"deletions":0,"changeType":"ADDED"},{"path":"b.rs","additions":1,"deletions":1,"changeType":"MODIFIED"},
+                    {"path":"Cargo.lock","additions":1,"deletions":1,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            // a.rs and Cargo.lock changed in the range, b.rs didn't; c.rs came in with a merge of main
+            "/repos/o/r/compare/c1...abc" | "/repos/o/r/compare/main...c2" => (
+                200,
+                "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1,2 +1,2 @@\n x\n-y\n+z\n\
+                 diff --git a/c.rs b/c.rs\n--- a/c.rs\n+++ b/c.rs\n@@ -1 +1 @@\n-p\n+q\n\
+                 diff --git a/Cargo.lock b/Cargo.lock\n--- a/Cargo.lock\n+++ b/Cargo.lock\n@@ -1 +1 @@\n-v1\n+v2\n"
+                    .to_string(),
+            ),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { stat_only: true, range: DiffRange::Commits { from: Some("c1"), to: None }, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.contains("a.rs") && out.contains("modified"), "{out}");
+        assert!(out.contains("+1") && out.contains("-1"), "{out}");
+        assert!(!out.contains("b.rs") && !out.contains("c.rs"), "{out}");
+        assert!(!out.contains("Cargo.lock"), "the noise filter still applies: {out}");
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, stat_only: true, range: DiffRange::Commits { from: Some("c1"), to: None }, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.contains("Cargo.lock"), "{out}");
+
+        // --to alone diffs from the merge base with the base branch
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { json: true, range: DiffRange::Commits { from: None, to: Some("c2") }, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out["files"], serde_json::json!({ "a.rs": [1, 2] }), "{out}");
+        assert_eq!(out["hunks"]["a.rs"][0]["new_count"], 2, "{out}");
+
+        // alice last reviewed c1, and c1 is the last commit before Feb 15
+        for range in [DiffRange::SinceReview, DiffRange::Since("2024-02-15")] {
+            let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, stat_only: true, range, ..Default::default() });
+            let (out, result) = pager::capture(run).await;
+            result.unwrap();
+            assert!(out.contains("a.rs") && !out.contains("b.rs"), "{out}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_diff_net_only_names_commits_that_cancel_out() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => {
+                let files = r#"[{"path":"b.rs","additions":1,"deletions":1,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            "/repos/o/r/pulls/1" => (200, "diff --git a/b.rs b/b.rs\n--- a/b.rs\n+++ b/b.rs\n@@ -1 +1 @@\n-p\n+q\n".to_string()),
+            "/repos/o/r/pulls/1/commits?per_page=100&page=1" => {
+                let commit = |sha: &str, message: &str| serde_json::json!({
+                    "sha": sha, "commit": { "message": message, "author": { "name": "alice", "date": "2024-01-01T00:00:00Z" } },
+                });
+                (200, serde_json::json!([commit("c100000", "Try x"), commit("c200000", "Edit b"), commit("c300000", "Revert x")]).to_string())
+            }
+            "/repos/o/r/pulls/1/commits?per_page=100&page=2" => (200, "[]".to_string()),
+            p if p.starts_with("/repos/o/r/commits/") => {
+                let (file, patch) = match &p["/repos/o/r/commits/".len()..] {
+                    "c100000" => ("a.rs", "@@ -1 +1 @@\n-x\n+y"),
+                    "c300000" => ("a.rs", "@@ -1 +1 @@\n-y\n+x"),
+                    _ => ("b.rs", "@@ -1 +1 @@\n-p\n+q"),
+                };
+                let files = serde_json::json!([{ "filename": file, "status": "modified", "additions": 1, "deletions": 1, "patch": patch }]);
+                (200, serde_json::json!({ "sha": "x", "commit": { "message": "m" }, "files": files }).to_string())
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, net_only: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.contains("+q") && !out.contains("a.rs"), "{out}");
+        // The PR, its diff, two commit-list pages and three commit details
+        assert_eq!(server.hits(), 7);
+
+        let commits = client.get_pr_commits("o/r", 1).await.unwrap();
+        assert_eq!(format_net_note(&commits, &[(0, 2)]), "net: c300000 reverts c100000 (Try x); neither is in the diff\n");
+        assert_eq!(format_net_note(&commits, &[]), "net: no commits cancel out\n");
+    }
+
+    #[tokio::test]
+    async fn test_diff_name_status_skips_the_patches() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => {
+                let files = r#"[{"path":"src/a.rs","additions":3,"deletions":0,"changeType":"ADDED"},{"path":"Cargo.lock","additions":9,"deletions":9,"changeType":"MODIFIED"},{"path":"src/b.rs","additions":1,"deletions":1,"changeType":"MODIFIED"},{"path":"src/c.rs","additions":1,"deletions":1,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let src_files = FileFilter::new(&["src/".to_string()], &[]).unwrap();
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { names: Some(NameMode::Status), ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert_eq!(out, "A\tsrc/a.rs\nM\tsrc/b.rs\nM\tsrc/c.rs\n");
+        assert_eq!(server.hits(), 1);
+
+        let exclude = ["**/c.rs".to_string()];
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { file_filter: src_files, include_all: true, json: true, exclude: &exclude, names: Some(NameMode::Only), ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out["files"], serde_json::json!([{ "path": "src/a.rs", "status": "added" }, { "path": "src/b.rs", "status": "modified" }]));
+        assert_eq!(out["state"], "OPEN");
+    }
+
+    #[tokio::test]
+    async fn test_diff_ignore_whitespace_hides_reformatted_files() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => {
+                let files = r#"[{"path":"a.rs","additions":1,"deletions":1,"changeType":"MODIFIED"},{"path":"b.rs","additions":1,"deletions":1,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            "/repos/o/r/pulls/1" => (
+                200,
+                "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-  x\n+\tx\n\
+                 diff --git a/b.rs b/b.rs\n--- a/b.rs\n+++ b/b.rs\n@@ -1 +1 @@\n-  y\n+\tz\n"
+                    .to_string(),
+            ),
+            "/repos/o/r/compare/main...abc" => (200, r#"{"merge_base_commit":{"sha":"mb"},"commits":[],"files":[]}"#.to_string()),
+            "/repos/o/r/contents/a.rs?ref=mb" => (200, contents_response("  x\n")),
+            "/repos/o/r/contents/a.rs?ref=abc" => (200, contents_response("\tx\n")),
+            "/repos/o/r/contents/b.rs?ref=mb" => (200, contents_response("  y\n")),
+            "/repos/o/r/contents/b.rs?ref=abc" => (200, contents_response("\tz\n")),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, stat_only: true, ignore_whitespace: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.contains("a.rs (whitespace-only)") && out.contains("  b.rs\n"), "{out}");
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, ignore_whitespace: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(!out.contains("a.rs") && out.contains("+++ b/b.rs") && out.contains("@@ -1,1 +1,1 @@"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn test_diff_falls_back_to_file_patches_on_406() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => {
+                let files = r#"[{"path":"a.rs","additions":1,"deletions":1,"changeType":"MODIFIED"},{"path":"big.rs","additions":30000,"deletions":0,"changeType":"ADDED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            "/repos/o/r/pulls/1" => (406, r#"{"message":"Sorry, the diff exceeded the maximum number of files (300)."}"#.to_string()),
+            "/repos/o/r/pulls/1/files?per_page=100&page=1" => (
+                200,
+                r#"[{"filename":"a.rs","status":"modified","additions":1,"deletions":1,"patch":"@@ -1 +1 @@\n-old\n+new"},
+                    {"filename":"big.rs","status":"added","additions":30000,"deletions":0}]"#
+                    .to_string(),
+            ),
+            "/repos/o/r/pulls/1/files?per_page=100&page=2" => (200, "[]".to_string()),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let pr = client.get_pr_with_patches("o/r", 1).await.unwrap();
+        assert_eq!(pr.files[0].patch.as_deref(), Some("@@ -1 +1 @@\n-old\n+new"));
+        assert!(!pr.files[0].patch_too_large);
+        assert_eq!(pr.files[1].patch, None);
+        assert!(pr.files[1].patch_too_large);
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.contains("+new") && out.contains("+++ b/big.rs\n(diff too large, use pr file)"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn test_diff_labels_binary_files() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => {
+                let files = r#"[{"path":"logo.png","additions":0,"deletions":0,"changeType":"MODIFIED"},{"path":"a.rs","additions":1,"deletions":1,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            "/repos/o/r/pulls/1" => (
+                200,
+                "diff --git a/logo.png b/logo.png\nindex 1..2 100644\nBinary files a/logo.png and b/logo.png differ\n\
+                 diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-x\n+y\n"
+                    .to_string(),
+            ),
+            "/repos/o/r/contents/logo.png?ref=main" => (200, r#"{"size":12700,"content":""}"#.to_string()),
+            "/repos/o/r/contents/logo.png?ref=abc" => (200, r#"{"size":13414,"content":""}"#.to_string()),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.starts_with("--- a/logo.png\n+++ b/logo.png\nbinary file changed (12.4 KB → 13.1 KB)\n\n--- a/a.rs"), "{out}");
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, json: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out["binary"], serde_json::json!(["logo.png"]));
+        assert_eq!(out["totals"], serde_json::json!({ "files": 2, "additions": 1, "deletions": 1 }));
+        assert_eq!(out["files"]["logo.png"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_diff_lines_trims_to_one_files_range() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => {
+                let files = r#"[{"path":"a.rs","additions":2,"deletions":1,"changeType":"MODIFIED"},{"path":"ab.rs","additions":1,"deletions":1,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            "/repos/o/r/pulls/1" => (
+                200,
+                "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1,8 +1,8 @@ fn main() {\n l1\n l2\n l3\n-l4\n+L4\n l5\n l6\n l7\n l8\n\
+                 @@ -40,2 +40,3 @@\n x\n+y\n z\n\
+                 diff --git a/ab.rs b/ab.rs\n--- a/ab.rs\n+++ b/ab.rs\n@@ -1 +1 @@\n-p\n+q\n"
+                    .to_string(),
+            ),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let one = FileFilter::new(&["a.rs".to_string()], &[]).unwrap();
+        let lines = [diff::LineRange { start: 4, end: Some(4) }];
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { file_filter: one.clone(), include_all: true, line_ranges: &lines, output: DiffFormat::Patch, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.ends_with("@@ -2,5 +2,5 @@ fn main() {\n l2\n l3\n-l4\n+L4\n l5\n l6\n"), "{out}");
+
+        // The part keeps its hunk's id, which --hunk and pr review take
+        let pr = client.get_pr_with_patches("o/r", 1).await.unwrap();
+        let ids = diff::hunk_ids("a.rs", &parse_patch(pr.files[0].patch.as_deref().unwrap()));
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { file_filter: one.clone(), include_all: true, line_ranges: &lines, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.contains(&format!("@@ -2,5 +2,5 @@ fn main() {{ [h:{}]", ids[0])), "{out}");
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { file_filter: one.clone(), include_all: true, json: true, line_ranges: &lines, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out["hunks"]["a.rs"][0]["id"], ids[0].as_str());
+        assert_eq!(out["files"]["a.rs"], serde_json::json!([2, 3, 4, 5, 6]));
+
+        // --hunk picks the hunks --lines cuts from
+        let wide = [diff::LineRange { start: 1, end: None }];
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { file_filter: one.clone(), include_all: true, hunk_filters: &ids[1..], line_ranges: &wide, output: DiffFormat::Patch, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.ends_with("@@ -40,2 +40,3 @@\n x\n+y\n z\n") && !out.contains("L4"), "{out}");
+
+        let lines = [diff::LineRange { start: 20, end: Some(30) }];
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { file_filter: one.clone(), include_all: true, line_ranges: &lines, output: DiffFormat::Patch, ..Default::default() });
+        let err = pager::capture(run).await.1.unwrap_err().to_string();
+        assert_eq!(err, "--lines 20-30: no changes in a.rs at those lines");
+
+        let both = FileFilter::new(&["rs".to_string()], &[]).unwrap();
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { file_filter: both, include_all: true, line_ranges: &lines, output: DiffFormat::Patch, ..Default::default() });
+        let err = pager::capture(run).await.1.unwrap_err().to_string();
+        assert!(err.contains("2 files match, but it needs exactly one; narrow --file down to one of: a.rs, ab.rs"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_diff_format_patch_applies_to_the_base() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => {
+                let files = r#"[{"path":"a.rs","additions":1,"deletions":1,"changeType":"MODIFIED"},
+                    {"path":"new.rs","additions":1,"deletions":0,"changeType":"ADDED"},
+                    {"path":"gone.rs","additions":0,"deletions":1,"changeType":"DELETED"},
+                    {"path":"moved.rs","additions":0,"deletions":0,"changeType":"RENAMED"},
+                    {"path":"logo.png","additions":0,"deletions":0,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            "/repos/o/r/pulls/1" => (
+                200,
+                "diff --git a/a.rs b/a.rs\nindex 1111111..2222222 100644\n--- a/a.rs\n+++ b/a.rs\n@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n\
+                 diff --git a/new.rs b/new.rs\nnew file mode 100644\nindex 0000000..3333333\n--- /dev/null\n+++ b/new.rs\n@@ -0,0 +1 @@\n+fresh\n\
+                 diff --git a/gone.rs b/gone.rs\ndeleted file mode 100644\nindex 4444444..0000000\n--- a/gone.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-x\n\
+                 diff --git a/old.rs b/moved.rs\nsimilarity index 100%\nrename from old.rs\nrename to moved.rs\n\
+                 diff --git a/logo.png b/logo.png\nindex 5555555..6666666 100644\nBinary files a/logo.png and b/logo.png differ\n"
+                    .to_string(),
+            ),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, output: DiffFormat::Patch, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.starts_with("diff --git a/a.rs b/a.rs\nindex 1111111..2222222 100644\n--- a/a.rs\n+++ b/a.rs\n@@ -1,3 +1,3 @@\n one\n"), "{out}");
+        assert!(!out.contains("logo.png") && !out.contains("   1 |"), "{out}");
+
+        let dir = std::env::temp_dir().join(format!("gh-agent-format-patch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (path, content) in [("a.rs", "one\ntwo\nthree\n"), ("gone.rs", "x\n"), ("old.rs", "keep\n")] {
+            std::fs::write(dir.join(path), content).unwrap();
+        }
+        std::fs::write(dir.join("pr.patch"), &out).unwrap();
+        for args in [&["apply", "--check", "pr.patch"][..], &["apply", "pr.patch"][..]] {
+            let git = std::process::Command::new("git").args(args).current_dir(&dir).output().unwrap();
+            assert!(git.status.success(), "git {args:?} failed: {}", String::from_utf8_lossy(&git.stderr));
+        }
+        let read = |path: &str| std::fs::read_to_string(dir.join(path)).ok();
+        assert_eq!(read("a.rs").as_deref(), Some("one\n2\nthree\n"));
+        assert_eq!(read("new.rs").as_deref(), Some("fresh\n"));
+        assert_eq!(read("moved.rs").as_deref(), Some("keep\n"));
+        assert_eq!((read("gone.rs"), read("old.rs")), (None, None));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_out_file_name_avoids_collisions() {
+        let mut taken = HashSet::new();
+        let names: Vec<String> = ["src/a.rs", "src__a.rs", "README.md", "readme.md", "w:eird?.rs", "src/a.rs"]
+            .iter()
+            .map(|p| out_file_name(p, "diff", &mut taken))
             .collect();
-        let skipped = before - filtered.len();
-        (filtered, skipped)
-    };
+        assert_eq!(
+            names,
+            ["src__a.rs.diff", "src__a.rs~2.diff", "README.md.diff", "readme.md~2.diff", "w_eird_.rs.diff", "src__a.rs~3.diff"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_diff_out_dir_writes_a_file_each_and_an_index() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => {
+                let files = r#"[{"path":"src/a.rs","additions":1,"deletions":1,"changeType":"MODIFIED"},{"path":"logo.png","additions":0,"deletions":0,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            "/repos/o/r/pulls/1" => (
+                200,
+                "diff --git a/src/a.rs b/src/a.rs\n--- a/src/a.rs\n+++ b/src/a.rs\n@@ -1 +1 @@\n-x\n+y\n\
+                 diff --git a/logo.png b/logo.png\nBinary files a/logo.png and b/logo.png differ\n"
+                    .to_string(),
+            ),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let dir = std::env::temp_dir().join(format!("gh-agent-out-dir-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("stale.diff"), "old").unwrap();
+        std::fs::write(dir.join("README.md"), "keep").unwrap();
+        std::fs::write(dir.join("notes.diff"), "keep").unwrap();
+        let old_index = serde_json::json!([
+            { "path": "stale", "status": "added", "additions": 1, "deletions": 0, "output_file": "stale.diff" },
+            { "path": "up", "status": "added", "additions": 1, "deletions": 0, "output_file": "../README.md" },
+        ]);
+        std::fs::write(dir.join("index.json"), old_index.to_string()).unwrap();
+        let out_dir = dir.to_str().unwrap();
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, output: DiffFormat::Patch, out_dir: Some(out_dir), clean: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert_eq!(out, "");
+        let patch = std::fs::read_to_string(dir.join("src__a.rs.diff")).unwrap();
+        assert_eq!(patch, "diff --git a/src/a.rs b/src/a.rs\n--- a/src/a.rs\n+++ b/src/a.rs\n@@ -1 +1 @@\n-x\n+y\n");
+        let index: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.join("index.json")).unwrap()).unwrap();
+        assert_eq!(
+            index,
+            serde_json::json!([
+                { "path": "src/a.rs", "status": "modified", "additions": 1, "deletions": 1, "output_file": "src__a.rs.diff" },
+                { "path": "logo.png", "status": "modified", "additions": 0, "deletions": 0, "output_file": null },
+            ])
+        );
+        // --clean took the stale diff the old index listed, and nothing else
+        assert!(!dir.join("stale.diff").exists());
+        assert!(dir.join("README.md").exists() && dir.join("notes.diff").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_diff_out_dir_writes_no_color() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => {
+                let files = r#"[{"path":"a.rs","additions":1,"deletions":1,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            "/repos/o/r/pulls/1" => (200, "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-x\n+y\n".to_string()),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let dir = std::env::temp_dir().join(format!("gh-agent-out-dir-color-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let out_dir = dir.to_str().unwrap();
+        let _color = format::override_color(true);
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(out.contains("\x1b[32m"), "the terminal gets color: {out:?}");
+
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, out_dir: Some(out_dir), ..Default::default() });
+        pager::capture(run).await.1.unwrap();
+        let written = std::fs::read_to_string(dir.join("a.rs.diff")).unwrap();
+        assert!(written.contains("+y") && !written.contains('\x1b'), "{written:?}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_filter_globs_words_and_regexes() {
+        let paths = ["src/a.ts", "src/api/deep/b.ts", "dist/src/a.ts", "src/c.rs", "tables.rs"];
+        let kept = |files: &[&str], regexes: &[&str]| -> Vec<&str> {
+            let files: Vec<String> = files.iter().map(|f| f.to_string()).collect();
+            let regexes: Vec<String> = regexes.iter().map(|r| r.to_string()).collect();
+            let filter = FileFilter::new(&files, &regexes).unwrap();
+            paths.iter().copied().filter(|p| filter.matches(p)).collect()
+        };
+        assert_eq!(kept(&["src/**/*.ts"], &[]), ["src/a.ts", "src/api/deep/b.ts"]);
+        assert_eq!(kept(&["src/*.ts"], &[]), ["src/a.ts"]);
+        assert_eq!(kept(&["src/"], &[]), ["src/a.ts", "src/api/deep/b.ts", "src/c.rs"]);
+        // A plain word is still a substring
+        assert_eq!(kept(&["api"], &[]), ["src/api/deep/b.ts"]);
+        assert_eq!(kept(&["abl"], &[]), ["tables.rs"]);
+        assert_eq!(kept(&["api"], &[r"\.rs$"]), ["src/api/deep/b.ts", "src/c.rs", "tables.rs"]);
+        assert_eq!(kept(&[], &[]), paths);
+
+        assert!(FileFilter::new(&["src/[a".to_string()], &[]).err().unwrap().to_string().contains("Invalid --file glob"));
+        assert!(FileFilter::new(&[], &["(".to_string()]).err().unwrap().to_string().contains("Invalid --file-regex"));
+    }
+
+    #[test]
+    fn test_exclude_globs() {
+        let excludes = exclude_globs(&["*.snap".to_string(), "tests/**".to_string(), "src/gen_[a-c].rs".to_string()]).unwrap();
+        for path in ["x.snap", "tests/a.rs", "tests/deep/b.rs", "src/gen_b.rs"] {
+            assert!(excludes.is_match(path), "{path}");
+        }
+        // `*` stays within a segment, and [a-c] is one character from the class
+        for path in ["src/x.snap", "src/tests/a.rs", "src/gen_d.rs", "src/gen_ab.rs"] {
+            assert!(!excludes.is_match(path), "{path}");
+        }
+        let any_depth = exclude_globs(&["**/*.snap".to_string()]).unwrap();
+        assert!(any_depth.is_match("x.snap") && any_depth.is_match("src/__snapshots__/x.snap"));
+
+        let err = exclude_globs(&["src/[a".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("Invalid --exclude glob \"src/[a\""), "{err}");
+    }
+
+    #[test]
+    fn test_file_diff_json_snapshot() {
+        let f = github::PrFile {
+            filename: "src/a.rs".to_string(),
+            status: "modified".to_string(),
+            additions: 2,
+            deletions: 1,
+            patch: Some("@@ -3,3 +3,4 @@ fn main() {\n let a = 1;\n-let b = 2;\n+let b = 3;\n+let c = 4;\n }".to_string()),
+            ..Default::default()
+        };
+        let (ids, hunks): (Vec<_>, Vec<_>) = identified_hunks(&f.filename, f.patch.as_deref().unwrap(), &[]).into_iter().unzip();
+        let line = |kind: &str, old: Option<u64>, new: Option<u64>, content: &str| serde_json::json!({
+            "kind": kind, "old_line": old, "new_line": new, "content": content,
+            // Review comments go on the new side
+            "commentable": new.is_some(),
+        });
+        assert_eq!(
+            serde_json::to_value(file_diff_json(&f, ids, hunks)).unwrap(),
+            serde_json::json!({
+                "path": "src/a.rs", "status": "modified", "additions": 2, "deletions": 1,
+                "hunks": [{
+                    "id": "a-a652",
+                    "header": "@@ -3,3 +3,4 @@ fn main() {",
+                    "old_start": 3, "old_count": 3, "new_start": 3, "new_count": 4, "context": "fn main() {",
+                    "lines": [
+                        line("context", Some(3), Some(3), "let a = 1;"),
+                        line("delete", Some(4), None, "let b = 2;"),
+                        line("add", None, Some(4), "let b = 3;"),
+                        line("add", None, Some(5), "let c = 4;"),
+                        line("context", Some(5), Some(6), "}"),
+                    ],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_since_timestamp() {
+        assert_eq!(since_timestamp("2024-02-15").unwrap(), "2024-02-15T00:00:00Z");
+        assert_eq!(since_timestamp("2024-02-15T09:30Z").unwrap(), "2024-02-15T09:30:00Z");
+        assert_eq!(since_timestamp("2024-02-15T09:30:15Z").unwrap(), "2024-02-15T09:30:15Z");
+        for bad in ["2024-2-15", "2024-02-15T09:30", "2024-02-15T09:30:00+02:00", "yesterday"] {
+            assert!(since_timestamp(bad).unwrap_err().to_string().contains("expected YYYY-MM-DD"), "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_suggestion_originals_fetch_each_file_once() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/repos/o/r/contents/a.rs?ref=abc" => (200, contents_response("one\ntwo\nthree\n")),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let comment = |id: u64, path: &str, line: u64, start_line: Option<u64>, body: &str| github::ReviewComment {
+            id,
+            thread_id: id,
+            path: path.to_string(),
+            line: Some(line),
+            start_line,
+            commit_id: "abc".to_string(),
+            outdated: false,
+            author: "alice".to_string(),
+            created_at: "2024-05-01T10:00:00Z".to_string(),
+            body: body.to_string(),
+        };
+        let comments = [
+            comment(1, "a.rs", 3, Some(2), "```suggestion\n2 and 3\n```"),
+            comment(2, "a.rs", 1, None, "````suggestion\nuno\n````"),
+            comment(3, "a.rs", 9, None, "```suggestion\npast the end\n```"),
+            comment(4, "a.rs", 1, None, "no suggestion here"),
+            comment(5, "gone.rs", 1, None, "```suggestion\nx\n```"),
+        ];
+        let originals = suggestion_originals(&client, "o/r", &comments).await.unwrap();
+        assert_eq!(originals.len(), 2);
+        assert_eq!(originals[&1], ["two", "three"]);
+        assert_eq!(originals[&2], ["one"]);
+        assert_eq!(server.hits(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_review_dry_run_posts_nothing() {
+        let server = MockServer::start(|method, path, _| match (method, path) {
+            ("POST", "/graphql") => {
+                let file = serde_json::json!([{ "path": "a.rs", "additions": 1, "deletions": 0, "changeType": "MODIFIED" }]);
+                // Merged: a dry run still validates
+                let pr = pr_response(1, "abc").replace(r#""state":"OPEN""#, r#""state":"MERGED""#);
+                (200, pr.replace(r#""nodes":[]"#, &format!(r#""nodes":{file}"#)))
+            }
+            ("GET", "/repos/o/r/pulls/1") => {
+                (200, "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1,2 +1,3 @@\n a\n+b\n c\n".to_string())
+            }
+            ("GET", p) if p.starts_with("/repos/o/r/pulls/1/comments") => (200, "[]".to_string()),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let path = std::env::temp_dir().join(format!("gh-agent-dry-run-{}.json", std::process::id()));
+        let file = path.to_str().unwrap();
+
+        std::fs::write(&path, r#"[{"path": "a.rs", "line": 2, "body": "nit"}]"#).unwrap();
+        pr_review(&client, "o/r", 1, ReviewOptions { comments_file: file, chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, dry_run: true, json: true, ..Default::default() }).await.unwrap();
+        std::fs::write(&path, r#"[{"path": "a.rs", "line": 2, "body": "nit"}, {"path": "a.rs", "line": 9, "body": "x"}]"#).unwrap();
+        let err = pr_review(&client, "o/r", 1, ReviewOptions { comments_file: file, chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, dry_run: true, ..Default::default() }).await.unwrap_err();
+        std::fs::remove_file(&path).ok();
+        assert!(err.to_string().contains("1 of 2 comments would be skipped"), "{err}");
+        // PR metadata, raw diff and existing comments per run, never a review POST
+        assert_eq!(server.hits(), 6);
+    }
+
+    #[test]
+    fn test_review_comment_ranges_stay_within_one_hunk() {
+        // New lines 10-20 and 25-45
+        let patch = format!("@@ -10,11 +10,11 @@\n{}@@ -30,20 +25,21 @@\n{}+new\n", " x\n".repeat(11), " y\n".repeat(20));
+        let file_hunks = HashMap::from([("a.rs".to_string(), parse_patch(&patch))]);
+        let file_commentable: HashMap<&str, Vec<u64>> =
+            file_hunks.iter().map(|(p, h)| (p.as_str(), commentable_lines(h))).collect();
+        let build = |json: serde_json::Value, split: bool| {
+            let c: CommentInput = serde_json::from_value(json).unwrap();
+            review_comment(&c, &file_hunks, &file_commentable, &[], 0, split, false)
+        };
+        let range = serde_json::json!({ "path": "a.rs", "start_line": 10, "line": 42, "body": "x" });
+
+        let err = build(range.clone(), false).unwrap_err();
+        assert_eq!(err.message, "SKIP: a.rs: range 10-42 crosses hunk boundary at 25");
+        let (comment, note) = build(range, true).unwrap();
+        assert_eq!((comment.start_line, comment.line), (Some(25), 42));
+        assert_eq!(note.unwrap(), "SPLIT: a.rs:10-42 → 25-42, the part within one hunk");
+        // Cut down to a single line
+        let (comment, _) = build(serde_json::json!({ "path": "a.rs", "start_line": 12, "line": 25, "body": "x" }), true).unwrap();
+        assert_eq!((comment.start_line, comment.line), (None, 25));
+        // Within one hunk: untouched
+        let (comment, note) = build(serde_json::json!({ "path": "a.rs", "start_line": 11, "line": 20, "body": "x" }), false).unwrap();
+        assert_eq!((comment.start_line, note), (Some(11), None));
+        // A suggestion is never cut
+        let suggestion = serde_json::json!({ "path": "a.rs", "start_line": 10, "line": 42, "suggestion": "y" });
+        assert!(build(suggestion, true).unwrap_err().message.contains("crosses hunk boundary at 25"));
+    }
+
+    #[test]
+    fn test_snap_line() {
+        let cl = [10, 11, 12, 20, 24];
+        assert_eq!(snap_line(&cl, 11, 0), Some(11));
+        assert_eq!(snap_line(&cl, 14, 0), None);
+        assert_eq!(snap_line(&cl, 14, 2), Some(12));
+        assert_eq!(snap_line(&cl, 14, 1), None);
+        // 22 is two away from both 20 and 24: the later line wins
+        assert_eq!(snap_line(&cl, 22, 2), Some(24));
+        assert_eq!(snap_line(&cl, 21, 5), Some(20));
+        assert_eq!(snap_line(&cl, 1, 3), None);
+        assert_eq!(snap_line(&[], 5, 100), None);
+    }
+
+    #[test]
+    fn test_review_comment_fuzz() {
+        // New lines 1-4 and 21-23
+        let patch = "@@ -1,3 +1,4 @@\n a\n-b\n+b2\n+b3\n c\n@@ -20,2 +21,3 @@\n x\n+y\n z";
+        let file_hunks = HashMap::from([("a.rs".to_string(), parse_patch(patch))]);
+        let file_commentable: HashMap<&str, Vec<u64>> =
+            file_hunks.iter().map(|(p, h)| (p.as_str(), commentable_lines(h))).collect();
+        let build = |raw: &str, fuzz| {
+            let c: CommentInput = serde_json::from_str(raw).unwrap();
+            review_comment(&c, &file_hunks, &file_commentable, &[], fuzz, false, false)
+        };
+
+        let (c, note) = build(r#"{"path": "a.rs", "line": 6, "body": "x"}"#, 2).unwrap();
+        assert_eq!(c.line, 4);
+        assert_eq!(note.unwrap(), "MOVED: a.rs:6 → 4, the nearest commentable line");
+        let (c, note) = build(r#"{"path": "a.rs", "line": 3, "body": "x"}"#, 2).unwrap();
+        assert_eq!((c.line, note), (3, None));
+        let (c, _) = build(r#"{"path": "a.rs", "line": 5, "start_line": 4, "body": "x"}"#, 3).unwrap();
+        assert_eq!((c.start_line, c.line), (Some(3), 4));
+
+        let err = build(r#"{"path": "a.rs", "line": 12, "body": "x"}"#, 5).unwrap_err();
+        assert!(err.message.contains("a.rs:12 is not a commentable line"), "{err:?}");
+        // Moving line 5 to 4 would put the start at 0
+        let err = build(r#"{"path": "a.rs", "line": 5, "start_line": 1, "body": "x"}"#, 1).unwrap_err();
+        assert!(err.message.contains("not a commentable line"), "{err:?}");
+    }
+
+    /// PR #1 with one earlier review carrying `existing_body`; POSTed reviews are kept in the returned slot
+    fn keyed_review_server(existing_body: &'static str) -> (MockServer, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        let posted = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = posted.clone();
+        let server = MockServer::start(move |method, path, body| match (method, path) {
+            ("POST", "/graphql") if body.contains("reviews(last:") => {
+                let node = serde_json::json!({
+                    "databaseId": 9, "author": { "login": "bot" }, "state": "COMMENTED",
+                    "submittedAt": "2026-01-01T00:00:00Z", "body": existing_body, "url": "u9",
+                });
+                let pr = serde_json::json!({ "reviews": { "nodes": [node] } });
+                (200, serde_json::json!({ "data": { "repository": { "pullRequest": pr } } }).to_string())
+            }
+            ("POST", "/graphql") => {
+                let file = r#"[{"path":"a.rs","additions":1,"deletions":0,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{file}"#)))
+            },
+            ("GET", "/repos/o/r/pulls/1") => {
+                (200, "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1,4 +1,5 @@\n x\n x\n+x\n x\n x\n".to_string())
+            }
+            ("GET", "/repos/o/r/contents/a.rs?ref=abc") => (200, contents_response("x\nx\nx\nx\nx\n")),
+            ("POST", "/repos/o/r/pulls/1/reviews") => {
+                seen.lock().unwrap().push(body.to_string());
+                (200, r#"{"id":10,"html_url":"u10"}"#.to_string())
+            }
+            _ => (404, "{}".to_string()),
+        });
+        (server, posted)
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_skips_existing_review() {
+        let (server, posted) = keyed_review_server("Suggestion from gh-agent\n\n<!-- gh-agent-key:run-42 -->");
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_suggest(&client, "o/r", 1, suggestion("a.rs", 3, 3, "y"), &SuggestOptions { idempotency_key: Some("run-42"), ..Default::default() }).await.unwrap();
+        // PR metadata and the review lookup, no POST
+        assert_eq!(server.hits(), 2);
+        assert!(posted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_embeds_marker_when_new() {
+        // A different key, and one that merely starts with ours, don't count
+        let (server, posted) = keyed_review_server("<!-- gh-agent-key:run-421 --> <!-- gh-agent-key:other -->");
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_suggest(&client, "o/r", 1, suggestion("a.rs", 3, 4, "y"), &SuggestOptions { idempotency_key: Some("run-42"), ..Default::default() }).await.unwrap();
+        let posted = posted.lock().unwrap();
+        assert_eq!(posted.len(), 1);
+        let review: serde_json::Value = serde_json::from_str(&posted[0]).unwrap();
+        assert_eq!(review["body"], "Suggestion from gh-agent\n\n<!-- gh-agent-key:run-42 -->");
+        // The suggestion comment itself stays a bare suggestion block
+        assert_eq!(review["comments"][0]["body"], "```suggestion\ny\n```");
+        assert_eq!(review["comments"][0]["start_line"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_review_body_from_template() {
+        let posted = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = posted.clone();
+        let server = MockServer::start(move |method, path, body| match (method, path) {
+            ("POST", "/graphql") => {
+                let file = r#"[{"path":"a.rs","additions":1,"deletions":0,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{file}"#)))
+            }
+            ("GET", "/repos/o/r/pulls/1") => {
+                (200, "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1,2 +1,3 @@\n x\n x\n+x\n".to_string())
+            }
+            ("POST", "/repos/o/r/pulls/1/reviews") => {
+                seen.lock().unwrap().push(body.to_string());
+                (200, r#"{"id":10,"html_url":"u10"}"#.to_string())
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let dir = std::env::temp_dir();
+        let comments = dir.join(format!("gh-agent-template-comments-{}.json", std::process::id()));
+        let template = dir.join(format!("gh-agent-template-body-{}.md", std::process::id()));
+        std::fs::write(&comments, r#"{"body": "ignored", "comments": [{"path": "a.rs", "line": 3, "body": "x", "severity": "blocker"}]}"#).unwrap();
+        std::fs::write(&template, "# {{pr_title}}\n{{comment_count}} comments ({{severity_counts}}), risk {{risk}}").unwrap();
+        let vars = ["risk=high".to_string()];
+        // --confirm --yes shows the summary and posts without asking
+        let run = pr_review(&client, "o/r", 1, ReviewOptions { comments_file: comments.to_str().unwrap(), template: template.to_str(), template_vars: &vars, chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, allow_duplicates: true, confirm: true, yes: true, ..Default::default() });
+        let (_, result) = pager::capture(run).await;
+        std::fs::remove_file(&comments).ok();
+        std::fs::remove_file(&template).ok();
+        result.unwrap();
+
+        let posted = posted.lock().unwrap();
+        let review: serde_json::Value = serde_json::from_str(&posted[0]).unwrap();
+        let body = review["body"].as_str().unwrap();
+        assert!(body.starts_with("# t\n1 comments (1 blocker), risk high\n\n<!-- gh-agent-review:"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_review_event_and_left_side_comments() {
+        let posted = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = posted.clone();
+        let server = MockServer::start(move |method, path, body| match (method, path) {
+            ("POST", "/graphql") => {
+                let file = r#"[{"path":"a.rs","additions":1,"deletions":1,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{file}"#)))
+            }
+            ("GET", "/repos/o/r/pulls/1") => {
+                (200, "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1,3 +1,3 @@\n x\n-y\n+z\n x\n".to_string())
+            }
+            ("POST", "/repos/o/r/pulls/1/reviews") => {
+                seen.lock().unwrap().push(body.to_string());
+                (200, r#"{"id":10,"html_url":"u10"}"#.to_string())
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let comments = std::env::temp_dir().join(format!("gh-agent-event-comments-{}.json", std::process::id()));
+        std::fs::write(
+            &comments,
+            r#"{"event": "REQUEST_CHANGES", "verdict": "no", "comments": [
+                {"path": "a.rs", "line": 2, "side": "LEFT", "body": "why remove y?"},
+                {"path": "a.rs", "start_line": 1, "line": 2, "side": "LEFT", "body": "range"},
+                {"path": "a.rs", "line": 9, "side": "LEFT", "body": "not in the base diff"},
+                {"path": "a.rs", "line": 2, "side": "LEFT", "suggestion": "y"},
+                {"path": "a.rs", "line": 2, "side": "RIGHT", "body": "z is fine"}
+            ]}"#,
+        )
+        .unwrap();
+        let run = pr_review(&client, "o/r", 1, ReviewOptions { comments_file: comments.to_str().unwrap(), chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, allow_duplicates: true, json: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        std::fs::remove_file(&comments).ok();
+        result.unwrap();
+
+        let posted = posted.lock().unwrap();
+        let review: serde_json::Value = serde_json::from_str(&posted[0]).unwrap();
+        assert_eq!(review["event"], "REQUEST_CHANGES");
+        assert_eq!(review["comments"][0], serde_json::json!({"path": "a.rs", "line": 2, "side": "LEFT", "body": "why remove y?"}));
+        assert_eq!(review["comments"][1]["start_side"], "LEFT");
+        assert!(review["comments"][2].get("side").is_none());
+        assert_eq!(review["comments"].as_array().unwrap().len(), 3);
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let reasons: Vec<&str> = out["skipped"].as_array().unwrap().iter().map(|s| s["reason"].as_str().unwrap()).collect();
+        assert_eq!(reasons, ["not_in_diff", "bad_target"]);
+    }
+
+    #[tokio::test]
+    async fn test_review_amend_adds_comments_on_my_latest_review() {
+        let posted = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = posted.clone();
+        let moved = std::sync::Arc::new(AtomicBool::new(false));
+        let head_moved = moved.clone();
+        let server = MockServer::start(move |method, path, body| match (method, path.split('?').next().unwrap_or_default()) {
+            ("POST", "/graphql") => {
+                let file = r#"[{"path":"a.rs","additions":1,"deletions":0,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{file}"#)))
+            }
+            ("GET", "/repos/o/r/pulls/1") => {
+                (200, "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1,2 +1,4 @@\n x\n x\n+x\n+x\n".to_string())
+            }
+            ("GET", "/user") => (200, r#"{"login":"bot"}"#.to_string()),
+            ("GET", "/repos/o/r/pulls/1/reviews") if path.ends_with("&page=1") => {
+                // Review 3 is on the head until the head moves
+                let on = |id: u64| if id == 3 && !head_moved.load(Ordering::SeqCst) { "abc".to_string() } else { format!("c{id}") };
+                let review = |id: u64, login: &str, state: &str| serde_json::json!({
+                    "id": id, "user": { "login": login }, "state": state, "body": "",
+                    "commit_id": on(id), "html_url": format!("r{id}"),
+                });
+                let reviews = [review(3, "bot", "COMMENTED"), review(4, "alice", "APPROVED"), review(5, "bot", "PENDING")];
+                (200, serde_json::Value::from_iter(reviews).to_string())
+            }
+            ("GET", _) => (200, "[]".to_string()),
+            ("POST", "/repos/o/r/pulls/1/comments") => {
+                let mut seen = seen.lock().unwrap();
+                seen.push(serde_json::from_str::<serde_json::Value>(body).unwrap());
+                (201, format!(r#"{{"html_url":"u{}"}}"#, seen.len()))
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let path = std::env::temp_dir().join(format!("gh-agent-amend-{}.json", std::process::id()));
+        std::fs::write(&path, r#"[{"path": "a.rs", "line": 3, "body": "x"}, {"path": "a.rs", "line": 4, "start_line": 3, "body": "y"}, {"path": "a.rs", "line": 9, "body": "z"}]"#).unwrap();
+        let options = || ReviewOptions { comments_file: path.to_str().unwrap(), chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, amend: true, ..Default::default() };
+        let (out, result) = pager::capture(pr_review(&client, "o/r", 1, options())).await;
+        result.unwrap();
+
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!((out["review_id"].as_u64(), out["commit_id"].as_str()), (Some(3), Some("abc")));
+        assert_eq!(out["skipped"], 1);
+        let urls: Vec<&str> = out["comments"].as_array().unwrap().iter().map(|c| c["url"].as_str().unwrap()).collect();
+        assert_eq!(urls, ["u1", "u2"]);
+        assert_eq!(
+            posted.lock().unwrap()[1],
+            serde_json::json!({ "commit_id": "abc", "side": "RIGHT", "path": "a.rs", "line": 4, "start_line": 3, "body": "y" })
+        );
+
+        // The lines were checked at the head, so a review of an older commit can't take them
+        moved.store(true, Ordering::SeqCst);
+        let (_, result) = pager::capture(pr_review(&client, "o/r", 1, options())).await;
+        std::fs::remove_file(&path).ok();
+        let err = result.unwrap_err().to_string();
+        assert!(err.starts_with("Review 3 was made on c3, but PR #1 is now at abc"), "{err}");
+        assert_eq!(posted.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_rewrite_suggestions_only_on_diff_lines() {
+        let content = "a(1)\nlog(2)\nc(\n4)\nlog(5)\n";
+        let rewritten = |text: &str, line: usize, to: &str| {
+            let start = content.find(text).unwrap();
+            search::SearchMatch {
+                file: "a.rs".to_string(),
+                line,
+                column: 1,
+                text: text.to_string(),
+                lead: String::new(),
+                context_before: vec![],
+                context_after: vec![],
+                rewrite: Some(search::Rewrite {
+                    span: start..start + text.len(),
+                    text: to.to_string(),
+                }),
+                highlight: None,
+            }
+        };
+        let matches = [rewritten("log(2)", 2, "debug(2)"), rewritten("c(\n4)", 3, "c(4)"), rewritten("log(5)", 5, "debug(5)")];
+        let matches: Vec<&search::SearchMatch> = matches.iter().collect();
+        let hunks = parse_patch("@@ -1,2 +1,3 @@\n a(1)\n+log(2)\n c(\n");
+        // log(5) is outside the diff; c(4) would also replace line 4, which is
+        assert_eq!(
+            rewrite_suggestions("a.rs", content, &hunks, &matches),
+            [Suggestion {
+                path: "a.rs".to_string(),
+                start_line: 2,
+                end_line: 2,
+                replacement: "debug(2)".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_comment_range_offers_nearest_ranges() {
+        let hunks = parse_patch("@@ -1,2 +1,3 @@\n a\n+b\n c\n@@ -20,2 +21,3 @@\n u\n+v\n w\n@@ -40,1 +42,2 @@\n x\n+y\n");
+        check_comment_range("a.rs", &hunks, 21, 23).unwrap();
+        let err = check_comment_range("a.rs", &hunks, 2, 9).unwrap_err().to_string();
+        assert_eq!(err, "a.rs:4 is not a commentable line (not in diff)\nNearest commentable ranges in a.rs: 1-3, 21-23, 42-43\nPass --force to post anyway");
+        let hunks = parse_patch("@@ -1,2 +1,3 @@\n a\n+b\n c\n@@ -4,2 +5,3 @@\n u\n+v\n w\n");
+        let err = check_comment_range("a.rs", &hunks, 3, 5).unwrap_err().to_string();
+        assert!(err.starts_with("a.rs:4 is not a commentable line"), "{err}");
+        let err = check_comment_range("a.rs", &parse_patch("@@ -1,2 +1,3 @@\n a\n+b\n c\n@@ -3,2 +4,3 @@\n u\n+v\n w\n"), 3, 4).unwrap_err();
+        assert!(err.to_string().starts_with("a.rs: range 3-4 crosses hunk boundary at 4"), "{err}");
+        assert!(check_comment_range("b.rs", &[], 1, 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_suggest_empty_replacement_deletes_the_lines() {
+        let (server, posted) = keyed_review_server("");
+        let client = github::Client::for_test(&server.url, None);
+        pr_suggest(&client, "o/r", 1, suggestion("a.rs", 3, 4, ""), &SuggestOptions::default()).await.unwrap();
+        let posted = posted.lock().unwrap();
+        let review: serde_json::Value = serde_json::from_str(&posted[0]).unwrap();
+        assert_eq!(review["comments"][0]["body"], "```suggestion\n```");
+        assert!(posted[0].contains(r#""body":"```suggestion\n```""#), "{}", posted[0]);
+        assert_eq!((review["comments"][0]["start_line"].as_u64(), review["comments"][0]["line"].as_u64()), (Some(3), Some(4)));
+    }
+
+    #[tokio::test]
+    async fn test_suggest_from_local_posts_one_review() {
+        let (server, posted) = keyed_review_server("");
+        let client = &github::Client::for_test(&server.url, None);
+        let local = std::env::temp_dir().join(format!("gh-agent-local-{}.rs", std::process::id()));
+        std::fs::write(&local, "x\nx\nY\nx\n").unwrap();
+        let local_path = local.to_str().unwrap();
+
+        let (out, result) = pager::capture(pr_suggest_from_local(client, "o/r", 1, "a.rs", local_path, &SuggestOptions { dry_run: true, ..Default::default() })).await;
+        result.unwrap();
+        assert!(out.contains("suggestion for a.rs:3-4:\n  -x\n  -x\n  +Y\n"), "{out}");
+        assert!(out.ends_with("1 suggestion would be posted\n"), "{out}");
+        assert!(posted.lock().unwrap().is_empty());
+
+        let (_, result) = pager::capture(pr_suggest_from_local(client, "o/r", 1, "a.rs", local_path, &SuggestOptions::default())).await;
+        std::fs::remove_file(&local).ok();
+        result.unwrap();
+        let posted = posted.lock().unwrap();
+        assert_eq!(posted.len(), 1);
+        let review: serde_json::Value = serde_json::from_str(&posted[0]).unwrap();
+        assert_eq!(review["comments"], serde_json::json!([{"path": "a.rs", "start_line": 3, "line": 4, "body": "```suggestion\nY\n```"}]));
+    }
+
+    #[tokio::test]
+    async fn test_suggest_sed_posts_one_suggestion_per_run() {
+        let (server, posted) = keyed_review_server("");
+        let client = &github::Client::for_test(&server.url, None);
+        let err = pr_suggest_sed(client, "o/r", 1, "a.rs", "s/y/z/", &SuggestOptions { dry_run: true, ..Default::default() }).await.unwrap_err();
+        assert_eq!(err.to_string(), "s/y/z/ matches nothing in a.rs at the PR head; nothing to suggest");
+
+        let (out, result) = pager::capture(pr_suggest_sed(client, "o/r", 1, "a.rs", r"s/(x)/[\1]/", &SuggestOptions { dry_run: true, ..Default::default() })).await;
+        result.unwrap();
+        assert!(out.starts_with("suggestion for a.rs:1-5:\n  -x\n"), "{out}");
+        assert!(out.ends_with("  +[x]\n1 suggestion would be posted\n"), "{out}");
+        assert!(posted.lock().unwrap().is_empty());
+
+        pr_suggest_sed(client, "o/r", 1, "a.rs", "s/x/y/", &SuggestOptions::default()).await.unwrap();
+        let posted = posted.lock().unwrap();
+        let review: serde_json::Value = serde_json::from_str(&posted[0]).unwrap();
+        assert_eq!(review["comments"], serde_json::json!([{"path": "a.rs", "start_line": 1, "line": 5, "body": "```suggestion\ny\ny\ny\ny\ny\n```"}]));
+    }
+
+    #[tokio::test]
+    async fn test_suggest_comment_and_review_body() {
+        let (server, posted) = keyed_review_server("");
+        let client = github::Client::for_test(&server.url, None);
+        let comment = "Indexing panics when empty:\n```rust\nv[0]\n```";
+        pr_suggest(&client, "o/r", 1, suggestion("a.rs", 3, 3, "v.first()"), &SuggestOptions { comment: Some(comment), review_body: Some("Two fixes"), ..Default::default() })
+            .await
+            .unwrap();
+        let posted = posted.lock().unwrap();
+        let review: serde_json::Value = serde_json::from_str(&posted[0]).unwrap();
+        assert_eq!(review["body"], "Two fixes");
+        assert_eq!(review["comments"][0]["body"], format!("{comment}\n\n````suggestion\nv.first()\n````"));
+    }
+
+    #[tokio::test]
+    async fn test_suggest_preview_posts_nothing() {
+        let (server, posted) = keyed_review_server("");
+        let client = &github::Client::for_test(&server.url, None);
+        let (out, result) = pager::capture(pr_suggest_preview(client, "o/r", 1, suggestion("a.rs", 5, 5, "y"), false, true)).await;
+        result.unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!((out["before"].as_str(), out["after"].as_str()), (Some("x\nx\nx\nx"), Some("x\nx\nx\ny")));
+        assert!(out["diff"].as_str().unwrap().ends_with("-x\n+y\n"), "{out}");
+        assert_eq!(server.hits(), 2);
+        assert!(posted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_suggest_force_posts_outside_the_diff() {
+        let (server, posted) = keyed_review_server("");
+        let client = github::Client::for_test(&server.url, None);
+        let err = pr_suggest(&client, "o/r", 1, suggestion("a.rs", 5, 6, "y"), &SuggestOptions::default()).await.unwrap_err();
+        assert!(err.to_string().contains("Nearest commentable ranges in a.rs: 1-5"), "{err}");
+        pr_suggest(&client, "o/r", 1, suggestion("a.rs", 5, 6, "y"), &SuggestOptions { force: true, ..Default::default() }).await.unwrap();
+        assert_eq!(posted.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_suggest_strict_refuses_noop_and_out_of_diff_suggestions() {
+        let (server, posted) = keyed_review_server("");
+        let client = github::Client::for_test(&server.url, None);
+
+        let err = pr_suggest(&client, "o/r", 1, suggestion("a.rs", 3, 3, "x"), &SuggestOptions { strict: true, ..Default::default() }).await.unwrap_err();
+        assert!(err.to_string().contains("a.rs:3-3: the replacement is identical"), "{err}");
+        let err = pr_suggest(&client, "o/r", 1, suggestion("a.rs", 6, 6, "y"), &SuggestOptions { strict: true, ..Default::default() }).await.unwrap_err();
+        assert!(err.to_string().contains("a.rs:6 is not a commentable line"), "{err}");
+        assert!(posted.lock().unwrap().is_empty());
+
+        // Without --strict they're only warnings
+        pr_suggest(&client, "o/r", 1, suggestion("a.rs", 3, 3, "x"), &SuggestOptions::default()).await.unwrap();
+        assert_eq!(posted.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_idempotency_keys_and_markers() {
+        for ok in ["run-42", "ci/123:retry.1", "a_b"] {
+            check_idempotency_key(ok).unwrap();
+        }
+        for bad in ["", "a--b", "x>y", "has space", &"k".repeat(129)] {
+            assert!(check_idempotency_key(bad).is_err(), "{bad}");
+        }
+        assert_eq!(idempotency_marker("k", 1, 1), "<!-- gh-agent-key:k -->");
+        assert_eq!(idempotency_marker("k", 2, 3), "<!-- gh-agent-key:k part 2/3 -->");
+        assert_eq!(with_marker("", "<!-- m -->"), "<!-- m -->");
+    }
+
+    /// Mock where the review POST fails with 502 but (if `lands`) the review is stored anyway
+    fn flaky_review_server(lands: bool) -> MockServer {
+        let posted = std::sync::Mutex::new(None::<String>);
+        MockServer::start(move |method, path, body| {
+            let mut posted = posted.lock().unwrap();
+            match (method, path.split('?').next().unwrap_or_default()) {
+                ("POST", "/repos/o/r/pulls/7/reviews") => {
+                    if lands {
+                        let review: serde_json::Value = serde_json::from_str(body).unwrap();
+                        *posted = review["body"].as_str().map(str::to_string);
+                    }
+                    (502, r#"{"message":"Server Error"}"#.to_string())
+                }
+                ("GET", "/repos/o/r/pulls/7/reviews") if !path.ends_with("&page=1") => (200, "[]".to_string()),
+                ("GET", "/repos/o/r/pulls/7/reviews") => {
+                    let mut reviews = vec![serde_json::json!({ "id": 1, "body": "older", "state": "APPROVED", "html_url": "u1" })];
+                    if let Some(body) = posted.as_ref() {
+                        reviews.push(serde_json::json!({ "id": 2, "body": body, "state": "COMMENTED", "html_url": "u2" }));
+                    }
+                    (200, serde_json::Value::from(reviews).to_string())
+                }
+                _ => (404, "{}".to_string()),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_review_created_despite_502() {
+        let server = flaky_review_server(true);
+        let client = github::Client::for_test(&server.url, None);
+
+        let out = post_review(&client, "o/r", 7, CreateReview { commit_id: "abc".to_string(), event: "COMMENT".to_string(), body: "LGTM".to_string(), comments: vec![comment("a.rs", 1)] }, false)
+            .await
+            .unwrap();
+        assert_eq!((out.id, out.url.as_str()), (2, "u2"));
+        // POST, then two pages of reviews
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_review_resumes_under_its_key() {
+        let stored = std::sync::Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+        let seen = stored.clone();
+        let attempts = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server = MockServer::start(move |method, path, body| match (method, path.split('?').next().unwrap_or_default()) {
+            ("POST", "/graphql") if body.contains("reviews(last:") => {
+                let node = |(i, body): (usize, &String)| serde_json::json!({
+                    "databaseId": 10 + i, "author": { "login": "bot" }, "state": "COMMENTED",
+                    "submittedAt": "2026-01-01T00:00:00Z", "body": body, "url": format!("u{}", 10 + i),
+                });
+                let nodes = serde_json::Value::from_iter(seen.lock().unwrap().iter().enumerate().map(node));
+                let pr = serde_json::json!({ "reviews": { "nodes": nodes } });
+                (200, serde_json::json!({ "data": { "repository": { "pullRequest": pr } } }).to_string())
+            }
+            ("POST", "/graphql") => {
+                let file = |p: &str| serde_json::json!({ "path": p, "additions": 1, "deletions": 0, "changeType": "MODIFIED" });
+                let files = serde_json::json!([file("a.rs"), file("b.rs"), file("c.rs")]);
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            ("GET", "/repos/o/r/pulls/1") => {
+                let patch = |p: &str| format!("diff --git a/{p} b/{p}\n--- a/{p}\n+++ b/{p}\n@@ -1,1 +1,2 @@\n a\n+b\n");
+                (200, ["a.rs", "b.rs", "c.rs"].map(patch).concat())
+            }
+            ("GET", "/repos/o/r/pulls/1/comments") => (200, "[]".to_string()),
+            ("POST", "/repos/o/r/pulls/1/reviews") => {
+                // The third POST fails once
+                if attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 2 {
+                    return (403, r#"{"message":"Forbidden"}"#.to_string());
+                }
+                let review: serde_json::Value = serde_json::from_str(body).unwrap();
+                let mut stored = seen.lock().unwrap();
+                stored.push(review["body"].as_str().unwrap().to_string());
+                (200, format!(r#"{{"id":{},"html_url":"u{}"}}"#, 9 + stored.len(), 9 + stored.len()))
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let path = std::env::temp_dir().join(format!("gh-agent-chunked-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"[{"path": "a.rs", "line": 2, "body": "x"}, {"path": "b.rs", "line": 2, "body": "y"}, {"path": "c.rs", "line": 2, "body": "z"}]"#,
+        )
+        .unwrap();
+        let file = path.to_str().unwrap();
+        let review = || pr_review(&client, "o/r", 1, ReviewOptions { comments_file: file, chunk_size: 1, max_comment_chars: MAX_BODY_CHARS, idempotency_key: Some("run-7"), ..Default::default() });
+
+        let err = review().await.unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("Review part 3/3 failed; these parts were posted:\n  part 1/3: review 10 u10\n  part 2/3: review 11 u11"), "{err}");
+        assert!(err.contains("Re-run with --idempotency-key run-7 to post only the missing parts"), "{err}");
+
+        let (out, result) = pager::capture(review()).await;
+        std::fs::remove_file(&path).ok();
+        result.unwrap();
+        let stored = stored.lock().unwrap();
+        assert_eq!(stored.len(), 3);
+        assert!(stored[2].starts_with("Continued review (part 3/3)"), "{}", stored[2]);
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let urls: Vec<&str> = out["reviews"].as_array().unwrap().iter().map(|r| r["url"].as_str().unwrap()).collect();
+        assert_eq!(urls, ["u10", "u11", "u12"]);
+    }
+
+    #[tokio::test]
+    async fn test_review_not_created_after_502() {
+        let server = flaky_review_server(false);
+        let client = github::Client::for_test(&server.url, None);
+
+        let err = post_review(&client, "o/r", 7, CreateReview { commit_id: "abc".to_string(), event: "COMMENT".to_string(), body: "LGTM".to_string(), comments: vec![comment("a.rs", 1)] }, false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("safe to retry"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_retry_without_invalid_drops_rejected_comments() {
+        let server = MockServer::start(|_, _, body| {
+            let review: serde_json::Value = serde_json::from_str(body).unwrap();
+            if review["comments"].as_array().unwrap().len() == 3 {
+                let err = r#"{"message":"Unprocessable Entity","errors":["Line could not be resolved for b.rs line 9"]}"#;
+                (422, err.to_string())
+            } else {
+                (200, r#"{"id":5,"html_url":"u5"}"#.to_string())
+            }
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let comments = || vec![comment("a.rs", 1), comment("b.rs", 9), comment("b.rs", 12)];
+
+        let err = post_review(&client, "o/r", 7, CreateReview { commit_id: "abc".to_string(), event: "COMMENT".to_string(), body: "".to_string(), comments: comments() }, false).await.unwrap_err();
+        assert!(err.to_string().contains("--retry-without-invalid"), "{err}");
+
+        let out = post_review(&client, "o/r", 7, CreateReview { commit_id: "abc".to_string(), event: "COMMENT".to_string(), body: "".to_string(), comments: comments() }, true).await.unwrap();
+        assert_eq!(out.id, 5);
+        let dropped: Vec<(&str, u64)> = out.dropped.iter().map(|d| (d.path.as_str(), d.line)).collect();
+        assert_eq!(dropped, [("b.rs", 9)]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_without_invalid_bisects_an_unexplained_422() {
+        let probes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = probes.clone();
+        let server = MockServer::start(move |method, _, body| {
+            if method == "DELETE" {
+                return (204, String::new());
+            }
+            let review: serde_json::Value = serde_json::from_str(body).unwrap();
+            let comments = review["comments"].as_array().unwrap();
+            let pending = review.get("event").is_none();
+            if pending {
+                seen.lock().unwrap().push(comments.len());
+            }
+            if comments.iter().any(|c| c["path"] == "b.rs" && c["line"] == 9) {
+                (422, r#"{"message":"Unprocessable Entity","errors":["Position could not be resolved"]}"#.to_string())
+            } else if pending {
+                (200, r#"{"id":77,"html_url":"u77"}"#.to_string())
+            } else {
+                (200, r#"{"id":5,"html_url":"u5"}"#.to_string())
+            }
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let comments = || vec![comment("a.rs", 1), comment("b.rs", 9), comment("b.rs", 12), comment("c.rs", 3)];
+
+        let err = post_review(&client, "o/r", 7, CreateReview { commit_id: "abc".to_string(), event: "COMMENT".to_string(), body: "".to_string(), comments: comments() }, false).await.unwrap_err();
+        assert!(format!("{err:#}").contains("--retry-without-invalid finds and drops it"), "{err:#}");
+        assert!(probes.lock().unwrap().is_empty());
+
+        let out = post_review(&client, "o/r", 7, CreateReview { commit_id: "abc".to_string(), event: "COMMENT".to_string(), body: "".to_string(), comments: comments() }, true).await.unwrap();
+        assert_eq!(out.id, 5);
+        let dropped: Vec<(&str, u64, &str)> = out.dropped.iter().map(|d| (d.path.as_str(), d.line, d.reason.as_str())).collect();
+        assert_eq!(dropped, [("b.rs", 9, "Unprocessable Entity (found by bisection)")]);
+        // [a, b:9] fails, [a] passes so b:9 is blamed unprobed, then [b:12, c] passes
+        assert_eq!(*probes.lock().unwrap(), [2, 1, 2]);
+    }
+
+    fn chunk_shape(chunks: &[Vec<ReviewCommentInput>]) -> Vec<Vec<(&str, u64)>> {
+        chunks
+            .iter()
+            .map(|c| c.iter().map(|c| (c.path.as_str(), c.line)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_keep_files_together() {
+        let comments = vec![
+            comment("a.rs", 1),
+            comment("b.rs", 1),
+            comment("a.rs", 2),
+            comment("c.rs", 1),
+            comment("c.rs", 2),
+            comment("b.rs", 2),
+        ];
+        let chunks = chunk_review_comments(comments, 3);
+        assert_eq!(
+            chunk_shape(&chunks),
+            [
+                vec![("a.rs", 1), ("a.rs", 2)],
+                vec![("b.rs", 1), ("b.rs", 2)],
+                vec![("c.rs", 1), ("c.rs", 2)],
+            ]
+        );
+        assert_eq!(chunk_review_comments(vec![comment("a.rs", 1)], 50).len(), 1);
+        assert!(chunk_review_comments(vec![], 50).is_empty());
+    }
+
+    #[test]
+    fn test_chunks_split_oversized_file() {
+        let mut comments: Vec<ReviewCommentInput> = (1..=5).map(|l| comment("big.rs", l)).collect();
+        comments.insert(0, comment("small.rs", 1));
+        let chunks = chunk_review_comments(comments, 2);
+        assert_eq!(
+            chunk_shape(&chunks),
+            [
+                vec![("small.rs", 1)],
+                vec![("big.rs", 1), ("big.rs", 2)],
+                vec![("big.rs", 3), ("big.rs", 4)],
+                vec![("big.rs", 5)],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_fails_if_blocked() {
+        let server = MockServer::start(|_, _, _| {
+            let pr = serde_json::json!({
+                "number": 1, "title": "t", "state": "OPEN", "isDraft": false,
+                "mergeable": "MERGEABLE", "mergeStateStatus": "BLOCKED", "reviewDecision": null,
+                "headRefName": "feature", "headRefOid": "abc",
+                "reviewRequests": { "nodes": [
+                    { "requestedReviewer": { "login": "alice" } },
+                    { "requestedReviewer": { "slug": "core", "organization": { "login": "org" } } },
+                    { "requestedReviewer": {} },
+                ]},
+                "commits": { "nodes": [{ "commit": { "statusCheckRollup": {
+                    "state": "FAILURE",
+                    "contexts": { "nodes": [
+                        { "__typename": "CheckRun", "name": "test", "status": "COMPLETED", "conclusion": "FAILURE", "detailsUrl": null },
+                        { "__typename": "StatusContext", "context": "ci/legacy", "state": "SUCCESS", "targetUrl": null },
+                    ]},
+                }}}]},
+            });
+            (200, serde_json::json!({ "data": { "repository": { "pullRequest": pr } } }).to_string())
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let status = client.get_pr_status("o/r", 1).await.unwrap();
+        assert_eq!(status.requested_reviewers, ["alice", "org/core"]);
+        assert_eq!(merge_blockers(&status), ["1 failing check"]);
+
+        let err = pr_status(&client, "o/r", 1, 0, true, true).await.unwrap_err();
+        assert!(err.to_string().contains("not ready to merge: 1 failing check"), "{err}");
+        assert!(pr_status(&client, "o/r", 1, 0, false, true).await.is_ok());
+    }
+
+    #[test]
+    fn test_merge_blockers() {
+        let mut status = github::PrStatus {
+            number: 1,
+            title: String::new(),
+            state: "OPEN".to_string(),
+            is_draft: false,
+            mergeable: "MERGEABLE".to_string(),
+            merge_state_status: "CLEAN".to_string(),
+            review_decision: Some("APPROVED".to_string()),
+            requested_reviewers: vec![],
+            head_ref: String::new(),
+            head_sha: String::new(),
+            is_cross_repository: false,
+            checks_state: None,
+            checks: vec![],
+        };
+        assert!(merge_blockers(&status).is_empty());
+
+        status.merge_state_status = "BLOCKED".to_string();
+        assert_eq!(merge_blockers(&status), ["blocked by branch protection"]);
+
+        status.is_draft = true;
+        status.mergeable = "CONFLICTING".to_string();
+        status.review_decision = Some("CHANGES_REQUESTED".to_string());
+        status.checks = vec![github::CheckRun {
+            name: "ci".to_string(),
+            status: "in_progress".to_string(),
+            conclusion: None,
+            details_url: None,
+        }];
+        assert_eq!(
+            merge_blockers(&status),
+            ["draft", "merge conflicts", "changes requested", "1 pending check"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_refuses_draft_and_deletes_branch() {
+        let draft = Arc::new(AtomicBool::new(true));
+        let flag = draft.clone();
+        let server = MockServer::start(move |method, path, body| match (method, path) {
+            ("POST", "/graphql") => {
+                let pr = serde_json::json!({
+                    "number": 1, "title": "t", "state": "OPEN", "isDraft": flag.load(Ordering::SeqCst),
+                    "mergeable": "MERGEABLE", "mergeStateStatus": "CLEAN", "reviewDecision": null,
+                    "headRefName": "feature", "headRefOid": "abc",
+                    "reviewRequests": { "nodes": [] },
+                    "commits": { "nodes": [] },
+                });
+                (200, serde_json::json!({ "data": { "repository": { "pullRequest": pr } } }).to_string())
+            }
+            ("PUT", "/repos/o/r/pulls/1/merge") => {
+                assert!(body.contains(r#""merge_method":"squash""#), "{body}");
+                assert!(!body.contains("commit_title"), "{body}");
+                (200, r#"{"sha": "def456", "merged": true, "message": "Pull Request successfully merged"}"#.to_string())
+            }
+            ("DELETE", "/repos/o/r/git/refs/heads/feature") => (204, String::new()),
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let err = pr_merge(&client, "o/r", 1, MergeOptions { method: "squash", subject: None, body: None, delete_branch: true, force: false }).await.unwrap_err();
+        assert!(err.to_string().contains("it is a draft"), "{err}");
+        assert_eq!(server.hits(), 1, "nothing merged");
+
+        draft.store(false, Ordering::SeqCst);
+        pr_merge(&client, "o/r", 1, MergeOptions { method: "squash", subject: None, body: None, delete_branch: true, force: false }).await.unwrap();
+        assert_eq!(server.hits(), 4, "status, merge, branch deletion");
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_base_branch_named_like_a_forks() {
+        let server = MockServer::start(|method, path, _| match (method, path) {
+            ("POST", "/graphql") => {
+                let pr = serde_json::json!({
+                    "number": 1, "title": "t", "state": "OPEN", "isDraft": false,
+                    "mergeable": "MERGEABLE", "mergeStateStatus": "CLEAN", "reviewDecision": null,
+                    "headRefName": "main", "headRefOid": "abc", "isCrossRepository": true,
+                    "reviewRequests": { "nodes": [] },
+                    "commits": { "nodes": [] },
+                });
+                (200, serde_json::json!({ "data": { "repository": { "pullRequest": pr } } }).to_string())
+            }
+            ("PUT", "/repos/o/r/pulls/1/merge") => {
+                (200, r#"{"sha": "def456", "merged": true, "message": "Pull Request successfully merged"}"#.to_string())
+            }
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_merge(&client, "o/r", 1, MergeOptions { method: "merge", subject: None, body: None, delete_branch: true, force: false }).await.unwrap();
+        assert_eq!(server.hits(), 2, "status and merge, no DELETE of o/r's main");
+    }
+
+    #[test]
+    fn test_merge_error_message() {
+        use reqwest::StatusCode;
+        let body = |m: &str| serde_json::json!({ "message": m }).to_string();
+        let cases = [
+            (
+                StatusCode::METHOD_NOT_ALLOWED,
+                body("At least 2 approving reviews are required by reviewers with write access."),
+                "branch protection requires 2 approvals",
+            ),
+            (
+                StatusCode::METHOD_NOT_ALLOWED,
+                body("At least 1 approving review is required by reviewers with write access."),
+                "branch protection requires 1 approval",
+            ),
+            (
+                StatusCode::METHOD_NOT_ALLOWED,
+                body("Required status check \"ci\" is expected."),
+                "branch protection requires passing status checks (Required status check \"ci\" is expected)",
+            ),
+            (
+                StatusCode::METHOD_NOT_ALLOWED,
+                body("Merge commits are not allowed on this repository."),
+                "Merge commits are not allowed on this repository; try a different --method",
+            ),
+            (
+                StatusCode::CONFLICT,
+                body("Head branch was modified. Review and try the merge again."),
+                "the head branch changed during the merge (Head branch was modified. Review and try the merge again); review the new commits and retry",
+            ),
+        ];
+        for (status, body, expected) in cases {
+            assert_eq!(merge_error_message(status, &body).as_deref(), Some(expected));
+        }
+        assert_eq!(merge_error_message(StatusCode::NOT_FOUND, "{}"), None);
+    }
+
+    #[tokio::test]
+    async fn test_writes_to_closed_pr_abort_before_fetching_content() {
+        let server = MockServer::start(|method, path, _| match (method, path) {
+            ("POST", "/graphql") => {
+                let file = r#"[{"path":"a.rs","additions":1,"deletions":0,"changeType":"MODIFIED"}]"#;
+                let pr = pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{file}"#));
+                (200, pr.replace(r#""state":"OPEN""#, r#""state":"MERGED""#))
+            }
+            ("GET", "/repos/o/r/pulls/1") => (200, "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -0,0 +1 @@\n+a\n".to_string()),
+            ("POST", "/repos/o/r/pulls/1/reviews") => (200, r#"{"id":10,"html_url":"u10"}"#.to_string()),
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        // The comments file is never read, and the raw diff never fetched
+        let err = pr_review(&client, "o/r", 1, ReviewOptions { comments_file: "/nonexistent.json", chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, ..Default::default() }).await.unwrap_err();
+        assert!(is_pr_not_open(&err), "{err}");
+        assert_eq!(err.to_string(), "PR #1 is merged; nothing was written (pass --allow-closed or --force to write anyway)");
+        assert_eq!(server.hits(), 1);
+
+        let err = pr_suggest(&client, "o/r", 1, suggestion("a.rs", 1, 1, "x"), &SuggestOptions { include_context: true, ..Default::default() }).await.unwrap_err();
+        assert!(is_pr_not_open(&err), "{err}");
+        assert_eq!(server.hits(), 2);
+
+        let err = pr_ready(&client, "o/r", 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "PR #1 is merged; nothing was written");
+
+        pr_suggest(&client, "o/r", 1, suggestion("a.rs", 1, 1, "x"), &SuggestOptions { allow_closed: true, ..Default::default() }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_metadata_writes_to_closed_pr_read_only_its_state() {
+        let cache = config::use_cache_dir_for_test("closed-writes");
+        let server = MockServer::start(|method, path, _| match (method, path) {
+            ("POST", "/graphql") => (200, pr_response(1, "abc").replace(r#""state":"OPEN""#, r#""state":"CLOSED""#)),
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = &github::Client::for_test(&server.url, None);
+        let pending = pending::PendingReview { id: 77, node_id: "PRR_1".to_string(), commit_id: "abc".to_string(), comments: 0 };
+        pending::store("o/r", 1, &pending).unwrap();
+        let names = ["x".to_string()];
+
+        let refusals = [
+            pr_label(client, "o/r", 1, &names, &[], false, false).await,
+            pr_assign(client, "o/r", 1, AssignOptions { add: &names, ..Default::default() }).await,
+            pr_reviewers(client, "o/r", 1, ReviewersOptions { add: &names, ..Default::default() }).await,
+            pr_dismiss_review(client, "o/r", 1, Some(3), false, "stale", false).await,
+            pr_review_add(client, "o/r", 1, ("a.rs", 1), None, "nit", false).await,
+            pr_review_submit(client, "o/r", 1, "COMMENT", None, false).await,
+        ];
+        for (i, result) in refusals.into_iter().enumerate() {
+            assert!(is_pr_not_open(result.as_ref().unwrap_err()), "{i}: {result:?}");
+        }
+        let (out, result) = pager::capture(pr_resolve(client, "o/r", 1, Some("T1"), None, false, false)).await;
+        assert!(is_pr_not_open(&result.unwrap_err()));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&out).unwrap()["state"], "closed");
+        // One metadata read each, nothing else fetched or written
+        assert_eq!(server.hits(), 7);
+        assert!(pending::load("o/r", 1).unwrap().is_some());
+
+        // --allow-closed gets past the check to the write
+        let err = pr_label(client, "o/r", 1, &names, &[], false, true).await.unwrap_err();
+        assert!(!is_pr_not_open(&err), "{err}");
+        // Listing alone writes nothing, so it doesn't ask
+        let err = pr_reviewers(client, "o/r", 1, ReviewersOptions { list: true, ..Default::default() }).await.unwrap_err();
+        assert!(!is_pr_not_open(&err), "{err}");
+        let _ = std::fs::remove_dir_all(cache);
+    }
+
+    #[tokio::test]
+    async fn test_read_json_carries_the_state() {
+        let server = MockServer::start(|method, path, body| match (method, path) {
+            ("POST", "/graphql") if body.contains("reviewThreads") => {
+                let threads = serde_json::json!({ "pageInfo": { "hasNextPage": false, "endCursor": null }, "nodes": [] });
+                (200, serde_json::json!({ "data": { "repository": { "pullRequest": { "reviewThreads": threads } } } }).to_string())
+            }
+            ("POST", "/graphql") => {
+                let file = r#"[{"path":"a.rs","additions":1,"deletions":0,"changeType":"MODIFIED"}]"#;
+                let pr = pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{file}"#));
+                (200, pr.replace(r#""state":"OPEN""#, r#""state":"MERGED""#))
+            }
+            ("GET", "/repos/o/r/pulls/1") => (200, "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1,2 @@\n a\n+b\n".to_string()),
+            ("GET", "/repos/o/r/pulls/1/commits?per_page=100&page=1") => {
+                let commit = serde_json::json!({
+                    "sha": "c1", "commit": { "message": "m", "author": { "name": "alice", "date": "2024-01-01T00:00:00Z" } },
+                });
+                (200, serde_json::json!([commit]).to_string())
+            }
+            ("GET", p) if p.contains("/check-runs") => (200, r#"{"total_count": 0, "check_runs": []}"#.to_string()),
+            ("GET", p) if p.starts_with("/repos/o/r/commits/abc/status") => (200, r#"{"statuses": []}"#.to_string()),
+            ("GET", "/repos/o/r/commits/c1") => {
+                let files = serde_json::json!([{ "filename": "a.rs", "status": "modified", "additions": 1, "deletions": 0 }]);
+                (200, serde_json::json!({ "sha": "c1", "commit": { "message": "m" }, "files": files }).to_string())
+            }
+            ("GET", "/repos/o/r/contents/a.rs?ref=feature") => (200, contents_response("a\nb\n")),
+            ("GET", p) if p.ends_with("page=1") || p.ends_with("page=2") => (200, "[]".to_string()),
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = &github::Client::for_test(&server.url, None);
+
+        let outputs = [
+            ("view", pager::capture(pr_view(client, "o/r", 1, ViewOptions { json: true, ..Default::default() })).await),
+            ("files", pager::capture(pr_files(client, "o/r", 1, false, &[], true)).await),
+            ("commits", pager::capture(pr_commits(client, "o/r", 1, false, false, true)).await),
+            ("comments", pager::capture(pr_comments(client, "o/r", 1, &[], false, true)).await),
+            ("reviews", pager::capture(pr_reviews(client, "o/r", 1, false, true)).await),
+            ("file", pager::capture(pr_file(client, "o/r", 1, "a.rs", false, None)).await),
+            ("file-history", pager::capture(pr_file_history(client, "o/r", 1, "a.rs", None, true)).await),
+            ("diff", pager::capture(pr_diff(client, "o/r", 1, DiffOptions { json: true, ..Default::default() })).await),
+            ("checks", pager::capture(pr_checks(client, "o/r", 1, false, true)).await),
+            ("priorities", pager::capture(pr_priorities(client, "o/r", 1, &config::PriorityConfig::default(), true)).await),
+        ];
+        for (command, (out, result)) in outputs {
+            result.unwrap_or_else(|e| panic!("{command}: {e:#}"));
+            let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+            assert_eq!(out["state"], "MERGED", "{command}: {out}");
+        }
+    }
+
+    #[test]
+    fn test_ensure_open_states() {
+        for (state, allow_closed, ok) in [
+            ("OPEN", Some(false), true),
+            ("OPEN", None, true),
+            ("CLOSED", Some(false), false),
+            ("CLOSED", Some(true), true),
+            ("CLOSED", None, false),
+            ("MERGED", Some(false), false),
+            ("MERGED", Some(true), true),
+            ("MERGED", None, false),
+        ] {
+            assert_eq!(ensure_open(7, state, allow_closed).is_ok(), ok, "{state} {allow_closed:?}");
+        }
+        let err = ensure_open(7, "CLOSED", Some(false)).unwrap_err();
+        assert_eq!(err.to_string(), "PR #7 is closed; nothing was written (pass --allow-closed or --force to write anyway)");
+    }
+
+    #[tokio::test]
+    async fn test_review_json_refusal_carries_the_state() {
+        let server = MockServer::start(|method, path, _| match (method, path) {
+            ("POST", "/graphql") => (200, pr_response(1, "abc").replace(r#""state":"OPEN""#, r#""state":"CLOSED""#)),
+            _ => (404, "{}".to_string()),
+        });
+        let client = &github::Client::for_test(&server.url, None);
+        let run = pr_review(client, "o/r", 1, ReviewOptions { comments_file: "/nonexistent.json", chunk_size: 50, max_comment_chars: MAX_BODY_CHARS, json: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        assert!(is_pr_not_open(&result.unwrap_err()));
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out, serde_json::json!({"error": "pr_not_open", "number": 1, "state": "closed"}));
+    }
+
+    #[tokio::test]
+    async fn test_json_refusals_carry_the_state() {
+        let server = MockServer::start(|method, path, body| match (method, path) {
+            // `pr merge` reads the state from the status query
+            ("POST", "/graphql") if body.contains("mergeStateStatus") => {
+                let pr = serde_json::json!({
+                    "number": 1, "title": "t", "state": "MERGED", "isDraft": false,
+                    "mergeable": "UNKNOWN", "mergeStateStatus": "UNKNOWN", "reviewDecision": null,
+                    "headRefName": "feature", "headRefOid": "abc",
+                    "reviewRequests": { "nodes": [] },
+                    "commits": { "nodes": [] },
+                });
+                (200, serde_json::json!({ "data": { "repository": { "pullRequest": pr } } }).to_string())
+            }
+            ("POST", "/graphql") => (200, pr_response(1, "abc").replace(r#""state":"OPEN""#, r#""state":"MERGED""#)),
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = &github::Client::for_test(&server.url, None);
+        async fn refusal(run: impl std::future::Future<Output = Result<()>>) -> serde_json::Value {
+            let (out, result) = pager::capture(run).await;
+            assert!(is_pr_not_open(&result.unwrap_err()));
+            serde_json::from_str(&out).unwrap()
+        }
+
+        let merged = serde_json::json!({"error": "pr_not_open", "number": 1, "state": "merged"});
+        assert_eq!(refusal(pr_suggest(client, "o/r", 1, suggestion("a.rs", 1, 1, "x"), &SuggestOptions::default())).await, merged);
+        assert_eq!(refusal(pr_approve(client, "o/r", 1, None, false)).await, merged);
+        assert_eq!(refusal(suggest_rewrites(client, "o/r", 1, &[], &[], false)).await, merged);
+        let options = MergeOptions { method: "merge", subject: None, body: None, delete_branch: false, force: true };
+        assert_eq!(refusal(pr_merge(client, "o/r", 1, options)).await, merged);
+        assert_eq!(refusal(pr_close(client, "o/r", 1, Some("stale"), false)).await, merged);
+        // One metadata read each, nothing written
+        assert_eq!(server.hits(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_approve_own_pr_is_a_clear_error() {
+        let server = MockServer::start(|method, _, body| match method {
+            "POST" if body.contains("query") => (200, pr_response(1, "abc")),
+            _ => {
+                assert!(body.contains(r#""event":"APPROVE""#) && body.contains(r#""comments":[]"#), "{body}");
+                (
+                    422,
+                    r#"{"message": "Unprocessable Entity", "errors": ["Review Can not approve your own pull request"]}"#.to_string(),
+                )
+            }
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let err = pr_approve(&client, "o/r", 1, Some("LGTM"), false).await.unwrap_err();
+        assert!(err.to_string().contains("doesn't let you approve your own pull request"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_reviewers_add_and_list() {
+        let server = MockServer::start(|method, path, body| match (method, path) {
+            ("POST", "/graphql") => (200, pr_response(1, "abc")),
+            ("POST", _) => {
+                assert!(
+                    body.contains(r#""reviewers":["alice"]"#) && body.contains(r#""team_reviewers":["core"]"#),
+                    "{body}"
+                );
+                (201, r#"{"requested_reviewers": [{"login": "alice"}], "requested_teams": [{"slug": "core"}]}"#.to_string())
+            }
+            ("DELETE", _) => {
+                assert_eq!(body, r#"{"reviewers":["carol"],"team_reviewers":[]}"#);
+                (200, "{}".to_string())
+            }
+            ("GET", p) if p.contains("/requested_reviewers") => (
+                200,
+                r#"{"users": [{"login": "alice"}], "teams": [{"slug": "core"}]}"#.to_string(),
+            ),
+            ("GET", p) if p.ends_with("&page=1") => (
+                200,
+                r#"[{"id": 1, "user": {"login": "bob"}, "state": "APPROVED", "submitted_at": "2024-05-01T00:00:00Z", "body": "", "html_url": "u"}]"#.to_string(),
+            ),
+            _ => (200, "[]".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let (add, add_team, remove) = (["@alice".to_string()], ["O/core".to_string()], ["carol".to_string()]);
+        let options = ReviewersOptions { add: &add, add_team: &add_team, remove: &remove, list: true, ..Default::default() };
+        pr_reviewers(&client, "o/r", 1, options).await.unwrap();
+        // PR state, request, withdrawal, then the two lists (reviews in two pages)
+        assert_eq!(server.hits(), 6);
+
+        let add_team = ["other/core".to_string()];
+        let err = pr_reviewers(&client, "o/r", 1, ReviewersOptions { add_team: &add_team, ..Default::default() })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("only teams of o can review"), "{err}");
+        let add_team = ["core".to_string()];
+        let err = pr_reviewers(&client, "o/r", 1, ReviewersOptions { add_team: &add_team, ..Default::default() })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("expects org/team"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_reviewers_author_is_a_clear_error() {
+        let server = MockServer::start(|_, path, _| match path {
+            "/graphql" => (200, pr_response(1, "abc")),
+            _ => (422, r#"{"message": "Review cannot be requested from pull request author."}"#.to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let add = ["me".to_string()];
+        let err = pr_reviewers(&client, "o/r", 1, ReviewersOptions { add: &add, ..Default::default() })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("can't request a review from the author of PR #1"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_close_with_comment_and_branch_delete() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let log = calls.clone();
+        let server = MockServer::start(move |method, path, body| {
+            log.lock().unwrap().push(format!("{method} {path}"));
+            match (method, path) {
+                ("POST", "/graphql") => (200, pr_response(1, "abc")),
+                ("POST", "/repos/o/r/issues/1/comments") => {
+                    assert_eq!(body, r#"{"body":"Stale, closing."}"#);
+                    (201, r#"{"id": 9, "html_url": "https://c/9"}"#.to_string())
+                }
+                ("PATCH", "/repos/o/r/pulls/1") => {
+                    assert_eq!(body, r#"{"state":"closed"}"#);
+                    (200, r#"{"number": 1, "state": "closed"}"#.to_string())
+                }
+                ("DELETE", "/repos/o/r/git/refs/heads/feature") => (204, String::new()),
+                _ => (404, format!("unexpected {method} {path}")),
+            }
+        });
+        let client = github::Client::for_test(&server.url, None);
 
-    if skipped > 0 {
-        eprintln!("skipped {} noise files (lock/generated/minified). Use --all to include.", skipped);
+        pr_close(&client, "o/r", 1, Some("Stale, closing."), true).await.unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "POST /graphql",
+                "POST /repos/o/r/issues/1/comments",
+                "PATCH /repos/o/r/pulls/1",
+                "DELETE /repos/o/r/git/refs/heads/feature",
+            ]
+        );
     }
 
-    if json {
-        let mut map = HashMap::new();
-        for f in &files {
-            let hunks = f.patch.as_deref().map(parse_patch).unwrap_or_default();
-            let cl = commentable_lines(&hunks);
-            map.insert(f.filename.clone(), cl);
-        }
-        return print_json(&DiffJson { files: map });
+    #[tokio::test]
+    async fn test_close_keeps_base_branch_named_like_a_forks() {
+        let server = MockServer::start(|method, path, _| match (method, path) {
+            ("POST", "/graphql") => (200, pr_response(1, "abc").replace(r#""isDraft":false"#, r#""isDraft":false,"isCrossRepository":true"#)),
+            ("PATCH", "/repos/o/r/pulls/1") => (200, r#"{"number": 1, "state": "closed"}"#.to_string()),
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_close(&client, "o/r", 1, None, true).await.unwrap();
+        assert_eq!(server.hits(), 2, "no DELETE of o/r's feature branch");
     }
 
-    if stat_only {
-        let borrowed: Vec<github::PrFile> = files.iter().map(|f| (*f).clone()).collect();
-        println!("{}", format::format_stat_table(&borrowed));
-        return Ok(());
+    #[tokio::test]
+    async fn test_close_closed_pr_is_a_no_op() {
+        let server = MockServer::start(|method, path, body| match (method, path) {
+            ("POST", "/graphql") => (200, pr_response(1, "abc").replace(r#""state":"OPEN""#, r#""state":"CLOSED""#)),
+            ("PATCH", "/repos/o/r/pulls/1") => {
+                assert_eq!(body, r#"{"state":"open"}"#);
+                (200, r#"{"number": 1, "state": "open"}"#.to_string())
+            }
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_close(&client, "o/r", 1, Some("again"), true).await.unwrap();
+        assert_eq!(server.hits(), 1);
+        pr_reopen(&client, "o/r", 1).await.unwrap();
+        assert_eq!(server.hits(), 3);
     }
 
-    for (i, f) in files.iter().enumerate() {
-        if i > 0 {
-            println!();
-        }
-        println!("{}", format::format_line_numbered_diff(f));
+    #[tokio::test]
+    async fn test_update_branch() {
+        let server = MockServer::start(|method, path, body| match (method, path) {
+            ("POST", "/graphql") if body.contains("updatePullRequestBranch") => {
+                assert!(
+                    body.contains(r#""expectedHeadOid":"abc""#) && body.contains(r#""updateMethod":"REBASE""#),
+                    "{body}"
+                );
+                let data = serde_json::json!({ "result": { "pullRequest": { "headRefOid": "def" } } });
+                (200, serde_json::json!({ "data": data }).to_string())
+            }
+            ("POST", "/graphql") => (200, pr_response(1, "abc")),
+            ("PUT", "/repos/o/r/pulls/1/update-branch") => {
+                assert_eq!(body, r#"{"expected_head_sha":"abc"}"#);
+                (202, r#"{"message": "Updating pull request branch.", "url": "u"}"#.to_string())
+            }
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_update_branch(&client, "o/r", 1, false).await.unwrap();
+        pr_update_branch(&client, "o/r", 1, true).await.unwrap();
+        assert_eq!(server.hits(), 4);
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_update_branch_conflict_is_a_clear_error() {
+        let server = MockServer::start(|method, _, _| match method {
+            "POST" => (200, pr_response(1, "abc")),
+            _ => (422, r#"{"message": "merge conflict between base and head"}"#.to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
 
-pub async fn pr_file(
-    client: &github::Client,
-    repo: &str,
-    number: u64,
-    path: &str,
-) -> Result<()> {
-    let pr = client.get_pr(repo, number).await?;
-    let content = client
-        .get_file_content(repo, path, &pr.head_ref)
-        .await?;
-    let lines = content.lines().count();
+        let err = pr_update_branch(&client, "o/r", 1, false).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "PR #1 can't be updated automatically: feature conflicts with main; resolve it locally and push"
+        );
+    }
 
-    let out = FileOut {
-        path: path.to_string(),
-        content,
-        lines,
-    };
-    print_json(&out)
-}
+    #[tokio::test]
+    async fn test_conflicts_attributes_newest_base_commit_and_fails() {
+        let server = MockServer::start(|method, path, body| match (method, path) {
+            ("POST", "/graphql") if body.contains("mergeStateStatus") => {
+                let pr = serde_json::json!({
+                    "number": 1, "title": "t", "state": "OPEN", "isDraft": false,
+                    "mergeable": "CONFLICTING", "mergeStateStatus": "DIRTY", "reviewDecision": null,
+                    "headRefName": "feature", "headRefOid": "abc",
+                    "reviewRequests": { "nodes": [] },
+                    "commits": { "nodes": [] },
+                });
+                (200, serde_json::json!({ "data": { "repository": { "pullRequest": pr } } }).to_string())
+            }
+            ("POST", "/graphql") => {
+                let file = |p: &str| serde_json::json!({ "path": p, "additions": 1, "deletions": 0, "changeType": "MODIFIED" });
+                let files = serde_json::json!([file("a.rs"), file("b.rs"), file("c.rs")]).to_string();
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            ("GET", "/repos/o/r/compare/abc...main") => {
+                let commit = |sha: &str, login: &str| serde_json::json!({
+                    "sha": sha, "author": { "login": login },
+                    "commit": { "message": "m", "author": { "name": login, "date": "2024-01-01T00:00:00Z" } },
+                });
+                let out = serde_json::json!({
+                    "merge_base_commit": { "sha": "base0" },
+                    "commits": [commit("c1", "alice"), commit("c2", "bob")],
+                    "files": [{ "filename": "a.rs" }, { "filename": "b.rs" }, { "filename": "d.rs" }],
+                });
+                (200, out.to_string())
+            }
+            ("GET", "/repos/o/r/commits/c2") => {
+                let files = serde_json::json!([{ "filename": "a.rs" }, { "filename": "b.rs" }]);
+                (200, serde_json::json!({ "sha": "c2", "commit": { "message": "m" }, "author": null, "files": files }).to_string())
+            }
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
 
-pub async fn pr_review(
-    client: &github::Client,
-    repo: &str,
-    number: u64,
-    comments_file: &str,
-) -> Result<()> {
-    let pr = client.get_pr_with_patches(repo, number).await?;
+        let err = pr_conflicts(&client, "o/r", 1, true).await.unwrap_err();
+        assert_eq!(err.to_string(), "PR #1 conflicts with main");
+        assert_eq!(server.hits(), 4, "c2 touches both overlapping paths, so c1 is never fetched");
+    }
 
-    let file_commentable: HashMap<String, Vec<u64>> = pr
-        .files
-        .iter()
-        .map(|f| {
-            let hunks = f.patch.as_deref().map(parse_patch).unwrap_or_default();
-            let cl = commentable_lines(&hunks);
-            (f.filename.clone(), cl)
-        })
-        .collect();
+    #[test]
+    fn test_comment_target_by_hunk() {
+        let file_hunks = HashMap::from([(
+            "src/auth.ts".to_string(),
+            parse_patch("@@ -1,2 +1,3 @@\n a\n+b\n+c\n@@ -10,2 +11,2 @@\n x\n-y\n+z"),
+        )]);
+        let ids = diff::hunk_ids("src/auth.ts", &file_hunks["src/auth.ts"]);
+        let index = vec![("src/auth.ts", ids.clone())];
+        let input = |json: serde_json::Value| -> CommentInput { serde_json::from_value(json).unwrap() };
 
-    let raw = std::fs::read_to_string(comments_file)
-        .with_context(|| format!("Failed to read {comments_file}"))?;
-    let input: ReviewInput = serde_json::from_str(&raw)
-        .with_context(|| format!("Failed to parse {comments_file}"))?;
+        let whole = input(serde_json::json!({ "hunk": ids[0], "body": "b" }));
+        assert_eq!(comment_target(&whole, &file_hunks, &index), Ok(("src/auth.ts".to_string(), 3, Some(2))));
 
-    let mut warnings = Vec::new();
-    let mut valid_comments = Vec::new();
+        let line = input(serde_json::json!({ "hunk": format!("[h:{}]", ids[1]), "line": 12, "body": "b" }));
+        assert_eq!(comment_target(&line, &file_hunks, &index), Ok(("src/auth.ts".to_string(), 12, None)));
 
-    for c in &input.comments {
-        if let Some(cl) = file_commentable.get(&c.path) {
-            if cl.contains(&c.line) {
-                valid_comments.push(ReviewCommentInput {
-                    path: c.path.clone(),
-                    line: c.line,
-                    body: c.body.clone(),
-                    start_line: c.start_line,
+        let outside = input(serde_json::json!({ "hunk": ids[1], "line": 2, "body": "b" }));
+        assert_eq!(
+            comment_target(&outside, &file_hunks, &index),
+            Err(Skipped::new(
+                "src/auth.ts",
+                Some(2),
+                SkipReason::BadTarget,
+                format!("SKIP: src/auth.ts:2 is outside hunk {}", ids[1])
+            ))
+        );
+        let wrong_file = input(serde_json::json!({ "hunk": ids[0], "path": "b.ts", "body": "b" }));
+        let err = comment_target(&wrong_file, &file_hunks, &index).unwrap_err();
+        assert!(err.message.contains("is in src/auth.ts, not b.ts"));
+        assert_eq!(err.path, "b.ts");
+        let neither = input(serde_json::json!({ "path": "src/auth.ts", "body": "b" }));
+        assert!(comment_target(&neither, &file_hunks, &index).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_files_across_pages_with_filters() {
+        let server = MockServer::start(|_, _, body| {
+            let node = |path: &str, change: &str| {
+                serde_json::json!({ "path": path, "additions": 1, "deletions": 0, "changeType": change })
+            };
+            if body.contains("after: $cursor") {
+                assert!(body.contains(r#""cursor":"c1""#), "{body}");
+                let files = serde_json::json!({
+                    "pageInfo": { "hasNextPage": false, "endCursor": null },
+                    "nodes": [node("src/b.rs", "RENAMED")],
                 });
-            } else {
-                warnings.push(format!(
-                    "SKIP: {}:{} is not a commentable line (not in diff)",
-                    c.path, c.line
-                ));
+                return (200, serde_json::json!({ "data": { "repository": { "pullRequest": { "files": files } } } }).to_string());
             }
-        } else {
-            warnings.push(format!(
-                "SKIP: {} is not a changed file in this PR",
-                c.path
-            ));
-        }
-    }
+            let mut pr: serde_json::Value = serde_json::from_str(&pr_response(1, "abc")).unwrap();
+            pr["data"]["repository"]["pullRequest"]["files"] = serde_json::json!({
+                "pageInfo": { "hasNextPage": true, "endCursor": "c1" },
+                "nodes": [node("package-lock.json", "MODIFIED"), node("src/a.rs", "ADDED"), node("src/c.rs", "MODIFIED")],
+            });
+            (200, pr.to_string())
+        });
+        let client = github::Client::for_test(&server.url, None);
+        let pr = client.get_pr("o/r", 1).await.unwrap();
+        assert_eq!(server.hits(), 2);
 
-    if !warnings.is_empty() {
-        eprintln!("⚠️  Validation warnings:");
-        for w in &warnings {
-            eprintln!("  {w}");
+        fn paths((files, skipped): (Vec<&github::PrFile>, usize)) -> (Vec<&str>, usize) {
+            (files.iter().map(|f| f.filename.as_str()).collect(), skipped)
         }
+        assert_eq!(paths(listed_files(&pr.files, false, &[])), (vec!["src/a.rs", "src/c.rs", "src/b.rs"], 1));
+        let statuses = ["added".to_string(), "renamed".to_string()];
+        assert_eq!(paths(listed_files(&pr.files, false, &statuses)), (vec!["src/a.rs", "src/b.rs"], 1));
+        assert_eq!(paths(listed_files(&pr.files, true, &["modified".to_string()])).0, ["package-lock.json", "src/c.rs"]);
     }
 
-    if valid_comments.is_empty() {
-        anyhow::bail!("No valid comments to post after validation");
+    #[tokio::test]
+    async fn test_draft_toggle() {
+        let server = MockServer::start(|_, _, body| {
+            if body.contains("convertPullRequestToDraft") {
+                assert!(body.contains(r#""pullRequestId":"PR_node""#), "{body}");
+                let data = serde_json::json!({ "result": { "pullRequest": { "isDraft": true } } });
+                (200, serde_json::json!({ "data": data }).to_string())
+            } else {
+                (200, pr_response(1, "abc"))
+            }
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        let err = pr_ready(&client, "o/r", 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "PR #1 is already ready for review");
+        assert_eq!(server.hits(), 1);
+
+        pr_draft(&client, "o/r", 1).await.unwrap();
+        assert_eq!(server.hits(), 3);
     }
 
-    let review = CreateReview {
-        commit_id: pr.head_sha,
-        event: "COMMENT".to_string(),
-        body: input.body,
-        comments: valid_comments,
-    };
+    #[tokio::test]
+    async fn test_label_add_remove() {
+        let server = MockServer::start(|method, path, body| match (method, path) {
+            ("POST", "/graphql") => (200, pr_response(1, "abc")),
+            ("GET", p) if !p.ends_with("&page=1") => (200, "[]".to_string()),
+            ("GET", _) => (
+                200,
+                r#"[{"name": "WIP", "color": "ededed"}, {"name": "area/ci tools", "color": "0e8a16"}]"#.to_string(),
+            ),
+            ("POST", "/repos/o/r/issues/1/labels") => {
+                assert_eq!(body, r#"{"labels":["needs-tests"]}"#);
+                (200, r#"[{"name": "WIP", "color": "ededed"}, {"name": "area/ci tools", "color": "0e8a16"}, {"name": "needs-tests", "color": "d73a4a"}]"#.to_string())
+            }
+            ("DELETE", "/repos/o/r/issues/1/labels/area%2Fci%20tools") => (200, "[]".to_string()),
+            _ => (404, format!("unexpected {method} {path}")),
+        });
+        let client = github::Client::for_test(&server.url, None);
 
-    let resp = client.create_review(repo, number, &review).await?;
+        let add = ["needs-tests".to_string(), "wip".to_string()];
+        let remove = ["area/ci tools".to_string(), "stale".to_string()];
+        pr_label(&client, "o/r", 1, &add, &remove, true, false).await.unwrap();
+        assert_eq!(server.hits(), 5, "PR state, two label pages, one POST for the new label, one DELETE");
+    }
 
-    let out = ReviewOut {
-        id: resp.id,
-        url: resp.html_url,
-    };
-    print_json(&out)
-}
+    #[test]
+    fn test_plan_labels() {
+        let label = |name: &str| github::Label {
+            name: name.to_string(),
+            color: "ffffff".to_string(),
+        };
+        let current = [label("bug"), label("Semver-Major")];
+        let names = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let plan = plan_labels(
+            &current,
+            &names(&["needs-tests", "BUG", "needs-tests"]),
+            &names(&["semver-major", "wip"]),
+        );
+        assert_eq!(
+            plan,
+            LabelPlan {
+                add: names(&["needs-tests"]),
+                remove: names(&["Semver-Major"]),
+                already_present: names(&["BUG"]),
+                already_absent: names(&["wip"]),
+            }
+        );
+    }
 
-pub async fn pr_suggest(
-    client: &github::Client,
-    repo: &str,
-    number: u64,
-    file: &str,
-    line_start: u64,
-    line_end: u64,
-    replacement: &str,
-) -> Result<()> {
-    let pr = client.get_pr(repo, number).await?;
+    #[test]
+    fn test_noise_segments_match_whole_directories() {
+        assert!(is_noise_file("services/api/node_modules/left-pad/index.js"));
+        assert!(is_noise_file("vendor/github.com/pkg/errors/errors.go"));
+        assert!(is_noise_file("libs/third_party/zlib/inflate.c"));
+        assert!(is_noise_file(".yarn/releases/yarn-4.0.2.cjs"));
+        // The word alone, or a file named like the directory, is not vendored code
+        assert!(!is_noise_file("src/vendor_adapter.rs"));
+        assert!(!is_noise_file("src/node_modules.rs"));
+        assert!(!is_noise_file("scripts/vendor"));
 
-    let body = format!("```suggestion\n{replacement}\n```");
+        let extra = ["generated/"];
+        assert_eq!(noise_dir("proto/generated/api.pb.go", extra.into_iter()), Some("generated"));
+        assert_eq!(noise_dir("src/generated_code.rs", extra.into_iter()), None);
 
-    let start_line = if line_start == line_end {
-        None
-    } else {
-        Some(line_start)
-    };
+        assert_eq!(noise_reason("web/package-lock.json"), Some(NoiseReason::FileName("package-lock.json")));
+        assert_eq!(noise_reason("dist/app.js").unwrap().to_string(), "path prefix dist/");
+        assert_eq!(noise_reason("a/vendor/b.go").unwrap().to_string(), "directory vendor/");
+    }
 
-    let review = CreateReview {
-        commit_id: pr.head_sha,
-        event: "COMMENT".to_string(),
-        body: "Suggestion from gh-agent".to_string(),
-        comments: vec![ReviewCommentInput {
-            path: file.to_string(),
-            line: line_end,
-            body,
-            start_line,
-        }],
-    };
+    #[test]
+    fn test_auto_noise_demotes_bulk_generated_files() {
+        let lines: Vec<String> = (0..1500).map(|i| format!("+  \"item-{i}\": {{ \"id\": {i} }},")).collect();
+        let file = |path: &str, additions: u64, patch: String| github::PrFile {
+            filename: path.to_string(),
+            status: "modified".to_string(),
+            additions,
+            patch: Some(patch),
+            ..Default::default()
+        };
+        let fixture = file("test/fixtures/items.json", 1500, lines.join("\n"));
+        let small = file("src/lib.rs", 2, "+fn a() {}\n+fn b() {}".to_string());
 
-    let resp = client.create_review(repo, number, &review).await?;
-    let out = ReviewOut {
-        id: resp.id,
-        url: resp.html_url,
-    };
-    print_json(&out)
-}
+        assert_eq!(file_noise_reason(&fixture, false), None);
+        assert_eq!(file_noise_reason(&fixture, true).unwrap().to_string(), "heuristic:bulk-generated");
+        assert_eq!(file_noise_reason(&small, true), None);
 
-/// Extract a text keyword from an ast-grep pattern for pre-filtering via code search.
-/// Takes everything before the first meta-variable ($) or opening paren with $.
-/// Falls back to the whole pattern if no good keyword found.
-fn extract_search_keyword(pattern: &str) -> &str {
-    let end = pattern.find('$').unwrap_or(pattern.len());
-    let keyword = pattern[..end].trim().trim_end_matches('(');
-    if keyword.is_empty() {
-        pattern.split_whitespace().next().unwrap_or(pattern)
-    } else {
-        keyword
+        let files = [fixture, small];
+        let (selected, noise) = select_files(&files, &FileFilter::default(), None, false, true);
+        assert_eq!((selected.len(), noise), (1, 1));
+        assert_eq!(select_files(&files, &FileFilter::default(), None, true, true).0.len(), 2);
     }
-}
 
-pub async fn pr_grep(
-    client: &github::Client,
-    repo: &str,
-    number: u64,
-    pattern: &str,
-    file_filters: &[String],
-    repo_wide: bool,
-    path_prefix: Option<&str>,
-    use_base: bool,
-    case_sensitive: bool,
-    context_lines: usize,
-    include_all: bool,
-) -> Result<()> {
-    let pr = client.get_pr(repo, number).await?;
-    let git_ref = if use_base { &pr.base_ref } else { &pr.head_ref };
+    #[test]
+    fn test_latest_review_per_user() {
+        let review = |id: u64, author: &str, state: &str| github::Review {
+            id,
+            author: author.to_string(),
+            state: state.to_string(),
+            submitted_at: None,
+            body: String::new(),
+            html_url: String::new(),
+            commit_id: None,
+        };
+        let reviews = [
+            review(1, "alice", "CHANGES_REQUESTED"),
+            review(2, "bob", "COMMENTED"),
+            review(3, "alice", "APPROVED"),
+            review(4, "alice", "COMMENTED"),
+            review(5, "carol", "APPROVED"),
+            review(6, "carol", "DISMISSED"),
+            review(7, "bob", "COMMENTED"),
+            review(8, "dave", "PENDING"),
+        ];
+        let ids: Vec<u64> = latest_review_per_user(&reviews).iter().map(|r| r.id).collect();
+        assert_eq!(ids, [3, 6, 7]);
+    }
+
+    #[tokio::test]
+    async fn test_dismiss_mine_picks_latest_changes_requested() {
+        let review = |id: u64, login: &str, state: &str| serde_json::json!({
+            "id": id, "user": { "login": login }, "state": state, "body": "", "html_url": format!("u{id}"),
+        });
+        let dismissed = Arc::new(Mutex::new(Vec::new()));
+        let seen = dismissed.clone();
+        let server = MockServer::start(move |method, path, body| match (method, path) {
+            ("POST", "/graphql") => (200, pr_response(1, "abc")),
+            ("GET", "/user") => (200, r#"{"login":"ci-bot"}"#.to_string()),
+            ("GET", p) if p.starts_with("/repos/o/r/pulls/1/reviews") && p.ends_with("&page=1") => {
+                let all = [
+                    review(2, "ci-bot", "CHANGES_REQUESTED"),
+                    review(3, "alice", "CHANGES_REQUESTED"),
+                    review(5, "CI-Bot", "CHANGES_REQUESTED"),
+                    review(6, "ci-bot", "COMMENTED"),
+                ];
+                (200, serde_json::Value::from_iter(all).to_string())
+            }
+            ("GET", p) if p.starts_with("/repos/o/r/pulls/1/reviews") => (200, "[]".to_string()),
+            ("PUT", p) if p.ends_with("/dismissals") => {
+                let req: serde_json::Value = serde_json::from_str(body).unwrap();
+                assert_eq!(req, serde_json::json!({ "message": "Fixed in abc", "event": "DISMISS" }));
+                seen.lock().unwrap().push(p.to_string());
+                (200, review(5, "ci-bot", "DISMISSED").to_string())
+            }
+            _ => (404, "{}".to_string()),
+        });
+        let client = github::Client::for_test(&server.url, None);
+
+        pr_dismiss_review(&client, "o/r", 1, None, true, "Fixed in abc", false).await.unwrap();
+        pr_dismiss_review(&client, "o/r", 1, Some(3), false, "Fixed in abc", false).await.unwrap();
+        assert_eq!(
+            *dismissed.lock().unwrap(),
+            ["/repos/o/r/pulls/1/reviews/5/dismissals", "/repos/o/r/pulls/1/reviews/3/dismissals"]
+        );
 
-    // Always search PR changed files at correct ref
-    let mut pr_file_paths: Vec<String> = pr.files.iter().map(|f| f.filename.clone()).collect();
-    if !file_filters.is_empty() {
-        pr_file_paths.retain(|p| file_filters.iter().any(|f| p.contains(f.as_str())));
+        let err = pr_dismiss_review(&client, "o/r", 1, Some(3), false, " ", false).await.unwrap_err();
+        assert_eq!(err.to_string(), "A dismissal message is required");
+        let reviews: Vec<github::Review> = vec![];
+        assert_eq!(latest_changes_requested(&reviews, "ci-bot"), None);
     }
-    if !include_all {
-        pr_file_paths.retain(|p| !is_noise_file(p));
+
+    #[test]
+    fn test_invalid_comment_indices() {
+        let comments = [comment("a.rs", 1), comment("b.rs", 2)];
+        let body = r#"{"errors":[{"field":"comments[1].line","message":"must be part of the diff"}]}"#;
+        assert_eq!(invalid_comment_indices(body, &comments), [(1, "comments[1].line: must be part of the diff".to_string())]);
+        assert!(invalid_comment_indices(r#"{"errors":["Something else"]}"#, &comments).is_empty());
+        assert!(invalid_comment_indices("not json", &comments).is_empty());
     }
 
-    eprintln!("Fetching {} PR files at {}...", pr_file_paths.len(), git_ref);
-    let pr_files = fetch_file_contents(client, repo, &pr_file_paths, git_ref).await;
-    let mut pr_matches = search::grep_files(&pr_files, pattern, case_sensitive, context_lines);
+    #[test]
+    fn test_review_marker_is_stable_and_content_derived() {
+        let review = |body: &str| CreateReview {
+            commit_id: "abc".to_string(),
+            event: "COMMENT".to_string(),
+            body: body.to_string(),
+            comments: vec![comment("a.rs", 1)],
+        };
+        assert_eq!(review_marker(&review("x")), review_marker(&review("x")));
+        assert_ne!(review_marker(&review("x")), review_marker(&review("y")));
+        assert!(review_marker(&review("x")).starts_with("<!-- gh-agent-review:"));
+    }
 
-    if repo_wide {
-        // Search the broader codebase via GitHub Code Search (default branch)
-        eprintln!("Searching codebase via GitHub Code Search...");
-        let search_results = client.search_code(repo, pattern, path_prefix).await?;
-        eprintln!("Code Search: {} results from default branch", search_results.total_count);
+    /// src/auth.rs at the head of `secret_pr_server`'s PR; `fn check_token` is on lines 9-13
+    const SECRET_SOURCE: &str =
+        "use crate::vault::SECRET;\n\n\n\n\n\n\n\nfn check_token(t: &str) -> bool {\n    let a = 1;\n    ct_eq(t, SECRET)\n    // end\n}\n";
 
-        // Convert code search results to SearchMatch, but skip files already in PR
-        let pr_file_set: std::collections::HashSet<&str> = pr_file_paths.iter().map(|s| s.as_str()).collect();
+    /// A review comment on `secret_pr_server`'s change, suggesting more code
+    const SECRET_COMMENT: &str = "Compare in constant time:\n```suggestion\n    ct_eq(t, SECRET) && check_token_len(t)\n```";
 
-        for item in &search_results.items {
-            if pr_file_set.contains(item.path.as_str()) {
-                continue; // PR version takes priority
+    /// A mock PR whose one change is inside `fn check_token` in src/auth.rs, with a
+    /// suggestion and a review quoting code, for checking that no JSON output names
+    /// the code under --redact-content
+    fn secret_pr_server() -> MockServer {
+        MockServer::start(|_, path, _| match path {
+            // At the head sha, or the head branch for searches
+            "/repos/o/r/contents/src/auth.rs?ref=abc" | "/repos/o/r/contents/src/auth.rs?ref=feature" => {
+                (200, contents_response(SECRET_SOURCE))
             }
-            if !include_all && is_noise_file(&item.path) {
-                continue;
+            "/repos/o/r/pulls/1/comments?per_page=100&page=1" => {
+                let comment = serde_json::json!({
+                    "id": 5, "path": "src/auth.rs", "line": 11, "original_line": 11, "commit_id": "abc",
+                    "user": { "login": "bob" }, "created_at": "2024-05-01T10:00:00Z", "body": SECRET_COMMENT,
+                });
+                (200, serde_json::json!([comment]).to_string())
             }
-            if let Some(text_matches) = &item.text_matches {
-                for tm in text_matches {
-                    for (line_idx, line) in tm.fragment.lines().enumerate() {
-                        let haystack = if case_sensitive { line.to_string() } else { line.to_lowercase() };
-                        let pat = if case_sensitive { pattern.to_string() } else { pattern.to_lowercase() };
-                        if haystack.contains(&pat) {
-                            pr_matches.push(search::SearchMatch {
-                                file: item.path.clone(),
-                                line: line_idx + 1,
-                                column: haystack.find(&pat).unwrap_or(0) + 1,
-                                text: line.to_string(),
-                                context_before: vec![],
-                                context_after: vec![],
-                            });
-                        }
-                    }
-                }
+            "/repos/o/r/pulls/1/reviews?per_page=100&page=1" => {
+                let review = serde_json::json!({
+                    "id": 7, "user": { "login": "bob" }, "state": "COMMENTED", "submitted_at": "2024-05-01T10:00:00Z",
+                    "html_url": "u", "commit_id": "abc", "body": "Was:\n```rust\n    t == SECRET\n```",
+                });
+                (200, serde_json::json!([review]).to_string())
             }
-        }
+            "/repos/o/r/pulls/1/commits?per_page=100&page=1" => {
+                let commit = |sha: &str| serde_json::json!({
+                    "sha": sha, "commit": { "message": "m", "author": { "name": "alice", "date": "2024-01-01T00:00:00Z" } },
+                });
+                (200, serde_json::json!([commit("c1"), commit("c2")]).to_string())
+            }
+            p if p.ends_with("&page=2") => (200, "[]".to_string()),
+            p if p.starts_with("/repos/o/r/commits/") => {
+                let files = serde_json::json!([{ "filename": "src/auth.rs", "status": "modified", "additions": 1, "deletions": 1 }]);
+                (200, serde_json::json!({ "sha": "x", "commit": { "message": "m" }, "files": files }).to_string())
+            }
+            "/repos/o/r/compare/c1...c2" => {
+                let patch = "@@ -10,2 +10,2 @@ fn check_token(t: &str) -> bool {\n     let a = 1;\n-    t == SECRET\n+    ct_eq(t, SECRET)";
+                let files = serde_json::json!([{ "filename": "src/auth.rs", "status": "modified", "additions": 1, "deletions": 1, "patch": patch }]);
+                (200, serde_json::json!({ "merge_base_commit": { "sha": "c1" }, "commits": [], "files": files }).to_string())
+            }
+            "/graphql" => {
+                let files = r#"[{"path":"src/auth.rs","additions":1,"deletions":1,"changeType":"MODIFIED"}]"#;
+                (200, pr_response(1, "abc").replace(r#""nodes":[]"#, &format!(r#""nodes":{files}"#)))
+            }
+            "/repos/o/r/pulls/1" => (
+                200,
+                "diff --git a/src/auth.rs b/src/auth.rs\n--- a/src/auth.rs\n+++ b/src/auth.rs\n\
+                 @@ -10,3 +10,3 @@ fn check_token(t: &str) -> bool {\n     let a = 1;\n-    t == SECRET\n+    ct_eq(t, SECRET)\n     // end\n"
+                    .to_string(),
+            ),
+            _ => (404, "{}".to_string()),
+        })
     }
 
-    println!("{}", search::format_matches(&pr_matches));
-    Ok(())
-}
+    fn assert_no_code(out: &str) {
+        let code = ["check_token", "SECRET", "ct_eq", "let a = 1"];
+        assert!(code.iter().all(|c| !out.contains(c)), "code leaked: {out}");
+    }
 
-pub async fn pr_ast_grep(
-    client: &github::Client,
-    repo: &str,
-    number: u64,
-    pattern: &str,
-    file_filters: &[String],
-    repo_wide: bool,
-    path_prefix: Option<&str>,
-    use_base: bool,
-    lang_override: Option<&str>,
-    include_all: bool,
-) -> Result<()> {
-    let pr = client.get_pr(repo, number).await?;
-    let git_ref = if use_base { &pr.base_ref } else { &pr.head_ref };
+    fn assert_redacted(out: &str) {
+        assert_no_code(out);
+        assert!(out.contains("fnv1a") && out.contains("src/auth.rs"), "{out}");
+    }
 
-    let lang: Option<ast_grep_language::SupportLang> = lang_override
-        .map(|l| l.parse())
-        .transpose()
-        .map_err(|e: ast_grep_language::SupportLangErr| anyhow::anyhow!("{e}"))
-        .context("Invalid language. Use: ts, tsx, js, jsx, py, rs, go, java, etc.")?;
+    #[tokio::test]
+    async fn test_redacted_json_carries_no_code() {
+        let server = secret_pr_server();
+        let client = github::Client::for_test(&server.url, None);
+        redact::enable_for_test();
+        let dir = std::env::temp_dir().join(format!("gh-agent-redact-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let comments = dir.join("comments.json");
+        let body = SECRET_COMMENT.replace("Compare", "Also compare");
+        let comment = serde_json::json!([{ "path": "src/auth.rs", "line": 11, "body": body }]);
+        std::fs::write(&comments, comment.to_string()).unwrap();
+        let comments = comments.to_str().unwrap();
+        let patch = dir.join("out.patch");
+        let patch = patch.to_str().unwrap();
+        let review = |dry_run, emit_patch| ReviewOptions {
+            comments_file: comments,
+            chunk_size: 50,
+            max_comment_chars: MAX_BODY_CHARS,
+            dry_run,
+            emit_patch,
+            json: true,
+            ..Default::default()
+        };
 
-    // Collect PR changed file paths
-    let mut pr_file_paths: Vec<String> = pr.files.iter().map(|f| f.filename.clone()).collect();
-    if !file_filters.is_empty() {
-        pr_file_paths.retain(|p| file_filters.iter().any(|f| p.contains(f.as_str())));
-    }
-    if !include_all {
-        pr_file_paths.retain(|p| !is_noise_file(p));
+        let outputs = [
+            pager::capture(pr_file(&client, "o/r", 1, "src/auth.rs", false, None)).await,
+            pager::capture(pr_file_history(&client, "o/r", 1, "src/auth.rs", Some("1..2"), true)).await,
+            pager::capture(pr_comments(&client, "o/r", 1, &[], false, true)).await,
+            pager::capture(pr_reviews(&client, "o/r", 1, false, true)).await,
+            pager::capture(pr_review(&client, "o/r", 1, review(true, None))).await,
+        ];
+        for (out, result) in outputs {
+            result.unwrap();
+            assert_no_code(&out);
+            assert!(out.contains("fnv1a"), "nothing redacted: {out}");
+        }
+
+        // --emit-patch names the file it wrote, which keeps the code
+        let (out, result) = pager::capture(pr_review(&client, "o/r", 1, review(false, Some(patch)))).await;
+        let written = std::fs::read_to_string(patch);
+        std::fs::remove_dir_all(&dir).ok();
+        result.unwrap();
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out["path"], patch, "{out}");
+        assert!(written.unwrap().contains("check_token_len"));
     }
 
-    let mut all_file_paths = pr_file_paths.clone();
+    #[tokio::test]
+    async fn test_diff_json_redacts_hunk_context() {
+        let server = secret_pr_server();
+        let client = github::Client::for_test(&server.url, None);
+        redact::enable_for_test();
 
-    if repo_wide {
-        // Use text keyword from AST pattern to pre-filter via Code Search
-        let keyword = extract_search_keyword(pattern);
-        eprintln!("Searching codebase for '{}' via GitHub Code Search...", keyword);
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, json: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert_redacted(&out);
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(out["hunks"]["src/auth.rs"][0]["new_start"], 10);
+    }
 
-        let search_results = client.search_code(repo, keyword, path_prefix).await?;
-        eprintln!("Code Search: {} candidate files from default branch", search_results.total_count);
+    #[tokio::test]
+    async fn test_diff_json_full_redacts_hunk_headers() {
+        let server = secret_pr_server();
+        let client = github::Client::for_test(&server.url, None);
+        redact::enable_for_test();
 
-        let pr_file_set: std::collections::HashSet<String> = pr_file_paths.iter().cloned().collect();
+        let run = pr_diff(&client, "o/r", 1, DiffOptions { include_all: true, json_full: true, ..Default::default() });
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert_redacted(&out);
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let hunk = &out["files"][0]["hunks"][0];
+        assert_eq!((hunk["new_start"].as_u64(), hunk["lines"][1]["kind"].as_str()), (Some(10), Some("delete")));
+    }
 
-        for item in &search_results.items {
-            if !pr_file_set.contains(&item.path) {
-                if include_all || !is_noise_file(&item.path) {
-                    all_file_paths.push(item.path.clone());
-                }
-            }
-        }
+    #[tokio::test]
+    async fn test_grep_text_is_not_redacted() {
+        let server = secret_pr_server();
+        let client = github::Client::for_test(&server.url, None);
+        redact::enable_for_test();
 
-        // Dedup
-        all_file_paths.sort();
-        all_file_paths.dedup();
+        // pr grep prints no JSON, and text output is left as it is
+        let options = GrepOptions { pattern: "SECRET", case_sensitive: true, context_lines: 0, limit: None };
+        let (out, result) = pager::capture(pr_grep(&client, "o/r", 1, &SearchFiles::default(), options)).await;
+        result.unwrap();
+        assert!(out.contains("src/auth.rs:11") && out.contains("ct_eq(t, SECRET)"), "{out}");
+        assert!(!out.contains("fnv1a"), "{out}");
     }
 
-    if all_file_paths.is_empty() {
-        println!("No files to search.");
-        return Ok(());
+    #[tokio::test]
+    async fn test_ast_grep_json_redacts_matches() {
+        redact::enable_for_test();
+        // What `pr ast-grep --json --rewrite` prints for a match in `secret_pr_server`'s file
+        let m = search::SearchMatch {
+            file: "src/auth.rs".to_string(),
+            line: 3,
+            column: 5,
+            text: "ct_eq(t, SECRET)".to_string(),
+            lead: "    ".to_string(),
+            context_before: vec!["fn check_token(t: &str) -> bool {".to_string()],
+            context_after: vec![],
+            rewrite: Some(search::Rewrite {
+                span: 0..16,
+                text: "ct_eq(SECRET, t)".to_string(),
+            }),
+            highlight: None,
+        };
+        let (out, result) = pager::capture(async { print_json(&[AstMatchJson::from(&m)]) }).await;
+        result.unwrap();
+        assert_redacted(&out);
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!((out[0]["line"].as_u64(), out[0]["text"]["len"].as_u64()), (Some(3), Some(16)));
     }
 
-    eprintln!("Fetching {} files at {}...", all_file_paths.len(), git_ref);
-    let files = fetch_file_contents(client, repo, &all_file_paths, git_ref).await;
+    #[tokio::test]
+    async fn test_suggest_preview_json_redacts_the_diff() {
+        let server = secret_pr_server();
+        let client = github::Client::for_test(&server.url, None);
+        redact::enable_for_test();
 
-    if files.is_empty() {
-        println!("No readable files found.");
-        return Ok(());
+        let run = pr_suggest_preview(&client, "o/r", 1, suggestion("src/auth.rs", 11, 11, "    t == SECRET"), false, true);
+        let (out, result) = pager::capture(run).await;
+        result.unwrap();
+        assert!(!out.contains("check_token") && !out.contains("SECRET"), "code leaked: {out}");
+        let out: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert!(["before", "after", "diff"].iter().all(|key| out[key]["fnv1a"].is_string()), "{out}");
     }
 
-    let matches = search::ast_grep_files(&files, pattern, lang)?;
-    println!("{}", search::format_matches(&matches));
-    Ok(())
-}
-
-/// Fetch file contents concurrently, skipping failures silently
-async fn fetch_file_contents(
-    client: &github::Client,
-    repo: &str,
-    paths: &[String],
-    git_ref: &str,
-) -> Vec<(String, String)> {
-    let futs: Vec<_> = paths
-        .iter()
-        .map(|path| {
-            let path = path.clone();
-            let repo = repo.to_string();
-            let git_ref = git_ref.to_string();
+    #[tokio::test]
+    async fn test_pr_each_keeps_input_order_and_isolates_errors() {
+        let in_flight = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        // The first PRs take longest, so they finish last
+        let jobs: Vec<(u64, u64)> = vec![(10, 40), (11, 30), (12, 20), (13, 10), (14, 0)];
+        let (out, result) = pager::capture(pr_each(jobs, 2, |delay| {
+            let (in_flight, peak) = (&in_flight, &peak);
             async move {
-                match client.get_file_content(&repo, &path, &git_ref).await {
-                    Ok(content) => Some((path, content)),
-                    Err(_) => None, // skip binary/too-large/404
+                peak.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                if delay == 20 {
+                    anyhow::bail!("PR gone");
                 }
+                outln!("{}", serde_json::json!({ "delay": delay }));
+                if delay == 0 {
+                    outln!("not json");
+                }
+                Ok(())
             }
-        })
-        .collect();
+        }))
+        .await;
 
-    futures::future::join_all(futs)
-        .await
-        .into_iter()
-        .flatten()
-        .collect()
+        assert_eq!(result.unwrap_err().to_string(), "1 of 5 PRs failed");
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        let lines: Vec<serde_json::Value> = out.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        let numbers: Vec<u64> = lines.iter().map(|l| l["number"].as_u64().unwrap()).collect();
+        assert_eq!(numbers, [10, 11, 12, 13, 14]);
+        assert_eq!(lines[0]["output"]["delay"], 40);
+        assert_eq!(lines[2]["ok"], false);
+        assert_eq!(lines[2]["error"], "PR gone");
+        assert!(lines[2].get("output").is_none());
+        assert_eq!(lines[3]["ok"], true);
+        assert_eq!(lines[4]["output"], "{\"delay\":0}\nnot json");
+    }
+
+    #[test]
+    fn test_parse_pr_numbers() {
+        assert_eq!(parse_pr_numbers("12\n15, 20\n#21\n").unwrap(), [12, 15, 20, 21]);
+        assert_eq!(parse_pr_numbers("12 abc").unwrap_err().to_string(), "Not a PR number: abc");
+        assert!(parse_pr_numbers("\n").is_err());
+    }
 }
@@ -0,0 +1,540 @@
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command, CommandFactory};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use crate::cli::Cli;
+use crate::owners::Precedence;
+
+/// On-disk config file (`~/.config/gh-agent/config.toml`)
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default, skip_serializing_if = "CoreConfig::is_empty")]
+    pub core: CoreConfig,
+    #[serde(default, skip_serializing_if = "NoiseConfig::is_empty")]
+    pub noise: NoiseConfig,
+    #[serde(default, skip_serializing_if = "PriorityConfig::is_empty")]
+    pub priority: PriorityConfig,
+    #[serde(default, skip_serializing_if = "OwnersConfig::is_empty")]
+    pub owners: OwnersConfig,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub repo: BTreeMap<String, RepoConfig>,
+}
+
+/// Extra noise rules on top of the built-in ones, stored under `[noise]`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NoiseConfig {
+    /// Directory names whose contents are noise at any depth (e.g. "generated")
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<String>,
+}
+
+impl NoiseConfig {
+    fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+}
+
+/// `pr priorities` scoring, stored under `[priority]`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PriorityConfig {
+    /// Factor name -> points per unit, overriding the built-in weights
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub weights: BTreeMap<String, f64>,
+    /// Extra path substrings that mark a file as sensitive
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensitive: Vec<String>,
+}
+
+impl PriorityConfig {
+    fn is_empty(&self) -> bool {
+        self.weights.is_empty() && self.sensitive.is_empty()
+    }
+}
+
+/// Per-directory OWNERS files alongside CODEOWNERS, stored under `[owners]`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OwnersConfig {
+    /// Read OWNERS files from each changed file's directories at the PR's base
+    /// commit, never the head, so a PR can't make itself its own owner
+    #[serde(default)]
+    pub enabled: bool,
+    /// Who wins when both name owners for a path: "codeowners" (default), "owners" or "union"
+    #[serde(default)]
+    pub precedence: Precedence,
+}
+
+impl OwnersConfig {
+    fn is_empty(&self) -> bool {
+        !self.enabled && self.precedence == Precedence::default()
+    }
+}
+
+/// Settings that apply to every repo, stored under `[core]`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CoreConfig {
+    /// Pager for text output (overrides $PAGER); "" or "cat" disables paging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pager: Option<String>,
+}
+
+impl CoreConfig {
+    fn is_empty(&self) -> bool {
+        self.pager.is_none()
+    }
+}
+
+/// Per-repo settings, stored under `[repo."owner/repo"]`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RepoConfig {
+    /// `pr` subcommand name -> flag long name -> value
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub defaults: BTreeMap<String, BTreeMap<String, toml::Value>>,
+}
+
+/// Config file location: $GH_AGENT_CONFIG, else $XDG_CONFIG_HOME/gh-agent/config.toml,
+/// else ~/.config/gh-agent/config.toml
+pub fn config_path() -> Result<PathBuf> {
+    if let Some(path) = std::env::var_os("GH_AGENT_CONFIG") {
+        return Ok(PathBuf::from(path));
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .context("Cannot locate config directory: set HOME or XDG_CONFIG_HOME")?;
+    Ok(base.join("gh-agent").join("config.toml"))
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Set by `use_cache_dir_for_test`, so tests never touch the user's cache
+    static CACHE_DIR_HERE: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Cache directory: $XDG_CACHE_HOME/gh-agent, else ~/.cache/gh-agent
+pub fn cache_dir() -> Result<PathBuf> {
+    #[cfg(test)]
+    if let Some(dir) = CACHE_DIR_HERE.with_borrow(|dir| dir.clone()) {
+        return Ok(dir);
+    }
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .context("Cannot locate cache directory: set HOME or XDG_CACHE_HOME")?;
+    Ok(base.join("gh-agent"))
+}
+
+/// `owner/repo` as a single path component for the cache: `owner__repo`, with
+/// anything but ASCII letters, digits, `-`, `_` and `.` turned into `_`, so no
+/// repo argument can reach outside its cache directory
+pub fn repo_cache_name(repo: &str) -> String {
+    let safe = |part: &str| {
+        let part: String = part
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+            .collect();
+        if part.chars().all(|c| c == '.') {
+            part.replace('.', "_")
+        } else {
+            part
+        }
+    };
+    repo.split('/').map(safe).collect::<Vec<_>>().join("__")
+}
+
+/// Use a fresh directory under the temp dir as the cache for the rest of the
+/// current test
+#[cfg(test)]
+pub fn use_cache_dir_for_test(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gh-agent-cache-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    CACHE_DIR_HERE.set(Some(dir.clone()));
+    dir
+}
+
+impl Config {
+    /// Load the config file, or an empty config if it doesn't exist yet
+    pub fn load() -> Result<Self> {
+        let path = config_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let raw = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&raw).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = config_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(&path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn get(&self, repo: &str, key: &str) -> Option<&toml::Value> {
+        let (command, flag) = key.split_once('.')?;
+        self.repo.get(repo)?.defaults.get(command)?.get(flag)
+    }
+
+    pub fn set(&mut self, repo: &str, key: &str, value: toml::Value) {
+        let (command, flag) = key.split_once('.').unwrap_or((key, ""));
+        self.repo
+            .entry(repo.to_string())
+            .or_default()
+            .defaults
+            .entry(command.to_string())
+            .or_default()
+            .insert(flag.to_string(), value);
+    }
+
+    /// Remove a key, pruning tables left empty. Returns whether anything was removed.
+    pub fn unset(&mut self, repo: &str, key: &str) -> bool {
+        let Some((command, flag)) = key.split_once('.') else {
+            return false;
+        };
+        let Some(repo_cfg) = self.repo.get_mut(repo) else {
+            return false;
+        };
+        let Some(flags) = repo_cfg.defaults.get_mut(command) else {
+            return false;
+        };
+        let removed = flags.remove(flag).is_some();
+        if flags.is_empty() {
+            repo_cfg.defaults.remove(command);
+        }
+        if repo_cfg.defaults.is_empty() {
+            self.repo.remove(repo);
+        }
+        removed
+    }
+
+    /// All entries as (repo, "command.flag", value), sorted by repo then key
+    pub fn entries(&self) -> Vec<(&str, String, &toml::Value)> {
+        let mut out = Vec::new();
+        for (repo, repo_cfg) in &self.repo {
+            for (command, flags) in &repo_cfg.defaults {
+                for (flag, value) in flags {
+                    out.push((repo.as_str(), format!("{command}.{flag}"), value));
+                }
+            }
+        }
+        out
+    }
+}
+
+// --- Key validation ---
+
+/// Normalize a user-supplied key (`diff.smart_files`, `diff.--all`) to `command.flag-name`
+/// and check it names a real flag of a `pr` subcommand.
+pub fn normalize_key(key: &str) -> Result<String> {
+    let cli = Cli::command();
+    let (command, arg) = resolve_key(&cli, key)?;
+    Ok(format!("{}.{}", command.get_name(), arg.get_long().unwrap_or_default()))
+}
+
+fn resolve_key<'a>(cli: &'a Command, key: &str) -> Result<(&'a Command, &'a Arg)> {
+    let (command, flag) = key
+        .split_once('.')
+        .ok_or_else(|| anyhow::anyhow!("Key must be <command>.<flag> (e.g. diff.all), got: {key}"))?;
+    let flag = flag.trim_start_matches('-').replace('_', "-");
+
+    let pr = cli
+        .find_subcommand("pr")
+        .expect("pr subcommand is always defined");
+    let sub = pr
+        .find_subcommand(command)
+        .ok_or_else(|| anyhow::anyhow!("Unknown command '{command}' in key {key}"))?;
+    let arg = sub
+        .get_arguments()
+        .find(|a| a.get_long() == Some(flag.as_str()) && !a.is_hide_set())
+        .ok_or_else(|| anyhow::anyhow!("Unknown flag '--{flag}' for `pr {command}`"))?;
+    if arg.get_id() == "repo" {
+        anyhow::bail!("--repo cannot have a default");
+    }
+    Ok((sub, arg))
+}
+
+/// Parse a `config set` value into the TOML type the flag expects, validating it
+/// with the flag's own clap value parser.
+pub fn parse_value(key: &str, raw: &str) -> Result<toml::Value> {
+    let cli = Cli::command();
+    let (command, arg) = resolve_key(&cli, key)?;
+    let long = arg.get_long().unwrap_or_default();
+
+    if is_switch(arg) {
+        return match raw {
+            "true" => Ok(toml::Value::Boolean(true)),
+            "false" => Ok(toml::Value::Boolean(false)),
+            _ => anyhow::bail!("`pr {} --{long}` is a switch; value must be true or false", command.get_name()),
+        };
+    }
+
+    Command::new("probe")
+        .no_binary_name(true)
+        .arg(arg.clone().required(false))
+        .try_get_matches_from([format!("--{long}"), raw.to_string()])
+        .map_err(|e| {
+            let msg = e.to_string();
+            let first = msg.lines().next().unwrap_or_default();
+            anyhow::anyhow!("{key}: {}", first.trim_start_matches("error: "))
+        })?;
+
+    Ok(match raw.parse::<i64>() {
+        Ok(n) => toml::Value::Integer(n),
+        Err(_) => toml::Value::String(raw.to_string()),
+    })
+}
+
+fn is_switch(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::SetTrue)
+}
+
+// --- Flag merging ---
+
+/// The CLI definition plus a hidden `--no-<flag>` for every switch under `pr`,
+/// so a repo default of `true` can be turned off for a single invocation.
+pub fn command() -> Command {
+    Cli::command().mut_subcommand("pr", |pr| {
+        let names: Vec<String> = pr
+            .get_subcommands()
+            .map(|s| s.get_name().to_string())
+            .collect();
+        names.iter().fold(pr, |pr, name| {
+            pr.mut_subcommand(name, |sub| {
+                let negations: Vec<Arg> = sub
+                    .get_arguments()
+                    .filter(|a| is_switch(a))
+                    .filter_map(|a| {
+                        let long = a.get_long()?;
+                        Some(
+                            Arg::new(negation_id(a))
+                                .long(format!("no-{long}"))
+                                .action(ArgAction::SetTrue)
+                                .hide(true)
+                                .overrides_with(a.get_id().clone()),
+                        )
+                    })
+                    .collect();
+                sub.args(negations)
+            })
+        })
+    })
+}
+
+fn negation_id(arg: &Arg) -> String {
+    format!("no_{}", arg.get_id())
+}
+
+/// Whether argv runs a `config` subcommand
+pub fn is_config_command(cmd: &Command, argv: &[OsString]) -> bool {
+    cmd.clone()
+        .try_get_matches_from(argv)
+        .is_ok_and(|matches| matches.subcommand_name() == Some("config"))
+}
+
+/// Add the targeted repo's defaults for the invoked `pr` subcommand to argv, at the
+/// end or before a `--` (after which they'd be read as positional arguments).
+///
+/// A default is skipped when its flag (or its `--no-` negation) was given explicitly
+/// on the command line, or when it conflicts with a flag that was (or with a default
+/// added before it), so `--json-full` quietly wins over a `stat` default. Argv that
+/// fails to parse is returned untouched so clap can report the error against what
+/// the user actually typed.
+pub fn apply_repo_defaults(cmd: &Command, config: &Config, argv: Vec<OsString>) -> Vec<OsString> {
+    let Ok(matches) = cmd.clone().try_get_matches_from(&argv) else {
+        return argv;
+    };
+    let Some(("pr", pr_matches)) = matches.subcommand() else {
+        return argv;
+    };
+    let Some((name, sub_matches)) = pr_matches.subcommand() else {
+        return argv;
+    };
+    let Some(repo) = sub_matches.get_one::<String>("repo") else {
+        return argv;
+    };
+    let Some(defaults) = config
+        .repo
+        .get(repo)
+        .and_then(|r| r.defaults.get(name))
+    else {
+        return argv;
+    };
+    let sub = cmd
+        .find_subcommand("pr")
+        .and_then(|pr| pr.find_subcommand(name))
+        .expect("matched subcommand is defined");
+
+    let mut added: Vec<OsString> = Vec::new();
+    let mut taken: Vec<&Arg> = sub
+        .get_arguments()
+        .filter(|a| explicitly_set(sub_matches, a.get_id().as_str()))
+        .collect();
+    for (flag, value) in defaults {
+        let Some(arg) = sub.get_arguments().find(|a| a.get_long() == Some(flag.as_str())) else {
+            continue;
+        };
+        if explicitly_set(sub_matches, arg.get_id().as_str())
+            || explicitly_set(sub_matches, &negation_id(arg))
+            || taken.iter().any(|t| conflict(sub, arg, t))
+        {
+            continue;
+        }
+        if is_switch(arg) {
+            if value.as_bool() == Some(true) {
+                added.push(format!("--{flag}").into());
+                taken.push(arg);
+            }
+            continue;
+        }
+        taken.push(arg);
+        let values = match value {
+            toml::Value::Array(items) => items.iter().map(value_to_arg).collect(),
+            other => vec![value_to_arg(other)],
+        };
+        for v in values {
+            added.push(format!("--{flag}").into());
+            added.push(v.into());
+        }
+    }
+    let mut argv = argv;
+    let at = argv.iter().position(|a| a == "--").unwrap_or(argv.len());
+    argv.splice(at..at, added);
+    argv
+}
+
+fn explicitly_set(matches: &ArgMatches, id: &str) -> bool {
+    // Not every flag has a `--no-` negation to look up
+    matches.try_contains_id(id).unwrap_or(false) && matches!(matches.value_source(id), Some(ValueSource::CommandLine))
+}
+
+/// Whether clap rejects `a` and `b` together; either side may declare it
+fn conflict(sub: &Command, a: &Arg, b: &Arg) -> bool {
+    sub.get_arg_conflicts_with(a).iter().any(|c| c.get_id() == b.get_id())
+        || sub.get_arg_conflicts_with(b).iter().any(|c| c.get_id() == a.get_id())
+}
+
+fn value_to_arg(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn config_with(repo: &str, key: &str, value: toml::Value) -> Config {
+        let mut config = Config::default();
+        config.set(repo, key, value);
+        config
+    }
+
+    #[test]
+    fn test_default_applied_when_flag_absent() {
+        let config = config_with("o/r", "view.smart", toml::Value::Boolean(true));
+        let out = apply_repo_defaults(&command(), &config, argv(&["gh-agent", "pr", "view", "-r", "o/r", "1"]));
+        assert_eq!(out, argv(&["gh-agent", "pr", "view", "-r", "o/r", "1", "--smart"]));
+    }
+
+    #[test]
+    fn test_default_goes_before_double_dash() {
+        let config = config_with("o/r", "view.smart", toml::Value::Boolean(true));
+        let out = apply_repo_defaults(&command(), &config, argv(&["gh-agent", "pr", "view", "-r", "o/r", "--", "1"]));
+        assert_eq!(out, argv(&["gh-agent", "pr", "view", "-r", "o/r", "--smart", "--", "1"]));
+
+        let config = config_with("o/r", "each.concurrency", toml::Value::Integer(2));
+        let out = apply_repo_defaults(&command(), &config, argv(&["gh-agent", "pr", "each", "-r", "o/r", "--", "view", "--json"]));
+        assert_eq!(
+            out,
+            argv(&["gh-agent", "pr", "each", "-r", "o/r", "--concurrency", "2", "--", "view", "--json"])
+        );
+    }
+
+    #[test]
+    fn test_repo_cache_name() {
+        assert_eq!(repo_cache_name("Org/my-repo.rs"), "Org__my-repo.rs");
+        assert_eq!(repo_cache_name("../.."), "______");
+        assert_eq!(repo_cache_name("o/r/../../x"), "o__r__________x");
+        assert_eq!(repo_cache_name("o\\r"), "o_r");
+    }
+
+    #[test]
+    fn test_is_config_command() {
+        assert!(is_config_command(&command(), &argv(&["gh-agent", "--verbose", "config", "list"])));
+        assert!(!is_config_command(&command(), &argv(&["gh-agent", "pr", "view", "-r", "o/r", "1"])));
+    }
+
+    #[test]
+    fn test_explicit_value_wins() {
+        let config = config_with("o/r", "grep.context", toml::Value::Integer(3));
+        let input = argv(&["gh-agent", "pr", "grep", "-r", "o/r", "1", "-p", "x", "-C", "5"]);
+        assert_eq!(apply_repo_defaults(&command(), &config, input.clone()), input);
+    }
+
+    #[test]
+    fn test_conflicting_default_yields_to_explicit_flag() {
+        let mut config = config_with("o/r", "diff.stat", toml::Value::Boolean(true));
+        config.set("o/r", "diff.min-move-lines", toml::Value::Integer(8));
+        let input = argv(&["gh-agent", "pr", "diff", "-r", "o/r", "1", "--json-full"]);
+        let out = apply_repo_defaults(&command(), &config, input.clone());
+        assert_eq!(out, argv(&["gh-agent", "pr", "diff", "-r", "o/r", "1", "--json-full", "--min-move-lines", "8"]));
+        assert!(command().try_get_matches_from(out).is_ok());
+
+        // Declared on the other side: --json-full names --stat, not the reverse
+        let config = config_with("o/r", "diff.json-full", toml::Value::Boolean(true));
+        let input = argv(&["gh-agent", "pr", "diff", "-r", "o/r", "1", "--stat"]);
+        assert_eq!(apply_repo_defaults(&command(), &config, input.clone()), input);
+    }
+
+    #[test]
+    fn test_negation_suppresses_default() {
+        let config = config_with("o/r", "view.smart", toml::Value::Boolean(true));
+        let input = argv(&["gh-agent", "pr", "view", "-r", "o/r", "1", "--no-smart"]);
+        let out = apply_repo_defaults(&command(), &config, input.clone());
+        assert_eq!(out, input);
+
+        let matches = command().get_matches_from(out);
+        let (_, pr) = matches.subcommand().unwrap();
+        let (_, view) = pr.subcommand().unwrap();
+        assert!(!view.get_flag("smart"));
+    }
+
+    #[test]
+    fn test_other_repo_and_command_untouched() {
+        let config = config_with("o/r", "diff.all", toml::Value::Boolean(true));
+        let other_repo = argv(&["gh-agent", "pr", "diff", "-r", "o/other", "1"]);
+        assert_eq!(apply_repo_defaults(&command(), &config, other_repo.clone()), other_repo);
+        let other_cmd = argv(&["gh-agent", "pr", "view", "-r", "o/r", "1"]);
+        assert_eq!(apply_repo_defaults(&command(), &config, other_cmd.clone()), other_cmd);
+    }
+
+    #[test]
+    fn test_key_validation() {
+        assert_eq!(normalize_key("diff.smart_files").unwrap(), "diff.smart-files");
+        assert!(normalize_key("diff.nope").is_err());
+        assert!(normalize_key("diff.repo").is_err());
+        assert!(parse_value("view.smart", "yes").is_err());
+        assert!(parse_value("grep.context", "abc").is_err());
+        assert_eq!(parse_value("grep.context", "2").unwrap(), toml::Value::Integer(2));
+    }
+
+    #[test]
+    fn test_unset_prunes_empty_tables() {
+        let mut config = config_with("o/r", "diff.all", toml::Value::Boolean(true));
+        assert!(config.unset("o/r", "diff.all"));
+        assert!(config.repo.is_empty());
+        assert!(!config.unset("o/r", "diff.all"));
+    }
+}
@@ -1,4 +1,7 @@
+use anyhow::Result;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::Range;
 
 /// A single line in a parsed diff hunk
 #[derive(Debug, Serialize, Clone)]
@@ -23,6 +26,9 @@ pub struct DiffHunk {
     pub new_start: u64,
     pub new_count: u64,
     pub header: String,
+    /// What follows the `@@ ... @@` marker: usually the enclosing function, as git
+    /// finds it. Empty when the header has none.
+    pub context: String,
     pub lines: Vec<DiffLine>,
 }
 
@@ -49,6 +55,7 @@ pub fn parse_patch(patch: &str) -> Vec<DiffHunk> {
                 new_start: ns,
                 new_count: nc,
                 header: raw_line.to_string(),
+                context: hunk_header_context(raw_line).to_string(),
                 lines: Vec::new(),
             });
             continue;
@@ -107,6 +114,361 @@ pub fn commentable_lines(hunks: &[DiffHunk]) -> Vec<u64> {
         .collect()
 }
 
+/// Each hunk's commentable lines as an inclusive (first, last) range
+pub fn commentable_ranges(hunks: &[DiffHunk]) -> Vec<(u64, u64)> {
+    hunks
+        .iter()
+        .filter_map(|h| {
+            let mut lines = h.lines.iter().filter(|l| l.commentable).filter_map(|l| l.new_line);
+            let first = lines.next()?;
+            Some((first, lines.next_back().unwrap_or(first)))
+        })
+        .collect()
+}
+
+/// Stable short ids for a file's hunks, e.g. `auth-7f3a`: the file stem plus a
+/// hash of (path, old start, first added line). The same head always yields the
+/// same ids, and an id changes when its hunk's first added line does. Ids that
+/// collide within the file get `-2`, `-3`, ... in hunk order.
+pub fn hunk_ids(path: &str, hunks: &[DiffHunk]) -> Vec<String> {
+    let stem = id_stem(path);
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    hunks
+        .iter()
+        .map(|h| {
+            let first_added = h.lines.iter().find(|l| l.kind == "add").map_or("", |l| l.content.as_str());
+            let hash = fnv1a(format!("{path}\0{}\0{first_added}", h.old_start).as_bytes());
+            let id = format!("{stem}-{:04x}", (hash ^ (hash >> 16)) & 0xffff);
+            let n = seen.entry(id.clone()).or_insert(0);
+            *n += 1;
+            if *n == 1 {
+                id
+            } else {
+                format!("{id}-{n}")
+            }
+        })
+        .collect()
+}
+
+/// Parse `patch` and pair each hunk with its id, keeping only the ids in `only`
+/// (every hunk when it's empty)
+pub fn identified_hunks(path: &str, patch: &str, only: &[String]) -> Vec<(String, DiffHunk)> {
+    let hunks = parse_patch(patch);
+    hunk_ids(path, &hunks)
+        .into_iter()
+        .zip(hunks)
+        .filter(|(id, _)| only.is_empty() || only.contains(id))
+        .collect()
+}
+
+/// Find which file's hunk `id` names, as (path, hunk index). Accepts the
+/// `[h:...]` form printed in diff headers.
+pub fn resolve_hunk<'a>(id: &str, files: impl IntoIterator<Item = (&'a str, &'a [String])>) -> Result<(&'a str, usize)> {
+    let id = id.trim().trim_start_matches("[h:").trim_end_matches(']').to_lowercase();
+    let found: Vec<(&str, usize)> = files
+        .into_iter()
+        .filter_map(|(path, ids)| Some((path, ids.iter().position(|i| *i == id)?)))
+        .collect();
+    match found.as_slice() {
+        [hit] => Ok(*hit),
+        [] => anyhow::bail!(
+            "Unknown hunk id {id}; run `pr diff` for the current ids (an id changes when its hunk's first added line does)"
+        ),
+        hits => {
+            let paths: Vec<&str> = hits.iter().map(|(path, _)| *path).collect();
+            anyhow::bail!("Hunk id {id} is ambiguous; it matches hunks in {}", paths.join(", "))
+        }
+    }
+}
+
+/// Lines a comment on a whole hunk should cover, as (start_line, line): its
+/// added lines, or its last commentable line when it only deletes
+pub fn hunk_comment_range(hunk: &DiffHunk) -> Option<(Option<u64>, u64)> {
+    let added: Vec<u64> = hunk.lines.iter().filter(|l| l.kind == "add").filter_map(|l| l.new_line).collect();
+    match added.as_slice() {
+        [] => hunk.lines.iter().rev().filter(|l| l.commentable).find_map(|l| l.new_line).map(|l| (None, l)),
+        [only] => Some((None, *only)),
+        [first, .., last] => Some((Some(*first), *last)),
+    }
+}
+
+/// Where a multi-line comment on new lines `start..=end` leaves the hunk holding
+/// `end`: that hunk's first line, when `start` comes before it. GitHub rejects a
+/// range spanning hunks. None when the range fits in one hunk, or `end` isn't in
+/// any.
+pub fn range_hunk_boundary(hunks: &[DiffHunk], start: u64, end: u64) -> Option<u64> {
+    let lines = |h: &'_ DiffHunk| h.lines.iter().filter(|l| l.commentable).filter_map(|l| l.new_line).collect::<Vec<_>>();
+    let first = hunks.iter().map(lines).find(|lines| lines.contains(&end))?[0];
+    (start < first).then_some(first)
+}
+
+/// Base of the rolling hash over line hashes in `detect_moves`
+const ROLL_BASE: u64 = 0x0000_0100_0000_01b3;
+
+/// A run of deleted lines added back elsewhere in the same file, unchanged
+/// apart from whitespace
+#[derive(Debug, PartialEq)]
+pub struct MovedBlock {
+    /// (hunk, line) index of the deleted copy's first line
+    pub from: (usize, usize),
+    /// (hunk, line) index of the added copy's first line
+    pub to: (usize, usize),
+    pub len: usize,
+    /// Old-file lines of the deleted copy, inclusive
+    pub old_lines: (u64, u64),
+    /// New-file lines of the added copy, inclusive
+    pub new_lines: (u64, u64),
+}
+
+/// The deleted or added lines of a file in order, with prefix hashes for
+/// O(1) window hashes. Lines adjacent in the same hunk share a run; lines of
+/// the same hunk with no context line between them share a change group.
+struct Side {
+    pos: Vec<(usize, usize)>,
+    run: Vec<usize>,
+    group: Vec<(usize, usize)>,
+    text: Vec<String>,
+    prefix: Vec<u64>,
+}
+
+impl Side {
+    fn new(hunks: &[DiffHunk], kind: &str) -> Self {
+        let mut side = Side { pos: vec![], run: vec![], group: vec![], text: vec![], prefix: vec![0] };
+        let mut run = 0;
+        for (h, hunk) in hunks.iter().enumerate() {
+            let mut group = 0;
+            for (j, line) in hunk.lines.iter().enumerate() {
+                if line.kind == "context" {
+                    group += 1;
+                }
+                if line.kind != kind {
+                    continue;
+                }
+                if side.pos.last().is_some_and(|&(ph, pj)| ph != h || pj + 1 != j) {
+                    run += 1;
+                }
+                let text = line.content.split_whitespace().collect::<Vec<_>>().join(" ");
+                let hash = u64::from(fnv1a(text.as_bytes()));
+                side.prefix.push(side.prefix[side.prefix.len() - 1].wrapping_mul(ROLL_BASE).wrapping_add(hash));
+                side.pos.push((h, j));
+                side.run.push(run);
+                side.group.push((h, group));
+                side.text.push(text);
+            }
+        }
+        side
+    }
+
+    fn len(&self) -> usize {
+        self.pos.len()
+    }
+
+    /// Hash of the `k` lines from `i`, or None when they span two runs
+    fn window(&self, i: usize, k: usize, base_pow: u64) -> Option<u64> {
+        (i + k <= self.len() && self.run[i] == self.run[i + k - 1])
+            .then(|| self.prefix[i + k].wrapping_sub(self.prefix[i].wrapping_mul(base_pow)))
+    }
+}
+
+/// Find added runs of at least `min_lines` lines that repeat a deleted run
+/// elsewhere in the file, comparing lines with whitespace collapsed. A run
+/// deleted and added back in the same change group, such as a block
+/// re-indented in place, stays where it was and isn't a move. Added lines
+/// are scanned in order and paired with the earliest unused deleted copy, so
+/// repeated identical blocks pair up one to one. `min_lines` 0 turns detection off.
+pub fn detect_moves(hunks: &[DiffHunk], min_lines: usize) -> Vec<MovedBlock> {
+    if min_lines == 0 {
+        return vec![];
+    }
+    let (del, add) = (Side::new(hunks, "delete"), Side::new(hunks, "add"));
+    let k = min_lines;
+    let base_pow = (0..k).fold(1u64, |p, _| p.wrapping_mul(ROLL_BASE));
+
+    let mut windows: HashMap<u64, Vec<usize>> = HashMap::new();
+    for i in 0..del.len() {
+        if let Some(hash) = del.window(i, k, base_pow) {
+            windows.entry(hash).or_default().push(i);
+        }
+    }
+
+    let mut used = vec![false; del.len()];
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < add.len() {
+        let found = add.window(i, k, base_pow).and_then(|hash| {
+            windows.get(&hash)?.iter().copied().find(|&d| {
+                del.group[d] != add.group[i]
+                    && (d..d + k).all(|x| !used[x])
+                    && (0..k).all(|o| del.text[d + o] == add.text[i + o])
+            })
+        });
+        let Some(d) = found else {
+            i += 1;
+            continue;
+        };
+        let mut len = k;
+        while i + len < add.len()
+            && d + len < del.len()
+            && add.run[i + len] == add.run[i]
+            && del.run[d + len] == del.run[d]
+            && !used[d + len]
+            && add.text[i + len] == del.text[d + len]
+        {
+            len += 1;
+        }
+        used[d..d + len].fill(true);
+
+        let line = |(h, j): (usize, usize)| &hunks[h].lines[j];
+        blocks.push(MovedBlock {
+            from: del.pos[d],
+            to: add.pos[i],
+            len,
+            old_lines: (
+                line(del.pos[d]).old_line.unwrap_or(0),
+                line(del.pos[d + len - 1]).old_line.unwrap_or(0),
+            ),
+            new_lines: (
+                line(add.pos[i]).new_line.unwrap_or(0),
+                line(add.pos[i + len - 1]).new_line.unwrap_or(0),
+            ),
+        });
+        i += len;
+    }
+    blocks
+}
+
+/// Lowercased file name without extension, reduced to [a-z0-9-]
+fn id_stem(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let stem = match name.rfind('.') {
+        Some(dot) if dot > 0 => &name[..dot],
+        _ => name,
+    };
+    let slug: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        "hunk".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Hash of a commit's change as (path, patch) pairs that ignores line numbers
+/// and context, so a revert hashes like the `inverted` original: each hunk
+/// contributes its removed lines, then its added lines, with the two swapped
+/// when `inverted`. None when a patch is missing or nothing changed.
+pub fn change_fingerprint(files: &[(String, Option<String>)], inverted: bool) -> Option<u64> {
+    let mut sorted: Vec<&(String, Option<String>)> = files.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut canonical = String::new();
+    for (path, patch) in sorted {
+        canonical.push_str(path);
+        canonical.push('\0');
+        for hunk in patch.as_deref()?.split("\n@@").filter(|h| !h.trim().is_empty()) {
+            let body = hunk.lines().skip(1);
+            let (mut removed, mut added) = (Vec::new(), Vec::new());
+            for line in body {
+                match line.as_bytes().first() {
+                    Some(b'-') => removed.push(line[1..].trim_end()),
+                    Some(b'+') => added.push(line[1..].trim_end()),
+                    _ => {}
+                }
+            }
+            if inverted {
+                std::mem::swap(&mut removed, &mut added);
+            }
+            for (sign, lines) in [('-', removed), ('+', added)] {
+                for l in lines {
+                    canonical.push(sign);
+                    canonical.push_str(l);
+                    canonical.push('\n');
+                }
+            }
+            canonical.push('\x01');
+        }
+    }
+    if !canonical.contains(['-', '+']) {
+        return None;
+    }
+    Some(fnv1a_64(canonical.as_bytes()))
+}
+
+/// Pair each commit with the latest earlier unpaired commit it exactly undoes,
+/// from (forward, inverted) fingerprints in commit order. Returns (original, revert).
+pub fn pair_reverts(fingerprints: &[Option<(u64, u64)>]) -> Vec<(usize, usize)> {
+    let mut paired = vec![false; fingerprints.len()];
+    let mut pairs = Vec::new();
+    for (j, fp) in fingerprints.iter().enumerate() {
+        let Some((forward, _)) = fp else {
+            continue;
+        };
+        let original = (0..j)
+            .rev()
+            .find(|&i| !paired[i] && fingerprints[i].is_some_and(|(_, inverse)| inverse == *forward));
+        if let Some(i) = original {
+            paired[i] = true;
+            paired[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs
+}
+
+/// 32-bit FNV-1a: unlike std's hasher, fixed across Rust releases, which ids
+/// shown to users and pasted back later depend on
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, b| (hash ^ u32::from(*b)).wrapping_mul(0x0100_0193))
+}
+
+/// 64-bit FNV-1a, for hashes that are stored or compared across runs: review
+/// markers, change fingerprints and redacted content
+pub fn fnv1a_64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// The text after a hunk header's closing `@@`
+fn hunk_header_context(header: &str) -> &str {
+    header
+        .strip_prefix("@@")
+        .and_then(|rest| rest.split_once("@@"))
+        .map_or("", |(_, context)| context.trim())
+}
+
+/// Words that open a function, for `--resolve-context`
+const FUNCTION_KEYWORDS: &[&str] = &["fn", "def", "function", "func"];
+
+/// The nearest line above new-file line `line` of `content` that opens a function
+/// (`fn`, `def`, `function` or `func`, after any modifiers such as `pub async`)
+pub fn enclosing_function(content: &str, line: u64) -> Option<String> {
+    let above = content.lines().take(line.saturating_sub(1) as usize);
+    above.filter(|l| opens_function(l)).last().map(|l| l.trim().to_string())
+}
+
+fn opens_function(line: &str) -> bool {
+    for word in line.split_whitespace() {
+        if FUNCTION_KEYWORDS.contains(&word) {
+            return true;
+        }
+        // Modifiers like `export`, `async` or `pub(crate)`; anything else isn't a declaration
+        let modifier = word.strip_suffix(")").and_then(|w| w.split_once('(')).map_or(word, |(w, _)| w);
+        if !modifier.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return false;
+        }
+    }
+    false
+}
+
+/// Fill in the context of hunks whose header has none from the enclosing function
+/// in `content`, the file they apply to
+pub fn resolve_contexts(hunks: &mut [DiffHunk], content: &str) {
+    for hunk in hunks.iter_mut().filter(|h| h.context.is_empty()) {
+        hunk.context = enclosing_function(content, hunk.new_start).unwrap_or_default();
+    }
+}
+
 fn parse_hunk_header(header: &str) -> (u64, u64, u64, u64) {
     let parts: Vec<&str> = header.split_whitespace().collect();
 
@@ -130,10 +492,492 @@ fn parse_range(s: &str) -> (u64, u64) {
     }
 }
 
+/// One changed run of lines between two versions of a file: old lines
+/// `old_start..old_start + old_count` (1-based) become `new_lines`. With
+/// `old_count` 0 the new lines go in before `old_start`.
+#[derive(Debug, PartialEq)]
+pub struct LineChange {
+    pub old_start: u64,
+    pub old_count: u64,
+    pub new_lines: Vec<String>,
+}
+
+/// Most old × new lines compared one by one; a bigger rewrite is a single change
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+/// The runs of lines that change `old` into `new`: lines in their longest common
+/// subsequence stay, the rest change. The common start and end are set aside first,
+/// so a local edit costs little however long the file is.
+pub fn line_changes(old: &str, new: &str) -> Vec<LineChange> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    changed_runs(&a, &b)
+        .into_iter()
+        .map(|(old, new)| LineChange {
+            old_start: (old.start + 1) as u64,
+            old_count: old.len() as u64,
+            new_lines: b[new].iter().map(|l| l.to_string()).collect(),
+        })
+        .collect()
+}
+
+/// The runs of lines that differ between `a` and `b`, as (old range, new range)
+/// pairs in order, outside their longest common subsequence
+fn changed_runs(a: &[&str], b: &[&str]) -> Vec<(Range<usize>, Range<usize>)> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let common = if a.len() * b.len() > MAX_LCS_CELLS { vec![] } else { common_lines(a, b) };
+
+    let mut runs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    // The end of both sides acts as a last common line
+    for (ci, cj) in common.into_iter().chain([(a.len(), b.len())]) {
+        if ci > i || cj > j {
+            runs.push((prefix + i..prefix + ci, prefix + j..prefix + cj));
+        }
+        (i, j) = (ci + 1, cj + 1);
+    }
+    runs
+}
+
+/// Lines of context around each change in `whitespace_insensitive_patch`
+const PATCH_CONTEXT: usize = 3;
+
+/// A line as `--ignore-whitespace` compares it: trimmed, with each inner run of
+/// spaces and tabs as one space
+fn normalize_whitespace(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The patch (hunks only, like GitHub's) from `old` to `new` with whitespace
+/// ignored, or "" when only whitespace changed. Lines that differ in indentation,
+/// spacing or tabs versus spaces are equal. Added or removed blank lines make no
+/// hunk of their own, but show inside one that a real change makes.
+pub fn whitespace_insensitive_patch(old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let na: Vec<String> = a.iter().map(|l| normalize_whitespace(l)).collect();
+    let nb: Vec<String> = b.iter().map(|l| normalize_whitespace(l)).collect();
+    let runs = changed_runs(
+        &na.iter().map(String::as_str).collect::<Vec<_>>(),
+        &nb.iter().map(String::as_str).collect::<Vec<_>>(),
+    );
+
+    // Every line as kept (' '), deleted or added, with the old and new line
+    // indexes at it, and whether a change other than blank lines makes it
+    let mut ops: Vec<(char, usize, usize, bool)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (old, new) in runs.into_iter().chain([(a.len()..a.len(), b.len()..b.len())]) {
+        while i < old.start {
+            ops.push((' ', i, j, false));
+            (i, j) = (i + 1, j + 1);
+        }
+        let real = !na[old.clone()].iter().chain(&nb[new.clone()]).all(String::is_empty);
+        ops.extend(old.clone().map(|k| ('-', k, new.start, real)));
+        ops.extend(new.clone().map(|k| ('+', old.end, k, real)));
+        (i, j) = (old.end, new.end);
+    }
+
+    // Distance from each line to the nearest real change, in lines
+    let mut near = vec![usize::MAX; ops.len()];
+    let mut last = None;
+    for (k, op) in ops.iter().enumerate() {
+        if op.3 {
+            last = Some(k);
+        }
+        near[k] = last.map_or(usize::MAX, |l| k - l);
+    }
+    last = None;
+    for (k, op) in ops.iter().enumerate().rev() {
+        if op.3 {
+            last = Some(k);
+        }
+        near[k] = near[k].min(last.map_or(usize::MAX, |l| l - k));
+    }
+
+    let mut hunks = Vec::new();
+    let mut k = 0;
+    while k < ops.len() {
+        if near[k] > PATCH_CONTEXT {
+            k += 1;
+            continue;
+        }
+        let end = (k..ops.len()).find(|&e| near[e] > PATCH_CONTEXT).unwrap_or(ops.len());
+        let hunk = &ops[k..end];
+        let old_count = hunk.iter().filter(|op| op.0 != '+').count();
+        let new_count = hunk.iter().filter(|op| op.0 != '-').count();
+        // A side with no lines starts at the line before, as in git
+        let start = |index: usize, count: usize| if count == 0 { index } else { index + 1 };
+        let mut lines = vec![format!(
+            "@@ -{},{old_count} +{},{new_count} @@",
+            start(hunk[0].1, old_count),
+            start(hunk[0].2, new_count)
+        )];
+        lines.extend(hunk.iter().map(|&(kind, i, j, _)| match kind {
+            '-' => format!("-{}", a[i]),
+            '+' => format!("+{}", b[j]),
+            _ => format!(" {}", b[j]),
+        }));
+        hunks.push(lines.join("\n"));
+        k = end;
+    }
+    hunks.join("\n")
+}
+
+/// Lines of context kept around the lines `pr diff --lines` asks for
+pub const LINES_CONTEXT: usize = 2;
+
+/// A span of new-file lines from `pr diff --lines`: `start` through `end`, or on
+/// to the end of the file when `end` is None
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl LineRange {
+    pub fn contains(&self, line: u64) -> bool {
+        line >= self.start && self.end.is_none_or(|end| line <= end)
+    }
+}
+
+impl std::fmt::Display for LineRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.end {
+            Some(end) if end == self.start => write!(f, "{end}"),
+            Some(end) => write!(f, "{}-{end}", self.start),
+            None => write!(f, "{}-", self.start),
+        }
+    }
+}
+
+/// `patch` cut down to its lines in one of `ranges` of the new file, plus
+/// `context` lines either side, as hunks with headers recounted to match, each
+/// with the index of the hunk it was cut from. A deleted line counts as being at
+/// the new line after it. Empty when no changed line is in range.
+pub fn trim_patch_to_lines(patch: &str, ranges: &[LineRange], context: usize) -> Vec<(usize, String)> {
+    let mut hunks = Vec::new();
+    let mut rest = patch.lines().peekable();
+    let mut index = 0;
+    while let Some(header) = rest.next() {
+        if !header.starts_with("@@") {
+            continue;
+        }
+        let source = index;
+        index += 1;
+        let (mut old, _, mut new, _) = parse_hunk_header(header);
+        let function = hunk_header_context(header);
+        // Every line with the old and new line numbers at it, and any
+        // "\ No newline at end of file" marker after it
+        let mut ops: Vec<(&str, u64, u64, Option<&str>)> = Vec::new();
+        while let Some(line) = rest.next_if(|l| !l.starts_with("@@")) {
+            if line.starts_with('\\') {
+                if let Some(op) = ops.last_mut() {
+                    op.3 = Some(line);
+                }
+                continue;
+            }
+            ops.push((line, old, new, None));
+            match line.as_bytes().first() {
+                Some(b'+') => new += 1,
+                Some(b'-') => old += 1,
+                _ => (old, new) = (old + 1, new + 1),
+            }
+        }
+
+        let wanted: Vec<usize> = (0..ops.len()).filter(|&k| ranges.iter().any(|r| r.contains(ops[k].2))).collect();
+        if !wanted.iter().any(|&k| matches!(ops[k].0.as_bytes().first(), Some(b'+' | b'-'))) {
+            continue;
+        }
+        let keep = |k: usize| wanted.iter().any(|&w| w.abs_diff(k) <= context);
+        let mut k = 0;
+        while k < ops.len() {
+            if !keep(k) {
+                k += 1;
+                continue;
+            }
+            let end = (k..ops.len()).find(|&e| !keep(e)).unwrap_or(ops.len());
+            let run = &ops[k..end];
+            let old_count = run.iter().filter(|op| !op.0.starts_with('+')).count();
+            let new_count = run.iter().filter(|op| !op.0.starts_with('-')).count();
+            // A side with no lines starts at the line before, as in git
+            let start = |line: u64, count: usize| if count == 0 { line.saturating_sub(1) } else { line };
+            let mut header = format!("@@ -{},{old_count} +{},{new_count} @@", start(run[0].1, old_count), start(run[0].2, new_count));
+            if !function.is_empty() {
+                header = format!("{header} {function}");
+            }
+            let mut lines = vec![header];
+            for &(line, _, _, marker) in run {
+                lines.push(line.to_string());
+                lines.extend(marker.map(str::to_string));
+            }
+            hunks.push((source, lines.join("\n")));
+            k = end;
+        }
+    }
+    hunks
+}
+
+/// Index pairs of a longest common subsequence of `a` and `b`, in order
+fn common_lines(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    // len[i * w + j]: LCS length of a[i..] and b[j..]
+    let w = b.len() + 1;
+    let mut len = vec![0u32; (a.len() + 1) * w];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            len[i * w + j] = if a[i] == b[j] {
+                len[(i + 1) * w + j + 1] + 1
+            } else {
+                len[(i + 1) * w + j].max(len[i * w + j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if len[(i + 1) * w + j] >= len[i * w + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// A piece of one side of a word diff, `changed` when the other side lacks it
+#[derive(Debug, PartialEq)]
+pub struct WordSegment {
+    pub text: String,
+    pub changed: bool,
+}
+
+/// Word-level diff of a deleted line against the added line that replaces it, as
+/// (old pieces, new pieces). A word is a run of letters, digits and `_`, a run of
+/// whitespace, or any other single character. Unchanged whitespace and punctuation
+/// between two changes joins them, so a rewritten line reads as one change.
+pub fn word_diff(old: &str, new: &str) -> (Vec<WordSegment>, Vec<WordSegment>) {
+    let a = words(old);
+    let b = words(new);
+    let common = if a.len() * b.len() > MAX_LCS_CELLS { vec![] } else { common_lines(&a, &b) };
+    let mut a_changed = vec![true; a.len()];
+    let mut b_changed = vec![true; b.len()];
+    for (i, j) in common {
+        a_changed[i] = false;
+        b_changed[j] = false;
+    }
+    (word_segments(&a, a_changed), word_segments(&b, b_changed))
+}
+
+fn words(line: &str) -> Vec<&str> {
+    let class = |c: char| if c.is_alphanumeric() || c == '_' { 0 } else if c.is_whitespace() { 1 } else { 2 };
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let joins = |next: Option<&(usize, char)>| next.is_some_and(|&(_, n)| class(c) != 2 && class(n) == class(c));
+        if !joins(chars.peek()) {
+            let end = i + c.len_utf8();
+            out.push(&line[start..end]);
+            start = end;
+        }
+    }
+    out
+}
+
+fn word_segments(words: &[&str], changed: Vec<bool>) -> Vec<WordSegment> {
+    let runs = merge_segments(words.iter().zip(changed).map(|(w, changed)| WordSegment {
+        text: w.to_string(),
+        changed,
+    }));
+    let joins = |k: usize| {
+        k > 0
+            && k + 1 < runs.len()
+            && runs[k - 1].changed
+            && runs[k + 1].changed
+            && !runs[k].text.chars().any(|c| c.is_alphanumeric() || c == '_')
+    };
+    let joined: Vec<bool> = (0..runs.len()).map(joins).collect();
+    merge_segments(runs.into_iter().zip(joined).map(|(mut run, joined)| {
+        run.changed |= joined;
+        run
+    }))
+}
+
+fn merge_segments(pieces: impl Iterator<Item = WordSegment>) -> Vec<WordSegment> {
+    let mut segments: Vec<WordSegment> = Vec::new();
+    for piece in pieces {
+        match segments.last_mut() {
+            Some(last) if last.changed == piece.changed => last.text.push_str(&piece.text),
+            _ => segments.push(piece),
+        }
+    }
+    segments
+}
+
+/// Index pairs (deleted line, added line) within `hunk` for a word diff: each run
+/// of deleted lines directly followed by added lines, paired in order. The lines
+/// one side has more of are left unpaired.
+pub fn replaced_line_pairs(hunk: &DiffHunk) -> Vec<(usize, usize)> {
+    let lines = &hunk.lines;
+    let mut pairs = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].kind != "delete" {
+            i += 1;
+            continue;
+        }
+        let deletes = i..i + lines[i..].iter().take_while(|l| l.kind == "delete").count();
+        let adds = deletes.end..deletes.end + lines[deletes.end..].iter().take_while(|l| l.kind == "add").count();
+        i = adds.end;
+        pairs.extend(deletes.zip(adds));
+    }
+    pairs
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn marked(segments: &[WordSegment]) -> String {
+        segments.iter().map(|s| if s.changed { format!("<{}>", s.text) } else { s.text.clone() }).collect()
+    }
+
+    #[test]
+    fn test_trim_patch_to_lines() {
+        let patch = "@@ -10,9 +10,10 @@ fn run() {\n a\n b\n c\n-d\n+D\n e\n f\n g\n+h\n i\n\\ No newline at end of file\n@@ -40,2 +41,3 @@\n x\n+y\n z";
+        let joined = |pieces: Vec<(usize, String)>| pieces.into_iter().map(|(_, h)| h).collect::<Vec<_>>().join("\n");
+        let only = |start, end| joined(trim_patch_to_lines(patch, &[LineRange { start, end }], LINES_CONTEXT));
+        assert_eq!(only(13, Some(13)), "@@ -11,5 +11,5 @@ fn run() {\n b\n c\n-d\n+D\n e\n f");
+        assert_eq!(only(17, None), "@@ -15,3 +15,4 @@ fn run() {\n f\n g\n+h\n i\n\\ No newline at end of file\n@@ -40,2 +41,3 @@\n x\n+y\n z");
+        // Context alone isn't worth a hunk
+        assert_eq!(only(10, Some(11)), "");
+        let both = trim_patch_to_lines(patch, &[LineRange { start: 13, end: Some(13) }, LineRange { start: 17, end: Some(17) }, LineRange { start: 42, end: Some(42) }], 0);
+        let sources: Vec<usize> = both.iter().map(|(i, _)| *i).collect();
+        assert_eq!(sources, [0, 0, 1], "each piece knows its hunk");
+        assert_eq!(joined(both), "@@ -13,1 +13,1 @@ fn run() {\n-d\n+D\n@@ -16,0 +17,1 @@ fn run() {\n+h\n@@ -40,0 +42,1 @@\n+y");
+        assert_eq!(LineRange { start: 480, end: Some(520) }.to_string(), "480-520");
+        assert_eq!(LineRange { start: 7, end: Some(7) }.to_string(), "7");
+        assert_eq!(LineRange { start: 7, end: None }.to_string(), "7-");
+    }
+
+    #[test]
+    fn test_fnv1a_known_values() {
+        assert_eq!(fnv1a(b""), 0x811c_9dc5);
+        assert_eq!(fnv1a(b"a"), 0xe40c_292c);
+        assert_eq!(fnv1a_64(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a_64(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_hunk_header_context() {
+        let hunks = parse_patch("@@ -10,2 +10,3 @@ fn connect(host: &str) -> Result<()> {\n a\n+b\n c\n@@ -40 +41 @@\n-x\n+y\n");
+        assert_eq!(hunks[0].context, "fn connect(host: &str) -> Result<()> {");
+        assert_eq!((hunks[0].new_start, hunks[0].lines.len()), (10, 3));
+        assert_eq!(hunks[1].context, "");
+        assert_eq!(hunk_header_context("@@ -1 +1 @@   "), "");
+    }
+
+    #[test]
+    fn test_resolve_contexts_from_the_file() {
+        let content = "use x;\n\npub(crate) async fn load(path: &Path) {\n    let f = open(path);\n    return fn_ptr(f);\n    f\n}\n";
+        let mut hunks = parse_patch("@@ -6 +6 @@\n-g\n+f\n@@ -1 +1 @@\n-use y;\n+use x;\n@@ -5 +5 @@ already named\n x\n");
+        resolve_contexts(&mut hunks, content);
+        // `return fn_ptr(f)` calls a function rather than opening one
+        assert_eq!(hunks[0].context, "pub(crate) async fn load(path: &Path) {");
+        assert_eq!(hunks[1].context, "");
+        assert_eq!(hunks[2].context, "already named");
+
+        assert_eq!(enclosing_function("def f(x):\n  return x\n", 2).as_deref(), Some("def f(x):"));
+        assert_eq!(enclosing_function("export default function App() {\n", 5).as_deref(), Some("export default function App() {"));
+        assert_eq!(enclosing_function("func (s *Server) Run() {\n", 2).as_deref(), Some("func (s *Server) Run() {"));
+    }
+
+    #[test]
+    fn test_word_diff() {
+        // One token
+        let (old, new) = word_diff("    connect(host, 8080, tls);", "    connect(host, 8443, tls);");
+        assert_eq!((marked(&old), marked(&new)), ("    connect(host, <8080>, tls);".into(), "    connect(host, <8443>, tls);".into()));
+
+        // A full rewrite is one change, past the shared indentation
+        let (old, new) = word_diff("  total += x", "  return fold(items)");
+        assert_eq!(marked(&old), "  <total += x>");
+        assert_eq!(marked(&new), "  <return fold(items)>");
+        // Unchanged punctuation between changes joins them too
+        let (old, _) = word_diff("let a = 1;", "let b = 2;");
+        assert_eq!(marked(&old), "let <a = 1>;");
+
+        let (old, new) = word_diff("same", "same");
+        assert_eq!((old.len(), new.len()), (1, 1));
+        assert!(!old[0].changed);
+        let (old, new) = word_diff("", "added");
+        assert_eq!((marked(&old), marked(&new)), ("".into(), "<added>".into()));
+    }
+
+    #[test]
+    fn test_replaced_line_pairs() {
+        // 3 deletes then 2 adds pair the first two; a lone add and a lone delete don't pair
+        let hunk = &parse_patch("@@ -1,6 +1,5 @@\n ctx\n-a\n-b\n-c\n+A\n+B\n ctx\n+new\n ctx\n-old\n")[0];
+        assert_eq!(replaced_line_pairs(hunk), [(1, 4), (2, 5)]);
+        // More adds than deletes
+        let hunk = &parse_patch("@@ -1,1 +1,3 @@\n-a\n+A\n+B\n+C\n")[0];
+        assert_eq!(replaced_line_pairs(hunk), [(0, 1)]);
+    }
+
+    #[test]
+    fn test_whitespace_insensitive_patch() {
+        let old = "fn f() {\n    let a = 1;\n    let b  =  2;\n\n    a + b\n}\n";
+        // Reindented with tabs, spacing fixed, a blank line added
+        let reformatted = "fn f() {\n\tlet a = 1;\n\tlet b = 2;\n\n\n\ta + b\n}\n";
+        assert_eq!(whitespace_insensitive_patch(old, reformatted), "");
+        assert_eq!(whitespace_insensitive_patch(old, "\n\nfn f() {\n  let a = 1;\n  let b = 2;\n  a + b\n}\n"), "");
+
+        // The real change shows, at head's indentation, with the blank line beside it
+        let changed = "fn f() {\n\tlet a = 1;\n\tlet b = 2;\n\n\n\ta - b\n}\n";
+        assert_eq!(
+            whitespace_insensitive_patch(old, changed),
+            "@@ -2,5 +2,6 @@\n \tlet a = 1;\n \tlet b = 2;\n \n-    a + b\n+\n+\ta - b\n }"
+        );
+
+        // Far apart changes get hunks of their own; a pure insertion counts from the line before
+        let old: String = (1..=20).map(|n| format!("{n}\n")).collect();
+        let new: String = (1..=20)
+            .map(|n| match n {
+                2 => "  2\nx\n".to_string(),
+                19 => "y\n".to_string(),
+                n => format!("{n}\n"),
+            })
+            .collect();
+        let patch = whitespace_insensitive_patch(&old, &new);
+        let headers: Vec<&str> = patch.lines().filter(|l| l.starts_with("@@")).collect();
+        assert_eq!(headers, ["@@ -1,5 +1,6 @@", "@@ -16,5 +17,5 @@"]);
+        let hunks = parse_patch(&patch);
+        assert_eq!(hunks[0].lines[2].content, "x");
+        assert_eq!(hunks[0].lines[2].new_line, Some(3));
+    }
+
+    #[test]
+    fn test_line_changes() {
+        let change = |old_start, old_count, new: &[&str]| LineChange {
+            old_start,
+            old_count,
+            new_lines: new.iter().map(|l| l.to_string()).collect(),
+        };
+        let old = "a\nb\nc\nd\ne\nf\n";
+        assert_eq!(line_changes(old, old), []);
+        assert_eq!(line_changes(old, "a\nB\nc\nd\ne\nf"), [change(2, 1, &["B"])]);
+        assert_eq!(
+            line_changes(old, "a\nc\nd\nx\ny\ne\nF\nG\n"),
+            [change(2, 1, &[]), change(5, 0, &["x", "y"]), change(6, 1, &["F", "G"])]
+        );
+        assert_eq!(line_changes(old, "z\na\nb\nc\nd\ne\nf\n"), [change(1, 0, &["z"])]);
+        assert_eq!(line_changes("", "a\n"), [change(1, 0, &["a"])]);
+    }
+
     #[test]
     fn test_parse_simple_patch() {
         let patch = "@@ -10,3 +10,4 @@ some context\n old line\n-removed\n+added1\n+added2\n unchanged";
@@ -168,4 +1012,165 @@ mod tests {
         let cl = commentable_lines(&hunks);
         assert_eq!(cl, vec![1, 2, 3]);
     }
+
+    #[test]
+    fn test_hunk_ids_are_stable() {
+        let patch = "@@ -1,2 +1,2 @@\n a\n-b\n+c\n@@ -20,1 +20,2 @@\n x\n+y";
+        let ids = hunk_ids("src/auth.ts", &parse_patch(patch));
+        assert_eq!(ids.len(), 2);
+        assert!(ids.iter().all(|id| id.starts_with("auth-") && id.len() == 9), "{ids:?}");
+        assert_ne!(ids[0], ids[1]);
+        // Re-parsing the same patch yields the same ids
+        assert_eq!(hunk_ids("src/auth.ts", &parse_patch(patch)), ids);
+        // Other hunks' edits don't move an id; editing its first added line does
+        let edited = hunk_ids("src/auth.ts", &parse_patch("@@ -1,2 +1,2 @@\n a\n-b\n+c2\n@@ -20,1 +20,2 @@\n x\n+y"));
+        assert_ne!(edited[0], ids[0]);
+        assert_eq!(edited[1], ids[1]);
+        // The same hunk in another file gets another id
+        assert_ne!(hunk_ids("lib/auth.ts", &parse_patch(patch))[1], ids[1]);
+        assert_eq!(hunk_ids(".gitignore", &parse_patch(patch))[0][..10], *"gitignore-");
+    }
+
+    #[test]
+    fn test_hunk_id_collisions_within_a_file() {
+        // Same old start and first added line: the hash collides
+        let patch = "@@ -5,1 +5,2 @@\n a\n+dup\n@@ -5,1 +9,2 @@\n b\n+dup\n@@ -5,1 +13,2 @@\n c\n+dup";
+        let ids = hunk_ids("a.rs", &parse_patch(patch));
+        assert_eq!(ids[1], format!("{}-2", ids[0]));
+        assert_eq!(ids[2], format!("{}-3", ids[0]));
+
+        let files = [("a.rs", ids.as_slice())];
+        assert_eq!(resolve_hunk(&ids[1], files).unwrap(), ("a.rs", 1));
+        assert_eq!(resolve_hunk(&format!("[h:{}]", ids[2].to_uppercase()), files).unwrap(), ("a.rs", 2));
+        assert!(resolve_hunk("a-0000", files).unwrap_err().to_string().contains("Unknown hunk id a-0000"));
+
+        let twice = [("a.rs", ids.as_slice()), ("b/a.rs", ids.as_slice())];
+        let err = resolve_hunk(&ids[0], twice).unwrap_err();
+        assert!(err.to_string().contains("matches hunks in a.rs, b/a.rs"), "{err}");
+    }
+
+    #[test]
+    fn test_hunk_comment_range() {
+        let hunks = parse_patch("@@ -1,3 +1,4 @@\n a\n+b\n+c\n d\n@@ -10,3 +11,2 @@\n x\n-y\n z");
+        assert_eq!(hunk_comment_range(&hunks[0]), Some((Some(2), 3)));
+        assert_eq!(hunk_comment_range(&hunks[1]), Some((None, 12)));
+    }
+
+    #[test]
+    fn test_range_hunk_boundary() {
+        // New lines 10-20 and 25-45
+        let hunks = parse_patch(&format!(
+            "@@ -10,11 +10,11 @@\n{}@@ -30,20 +25,21 @@\n{}+new\n",
+            " x\n".repeat(11),
+            " y\n".repeat(20)
+        ));
+        assert_eq!(range_hunk_boundary(&hunks, 10, 42), Some(25));
+        assert_eq!(range_hunk_boundary(&hunks, 18, 25), Some(25));
+        assert_eq!(range_hunk_boundary(&hunks, 25, 45), None);
+        assert_eq!(range_hunk_boundary(&hunks, 12, 20), None);
+        // `end` outside every hunk: nothing to say
+        assert_eq!(range_hunk_boundary(&hunks, 10, 22), None);
+    }
+
+    /// A patch deleting `deleted` at the top and adding `added` further down
+    fn move_patch(deleted: &[&str], added: &[&str]) -> String {
+        let del: String = deleted.iter().map(|l| format!("-{l}\n")).collect();
+        let add: String = added.iter().map(|l| format!("+{l}\n")).collect();
+        format!(
+            "@@ -1,{} +1,1 @@\n top\n{del}@@ -50,1 +40,{} @@\n anchor\n{add}",
+            deleted.len() + 1,
+            added.len() + 1
+        )
+    }
+
+    const BLOCK: &[&str] = &["fn a() {", "    one();", "    two();", "    three();", "}"];
+
+    #[test]
+    fn test_detect_moved_block() {
+        // Re-indented on the way: whitespace doesn't count
+        let added: Vec<String> = BLOCK.iter().map(|l| format!("    {l}")).collect();
+        let added: Vec<&str> = added.iter().map(String::as_str).collect();
+        let hunks = parse_patch(&move_patch(BLOCK, &added));
+        assert_eq!(
+            detect_moves(&hunks, 5),
+            [MovedBlock {
+                from: (0, 1),
+                to: (1, 1),
+                len: 5,
+                old_lines: (2, 6),
+                new_lines: (41, 45),
+            }]
+        );
+        assert!(detect_moves(&hunks, 6).is_empty());
+        assert!(detect_moves(&hunks, 0).is_empty());
+    }
+
+    #[test]
+    fn test_moved_block_with_an_edit_is_not_a_move() {
+        let mut edited = BLOCK.to_vec();
+        edited[2] = "    two(x);";
+        assert!(detect_moves(&parse_patch(&move_patch(BLOCK, &edited)), 5).is_empty());
+    }
+
+    #[test]
+    fn test_block_reindented_in_place_is_not_a_move() {
+        let del: String = BLOCK.iter().map(|l| format!("-{l}\n")).collect();
+        let add: String = BLOCK.iter().map(|l| format!("+    {l}\n")).collect();
+        let in_place = format!("@@ -1,7 +1,7 @@\n mod m {{\n{del}{add} }}\n");
+        assert!(detect_moves(&parse_patch(&in_place), 5).is_empty());
+
+        // The same lines with a context line between them did move
+        let moved = format!("@@ -1,7 +1,7 @@\n mod m {{\n{del} }}\n{add}");
+        let blocks = detect_moves(&parse_patch(&moved), 5);
+        assert_eq!(blocks.iter().map(|b| (b.old_lines, b.new_lines)).collect::<Vec<_>>(), [((2, 6), (3, 7))]);
+    }
+
+    #[test]
+    fn test_repeated_blocks_pair_one_to_one() {
+        let twice = [BLOCK, BLOCK].concat();
+        let thrice = [BLOCK, &["// gap"], BLOCK, &["// gap"], BLOCK].concat();
+        let blocks = detect_moves(&parse_patch(&move_patch(&twice, &thrice)), 5);
+        let pairs: Vec<_> = blocks.iter().map(|b| (b.old_lines, b.new_lines)).collect();
+        // Two deleted copies can account for only two of the three added ones
+        assert_eq!(pairs, [((2, 6), (41, 45)), ((7, 11), (47, 51))]);
+    }
+
+    fn commit(files: &[(&str, &str)]) -> Option<(u64, u64)> {
+        let files: Vec<(String, Option<String>)> =
+            files.iter().map(|(p, patch)| (p.to_string(), Some(patch.to_string()))).collect();
+        Some((change_fingerprint(&files, false)?, change_fingerprint(&files, true)?))
+    }
+
+    #[test]
+    fn test_exact_revert_pairs() {
+        let change = commit(&[
+            ("a.rs", "@@ -1,3 +1,3 @@\n x\n-old\n+new\n y\n@@ -40,2 +40,3 @@\n z\n+added"),
+            ("b.rs", "@@ -0,0 +1,1 @@\n+created"),
+        ]);
+        // A later revert: same hunks with the sides swapped, at shifted line numbers
+        let revert = commit(&[
+            ("b.rs", "@@ -1,1 +0,0 @@\n-created"),
+            ("a.rs", "@@ -2,3 +2,3 @@\n x\n-new\n+old\n y\n@@ -41,3 +41,2 @@\n z\n-added"),
+        ]);
+        let unrelated = commit(&[("c.rs", "@@ -1 +1 @@\n-a\n+b")]);
+        assert_eq!(pair_reverts(&[change, unrelated, revert]), [(0, 2)]);
+        // Nothing changed, or a missing patch: never paired
+        assert_eq!(change_fingerprint(&[("a.rs".to_string(), None)], false), None);
+        assert_eq!(change_fingerprint(&[("a.rs".to_string(), Some(String::new()))], false), None);
+    }
+
+    #[test]
+    fn test_partial_revert_does_not_pair() {
+        let change = commit(&[
+            ("a.rs", "@@ -1,3 +1,3 @@\n x\n-old\n+new\n y"),
+            ("b.rs", "@@ -0,0 +1,1 @@\n+created"),
+        ]);
+        let partial = commit(&[("a.rs", "@@ -1,3 +1,3 @@\n x\n-new\n+old\n y")]);
+        assert!(pair_reverts(&[change, partial]).is_empty());
+
+        // Toggled twice: each revert pairs with the nearest change it undoes
+        let on = commit(&[("f.rs", "@@ -1 +1 @@\n-off\n+on")]);
+        let off = commit(&[("f.rs", "@@ -1 +1 @@\n-on\n+off")]);
+        assert_eq!(pair_reverts(&[on, off, on, off]), [(0, 1), (2, 3)]);
+    }
 }
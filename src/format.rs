@@ -1,5 +1,74 @@
-use crate::diff::{parse_patch, DiffHunk};
-use crate::github::{PrFile, PullRequest};
+use crate::diff::{
+    detect_moves, hunk_ids, parse_patch, replaced_line_pairs, resolve_contexts, word_diff, DiffHunk, MovedBlock, WordSegment,
+};
+use crate::github::{
+    Assignment, CheckRun, CommitStat, Label, PrCommit, PrFile, PrStatus, PrSummary, PullRequest, RequestedReviewers, Review,
+    ReviewComment,
+};
+use crate::history::SmartDiff;
+use std::borrow::Borrow;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// GitHub rejects comment bodies longer than this
+pub const MAX_COMMENT_BODY: usize = 65536;
+
+/// Lines of hunk context quoted around a commented range
+const QUOTE_CONTEXT: usize = 2;
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Set while a `ColorOverride` is alive on this thread, and wins over COLOR
+    static COLOR_OVERRIDE: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+/// Whether to color text output: never for JSON or `--color never`, always for
+/// `--color always`, otherwise when stdout is a terminal and `NO_COLOR` is unset
+/// or empty
+pub fn should_color(mode: &str, json: bool, no_color: Option<&str>) -> bool {
+    match mode {
+        _ if json => false,
+        "always" => true,
+        "never" => false,
+        _ => no_color.is_none_or(str::is_empty) && std::io::stdout().is_terminal(),
+    }
+}
+
+pub fn enable_color() {
+    COLOR.store(true, Ordering::Relaxed);
+}
+
+/// Color forced on or off on this thread until dropped, e.g. off while
+/// rendering text that goes to a file rather than the terminal
+pub struct ColorOverride(Option<bool>);
+
+pub fn override_color(on: bool) -> ColorOverride {
+    ColorOverride(COLOR_OVERRIDE.replace(Some(on)))
+}
+
+impl Drop for ColorOverride {
+    fn drop(&mut self) {
+        COLOR_OVERRIDE.set(self.0);
+    }
+}
+
+/// `text` in the ANSI SGR style `code` (e.g. "32" for green) when color is on.
+/// Styles already inside `text` end where they did, then `code` picks up again.
+pub fn paint(code: &str, text: &str) -> String {
+    let on = COLOR_OVERRIDE.get().unwrap_or_else(|| COLOR.load(Ordering::Relaxed));
+    styled(on, code, text)
+}
+
+fn styled(on: bool, code: &str, text: &str) -> String {
+    if !on || text.is_empty() {
+        return text.to_string();
+    }
+    format!("\x1b[{code}m{}\x1b[m", text.replace("\x1b[m", &format!("\x1b[m\x1b[{code}m")))
+}
 
 /// Format the metadata header for `pr view`
 pub fn format_metadata(pr: &PullRequest) -> String {
@@ -16,57 +85,766 @@ pub fn format_metadata(pr: &PullRequest) -> String {
     )
 }
 
-/// Format the file stat table
-pub fn format_stat_table(files: &[PrFile]) -> String {
+/// Format the `pr list` table
+pub fn format_pr_list(prs: &[PrSummary]) -> String {
     let mut lines = Vec::new();
-    for f in files {
+    for pr in prs {
+        let title: String = if pr.title.chars().count() > 60 {
+            let mut t: String = pr.title.chars().take(59).collect();
+            t.push('…');
+            t
+        } else {
+            pr.title.clone()
+        };
+        // updatedAt is ISO 8601; "2024-05-01T12:34:56Z" -> "2024-05-01 12:34"
+        let updated = pr.updated_at.get(..16).unwrap_or(&pr.updated_at).replace('T', " ");
+        lines.push(format!(
+            "#{:<6} {:<60}  @{:<16} {:>+5} {:>-5}  {}",
+            pr.number,
+            title,
+            pr.author,
+            pr.additions as i64,
+            -(pr.deletions as i64),
+            updated,
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Format the `pr commits` list; `stats` is parallel to `commits` when --stat is set
+/// `reverts` pairs (original, revert) by index, marked on both rows
+pub fn format_commits(commits: &[PrCommit], stats: Option<&[Option<CommitStat>]>, reverts: &[(usize, usize)]) -> String {
+    let mut lines = Vec::new();
+    for (i, c) in commits.iter().enumerate() {
+        let date = c.date.get(..10).unwrap_or(&c.date);
+        let mut line = format!("{}  {:<16} {}  {}", c.short_sha(), c.author, date, c.subject());
+        if let Some(Some(st)) = stats.map(|s| &s[i]) {
+            line.push_str(&format!("  ({} files +{} -{})", st.files, st.additions, st.deletions));
+        }
+        for &(original, revert) in reverts {
+            if original == i {
+                line.push_str(&format!("  [reverted by {}]", commits[revert].short_sha()));
+            } else if revert == i {
+                line.push_str(&format!("  [reverts {}]", commits[original].short_sha()));
+            }
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// Format `pr file-history`: one numbered row per commit that touched the file
+pub fn format_file_history(path: &str, revisions: &[(&PrCommit, &PrFile)], commits: usize) -> String {
+    let mut lines = vec![format!(
+        "{path}: {} revision{} in {commits} commits",
+        revisions.len(),
+        if revisions.len() == 1 { "" } else { "s" }
+    )];
+    for (i, (c, f)) in revisions.iter().enumerate() {
         lines.push(format!(
-            " {:>9}  {:>+4} {:>-4}  {}",
+            "{:>3}  {}  {:<9} {:>5} {:>5}  {}",
+            i + 1,
+            c.short_sha(),
             f.status,
-            f.additions as i64,
-            -(f.deletions as i64),
-            f.filename,
+            format!("+{}", f.additions),
+            format!("-{}", f.deletions),
+            c.subject()
         ));
     }
     lines.join("\n")
 }
 
+/// Bucket a check into "pass", "fail" or "pending"
+pub fn check_outcome(check: &CheckRun) -> &'static str {
+    if check.status != "completed" {
+        return "pending";
+    }
+    match check.conclusion.as_deref() {
+        Some("success") | Some("neutral") | Some("skipped") => "pass",
+        _ => "fail",
+    }
+}
+
+/// Format the `pr checks` table
+pub fn format_checks(checks: &[&CheckRun]) -> String {
+    let mut lines = Vec::new();
+    for c in checks {
+        let detail = match (check_outcome(c), c.conclusion.as_deref()) {
+            ("pending", _) => c.status.clone(),
+            (_, Some(conclusion)) => conclusion.to_string(),
+            _ => String::new(),
+        };
+        lines.push(format!(
+            " {:<7}  {:<40}  {:<16}  {}",
+            check_outcome(c),
+            c.name,
+            detail,
+            c.details_url.as_deref().unwrap_or(""),
+        ).trim_end().to_string());
+    }
+    lines.join("\n")
+}
+
+/// Group review comments into threads ordered by file, line, then start time.
+/// Replies stay in creation order after the comment that started the thread.
+pub fn group_threads(comments: &[ReviewComment]) -> Vec<Vec<&ReviewComment>> {
+    let mut threads: Vec<Vec<&ReviewComment>> = Vec::new();
+    let mut index: HashMap<u64, usize> = HashMap::new();
+    for c in comments {
+        match index.get(&c.thread_id) {
+            Some(&i) => threads[i].push(c),
+            None => {
+                index.insert(c.thread_id, threads.len());
+                threads.push(vec![c]);
+            }
+        }
+    }
+    threads.sort_by(|a, b| {
+        (&a[0].path, a[0].line, &a[0].created_at).cmp(&(&b[0].path, b[0].line, &b[0].created_at))
+    });
+    threads
+}
+
+/// Format review threads for `pr comments`. Each comment starts with the same
+/// `file:line:` prefix as `search::format_matches`; bodies are indented below it.
+pub fn format_review_threads(threads: &[Vec<&ReviewComment>], originals: &HashMap<u64, Vec<String>>) -> String {
+    if threads.is_empty() {
+        return "No review comments.".to_string();
+    }
+
+    let mut lines = Vec::new();
+    for thread in threads {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        for (i, c) in thread.iter().enumerate() {
+            let reply = if i > 0 { "↳ " } else { "" };
+            let outdated = if c.outdated { " [outdated]" } else { "" };
+            lines.push(format!(
+                "{}:{}: {reply}@{} ({}){outdated}",
+                c.path,
+                c.line.unwrap_or(0),
+                c.author,
+                c.created_at,
+            ));
+            let body: Vec<&str> = c.body.lines().collect();
+            let mut next = 0;
+            for block in parse_suggestions(&c.body) {
+                lines.extend(body[next..block.span.start].iter().map(|l| format!("    {l}")));
+                let original = originals.get(&c.id);
+                lines.extend(
+                    suggestion_diff(original.map(Vec::as_slice), &block.lines)
+                        .into_iter()
+                        .map(|l| format!("    {l}")),
+                );
+                next = block.span.end;
+            }
+            lines.extend(body[next..].iter().map(|l| format!("    {l}")));
+        }
+    }
+
+    let comments: usize = threads.iter().map(Vec::len).sum();
+    let files: BTreeSet<&str> = threads.iter().map(|t| t[0].path.as_str()).collect();
+    lines.push(format!(
+        "\n{comments} comments in {} threads across {} files",
+        threads.len(),
+        files.len()
+    ));
+    lines.join("\n")
+}
+
+/// Format the `pr label --list` output: one label per line with its color
+pub fn format_labels(labels: &[Label]) -> String {
+    if labels.is_empty() {
+        return "No labels.".to_string();
+    }
+    let width = labels.iter().map(|l| l.name.chars().count()).max().unwrap_or(0);
+    labels
+        .iter()
+        .map(|l| format!("{:<width$}  #{}", l.name, l.color))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `pr assign` output: current assignees and milestone
+pub fn format_assignment(assignment: &Assignment) -> String {
+    let assignees = if assignment.assignees.is_empty() {
+        "(none)".to_string()
+    } else {
+        assignment.assignees.iter().map(|a| format!("@{a}")).collect::<Vec<_>>().join(", ")
+    };
+    let milestone = assignment.milestone.as_ref().map_or("(none)", |m| m.title.as_str());
+    format!("Assignees: {assignees}\nMilestone: {milestone}")
+}
+
+/// Format the `pr reviewers --list` output: who is still requested, then who
+/// has reviewed and their latest verdict
+pub fn format_reviewers(requested: &RequestedReviewers, reviewed: &[&Review]) -> String {
+    let mut lines = vec!["Requested:".to_string()];
+    let pending: Vec<String> = requested
+        .users
+        .iter()
+        .map(|u| format!("@{u}"))
+        .chain(requested.teams.iter().cloned())
+        .collect();
+    if pending.is_empty() {
+        lines.push("  (none)".to_string());
+    }
+    lines.extend(pending.iter().map(|r| format!("  {r}")));
+
+    lines.push("Reviewed:".to_string());
+    if reviewed.is_empty() {
+        lines.push("  (none)".to_string());
+    }
+    let width = reviewed.iter().map(|r| r.author.chars().count() + 1).max().unwrap_or(0);
+    for r in reviewed {
+        lines.push(format!("  {:<width$}  {}", format!("@{}", r.author), r.state));
+    }
+    lines.join("\n")
+}
+
+/// First 7 characters of a commit SHA
+pub fn short_sha(sha: &str) -> &str {
+    &sha[..sha.len().min(7)]
+}
+
+/// Format the `pr smart-diff` report: what changed since the analysis of `from`
+pub fn format_smart_diff(diff: &SmartDiff, from: &str, to: &str) -> String {
+    let mut out = vec![format!("Smart analysis {} → {}", short_sha(from), short_sha(to))];
+    if diff.is_empty() {
+        out.push(match diff.mechanical_changed {
+            0 => "No changes since last analysis.".to_string(),
+            n => format!("Only mechanical changes since last analysis ({n} entries)."),
+        });
+        return out.join("\n");
+    }
+
+    let label = |category: &str| category.replace('_', " ");
+    let hint = |h: &Option<String>| h.as_ref().map(|h| format!(" — {h}")).unwrap_or_default();
+    if !diff.moved.is_empty() {
+        out.push(format!("\nMOVED ({}):", diff.moved.len()));
+        for m in &diff.moved {
+            out.push(format!("  ↻ {}:{}  {} → {}", m.file, m.entity, label(&m.from), label(&m.to)));
+        }
+    }
+    // Behavioral first: those are what a re-review has to verify
+    let mut appeared: Vec<_> = diff.appeared.iter().collect();
+    appeared.sort_by_key(|e| e.category != "behavioral");
+    if !appeared.is_empty() {
+        out.push(format!("\nNEW ({}):", appeared.len()));
+        for e in appeared {
+            out.push(format!("  ⊕ {}:{}  {}{}", e.file, e.entity, label(&e.category), hint(&e.hint)));
+        }
+    }
+    if !diff.resolved.is_empty() {
+        out.push(format!("\nRESOLVED ({}):", diff.resolved.len()));
+        for e in &diff.resolved {
+            out.push(format!("  ✓ {}:{}  was {}{}", e.file, e.entity, label(&e.category), hint(&e.hint)));
+        }
+    }
+    if diff.mechanical_changed > 0 {
+        out.push(format!("\n({} mechanical entries changed)", diff.mechanical_changed));
+    }
+    out.join("\n")
+}
+
+/// Format the `pr status` summary. `blockers` is empty when the PR is ready to merge.
+pub fn format_status(status: &PrStatus, blockers: &[String]) -> String {
+    let count = |outcome: &str| status.checks.iter().filter(|c| check_outcome(c) == outcome).count();
+    let draft = if status.is_draft { " (draft)" } else { "" };
+    let requested = if status.requested_reviewers.is_empty() {
+        "none".to_string()
+    } else {
+        status
+            .requested_reviewers
+            .iter()
+            .map(|r| format!("@{r}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let checks = match &status.checks_state {
+        Some(state) => format!(
+            "{state} ({} passed, {} failed, {} pending)",
+            count("pass"),
+            count("fail"),
+            count("pending")
+        ),
+        None => "none reported".to_string(),
+    };
+
+    let mut lines = vec![
+        format!("#{} {}", status.number, status.title),
+        format!("State:      {}{draft}", status.state),
+        format!("Mergeable:  {} (merge state {})", status.mergeable, status.merge_state_status),
+        format!("Review:     {}", status.review_decision.as_deref().unwrap_or("not required")),
+        format!("Requested:  {requested}"),
+        format!("Checks:     {checks}"),
+    ];
+    let failing: Vec<&CheckRun> = status.checks.iter().filter(|c| check_outcome(c) == "fail").collect();
+    if !failing.is_empty() {
+        lines.extend(format_checks(&failing).lines().map(|l| format!("           {l}")));
+    }
+    lines.push(if blockers.is_empty() {
+        "Ready:      yes".to_string()
+    } else {
+        format!("Ready:      no — {}", blockers.join("; "))
+    });
+    lines.join("\n")
+}
+
+/// Longest body preview shown by `pr reviews`, in characters
+const REVIEW_PREVIEW_CHARS: usize = 72;
+
+/// Format the `pr reviews` table: state, reviewer, submitted date, body preview
+pub fn format_reviews(reviews: &[&Review]) -> String {
+    let mut lines = Vec::new();
+    for r in reviews {
+        let date = r.submitted_at.as_deref().map_or("-", |d| d.get(..10).unwrap_or(d));
+        let first = r.body.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or_default();
+        let preview = match first.char_indices().nth(REVIEW_PREVIEW_CHARS) {
+            Some((cut, _)) => format!("{}…", &first[..cut]),
+            None => first.to_string(),
+        };
+        lines.push(
+            format!("{:<17}  @{:<16} {}  {}", r.state, r.author, date, preview)
+                .trim_end()
+                .to_string(),
+        );
+    }
+    lines.join("\n")
+}
+
+/// Format the file stat table
+/// `--sort` for stat tables
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatSort {
+    Path,
+    Additions,
+    Deletions,
+    /// Additions plus deletions
+    Total,
+}
+
+impl StatSort {
+    /// Sort `files` by path, or by line counts with the largest first. The sort is
+    /// stable, so ties keep the API's order.
+    pub fn sort<T: Borrow<PrFile>>(self, files: &mut [T]) {
+        let count = |f: &T| {
+            let f = f.borrow();
+            match self {
+                StatSort::Additions => f.additions,
+                StatSort::Deletions => f.deletions,
+                _ => f.additions + f.deletions,
+            }
+        };
+        match self {
+            StatSort::Path => files.sort_by(|a, b| a.borrow().filename.cmp(&b.borrow().filename)),
+            _ => files.sort_by_key(|f| Reverse(count(f))),
+        }
+    }
+}
+
+/// Widest `--graph` bar; longer changes are scaled down to fit
+const STAT_GRAPH_WIDTH: u64 = 40;
+
+/// The stat table with a totals row. `graph` appends a `+++---` bar to each
+/// file, as `git diff --stat` does.
+pub fn format_stat_table(files: &[PrFile], graph: bool) -> String {
+    let counts = |label: &str, additions: u64, deletions: u64| {
+        format!(
+            " {label:>9}  {} {}",
+            paint("32", &format!("{:>+4}", additions as i64)),
+            paint("31", &format!("{:>-4}", -(deletions as i64))),
+        )
+    };
+    let name = |f: &PrFile| if f.binary { format!("{} (binary)", f.filename) } else { f.filename.clone() };
+    let path_width = files.iter().map(|f| name(f).chars().count()).max().unwrap_or(0);
+    let largest = files.iter().map(|f| f.additions + f.deletions).max().unwrap_or(0);
+    let mut lines = Vec::new();
+    for f in files {
+        let name = name(f);
+        let mut line = format!("{}  {name}", counts(&f.status, f.additions, f.deletions));
+        if graph {
+            let (plus, minus) = stat_bar(f.additions, f.deletions, largest);
+            let pad = path_width - name.chars().count();
+            line.push_str(&format!("{:pad$} | {}{}", "", paint("32", &"+".repeat(plus)), paint("31", &"-".repeat(minus))));
+        }
+        lines.push(line);
+    }
+    let additions = files.iter().map(|f| f.additions).sum();
+    let deletions = files.iter().map(|f| f.deletions).sum();
+    let s = if files.len() == 1 { "" } else { "s" };
+    lines.push(counts(&format!("{} file{s}", files.len()), additions, deletions));
+    lines.join("\n")
+}
+
+/// `+` and `-` counts for one file's bar: its line counts, scaled so the largest
+/// change fits STAT_GRAPH_WIDTH, with at least one of each kind it has
+fn stat_bar(additions: u64, deletions: u64, largest: u64) -> (usize, usize) {
+    let scale = |n: u64| match n {
+        0 => 0,
+        _ if largest <= STAT_GRAPH_WIDTH => n as usize,
+        _ => ((n * STAT_GRAPH_WIDTH + largest / 2) / largest).max(1) as usize,
+    };
+    (scale(additions), scale(deletions))
+}
+
+/// How `pr diff` renders a file
+#[derive(Debug, Default)]
+pub struct DiffView<'a> {
+    /// Hunk ids to show; every hunk when empty
+    pub only_hunks: &'a [String],
+    /// The hunks' ids, when the patch was cut down from hunks that had them
+    /// (`--lines`); otherwise they're worked out from the patch
+    pub hunk_ids: Option<&'a [String]>,
+    /// Shortest run of lines flagged as moved; 0 turns detection off
+    pub min_move_lines: usize,
+    /// Replace the added copy of a moved block with one marker line
+    pub collapse_moves: bool,
+    /// Mark the words that changed between a deleted line and the added line after it
+    pub word_diff: Option<WordDiff>,
+    /// The file at the PR head, to name the enclosing function of hunks whose
+    /// header doesn't (`--resolve-context`)
+    pub context_source: Option<&'a str>,
+    /// Most lines to show, headers included; later hunks are left out whole
+    pub max_lines: Option<usize>,
+    /// A binary file's size in bytes before and after, where known
+    pub binary_sizes: (Option<u64>, Option<u64>),
+}
+
+/// How `--word-diff` marks changed words
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordDiff {
+    /// `[-old-]` and `{+new+}`, as `git diff --word-diff=plain`
+    Plain,
+    /// Red and green ANSI colors
+    Color,
+}
+
 /// Format line-numbered unified diff for a single file
-pub fn format_line_numbered_diff(file: &PrFile) -> String {
+pub fn format_line_numbered_diff(file: &PrFile, view: &DiffView) -> String {
+    let sizes = match view.binary_sizes {
+        (Some(before), Some(after)) => format!("{} → {}", human_size(before), human_size(after)),
+        (Some(size), None) | (None, Some(size)) => human_size(size),
+        (None, None) => String::new(),
+    };
     if file.status == "removed" {
+        if file.binary {
+            let sizes = if sizes.is_empty() { sizes } else { format!(", {sizes}") };
+            return format!("deleted: {} (binary file{sizes})", file.filename);
+        }
         let total = file.deletions;
         return format!("deleted: {} ({} lines)", file.filename, total);
     }
 
+    let old_path = file.previous_filename.as_deref().unwrap_or(&file.filename);
+    if file.binary {
+        let change = if file.status == "added" { "added" } else { "changed" };
+        let sizes = if sizes.is_empty() { sizes } else { format!(" ({sizes})") };
+        return format!("--- a/{old_path}\n+++ b/{}\nbinary file {change}{sizes}", file.filename);
+    }
     let patch = match &file.patch {
         Some(p) if !p.is_empty() => p,
-        _ => return format!("--- a/{}\n+++ b/{}\n(no diff)", file.filename, file.filename),
+        _ if file.patch_too_large => {
+            return format!("--- a/{old_path}\n+++ b/{}\n(diff too large, use pr file)", file.filename)
+        }
+        _ => return format!("--- a/{old_path}\n+++ b/{}\n(no diff)", file.filename),
     };
 
-    let hunks = parse_patch(patch);
     let mut out = Vec::new();
-    out.push(format!("--- a/{}", file.filename));
+    out.push(format!("--- a/{old_path}"));
     out.push(format!("+++ b/{}", file.filename));
 
-    for hunk in &hunks {
-        out.push(format_hunk(hunk));
+    let mut hunks = parse_patch(patch);
+    if let Some(content) = view.context_source {
+        resolve_contexts(&mut hunks, content);
+    }
+    // Moves are found across the whole file, even when showing only some hunks
+    let moves = detect_moves(&hunks, view.min_move_lines);
+    let mut shown = Vec::new();
+    let ids = view.hunk_ids.map_or_else(|| hunk_ids(&file.filename, &hunks), <[String]>::to_vec);
+    for (i, (id, hunk)) in ids.iter().zip(&hunks).enumerate() {
+        if view.only_hunks.is_empty() || view.only_hunks.contains(id) {
+            shown.push(format_hunk(hunk, id, i, &moves, view));
+        }
+    }
+    let Some(max_lines) = view.max_lines else {
+        out.extend(shown);
+        return out.join("\n");
+    };
+    let (kept, hidden) = cap_hunks(shown, max_lines.saturating_sub(out.len()));
+    out.extend(kept);
+    if hidden > 0 {
+        out.push(format!(
+            "… ({} more line{}; --file {} with no line cap shows them)",
+            thousands(hidden),
+            if hidden == 1 { "" } else { "s" },
+            file.filename
+        ));
     }
-
     out.join("\n")
 }
 
-fn format_hunk(hunk: &DiffHunk) -> String {
+/// The formatted hunks that fit in `budget` lines, and how many lines are left
+/// out. Hunks are kept whole; only a first hunk longer than the whole budget is cut.
+fn cap_hunks(hunks: Vec<String>, budget: usize) -> (Vec<String>, usize) {
+    let mut kept = Vec::new();
+    let mut used = 0;
+    let mut hidden = 0;
+    for hunk in hunks {
+        let n = hunk.lines().count();
+        if hidden == 0 && used + n <= budget {
+            used += n;
+            kept.push(hunk);
+        } else if hidden == 0 && kept.is_empty() && budget > 0 {
+            kept.push(hunk.lines().take(budget).collect::<Vec<_>>().join("\n"));
+            hidden += n - budget;
+        } else {
+            hidden += n;
+        }
+    }
+    (kept, hidden)
+}
+
+/// `file` as a unified diff that `git apply` takes: the raw diff's `diff --git`
+/// header (rebuilt from the status when it wasn't kept) and the hunks as GitHub
+/// sent them, only those in `only_hunks` when that isn't empty. None for binary
+/// files and those whose patch GitHub left out.
+pub fn format_git_patch(file: &PrFile, only_hunks: &[String]) -> Option<String> {
+    if file.binary {
+        return None;
+    }
+    let patch = file.patch.as_deref()?;
+    let mut out = file.git_header.clone().unwrap_or_else(|| git_header(file, !patch.is_empty()));
+    for hunk in raw_hunks(&file.filename, patch, only_hunks) {
+        out.push('\n');
+        out.push_str(&hunk);
+    }
+    Some(out)
+}
+
+/// The hunks of `patch` as GitHub sent them, only those in `only_hunks` when that
+/// isn't empty
+fn raw_hunks(path: &str, patch: &str, only_hunks: &[String]) -> Vec<String> {
+    let mut hunks: Vec<Vec<&str>> = Vec::new();
+    for line in patch.lines() {
+        if line.starts_with("@@") || hunks.is_empty() {
+            hunks.push(Vec::new());
+        }
+        hunks.last_mut().expect("pushed above").push(line);
+    }
+    hunk_ids(path, &parse_patch(patch))
+        .iter()
+        .zip(hunks)
+        .filter(|(id, _)| only_hunks.is_empty() || only_hunks.contains(id))
+        .map(|(_, hunk)| hunk.join("\n"))
+        .collect()
+}
+
+/// `pr diff --format markdown`: the stat table as a markdown table
+pub fn format_markdown_stat_table(files: &[&PrFile]) -> String {
+    let mut lines = vec!["| File | Status | + | − |".to_string(), "|---|---|--:|--:|".to_string()];
+    for f in files {
+        let binary = if f.binary { " (binary)" } else { "" };
+        lines.push(format!(
+            "| `{}`{binary} | {} | +{} | −{} |",
+            table_cell(&f.filename),
+            f.status,
+            f.additions,
+            f.deletions
+        ));
+    }
+    let additions: u64 = files.iter().map(|f| f.additions).sum();
+    let deletions: u64 = files.iter().map(|f| f.deletions).sum();
+    let s = if files.len() == 1 { "" } else { "s" };
+    lines.push(format!("| **{} file{s}** | | **+{additions}** | **−{deletions}** |", files.len()));
+    lines.join("\n")
+}
+
+/// `pr diff --format markdown`: one file's hunks in a collapsed section, headed
+/// by its path and line counts. The fence is `diff`, the only one GitHub colors
+/// as added and deleted lines.
+pub fn format_markdown_file(file: &PrFile, only_hunks: &[String]) -> String {
+    let summary = format!("{} (+{} −{})", html_escape(&file.filename), file.additions, file.deletions);
+    let hunks = file.patch.as_deref().map(|p| raw_hunks(&file.filename, p, only_hunks)).unwrap_or_default();
+    let body = if file.binary {
+        "_Binary file, not shown._".to_string()
+    } else if file.patch_too_large {
+        "_Diff too large to show; `gh-agent pr file` reads the file._".to_string()
+    } else if hunks.is_empty() {
+        match &file.previous_filename {
+            Some(old) => format!("_Renamed from `{old}` without changes._"),
+            None => "_No changes to show._".to_string(),
+        }
+    } else {
+        fenced("diff", &hunks.join("\n"))
+    };
+    details(&summary, &body)
+}
+
+/// Fence language for a file, from its name, so code blocks get highlighted.
+/// Empty when there's no better guess than plain text.
+pub fn fence_lang(path: &str) -> &'static str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name {
+        "Dockerfile" => return "dockerfile",
+        "Makefile" | "GNUmakefile" => return "makefile",
+        _ => {}
+    }
+    let Some((_, ext)) = name.rsplit_once('.') else {
+        return "";
+    };
+    match ext.to_ascii_lowercase().as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "tsx",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "swift" => "swift",
+        "rb" => "ruby",
+        "php" => "php",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hh" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "scala" => "scala",
+        "ex" | "exs" => "elixir",
+        "lua" => "lua",
+        "sh" | "bash" | "zsh" => "bash",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "json" => "json",
+        "yml" | "yaml" => "yaml",
+        "toml" => "toml",
+        "xml" => "xml",
+        "md" | "markdown" => "markdown",
+        "proto" => "protobuf",
+        "diff" | "patch" => "diff",
+        _ => "",
+    }
+}
+
+/// Escape text placed inside HTML, such as a `<summary>`
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// A `diff --git` header for a file whose patch came without one
+fn git_header(file: &PrFile, has_hunks: bool) -> String {
+    let old = file.previous_filename.as_deref().unwrap_or(&file.filename);
+    let mut lines = vec![format!("diff --git a/{old} b/{}", file.filename)];
+    match file.status.as_str() {
+        "added" => lines.push("new file mode 100644".to_string()),
+        "removed" => lines.push("deleted file mode 100644".to_string()),
+        _ => {}
+    }
+    if file.previous_filename.is_some() {
+        let how = if file.status == "copied" { "copy" } else { "rename" };
+        lines.push(format!("{how} from {old}"));
+        lines.push(format!("{how} to {}", file.filename));
+    }
+    if has_hunks {
+        lines.push(if file.status == "added" { "--- /dev/null".to_string() } else { format!("--- a/{old}") });
+        lines.push(if file.status == "removed" { "+++ /dev/null".to_string() } else { format!("+++ b/{}", file.filename) });
+    }
+    lines.join("\n")
+}
+
+/// A byte count as `812 B`, `12.4 KB` or `3.1 MB`
+pub fn human_size(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    if bytes < KB {
+        format!("{bytes} B")
+    } else if bytes < KB * KB {
+        format!("{:.1} KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (KB * KB) as f64)
+    }
+}
+
+/// `1842` as `1,842`
+pub fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn format_hunk(hunk: &DiffHunk, id: &str, index: usize, moves: &[MovedBlock], view: &DiffView) -> String {
     let mut lines = Vec::new();
-    lines.push(hunk.header.clone());
+    // A resolved context isn't in the header yet
+    let header = if hunk.header.ends_with(&hunk.context) {
+        hunk.header.clone()
+    } else {
+        format!("{} {}", hunk.header.trim_end(), hunk.context)
+    };
+    lines.push(paint("36", &format!("{header} [h:{id}]")));
 
-    for line in &hunk.lines {
+    // Lines of moved blocks are shown as moves, not word-diffed
+    let moved = |j: usize| {
+        moves.iter().any(|m| {
+            [m.from, m.to].iter().any(|&(h, first)| h == index && (first..first + m.len).contains(&j))
+        })
+    };
+    let mut marked: HashMap<usize, String> = HashMap::new();
+    if let Some(mode) = view.word_diff {
+        for (d, a) in replaced_line_pairs(hunk) {
+            if moved(d) || moved(a) {
+                continue;
+            }
+            let (old, new) = word_diff(&hunk.lines[d].content, &hunk.lines[a].content);
+            marked.insert(d, mark_words(&old, mode, false));
+            marked.insert(a, mark_words(&new, mode, true));
+        }
+    }
+    let content = |j: usize| marked.get(&j).map_or(hunk.lines[j].content.as_str(), String::as_str);
+    // A line whose changed words are colored already stays uncolored around them
+    let line_paint = |j: usize, code: &str, line: String| {
+        if view.word_diff == Some(WordDiff::Color) && marked.contains_key(&j) {
+            line
+        } else {
+            paint(code, &line)
+        }
+    };
+
+    let mut skip = 0;
+    for (j, line) in hunk.lines.iter().enumerate() {
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        if let Some(m) = moves.iter().find(|m| m.from == (index, j)) {
+            lines.push(format!("     | (moved to lines {}-{})", m.new_lines.0, m.new_lines.1));
+        }
+        if let Some(m) = moves.iter().find(|m| m.to == (index, j)) {
+            let (from, to) = (m.old_lines, m.new_lines);
+            if view.collapse_moves {
+                lines.push(format!(
+                    "     | (lines {}-{} moved from lines {}-{}, unchanged; hidden by --collapse-moves)",
+                    to.0, to.1, from.0, from.1
+                ));
+                skip = m.len - 1;
+                continue;
+            }
+            lines.push(format!("     | (moved from lines {}-{})", from.0, from.1));
+        }
         match line.kind.as_str() {
             "add" => {
                 let ln = line.new_line.unwrap_or(0);
-                lines.push(format!("{:>4} | +{}", ln, line.content));
+                lines.push(line_paint(j, "32", format!("{:>4} | +{}", ln, content(j))));
             }
             "delete" => {
-                lines.push(format!("     | -{}", line.content));
+                lines.push(line_paint(j, "31", format!("     | -{}", content(j))));
             }
             _ => {
                 // context
@@ -78,3 +856,699 @@ fn format_hunk(hunk: &DiffHunk) -> String {
 
     lines.join("\n")
 }
+
+/// One side of a word diff with its changed words marked
+fn mark_words(segments: &[WordSegment], mode: WordDiff, added: bool) -> String {
+    let (open, close) = match (mode, added) {
+        (WordDiff::Plain, false) => ("[-", "-]"),
+        (WordDiff::Plain, true) => ("{+", "+}"),
+        (WordDiff::Color, false) => ("\x1b[31m", "\x1b[m"),
+        (WordDiff::Color, true) => ("\x1b[32m", "\x1b[m"),
+    };
+    segments
+        .iter()
+        .map(|s| if s.changed { format!("{open}{}{close}", s.text) } else { s.text.clone() })
+        .collect()
+}
+
+/// Render the hunk lines around `start..=end` (new-file lines) as a collapsed
+/// `<details>` diff snippet. Returns None if the range isn't inside a single hunk.
+pub fn format_comment_context(hunks: &[DiffHunk], path: &str, start: u64, end: u64) -> Option<String> {
+    let hunk = hunks.iter().find(|h| {
+        h.lines.iter().any(|l| l.kind != "delete" && l.new_line == Some(start))
+            && h.lines.iter().any(|l| l.kind != "delete" && l.new_line == Some(end))
+    })?;
+    let first = hunk.lines.iter().position(|l| l.kind != "delete" && l.new_line == Some(start))?;
+    let last = hunk.lines.iter().position(|l| l.kind != "delete" && l.new_line == Some(end))?;
+
+    let from = first.saturating_sub(QUOTE_CONTEXT);
+    let to = (last + QUOTE_CONTEXT + 1).min(hunk.lines.len());
+    let quoted: Vec<String> = hunk.lines[from..to]
+        .iter()
+        .map(|l| {
+            let prefix = match l.kind.as_str() {
+                "add" => '+',
+                "delete" => '-',
+                _ => ' ',
+            };
+            format!("{prefix}{}", l.content)
+        })
+        .collect();
+
+    let location = if start == end {
+        format!("{path}:{end}")
+    } else {
+        format!("{path}:{start}-{end}")
+    };
+    Some(details(&location, &fenced("diff", &quoted.join("\n"))))
+}
+
+// --- Markdown helpers ---
+
+/// `code` in a fenced block tagged `lang`. The fence is longer than any backtick
+/// run inside, so quoted code can't close it early.
+pub fn fenced(lang: &str, code: &str) -> String {
+    let longest_run = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{fence}{lang}\n{code}\n{fence}")
+}
+
+/// A collapsed `<details>` section
+pub fn details(summary: &str, body: &str) -> String {
+    format!("<details>\n<summary>{summary}</summary>\n\n{body}\n\n</details>")
+}
+
+/// Escape text for a markdown table cell
+pub fn table_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// GitHub suggestion block replacing the commented lines with `replacement`. The
+/// fence is longer than any run of backticks in `replacement`, so code holding a
+/// fence of its own can't close the block early. An empty replacement gives a block
+/// with no lines, which deletes the commented lines.
+pub fn suggestion_block(replacement: &str) -> String {
+    fenced_suggestion(replacement, "")
+}
+
+/// A comment explaining a suggestion: `text`, then the suggestion block. The fence
+/// also outlasts any in `text`, so the block still parses after a fenced snippet.
+pub fn suggestion_comment(text: &str, replacement: &str) -> String {
+    if text.trim().is_empty() {
+        return suggestion_block(replacement);
+    }
+    format!("{text}\n\n{}", fenced_suggestion(replacement, text))
+}
+
+/// Suggestion block with a fence longer than any run of backticks in `replacement`
+/// and `also`
+fn fenced_suggestion(replacement: &str, also: &str) -> String {
+    let replacement = replacement.strip_suffix('\n').unwrap_or(replacement);
+    let longest = [replacement, also]
+        .iter()
+        .flat_map(|t| t.split(|c| c != '`'))
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    if replacement.is_empty() {
+        return format!("{fence}suggestion\n{fence}");
+    }
+    format!("{fence}suggestion\n{replacement}\n{fence}")
+}
+
+/// A suggestion block in a comment body
+#[derive(Debug, PartialEq)]
+pub struct SuggestionBlock<'a> {
+    /// Body lines the block covers, fences included
+    pub span: std::ops::Range<usize>,
+    /// The proposed replacement lines
+    pub lines: Vec<&'a str>,
+}
+
+/// Find the suggestion blocks in a comment body. A fence may use more than three
+/// backticks (so the suggestion can itself contain a fence) and is closed by a line
+/// of at least as many; an unclosed block runs to the end of the body.
+pub fn parse_suggestions(body: &str) -> Vec<SuggestionBlock<'_>> {
+    let lines: Vec<&str> = body.lines().collect();
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let open = lines[i].trim_start();
+        let ticks = open.len() - open.trim_start_matches('`').len();
+        if ticks < 3 || open[ticks..].trim() != "suggestion" {
+            i += 1;
+            continue;
+        }
+        let close = (i + 1..lines.len()).find(|&j| {
+            let l = lines[j].trim();
+            l.len() >= ticks && l.bytes().all(|b| b == b'`')
+        });
+        let content_end = close.unwrap_or(lines.len());
+        let end = close.map_or(lines.len(), |c| c + 1);
+        blocks.push(SuggestionBlock {
+            span: i..end,
+            lines: lines[i + 1..content_end].to_vec(),
+        });
+        i = end;
+    }
+    blocks
+}
+
+/// A suggestion as a small diff: the lines it replaces (when known) prefixed
+/// `-`, then its own lines prefixed `+`
+pub fn suggestion_diff(original: Option<&[String]>, suggested: &[&str]) -> Vec<String> {
+    let mut out = vec![match original {
+        Some(_) => "suggested change:".to_string(),
+        None => "suggested change (original lines unavailable):".to_string(),
+    }];
+    out.extend(original.into_iter().flatten().map(|l| format!("-{l}")));
+    out.extend(suggested.iter().map(|l| format!("+{l}")));
+    out
+}
+
+/// Prepend a context snippet to a comment body, unless that would push it past
+/// GitHub's body limit (the body is kept as-is in that case).
+pub fn with_comment_context(body: &str, context: Option<String>) -> String {
+    match context {
+        Some(ctx) if ctx.len() + 2 + body.len() <= MAX_COMMENT_BODY => format!("{ctx}\n\n{body}"),
+        _ => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "@@ -10,6 +10,7 @@ fn main() {\n a\n b\n-old\n+new\n+more\n c\n d\n e";
+
+    fn review_comment(id: u64, thread_id: u64, path: &str, line: u64, author: &str, body: &str) -> ReviewComment {
+        ReviewComment {
+            id,
+            thread_id,
+            path: path.to_string(),
+            line: Some(line),
+            start_line: None,
+            commit_id: "abc".to_string(),
+            outdated: false,
+            author: author.to_string(),
+            created_at: format!("2024-05-01T10:0{id}:00Z"),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_smart_diff_snapshot() {
+        use crate::history::{Entry, Moved};
+        let entry = |entity: &str, category: &str, hint: Option<&str>| Entry {
+            file: "src/db.rs".to_string(),
+            entity: entity.to_string(),
+            entity_type: "function".to_string(),
+            category: category.to_string(),
+            hint: hint.map(str::to_string),
+        };
+        let diff = SmartDiff {
+            moved: vec![Moved {
+                file: "src/db.rs".to_string(),
+                entity: "save".to_string(),
+                from: "mechanical".to_string(),
+                to: "behavioral".to_string(),
+            }],
+            appeared: vec![entry("connect", "new_logic", None), entry("flush", "behavioral", None)],
+            resolved: vec![entry("close", "behavioral", None)],
+            mechanical_changed: 3,
+        };
+        assert_eq!(
+            format_smart_diff(&diff, "aaaaaaaaaa", "bbbbbbbbbb"),
+            "\
+Smart analysis aaaaaaa → bbbbbbb
+
+MOVED (1):
+  ↻ src/db.rs:save  mechanical → behavioral
+
+NEW (2):
+  ⊕ src/db.rs:flush  behavioral
+  ⊕ src/db.rs:connect  new logic
+
+RESOLVED (1):
+  ✓ src/db.rs:close  was behavioral
+
+(3 mechanical entries changed)"
+        );
+        let quiet = SmartDiff { mechanical_changed: 2, ..Default::default() };
+        assert!(format_smart_diff(&quiet, "a", "b").ends_with("Only mechanical changes since last analysis (2 entries)."));
+    }
+
+    #[test]
+    fn test_status_snapshot() {
+        let status = PrStatus {
+            number: 12,
+            title: "Add retries".to_string(),
+            state: "OPEN".to_string(),
+            is_draft: false,
+            mergeable: "MERGEABLE".to_string(),
+            merge_state_status: "BLOCKED".to_string(),
+            review_decision: Some("REVIEW_REQUIRED".to_string()),
+            requested_reviewers: vec!["alice".to_string(), "org/core".to_string()],
+            head_ref: "retries".to_string(),
+            head_sha: "abc".to_string(),
+            is_cross_repository: false,
+            checks_state: Some("FAILURE".to_string()),
+            checks: vec![
+                CheckRun {
+                    name: "lint".to_string(),
+                    status: "completed".to_string(),
+                    conclusion: Some("success".to_string()),
+                    details_url: None,
+                },
+                CheckRun {
+                    name: "test".to_string(),
+                    status: "completed".to_string(),
+                    conclusion: Some("failure".to_string()),
+                    details_url: None,
+                },
+            ],
+        };
+        let blockers = vec!["review required".to_string(), "1 failing check".to_string()];
+        assert_eq!(
+            format_status(&status, &blockers),
+            "\
+#12 Add retries
+State:      OPEN
+Mergeable:  MERGEABLE (merge state BLOCKED)
+Review:     REVIEW_REQUIRED
+Requested:  @alice, @org/core
+Checks:     FAILURE (1 passed, 1 failed, 0 pending)
+            fail     test                                      failure
+Ready:      no — review required; 1 failing check"
+        );
+    }
+
+    #[test]
+    fn test_reviews_table() {
+        let review = |state: &str, body: &str| Review {
+            id: 1,
+            author: "alice".to_string(),
+            state: state.to_string(),
+            submitted_at: Some("2024-05-01T10:00:00Z".to_string()),
+            body: body.to_string(),
+            html_url: String::new(),
+            commit_id: None,
+        };
+        let approved = review("APPROVED", "\nLooks good\nmore detail");
+        let long = review("COMMENTED", &"é".repeat(80));
+        let mut pending = review("PENDING", "");
+        pending.submitted_at = None;
+        let out = format_reviews(&[&approved, &long, &pending]);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "APPROVED           @alice            2024-05-01  Looks good");
+        assert!(lines[1].ends_with(&format!("{}…", "é".repeat(72))));
+        assert_eq!(lines[2], "PENDING            @alice            -");
+    }
+
+    #[test]
+    fn test_review_threads_snapshot() {
+        let mut outdated = review_comment(4, 4, "src/b.rs", 3, "carol", "Stale?");
+        outdated.outdated = true;
+        let comments = vec![
+            review_comment(1, 1, "src/main.rs", 42, "alice", "Handle the error here\nor document it"),
+            review_comment(2, 2, "src/main.rs", 7, "alice", "Typo"),
+            review_comment(3, 1, "src/main.rs", 42, "bob", "Done"),
+            outdated,
+        ];
+        let threads = group_threads(&comments);
+        assert_eq!(
+            format_review_threads(&threads, &HashMap::new()),
+            "\
+src/b.rs:3: @carol (2024-05-01T10:04:00Z) [outdated]
+    Stale?
+
+src/main.rs:7: @alice (2024-05-01T10:02:00Z)
+    Typo
+
+src/main.rs:42: @alice (2024-05-01T10:01:00Z)
+    Handle the error here
+    or document it
+src/main.rs:42: ↳ @bob (2024-05-01T10:03:00Z)
+    Done
+
+4 comments in 3 threads across 2 files"
+        );
+        assert_eq!(format_review_threads(&[], &HashMap::new()), "No review comments.");
+    }
+
+    #[test]
+    fn test_suggestion_comment() {
+        assert_eq!(suggestion_comment("", "x"), "```suggestion\nx\n```");
+        assert_eq!(suggestion_comment("Use `?`:", "x"), "Use `?`:\n\n```suggestion\nx\n```");
+        let text = "This panics on empty input:\n```rust\nv[0]\n```";
+        let body = suggestion_comment(text, "v.first()");
+        assert_eq!(body, format!("{text}\n\n````suggestion\nv.first()\n````"));
+        let blocks = parse_suggestions(&body);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].lines, ["v.first()"]);
+    }
+
+    #[test]
+    fn test_suggestion_block_deletes_with_no_lines() {
+        assert_eq!(suggestion_block(""), "```suggestion\n```");
+        assert_eq!(suggestion_block("\n"), "```suggestion\n```");
+        assert!(parse_suggestions(&suggestion_block(""))[0].lines.is_empty());
+    }
+
+    #[test]
+    fn test_suggestion_block_outlasts_inner_fences() {
+        assert_eq!(suggestion_block("let x = `y`;\n"), "```suggestion\nlet x = `y`;\n```");
+        let replacement = "/// ```\n/// f();\n/// ```\nfn f() {}";
+        let block = suggestion_block(replacement);
+        assert!(block.starts_with("````suggestion\n") && block.ends_with("\n````"), "{block}");
+        let parsed = parse_suggestions(&block);
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].lines.join("\n"), replacement);
+        assert_eq!(crate::patch::extract_suggestion(&format!("Doc it:\n\n{block}")).as_deref(), Some(replacement));
+    }
+
+    #[test]
+    fn test_parse_suggestions() {
+        let body = "Two options:\n```suggestion\nlet x = 1;\n```\nor\n```suggestion\nlet x = 2;\nlet y = x;\n```";
+        let blocks = parse_suggestions(body);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!((blocks[0].span.clone(), blocks[0].lines.clone()), (1..4, vec!["let x = 1;"]));
+        assert_eq!((blocks[1].span.clone(), blocks[1].lines.clone()), (5..9, vec!["let x = 2;", "let y = x;"]));
+
+        // A longer fence can hold a shorter one, and an empty block deletes the lines
+        let body = "````suggestion\n/// ```\n/// f();\n/// ```\n````\n```suggestion\n```";
+        let blocks = parse_suggestions(body);
+        assert_eq!(blocks[0].lines, ["/// ```", "/// f();", "/// ```"]);
+        assert_eq!((blocks[1].span.clone(), blocks[1].lines.len()), (5..7, 0));
+
+        // Unclosed runs to the end; other fences and prose are not suggestions
+        assert_eq!(parse_suggestions("```suggestion\nx")[0].lines, ["x"]);
+        assert!(parse_suggestions("```rust\nx\n```\n``suggestion\nsuggestion").is_empty());
+    }
+
+    #[test]
+    fn test_review_threads_render_suggestions() {
+        let comment = review_comment(1, 1, "src/a.rs", 3, "alice", "Simpler:\n```suggestion\nlet x = y?;\n```\nThoughts?");
+        let other = review_comment(2, 2, "src/a.rs", 9, "bob", "```suggestion\nnew\n```");
+        let comments = vec![comment, other];
+        let threads = group_threads(&comments);
+        let originals = HashMap::from([(1, vec!["let x = match y {".to_string(), "};".to_string()])]);
+        assert_eq!(
+            format_review_threads(&threads, &originals),
+            "\
+src/a.rs:3: @alice (2024-05-01T10:01:00Z)
+    Simpler:
+    suggested change:
+    -let x = match y {
+    -};
+    +let x = y?;
+    Thoughts?
+
+src/a.rs:9: @bob (2024-05-01T10:02:00Z)
+    suggested change (original lines unavailable):
+    +new
+
+2 comments in 2 threads across 1 files"
+        );
+    }
+
+    #[test]
+    fn test_moved_block_annotations() {
+        let patch = "@@ -1,4 +1,1 @@\n top\n-a();\n-b();\n-c();\n@@ -20,1 +17,4 @@\n end\n+a();\n+b();\n+c();";
+        let file = PrFile {
+            filename: "m.rs".to_string(),
+            status: "modified".to_string(),
+            additions: 3,
+            deletions: 3,
+            patch: Some(patch.to_string()),
+            ..Default::default()
+        };
+        let ids = hunk_ids("m.rs", &parse_patch(patch));
+        let view = DiffView {
+            min_move_lines: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_line_numbered_diff(&file, &view),
+            format!(
+                "--- a/m.rs\n+++ b/m.rs\n\
+                 @@ -1,4 +1,1 @@ [h:{}]\n   1 |  top\n     | (moved to lines 18-20)\n     | -a();\n     | -b();\n     | -c();\n\
+                 @@ -20,1 +17,4 @@ [h:{}]\n  17 |  end\n     | (moved from lines 2-4)\n  18 | +a();\n  19 | +b();\n  20 | +c();",
+                ids[0], ids[1]
+            )
+        );
+
+        let collapsed = DiffView {
+            only_hunks: &ids[1..],
+            min_move_lines: 3,
+            collapse_moves: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_line_numbered_diff(&file, &collapsed),
+            format!(
+                "--- a/m.rs\n+++ b/m.rs\n\
+                 @@ -20,1 +17,4 @@ [h:{}]\n  17 |  end\n     | (lines 18-20 moved from lines 2-4, unchanged; hidden by --collapse-moves)",
+                ids[1]
+            )
+        );
+    }
+
+    #[test]
+    fn test_stat_table_sort_totals_and_graph() {
+        let file = |name: &str, additions: u64, deletions: u64| PrFile {
+            filename: name.to_string(),
+            status: "modified".to_string(),
+            additions,
+            deletions,
+            ..Default::default()
+        };
+        let mut files = vec![file("b.rs", 1, 4), file("a.rs", 3, 0), file("c.rs", 3, 1)];
+        StatSort::Additions.sort(&mut files);
+        let names: Vec<&str> = files.iter().map(|f| f.filename.as_str()).collect();
+        assert_eq!(names, ["a.rs", "c.rs", "b.rs"]);
+        StatSort::Path.sort(&mut files);
+        assert_eq!(files[0].filename, "a.rs");
+        StatSort::Total.sort(&mut files);
+        let names: Vec<&str> = files.iter().map(|f| f.filename.as_str()).collect();
+        assert_eq!(names, ["b.rs", "c.rs", "a.rs"]);
+
+        assert_eq!(
+            format_stat_table(&files, true),
+            "  modified    +1   -4  b.rs | +----\n  modified    +3   -1  c.rs | +++-\n  modified    +3    0  a.rs | +++\n   3 files    +7   -5"
+        );
+        // Scaled to 40 columns, keeping at least one of each
+        assert_eq!(stat_bar(200, 1, 201), (40, 1));
+        assert_eq!(stat_bar(50, 50, 400), (5, 5));
+    }
+
+    #[test]
+    fn test_binary_files() {
+        let file = |status: &str| PrFile {
+            filename: "logo.png".to_string(),
+            status: status.to_string(),
+            patch: Some(String::new()),
+            binary: true,
+            ..Default::default()
+        };
+        let sized = |before, after| DiffView {
+            binary_sizes: (before, after),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_line_numbered_diff(&file("modified"), &sized(Some(12_700), Some(13_414))),
+            "--- a/logo.png\n+++ b/logo.png\nbinary file changed (12.4 KB → 13.1 KB)"
+        );
+        assert_eq!(
+            format_line_numbered_diff(&file("added"), &sized(None, Some(812))),
+            "--- a/logo.png\n+++ b/logo.png\nbinary file added (812 B)"
+        );
+        assert_eq!(format_line_numbered_diff(&file("modified"), &DiffView::default()), "--- a/logo.png\n+++ b/logo.png\nbinary file changed");
+        assert_eq!(
+            format_line_numbered_diff(&file("removed"), &sized(Some(3_250_586), None)),
+            "deleted: logo.png (binary file, 3.1 MB)"
+        );
+        assert_eq!(format_stat_table(&[file("modified")], false), "  modified    +0    0  logo.png (binary)\n    1 file    +0    0");
+    }
+
+    #[test]
+    fn test_git_patch_rebuilds_missing_headers() {
+        let file = |status: &str, previous: Option<&str>, patch: &str| PrFile {
+            filename: "b.rs".to_string(),
+            status: status.to_string(),
+            additions: 1,
+            patch: Some(patch.to_string()),
+            previous_filename: previous.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_git_patch(&file("added", None, "@@ -0,0 +1 @@\n+a"), &[]).unwrap(),
+            "diff --git a/b.rs b/b.rs\nnew file mode 100644\n--- /dev/null\n+++ b/b.rs\n@@ -0,0 +1 @@\n+a"
+        );
+        assert_eq!(
+            format_git_patch(&file("renamed", Some("a.rs"), ""), &[]).unwrap(),
+            "diff --git a/a.rs b/b.rs\nrename from a.rs\nrename to b.rs"
+        );
+        let two = file("modified", None, "@@ -1 +1 @@\n-a\n+b\n@@ -9 +9 @@\n-c\n+d");
+        let ids = hunk_ids("b.rs", &parse_patch(two.patch.as_deref().unwrap()));
+        assert_eq!(
+            format_git_patch(&two, &ids[1..]).unwrap(),
+            "diff --git a/b.rs b/b.rs\n--- a/b.rs\n+++ b/b.rs\n@@ -9 +9 @@\n-c\n+d"
+        );
+        assert_eq!(format_git_patch(&PrFile { binary: true, ..two }, &[]), None);
+    }
+
+    #[test]
+    fn test_markdown_diff() {
+        let file = |name: &str, patch: &str| PrFile {
+            filename: name.to_string(),
+            status: "modified".to_string(),
+            additions: 2,
+            deletions: 1,
+            patch: Some(patch.to_string()),
+            ..Default::default()
+        };
+        let docs = file("docs/<guide>.md", "@@ -1,2 +1,3 @@\n ```rust\n-old()\n+new()\n+```");
+        assert_eq!(
+            format_markdown_file(&docs, &[]),
+            "<details>\n<summary>docs/&lt;guide&gt;.md (+2 −1)</summary>\n\n\
+             ````diff\n@@ -1,2 +1,3 @@\n ```rust\n-old()\n+new()\n+```\n````\n\n</details>"
+        );
+        let logo = PrFile { binary: true, ..file("a|b.png", "") };
+        assert!(format_markdown_file(&logo, &[]).contains("_Binary file, not shown._"));
+        assert_eq!(
+            format_markdown_stat_table(&[&docs, &logo]),
+            "| File | Status | + | − |\n|---|---|--:|--:|\n\
+             | `docs/<guide>.md` | modified | +2 | −1 |\n| `a\\|b.png` (binary) | modified | +2 | −1 |\n\
+             | **2 files** | | **+4** | **−2** |"
+        );
+
+        assert_eq!(fence_lang("src/main.rs"), "rust");
+        assert_eq!(fence_lang("web/App.TSX"), "tsx");
+        assert_eq!(fence_lang("docker/Dockerfile"), "dockerfile");
+        assert_eq!(fence_lang("LICENSE"), "");
+    }
+
+    #[test]
+    fn test_color_choice_and_styling() {
+        assert!(should_color("always", false, Some("1")));
+        assert!(!should_color("always", true, None));
+        assert!(!should_color("never", false, None));
+        assert!(!should_color("auto", false, Some("1")));
+        assert_eq!(styled(false, "32", "+a"), "+a");
+        assert_eq!(styled(true, "32", ""), "");
+        assert_eq!(styled(true, "36", "@@ -1 +1 @@"), "\x1b[36m@@ -1 +1 @@\x1b[m");
+        // An inner style ends, then the outer one resumes
+        assert_eq!(styled(true, "31", "a \x1b[1mb\x1b[m c"), "\x1b[31ma \x1b[1mb\x1b[m\x1b[31m c\x1b[m");
+    }
+
+    #[test]
+    fn test_max_lines_cuts_between_hunks() {
+        let file = PrFile {
+            filename: "a.rs".to_string(),
+            status: "modified".to_string(),
+            additions: 3,
+            deletions: 1,
+            patch: Some("@@ -1,2 +1,3 @@\n x\n-a\n+b\n+c\n@@ -9 +10 @@\n+d".to_string()),
+            ..Default::default()
+        };
+        let capped = |max_lines: usize| {
+            let view = DiffView {
+                max_lines: Some(max_lines),
+                ..Default::default()
+            };
+            format_line_numbered_diff(&file, &view)
+        };
+        let whole = format_line_numbered_diff(&file, &DiffView::default());
+        assert_eq!(capped(9), whole);
+        // The second hunk doesn't fit, so it's left out whole
+        let out = capped(8);
+        assert_eq!(out.lines().count(), 8);
+        assert!(out.contains("+c") && !out.contains("+d"), "{out}");
+        assert!(out.ends_with("… (2 more lines; --file a.rs with no line cap shows them)"), "{out}");
+        // Nothing fits whole, so the first hunk is cut
+        let out = capped(4);
+        assert!(out.contains(" x") && !out.contains("-a"), "{out}");
+        assert!(out.ends_with("… (5 more lines; --file a.rs with no line cap shows them)"), "{out}");
+
+        assert_eq!(thousands(7), "7");
+        assert_eq!(thousands(1842), "1,842");
+        assert_eq!(thousands(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn test_hunk_headers_show_context() {
+        let patch = "@@ -1,2 +1,2 @@ def outer():\n x\n-a\n+b\n@@ -9 +9 @@\n-c\n+d";
+        let file = PrFile {
+            filename: "p.py".to_string(),
+            status: "modified".to_string(),
+            additions: 2,
+            deletions: 2,
+            patch: Some(patch.to_string()),
+            ..Default::default()
+        };
+        let headers = |view: &DiffView| -> Vec<String> {
+            format_line_numbered_diff(&file, view)
+                .lines()
+                .filter(|l| l.starts_with("@@"))
+                .map(|l| l[..l.find(" [h:").unwrap()].to_string())
+                .collect()
+        };
+        assert_eq!(headers(&DiffView::default()), ["@@ -1,2 +1,2 @@ def outer():", "@@ -9 +9 @@"]);
+        let content = "def outer():\n    x\n    b\n\ndef inner(y):\n    pass\n\n\nd\n";
+        let resolved = DiffView {
+            context_source: Some(content),
+            ..Default::default()
+        };
+        assert_eq!(headers(&resolved), ["@@ -1,2 +1,2 @@ def outer():", "@@ -9 +9 @@ def inner(y):"]);
+    }
+
+    #[test]
+    fn test_word_diff_marks_paired_lines() {
+        let patch = "@@ -1,4 +1,3 @@\n-retry(conn, 3, false);\n-let a = 1;\n-gone();\n+retry(conn, 5, false);\n+let b = 2;\n same";
+        let file = PrFile {
+            filename: "w.rs".to_string(),
+            status: "modified".to_string(),
+            additions: 2,
+            deletions: 3,
+            patch: Some(patch.to_string()),
+            ..Default::default()
+        };
+        let plain = DiffView {
+            word_diff: Some(WordDiff::Plain),
+            ..Default::default()
+        };
+        let out = format_line_numbered_diff(&file, &plain);
+        let body: Vec<&str> = out.lines().skip(3).collect();
+        assert_eq!(
+            body,
+            [
+                "     | -retry(conn, [-3-], false);",
+                "     | -let [-a = 1-];",
+                "     | -gone();",
+                "   1 | +retry(conn, {+5+}, false);",
+                "   2 | +let {+b = 2+};",
+                "   3 |  same",
+            ]
+        );
+        let color = DiffView {
+            word_diff: Some(WordDiff::Color),
+            ..Default::default()
+        };
+        assert!(format_line_numbered_diff(&file, &color).contains("   1 | +retry(conn, \x1b[32m5\x1b[m, false);"));
+    }
+
+    #[test]
+    fn test_comment_context_snapshot() {
+        let hunks = parse_patch(PATCH);
+        let ctx = format_comment_context(&hunks, "src/main.rs", 12, 12).unwrap();
+        assert_eq!(
+            ctx,
+            "<details>\n<summary>src/main.rs:12</summary>\n\n```diff\n b\n-old\n+new\n+more\n c\n```\n\n</details>"
+        );
+        assert_eq!(
+            with_comment_context("Why?", Some(ctx.clone())),
+            format!("{ctx}\n\nWhy?")
+        );
+    }
+
+    #[test]
+    fn test_comment_context_range_and_edges() {
+        let hunks = parse_patch(PATCH);
+        let ctx = format_comment_context(&hunks, "src/main.rs", 10, 11).unwrap();
+        assert!(ctx.contains("<summary>src/main.rs:10-11</summary>"));
+        assert!(ctx.contains("```diff\n a\n b\n-old\n+new\n```"));
+        assert!(format_comment_context(&hunks, "src/main.rs", 40, 40).is_none());
+    }
+
+    #[test]
+    fn test_comment_context_fence_and_limit() {
+        let hunks = parse_patch("@@ -1,1 +1,1 @@\n-x\n+let s = \"```\";");
+        let ctx = format_comment_context(&hunks, "a.rs", 1, 1).unwrap();
+        assert!(ctx.contains("````diff\n"));
+
+        let huge = "x".repeat(MAX_COMMENT_BODY - 10);
+        assert_eq!(with_comment_context(&huge, Some(ctx)), huge);
+    }
+}
@@ -0,0 +1,249 @@
+//! `--auto-noise`: spot machine-generated files that dodge every name rule, by the
+//! shape of their diff. A file is flagged only when all three hold:
+//! - at least `MIN_CHANGED_LINES` lines changed;
+//! - the change is one-sided, over 95% additions or over 95% deletions;
+//! - the changed lines read like machine output: very long on average (minified
+//!   code), or a token entropy far from hand-written code's, either too repetitive
+//!   (snapshots, fixtures dumped from a run) or too random (hashes, encoded blobs).
+//!
+//! Hand-written code that happens to be large and one-sided, such as a new
+//! migration, fails the third test. Everything here is pure over the patch.
+
+use std::collections::HashMap;
+
+/// Fewer changed lines than this is never flagged
+pub const MIN_CHANGED_LINES: u64 = 1000;
+/// Share of the changed lines that must be additions (or deletions)
+const ONE_SIDED: f64 = 0.95;
+/// Mean characters per changed line above which lines look minified
+const LONG_LINES: f64 = 150.0;
+/// Token entropy (bits per token) below which the changed lines are too repetitive
+/// for hand-written code
+const LOW_ENTROPY: f64 = 6.0;
+/// `Shape::spread` above which nearly every token is unique
+const HIGH_SPREAD: f64 = 0.9;
+
+/// What the heuristic measured on one file's changed lines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shape {
+    pub changed: u64,
+    /// Share of the larger side (additions or deletions), 0.5 to 1
+    pub one_sided: f64,
+    pub mean_line_len: f64,
+    /// Shannon entropy, in bits, of the whitespace-separated tokens with digit runs
+    /// folded together, so `item-7` and `item-8` count as one token
+    pub token_entropy: f64,
+    /// Entropy of the tokens as written, as a share of log2 of their count: 1 when
+    /// every token is different
+    pub spread: f64,
+}
+
+impl Shape {
+    pub fn of(additions: u64, deletions: u64, patch: &str) -> Shape {
+        let changed = additions + deletions;
+        let one_sided = if changed == 0 {
+            0.0
+        } else {
+            additions.max(deletions) as f64 / changed as f64
+        };
+        let lines: Vec<&str> = patch
+            .lines()
+            .filter(|l| (l.starts_with('+') || l.starts_with('-')) && !l.starts_with("+++") && !l.starts_with("---"))
+            .map(|l| &l[1..])
+            .collect();
+        let mean_line_len = if lines.is_empty() {
+            0.0
+        } else {
+            lines.iter().map(|l| l.chars().count()).sum::<usize>() as f64 / lines.len() as f64
+        };
+        let tokens: Vec<&str> = lines.iter().flat_map(|l| l.split_whitespace()).collect();
+        let folded: Vec<String> = tokens.iter().map(|t| fold_digits(t)).collect();
+        let max = (tokens.len() as f64).log2();
+        Shape {
+            changed,
+            one_sided,
+            mean_line_len,
+            token_entropy: entropy(folded.iter().map(String::as_str)),
+            spread: if max > 0.0 { entropy(tokens.iter().copied()) / max } else { 0.0 },
+        }
+    }
+
+    pub fn looks_generated(&self) -> bool {
+        let machine_like = self.mean_line_len >= LONG_LINES
+            || self.token_entropy < LOW_ENTROPY
+            || self.spread > HIGH_SPREAD;
+        self.changed >= MIN_CHANGED_LINES && self.one_sided > ONE_SIDED && machine_like
+    }
+}
+
+fn fold_digits(token: &str) -> String {
+    let mut out = String::with_capacity(token.len());
+    for c in token.chars() {
+        let c = if c.is_ascii_digit() { '0' } else { c };
+        if !(c == '0' && out.ends_with('0')) {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn entropy<'a>(tokens: impl Iterator<Item = &'a str>) -> f64 {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut total = 0.0;
+    for t in tokens {
+        *counts.entry(t).or_insert(0) += 1;
+        total += 1.0;
+    }
+    counts
+        .values()
+        .map(|&c| {
+            let p = c as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(added: &[String], removed: &[String]) -> String {
+        let mut out = format!("@@ -1,{} +1,{} @@\n", removed.len(), added.len());
+        for l in removed {
+            out.push_str(&format!("-{l}\n"));
+        }
+        for l in added {
+            out.push_str(&format!("+{l}\n"));
+        }
+        out
+    }
+
+    fn shape_of(added: &[String], removed: &[String]) -> Shape {
+        Shape::of(added.len() as u64, removed.len() as u64, &patch(added, removed))
+    }
+
+    /// A Jest snapshot under a name no rule knows
+    fn snapshot(items: usize) -> Vec<String> {
+        (0..items)
+            .flat_map(|i| {
+                [
+                    format!("exports[`List renders item {i} 1`] = `"),
+                    "<li".to_string(),
+                    "  className=\"list-item\"".to_string(),
+                    format!("  data-id=\"{i}\""),
+                    ">".to_string(),
+                    format!("  Item {i}"),
+                    "</li>".to_string(),
+                    "`;".to_string(),
+                ]
+            })
+            .collect()
+    }
+
+    /// A webpack bundle that lost its `.min.js`
+    fn bundle() -> Vec<String> {
+        (0..1200)
+            .map(|i| {
+                format!(
+                    "/***/ {i}: (function(e,t,n){{\"use strict\";var r=n({}),o=n.n(r),i=function(e){{return o.a.createElement(\"div\",{{className:\"c{i}\",onClick:function(){{return e.onSelect({i})}}}},e.children)}};t.default=i;var a=function(e,t){{for(var n=0;n<e.length;n++)if(e[n]===t)return n;return-1}}}}),",
+                    i * 7 % 1000
+                )
+            })
+            .collect()
+    }
+
+    /// Integrity hashes, one per line
+    fn hashes() -> Vec<String> {
+        (0..1200u64)
+            .map(|i| {
+                let h = i.wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(17) ^ 0xdead_beef;
+                format!("  \"pkg-{i}\": \"sha512-{h:016x}{:016x}\",", h.rotate_left(29))
+            })
+            .collect()
+    }
+
+    /// A hand-written schema migration: large and all additions, but ordinary code
+    fn migration() -> Vec<String> {
+        let tables = [
+            ("accounts", "who owns a billing relationship"),
+            ("invoices", "one per billing period and account"),
+            ("line_items", "priced rows on an invoice"),
+            ("payments", "money received against an invoice"),
+            ("refunds", "money returned, always tied to a payment"),
+            ("customers", "people we bill, separate from login users"),
+            ("addresses", "postal addresses used for tax decisions"),
+            ("subscriptions", "recurring plans attached to an account"),
+            ("plans", "the catalogue of prices customers can pick"),
+            ("coupons", "discounts that expire or run out"),
+            ("tax_rates", "jurisdiction rates, versioned by effective date"),
+            ("audit_events", "append-only trail for finance reviews"),
+        ];
+        let columns = [
+            ("created_at", "TIMESTAMPTZ NOT NULL DEFAULT now()", "set on insert"),
+            ("updated_at", "TIMESTAMPTZ NOT NULL DEFAULT now()", "bumped by the trigger below"),
+            ("status", "TEXT NOT NULL", "draft, open, paid or void"),
+            ("amount_cents", "BIGINT NOT NULL CHECK (amount_cents >= 0)", "never negative; refunds have their own table"),
+            ("currency", "CHAR(3) NOT NULL DEFAULT 'USD'", "ISO 4217"),
+            ("external_ref", "TEXT", "the processor's id, when there is one"),
+            ("notes", "TEXT", "free text from support"),
+            ("metadata", "JSONB NOT NULL DEFAULT '{}'", "integration-specific extras"),
+            ("deleted_at", "TIMESTAMPTZ", "soft delete; rows are kept for audits"),
+            ("version", "INTEGER NOT NULL DEFAULT 1", "optimistic locking"),
+        ];
+        let mut out = vec![];
+        for round in 0..10 {
+            for (t, (table, about)) in tables.iter().enumerate() {
+                let name = format!("{table}_v{round}");
+                out.push(format!("-- {table}: {about} (step {round} of the billing split)"));
+                out.push(format!("CREATE TABLE {name} ("));
+                out.push("    id BIGSERIAL PRIMARY KEY,".to_string());
+                if t > 0 {
+                    let parent = tables[t - 1].0;
+                    out.push(format!("    {parent}_id BIGINT NOT NULL REFERENCES {parent}_v{round}(id),"));
+                }
+                for (c, (col, ty, note)) in columns.iter().enumerate().filter(|(c, _)| (c + t + round) % 3 != 0) {
+                    out.push(format!("    {col} {ty}, -- {note}"));
+                    if c == 2 {
+                        out.push(format!("    CONSTRAINT {name}_{col}_known CHECK ({col} IN ('draft', 'open', 'paid', 'void')),"));
+                    }
+                }
+                out.push(");".to_string());
+                out.push(format!("CREATE INDEX {name}_created_idx ON {name} (created_at);"));
+                out.push(format!("COMMENT ON TABLE {name} IS '{about}';"));
+                out.push(String::new());
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_flags_bulk_generated_files() {
+        for (name, lines) in [("snapshot", snapshot(300)), ("bundle", bundle()), ("hashes", hashes())] {
+            let shape = shape_of(&lines, &[]);
+            assert!(shape.looks_generated(), "{name}: {shape:?}");
+            // Deleting it is just as generated
+            assert!(shape_of(&[], &lines).looks_generated(), "{name} removed");
+        }
+    }
+
+    #[test]
+    fn test_keeps_hand_written_files() {
+        let migration = migration();
+        let shape = shape_of(&migration, &[]);
+        assert!(shape.changed >= MIN_CHANGED_LINES && shape.one_sided == 1.0, "{shape:?}");
+        assert!(!shape.looks_generated(), "{shape:?}");
+
+        // Machine-like but too small, or not one-sided
+        assert!(!shape_of(&snapshot(100), &[]).looks_generated());
+        let snapshot = snapshot(300);
+        let (added, removed) = snapshot.split_at(2000);
+        assert!(!shape_of(added, removed).looks_generated());
+    }
+
+    #[test]
+    fn test_fold_digits() {
+        assert_eq!(fold_digits("data-id=\"1234\""), "data-id=\"0\"");
+        assert_eq!(fold_digits("v2_3"), "v0_0");
+        assert_eq!(fold_digits("plain"), "plain");
+    }
+}
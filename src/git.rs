@@ -0,0 +1,162 @@
+//! Local git operations for `pr checkout`, run through the git CLI in `dir`
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// The branch (None when detached) and commit left checked out
+#[derive(Debug)]
+pub struct Checkout {
+    pub branch: Option<String>,
+    pub head_sha: String,
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let out = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git (is it installed?)")?;
+    if !out.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Whether `git` exits successfully, for yes/no queries
+fn git_ok(dir: &Path, args: &[&str]) -> bool {
+    Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .is_ok_and(|o| o.status.success())
+}
+
+/// Fetch PR `number` from `origin` via `refs/pull/N/head`, which also exists
+/// for fork PRs, and check it out on `branch` (or detached). The base branch is
+/// fetched into `origin/<base>` alongside so local diffs against it work.
+/// Without `force`, refuses to touch a dirty worktree or move `branch` off
+/// commits the PR head doesn't contain.
+pub fn checkout_pr(dir: &Path, number: u64, base: &str, branch: Option<&str>, force: bool) -> Result<Checkout> {
+    git(dir, &["rev-parse", "--git-dir"]).context("Not in a git repo")?;
+    if !force && !git(dir, &["status", "--porcelain", "--untracked-files=no"])?.is_empty() {
+        anyhow::bail!("Worktree has uncommitted changes; commit or stash them, or pass --force to discard them");
+    }
+
+    git(
+        dir,
+        &[
+            "fetch",
+            "origin",
+            &format!("refs/pull/{number}/head"),
+            &format!("+refs/heads/{base}:refs/remotes/origin/{base}"),
+        ],
+    )?;
+    // FETCH_HEAD lists refspecs in order, so it resolves to the PR head
+    let fetched = git(dir, &["rev-parse", "FETCH_HEAD"])?;
+
+    let mut args = vec!["checkout"];
+    if force {
+        args.push("--force");
+    }
+    match branch {
+        None => {
+            args.extend(["--detach", fetched.as_str()]);
+            git(dir, &args)?;
+        }
+        Some(branch) => {
+            let local = format!("refs/heads/{branch}");
+            if !force
+                && git_ok(dir, &["rev-parse", "--verify", "--quiet", &local])
+                && !git_ok(dir, &["merge-base", "--is-ancestor", &local, &fetched])
+            {
+                anyhow::bail!(
+                    "Local branch {branch} has commits the PR head doesn't; pick another --branch-name or pass --force to reset it"
+                );
+            }
+            args.extend(["-B", branch, fetched.as_str()]);
+            git(dir, &args)?;
+        }
+    }
+
+    Ok(Checkout {
+        branch: branch.map(String::from),
+        head_sha: git(dir, &["rev-parse", "HEAD"])?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A bare "origin" with `main` and PR #1's head under `refs/pull/1/head`,
+    /// plus a clone of it. Returns (scratch root, clone, PR head SHA).
+    fn fixture(name: &str) -> (PathBuf, PathBuf, String) {
+        let root = std::env::temp_dir().join(format!("gh-agent-git-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let work = root.join("work");
+        std::fs::create_dir_all(&work).unwrap();
+        let run = |dir: &Path, args: &[&str]| git(dir, args).unwrap();
+
+        run(&work, &["init", "-q", "-b", "main"]);
+        run(&work, &["config", "user.email", "t@example.com"]);
+        run(&work, &["config", "user.name", "t"]);
+        std::fs::write(work.join("a.txt"), "base\n").unwrap();
+        run(&work, &["add", "."]);
+        run(&work, &["commit", "-qm", "base"]);
+        run(&work, &["checkout", "-qb", "feature"]);
+        std::fs::write(work.join("a.txt"), "feature\n").unwrap();
+        run(&work, &["commit", "-qam", "feature"]);
+        let head = run(&work, &["rev-parse", "HEAD"]);
+        run(&work, &["checkout", "-q", "main"]);
+
+        run(&root, &["clone", "-q", "--bare", "work", "origin.git"]);
+        let origin = root.join("origin.git");
+        run(&origin, &["update-ref", "refs/pull/1/head", &head]);
+        // Like a fork PR: the head branch itself isn't on origin
+        run(&origin, &["branch", "-D", "feature"]);
+
+        run(&root, &["clone", "-q", "origin.git", "clone"]);
+        let clone = root.join("clone");
+        run(&clone, &["config", "user.email", "t@example.com"]);
+        run(&clone, &["config", "user.name", "t"]);
+        (root, clone, head)
+    }
+
+    #[test]
+    fn test_checkout_pr_branch_and_detached() {
+        let (root, clone, head) = fixture("checkout");
+
+        let out = checkout_pr(&clone, 1, "main", Some("pr-1"), false).unwrap();
+        assert_eq!(out.head_sha, head);
+        assert_eq!(git(&clone, &["branch", "--show-current"]).unwrap(), "pr-1");
+        assert!(git_ok(&clone, &["rev-parse", "--verify", "origin/main"]));
+
+        git(&clone, &["checkout", "-q", "main"]).unwrap();
+        let out = checkout_pr(&clone, 1, "main", None, false).unwrap();
+        assert_eq!((out.branch, out.head_sha), (None, head));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_checkout_pr_refuses_to_lose_work() {
+        let (root, clone, _) = fixture("refuse");
+
+        std::fs::write(clone.join("a.txt"), "edited\n").unwrap();
+        let err = checkout_pr(&clone, 1, "main", Some("pr-1"), false).unwrap_err();
+        assert!(err.to_string().contains("uncommitted changes"), "{err}");
+        checkout_pr(&clone, 1, "main", Some("pr-1"), true).unwrap();
+
+        git(&clone, &["commit", "-q", "--allow-empty", "-m", "local"]).unwrap();
+        let local = git(&clone, &["rev-parse", "HEAD"]).unwrap();
+        let err = checkout_pr(&clone, 1, "main", Some("pr-1"), false).unwrap_err();
+        assert!(err.to_string().contains("has commits the PR head doesn't"), "{err}");
+        assert_eq!(git(&clone, &["rev-parse", "HEAD"]).unwrap(), local, "branch left alone");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
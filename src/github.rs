@@ -1,11 +1,56 @@
 use anyhow::{Context, Result};
 use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT};
+use reqwest::StatusCode;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::raw_diff::{self, FilePatch};
 
 pub struct Client {
     http: reqwest::Client,
     base_url: String,
+    verbose: bool,
+    max_requests: Option<usize>,
+    requests: AtomicUsize,
+}
+
+/// Returned (inside `anyhow::Error`) when a request would exceed `--max-requests`
+#[derive(Debug)]
+pub struct BudgetExhausted {
+    pub max: usize,
+}
+
+impl std::fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request budget exhausted (--max-requests {})", self.max)
+    }
+}
+
+impl std::error::Error for BudgetExhausted {}
+
+pub fn is_budget_exhausted(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<BudgetExhausted>().is_some()
+}
+
+/// Non-success REST response, kept structured so callers can react to the status
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GitHub API error {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+pub fn api_error(err: &anyhow::Error) -> Option<&ApiError> {
+    err.downcast_ref::<ApiError>()
 }
 
 // --- GraphQL response types ---
@@ -35,6 +80,7 @@ struct RepositoryNode {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct GraphQLPullRequest {
+    id: String,
     number: u64,
     title: String,
     body: Option<String>,
@@ -42,9 +88,14 @@ struct GraphQLPullRequest {
     additions: u64,
     deletions: u64,
     changed_files: u64,
+    is_draft: bool,
     head_ref_name: String,
     base_ref_name: String,
     head_ref_oid: String,
+    #[serde(default)]
+    base_ref_oid: String,
+    #[serde(default)]
+    is_cross_repository: bool,
     files: FileConnection,
 }
 
@@ -89,22 +140,315 @@ struct FilesPagePR {
     files: FileConnection,
 }
 
+// --- PR search (pr list) ---
+
+#[derive(Debug, Deserialize)]
+struct SearchData {
+    search: SearchConnection,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchConnection {
+    page_info: PageInfo,
+    nodes: Vec<SearchPrNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchPrNode {
+    number: u64,
+    title: String,
+    body: Option<String>,
+    state: String,
+    author: Option<Actor>,
+    additions: u64,
+    deletions: u64,
+    changed_files: u64,
+    head_ref_name: String,
+    base_ref_name: String,
+    head_ref_oid: String,
+    updated_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Actor {
+    login: String,
+}
+
+// --- Merge readiness (pr status) ---
+
+// --- Draft toggling (ready / draft) ---
+
+#[derive(Debug, Deserialize)]
+struct UpdateBranchMutationData {
+    result: UpdateBranchPayload,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateBranchPayload {
+    pull_request: UpdatedPullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdatedPullRequest {
+    head_ref_oid: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DraftMutationData {
+    result: DraftPayload,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DraftPayload {
+    pull_request: DraftPullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DraftPullRequest {
+    is_draft: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusData {
+    repository: StatusRepository,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusRepository {
+    pull_request: StatusPullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusPullRequest {
+    number: u64,
+    title: String,
+    state: String,
+    is_draft: bool,
+    mergeable: String,
+    merge_state_status: String,
+    review_decision: Option<String>,
+    head_ref_name: String,
+    head_ref_oid: String,
+    #[serde(default)]
+    is_cross_repository: bool,
+    review_requests: ReviewRequestConnection,
+    commits: StatusCommitConnection,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReviewRequestConnection {
+    nodes: Vec<ReviewRequestNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReviewRequestNode {
+    requested_reviewer: Option<RequestedReviewer>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RequestedReviewer {
+    Team { slug: String, organization: Actor },
+    User { login: String },
+    /// Mannequins, bots and other reviewer types we don't render
+    Other {},
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusCommitConnection {
+    nodes: Vec<StatusCommitNode>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusCommitNode {
+    commit: StatusCommit,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusCommit {
+    status_check_rollup: Option<CheckRollup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRollup {
+    state: String,
+    contexts: RollupContextConnection,
+}
+
+#[derive(Debug, Deserialize)]
+struct RollupContextConnection {
+    nodes: Vec<RollupContext>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "__typename")]
+enum RollupContext {
+    CheckRun {
+        name: String,
+        status: String,
+        conclusion: Option<String>,
+        #[serde(rename = "detailsUrl")]
+        details_url: Option<String>,
+    },
+    StatusContext {
+        context: String,
+        state: String,
+        #[serde(rename = "targetUrl")]
+        target_url: Option<String>,
+    },
+}
+
+// --- Review threads (pr comments --unresolved) ---
+
+#[derive(Debug, Deserialize)]
+struct ThreadsData {
+    repository: ThreadsRepository,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ThreadsRepository {
+    pull_request: ThreadsPullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ThreadsPullRequest {
+    review_threads: ThreadConnection,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ThreadConnection {
+    page_info: PageInfo,
+    nodes: Vec<ThreadNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ThreadNode {
+    id: String,
+    is_resolved: bool,
+    is_outdated: bool,
+    path: String,
+    line: Option<u64>,
+    original_line: Option<u64>,
+    comments: ThreadComments,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentReviewsData {
+    repository: RecentReviewsRepository,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecentReviewsRepository {
+    pull_request: RecentReviewsPullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentReviewsPullRequest {
+    reviews: RecentReviewNodes,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentReviewNodes {
+    nodes: Vec<RecentReviewNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecentReviewNode {
+    database_id: u64,
+    author: Option<Actor>,
+    state: String,
+    submitted_at: Option<String>,
+    body: String,
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ThreadComments {
+    nodes: Vec<ThreadComment>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ThreadComment {
+    database_id: Option<u64>,
+    author: Option<Actor>,
+    body: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveMutationData {
+    result: ResolvePayload,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolvePayload {
+    thread: ThreadState,
+}
+
+/// A review thread's state after resolving or unresolving it
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadState {
+    pub id: String,
+    pub is_resolved: bool,
+    pub path: String,
+    pub line: Option<u64>,
+}
+
 // --- REST file type (has patch) ---
 
+/// A changed file as the commits, compare and PR files APIs list it
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
 struct RestPrFile {
     filename: String,
+    #[serde(default)]
     status: String,
+    #[serde(default)]
     additions: u64,
+    #[serde(default)]
     deletions: u64,
     patch: Option<String>,
+    previous_filename: Option<String>,
+}
+
+impl From<RestPrFile> for PrFile {
+    fn from(f: RestPrFile) -> Self {
+        PrFile {
+            filename: f.filename,
+            status: f.status,
+            additions: f.additions,
+            deletions: f.deletions,
+            patch: f.patch,
+            patch_too_large: false,
+            previous_filename: f.previous_filename,
+            binary: false,
+            git_header: None,
+        }
+    }
 }
 
 // --- Public types ---
 
 #[derive(Debug, Clone)]
 pub struct PullRequest {
+    /// GraphQL node id, for mutations
+    pub node_id: String,
     pub number: u64,
     pub title: String,
     pub body: Option<String>,
@@ -112,24 +456,58 @@ pub struct PullRequest {
     pub additions: u64,
     pub deletions: u64,
     pub changed_files: u64,
+    pub is_draft: bool,
     pub head_ref: String,
     pub base_ref: String,
     pub head_sha: String,
+    /// The base branch's tip, as GitHub last computed it for the PR
+    pub base_sha: String,
+    /// Whether the head branch lives in a fork rather than in the base repo
+    pub is_cross_repository: bool,
     pub files: Vec<PrFile>,
 }
 
+/// PR metadata without files, as returned by `list_prs`
 #[derive(Debug, Clone)]
+pub struct PrSummary {
+    pub number: u64,
+    pub title: String,
+    pub body: Option<String>,
+    pub state: String,
+    pub author: String,
+    pub additions: u64,
+    pub deletions: u64,
+    pub changed_files: u64,
+    pub head_ref: String,
+    pub base_ref: String,
+    pub head_sha: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct PrFile {
     pub filename: String,
     pub status: String,
     pub additions: u64,
     pub deletions: u64,
     pub patch: Option<String>,
+    /// GitHub left the patch out for its size; `pr file` still reads the file
+    pub patch_too_large: bool,
+    /// The path before a rename or copy, when the patches say
+    pub previous_filename: Option<String>,
+    /// No text diff: git's diff says binary, or the name and line counts suggest it
+    pub binary: bool,
+    /// The `diff --git` line and extended headers before the first hunk, as the
+    /// raw diff has them
+    pub git_header: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct FileContent {
     pub content: Option<String>,
+    /// In bytes
+    #[serde(default)]
+    pub size: u64,
     #[allow(dead_code)]
     pub encoding: Option<String>,
 }
@@ -137,120 +515,720 @@ pub struct FileContent {
 #[derive(Debug, Serialize)]
 pub struct CreateReview {
     pub commit_id: String,
+    /// Empty leaves the review pending
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub event: String,
     pub body: String,
     pub comments: Vec<ReviewCommentInput>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ReviewCommentInput {
     pub path: String,
     pub line: u64,
     pub body: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub start_line: Option<u64>,
+    /// "LEFT" for lines of the base; GitHub's default is RIGHT, the head
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub side: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_side: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SingleReviewComment<'a> {
+    commit_id: &'a str,
+    path: &'a str,
+    line: u64,
+    side: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_line: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_side: Option<&'a str>,
+    body: &'a str,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateReviewResponse {
     pub id: u64,
     pub html_url: String,
+    /// GraphQL node id, which adding comments to a pending review needs
+    #[serde(default)]
+    pub node_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PendingReviewRequest<'a> {
+    commit_id: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct SubmitReviewRequest<'a> {
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<&'a str>,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct CodeSearchResponse {
-    pub total_count: u64,
-    pub items: Vec<CodeSearchItem>,
+struct AddThreadData {
+    result: AddThreadPayload,
 }
 
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-pub struct CodeSearchItem {
-    pub name: String,
-    pub path: String,
-    pub repository: CodeSearchRepo,
-    pub html_url: String,
-    pub text_matches: Option<Vec<TextMatch>>,
+struct AddThreadPayload {
+    thread: AddedThread,
 }
 
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-pub struct CodeSearchRepo {
-    pub full_name: String,
+struct AddedThread {
+    id: String,
 }
 
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-pub struct TextMatch {
-    pub fragment: String,
-    pub matches: Vec<TextMatchLocation>,
+struct ReviewMutationData {
+    result: ReviewMutationPayload,
 }
 
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-pub struct TextMatchLocation {
-    pub indices: Vec<u64>,
+#[serde(rename_all = "camelCase")]
+struct ReviewMutationPayload {
+    pull_request_review: ReviewMutationNode,
 }
 
-/// Parse a raw unified diff string into a map of filename -> patch content
-fn parse_raw_diff(raw: &str) -> std::collections::HashMap<String, String> {
-    let mut map = std::collections::HashMap::new();
-    let mut current_file: Option<String> = None;
-    let mut current_patch = String::new();
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReviewMutationNode {
+    id: String,
+    database_id: u64,
+    url: String,
+}
 
-    for line in raw.lines() {
-        if line.starts_with("diff --git ") {
-            // Save previous file's patch
-            if let Some(file) = current_file.take() {
-                if !current_patch.is_empty() {
-                    map.insert(file, current_patch.trim_start_matches('\n').to_string());
-                }
-            }
-            current_patch = String::new();
-        } else if line.starts_with("+++ b/") {
-            current_file = Some(line[6..].to_string());
-        } else if line.starts_with("@@") || current_file.is_some() && !line.starts_with("--- ") && !line.starts_with("+++ ") && !line.starts_with("index ") && !line.starts_with("new file") && !line.starts_with("deleted file") && !line.starts_with("old mode") && !line.starts_with("new mode") && !line.starts_with("similarity") && !line.starts_with("rename ") {
-            if current_file.is_some() {
-                if !current_patch.is_empty() {
-                    current_patch.push('\n');
-                }
-                current_patch.push_str(line);
-            }
+impl From<ReviewMutationData> for CreateReviewResponse {
+    fn from(data: ReviewMutationData) -> Self {
+        let review = data.result.pull_request_review;
+        CreateReviewResponse {
+            id: review.database_id,
+            html_url: review.url,
+            node_id: review.id,
         }
     }
+}
+
+#[derive(Debug, Deserialize)]
+struct RestReview {
+    id: u64,
+    user: Option<Actor>,
+    #[serde(default)]
+    body: Option<String>,
+    state: String,
+    submitted_at: Option<String>,
+    html_url: String,
+    #[serde(default)]
+    commit_id: Option<String>,
+}
 
-    // Save last file
-    if let Some(file) = current_file {
-        if !current_patch.is_empty() {
-            map.insert(file, current_patch.trim_start_matches('\n').to_string());
+impl From<RestReview> for Review {
+    fn from(r: RestReview) -> Self {
+        Review {
+            id: r.id,
+            author: r.user.map(|u| u.login).unwrap_or_else(|| "ghost".to_string()),
+            state: r.state,
+            submitted_at: r.submitted_at,
+            body: r.body.unwrap_or_default(),
+            html_url: r.html_url,
+            commit_id: r.commit_id,
         }
     }
-
-    map
 }
 
-fn map_change_type(ct: &str) -> String {
-    match ct {
-        "ADDED" => "added".to_string(),
-        "DELETED" | "REMOVED" => "removed".to_string(),
-        "MODIFIED" | "CHANGED" => "modified".to_string(),
-        "RENAMED" => "renamed".to_string(),
-        "COPIED" => "copied".to_string(),
-        other => other.to_lowercase(),
-    }
+#[derive(Debug, Serialize)]
+struct DismissReviewRequest<'a> {
+    message: &'a str,
+    event: &'a str,
 }
 
-fn split_repo(repo: &str) -> Result<(&str, &str)> {
-    repo.split_once('/')
-        .ok_or_else(|| anyhow::anyhow!("Repository must be in owner/repo format, got: {repo}"))
+#[derive(Debug, Deserialize)]
+struct RestTeam {
+    slug: String,
+    organization: Actor,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestCommit {
+    sha: String,
+    commit: RestCommitDetail,
+    author: Option<Actor>,
+    #[serde(default)]
+    stats: Option<RestCommitStats>,
+    #[serde(default)]
+    files: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestCommitDetail {
+    message: String,
+    author: Option<RestGitAuthor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestGitAuthor {
+    name: String,
+    date: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestCommitStats {
+    additions: u64,
+    deletions: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestComparison {
+    merge_base_commit: RestSha,
+    commits: Vec<RestCommit>,
+    #[serde(default)]
+    files: Vec<RestPrFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestSha {
+    sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestTree {
+    tree: Vec<RestTreeEntry>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestTreeEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRunsPage {
+    total_count: usize,
+    check_runs: Vec<RestCheckRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestCheckRun {
+    name: String,
+    status: String,
+    conclusion: Option<String>,
+    details_url: Option<String>,
+    html_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestReviewComment {
+    id: u64,
+    path: String,
+    line: Option<u64>,
+    original_line: Option<u64>,
+    start_line: Option<u64>,
+    original_start_line: Option<u64>,
+    commit_id: Option<String>,
+    original_commit_id: Option<String>,
+    user: Option<Actor>,
+    created_at: String,
+    body: String,
+    in_reply_to_id: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CombinedStatus {
+    statuses: Vec<CommitStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitStatus {
+    context: String,
+    state: String,
+    target_url: Option<String>,
+}
+
+/// A commit in a PR, oldest first
+#[derive(Debug, Clone)]
+pub struct PrCommit {
+    pub sha: String,
+    /// GitHub login, or the git author name when the commit isn't linked to an account
+    pub author: String,
+    /// ISO 8601 author date
+    pub date: String,
+    pub message: String,
+}
+
+impl From<RestCommit> for PrCommit {
+    fn from(c: RestCommit) -> Self {
+        let (name, date) = c.commit.author.map(|a| (a.name, a.date)).unwrap_or_default();
+        PrCommit {
+            sha: c.sha,
+            author: c.author.map(|a| a.login).unwrap_or(name),
+            date,
+            message: c.commit.message,
+        }
+    }
+}
+
+impl PrCommit {
+    pub fn short_sha(&self) -> &str {
+        &self.sha[..self.sha.len().min(7)]
+    }
+
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+}
+
+/// A review thread, as GraphQL sees it (REST has no thread objects)
+#[derive(Debug, Clone)]
+pub struct ReviewThread {
+    /// GraphQL node id, what the resolve mutations take
+    pub id: String,
+    pub path: String,
+    /// Line in the current diff, or the original line when the thread is outdated
+    pub line: Option<u64>,
+    pub resolved: bool,
+    /// The thread's first comment (matches `ReviewComment::thread_id`)
+    pub root_comment_id: Option<u64>,
+    pub author: String,
+    /// First comment's body
+    pub body: String,
+}
+
+impl From<ThreadNode> for ReviewThread {
+    fn from(t: ThreadNode) -> Self {
+        let root = t.comments.nodes.into_iter().next();
+        ReviewThread {
+            id: t.id,
+            path: t.path,
+            line: if t.is_outdated { t.original_line } else { t.line },
+            resolved: t.is_resolved,
+            root_comment_id: root.as_ref().and_then(|c| c.database_id),
+            author: root
+                .as_ref()
+                .and_then(|c| c.author.as_ref())
+                .map_or_else(|| "ghost".to_string(), |a| a.login.clone()),
+            body: root.map(|c| c.body).unwrap_or_default(),
+        }
+    }
+}
+
+/// An inline review comment
+#[derive(Debug, Clone)]
+pub struct ReviewComment {
+    pub id: u64,
+    /// Id of the comment that started the thread (equal to `id` for the first comment)
+    pub thread_id: u64,
+    pub path: String,
+    /// Line in the current diff, or the original line when the comment is outdated
+    pub line: Option<u64>,
+    /// First line of a multi-line comment, counted like `line`
+    pub start_line: Option<u64>,
+    /// Commit `line` refers to (the original commit when outdated); empty if unknown
+    pub commit_id: String,
+    /// The commented line is no longer part of the diff
+    pub outdated: bool,
+    pub author: String,
+    /// ISO 8601 creation time
+    pub created_at: String,
+    pub body: String,
+}
+
+impl From<RestReviewComment> for ReviewComment {
+    fn from(c: RestReviewComment) -> Self {
+        let outdated = c.line.is_none();
+        let (line, start_line, commit_id) = if outdated {
+            (c.original_line, c.original_start_line, c.original_commit_id)
+        } else {
+            (c.line, c.start_line, c.commit_id)
+        };
+        ReviewComment {
+            id: c.id,
+            thread_id: c.in_reply_to_id.unwrap_or(c.id),
+            path: c.path,
+            line,
+            start_line,
+            commit_id: commit_id.unwrap_or_default(),
+            outdated,
+            author: c.user.map(|u| u.login).unwrap_or_else(|| "ghost".to_string()),
+            created_at: c.created_at,
+            body: c.body,
+        }
+    }
+}
+
+/// A submitted (or pending) PR review
+#[derive(Debug, Clone)]
+pub struct Review {
+    pub id: u64,
+    pub author: String,
+    /// APPROVED, CHANGES_REQUESTED, COMMENTED, DISMISSED or PENDING
+    pub state: String,
+    /// ISO 8601; absent for PENDING reviews
+    pub submitted_at: Option<String>,
+    pub body: String,
+    pub html_url: String,
+    /// Head commit the review was made on (only `get_reviews` fills it in)
+    pub commit_id: Option<String>,
+}
+
+/// Per-commit change totals
+#[derive(Debug, Clone)]
+pub struct CommitStat {
+    pub files: usize,
+    pub additions: u64,
+    pub deletions: u64,
+}
+
+/// One commit's totals and the files it changed. A file's patch is None when
+/// GitHub leaves it out (binary or very large files).
+#[derive(Debug, Clone)]
+pub struct CommitDetail {
+    pub stat: CommitStat,
+    pub files: Vec<PrFile>,
+}
+
+/// What `head` has that `base` doesn't, since their merge base
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub merge_base_sha: String,
+    /// Oldest first; the API returns at most 250
+    pub commits: Vec<PrCommit>,
+    /// Paths changed between the merge base and `head`; the API returns at most 300
+    pub files: Vec<String>,
+}
+
+/// A CI check on a commit: either a check run or a legacy commit status.
+/// `status` is "queued", "in_progress", "pending" or "completed";
+/// `conclusion` is set once completed ("success", "failure", ...).
+#[derive(Debug, Clone)]
+pub struct CheckRun {
+    pub name: String,
+    pub status: String,
+    pub conclusion: Option<String>,
+    pub details_url: Option<String>,
+}
+
+/// Everything `pr status` needs to decide whether a PR is ready to merge
+#[derive(Debug, Clone)]
+pub struct PrStatus {
+    pub number: u64,
+    pub title: String,
+    pub state: String,
+    pub is_draft: bool,
+    /// MERGEABLE, CONFLICTING or UNKNOWN (GitHub computes it asynchronously)
+    pub mergeable: String,
+    /// CLEAN, BLOCKED, BEHIND, DIRTY, UNSTABLE, HAS_HOOKS, DRAFT or UNKNOWN
+    pub merge_state_status: String,
+    /// APPROVED, CHANGES_REQUESTED or REVIEW_REQUIRED; None when reviews aren't required
+    pub review_decision: Option<String>,
+    /// Logins and "org/team" slugs still asked to review
+    pub requested_reviewers: Vec<String>,
+    pub head_ref: String,
+    pub head_sha: String,
+    /// Whether the head branch lives in a fork rather than in the base repo
+    pub is_cross_repository: bool,
+    /// Overall rollup state (SUCCESS, FAILURE, PENDING, ...), None when nothing reported
+    pub checks_state: Option<String>,
+    pub checks: Vec<CheckRun>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Label {
+    pub name: String,
+    /// Hex color without the leading `#`
+    pub color: String,
+}
+
+/// Users and teams still asked to review a PR
+#[derive(Debug, Clone, Default)]
+pub struct RequestedReviewers {
+    pub users: Vec<String>,
+    /// "org/team" slugs
+    pub teams: Vec<String>,
+}
+
+/// Both the requested_reviewers endpoint and the PR object returned by
+/// requesting reviews list the reviewers, under different keys
+#[derive(Debug, Deserialize)]
+struct RestRequestedReviewers {
+    #[serde(alias = "requested_reviewers")]
+    users: Vec<Actor>,
+    #[serde(alias = "requested_teams")]
+    teams: Vec<RestTeamSlug>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestTeamSlug {
+    slug: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ReviewersRequest {
+    reviewers: Vec<String>,
+    team_reviewers: Vec<String>,
+}
+
+impl ReviewersRequest {
+    /// Sort logins and "org/team" slugs into their request fields. GitHub wants
+    /// bare team slugs, and only teams of the repo's own organization can review.
+    fn new(repo: &str, reviewers: &[String]) -> Result<Self> {
+        let (owner, _) = split_repo(repo)?;
+        let mut req = Self::default();
+        for reviewer in reviewers {
+            let reviewer = reviewer.trim_start_matches('@');
+            match reviewer.split_once('/') {
+                Some((org, team)) if org.eq_ignore_ascii_case(owner) => req.team_reviewers.push(team.to_string()),
+                Some((org, _)) => anyhow::bail!(
+                    "Team {reviewer} belongs to {org}; only teams of {owner} can review {repo}"
+                ),
+                None => req.reviewers.push(reviewer.to_string()),
+            }
+        }
+        Ok(req)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct UpdateBranchRequest<'a> {
+    expected_head_sha: &'a str,
+}
+
+/// GitHub's 202 for `update-branch`: the merge from base runs in the background
+#[derive(Debug, Deserialize)]
+pub struct UpdateBranchAccepted {
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct StateRequest<'a> {
+    state: &'a str,
+}
+
+/// A PR's state after `PATCH /pulls/{number}`: "open" or "closed"
+#[derive(Debug, Deserialize)]
+pub struct PrState {
+    pub state: String,
+}
+
+#[derive(Debug, Serialize)]
+struct IssueCommentRequest<'a> {
+    body: &'a str,
+}
+
+/// A posted issue comment or review reply
+#[derive(Debug, Deserialize)]
+pub struct IssueComment {
+    pub html_url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AssigneesRequest<'a> {
+    assignees: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+struct MilestoneRequest {
+    /// None clears the milestone
+    milestone: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestAssignment {
+    #[serde(default)]
+    assignees: Vec<Actor>,
+    milestone: Option<Milestone>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Milestone {
+    pub number: u64,
+    pub title: String,
+}
+
+/// Who a PR is assigned to and its milestone, as the issues API reports them
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub assignees: Vec<String>,
+    pub milestone: Option<Milestone>,
+}
+
+impl From<RestAssignment> for Assignment {
+    fn from(raw: RestAssignment) -> Self {
+        Assignment {
+            assignees: raw.assignees.into_iter().map(|a| a.login).collect(),
+            milestone: raw.milestone,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AddLabelsRequest<'a> {
+    labels: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+struct MergeRequest<'a> {
+    merge_method: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commit_title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commit_message: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeResult {
+    pub sha: String,
+    pub merged: bool,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CodeSearchResponse {
+    pub total_count: u64,
+    pub items: Vec<CodeSearchItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct CodeSearchItem {
+    pub name: String,
+    pub path: String,
+    pub repository: CodeSearchRepo,
+    pub html_url: String,
+    pub text_matches: Option<Vec<TextMatch>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct CodeSearchRepo {
+    pub full_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct TextMatch {
+    pub fragment: String,
+    pub matches: Vec<TextMatchLocation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct TextMatchLocation {
+    pub indices: Vec<u64>,
+}
+
+/// Attach each file's patch from the PR's diff
+fn with_patches(pr: PullRequest, patches: &HashMap<String, FilePatch>) -> PullRequest {
+    let files = pr.files.into_iter().map(|mut f| {
+        if let Some(p) = patches.get(&f.filename) {
+            f.patch = p.patch.clone();
+            f.patch_too_large = p.patch.is_none() && !p.binary;
+            f.previous_filename = p.old_path.clone();
+            f.binary = p.binary;
+            f.git_header = p.header.clone();
+        }
+        f
+    }).collect();
+
+    PullRequest {
+        files,
+        ..pr
+    }
+}
+
+/// The PR's files that `raw_diff` (a range within the PR) changes, with that
+/// change's patch and line counts. A file the PR adds is only `added` in the
+/// range that creates it.
+fn with_range_patches(pr: PullRequest, raw_diff: &str) -> PullRequest {
+    let mut patches = raw_diff::parse(raw_diff);
+    let files = pr
+        .files
+        .into_iter()
+        .filter_map(|f| {
+            let FilePatch { patch: Some(patch), old_path, binary, header } = patches.remove(&f.filename)? else {
+                return None;
+            };
+            let count = |prefix: char| patch.lines().filter(|l| l.starts_with(prefix)).count() as u64;
+            let status = match f.status.as_str() {
+                _ if patch.starts_with("@@ -0,0 ") => "added".to_string(),
+                "added" => "modified".to_string(),
+                _ => f.status,
+            };
+            Some(PrFile {
+                status,
+                additions: count('+'),
+                deletions: count('-'),
+                patch: Some(patch),
+                previous_filename: old_path,
+                binary,
+                git_header: header,
+                ..f
+            })
+        })
+        .collect();
+    PullRequest { files, ..pr }
+}
+
+fn map_change_type(ct: &str) -> String {
+    match ct {
+        "ADDED" => "added".to_string(),
+        "DELETED" | "REMOVED" => "removed".to_string(),
+        "MODIFIED" | "CHANGED" => "modified".to_string(),
+        "RENAMED" => "renamed".to_string(),
+        "COPIED" => "copied".to_string(),
+        other => other.to_lowercase(),
+    }
+}
+
+/// Mask credential-bearing query params before a URL is logged
+fn redact_url(url: &reqwest::Url) -> String {
+    if !url.query_pairs().any(|(k, _)| k.contains("token")) {
+        return url.to_string();
+    }
+    let mut redacted = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(k, v)| {
+            let v = if k.contains("token") { "***".to_string() } else { v.into_owned() };
+            (k.into_owned(), v)
+        })
+        .collect();
+    redacted.query_pairs_mut().clear().extend_pairs(pairs);
+    redacted.to_string()
+}
+
+fn split_repo(repo: &str) -> Result<(&str, &str)> {
+    repo.split_once('/')
+        .ok_or_else(|| anyhow::anyhow!("Repository must be in owner/repo format, got: {repo}"))
+}
+
+fn requested_reviewers(repo: &str, raw: RestRequestedReviewers) -> Result<RequestedReviewers> {
+    let (owner, _) = split_repo(repo)?;
+    Ok(RequestedReviewers {
+        users: raw.users.into_iter().map(|u| u.login).collect(),
+        teams: raw.teams.into_iter().map(|t| format!("{owner}/{}", t.slug)).collect(),
+    })
 }
 
 impl Client {
-    pub fn new() -> Result<Self> {
+    /// `verbose` logs every API request (method, URL, status, size, timing) to stderr.
+    /// `max_requests` caps the total number of API requests this client will send.
+    pub fn new(verbose: bool, max_requests: Option<usize>) -> Result<Self> {
         let token = std::env::var("GITHUB_TOKEN")
             .or_else(|_| Self::token_from_gh_cli())
             .context("Set GITHUB_TOKEN or install/auth gh CLI")?;
+        Self::with_base_url("https://api.github.com", &token, verbose, max_requests)
+    }
 
+    fn with_base_url(base_url: &str, token: &str, verbose: bool, max_requests: Option<usize>) -> Result<Self> {
         let mut headers = HeaderMap::new();
         headers.insert(
             AUTHORIZATION,
@@ -272,10 +1250,51 @@ impl Client {
 
         Ok(Self {
             http,
-            base_url: "https://api.github.com".to_string(),
+            base_url: base_url.to_string(),
+            verbose,
+            max_requests,
+            requests: AtomicUsize::new(0),
         })
     }
 
+    /// Client pointed at a local mock server
+    #[cfg(test)]
+    pub fn for_test(base_url: &str, max_requests: Option<usize>) -> Self {
+        Self::with_base_url(base_url, "test-token", false, max_requests).unwrap()
+    }
+
+    /// Number of API requests sent so far
+    pub fn requests_used(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// Fail up front if `planned` more requests would not fit in the budget.
+    /// Call before a write phase so a capped run never posts half its writes.
+    pub fn check_budget(&self, planned: usize) -> Result<()> {
+        if let Some(max) = self.max_requests {
+            let used = self.requests_used();
+            if used + planned > max {
+                anyhow::bail!(
+                    "needs {planned} more requests but only {} of --max-requests {max} remain",
+                    max.saturating_sub(used)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Claim one request from the budget
+    fn reserve(&self) -> Result<()> {
+        let used = self.requests.fetch_add(1, Ordering::SeqCst);
+        if let Some(max) = self.max_requests {
+            if used >= max {
+                self.requests.fetch_sub(1, Ordering::SeqCst);
+                return Err(BudgetExhausted { max }.into());
+            }
+        }
+        Ok(())
+    }
+
     fn token_from_gh_cli() -> Result<String> {
         let output = std::process::Command::new("gh")
             .args(["auth", "token"])
@@ -287,6 +1306,39 @@ impl Client {
         Ok(String::from_utf8(output.stdout)?.trim().to_string())
     }
 
+    // --- Transport ---
+
+    /// Send a request and read the full body. With `verbose`, logs one line per request.
+    async fn send(&self, req: reqwest::RequestBuilder) -> Result<(StatusCode, String)> {
+        let req = req.build()?;
+        self.reserve()?;
+        let method = req.method().clone();
+        let url = redact_url(req.url());
+        let start = std::time::Instant::now();
+
+        let result = async {
+            let resp = self.http.execute(req).await?;
+            let status = resp.status();
+            let body = resp.text().await?;
+            Ok::<_, reqwest::Error>((status, body))
+        }
+        .await;
+
+        if self.verbose {
+            let ms = start.elapsed().as_millis();
+            match &result {
+                Ok((status, body)) => eprintln!(
+                    "[http] {method} {url} → {} ({} bytes, {ms} ms)",
+                    status.as_u16(),
+                    body.len(),
+                ),
+                Err(e) => eprintln!("[http] {method} {url} → failed after {ms} ms: {e}"),
+            }
+        }
+
+        Ok(result?)
+    }
+
     // --- GraphQL ---
 
     async fn graphql<T: DeserializeOwned>(&self, query: &str, variables: &serde_json::Value) -> Result<T> {
@@ -295,13 +1347,11 @@ impl Client {
             "variables": variables,
         });
         let url = format!("{}/graphql", self.base_url);
-        let resp = self.http.post(&url).json(&body).send().await?;
-        let status = resp.status();
+        let (status, text) = self.send(self.http.post(&url).json(&body)).await?;
         if !status.is_success() {
-            let text = resp.text().await.unwrap_or_default();
             anyhow::bail!("GitHub GraphQL error {status}: {text}");
         }
-        let gql_resp: GraphQLResponse<T> = resp.json().await?;
+        let gql_resp: GraphQLResponse<T> = serde_json::from_str(&text)?;
         if let Some(errors) = gql_resp.errors {
             let msgs: Vec<String> = errors.into_iter().map(|e| e.message).collect();
             anyhow::bail!("GraphQL errors: {}", msgs.join("; "));
@@ -309,17 +1359,28 @@ impl Client {
         gql_resp.data.ok_or_else(|| anyhow::anyhow!("No data in GraphQL response"))
     }
 
+    /// Run a mutation taking a single `input` argument. Errors name the mutation,
+    /// since GitHub's messages alone often don't say which write failed.
+    async fn graphql_mutation<T: DeserializeOwned>(
+        &self,
+        name: &str,
+        mutation: &str,
+        input: serde_json::Value,
+    ) -> Result<T> {
+        self.graphql(mutation, &serde_json::json!({ "input": input }))
+            .await
+            .with_context(|| format!("{name} failed"))
+    }
+
     // --- REST helpers ---
 
     async fn rest_get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
         let url = format!("{}{}", self.base_url, path);
-        let resp = self.http.get(&url).send().await?;
-        let status = resp.status();
+        let (status, body) = self.send(self.http.get(&url)).await?;
         if !status.is_success() {
-            let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("GitHub API error {status}: {body}");
+            return Err(ApiError { status, body }.into());
         }
-        Ok(resp.json().await?)
+        Ok(serde_json::from_str(&body)?)
     }
 
     async fn rest_get_all_pages<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
@@ -331,13 +1392,11 @@ impl Client {
                 "{}{}{}per_page=100&page={}",
                 self.base_url, path, sep, page
             );
-            let resp = self.http.get(&url).send().await?;
-            let status = resp.status();
+            let (status, body) = self.send(self.http.get(&url)).await?;
             if !status.is_success() {
-                let body = resp.text().await.unwrap_or_default();
-                anyhow::bail!("GitHub API error {status}: {body}");
+                return Err(ApiError { status, body }.into());
             }
-            let items: Vec<T> = resp.json().await?;
+            let items: Vec<T> = serde_json::from_str(&body)?;
             if items.is_empty() {
                 break;
             }
@@ -349,13 +1408,56 @@ impl Client {
 
     async fn rest_post<B: Serialize, R: DeserializeOwned>(&self, path: &str, body: &B) -> Result<R> {
         let url = format!("{}{}", self.base_url, path);
-        let resp = self.http.post(&url).json(body).send().await?;
-        let status = resp.status();
+        let (status, text) = self.send(self.http.post(&url).json(body)).await?;
+        if !status.is_success() {
+            return Err(ApiError { status, body: text }.into());
+        }
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    async fn rest_put<B: Serialize, R: DeserializeOwned>(&self, path: &str, body: &B) -> Result<R> {
+        let url = format!("{}{}", self.base_url, path);
+        let (status, text) = self.send(self.http.put(&url).json(body)).await?;
         if !status.is_success() {
-            let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("GitHub API error {status}: {body}");
+            return Err(ApiError { status, body: text }.into());
         }
-        Ok(resp.json().await?)
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    async fn rest_patch<B: Serialize, R: DeserializeOwned>(&self, path: &str, body: &B) -> Result<R> {
+        let url = format!("{}{}", self.base_url, path);
+        let (status, text) = self.send(self.http.patch(&url).json(body)).await?;
+        if !status.is_success() {
+            return Err(ApiError { status, body: text }.into());
+        }
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    async fn rest_delete(&self, path: &str) -> Result<()> {
+        let url = format!("{}{}", self.base_url, path);
+        let (status, text) = self.send(self.http.delete(&url)).await?;
+        if !status.is_success() {
+            return Err(ApiError { status, body: text }.into());
+        }
+        Ok(())
+    }
+
+    async fn rest_delete_with<B: Serialize>(&self, path: &str, body: &B) -> Result<()> {
+        let url = format!("{}{}", self.base_url, path);
+        let (status, text) = self.send(self.http.delete(&url).json(body)).await?;
+        if !status.is_success() {
+            return Err(ApiError { status, body: text }.into());
+        }
+        Ok(())
+    }
+
+    async fn rest_delete_json<B: Serialize, R: DeserializeOwned>(&self, path: &str, body: &B) -> Result<R> {
+        let url = format!("{}{}", self.base_url, path);
+        let (status, text) = self.send(self.http.delete(&url).json(body)).await?;
+        if !status.is_success() {
+            return Err(ApiError { status, body: text }.into());
+        }
+        Ok(serde_json::from_str(&text)?)
     }
 
     // --- Public API ---
@@ -368,6 +1470,7 @@ impl Client {
 query($owner: String!, $repo: String!, $number: Int!) {
   repository(owner: $owner, name: $repo) {
     pullRequest(number: $number) {
+      id
       number
       title
       body
@@ -375,9 +1478,12 @@ query($owner: String!, $repo: String!, $number: Int!) {
       additions
       deletions
       changedFiles
+      isDraft
       headRefName
       baseRefName
       headRefOid
+      baseRefOid
+      isCrossRepository
       files(first: 100) {
         pageInfo { hasNextPage endCursor }
         nodes {
@@ -407,6 +1513,10 @@ query($owner: String!, $repo: String!, $number: Int!) {
             additions: f.additions,
             deletions: f.deletions,
             patch: None,
+            patch_too_large: false,
+            previous_filename: None,
+            binary: false,
+            git_header: None,
         }).collect();
 
         // Paginate remaining files
@@ -421,12 +1531,17 @@ query($owner: String!, $repo: String!, $number: Int!) {
                     additions: f.additions,
                     deletions: f.deletions,
                     patch: None,
+                    patch_too_large: false,
+                    previous_filename: None,
+                    binary: false,
+                    git_header: None,
                 });
             }
             page_info = more.page_info;
         }
 
         Ok(PullRequest {
+            node_id: pr.id,
             number: pr.number,
             title: pr.title,
             body: pr.body,
@@ -434,9 +1549,12 @@ query($owner: String!, $repo: String!, $number: Int!) {
             additions: pr.additions,
             deletions: pr.deletions,
             changed_files: pr.changed_files,
+            is_draft: pr.is_draft,
             head_ref: pr.head_ref_name,
             base_ref: pr.base_ref_name,
             head_sha: pr.head_ref_oid,
+            base_sha: pr.base_ref_oid,
+            is_cross_repository: pr.is_cross_repository,
             files,
         })
     }
@@ -465,54 +1583,176 @@ query($owner: String!, $repo: String!, $number: Int!, $cursor: String!) {
   }
 }
 "#;
-        let vars = serde_json::json!({
-            "owner": owner,
-            "repo": name,
-            "number": number as i64,
-            "cursor": cursor,
-        });
+        let vars = serde_json::json!({
+            "owner": owner,
+            "repo": name,
+            "number": number as i64,
+            "cursor": cursor,
+        });
+
+        let data: FilesPageData = self.graphql(QUERY, &vars).await?;
+        Ok(data.repository.pull_request.files)
+    }
+
+    /// List PRs via GraphQL search, most recently updated first.
+    /// `state` is "open", "closed" or "all"; labels are ANDed. Pages until `limit` is reached.
+    pub async fn list_prs(
+        &self,
+        repo: &str,
+        state: &str,
+        author: Option<&str>,
+        labels: &[String],
+        base: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<PrSummary>> {
+        split_repo(repo)?;
+
+        const QUERY: &str = r#"
+query($q: String!, $first: Int!, $cursor: String) {
+  search(query: $q, type: ISSUE, first: $first, after: $cursor) {
+    pageInfo { hasNextPage endCursor }
+    nodes {
+      ... on PullRequest {
+        number
+        title
+        body
+        state
+        author { login }
+        additions
+        deletions
+        changedFiles
+        headRefName
+        baseRefName
+        headRefOid
+        updatedAt
+      }
+    }
+  }
+}
+"#;
 
-        let data: FilesPageData = self.graphql(QUERY, &vars).await?;
-        Ok(data.repository.pull_request.files)
+        let mut q = format!("repo:{repo} is:pr sort:updated-desc");
+        match state {
+            "open" => q.push_str(" is:open"),
+            "closed" => q.push_str(" is:closed"),
+            _ => {}
+        }
+        if let Some(author) = author {
+            q.push_str(&format!(" author:{author}"));
+        }
+        for label in labels {
+            q.push_str(&format!(" label:\"{label}\""));
+        }
+        if let Some(base) = base {
+            q.push_str(&format!(" base:{base}"));
+        }
+
+        let mut prs = Vec::new();
+        let mut cursor: Option<String> = None;
+        while prs.len() < limit {
+            let vars = serde_json::json!({
+                "q": q,
+                "first": (limit - prs.len()).min(100) as i64,
+                "cursor": cursor,
+            });
+            let data: SearchData = self.graphql(QUERY, &vars).await?;
+            prs.extend(data.search.nodes.into_iter().map(|n| PrSummary {
+                number: n.number,
+                title: n.title,
+                body: n.body,
+                state: n.state,
+                author: n.author.map(|a| a.login).unwrap_or_else(|| "ghost".to_string()),
+                additions: n.additions,
+                deletions: n.deletions,
+                changed_files: n.changed_files,
+                head_ref: n.head_ref_name,
+                base_ref: n.base_ref_name,
+                head_sha: n.head_ref_oid,
+                updated_at: n.updated_at,
+            }));
+            if !data.search.page_info.has_next_page {
+                break;
+            }
+            cursor = data.search.page_info.end_cursor;
+        }
+        prs.truncate(limit);
+        Ok(prs)
     }
 
     /// Fetch the raw unified diff for a PR (single request, no pagination)
     async fn get_pr_raw_diff(&self, repo: &str, number: u64) -> Result<String> {
         let url = format!("{}/repos/{}/pulls/{}", self.base_url, repo, number);
-        let resp = self.http
-            .get(&url)
-            .header(ACCEPT, "application/vnd.github.diff")
-            .send()
+        let (status, body) = self
+            .send(self.http.get(&url).header(ACCEPT, "application/vnd.github.diff"))
             .await?;
-        let status = resp.status();
         if !status.is_success() {
-            let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("GitHub API error {status}: {body}");
+            return Err(ApiError { status, body }.into());
+        }
+        Ok(body)
+    }
+
+    /// Each file's patch, from the raw diff. GitHub refuses that with 406 past
+    /// about 300 files or 20k lines; the paged file list is used then, which
+    /// leaves out the patch of each file over its own size limit, and of binary
+    /// files. Those are told apart by extension, or by a modified file having no
+    /// changed lines.
+    async fn get_pr_patches(&self, repo: &str, number: u64) -> Result<HashMap<String, FilePatch>> {
+        match self.get_pr_raw_diff(repo, number).await {
+            Err(err) if api_error(&err).is_some_and(|a| a.status == StatusCode::NOT_ACCEPTABLE) => {
+                let files: Vec<RestPrFile> = self
+                    .rest_get_all_pages(&format!("/repos/{repo}/pulls/{number}/files"))
+                    .await?;
+                Ok(files
+                    .into_iter()
+                    .map(|f| {
+                        let binary = f.patch.is_none()
+                            && (raw_diff::binary_extension(&f.filename)
+                                || f.status == "modified" && f.additions + f.deletions == 0);
+                        let file = FilePatch { patch: f.patch, old_path: f.previous_filename, binary, header: None };
+                        (f.filename, file)
+                    })
+                    .collect())
+            }
+            raw_diff => Ok(raw_diff::parse(&raw_diff?)),
         }
-        Ok(resp.text().await?)
     }
 
-    /// Fetch PR metadata (GraphQL) + raw diff (REST) in parallel
+    /// Fetch PR metadata (GraphQL) + patches (REST) in parallel
     pub async fn get_pr_with_patches(&self, repo: &str, number: u64) -> Result<PullRequest> {
-        let (pr, raw_diff) = tokio::try_join!(
+        let (pr, patches) = tokio::try_join!(
             self.get_pr(repo, number),
-            self.get_pr_raw_diff(repo, number),
+            self.get_pr_patches(repo, number),
         )?;
 
-        // Parse raw unified diff into per-file patches
-        let patch_map = parse_raw_diff(&raw_diff);
+        Ok(with_patches(pr, &patches))
+    }
 
-        let files = pr.files.into_iter().map(|mut f| {
-            if let Some(patch) = patch_map.get(&f.filename) {
-                f.patch = Some(patch.clone());
-            }
-            f
-        }).collect();
+    /// The raw unified diff between two refs (`GET /compare/{base}...{head}` as
+    /// `application/vnd.github.diff`): from their merge base to `head`
+    pub async fn compare_diff(&self, repo: &str, base: &str, head: &str) -> Result<String> {
+        let url = format!("{}/repos/{repo}/compare/{base}...{head}", self.base_url);
+        let (status, body) = self
+            .send(self.http.get(&url).header(ACCEPT, "application/vnd.github.diff"))
+            .await?;
+        if !status.is_success() {
+            return Err(ApiError { status, body }.into());
+        }
+        Ok(body)
+    }
 
-        Ok(PullRequest {
-            files,
-            ..pr
-        })
+    /// Replace the files of a PR fetched with `get_pr` by how they changed from
+    /// `from` to `to`. Only the PR's changed files are kept, and only those that
+    /// changed in that range.
+    pub async fn with_range_patches(&self, repo: &str, pr: PullRequest, from: &str, to: &str) -> Result<PullRequest> {
+        let raw_diff = self.compare_diff(repo, from, to).await?;
+        Ok(with_range_patches(pr, &raw_diff))
+    }
+
+    /// Fill in the per-file patches of a PR fetched with `get_pr`, for callers
+    /// that check its metadata before paying for the diff
+    pub async fn add_patches(&self, repo: &str, pr: PullRequest) -> Result<PullRequest> {
+        let patches = self.get_pr_patches(repo, pr.number).await?;
+        Ok(with_patches(pr, &patches))
     }
 
     pub async fn get_file_content(
@@ -530,6 +1770,14 @@ query($owner: String!, $repo: String!, $number: Int!, $cursor: String!) {
         Ok(String::from_utf8(bytes)?)
     }
 
+    /// A file's size in bytes at `git_ref`
+    pub async fn get_file_size(&self, repo: &str, path: &str, git_ref: &str) -> Result<u64> {
+        let fc: FileContent = self
+            .rest_get(&format!("/repos/{repo}/contents/{path}?ref={git_ref}"))
+            .await?;
+        Ok(fc.size)
+    }
+
     /// Fetch before/after contents for a list of files.
     /// Returns Vec of (filename, status, before_content, after_content).
     /// Fetches all files concurrently. Silently skips files that fail (binary, too large, etc).
@@ -540,10 +1788,13 @@ query($owner: String!, $repo: String!, $number: Int!, $cursor: String!) {
         base_ref: &str,
         head_ref: &str,
     ) -> Vec<(String, String, Option<String>, Option<String>)> {
+        let skipped = AtomicUsize::new(0);
+        let skipped = &skipped;
         let futs: Vec<_> = files
             .iter()
             .map(|f| {
                 let filename = f.filename.clone();
+                let old_name = f.previous_filename.clone().unwrap_or_else(|| filename.clone());
                 let status = f.status.clone();
                 let repo = repo.to_string();
                 let base = base_ref.to_string();
@@ -553,13 +1804,13 @@ query($owner: String!, $repo: String!, $number: Int!, $cursor: String!) {
                     let before = if status == "added" {
                         None
                     } else {
-                        self.get_file_content(&repo, &filename, &base).await.ok()
+                        self.fetch_or_skip(&repo, &old_name, &base, skipped).await
                     };
 
                     let after = if status == "removed" {
                         None
                     } else {
-                        self.get_file_content(&repo, &filename, &head).await.ok()
+                        self.fetch_or_skip(&repo, &filename, &head, skipped).await
                     };
 
                     (filename, status, before, after)
@@ -567,33 +1818,431 @@ query($owner: String!, $repo: String!, $number: Int!, $cursor: String!) {
             })
             .collect();
 
-        futures::future::join_all(futs).await
+        let pairs = futures::future::join_all(futs).await;
+        let skipped = skipped.load(Ordering::SeqCst);
+        if skipped > 0 {
+            eprintln!("warning: request budget exhausted; {skipped} file versions not fetched (results are partial)");
+        }
+        pairs
+    }
+
+    /// `get_file_content` that swallows errors, counting the ones caused by the request budget
+    async fn fetch_or_skip(&self, repo: &str, path: &str, git_ref: &str, skipped: &AtomicUsize) -> Option<String> {
+        match self.get_file_content(repo, path, git_ref).await {
+            Ok(content) => Some(content),
+            Err(e) => {
+                if is_budget_exhausted(&e) {
+                    skipped.fetch_add(1, Ordering::SeqCst);
+                }
+                None
+            }
+        }
+    }
+
+    /// Login of the authenticated user
+    pub async fn get_authenticated_user(&self) -> Result<String> {
+        let user: Actor = self.rest_get("/user").await?;
+        Ok(user.login)
+    }
+
+    /// Teams the authenticated user belongs to, as "org/slug"
+    pub async fn get_user_teams(&self) -> Result<Vec<String>> {
+        let teams: Vec<RestTeam> = self.rest_get_all_pages("/user/teams").await?;
+        Ok(teams
+            .into_iter()
+            .map(|t| format!("{}/{}", t.organization.login, t.slug))
+            .collect())
+    }
+
+    /// List the commits in a PR (oldest first). GitHub caps this endpoint at 250 commits.
+    pub async fn get_pr_commits(&self, repo: &str, number: u64) -> Result<Vec<PrCommit>> {
+        let commits: Vec<RestCommit> = self
+            .rest_get_all_pages(&format!("/repos/{repo}/pulls/{number}/commits"))
+            .await?;
+        Ok(commits.into_iter().map(PrCommit::from).collect())
+    }
+
+    /// Every file path in the tree at `sha` (`GET /git/trees/{sha}?recursive=1`),
+    /// and whether GitHub truncated the listing (very large repos)
+    pub async fn get_tree_paths(&self, repo: &str, sha: &str) -> Result<(Vec<String>, bool)> {
+        let t: RestTree = self.rest_get(&format!("/repos/{repo}/git/trees/{sha}?recursive=1")).await?;
+        let paths = t.tree.into_iter().filter(|e| e.kind == "blob").map(|e| e.path).collect();
+        Ok((paths, t.truncated))
+    }
+
+    /// Compare two refs (`GET /compare/{base}...{head}`)
+    pub async fn compare(&self, repo: &str, base: &str, head: &str) -> Result<Comparison> {
+        let c: RestComparison = self.rest_get(&format!("/repos/{repo}/compare/{base}...{head}")).await?;
+        Ok(Comparison {
+            merge_base_sha: c.merge_base_commit.sha,
+            commits: c.commits.into_iter().map(PrCommit::from).collect(),
+            files: c.files.into_iter().map(|f| f.filename).collect(),
+        })
+    }
+
+    /// How `path` changed between two refs (`GET /compare/{base}...{head}`), or
+    /// None if it didn't. A rename counts under either name. The API lists at most
+    /// 300 files, so a path past that is missed.
+    pub async fn compare_path(&self, repo: &str, base: &str, head: &str, path: &str) -> Result<Option<PrFile>> {
+        let c: RestComparison = self.rest_get(&format!("/repos/{repo}/compare/{base}...{head}")).await?;
+        Ok(c.files
+            .into_iter()
+            .find(|f| f.filename == path || f.previous_filename.as_deref() == Some(path))
+            .map(PrFile::from))
+    }
+
+    /// Commits on `sha` touching `path` since the ISO 8601 time `since`, counting at most 100
+    pub async fn count_path_commits(&self, repo: &str, path: &str, sha: &str, since: &str) -> Result<usize> {
+        let commits: Vec<serde_json::Value> = self
+            .rest_get(&format!(
+                "/repos/{repo}/commits?sha={}&path={}&since={since}&per_page=100",
+                urlencoding::encode(sha),
+                urlencoding::encode(path)
+            ))
+            .await?;
+        Ok(commits.len())
+    }
+
+    /// Paths a single commit touched
+    pub async fn get_commit_files(&self, repo: &str, sha: &str) -> Result<Vec<String>> {
+        let c: RestCommit = self.rest_get(&format!("/repos/{repo}/commits/{sha}")).await?;
+        Ok(c.files
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|f| f["filename"].as_str().map(String::from))
+            .collect())
+    }
+
+    /// Totals and per-file patches for a single commit, in one request
+    pub async fn get_commit_detail(&self, repo: &str, sha: &str) -> Result<CommitDetail> {
+        let c: RestCommit = self.rest_get(&format!("/repos/{repo}/commits/{sha}")).await?;
+        let stats = c.stats.unwrap_or(RestCommitStats { additions: 0, deletions: 0 });
+        let files = c.files.unwrap_or_default();
+        Ok(CommitDetail {
+            stat: CommitStat {
+                files: files.len(),
+                additions: stats.additions,
+                deletions: stats.deletions,
+            },
+            files: files
+                .into_iter()
+                .filter_map(|f| serde_json::from_value::<RestPrFile>(f).ok())
+                .map(PrFile::from)
+                .collect(),
+        })
+    }
+
+    /// Mergeability, review decision, requested reviewers and head check rollup in one query
+    pub async fn get_pr_status(&self, repo: &str, number: u64) -> Result<PrStatus> {
+        let (owner, name) = split_repo(repo)?;
+
+        const QUERY: &str = r#"
+query($owner: String!, $repo: String!, $number: Int!) {
+  repository(owner: $owner, name: $repo) {
+    pullRequest(number: $number) {
+      number
+      title
+      state
+      isDraft
+      mergeable
+      mergeStateStatus
+      reviewDecision
+      headRefName
+      headRefOid
+      isCrossRepository
+      reviewRequests(first: 100) {
+        nodes {
+          requestedReviewer {
+            ... on User { login }
+            ... on Team { slug organization { login } }
+          }
+        }
+      }
+      commits(last: 1) {
+        nodes {
+          commit {
+            statusCheckRollup {
+              state
+              contexts(first: 100) {
+                nodes {
+                  __typename
+                  ... on CheckRun { name status conclusion detailsUrl }
+                  ... on StatusContext { context state targetUrl }
+                }
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+        let vars = serde_json::json!({
+            "owner": owner,
+            "repo": name,
+            "number": number,
+        });
+        let data: StatusData = self.graphql(QUERY, &vars).await?;
+        let pr = data.repository.pull_request;
+
+        let requested_reviewers = pr
+            .review_requests
+            .nodes
+            .into_iter()
+            .filter_map(|n| match n.requested_reviewer? {
+                RequestedReviewer::Team { slug, organization } => Some(format!("{}/{slug}", organization.login)),
+                RequestedReviewer::User { login } => Some(login),
+                RequestedReviewer::Other {} => None,
+            })
+            .collect();
+
+        let rollup = pr
+            .commits
+            .nodes
+            .into_iter()
+            .next()
+            .and_then(|n| n.commit.status_check_rollup);
+        let (checks_state, checks) = match rollup {
+            Some(r) => {
+                // Normalize to the REST spelling `format::check_outcome` expects
+                let checks = r
+                    .contexts
+                    .nodes
+                    .into_iter()
+                    .map(|c| match c {
+                        RollupContext::CheckRun { name, status, conclusion, details_url } => CheckRun {
+                            name,
+                            status: status.to_lowercase(),
+                            conclusion: conclusion.map(|c| c.to_lowercase()),
+                            details_url,
+                        },
+                        RollupContext::StatusContext { context, state, target_url } => {
+                            let state = state.to_lowercase();
+                            let (status, conclusion) = match state.as_str() {
+                                "pending" | "expected" => ("pending".to_string(), None),
+                                _ => ("completed".to_string(), Some(state)),
+                            };
+                            CheckRun {
+                                name: context,
+                                status,
+                                conclusion,
+                                details_url: target_url,
+                            }
+                        }
+                    })
+                    .collect();
+                (Some(r.state), checks)
+            }
+            None => (None, vec![]),
+        };
+
+        Ok(PrStatus {
+            number: pr.number,
+            title: pr.title,
+            state: pr.state,
+            is_draft: pr.is_draft,
+            mergeable: pr.mergeable,
+            merge_state_status: pr.merge_state_status,
+            review_decision: pr.review_decision,
+            requested_reviewers,
+            head_ref: pr.head_ref_name,
+            head_sha: pr.head_ref_oid,
+            is_cross_repository: pr.is_cross_repository,
+            checks_state,
+            checks,
+        })
+    }
+
+    /// All inline review comments on a PR, in creation order
+    pub async fn get_review_comments(&self, repo: &str, number: u64) -> Result<Vec<ReviewComment>> {
+        let comments: Vec<RestReviewComment> = self
+            .rest_get_all_pages(&format!("/repos/{repo}/pulls/{number}/comments"))
+            .await?;
+        Ok(comments.into_iter().map(ReviewComment::from).collect())
+    }
+
+    /// One inline review comment by id
+    pub async fn get_review_comment(&self, repo: &str, comment_id: u64) -> Result<ReviewComment> {
+        let comment: RestReviewComment = self.rest_get(&format!("/repos/{repo}/pulls/comments/{comment_id}")).await?;
+        Ok(comment.into())
+    }
+
+    /// Start a thread with a single review comment on `commit_id`, outside any review
+    pub async fn create_review_comment(
+        &self,
+        repo: &str,
+        number: u64,
+        commit_id: &str,
+        comment: &ReviewCommentInput,
+    ) -> Result<IssueComment> {
+        self.rest_post(
+            &format!("/repos/{repo}/pulls/{number}/comments"),
+            &SingleReviewComment {
+                commit_id,
+                path: &comment.path,
+                line: comment.line,
+                side: comment.side.as_deref().unwrap_or("RIGHT"),
+                start_line: comment.start_line,
+                start_side: comment.start_side.as_deref(),
+                body: &comment.body,
+            },
+        )
+        .await
+    }
+
+    /// Reply in the thread started by `comment_id`, which must be the thread's first comment
+    pub async fn reply_to_review_comment(
+        &self,
+        repo: &str,
+        number: u64,
+        comment_id: u64,
+        body: &str,
+    ) -> Result<IssueComment> {
+        self.rest_post(
+            &format!("/repos/{repo}/pulls/{number}/comments/{comment_id}/replies"),
+            &IssueCommentRequest { body },
+        )
+        .await
+    }
+
+    /// Ids of the first comment of every unresolved review thread (matches `ReviewComment::thread_id`)
+    pub async fn get_unresolved_thread_ids(&self, repo: &str, number: u64) -> Result<HashSet<u64>> {
+        Ok(self
+            .get_review_threads(repo, number)
+            .await?
+            .into_iter()
+            .filter(|t| !t.resolved)
+            .filter_map(|t| t.root_comment_id)
+            .collect())
+    }
+
+    /// Every review thread on a PR, with its node id, position and resolved state
+    pub async fn get_review_threads(&self, repo: &str, number: u64) -> Result<Vec<ReviewThread>> {
+        let (owner, name) = split_repo(repo)?;
+
+        const QUERY: &str = r#"
+query($owner: String!, $repo: String!, $number: Int!, $cursor: String) {
+  repository(owner: $owner, name: $repo) {
+    pullRequest(number: $number) {
+      reviewThreads(first: 100, after: $cursor) {
+        pageInfo { hasNextPage endCursor }
+        nodes {
+          id
+          isResolved
+          isOutdated
+          path
+          line
+          originalLine
+          comments(first: 1) { nodes { databaseId author { login } body } }
+        }
+      }
+    }
+  }
+}
+"#;
+
+        let mut all = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let vars = serde_json::json!({
+                "owner": owner,
+                "repo": name,
+                "number": number,
+                "cursor": cursor,
+            });
+            let data: ThreadsData = self.graphql(QUERY, &vars).await?;
+            let threads = data.repository.pull_request.review_threads;
+            all.extend(threads.nodes.into_iter().map(ReviewThread::from));
+            if !threads.page_info.has_next_page {
+                break;
+            }
+            cursor = threads.page_info.end_cursor;
+        }
+        Ok(all)
+    }
+
+    /// Resolve a review thread by node id, or unresolve it when `resolved` is false.
+    /// Returns the thread's state afterwards.
+    pub async fn set_thread_resolved(&self, thread_id: &str, resolved: bool) -> Result<ThreadState> {
+        let (name, input_type) = if resolved {
+            ("resolveReviewThread", "ResolveReviewThreadInput")
+        } else {
+            ("unresolveReviewThread", "UnresolveReviewThreadInput")
+        };
+        let mutation = format!(
+            "mutation($input: {input_type}!) {{ result: {name}(input: $input) {{ thread {{ id isResolved path line }} }} }}"
+        );
+        let data: ResolveMutationData = self
+            .graphql_mutation(name, &mutation, serde_json::json!({ "threadId": thread_id }))
+            .await?;
+        Ok(data.result.thread)
     }
 
-    /// Search code in a repo via GitHub Code Search API (searches default branch).
-    /// Returns up to 100 results (API limit per page).
-    pub async fn search_code(&self, repo: &str, query: &str, path_prefix: Option<&str>) -> Result<CodeSearchResponse> {
-        let mut q = format!("{} repo:{}", query, repo);
-        if let Some(prefix) = path_prefix {
-            q.push_str(&format!(" path:{}", prefix));
+    /// Fetch all check runs plus legacy commit statuses for a commit
+    pub async fn get_check_runs(&self, repo: &str, sha: &str) -> Result<Vec<CheckRun>> {
+        let mut checks = Vec::new();
+        let mut fetched = 0usize;
+        let mut page = 1u32;
+        loop {
+            let resp: CheckRunsPage = self
+                .rest_get(&format!(
+                    "/repos/{repo}/commits/{sha}/check-runs?per_page=100&page={page}"
+                ))
+                .await?;
+            let count = resp.check_runs.len();
+            fetched += count;
+            checks.extend(resp.check_runs.into_iter().map(|r| CheckRun {
+                name: r.name,
+                status: r.status,
+                conclusion: r.conclusion,
+                details_url: r.details_url.or(r.html_url),
+            }));
+            if count == 0 || fetched >= resp.total_count {
+                break;
+            }
+            page += 1;
         }
 
-        let encoded_q = urlencoding::encode(&q);
+        let combined: CombinedStatus = self
+            .rest_get(&format!("/repos/{repo}/commits/{sha}/status?per_page=100"))
+            .await?;
+        checks.extend(combined.statuses.into_iter().map(|s| {
+            let (status, conclusion) = match s.state.as_str() {
+                "pending" => ("pending".to_string(), None),
+                _ => ("completed".to_string(), Some(s.state)),
+            };
+            CheckRun {
+                name: s.context,
+                status,
+                conclusion,
+                details_url: s.target_url,
+            }
+        }));
+
+        Ok(checks)
+    }
+
+    /// Run a GitHub Code Search query (see `search::code_search_queries`; searches the
+    /// default branch). Returns up to 100 results (API limit per page).
+    pub async fn search_code(&self, q: &str) -> Result<CodeSearchResponse> {
+        let encoded_q = urlencoding::encode(q);
         let url = format!("{}/search/code?q={}&per_page=100", self.base_url, encoded_q);
 
-        let resp = self.http
-            .get(&url)
-            .header(reqwest::header::ACCEPT, "application/vnd.github.text-match+json")
-            .send()
+        let (status, body) = self
+            .send(
+                self.http
+                    .get(&url)
+                    .header(reqwest::header::ACCEPT, "application/vnd.github.text-match+json"),
+            )
             .await?;
 
-        let status = resp.status();
         if !status.is_success() {
-            let body = resp.text().await.unwrap_or_default();
             anyhow::bail!("GitHub Code Search error {status}: {body}");
         }
 
-        Ok(resp.json().await?)
+        Ok(serde_json::from_str(&body)?)
     }
 
     pub async fn create_review(
@@ -605,4 +2254,293 @@ query($owner: String!, $repo: String!, $number: Int!, $cursor: String!) {
         self.rest_post(&format!("/repos/{repo}/pulls/{number}/reviews"), review)
             .await
     }
+
+    /// Open a pending review on `commit_id`: created without an event, it stays
+    /// invisible to others until submitted
+    pub async fn create_pending_review(&self, repo: &str, number: u64, commit_id: &str) -> Result<CreateReviewResponse> {
+        self.rest_post(
+            &format!("/repos/{repo}/pulls/{number}/reviews"),
+            &PendingReviewRequest { commit_id },
+        )
+        .await
+    }
+
+    /// Add a comment on `path` (lines `start_line..=line` of `side`) to a
+    /// pending review. REST can't add to an existing pending review, so this goes
+    /// through GraphQL. Returns the new thread's node id.
+    pub async fn add_pending_review_comment(
+        &self,
+        review_node_id: &str,
+        path: &str,
+        line: u64,
+        start_line: Option<u64>,
+        side: &str,
+        body: &str,
+    ) -> Result<String> {
+        const MUTATION: &str = "mutation($input: AddPullRequestReviewThreadInput!) { result: addPullRequestReviewThread(input: $input) { thread { id } } }";
+        let mut input = serde_json::json!({
+            "pullRequestReviewId": review_node_id,
+            "path": path,
+            "line": line,
+            "side": side,
+            "body": body,
+        });
+        if let Some(start) = start_line {
+            input["startLine"] = start.into();
+            input["startSide"] = side.into();
+        }
+        let data: AddThreadData = self
+            .graphql_mutation("addPullRequestReviewThread", MUTATION, input)
+            .await?;
+        Ok(data.result.thread.id)
+    }
+
+    /// Start a pending review on `commit_oid` through GraphQL (`pr review --via graphql`)
+    pub async fn start_review_graphql(&self, pr_node_id: &str, commit_oid: &str) -> Result<CreateReviewResponse> {
+        const MUTATION: &str = "mutation($input: AddPullRequestReviewInput!) { result: addPullRequestReview(input: $input) { pullRequestReview { id databaseId url } } }";
+        let input = serde_json::json!({ "pullRequestId": pr_node_id, "commitOID": commit_oid });
+        let data: ReviewMutationData = self.graphql_mutation("addPullRequestReview", MUTATION, input).await?;
+        Ok(data.into())
+    }
+
+    /// Submit a pending review started with `start_review_graphql`
+    pub async fn submit_review_graphql(&self, review_node_id: &str, event: &str, body: &str) -> Result<CreateReviewResponse> {
+        const MUTATION: &str = "mutation($input: SubmitPullRequestReviewInput!) { result: submitPullRequestReview(input: $input) { pullRequestReview { id databaseId url } } }";
+        let input = serde_json::json!({ "pullRequestReviewId": review_node_id, "event": event, "body": body });
+        let data: ReviewMutationData = self.graphql_mutation("submitPullRequestReview", MUTATION, input).await?;
+        Ok(data.into())
+    }
+
+    /// Submit a pending review with `event` (COMMENT, APPROVE or REQUEST_CHANGES)
+    pub async fn submit_pending_review(
+        &self,
+        repo: &str,
+        number: u64,
+        review_id: u64,
+        event: &str,
+        body: Option<&str>,
+    ) -> Result<CreateReviewResponse> {
+        self.rest_post(
+            &format!("/repos/{repo}/pulls/{number}/reviews/{review_id}/events"),
+            &SubmitReviewRequest { event, body },
+        )
+        .await
+    }
+
+    /// Delete a pending review and its comments (submitted reviews can't be deleted)
+    pub async fn delete_pending_review(&self, repo: &str, number: u64, review_id: u64) -> Result<()> {
+        self.rest_delete(&format!("/repos/{repo}/pulls/{number}/reviews/{review_id}"))
+            .await
+    }
+
+    /// Labels on a PR (PRs share the issues labels API)
+    pub async fn get_labels(&self, repo: &str, number: u64) -> Result<Vec<Label>> {
+        self.rest_get_all_pages(&format!("/repos/{repo}/issues/{number}/labels"))
+            .await
+    }
+
+    /// Add labels, creating any the repo doesn't have yet. Returns the PR's labels afterwards.
+    pub async fn add_labels(&self, repo: &str, number: u64, labels: &[String]) -> Result<Vec<Label>> {
+        self.rest_post(&format!("/repos/{repo}/issues/{number}/labels"), &AddLabelsRequest { labels })
+            .await
+    }
+
+    pub async fn remove_label(&self, repo: &str, number: u64, name: &str) -> Result<()> {
+        // Label names may contain spaces and slashes, which must not split the path
+        let name = urlencoding::encode(name);
+        self.rest_delete(&format!("/repos/{repo}/issues/{number}/labels/{name}"))
+            .await
+    }
+
+    pub async fn get_assignment(&self, repo: &str, number: u64) -> Result<Assignment> {
+        let raw: RestAssignment = self.rest_get(&format!("/repos/{repo}/issues/{number}")).await?;
+        Ok(raw.into())
+    }
+
+    /// Whether `login` can be assigned in `repo`. GitHub silently drops
+    /// unassignable logins from an assign call, so check first.
+    pub async fn is_assignable(&self, repo: &str, login: &str) -> Result<bool> {
+        let url = format!("{}/repos/{repo}/assignees/{}", self.base_url, urlencoding::encode(login));
+        let (status, body) = self.send(self.http.get(&url)).await?;
+        match status {
+            StatusCode::NO_CONTENT => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(ApiError { status, body }.into()),
+        }
+    }
+
+    /// Returns the assignment afterwards
+    pub async fn add_assignees(&self, repo: &str, number: u64, logins: &[String]) -> Result<Assignment> {
+        let raw: RestAssignment = self
+            .rest_post(&format!("/repos/{repo}/issues/{number}/assignees"), &AssigneesRequest { assignees: logins })
+            .await?;
+        Ok(raw.into())
+    }
+
+    /// Returns the assignment afterwards
+    pub async fn remove_assignees(&self, repo: &str, number: u64, logins: &[String]) -> Result<Assignment> {
+        let raw: RestAssignment = self
+            .rest_delete_json(&format!("/repos/{repo}/issues/{number}/assignees"), &AssigneesRequest { assignees: logins })
+            .await?;
+        Ok(raw.into())
+    }
+
+    /// Open and closed milestones
+    pub async fn get_milestones(&self, repo: &str) -> Result<Vec<Milestone>> {
+        self.rest_get_all_pages(&format!("/repos/{repo}/milestones?state=all")).await
+    }
+
+    /// Set the milestone by number, or clear it with None. Returns the assignment afterwards.
+    pub async fn set_milestone(&self, repo: &str, number: u64, milestone: Option<u64>) -> Result<Assignment> {
+        let raw: RestAssignment = self
+            .rest_patch(&format!("/repos/{repo}/issues/{number}"), &MilestoneRequest { milestone })
+            .await?;
+        Ok(raw.into())
+    }
+
+    pub async fn get_requested_reviewers(&self, repo: &str, number: u64) -> Result<RequestedReviewers> {
+        let raw: RestRequestedReviewers = self
+            .rest_get(&format!("/repos/{repo}/pulls/{number}/requested_reviewers"))
+            .await?;
+        requested_reviewers(repo, raw)
+    }
+
+    /// Ask users and "org/team" slugs to review. Returns who is requested afterwards.
+    pub async fn request_reviewers(&self, repo: &str, number: u64, reviewers: &[String]) -> Result<RequestedReviewers> {
+        let req = ReviewersRequest::new(repo, reviewers)?;
+        let raw: RestRequestedReviewers = self
+            .rest_post(&format!("/repos/{repo}/pulls/{number}/requested_reviewers"), &req)
+            .await?;
+        requested_reviewers(repo, raw)
+    }
+
+    pub async fn remove_requested_reviewers(&self, repo: &str, number: u64, reviewers: &[String]) -> Result<()> {
+        let req = ReviewersRequest::new(repo, reviewers)?;
+        self.rest_delete_with(&format!("/repos/{repo}/pulls/{number}/requested_reviewers"), &req)
+            .await
+    }
+
+    /// Merge a PR with `method` ("merge", "squash" or "rebase"); title and message
+    /// default to GitHub's own when None
+    pub async fn merge_pr(
+        &self,
+        repo: &str,
+        number: u64,
+        method: &str,
+        commit_title: Option<&str>,
+        commit_message: Option<&str>,
+    ) -> Result<MergeResult> {
+        let req = MergeRequest {
+            merge_method: method,
+            commit_title,
+            commit_message,
+        };
+        self.rest_put(&format!("/repos/{repo}/pulls/{number}/merge"), &req)
+            .await
+    }
+
+    /// Close (`state = "closed"`) or reopen (`"open"`) a PR
+    pub async fn set_pr_state(&self, repo: &str, number: u64, state: &str) -> Result<PrState> {
+        self.rest_patch(&format!("/repos/{repo}/pulls/{number}"), &StateRequest { state })
+            .await
+    }
+
+    /// A top-level conversation comment (PRs share the issues comments API)
+    pub async fn add_issue_comment(&self, repo: &str, number: u64, body: &str) -> Result<IssueComment> {
+        self.rest_post(&format!("/repos/{repo}/issues/{number}/comments"), &IssueCommentRequest { body })
+            .await
+    }
+
+    pub async fn delete_branch(&self, repo: &str, branch: &str) -> Result<()> {
+        self.rest_delete(&format!("/repos/{repo}/git/refs/heads/{branch}"))
+            .await
+    }
+
+    /// Mark a PR ready for review (`draft = false`) or convert it back to a draft.
+    /// Returns the draft state GitHub reports afterwards.
+    pub async fn set_pr_draft(&self, node_id: &str, draft: bool) -> Result<bool> {
+        let (name, input_type) = if draft {
+            ("convertPullRequestToDraft", "ConvertPullRequestToDraftInput")
+        } else {
+            ("markPullRequestReadyForReview", "MarkPullRequestReadyForReviewInput")
+        };
+        let mutation = format!(
+            "mutation($input: {input_type}!) {{ result: {name}(input: $input) {{ pullRequest {{ isDraft }} }} }}"
+        );
+        let data: DraftMutationData = self
+            .graphql_mutation(name, &mutation, serde_json::json!({ "pullRequestId": node_id }))
+            .await?;
+        Ok(data.result.pull_request.is_draft)
+    }
+
+    /// Merge the base branch into the PR's head, as the "Update branch" button does.
+    /// GitHub refuses (422) if the head is no longer `expected_head_sha`.
+    pub async fn update_branch(&self, repo: &str, number: u64, expected_head_sha: &str) -> Result<UpdateBranchAccepted> {
+        self.rest_put(
+            &format!("/repos/{repo}/pulls/{number}/update-branch"),
+            &UpdateBranchRequest { expected_head_sha },
+        )
+        .await
+    }
+
+    /// Rebase the PR's head onto its base. Returns the new head SHA.
+    pub async fn rebase_branch(&self, node_id: &str, expected_head_sha: &str) -> Result<String> {
+        const MUTATION: &str = "mutation($input: UpdatePullRequestBranchInput!) { result: updatePullRequestBranch(input: $input) { pullRequest { headRefOid } } }";
+        let input = serde_json::json!({
+            "pullRequestId": node_id,
+            "expectedHeadOid": expected_head_sha,
+            "updateMethod": "REBASE",
+        });
+        let data: UpdateBranchMutationData = self
+            .graphql_mutation("updatePullRequestBranch", MUTATION, input)
+            .await?;
+        Ok(data.result.pull_request.head_ref_oid)
+    }
+
+    /// The newest `last` reviews on a PR (at most 100), oldest first, in one request
+    pub async fn get_recent_reviews(&self, repo: &str, number: u64, last: usize) -> Result<Vec<Review>> {
+        let (owner, name) = split_repo(repo)?;
+        const QUERY: &str = "query($owner: String!, $repo: String!, $number: Int!, $last: Int!) { repository(owner: $owner, name: $repo) { pullRequest(number: $number) { reviews(last: $last) { nodes { databaseId author { login } state submittedAt body url } } } } }";
+        let vars = serde_json::json!({ "owner": owner, "repo": name, "number": number, "last": last.min(100) });
+        let data: RecentReviewsData = self.graphql(QUERY, &vars).await?;
+        Ok(data
+            .repository
+            .pull_request
+            .reviews
+            .nodes
+            .into_iter()
+            .map(|r| Review {
+                id: r.database_id,
+                author: r.author.map(|a| a.login).unwrap_or_else(|| "ghost".to_string()),
+                state: r.state,
+                submitted_at: r.submitted_at,
+                body: r.body,
+                html_url: r.url,
+                commit_id: None,
+            })
+            .collect())
+    }
+
+    /// All reviews on a PR, oldest first (dismissed ones included, state DISMISSED)
+    pub async fn get_reviews(&self, repo: &str, number: u64) -> Result<Vec<Review>> {
+        let reviews: Vec<RestReview> = self
+            .rest_get_all_pages(&format!("/repos/{repo}/pulls/{number}/reviews"))
+            .await?;
+        Ok(reviews
+            .into_iter()
+            .map(Review::from)
+            .collect())
+    }
+
+    /// Dismiss a submitted review with `message` (GitHub requires one). Returns the
+    /// review as it stands afterwards.
+    pub async fn dismiss_review(&self, repo: &str, number: u64, review_id: u64, message: &str) -> Result<Review> {
+        let review: RestReview = self
+            .rest_put(
+                &format!("/repos/{repo}/pulls/{number}/reviews/{review_id}/dismissals"),
+                &DismissReviewRequest { message, event: "DISMISS" },
+            )
+            .await?;
+        Ok(review.into())
+    }
 }
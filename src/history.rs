@@ -0,0 +1,240 @@
+//! Smart-analysis results persisted per PR revision, so `pr smart-diff` can
+//! report what changed between two analyses of the same PR.
+
+use crate::config;
+use crate::sem::SmartChange;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Below this name similarity a removed + added pair isn't flagged as a rename
+const RENAME_SIMILARITY: f64 = 0.5;
+
+/// One smart analysis of a PR at `head_sha`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SmartRecord {
+    pub head_sha: String,
+    /// Unix seconds
+    pub recorded_at: u64,
+    pub changes: Vec<SmartChange>,
+}
+
+/// Where the analyses of one PR live: `<cache>/smart/<owner>/<repo>/<number>/`
+pub fn record_dir(repo: &str, number: u64) -> Result<PathBuf> {
+    Ok(config::cache_dir()?.join("smart").join(repo).join(number.to_string()))
+}
+
+/// The stored analysis for `head_sha`, if any
+pub fn load(dir: &Path, head_sha: &str) -> Option<SmartRecord> {
+    let raw = std::fs::read_to_string(dir.join(format!("{head_sha}.json"))).ok()?;
+    serde_json::from_str(&raw).ok()
+}
+
+pub fn store(dir: &Path, record: &SmartRecord) -> Result<()> {
+    let path = dir.join(format!("{}.json", record.head_sha));
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    std::fs::write(&path, serde_json::to_string(record)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The most recent analysis of any revision other than `head_sha`
+pub fn previous(dir: &Path, head_sha: &str) -> Option<SmartRecord> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| std::fs::read_to_string(entry.ok()?.path()).ok())
+        .filter_map(|raw| serde_json::from_str::<SmartRecord>(&raw).ok())
+        .filter(|r| r.head_sha != head_sha)
+        .max_by_key(|r| r.recorded_at)
+}
+
+/// An entity whose category changed between the two analyses
+#[derive(Debug, Serialize)]
+pub struct Moved {
+    pub file: String,
+    pub entity: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// An entity present in only one of the two analyses
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub file: String,
+    pub entity: String,
+    pub entity_type: String,
+    pub category: String,
+    /// e.g. "possibly renamed from `load_user` (name 80% similar)"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+/// What changed from one analysis to the next. Mechanical entries that merely
+/// appeared or disappeared are only counted.
+#[derive(Debug, Default, Serialize)]
+pub struct SmartDiff {
+    pub moved: Vec<Moved>,
+    pub appeared: Vec<Entry>,
+    pub resolved: Vec<Entry>,
+    pub mechanical_changed: usize,
+}
+
+impl SmartDiff {
+    pub fn is_empty(&self) -> bool {
+        self.moved.is_empty() && self.appeared.is_empty() && self.resolved.is_empty()
+    }
+}
+
+/// Compare two analyses entity by entity, keyed by (file, entity name). A renamed
+/// entity shows up as resolved + appeared; pairs in the same file with similar
+/// names get a hint pointing at each other.
+pub fn compare(before: &[SmartChange], after: &[SmartChange]) -> SmartDiff {
+    let key = |c: &SmartChange| (c.file.clone(), c.entity_name.clone());
+    let old: HashMap<_, &SmartChange> = before.iter().map(|c| (key(c), c)).collect();
+    let new: HashMap<_, &SmartChange> = after.iter().map(|c| (key(c), c)).collect();
+
+    let mut diff = SmartDiff::default();
+    let entry = |c: &SmartChange| Entry {
+        file: c.file.clone(),
+        entity: c.entity_name.clone(),
+        entity_type: c.entity_type.clone(),
+        category: c.category.clone(),
+        hint: None,
+    };
+    for c in after {
+        match old.get(&key(c)) {
+            Some(prev) if prev.category != c.category => diff.moved.push(Moved {
+                file: c.file.clone(),
+                entity: c.entity_name.clone(),
+                from: prev.category.clone(),
+                to: c.category.clone(),
+            }),
+            Some(_) => {}
+            None if c.category == "mechanical" => diff.mechanical_changed += 1,
+            None => diff.appeared.push(entry(c)),
+        }
+    }
+    for c in before.iter().filter(|c| !new.contains_key(&key(c))) {
+        if c.category == "mechanical" {
+            diff.mechanical_changed += 1;
+        } else {
+            diff.resolved.push(entry(c));
+        }
+    }
+
+    for added in &mut diff.appeared {
+        let best = diff
+            .resolved
+            .iter_mut()
+            .filter(|r| r.hint.is_none() && r.file == added.file && r.entity_type == added.entity_type)
+            .map(|r| (name_similarity(&r.entity, &added.entity), r))
+            .filter(|(sim, _)| *sim >= RENAME_SIMILARITY)
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        if let Some((sim, removed)) = best {
+            let pct = (sim * 100.0).round();
+            added.hint = Some(format!("possibly renamed from `{}` (name {pct}% similar)", removed.entity));
+            removed.hint = Some(format!("possibly renamed to `{}` (name {pct}% similar)", added.entity));
+        }
+    }
+    diff
+}
+
+/// Dice coefficient over character bigrams, case-insensitive
+fn name_similarity(a: &str, b: &str) -> f64 {
+    let bigrams = |s: &str| {
+        let chars: Vec<char> = s.to_lowercase().chars().collect();
+        chars.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>()
+    };
+    let (a, mut b) = (bigrams(a), bigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let total = a.len() + b.len();
+    let shared = a
+        .iter()
+        .filter(|pair| b.iter().position(|p| p == *pair).map(|i| b.swap_remove(i)).is_some())
+        .count();
+    2.0 * shared as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(file: &str, name: &str, category: &str) -> SmartChange {
+        SmartChange {
+            category: category.to_string(),
+            change_type: "modified".to_string(),
+            entity_type: "function".to_string(),
+            entity_name: name.to_string(),
+            parent: None,
+            file: file.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_compare_transitions() {
+        let before = [
+            change("src/db.rs", "save", "mechanical"),
+            change("src/db.rs", "load_user", "behavioral"),
+            change("src/db.rs", "connect", "new_logic"),
+            change("src/api.rs", "handler", "behavioral"),
+            change("src/fmt.rs", "indent", "mechanical"),
+        ];
+        let after = [
+            // moved into a riskier category
+            change("src/db.rs", "save", "behavioral"),
+            // renamed
+            change("src/db.rs", "load_users", "behavioral"),
+            // unchanged
+            change("src/api.rs", "handler", "behavioral"),
+            // new behavioral change
+            change("src/api.rs", "route", "behavioral"),
+            change("src/fmt.rs", "wrap", "mechanical"),
+        ];
+        let diff = compare(&before, &after);
+
+        let moved: Vec<_> = diff.moved.iter().map(|m| (m.entity.as_str(), m.from.as_str(), m.to.as_str())).collect();
+        assert_eq!(moved, [("save", "mechanical", "behavioral")]);
+
+        let appeared: Vec<_> = diff.appeared.iter().map(|e| (e.entity.as_str(), e.hint.as_deref())).collect();
+        assert_eq!(
+            appeared,
+            [
+                ("load_users", Some("possibly renamed from `load_user` (name 94% similar)")),
+                ("route", None),
+            ]
+        );
+
+        let resolved: Vec<_> = diff.resolved.iter().map(|e| (e.entity.as_str(), e.hint.is_some())).collect();
+        assert_eq!(resolved, [("load_user", true), ("connect", false)]);
+        assert_eq!(diff.mechanical_changed, 2);
+    }
+
+    #[test]
+    fn test_compare_identical_is_empty() {
+        let analysis = [change("a.rs", "f", "behavioral"), change("a.rs", "g", "mechanical")];
+        let diff = compare(&analysis, &analysis);
+        assert!(diff.is_empty());
+        assert_eq!(diff.mechanical_changed, 0);
+    }
+
+    #[test]
+    fn test_previous_skips_current_head() {
+        let dir = std::env::temp_dir().join(format!("gh-agent-history-{}", std::process::id()));
+        let record = |sha: &str, at: u64| SmartRecord {
+            head_sha: sha.to_string(),
+            recorded_at: at,
+            changes: vec![change("a.rs", sha, "behavioral")],
+        };
+        store(&dir, &record("aaa", 1)).unwrap();
+        store(&dir, &record("bbb", 2)).unwrap();
+        store(&dir, &record("ccc", 3)).unwrap();
+
+        assert_eq!(previous(&dir, "ccc").unwrap().head_sha, "bbb");
+        assert_eq!(previous(&dir, "ddd").unwrap().head_sha, "ccc");
+        assert_eq!(load(&dir, "aaa").unwrap().changes[0].entity_name, "aaa");
+        assert!(load(&dir, "zzz").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
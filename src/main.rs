@@ -1,106 +1,806 @@
+mod budget;
 mod cli;
+mod codeowners;
 mod commands;
+mod config;
 mod diff;
 mod format;
+mod generated;
+mod git;
 mod github;
+mod history;
+mod owners;
+mod pager;
+mod patch;
+mod pending;
+mod priority;
+mod raw_diff;
+mod redact;
+mod report;
+mod review_input;
 mod search;
+mod sed;
 mod sem;
+mod severity;
+#[cfg(test)]
+mod testutil;
 
 use anyhow::Result;
-use clap::Parser;
-use cli::{Cli, Commands, PrCommands};
+use std::ffi::OsString;
+use budget::Limit;
+use clap::{Command, FromArgMatches};
+use cli::{
+    AstGrepArgs, Cli, Commands, ConfigCommands, DiffArgs, GrepArgs, PostReviewArgs, PrCommands, ReviewCommands, SuggestArgs,
+    SuggestLines,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let client = github::Client::new()?;
+    let cmd = config::command();
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    // A broken config file mustn't lock out every command, least of all the `config`
+    // ones that fix it; those load (and report on) the file themselves
+    let config = config::Config::load().unwrap_or_else(|e| {
+        if !config::is_config_command(&cmd, &argv) {
+            eprintln!("Warning: ignoring the config file: {e:#}");
+        }
+        config::Config::default()
+    });
+    commands::set_noise_segments(config.noise.segments.clone());
+    commands::set_owners_config(config.owners.clone());
+    let argv = config::apply_repo_defaults(&cmd, &config, argv);
+    let matches = cmd.clone().get_matches_from(argv);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if cli.redact_content {
+        redact::enable();
+    }
 
     match cli.command {
-        Commands::Pr { command } => match command {
-            PrCommands::View {
-                number,
-                repo,
-                sem,
-                smart,
-                json,
-            } => {
-                commands::pr_view(&client, &repo, number, sem, smart, json).await?;
+        Commands::Config { command } => match command {
+            ConfigCommands::Set { repo, key, value } => commands::config_set(&repo, &key, &value)?,
+            ConfigCommands::Get { repo, key } => commands::config_get(&repo, &key)?,
+            ConfigCommands::Unset { repo, key } => commands::config_unset(&repo, &key)?,
+            ConfigCommands::List { repo } => commands::config_list(repo.as_deref())?,
+        },
+        Commands::Pr { command } => {
+            let json = command.prints_json();
+            // Offline, so it works without a token
+            if let PrCommands::Review(review) = &command {
+                if let Some(post) = review.post.as_ref().filter(|post| post.validate_only) {
+                    return commands::pr_review_validate(&post.comments_file);
+                }
+            }
+            if format::should_color(&cli.color, json, std::env::var("NO_COLOR").ok().as_deref()) {
+                format::enable_color();
             }
-            PrCommands::Diff {
+            if pager::should_page(cli.paginate, cli.no_pager, json) {
+                let own_env = std::env::var("GH_AGENT_PAGER").ok();
+                let env = std::env::var("PAGER").ok();
+                let configured = config.core.pager.as_deref();
+                if let Some(pager) = pager::pager_command(own_env.as_deref(), configured, env.as_deref()) {
+                    pager::enable(pager);
+                }
+            }
+
+            let client = github::Client::new(cli.verbose, cli.max_requests)?;
+            let result = run_pr(&client, command, &config, &cmd).await;
+            pager::finish();
+            if cli.verbose {
+                eprintln!("[http] {} requests", client.requests_used());
+            }
+            if let Err(e) = &result {
+                if commands::is_pr_not_open(e) {
+                    eprintln!("Error: {e:?}");
+                    std::process::exit(commands::EXIT_PR_NOT_OPEN);
+                }
+                if commands::is_all_skipped(e) {
+                    eprintln!("Error: {e:?}");
+                    std::process::exit(commands::EXIT_ALL_SKIPPED);
+                }
+                if commands::is_declined(e) {
+                    eprintln!("Error: {e:?}");
+                    std::process::exit(commands::EXIT_DECLINED);
+                }
+            }
+            result?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Run one `pr` subcommand
+async fn run_pr(client: &github::Client, command: PrCommands, config: &config::Config, cmd: &Command) -> Result<()> {
+    match command {
+        PrCommands::Each {
+            repo,
+            numbers,
+            concurrency,
+            args,
+        } => {
+            let numbers = if numbers.is_empty() {
+                commands::read_pr_numbers()?
+            } else {
+                numbers
+            };
+            // Parse every PR's command up front, so a typo fails once instead of per PR
+            let jobs = numbers
+                .iter()
+                .map(|&n| Ok((n, each_command(cmd, config, &repo, &args, n)?)))
+                .collect::<Result<Vec<_>>>()?;
+            let run = |command| Box::pin(run_pr(client, command, config, cmd));
+            commands::pr_each(jobs, concurrency, run).await?;
+        }
+        PrCommands::View {
+            number,
+            repo,
+            sem,
+            smart,
+            owned_by,
+            sort,
+            graph,
+            json,
+        } => {
+            let sort = sort.as_deref().map(stat_sort);
+            let options = commands::ViewOptions {
+                use_sem: sem,
+                use_smart: smart,
+                owned_by: owned_by.as_deref(),
+                sort,
+                graph,
+                json,
+            };
+            commands::pr_view(client, &repo, number, options).await?;
+        }
+        PrCommands::Files {
+            number,
+            repo,
+            all,
+            status,
+            json,
+        } => {
+            commands::pr_files(client, &repo, number, all, &status, json).await?;
+        }
+        PrCommands::List {
+            repo,
+            state,
+            author,
+            label,
+            base,
+            limit,
+            json,
+        } => {
+            let filters = commands::ListFilters {
+                state: &state,
+                author: author.as_deref(),
+                labels: &label,
+                base: base.as_deref(),
+            };
+            commands::pr_list(client, &repo, &filters, limit, json).await?;
+        }
+        PrCommands::Diff(args) => {
+            let DiffArgs {
                 number,
                 repo,
                 file,
+                exclude,
+                file_regex,
                 smart_files,
+                owned_by,
                 all,
+                auto_noise,
                 stat,
+                sort,
+                graph,
                 json,
-            } => {
-                commands::pr_diff(&client, &repo, number, &file, smart_files, all, stat, json).await?;
-            }
-            PrCommands::File { number, repo, path } => {
-                commands::pr_file(&client, &repo, number, &path).await?;
-            }
-            PrCommands::Review {
-                number,
-                repo,
-                comments_file,
-            } => {
-                commands::pr_review(&client, &repo, number, &comments_file).await?;
+                format,
+                json_full,
+                name_only,
+                name_status,
+                hunk,
+                lines,
+                min_move_lines,
+                collapse_moves,
+                word_diff,
+                resolve_context,
+                ignore_whitespace,
+                from,
+                to,
+                since_review,
+                since,
+                net_only,
+                out_dir,
+                clean,
+                max_lines_per_file,
+                max_total_lines,
+                max_bytes,
+                max_tokens,
+            } = *args;
+            let word_diff = word_diff.map(|mode| match mode.as_str() {
+                "color" => format::WordDiff::Color,
+                _ => format::WordDiff::Plain,
+            });
+            let names = match (name_only, name_status) {
+                (true, _) => Some(commands::NameMode::Only),
+                (_, true) => Some(commands::NameMode::Status),
+                _ => None,
+            };
+            let range = match (&since, since_review) {
+                (Some(time), _) => commands::DiffRange::Since(time),
+                (None, true) => commands::DiffRange::SinceReview,
+                _ if from.is_some() || to.is_some() => commands::DiffRange::Commits { from: from.as_deref(), to: to.as_deref() },
+                _ => commands::DiffRange::Full,
+            };
+            let file_filter = commands::FileFilter::new(&file, &file_regex)?;
+            let options = commands::DiffOptions {
+                file_filter,
+                smart_files,
+                owned_by: owned_by.as_deref(),
+                include_all: all,
+                auto_noise,
+                stat_only: stat,
+                stat_sort: sort.as_deref().map(stat_sort),
+                graph,
+                json,
+                json_full,
+                hunk_filters: &hunk,
+                line_ranges: &lines,
+                min_move_lines,
+                collapse_moves,
+                word_diff,
+                resolve_context,
+                ignore_whitespace,
+                exclude: &exclude,
+                names,
+                output: diff_format(&format),
+                out_dir: out_dir.as_deref(),
+                clean,
+                range,
+                net_only,
+                max_lines_per_file,
+                max_total_lines,
+                limit: Limit::from_flags(max_bytes, max_tokens),
+            };
+            commands::pr_diff(client, &repo, number, options).await?;
+        }
+        PrCommands::Checks {
+            number,
+            repo,
+            failed,
+            json,
+        } => {
+            commands::pr_checks(client, &repo, number, failed, json).await?;
+        }
+        PrCommands::Commits {
+            number,
+            repo,
+            stat,
+            detect_reverts,
+            json,
+        } => {
+            commands::pr_commits(client, &repo, number, stat, detect_reverts, json).await?;
+        }
+        PrCommands::FileHistory {
+            number,
+            repo,
+            path,
+            diff,
+            json,
+        } => {
+            commands::pr_file_history(client, &repo, number, &path, diff.as_deref(), json).await?;
+        }
+        PrCommands::Comments {
+            number,
+            repo,
+            file,
+            unresolved,
+            json,
+        } => {
+            commands::pr_comments(client, &repo, number, &file, unresolved, json).await?;
+        }
+        PrCommands::Reviews {
+            number,
+            repo,
+            latest_per_user,
+            json,
+        } => {
+            commands::pr_reviews(client, &repo, number, latest_per_user, json).await?;
+        }
+        PrCommands::Report {
+            number,
+            repo,
+            out,
+            include_diff,
+        } => {
+            commands::pr_report(client, &repo, number, out.as_deref(), include_diff).await?;
+        }
+        PrCommands::Priorities { number, repo, json } => {
+            commands::pr_priorities(client, &repo, number, &config.priority, json).await?;
+        }
+        PrCommands::SmartDiff { number, repo, json } => {
+            commands::pr_smart_diff(client, &repo, number, json).await?;
+        }
+        PrCommands::Status {
+            number,
+            repo,
+            poll,
+            fail_if_blocked,
+            json,
+        } => {
+            commands::pr_status(client, &repo, number, poll, fail_if_blocked, json).await?;
+        }
+        PrCommands::Conflicts { number, repo, json } => {
+            commands::pr_conflicts(client, &repo, number, json).await?;
+        }
+        PrCommands::Approve {
+            number,
+            repo,
+            body,
+            allow_closed,
+        } => {
+            commands::pr_approve(client, &repo, number, body.as_deref(), allow_closed).await?;
+        }
+        PrCommands::RequestChanges {
+            number,
+            repo,
+            body,
+            allow_closed,
+        } => {
+            commands::pr_request_changes(client, &repo, number, body.as_deref(), allow_closed).await?;
+        }
+        PrCommands::Reply {
+            number,
+            repo,
+            comment_id,
+            file,
+            line,
+            body,
+            allow_closed,
+        } => {
+            let at = file.as_deref().zip(line);
+            commands::pr_reply(client, &repo, number, comment_id, at, &body, allow_closed).await?;
+        }
+        PrCommands::DismissReview {
+            number,
+            repo,
+            review_id,
+            mine,
+            message,
+            allow_closed,
+        } => {
+            commands::pr_dismiss_review(client, &repo, number, review_id, mine, &message, allow_closed).await?;
+        }
+        PrCommands::Resolve {
+            number,
+            repo,
+            thread_id,
+            file,
+            line,
+            undo,
+            allow_closed,
+        } => {
+            let at = file.as_deref().zip(line);
+            commands::pr_resolve(client, &repo, number, thread_id.as_deref(), at, undo, allow_closed).await?;
+        }
+        PrCommands::Label {
+            number,
+            repo,
+            add,
+            remove,
+            list,
+            allow_closed,
+        } => {
+            commands::pr_label(client, &repo, number, &add, &remove, list, allow_closed).await?;
+        }
+        PrCommands::Assign {
+            number,
+            repo,
+            add,
+            remove,
+            milestone,
+            json,
+            allow_closed,
+        } => {
+            let options = commands::AssignOptions {
+                add: &add,
+                remove: &remove,
+                milestone: milestone.as_deref(),
+                json,
+                allow_closed,
+            };
+            commands::pr_assign(client, &repo, number, options).await?;
+        }
+        PrCommands::Reviewers {
+            number,
+            repo,
+            add,
+            add_team,
+            remove,
+            list,
+            allow_closed,
+        } => {
+            let options = commands::ReviewersOptions {
+                add: &add,
+                add_team: &add_team,
+                remove: &remove,
+                list,
+                allow_closed,
+            };
+            commands::pr_reviewers(client, &repo, number, options).await?;
+        }
+        PrCommands::Checkout {
+            number,
+            repo,
+            branch_name,
+            detach,
+            force,
+        } => {
+            commands::pr_checkout(client, &repo, number, branch_name.as_deref(), detach, force).await?;
+        }
+        PrCommands::Ready { number, repo } => {
+            commands::pr_ready(client, &repo, number).await?;
+        }
+        PrCommands::Draft { number, repo } => {
+            commands::pr_draft(client, &repo, number).await?;
+        }
+        PrCommands::Close {
+            number,
+            repo,
+            comment,
+            delete_branch,
+        } => {
+            commands::pr_close(client, &repo, number, comment.as_deref(), delete_branch).await?;
+        }
+        PrCommands::Reopen { number, repo } => {
+            commands::pr_reopen(client, &repo, number).await?;
+        }
+        PrCommands::UpdateBranch { number, repo, rebase } => {
+            commands::pr_update_branch(client, &repo, number, rebase).await?;
+        }
+        PrCommands::Merge {
+            number,
+            repo,
+            method,
+            subject,
+            body,
+            delete_branch,
+            force,
+        } => {
+            let options = commands::MergeOptions {
+                method: &method,
+                subject: subject.as_deref(),
+                body: body.as_deref(),
+                delete_branch,
+                force,
+            };
+            commands::pr_merge(client, &repo, number, options).await?;
+        }
+        PrCommands::File {
+            number,
+            repo,
+            path,
+            format,
+            max_bytes,
+            max_tokens,
+        } => {
+            let markdown = format == "markdown";
+            commands::pr_file(client, &repo, number, &path, markdown, Limit::from_flags(max_bytes, max_tokens))
+                .await?;
+        }
+        PrCommands::Review(review) => {
+            if let Some(action) = review.action {
+                match action {
+                    ReviewCommands::Start {
+                        number,
+                        repo,
+                        allow_closed,
+                    } => commands::pr_review_start(client, &repo, number, allow_closed).await?,
+                    ReviewCommands::Add {
+                        number,
+                        repo,
+                        file,
+                        line,
+                        start_line,
+                        body,
+                        allow_closed,
+                    } => {
+                        commands::pr_review_add(client, &repo, number, (&file, line), start_line, &body, allow_closed).await?
+                    }
+                    ReviewCommands::Submit {
+                        number,
+                        repo,
+                        event,
+                        body,
+                        allow_closed,
+                    } => {
+                        commands::pr_review_submit(client, &repo, number, &event, body.as_deref(), allow_closed).await?
+                    }
+                    ReviewCommands::Abandon { number, repo } => {
+                        commands::pr_review_abandon(client, &repo, number).await?
+                    }
+                }
+            } else if let Some(post) = review.post {
+                let PostReviewArgs {
+                    number,
+                    repo,
+                    comments_file,
+                    body,
+                    body_file,
+                    template,
+                    template_vars,
+                    emit_patch,
+                    retry_without_invalid,
+                    chunk_size,
+                    max_comment_chars,
+                    truncate_long_comments,
+                    fuzz,
+                    split_ranges,
+                    via,
+                    amend,
+                    allow_noise_comments,
+                    allow_duplicates,
+                    strict,
+                    min_severity,
+                    validate_only: _,
+                    idempotency_key,
+                    allow_closed,
+                    dry_run,
+                    json,
+                    confirm,
+                    yes,
+                } = post;
+                let options = commands::ReviewOptions {
+                    comments_file: &comments_file,
+                    body: body.as_deref(),
+                    body_file: body_file.as_deref(),
+                    template: template.as_deref(),
+                    template_vars: &template_vars,
+                    emit_patch: emit_patch.as_deref(),
+                    retry_without_invalid,
+                    chunk_size,
+                    max_comment_chars,
+                    truncate_long_comments,
+                    fuzz,
+                    split_ranges,
+                    via_graphql: via == "graphql",
+                    amend,
+                    allow_noise_comments,
+                    allow_duplicates,
+                    strict,
+                    min_severity: min_severity.as_deref().and_then(severity::Severity::parse),
+                    idempotency_key: idempotency_key.as_deref(),
+                    allow_closed,
+                    dry_run,
+                    json,
+                    confirm,
+                    yes,
+                };
+                commands::pr_review(client, &repo, number, options).await?;
             }
-            PrCommands::Grep {
+        }
+        PrCommands::Grep(args) => {
+            let GrepArgs {
                 number,
                 repo,
                 pattern,
                 file,
+                exclude,
+                file_regex,
                 repo_wide,
                 path,
+                exclude_path,
+                language,
+                search_qualifier,
                 base,
+                git_ref,
+                files_from,
+                null,
                 case_sensitive,
                 context,
+                owned_by,
                 all,
-            } => {
-                commands::pr_grep(
-                    &client, &repo, number, &pattern, &file,
-                    repo_wide, path.as_deref(), base, case_sensitive, context, all,
-                ).await?;
-            }
-            PrCommands::AstGrep {
+                max_bytes,
+                max_tokens,
+            } = *args;
+            let scope = search::CodeSearchScope {
+                paths: path,
+                exclude_paths: exclude_path,
+                language,
+                qualifiers: search_qualifier,
+            };
+            let targets = search::SearchTargets {
+                files_from: files_from.map(|spec| commands::read_files_from(&spec, null)).transpose()?,
+                git_ref,
+                base,
+                exclude,
+            };
+            let files = commands::SearchFiles {
+                file_filter: commands::FileFilter::new(&file, &file_regex)?,
+                repo_wide,
+                scope,
+                targets,
+                owned_by: owned_by.as_deref(),
+                include_all: all,
+            };
+            let options = commands::GrepOptions {
+                pattern: &pattern,
+                case_sensitive,
+                context_lines: context,
+                limit: Limit::from_flags(max_bytes, max_tokens),
+            };
+            commands::pr_grep(client, &repo, number, &files, options).await?;
+        }
+        PrCommands::AstGrep(args) => {
+            let AstGrepArgs {
                 number,
                 repo,
                 pattern,
                 file,
+                exclude,
+                file_regex,
                 repo_wide,
                 path,
+                exclude_path,
+                language,
+                search_qualifier,
                 base,
+                git_ref,
+                files_from,
+                null,
                 lang,
+                owned_by,
                 all,
-            } => {
-                commands::pr_ast_grep(
-                    &client, &repo, number, &pattern, &file,
-                    repo_wide, path.as_deref(), base, lang.as_deref(), all,
-                ).await?;
-            }
-            PrCommands::Suggest {
+                max_match_lines,
+                json,
+                rewrite,
+                suggest,
+                allow_closed,
+            } = *args;
+            let scope = search::CodeSearchScope {
+                paths: path,
+                exclude_paths: exclude_path,
+                language,
+                qualifiers: search_qualifier,
+            };
+            let targets = search::SearchTargets {
+                files_from: files_from.map(|spec| commands::read_files_from(&spec, null)).transpose()?,
+                git_ref,
+                base,
+                exclude,
+            };
+            let files = commands::SearchFiles {
+                file_filter: commands::FileFilter::new(&file, &file_regex)?,
+                repo_wide,
+                scope,
+                targets,
+                owned_by: owned_by.as_deref(),
+                include_all: all,
+            };
+            let options = commands::AstGrepOptions {
+                pattern: &pattern,
+                lang_override: lang.as_deref(),
+                max_match_lines,
+                json,
+                rewrite: rewrite.as_deref(),
+                suggest,
+                allow_closed,
+            };
+            commands::pr_ast_grep(client, &repo, number, &files, options).await?;
+        }
+        PrCommands::Suggest(args) => {
+            let SuggestArgs {
                 number,
                 repo,
                 file,
-                line_start,
-                line_end,
+                lines,
                 replacement,
-            } => {
-                commands::pr_suggest(
-                    &client,
-                    &repo,
-                    number,
-                    &file,
-                    line_start,
-                    line_end,
-                    &replacement,
-                )
-                .await?;
+                replacement_file,
+                delete,
+                comment,
+                comment_file,
+                review_body,
+                emit_patch,
+                include_context,
+                strict,
+                idempotency_key,
+                allow_closed,
+                force,
+                match_indent,
+                preview,
+                json,
+                from_local,
+                sed,
+                dry_run,
+            } = *args;
+            if let Some(SuggestLines { line_start, line_end }) = lines {
+                let comment = if preview {
+                    None
+                } else {
+                    commands::read_suggestion_comment(comment, comment_file.as_deref(), replacement_file.as_deref())?
+                };
+                let replacement = if delete {
+                    String::new()
+                } else {
+                    commands::read_replacement(replacement, replacement_file.as_deref())?
+                };
+                let suggestion = patch::Suggestion {
+                    path: file,
+                    start_line: line_start,
+                    end_line: line_end,
+                    replacement,
+                };
+                if preview {
+                    commands::pr_suggest_preview(client, &repo, number, suggestion, match_indent, json).await?;
+                } else {
+                    let options = commands::SuggestOptions {
+                        emit_patch: emit_patch.as_deref(),
+                        include_context,
+                        strict,
+                        idempotency_key: idempotency_key.as_deref(),
+                        allow_closed,
+                        force,
+                        match_indent,
+                        comment: comment.as_deref(),
+                        review_body: review_body.as_deref(),
+                        ..Default::default()
+                    };
+                    commands::pr_suggest(client, &repo, number, suggestion, &options).await?;
+                }
+            } else {
+                let comment = commands::read_suggestion_comment(comment, comment_file.as_deref(), None)?;
+                let options = commands::SuggestOptions {
+                    dry_run,
+                    allow_closed,
+                    force,
+                    comment: comment.as_deref(),
+                    review_body: review_body.as_deref(),
+                    ..Default::default()
+                };
+                if let Some(local_path) = from_local {
+                    commands::pr_suggest_from_local(client, &repo, number, &file, &local_path, &options).await?;
+                } else if let Some(expr) = sed {
+                    commands::pr_suggest_sed(client, &repo, number, &file, &expr, &options).await?;
+                }
             }
-        },
+        }
     }
-
     Ok(())
 }
+
+/// `pr each`: the subcommand in `args` for PR `number`, parsed (with the repo's
+/// defaults) as if it had been typed on its own
+fn each_command(cmd: &Command, config: &config::Config, repo: &str, args: &[String], number: u64) -> Result<PrCommands> {
+    let argv: Vec<OsString> = ["gh-agent", "pr"]
+        .into_iter()
+        .map(OsString::from)
+        .chain(args.iter().map(OsString::from))
+        .chain(["--repo".into(), repo.into(), number.to_string().into()])
+        .collect();
+    let argv = config::apply_repo_defaults(cmd, config, argv);
+    let matches = cmd.clone().try_get_matches_from(argv)?;
+    match Cli::from_arg_matches(&matches)?.command {
+        Commands::Pr {
+            command: PrCommands::Each { .. },
+        } => anyhow::bail!("pr each can't run pr each"),
+        Commands::Pr { command } => Ok(command),
+        Commands::Config { .. } => unreachable!("argv starts with `pr`"),
+    }
+}
+
+/// `--sort`, already checked by clap
+fn stat_sort(key: &str) -> format::StatSort {
+    match key {
+        "path" => format::StatSort::Path,
+        "additions" => format::StatSort::Additions,
+        "deletions" => format::StatSort::Deletions,
+        _ => format::StatSort::Total,
+    }
+}
+
+fn diff_format(name: &str) -> commands::DiffFormat {
+    match name {
+        "patch" => commands::DiffFormat::Patch,
+        "markdown" => commands::DiffFormat::Markdown,
+        _ => commands::DiffFormat::Numbered,
+    }
+}
@@ -0,0 +1,298 @@
+//! Bazel/Chromium-style per-directory `OWNERS` files, and how their owners
+//! combine with CODEOWNERS. Only the simple syntax is understood: one owner
+//! per line, `set noparent`, and `per-file GLOB[,GLOB]=OWNER[,OWNER]`.
+//! `include`, `file:` and `*` lines are ignored.
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::codeowners::{normalize_owner, Codeowners};
+
+const OWNERS_FILE: &str = "OWNERS";
+
+struct PerFile {
+    matchers: Vec<GlobMatcher>,
+    owners: Vec<String>,
+    /// `per-file GLOB=set noparent`: only this rule's owners apply to matching files
+    noparent: bool,
+}
+
+/// One parsed OWNERS file
+pub struct OwnersFile {
+    owners: Vec<String>,
+    per_file: Vec<PerFile>,
+    /// `set noparent`: stop inheriting owners from parent directories
+    noparent: bool,
+}
+
+impl OwnersFile {
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut file = OwnersFile {
+            owners: Vec::new(),
+            per_file: Vec::new(),
+            noparent: false,
+        };
+        for (i, raw) in content.lines().enumerate() {
+            let line = raw.split_once('#').map_or(raw, |(l, _)| l).trim();
+            if line.is_empty() || line == "*" || line.starts_with("include ") || line.starts_with("file:") {
+                continue;
+            }
+            if line == "set noparent" {
+                file.noparent = true;
+                continue;
+            }
+            let Some(rule) = line.strip_prefix("per-file ") else {
+                file.owners.push(normalize_owner(line));
+                continue;
+            };
+            let (globs, owners) = rule
+                .split_once('=')
+                .with_context(|| format!("OWNERS line {}: per-file needs GLOB=OWNER", i + 1))?;
+            let matchers = globs
+                .split(',')
+                .map(|g| {
+                    GlobBuilder::new(g.trim())
+                        .literal_separator(true)
+                        .build()
+                        .map(|g| g.compile_matcher())
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("OWNERS line {}: invalid per-file pattern {globs}", i + 1))?;
+            let owners = owners.trim();
+            file.per_file.push(PerFile {
+                matchers,
+                noparent: owners == "set noparent",
+                owners: if owners == "set noparent" {
+                    Vec::new()
+                } else {
+                    owners.split(',').map(|o| normalize_owner(o.trim())).collect()
+                },
+            });
+        }
+        Ok(file)
+    }
+}
+
+/// The OWNERS files that matter for a set of changed paths, keyed by the
+/// directory holding them ("" for the repo root)
+#[derive(Default)]
+pub struct OwnersTree {
+    files: BTreeMap<String, OwnersFile>,
+}
+
+impl OwnersTree {
+    pub fn insert(&mut self, dir: String, file: OwnersFile) {
+        self.files.insert(dir, file);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Owners of `path`: per-file and directory owners from its nearest OWNERS
+    /// outward, stopping at `set noparent`. Empty if unowned.
+    pub fn owners_of(&self, path: &str) -> Vec<String> {
+        let mut owners = Vec::new();
+        for dir in ancestor_dirs(path) {
+            let Some(file) = self.files.get(&dir) else {
+                continue;
+            };
+            let rel = if dir.is_empty() { path } else { &path[dir.len() + 1..] };
+            let matching: Vec<&PerFile> = file
+                .per_file
+                .iter()
+                .filter(|r| r.matchers.iter().any(|m| m.is_match(rel)))
+                .collect();
+            owners.extend(matching.iter().flat_map(|r| r.owners.iter().cloned()));
+            if matching.iter().any(|r| r.noparent) {
+                break;
+            }
+            owners.extend(file.owners.iter().cloned());
+            if file.noparent {
+                break;
+            }
+        }
+        let mut seen = HashSet::new();
+        owners.retain(|o| seen.insert(o.clone()));
+        owners
+    }
+}
+
+/// Directories containing `path`, nearest first, ending with "" (the repo root)
+pub fn ancestor_dirs(path: &str) -> Vec<String> {
+    let mut dirs = Vec::new();
+    let mut rest = path;
+    while let Some((parent, _)) = rest.rsplit_once('/') {
+        dirs.push(parent.to_string());
+        rest = parent;
+    }
+    dirs.push(String::new());
+    dirs
+}
+
+/// Of the OWNERS files in a tree listing, the directories of those that sit
+/// on some changed path's ancestor chain; only these need fetching
+pub fn needed_dirs<'a>(tree_paths: impl IntoIterator<Item = &'a str>, changed: &[&str]) -> Vec<String> {
+    let ancestors: HashSet<String> = changed.iter().flat_map(|p| ancestor_dirs(p)).collect();
+    tree_paths
+        .into_iter()
+        .filter_map(|p| match p.rsplit_once('/') {
+            Some((dir, OWNERS_FILE)) => Some(dir.to_string()),
+            None if p == OWNERS_FILE => Some(String::new()),
+            _ => None,
+        })
+        .filter(|dir| ancestors.contains(dir))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Repo path of the OWNERS file in `dir`
+pub fn owners_path(dir: &str) -> String {
+    if dir.is_empty() {
+        OWNERS_FILE.to_string()
+    } else {
+        format!("{dir}/{OWNERS_FILE}")
+    }
+}
+
+/// Which system answers when CODEOWNERS and OWNERS both name owners for a path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precedence {
+    /// CODEOWNERS, falling back to OWNERS for paths it doesn't cover
+    #[default]
+    Codeowners,
+    /// OWNERS, falling back to CODEOWNERS
+    Owners,
+    /// Everyone either system names
+    Union,
+}
+
+/// Ownership rules from CODEOWNERS, OWNERS files, or both
+pub struct Ownership {
+    pub codeowners: Option<Codeowners>,
+    pub owners_files: Option<OwnersTree>,
+    pub precedence: Precedence,
+}
+
+impl Ownership {
+    /// Owners of `path` (normalized, see `normalize_owner`). Empty if unowned.
+    pub fn owners_of(&self, path: &str) -> Vec<String> {
+        let co = self.codeowners.as_ref().map(|c| c.owners_of(path).to_vec()).unwrap_or_default();
+        let of = self.owners_files.as_ref().map(|o| o.owners_of(path)).unwrap_or_default();
+        let (first, second) = match self.precedence {
+            Precedence::Codeowners => (co, of),
+            Precedence::Owners => (of, co),
+            Precedence::Union => {
+                let mut all = co;
+                all.extend(of.into_iter().filter(|o| !all.contains(o)).collect::<Vec<_>>());
+                return all;
+            }
+        };
+        if first.is_empty() {
+            second
+        } else {
+            first
+        }
+    }
+
+    /// Whether any of `owners` owns `path`
+    pub fn is_owned_by(&self, path: &str, owners: &HashSet<String>) -> bool {
+        if let (Some(codeowners), None) = (&self.codeowners, &self.owners_files) {
+            return codeowners.is_owned_by(path, owners);
+        }
+        self.owners_of(path).iter().any(|o| owners.contains(o))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(files: &[(&str, &str)]) -> OwnersTree {
+        let mut t = OwnersTree::default();
+        for (dir, content) in files {
+            t.insert(dir.to_string(), OwnersFile::parse(content).unwrap());
+        }
+        t
+    }
+
+    #[test]
+    fn test_parse() {
+        let f = OwnersFile::parse(
+            "\
+# comment
+alice
+Bob@Example.com  # inline comment
+org/Team
+*
+include //tools/OWNERS
+set noparent
+per-file *.md,BUILD=docs-bot
+per-file *.pb.go=set noparent
+",
+        )
+        .unwrap();
+        assert_eq!(f.owners, ["@alice", "bob@example.com", "@org/team"]);
+        assert!(f.noparent);
+        assert_eq!(f.per_file.len(), 2);
+        assert_eq!(f.per_file[0].owners, ["@docs-bot"]);
+        assert!(f.per_file[1].noparent && f.per_file[1].owners.is_empty());
+
+        let err = OwnersFile::parse("per-file *.rs").err().unwrap();
+        assert!(err.to_string().contains("line 1"), "{err}");
+    }
+
+    #[test]
+    fn test_owners_inherit_until_noparent() {
+        let t = tree(&[
+            ("", "root-owner\n"),
+            ("src", "src-owner\nper-file *.md=writer\n"),
+            ("src/secure", "set noparent\nsec-owner\n"),
+        ]);
+        assert_eq!(t.owners_of("README.md"), ["@root-owner"]);
+        assert_eq!(t.owners_of("src/lib/a.rs"), ["@src-owner", "@root-owner"]);
+        // per-file globs match only files directly in the OWNERS directory
+        assert_eq!(t.owners_of("src/guide.md"), ["@writer", "@src-owner", "@root-owner"]);
+        assert_eq!(t.owners_of("src/lib/guide.md"), ["@src-owner", "@root-owner"]);
+        assert_eq!(t.owners_of("src/secure/key.rs"), ["@sec-owner"]);
+        assert_eq!(t.owners_of("src/secure/deep/key.rs"), ["@sec-owner"]);
+    }
+
+    #[test]
+    fn test_per_file_noparent() {
+        let t = tree(&[("", "root\n"), ("gen", "gen-owner\nper-file *.pb.go=bot\nper-file *.pb.go=set noparent\n")]);
+        assert_eq!(t.owners_of("gen/api.pb.go"), ["@bot"]);
+        assert_eq!(t.owners_of("gen/api.go"), ["@gen-owner", "@root"]);
+    }
+
+    #[test]
+    fn test_ancestor_dirs_and_needed_dirs() {
+        assert_eq!(ancestor_dirs("a/b/c.rs"), ["a/b", "a", ""]);
+        assert_eq!(ancestor_dirs("c.rs"), [""]);
+
+        let listing = ["OWNERS", "a/OWNERS", "a/b/OWNERS", "x/OWNERS", "a/OWNERS.md", "a/NOT_OWNERS"];
+        assert_eq!(needed_dirs(listing, &["a/b/c.rs", "a/d.rs"]), ["", "a", "a/b"]);
+        assert_eq!(needed_dirs(listing, &["top.rs"]), [""]);
+        assert_eq!(owners_path(""), "OWNERS");
+        assert_eq!(owners_path("a/b"), "a/b/OWNERS");
+    }
+
+    #[test]
+    fn test_precedence() {
+        let codeowners = || Some(Codeowners::parse("/src/ @org/core\n").unwrap());
+        let owners_files = || Some(tree(&[("", "root\n")]));
+        let ownership = |precedence| Ownership {
+            codeowners: codeowners(),
+            owners_files: owners_files(),
+            precedence,
+        };
+        assert_eq!(ownership(Precedence::Codeowners).owners_of("src/a.rs"), ["@org/core"]);
+        assert_eq!(ownership(Precedence::Codeowners).owners_of("docs/a.md"), ["@root"]);
+        assert_eq!(ownership(Precedence::Owners).owners_of("src/a.rs"), ["@root"]);
+        assert_eq!(ownership(Precedence::Union).owners_of("src/a.rs"), ["@org/core", "@root"]);
+    }
+}
@@ -0,0 +1,223 @@
+//! Paging for human-facing output, the way git does it.
+//!
+//! Commands write through `out!`/`outln!` rather than `print!`/`println!`. Once
+//! paging is enabled, the first write spawns the pager and everything after goes
+//! to its stdin. The default `less -FRX` exits by itself when the output fits on
+//! one screen. If the user quits the pager early, the rest of the output is
+//! dropped silently: a broken pipe is not an error.
+//!
+//! `capture` collects a command's output instead, for `pr each`, which runs
+//! several commands at once and prints each one's output as a unit.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Mutex;
+
+pub const DEFAULT_PAGER: &str = "less -FRX";
+
+enum Output {
+    Stdout,
+    /// Paging enabled, pager not started until there's something to show
+    Pending(String),
+    Paging(Pager),
+}
+
+static OUTPUT: Mutex<Output> = Mutex::new(Output::Stdout);
+
+tokio::task_local! {
+    /// Output of the future running under `capture`
+    static CAPTURED: RefCell<String>;
+}
+
+/// Run `fut`, collecting what it writes through `out!`/`outln!` instead of printing it
+pub async fn capture<T>(fut: impl Future<Output = T>) -> (String, T) {
+    CAPTURED
+        .scope(RefCell::new(String::new()), async move {
+            let value = fut.await;
+            (CAPTURED.with(RefCell::take), value)
+        })
+        .await
+}
+
+/// Whether to page: never for `--no-pager` or JSON, always for `--paginate`,
+/// otherwise only when stdout is a terminal
+pub fn should_page(paginate: bool, no_pager: bool, json: bool) -> bool {
+    !no_pager && !json && (paginate || io::stdout().is_terminal())
+}
+
+/// The pager command: `$GH_AGENT_PAGER`, else `core.pager` from the config, else
+/// `$PAGER`, else `less -FRX`, the order git gives `$GIT_PAGER` and the rest. An
+/// empty command or `cat` turns paging off, as in git.
+pub fn pager_command(own_env: Option<&str>, configured: Option<&str>, env: Option<&str>) -> Option<String> {
+    let command = own_env.or(configured).or(env).unwrap_or(DEFAULT_PAGER).trim();
+    (!command.is_empty() && command != "cat").then(|| command.to_string())
+}
+
+/// Send output written from now on through `command`
+pub fn enable(command: String) {
+    *lock() = Output::Pending(command);
+}
+
+/// Close the pager's input and wait for the user to quit it. Call before exiting
+/// so the shell prompt doesn't draw over the pager.
+pub fn finish() {
+    let output = std::mem::replace(&mut *lock(), Output::Stdout);
+    if let Output::Paging(pager) = output {
+        let _ = pager.finish();
+    }
+}
+
+/// Backend of `out!`/`outln!`
+pub fn write(args: fmt::Arguments) {
+    if CAPTURED.try_with(|c| fmt::Write::write_fmt(&mut *c.borrow_mut(), args)).is_ok() {
+        return;
+    }
+    let mut output = lock();
+    if let Output::Pending(command) = &*output {
+        *output = match Pager::spawn(command) {
+            Ok(pager) => Output::Paging(pager),
+            Err(e) => {
+                eprintln!("warning: could not start pager `{command}`: {e}");
+                Output::Stdout
+            }
+        };
+    }
+    let result = match &mut *output {
+        Output::Paging(pager) => pager.write(args.to_string().as_bytes()).map(|_| ()),
+        _ => io::stdout().lock().write_fmt(args),
+    };
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => panic!("failed printing to stdout: {e}"),
+        _ => {}
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Output> {
+    OUTPUT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A running pager process fed through a pipe
+pub struct Pager {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl Pager {
+    pub fn spawn(command: &str) -> io::Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        Ok(Self { child, stdin })
+    }
+
+    /// Write `data` to the pager. Ok(false) once it has exited, after which
+    /// further writes are dropped.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<bool> {
+        let Some(stdin) = &mut self.stdin else {
+            return Ok(false);
+        };
+        match stdin.write_all(data) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.stdin = None;
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        drop(self.stdin.take());
+        self.child.wait().map(|_| ())
+    }
+}
+
+/// `print!` through the pager when paging is enabled
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::pager::write(format_args!($($arg)*))
+    };
+}
+
+/// `println!` through the pager when paging is enabled
+macro_rules! outln {
+    () => {
+        $crate::pager::write(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::pager::write(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+pub(crate) use {out, outln};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pager_exiting_early_is_not_an_error() {
+        // Reads one byte and quits, like a user pressing `q` on the first screen
+        let mut pager = Pager::spawn("head -c 1 >/dev/null").unwrap();
+        let chunk = vec![b'x'; 64 * 1024];
+        let open = (0..64).all(|_| pager.write(&chunk).unwrap());
+        assert!(!open, "writes past the pager's exit report it as gone");
+        assert!(!pager.write(b"more").unwrap());
+        pager.finish().unwrap();
+    }
+
+    #[test]
+    fn test_pager_receives_output() {
+        let path = std::env::temp_dir().join(format!("gh-agent-pager-{}", std::process::id()));
+        let mut pager = Pager::spawn(&format!("cat > {}", path.display())).unwrap();
+        assert!(pager.write(b"line 1\nline 2\n").unwrap());
+        pager.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "line 1\nline 2\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_capture_collects_output() {
+        let (text, value) = capture(async {
+            outln!("one");
+            let (inner, ()) = capture(async { out!("nested") }).await;
+            outln!("two {inner}");
+            7
+        })
+        .await;
+        assert_eq!((text.as_str(), value), ("one\ntwo nested\n", 7));
+    }
+
+    #[test]
+    fn test_json_is_never_paged() {
+        use clap::Parser;
+        let command = |args: &[&str]| match crate::cli::Cli::parse_from([&["gh-agent", "pr"], args].concat()).command {
+            crate::cli::Commands::Pr { command } => command,
+            crate::cli::Commands::Config { .. } => unreachable!(),
+        };
+        // `pr file` and `pr merge` print JSON without a --json flag
+        for args in [&["file", "1", "-r", "o/r", "-p", "a.rs"][..], &["merge", "1", "-r", "o/r"], &["diff", "1", "-r", "o/r", "--json"]] {
+            assert!(!should_page(true, false, command(args).prints_json()), "{args:?}");
+        }
+        let markdown = command(&["file", "1", "-r", "o/r", "-p", "a.rs", "--format", "markdown"]);
+        assert!(should_page(true, false, markdown.prints_json()));
+        assert!(!should_page(true, true, markdown.prints_json()));
+    }
+
+    #[test]
+    fn test_pager_command() {
+        assert_eq!(pager_command(None, None, None).as_deref(), Some(DEFAULT_PAGER));
+        assert_eq!(pager_command(None, None, Some("more")).as_deref(), Some("more"));
+        assert_eq!(pager_command(None, Some("less -R"), Some("more")).as_deref(), Some("less -R"));
+        assert_eq!(pager_command(Some("most"), Some("less -R"), Some("more")).as_deref(), Some("most"));
+        assert_eq!(pager_command(None, Some(""), Some("more")), None);
+        assert_eq!(pager_command(Some(""), None, Some("more")), None);
+        assert_eq!(pager_command(None, None, Some("cat")), None);
+    }
+}
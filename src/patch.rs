@@ -0,0 +1,525 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use crate::sed::Substitution;
+
+/// Lines of unchanged context around each hunk (git's default)
+const CONTEXT: usize = 3;
+
+/// A suggested replacement of lines `start_line..=end_line` (1-indexed, head side)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub path: String,
+    pub start_line: u64,
+    pub end_line: u64,
+    pub replacement: String,
+}
+
+/// Pull the replacement text out of the first ```suggestion block in a comment body
+/// (fenced with three or more backticks)
+pub fn extract_suggestion(body: &str) -> Option<String> {
+    crate::format::parse_suggestions(body).first().map(|block| block.lines.join("\n"))
+}
+
+/// A suggestion held against the file it would change, before posting it
+#[derive(Debug, PartialEq)]
+pub struct SuggestionCheck {
+    /// The lines it replaces as they are now; None when they're past the end of the file
+    pub original: Option<String>,
+    /// Reasons its "Apply suggestion" button would be wrong or pointless
+    pub problems: Vec<String>,
+}
+
+/// Check `s` against `content` (the file at the PR head) and, when given, the file's
+/// commentable diff lines: the lines must exist and be in the diff, and the
+/// replacement must change something.
+pub fn check_suggestion(content: &str, s: &Suggestion, commentable: Option<&[u64]>) -> SuggestionCheck {
+    let at = format!("{}:{}-{}", s.path, s.start_line, s.end_line);
+    let mut problems = Vec::new();
+    let outside = commentable.and_then(|cl| (s.start_line..=s.end_line).find(|l| !cl.contains(l)));
+    if let Some(line) = outside {
+        problems.push(format!("{at}: line {line} is not in the diff"));
+    }
+    let lines: Vec<&str> = content.lines().collect();
+    let total = lines.len() as u64;
+    if s.start_line == 0 || s.start_line > s.end_line || s.end_line > total {
+        problems.push(format!("{at} is outside the file ({total} lines)"));
+        return SuggestionCheck { original: None, problems };
+    }
+    let original = lines[(s.start_line - 1) as usize..s.end_line as usize].join("\n");
+    if s.replacement.strip_suffix('\n').unwrap_or(&s.replacement) == original {
+        problems.push(format!("{at}: the replacement is identical to the current lines (no-op suggestion)"));
+    }
+    SuggestionCheck {
+        original: Some(original),
+        problems,
+    }
+}
+
+/// Re-indent `replacement` to sit where `original` is: its own common indentation
+/// is swapped for the original lines' (tabs and spaces kept as they are). Blank
+/// lines stay empty.
+pub fn match_indent(original: &str, replacement: &str) -> String {
+    let indent = common_indent(original);
+    let own = common_indent(replacement).len();
+    let mut out: Vec<String> = replacement
+        .lines()
+        .map(|l| if l.trim().is_empty() { String::new() } else { format!("{indent}{}", &l[own..]) })
+        .collect();
+    if replacement.ends_with('\n') {
+        out.push(String::new());
+    }
+    out.join("\n")
+}
+
+/// The leading whitespace all non-blank lines of `text` share, compared by char
+pub(crate) fn common_indent(text: &str) -> &str {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let Some(first) = lines.next() else {
+        return "";
+    };
+    let mut indent = &first[..first.len() - first.trim_start().len()];
+    for l in lines {
+        let shared: usize = indent.chars().zip(l.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+        indent = &indent[..shared];
+    }
+    indent
+}
+
+/// The current lines (`-`) above their replacement (`+`), for eyeballing a suggestion
+pub fn render_check(s: &Suggestion, check: &SuggestionCheck) -> String {
+    let mut out = format!("suggestion for {}:{}-{}:\n", s.path, s.start_line, s.end_line);
+    match &check.original {
+        Some(original) => original.lines().for_each(|l| out.push_str(&format!("  -{l}\n"))),
+        None => out.push_str("  (current lines unknown)\n"),
+    }
+    let replacement = s.replacement.strip_suffix('\n').unwrap_or(&s.replacement);
+    replacement.lines().for_each(|l| out.push_str(&format!("  +{l}\n")));
+    out
+}
+
+/// Suggestions that turn `old` (the file at the PR head) into `new`, one per changed
+/// run of lines. Lines only added have none of their own to comment on, so they're
+/// suggested as a replacement of the line above, or at the top, of the line below.
+/// Changes that land on the same line are merged into one suggestion, since
+/// GitHub can't apply overlapping ones together.
+pub fn suggestions_from_edit(path: &str, old: &str, new: &str) -> Vec<Suggestion> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    if old_lines.is_empty() {
+        return vec![];
+    }
+    // Each change with the old lines its suggestion replaces, inclusive
+    let mut groups: Vec<(u64, u64, Vec<crate::diff::LineChange>)> = Vec::new();
+    for c in crate::diff::line_changes(old, new) {
+        let (start, end) = match (c.old_count, c.old_start) {
+            (0, 1) => (1, 1),
+            (0, at) => (at - 1, at - 1),
+            (n, at) => (at, at + n - 1),
+        };
+        match groups.last_mut() {
+            Some((_, last_end, changes)) if start <= *last_end => {
+                *last_end = (*last_end).max(end);
+                changes.push(c);
+            }
+            _ => groups.push((start, end, vec![c])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(start, end, changes)| {
+            // The old lines start..=end with each change applied in turn
+            let mut lines: Vec<&str> = Vec::new();
+            let mut next = start;
+            for c in &changes {
+                lines.extend(&old_lines[next as usize - 1..c.old_start as usize - 1]);
+                lines.extend(c.new_lines.iter().map(String::as_str));
+                next = c.old_start + c.old_count;
+            }
+            lines.extend(&old_lines[next as usize - 1..end as usize]);
+            Suggestion {
+                path: path.to_string(),
+                start_line: start,
+                end_line: end,
+                replacement: lines.join("\n"),
+            }
+        })
+        .collect()
+}
+
+/// `sub` made on every line of `content` within `ranges` (inclusive, 1-indexed), one
+/// suggestion per run of adjacent changed lines in the same range
+pub fn suggestions_from_substitution(
+    path: &str,
+    content: &str,
+    sub: &Substitution,
+    ranges: &[(u64, u64)],
+) -> Vec<Suggestion> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut suggestions: Vec<Suggestion> = Vec::new();
+    for &(first, last) in ranges {
+        let mut run: Option<Suggestion> = None;
+        for n in first..=last.min(lines.len() as u64) {
+            let line = lines[n as usize - 1];
+            match sub.apply(line).filter(|new| new != line) {
+                Some(new) => match run.as_mut() {
+                    Some(s) => {
+                        s.end_line = n;
+                        s.replacement.push('\n');
+                        s.replacement.push_str(&new);
+                    }
+                    None => {
+                        run = Some(Suggestion {
+                            path: path.to_string(),
+                            start_line: n,
+                            end_line: n,
+                            replacement: new,
+                        })
+                    }
+                },
+                None => suggestions.extend(run.take()),
+            }
+        }
+        suggestions.extend(run);
+    }
+    suggestions
+}
+
+/// What "Apply suggestion" would do to a file: the lines around the edit as they
+/// are and as they'd become, and the change as a unified diff
+#[derive(Debug, Serialize)]
+pub struct SuggestionPreview {
+    pub before: String,
+    pub after: String,
+    pub diff: String,
+}
+
+/// Preview `s` against `content` (the file at the PR head), with the same context
+/// the diff shows
+pub fn preview_suggestion(content: &str, s: &Suggestion) -> Result<SuggestionPreview> {
+    let diff = build_patch(&HashMap::from([(s.path.clone(), content.to_string())]), std::slice::from_ref(s))?;
+    let lines: Vec<&str> = content.lines().collect();
+    let (start, end) = (s.start_line as usize, s.end_line as usize);
+    let from = start.saturating_sub(CONTEXT).max(1);
+    let to = (end + CONTEXT).min(lines.len());
+    let replacement: Vec<&str> = s.replacement.lines().collect();
+    let after: Vec<&str> = lines[from - 1..start - 1]
+        .iter()
+        .chain(&replacement)
+        .chain(&lines[end..to])
+        .copied()
+        .collect();
+    Ok(SuggestionPreview {
+        before: lines[from - 1..to].join("\n"),
+        after: after.join("\n"),
+        diff,
+    })
+}
+
+/// Render suggestions as a single git-applyable unified diff.
+/// `contents` maps each path to its current (head) content.
+/// Errors on ranges outside the file and on overlapping suggestions within a file.
+pub fn build_patch(contents: &HashMap<String, String>, suggestions: &[Suggestion]) -> Result<String> {
+    let mut by_file: BTreeMap<&str, Vec<&Suggestion>> = BTreeMap::new();
+    for s in suggestions {
+        by_file.entry(s.path.as_str()).or_default().push(s);
+    }
+
+    let mut out = String::new();
+    for (path, mut edits) in by_file {
+        let content = contents
+            .get(path)
+            .ok_or_else(|| anyhow::anyhow!("No content fetched for {path}"))?;
+        edits.sort_by_key(|s| s.start_line);
+
+        let total = content.lines().count() as u64;
+        for s in &edits {
+            if s.start_line == 0 || s.start_line > s.end_line || s.end_line > total {
+                anyhow::bail!(
+                    "{path}:{}-{} is outside the file ({total} lines)",
+                    s.start_line, s.end_line
+                );
+            }
+        }
+        for pair in edits.windows(2) {
+            if pair[1].start_line <= pair[0].end_line {
+                anyhow::bail!(
+                    "Overlapping suggestions in {path}: lines {}-{} and {}-{}",
+                    pair[0].start_line, pair[0].end_line, pair[1].start_line, pair[1].end_line
+                );
+            }
+        }
+
+        out.push_str(&format!("diff --git a/{path} b/{path}\n--- a/{path}\n+++ b/{path}\n"));
+        out.push_str(&file_hunks(content, &edits));
+    }
+    Ok(out)
+}
+
+/// Build the hunks for one file. Edits must be sorted and non-overlapping.
+fn file_hunks(content: &str, edits: &[&Suggestion]) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let no_eol = !content.is_empty() && !content.ends_with('\n');
+    let last = lines.len();
+
+    // Group edits whose context windows touch into a single hunk
+    let mut groups: Vec<Vec<&Suggestion>> = Vec::new();
+    for &e in edits {
+        match groups.last_mut() {
+            Some(g) if e.start_line as usize <= g.last().unwrap().end_line as usize + 2 * CONTEXT + 1 => {
+                g.push(e)
+            }
+            _ => groups.push(vec![e]),
+        }
+    }
+
+    let mut out = String::new();
+    let mut delta: i64 = 0;
+    for group in groups {
+        let first = group[0].start_line as usize;
+        let end = group.last().unwrap().end_line as usize;
+        let hunk_start = first.saturating_sub(CONTEXT).max(1);
+        let hunk_end = (end + CONTEXT).min(last);
+
+        let mut body = String::new();
+        let mut old_count = 0usize;
+        let mut new_count = 0usize;
+        let mut line = hunk_start;
+        let push = |body: &mut String, prefix: char, text: &str, at_eof: bool| {
+            body.push(prefix);
+            body.push_str(text);
+            body.push('\n');
+            if at_eof && no_eol {
+                body.push_str("\\ No newline at end of file\n");
+            }
+        };
+
+        for e in &group {
+            let (s, t) = (e.start_line as usize, e.end_line as usize);
+            while line < s {
+                push(&mut body, ' ', lines[line - 1], line == last);
+                old_count += 1;
+                new_count += 1;
+                line += 1;
+            }
+            for l in s..=t {
+                push(&mut body, '-', lines[l - 1], l == last);
+                old_count += 1;
+            }
+            let replacement: Vec<&str> = if e.replacement.is_empty() {
+                vec![]
+            } else {
+                e.replacement.lines().collect()
+            };
+            for (i, r) in replacement.iter().enumerate() {
+                push(&mut body, '+', r, t == last && i + 1 == replacement.len());
+                new_count += 1;
+            }
+            line = t + 1;
+        }
+        while line <= hunk_end {
+            push(&mut body, ' ', lines[line - 1], line == last);
+            old_count += 1;
+            new_count += 1;
+            line += 1;
+        }
+
+        let new_start = hunk_start as i64 + delta;
+        let new_start = if new_count == 0 { new_start - 1 } else { new_start };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            hunk_start, old_count, new_start, new_count
+        ));
+        out.push_str(&body);
+        delta += new_count as i64 - old_count as i64;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    fn suggestion(start: u64, end: u64, replacement: &str) -> Suggestion {
+        Suggestion {
+            path: "src/lib.rs".to_string(),
+            start_line: start,
+            end_line: end,
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_check_suggestion() {
+        let content = "fn a() {\n    old();\n}\n";
+        let commentable = [1, 2, 3];
+
+        let fine = check_suggestion(content, &suggestion(2, 2, "    new();\n"), Some(&commentable));
+        assert_eq!(fine.original.as_deref(), Some("    old();"));
+        assert!(fine.problems.is_empty(), "{:?}", fine.problems);
+
+        let noop = check_suggestion(content, &suggestion(2, 2, "    old();"), Some(&commentable));
+        assert_eq!(noop.problems, ["src/lib.rs:2-2: the replacement is identical to the current lines (no-op suggestion)"]);
+
+        let outside = check_suggestion(content, &suggestion(2, 3, "x"), Some(&[2]));
+        assert_eq!(outside.problems, ["src/lib.rs:2-3: line 3 is not in the diff"]);
+
+        let past_end = check_suggestion(content, &suggestion(3, 5, "x"), Some(&commentable));
+        assert_eq!(past_end.original, None);
+        assert_eq!(past_end.problems[1], "src/lib.rs:3-5 is outside the file (3 lines)");
+
+        let s = suggestion(2, 2, "    new();");
+        assert_eq!(render_check(&s, &fine), "suggestion for src/lib.rs:2-2:\n  -    old();\n  +    new();\n");
+    }
+
+    /// Write `content` to a scratch dir, `git apply` the patch, return the result
+    fn apply(name: &str, content: &str, patch: &str) -> String {
+        let dir: PathBuf = std::env::temp_dir().join(format!("gh-agent-patch-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), content).unwrap();
+        std::fs::write(dir.join("fix.patch"), patch).unwrap();
+
+        for args in [&["apply", "--check", "fix.patch"][..], &["apply", "fix.patch"][..]] {
+            let out = Command::new("git").args(args).current_dir(&dir).output().unwrap();
+            assert!(out.status.success(), "git {args:?} failed: {}", String::from_utf8_lossy(&out.stderr));
+        }
+        let result = std::fs::read_to_string(dir.join("src/lib.rs")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    fn fixture() -> String {
+        (1..=20).map(|i| format!("line {i}\n")).collect()
+    }
+
+    #[test]
+    fn test_round_trip_multiple_suggestions() {
+        let content = fixture();
+        let contents = HashMap::from([("src/lib.rs".to_string(), content.clone())]);
+        let patch = build_patch(
+            &contents,
+            &[
+                suggestion(2, 3, "two\nthree\nthree and a half"),
+                suggestion(5, 5, "five"),
+                suggestion(15, 16, "fifteen"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(patch.matches("@@ ").count(), 2, "nearby edits share a hunk:\n{patch}");
+
+        let expected = content
+            .replace("line 2\nline 3\n", "two\nthree\nthree and a half\n")
+            .replace("line 5\n", "five\n")
+            .replace("line 15\nline 16\n", "fifteen\n");
+        assert_eq!(apply("multi", &content, &patch), expected);
+    }
+
+    #[test]
+    fn test_round_trip_deletion_at_eof_without_newline() {
+        let content = "a\nb\nc";
+        let contents = HashMap::from([("src/lib.rs".to_string(), content.to_string())]);
+        let patch = build_patch(&contents, &[suggestion(3, 3, "C")]).unwrap();
+        assert_eq!(apply("eof", content, &patch), "a\nb\nC");
+
+        let patch = build_patch(&contents, &[suggestion(2, 2, "")]).unwrap();
+        assert_eq!(apply("delete", content, &patch), "a\nc");
+    }
+
+    #[test]
+    fn test_suggestions_from_edit() {
+        let old = "a\nb\nc\nd\n";
+        let got = suggestions_from_edit("src/lib.rs", old, "top\na\nB\nc\nd\nend\n");
+        assert_eq!(got, [suggestion(1, 1, "top\na"), suggestion(2, 2, "B"), suggestion(4, 4, "d\nend")]);
+        assert_eq!(suggestions_from_edit("src/lib.rs", old, "a\nd\n"), [suggestion(2, 3, "")]);
+        assert_eq!(suggestions_from_edit("src/lib.rs", "", "new\n"), []);
+        // Insertions on both sides of line 1 land on it together
+        assert_eq!(suggestions_from_edit("src/lib.rs", "a\nb", "x\na\ny\nb"), [suggestion(1, 1, "x\na\ny")]);
+
+        // Applied together, they give the local file
+        let contents = HashMap::from([("src/lib.rs".to_string(), old.to_string())]);
+        let patch = build_patch(&contents, &suggestions_from_edit("src/lib.rs", old, "a\nB\nc\nC\nd\n")).unwrap();
+        assert_eq!(apply("from-edit", old, &patch), "a\nB\nc\nC\nd\n");
+    }
+
+    #[test]
+    fn test_common_indent_compares_chars() {
+        // En space and em space share their first UTF-8 byte
+        assert_eq!(common_indent("\u{2002}a\n\u{2003}b"), "");
+        assert_eq!(common_indent("\u{2002}\u{2002}a\n\u{2002}\u{2003}b\n\n"), "\u{2002}");
+        assert_eq!(match_indent("  x", "\u{2002}a\n\u{2003}b"), "  \u{2002}a\n  \u{2003}b");
+    }
+
+    #[test]
+    fn test_suggestions_from_substitution() {
+        let sub = Substitution::parse("s/fooBar/foo_bar/g").unwrap();
+        let content = "fooBar\nfooBar()\nx\nfooBar\nfooBar\nfooBar\n";
+        // Line 2 is outside the ranges, line 4 ends one range and line 5 starts the next
+        let got = suggestions_from_substitution("src/lib.rs", content, &sub, &[(1, 1), (3, 4), (5, 9)]);
+        assert_eq!(
+            got,
+            [suggestion(1, 1, "foo_bar"), suggestion(4, 4, "foo_bar"), suggestion(5, 6, "foo_bar\nfoo_bar")]
+        );
+
+        // A match that changes nothing isn't a suggestion
+        let same = Substitution::parse("s/x/x/").unwrap();
+        assert_eq!(suggestions_from_substitution("src/lib.rs", content, &same, &[(1, 6)]), []);
+    }
+
+    #[test]
+    fn test_match_indent() {
+        // Go: tabs, replacement written flush left with a nested block
+        let original = "\tif err != nil {\n\t\treturn err\n\t}";
+        let replacement = "if err != nil {\n\treturn fmt.Errorf(\"read: %w\", err)\n}\n";
+        assert_eq!(match_indent(original, replacement), "\tif err != nil {\n\t\treturn fmt.Errorf(\"read: %w\", err)\n\t}\n");
+
+        // TS: 2 spaces, replacement over-indented by 4, with a blank line that has spaces
+        let original = "  const a = 1;\n\n  const b = 2;";
+        let replacement = "      const a = 1;\n      \n      const b = a + 1;";
+        assert_eq!(match_indent(original, replacement), "  const a = 1;\n\n  const b = a + 1;");
+
+        assert_eq!(match_indent("    x", ""), "");
+        assert_eq!(match_indent("x", "  y"), "y");
+    }
+
+    #[test]
+    fn test_preview_at_the_edges_of_the_file() {
+        let content = fixture();
+        let first = preview_suggestion(&content, &suggestion(1, 2, "one")).unwrap();
+        assert_eq!(first.before, "line 1\nline 2\nline 3\nline 4\nline 5");
+        assert_eq!(first.after, "one\nline 3\nline 4\nline 5");
+        assert!(first.diff.contains("@@ -1,5 +1,4 @@\n-line 1\n-line 2\n+one\n line 3\n"), "{}", first.diff);
+
+        let last = preview_suggestion(&content, &suggestion(20, 20, "twenty\ntwenty-one\n")).unwrap();
+        assert_eq!(last.before, "line 17\nline 18\nline 19\nline 20");
+        assert_eq!(last.after, "line 17\nline 18\nline 19\ntwenty\ntwenty-one");
+        assert_eq!(apply("preview-end", &content, &last.diff), content.replace("line 20\n", "twenty\ntwenty-one\n"));
+
+        let no_eol = preview_suggestion("a\nb", &suggestion(2, 2, "B")).unwrap();
+        assert_eq!((no_eol.before.as_str(), no_eol.after.as_str()), ("a\nb", "a\nB"));
+        assert!(no_eol.diff.ends_with("-b\n\\ No newline at end of file\n+B\n\\ No newline at end of file\n"), "{}", no_eol.diff);
+
+        assert!(preview_suggestion("a\n", &suggestion(2, 3, "x")).is_err());
+    }
+
+    #[test]
+    fn test_overlap_rejected() {
+        let contents = HashMap::from([("src/lib.rs".to_string(), fixture())]);
+        let err = build_patch(&contents, &[suggestion(4, 6, "x"), suggestion(6, 8, "y")]).unwrap_err();
+        assert!(err.to_string().contains("Overlapping"));
+        assert!(build_patch(&contents, &[suggestion(19, 21, "x")]).is_err());
+    }
+
+    #[test]
+    fn test_extract_suggestion() {
+        assert_eq!(
+            extract_suggestion("Use this:\n```suggestion\nlet x = 1;\n```\nthanks").as_deref(),
+            Some("let x = 1;")
+        );
+        assert_eq!(extract_suggestion("```suggestion\n```").as_deref(), Some(""));
+        assert_eq!(extract_suggestion("no block here"), None);
+    }
+}
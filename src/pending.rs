@@ -0,0 +1,51 @@
+//! The pending review `pr review start` opens, remembered per PR so the later
+//! `pr review add`, `submit` and `abandon` calls can find it.
+
+use crate::config;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingReview {
+    /// REST id, for submitting and deleting
+    pub id: u64,
+    /// GraphQL node id, for adding comments
+    pub node_id: String,
+    /// Head the review was started on
+    pub commit_id: String,
+    /// Comments added so far
+    pub comments: usize,
+}
+
+/// `<cache>/pending/<owner>__<repo>/<number>.json`
+fn path(repo: &str, number: u64) -> Result<PathBuf> {
+    let dir = config::cache_dir()?.join("pending").join(config::repo_cache_name(repo));
+    Ok(dir.join(format!("{number}.json")))
+}
+
+/// The pending review started on this PR, if any
+pub fn load(repo: &str, number: u64) -> Result<Option<PendingReview>> {
+    let path = path(repo, number)?;
+    let Ok(raw) = std::fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+    let review = serde_json::from_str(&raw).with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(review))
+}
+
+pub fn store(repo: &str, number: u64, review: &PendingReview) -> Result<()> {
+    let path = path(repo, number)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(&path, serde_json::to_string(review)?).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Forget the PR's pending review (after submitting or abandoning it)
+pub fn clear(repo: &str, number: u64) -> Result<()> {
+    match std::fs::remove_file(path(repo, number)?) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
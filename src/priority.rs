@@ -0,0 +1,305 @@
+//! `pr priorities`: rank changed files by where review attention pays off first.
+//! Every score is a plain sum of `count × weight` factors, and each factor is
+//! shown, so a ranking can always be explained. Scoring is pure;
+//! `commands::pr_priorities` gathers the signals.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Path substrings (case-insensitive) that mark a file as sensitive
+pub const SENSITIVE_PATTERNS: &[&str] = &[
+    "auth",
+    "crypto",
+    "secret",
+    "password",
+    "token",
+    "permission",
+    "security",
+    "migration",
+    ".github/workflows",
+    "dockerfile",
+];
+
+/// Points per unit of each factor. Names match the keys of `[priority.weights]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weights {
+    /// Per behavioral change in the smart analysis
+    pub behavioral: f64,
+    /// Per new-logic change in the smart analysis
+    pub new_logic: f64,
+    /// Per mechanical change in the smart analysis
+    pub mechanical: f64,
+    /// Per added line outside test code
+    pub code_lines: f64,
+    /// Once, when the path matches a sensitive pattern
+    pub sensitive: f64,
+    /// Once, when CODEOWNERS requires a review for the path
+    pub codeowners: f64,
+    /// Per unresolved review thread on the file
+    pub unresolved: f64,
+    /// Per commit touching the path on base in the churn window
+    pub churn: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            behavioral: 5.0,
+            new_logic: 3.0,
+            mechanical: 0.5,
+            code_lines: 0.1,
+            sensitive: 8.0,
+            codeowners: 2.0,
+            unresolved: 4.0,
+            churn: 0.5,
+        }
+    }
+}
+
+impl Weights {
+    /// Defaults with `overrides` (factor name -> weight) applied
+    pub fn with_overrides(overrides: &BTreeMap<String, f64>) -> Result<Self> {
+        let mut w = Self::default();
+        for (name, &value) in overrides {
+            let slot = match name.as_str() {
+                "behavioral" => &mut w.behavioral,
+                "new_logic" => &mut w.new_logic,
+                "mechanical" => &mut w.mechanical,
+                "code_lines" => &mut w.code_lines,
+                "sensitive" => &mut w.sensitive,
+                "codeowners" => &mut w.codeowners,
+                "unresolved" => &mut w.unresolved,
+                "churn" => &mut w.churn,
+                _ => anyhow::bail!(
+                    "Unknown priority weight '{name}' (expected behavioral, new_logic, mechanical, code_lines, sensitive, codeowners, unresolved or churn)"
+                ),
+            };
+            *slot = value;
+        }
+        Ok(w)
+    }
+}
+
+/// What's known about one changed file
+#[derive(Debug, Default)]
+pub struct FileSignals {
+    pub path: String,
+    pub behavioral: usize,
+    pub new_logic: usize,
+    pub mechanical: usize,
+    /// Added lines; zero for test files
+    pub code_additions: u64,
+    /// The sensitive pattern the path matched
+    pub sensitive: Option<String>,
+    /// Owners (CODEOWNERS or OWNERS files) whose review the path needs
+    pub owners: Vec<String>,
+    pub unresolved: usize,
+    /// Commits on base in the churn window; None when not fetched
+    pub churn: Option<usize>,
+}
+
+/// One term of a file's score
+#[derive(Debug, Serialize)]
+pub struct Factor {
+    pub name: &'static str,
+    pub count: f64,
+    pub weight: f64,
+    pub points: f64,
+    /// Why the factor applies, when the count alone doesn't say
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Ranked {
+    pub path: String,
+    pub score: f64,
+    pub factors: Vec<Factor>,
+    /// Command to inspect the file next
+    pub command: String,
+}
+
+/// Test code matters less than the code it tests
+pub fn is_test_path(path: &str) -> bool {
+    let lower = path.to_lowercase();
+    let name = lower.rsplit('/').next().unwrap_or_default();
+    lower.split('/').any(|seg| matches!(seg, "test" | "tests" | "spec" | "__tests__" | "testdata"))
+        || name.starts_with("test_")
+        || name.contains("_test.")
+        || name.contains(".test.")
+        || name.contains(".spec.")
+}
+
+/// First pattern (built-in, then `extra`) the path contains, case-insensitively
+pub fn sensitive_match(path: &str, extra: &[String]) -> Option<String> {
+    let lower = path.to_lowercase();
+    SENSITIVE_PATTERNS
+        .iter()
+        .map(|p| p.to_string())
+        .chain(extra.iter().cloned())
+        .find(|p| lower.contains(&p.to_lowercase()))
+}
+
+fn round2(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
+
+/// The file's nonzero factors and their sum
+pub fn score(s: &FileSignals, w: &Weights) -> (f64, Vec<Factor>) {
+    let terms = [
+        ("behavioral", s.behavioral as f64, w.behavioral, None),
+        ("new_logic", s.new_logic as f64, w.new_logic, None),
+        ("mechanical", s.mechanical as f64, w.mechanical, None),
+        ("code_lines", s.code_additions as f64, w.code_lines, None),
+        (
+            "sensitive",
+            f64::from(u8::from(s.sensitive.is_some())),
+            w.sensitive,
+            s.sensitive.as_ref().map(|p| format!("path matches \"{p}\"")),
+        ),
+        (
+            "codeowners",
+            f64::from(u8::from(!s.owners.is_empty())),
+            w.codeowners,
+            (!s.owners.is_empty()).then(|| format!("needs review from {}", s.owners.join(", "))),
+        ),
+        ("unresolved", s.unresolved as f64, w.unresolved, None),
+        ("churn", s.churn.unwrap_or(0) as f64, w.churn, None),
+    ];
+    let factors: Vec<Factor> = terms
+        .into_iter()
+        .filter(|(_, count, weight, _)| *count > 0.0 && *weight != 0.0)
+        .map(|(name, count, weight, note)| Factor {
+            name,
+            count,
+            weight,
+            points: round2(count * weight),
+            note,
+        })
+        .collect();
+    let total = round2(factors.iter().map(|f| f.points).sum());
+    (total, factors)
+}
+
+/// Score every file, highest first; ties keep PR order
+pub fn rank(signals: &[FileSignals], w: &Weights, repo: &str, number: u64) -> Vec<Ranked> {
+    let mut ranked: Vec<Ranked> = signals
+        .iter()
+        .map(|s| {
+            let (score, factors) = score(s, w);
+            Ranked {
+                path: s.path.clone(),
+                score,
+                factors,
+                command: format!("gh-agent pr diff --repo {repo} {number} --file {}", s.path),
+            }
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+    ranked
+}
+
+pub fn format_ranked(ranked: &[Ranked]) -> String {
+    let mut out = Vec::new();
+    for (i, r) in ranked.iter().enumerate() {
+        out.push(format!("{}. {}  score {}", i + 1, r.path, r.score));
+        let width = r.factors.iter().map(|f| f.name.len()).max().unwrap_or(0);
+        for f in &r.factors {
+            let note = f.note.as_ref().map(|n| format!("  ({n})")).unwrap_or_default();
+            out.push(format!(
+                "     {:<width$}  {} × {} = {}{note}",
+                f.name, f.count, f.weight, f.points
+            ));
+        }
+        out.push(format!("     → {}", r.command));
+    }
+    out.join("\n")
+}
+
+/// `YYYY-MM-DDT00:00:00Z`, `days` before the unix time `now`
+pub fn iso_date_days_ago(now: u64, days: u64) -> String {
+    // Civil-from-days (Howard Hinnant's algorithm)
+    let z = (now / 86_400).saturating_sub(days) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}T00:00:00Z")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_is_explainable() {
+        let s = FileSignals {
+            path: "src/auth/session.rs".to_string(),
+            behavioral: 2,
+            code_additions: 120,
+            sensitive: sensitive_match("src/auth/session.rs", &[]),
+            owners: vec!["@sec".to_string()],
+            churn: Some(3),
+            ..Default::default()
+        };
+        let ranked = rank(
+            &[
+                FileSignals {
+                    path: "README.md".to_string(),
+                    code_additions: 4,
+                    ..Default::default()
+                },
+                s,
+            ],
+            &Weights::default(),
+            "o/r",
+            7,
+        );
+        assert_eq!(
+            format_ranked(&ranked),
+            "\
+1. src/auth/session.rs  score 33.5
+     behavioral  2 × 5 = 10
+     code_lines  120 × 0.1 = 12
+     sensitive   1 × 8 = 8  (path matches \"auth\")
+     codeowners  1 × 2 = 2  (needs review from @sec)
+     churn       3 × 0.5 = 1.5
+     → gh-agent pr diff --repo o/r 7 --file src/auth/session.rs
+2. README.md  score 0.4
+     code_lines  4 × 0.1 = 0.4
+     → gh-agent pr diff --repo o/r 7 --file README.md"
+        );
+    }
+
+    #[test]
+    fn test_weight_overrides() {
+        let w = Weights::with_overrides(&BTreeMap::from([("churn".to_string(), 0.0)])).unwrap();
+        assert_eq!(w.churn, 0.0);
+        assert_eq!(w.behavioral, Weights::default().behavioral);
+        let err = Weights::with_overrides(&BTreeMap::from([("vibes".to_string(), 1.0)])).unwrap_err();
+        assert!(err.to_string().contains("Unknown priority weight 'vibes'"), "{err}");
+    }
+
+    #[test]
+    fn test_is_test_path() {
+        for path in ["tests/api.rs", "src/foo_test.go", "web/a.spec.ts", "pkg/test_util.py", "src/__tests__/x.js"] {
+            assert!(is_test_path(path), "{path}");
+        }
+        for path in ["src/attest.rs", "src/contest/mod.rs", "src/lib.rs"] {
+            assert!(!is_test_path(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_iso_date_days_ago() {
+        // 2024-03-01T12:00:00Z
+        assert_eq!(iso_date_days_ago(1_709_294_400, 0), "2024-03-01T00:00:00Z");
+        assert_eq!(iso_date_days_ago(1_709_294_400, 1), "2024-02-29T00:00:00Z");
+        assert_eq!(iso_date_days_ago(1_709_294_400, 90), "2023-12-02T00:00:00Z");
+    }
+}
@@ -0,0 +1,332 @@
+//! Splitting a raw `git diff` (GitHub's `application/vnd.github.diff`) into
+//! per-file patches.
+//!
+//! Each file starts at a `diff --git` line. Its extended headers (`index`, modes,
+//! `similarity`, `rename from`/`rename to`, …) and the `---`/`+++` lines come
+//! before the first `@@`; from there on every line up to the next `diff --git` is
+//! patch content, whatever it starts with. git quotes paths with unusual
+//! characters C-style, as in `"a/caf\303\251.rs"`. A binary file has no hunks,
+//! but a `Binary files … differ` or `GIT binary patch` line instead.
+
+use std::collections::HashMap;
+
+/// One file's part of a diff
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FilePatch {
+    /// The hunks, from the first `@@`. Empty for pure renames, mode changes and
+    /// binary files; None when GitHub left a too large patch out of its file list.
+    pub patch: Option<String>,
+    /// The path before a rename or copy
+    pub old_path: Option<String>,
+    pub binary: bool,
+    /// The `diff --git` line and the headers after it, up to the first hunk.
+    /// None from GitHub's per-file list, which has no headers.
+    pub header: Option<String>,
+}
+
+/// Extensions of formats that are binary whatever the file holds
+const BINARY_EXTENSIONS: &[&str] = &[
+    "7z", "a", "avi", "bin", "bmp", "bz2", "class", "db", "dll", "dylib", "eot", "exe", "flac", "gif", "gz", "ico",
+    "jar", "jpeg", "jpg", "mov", "mp3", "mp4", "o", "ogg", "otf", "pdf", "png", "psd", "pyc", "rar", "so", "sqlite",
+    "tgz", "tiff", "ttf", "wasm", "wav", "webm", "webp", "woff", "woff2", "xz", "zip",
+];
+
+/// Whether `path` has the extension of a binary format
+pub fn binary_extension(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rsplit_once('.')
+        .is_some_and(|(stem, ext)| !stem.is_empty() && BINARY_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Each file's patch, keyed by its path after the change (before it, for a
+/// deleted file)
+pub fn parse(raw: &str) -> HashMap<String, FilePatch> {
+    let mut files = HashMap::new();
+    let mut section: Option<Section> = None;
+    for line in raw.lines() {
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            if let Some(done) = section.take() {
+                done.finish(&mut files);
+            }
+            section = Some(Section {
+                git_paths: git_paths(paths),
+                header: line.to_string(),
+                ..Section::default()
+            });
+        } else if let Some(section) = section.as_mut() {
+            section.push(line);
+        }
+    }
+    if let Some(done) = section {
+        done.finish(&mut files);
+    }
+    files
+}
+
+/// The lines of one file, as they're read
+#[derive(Default)]
+struct Section {
+    /// Old and new path from the `diff --git` line, when it could be split
+    git_paths: Option<(String, String)>,
+    /// From `rename from`/`copy from`
+    from: Option<String>,
+    /// From `rename to`/`copy to`
+    to: Option<String>,
+    /// From `---`, None for /dev/null
+    old: Option<String>,
+    /// From `+++`, None for /dev/null
+    new: Option<String>,
+    in_hunks: bool,
+    binary: bool,
+    header: String,
+    patch: String,
+}
+
+impl Section {
+    fn push(&mut self, line: &str) {
+        if !self.in_hunks && !line.starts_with("@@") {
+            self.header(line);
+            return;
+        }
+        self.in_hunks = true;
+        if !self.patch.is_empty() {
+            self.patch.push('\n');
+        }
+        self.patch.push_str(line);
+    }
+
+    fn header(&mut self, line: &str) {
+        if self.binary {
+            return;
+        }
+        self.header.push('\n');
+        self.header.push_str(line);
+        if let Some(path) = line.strip_prefix("rename from ").or_else(|| line.strip_prefix("copy from ")) {
+            self.from = Some(unquote(path));
+        } else if let Some(path) = line.strip_prefix("rename to ").or_else(|| line.strip_prefix("copy to ")) {
+            self.to = Some(unquote(path));
+        } else if let Some(path) = line.strip_prefix("--- ") {
+            self.old = side_path(path, "a/");
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            self.new = side_path(path, "b/");
+        } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+            self.binary = true;
+        }
+        // index, mode and similarity lines, and a binary patch's data, aren't kept
+    }
+
+    fn finish(self, files: &mut HashMap<String, FilePatch>) {
+        let (git_old, git_new) = self.git_paths.unzip();
+        let old = self.from.or(self.old).or(git_old);
+        let Some(path) = self.to.or(self.new).or(git_new).or_else(|| old.clone()) else {
+            return;
+        };
+        let old_path = old.filter(|old| *old != path);
+        files.insert(
+            path,
+            FilePatch {
+                patch: Some(self.patch),
+                old_path,
+                binary: self.binary,
+                header: Some(self.header),
+            },
+        );
+    }
+}
+
+/// The path on a `---`/`+++` line without its `a/` or `b/`, or None for /dev/null.
+/// git ends the path with a tab when it contains a space.
+fn side_path(path: &str, prefix: &str) -> Option<String> {
+    let path = path.strip_suffix('\t').unwrap_or(path);
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = unquote(path);
+    Some(path.strip_prefix(prefix).map(str::to_string).unwrap_or(path))
+}
+
+/// Split what follows `diff --git ` into the old and new path. Unquoted paths
+/// with spaces are ambiguous, but they're the same path unless the file was
+/// renamed, and then `rename from`/`rename to` name both.
+fn git_paths(paths: &str) -> Option<(String, String)> {
+    let (old, new) = if paths.starts_with('"') {
+        let (old, rest) = split_quoted(paths)?;
+        (old, unquote(rest.strip_prefix(' ')?))
+    } else if let Some(i) = paths.find(" \"b/") {
+        (paths[..i].to_string(), unquote(&paths[i + 1..]))
+    } else {
+        let mid = paths.len() / 2;
+        let same = paths.len() % 2 == 1
+            && paths.is_char_boundary(mid)
+            && paths[..mid].strip_prefix("a/").is_some_and(|old| paths[mid + 1..].strip_prefix("b/") == Some(old));
+        let i = if same { mid } else { paths.find(" b/")? };
+        (paths[..i].to_string(), paths[i + 1..].to_string())
+    };
+    Some((old.strip_prefix("a/")?.to_string(), new.strip_prefix("b/")?.to_string()))
+}
+
+/// A path as git prints it, with C-style quoting undone
+fn unquote(path: &str) -> String {
+    match split_quoted(path) {
+        Some((unquoted, "")) => unquoted,
+        _ => path.to_string(),
+    }
+}
+
+/// The quoted string `s` starts with, unescaped, and what follows its closing quote
+fn split_quoted(s: &str) -> Option<(String, &str)> {
+    let mut bytes = Vec::new();
+    let mut chars = s.strip_prefix('"')?.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((String::from_utf8_lossy(&bytes).into_owned(), &s[i + 2..])),
+            '\\' => {
+                let (_, escaped) = chars.next()?;
+                let byte = match escaped {
+                    'a' => 0x07,
+                    'b' => 0x08,
+                    'f' => 0x0c,
+                    'n' => b'\n',
+                    'r' => b'\r',
+                    't' => b'\t',
+                    'v' => 0x0b,
+                    '0'..='7' => {
+                        let mut value = escaped as u32 - '0' as u32;
+                        for _ in 0..2 {
+                            let (_, digit) = chars.next()?;
+                            value = value * 8 + digit.to_digit(8)?;
+                        }
+                        u8::try_from(value).ok()?
+                    }
+                    other => {
+                        let mut buf = [0; 4];
+                        bytes.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+                        continue;
+                    }
+                };
+                bytes.push(byte);
+            }
+            other => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(patch: &str, old_path: Option<&str>) -> FilePatch {
+        FilePatch {
+            patch: Some(patch.to_string()),
+            old_path: old_path.map(str::to_string),
+            binary: false,
+            header: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_tricky_diffs() {
+        // (name, raw diff, expected files)
+        type Case<'a> = (&'a str, &'a str, &'a [(&'a str, FilePatch)]);
+        let cases: &[Case] = &[
+            (
+                "plain modification",
+                "diff --git a/src/a.rs b/src/a.rs\nindex 1..2 100644\n--- a/src/a.rs\n+++ b/src/a.rs\n@@ -1 +1 @@\n-x\n+y",
+                &[("src/a.rs", patch("@@ -1 +1 @@\n-x\n+y", None))],
+            ),
+            (
+                "pure rename has no hunks",
+                "diff --git a/old.rs b/new.rs\nsimilarity index 100%\nrename from old.rs\nrename to new.rs",
+                &[("new.rs", patch("", Some("old.rs")))],
+            ),
+            (
+                "rename with edits",
+                "diff --git a/lib/old.rs b/lib/new.rs\nsimilarity index 90%\nrename from lib/old.rs\nrename to lib/new.rs\n\
+                 index 1..2 100644\n--- a/lib/old.rs\n+++ b/lib/new.rs\n@@ -1 +1 @@\n-a\n+b",
+                &[("lib/new.rs", patch("@@ -1 +1 @@\n-a\n+b", Some("lib/old.rs")))],
+            ),
+            (
+                "mode-only change",
+                "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n\
+                 diff --git a/b.rs b/b.rs\n--- a/b.rs\n+++ b/b.rs\n@@ -1 +1 @@\n-1\n+2",
+                &[("run.sh", patch("", None)), ("b.rs", patch("@@ -1 +1 @@\n-1\n+2", None))],
+            ),
+            (
+                "path with spaces",
+                "diff --git a/docs/my notes.md b/docs/my notes.md\nindex 1..2 100644\n--- a/docs/my notes.md\t\n+++ b/docs/my notes.md\t\n@@ -1 +1 @@\n-a\n+b",
+                &[("docs/my notes.md", patch("@@ -1 +1 @@\n-a\n+b", None))],
+            ),
+            (
+                "quoted paths with escapes",
+                "diff --git \"a/caf\\303\\251 \\\"x\\\".rs\" \"b/caf\\303\\251 \\\"x\\\".rs\"\n--- \"a/caf\\303\\251 \\\"x\\\".rs\"\n+++ \"b/caf\\303\\251 \\\"x\\\".rs\"\n@@ -1 +1 @@\n-a\n+b",
+                &[("café \"x\".rs", patch("@@ -1 +1 @@\n-a\n+b", None))],
+            ),
+            (
+                "content lines that look like headers",
+                "diff --git a/h.txt b/h.txt\n--- a/h.txt\n+++ b/h.txt\n@@ -1,4 +1,4 @@\n index 1..2\n--- a/x\n+++ b/y\n new file mode 100644\n-rename from a\n+similarity index 5%",
+                &[(
+                    "h.txt",
+                    patch("@@ -1,4 +1,4 @@\n index 1..2\n--- a/x\n+++ b/y\n new file mode 100644\n-rename from a\n+similarity index 5%", None),
+                )],
+            ),
+            (
+                "added and deleted files",
+                "diff --git a/new.rs b/new.rs\nnew file mode 100644\nindex 0..1\n--- /dev/null\n+++ b/new.rs\n@@ -0,0 +1 @@\n+a\n\
+                 diff --git a/gone.rs b/gone.rs\ndeleted file mode 100644\nindex 1..0\n--- a/gone.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-a\n\\ No newline at end of file",
+                &[
+                    ("new.rs", patch("@@ -0,0 +1 @@\n+a", None)),
+                    ("gone.rs", patch("@@ -1 +0,0 @@\n-a\n\\ No newline at end of file", None)),
+                ],
+            ),
+            (
+                "binary patch with data that looks like headers",
+                "diff --git a/f.bin b/f.bin\nindex 1..2\nGIT binary patch\nliteral 4\n--- a/x\n+++ b/y\n\nliteral 2\nzcmV",
+                &[("f.bin", FilePatch { binary: true, ..patch("", None) })],
+            ),
+            (
+                "binary file",
+                "diff --git a/logo.png b/logo.png\nindex 1..2 100644\nBinary files a/logo.png and b/logo.png differ",
+                &[("logo.png", FilePatch { binary: true, ..patch("", None) })],
+            ),
+        ];
+        for (name, raw, expected) in cases {
+            let parsed: HashMap<String, FilePatch> =
+                parse(raw).into_iter().map(|(path, p)| (path, FilePatch { header: None, ..p })).collect();
+            let expected: HashMap<String, FilePatch> = expected.iter().map(|(path, p)| (path.to_string(), p.clone())).collect();
+            assert_eq!(parsed, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_parse_keeps_headers() {
+        let raw = "diff --git a/old.sh b/new.sh\nold mode 100644\nnew mode 100755\nsimilarity index 90%\nrename from old.sh\nrename to new.sh\n\
+                   --- a/old.sh\n+++ b/new.sh\n@@ -1 +1 @@\n-a\n+b\n";
+        assert_eq!(
+            parse(raw)["new.sh"].header.as_deref(),
+            Some("diff --git a/old.sh b/new.sh\nold mode 100644\nnew mode 100755\nsimilarity index 90%\nrename from old.sh\nrename to new.sh\n--- a/old.sh\n+++ b/new.sh")
+        );
+    }
+
+    #[test]
+    fn test_binary_extension() {
+        assert!(binary_extension("assets/Logo.PNG"));
+        assert!(binary_extension("fonts/a.woff2"));
+        assert!(!binary_extension("src/png.rs"));
+        assert!(!binary_extension(".png"));
+        assert!(!binary_extension("png"));
+    }
+
+    #[test]
+    fn test_git_paths() {
+        let paths = |s| git_paths(s).unwrap();
+        assert_eq!(paths("a/x y.rs b/x y.rs"), ("x y.rs".into(), "x y.rs".into()));
+        assert_eq!(paths("a/old.rs b/new.rs"), ("old.rs".into(), "new.rs".into()));
+        assert_eq!(paths("a/x y.rs \"b/x\\ty.rs\""), ("x y.rs".into(), "x\ty.rs".into()));
+        assert_eq!(paths("\"a/\\303\\251\" b/e"), ("é".into(), "e".into()));
+        assert_eq!(git_paths("nonsense"), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! `--redact-content`: strip source code from JSON output so review metadata can
+//! leave the machine without the code it's about.
+//!
+//! Applied once, at the JSON sink, by field name. Every string under a
+//! content-bearing key becomes `{"fnv1a": HASH, "len": BYTES}`, and fenced code
+//! in a comment or review body becomes a line holding the same; everything else
+//! (paths, line numbers, counts, categories, ids) passes through unchanged. The
+//! hash is stable, so two outputs can still be compared for "same code here".
+
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(test)]
+thread_local! {
+    /// Redaction for this thread only, so a test can run a command with it
+    /// without changing the output of tests running beside it
+    static ENABLED_HERE: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Keys whose string values (or arrays of strings) are code: file contents, diff
+/// lines and patches, hunk headers and the function they name, ast-grep match
+/// text and rewrites, suggestion lines and previews, and smart-review
+/// before/after tokens
+const CONTENT_KEYS: &[&str] = &[
+    "content",
+    "patch",
+    "header",
+    "context",
+    "text",
+    "display",
+    "rewrite",
+    "original_lines",
+    "suggested_lines",
+    "removed_tokens",
+    "added_tokens",
+    "before",
+    "after",
+    "diff",
+];
+
+/// Keys whose strings are prose that may quote code: review and comment bodies,
+/// where ``` blocks (```suggestion among them) are hashed and the words kept
+const PROSE_KEYS: &[&str] = &["body"];
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    #[cfg(test)]
+    if ENABLED_HERE.get() {
+        return true;
+    }
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Turn redaction on for the rest of the current test
+#[cfg(test)]
+pub fn enable_for_test() {
+    ENABLED_HERE.set(true);
+}
+
+/// Replace every content-bearing string in `value`, at any depth
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if CONTENT_KEYS.contains(&key.as_str()) {
+                    hash_strings(v);
+                } else if let (true, Value::String(s)) = (PROSE_KEYS.contains(&key.as_str()), &mut *v) {
+                    *s = redact_fences(s);
+                } else {
+                    redact(v);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn hash_strings(value: &mut Value) {
+    match value {
+        Value::String(s) => *value = digest(s),
+        Value::Array(items) => items.iter_mut().for_each(hash_strings),
+        Value::Object(_) => redact(value),
+        _ => {}
+    }
+}
+
+/// `text` with the lines inside each ``` or ~~~ fence swapped for one
+/// `[redacted fnv1a:HASH len:BYTES]` line; an unclosed fence runs to the end
+fn redact_fences(text: &str) -> String {
+    let mut out: Vec<String> = Vec::new();
+    let mut code: Option<Vec<&str>> = None;
+    for line in text.lines() {
+        let fence = ["```", "~~~"].iter().any(|f| line.trim_start().starts_with(f));
+        match code.take() {
+            None => {
+                if fence {
+                    code = Some(Vec::new());
+                }
+                out.push(line.to_string());
+            }
+            Some(lines) if fence => {
+                out.push(placeholder(&lines.join("\n")));
+                out.push(line.to_string());
+            }
+            Some(mut lines) => {
+                lines.push(line);
+                code = Some(lines);
+            }
+        }
+    }
+    if let Some(lines) = code {
+        out.push(placeholder(&lines.join("\n")));
+    }
+    out.join("\n")
+}
+
+fn placeholder(code: &str) -> String {
+    format!("[redacted fnv1a:{:016x} len:{}]", crate::diff::fnv1a_64(code.as_bytes()), code.len())
+}
+
+fn digest(s: &str) -> Value {
+    json!({ "fnv1a": format!("{:016x}", crate::diff::fnv1a_64(s.as_bytes())), "len": s.len() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_keeps_structure() {
+        let mut v = json!({
+            "path": "src/lib.rs",
+            "content": "fn secret() {}",
+            "lines": 1,
+            "matches": [{ "file": "a.rs", "line": 3, "text": "let x = 1;", "display": "let x = 1;" }],
+            "suggestions": [{ "original_lines": null, "suggested_lines": ["a", "b"] }],
+        });
+        redact(&mut v);
+        assert_eq!(v["path"], "src/lib.rs");
+        assert_eq!(v["lines"], 1);
+        assert_eq!(v["matches"][0]["line"], 3);
+        assert_eq!(v["content"]["len"], 14);
+        assert_eq!(v["matches"][0]["text"], v["matches"][0]["display"]);
+        assert_eq!(v["suggestions"][0]["original_lines"], Value::Null);
+        assert_eq!(v["suggestions"][0]["suggested_lines"][1]["len"], 1);
+        assert_ne!(v["suggestions"][0]["suggested_lines"][0], v["suggestions"][0]["suggested_lines"][1]);
+    }
+
+    #[test]
+    fn test_redact_fences_in_bodies() {
+        let mut v = json!({ "body": "Compare in constant time:\n```suggestion\n    ct_eq(t, SECRET)\n```\nthen\n~~~\nleft open" });
+        redact(&mut v);
+        let body = v["body"].as_str().unwrap();
+        assert!(!body.contains("SECRET") && !body.contains("left open"), "{body}");
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!((lines[0], lines[1], lines[3], lines[4], lines[5]), ("Compare in constant time:", "```suggestion", "```", "then", "~~~"));
+        assert_eq!(lines[2], placeholder("    ct_eq(t, SECRET)"));
+        assert!(lines[6].starts_with("[redacted fnv1a:") && lines[6].ends_with(" len:9]"), "{body}");
+    }
+}
@@ -0,0 +1,392 @@
+//! `pr report`: one self-contained markdown document per PR, for release or
+//! audit records. Rendering is pure; `commands::pr_report` gathers the inputs.
+
+use crate::budget::{self, Limit};
+use crate::format::{self, details, fenced, table_cell};
+use crate::github::{PrStatus, PullRequest, ReviewComment};
+use crate::sem::SmartChange;
+use std::collections::{HashMap, HashSet};
+
+/// Everything the report shows, fetched up front
+pub struct ReportInput<'a> {
+    pub repo: &'a str,
+    pub pr: &'a PullRequest,
+    pub status: &'a PrStatus,
+    /// Smart analysis recorded for the PR's head, if any
+    pub smart: Option<&'a [SmartChange]>,
+    pub comments: &'a [ReviewComment],
+    /// Thread ids still unresolved
+    pub unresolved: &'a HashSet<u64>,
+    /// Embed each file's patch, cut at this many bytes; None leaves diffs out
+    pub diff_cap: Option<usize>,
+}
+
+pub fn render(input: &ReportInput) -> String {
+    let pr = input.pr;
+    let threads = format::group_threads(input.comments);
+    let mut by_file: HashMap<&str, Vec<&Vec<&ReviewComment>>> = HashMap::new();
+    for thread in &threads {
+        by_file.entry(thread[0].path.as_str()).or_default().push(thread);
+    }
+    let anchors = file_anchors(pr.files.iter().map(|f| f.filename.as_str()));
+
+    let mut out = vec![format!("# {} #{}: {}", input.repo, pr.number, pr.title), String::new()];
+    out.extend(summary(input));
+
+    out.push("\n## Files\n".to_string());
+    out.push("| File | Status | + | − | Threads |".to_string());
+    out.push("|---|---|---:|---:|---|".to_string());
+    for f in &pr.files {
+        let file_threads = by_file.get(f.filename.as_str()).map_or(&[][..], Vec::as_slice);
+        out.push(format!(
+            "| [{}](#{}) | {} | {} | {} | {} |",
+            table_cell(&f.filename),
+            anchors[f.filename.as_str()],
+            f.status,
+            f.additions,
+            f.deletions,
+            thread_counts(file_threads, input.unresolved),
+        ));
+    }
+
+    out.push("\n## Smart analysis\n".to_string());
+    match input.smart {
+        Some([]) => out.push("No semantic changes found.".to_string()),
+        Some(changes) => {
+            out.push("| Category | Entity | File |".to_string());
+            out.push("|---|---|---|".to_string());
+            for c in changes {
+                let entity = match &c.parent {
+                    Some(parent) => format!("{parent} › {} {}", c.entity_type, c.entity_name),
+                    None => format!("{} {}", c.entity_type, c.entity_name),
+                };
+                out.push(format!(
+                    "| {} | `{}` | {} |",
+                    c.category.replace('_', " "),
+                    table_cell(&entity),
+                    table_cell(&c.file)
+                ));
+            }
+        }
+        None => out.push(format!(
+            "Not recorded for this head; run `gh-agent pr view --repo {} {} --smart` first to include it.",
+            input.repo, pr.number
+        )),
+    }
+
+    out.push("\n## Checks\n".to_string());
+    out.extend(checks(input.status));
+
+    out.push("\n## Review threads by file".to_string());
+    for f in &pr.files {
+        out.push(format!("\n<a id=\"{}\"></a>\n### {}\n", anchors[f.filename.as_str()], f.filename));
+        match by_file.get(f.filename.as_str()) {
+            Some(file_threads) => {
+                let rendered: Vec<String> = file_threads.iter().map(|t| thread(t, input.unresolved)).collect();
+                out.push(rendered.join("\n\n"));
+            }
+            None => out.push("No review threads.".to_string()),
+        }
+        if let (Some(cap), Some(patch)) = (input.diff_cap, f.patch.as_deref()) {
+            let cut = budget::truncate(patch, Limit::Bytes(cap));
+            let mut body = fenced("diff", cut.kept.trim_end_matches('\n'));
+            if cut.is_truncated() {
+                body.push_str(&format!("\n\n_{} more bytes not shown._", cut.omitted.len()));
+            }
+            out.push(String::new());
+            out.push(details("Diff", &body));
+        }
+    }
+
+    // Threads on files no longer in the PR (e.g. reverted changes)
+    let orphaned: Vec<_> = threads
+        .iter()
+        .filter(|t| !anchors.contains_key(t[0].path.as_str()))
+        .collect();
+    if !orphaned.is_empty() {
+        out.push("\n### Files no longer changed\n".to_string());
+        let rendered: Vec<String> = orphaned
+            .iter()
+            .map(|t| format!("`{}`\n\n{}", t[0].path, thread(t, input.unresolved)))
+            .collect();
+        out.push(rendered.join("\n\n"));
+    }
+
+    out.join("\n") + "\n"
+}
+
+fn summary(input: &ReportInput) -> Vec<String> {
+    let (pr, status) = (input.pr, input.status);
+    let draft = if status.is_draft { " (draft)" } else { "" };
+    vec![
+        format!("- **State:** {}{draft}", pr.state),
+        format!("- **Branches:** `{}` ← `{}`", pr.base_ref, pr.head_ref),
+        format!("- **Head:** `{}`", pr.head_sha),
+        format!("- **Changes:** +{} −{} in {} files", pr.additions, pr.deletions, pr.changed_files),
+        format!("- **Review decision:** {}", status.review_decision.as_deref().unwrap_or("none required")),
+        format!("- **Mergeable:** {} ({})", status.mergeable, status.merge_state_status),
+    ]
+}
+
+fn checks(status: &PrStatus) -> Vec<String> {
+    if status.checks.is_empty() {
+        return vec!["No checks reported.".to_string()];
+    }
+    let count = |outcome: &str| status.checks.iter().filter(|c| format::check_outcome(c) == outcome).count();
+    let mut out = vec![
+        format!(
+            "**Rollup:** {} · {} passed, {} failed, {} pending",
+            status.checks_state.as_deref().unwrap_or("UNKNOWN"),
+            count("pass"),
+            count("fail"),
+            count("pending")
+        ),
+        String::new(),
+        "| Check | Result |".to_string(),
+        "|---|---|".to_string(),
+    ];
+    for c in &status.checks {
+        let name = match &c.details_url {
+            Some(url) => format!("[{}]({url})", table_cell(&c.name)),
+            None => table_cell(&c.name),
+        };
+        out.push(format!("| {name} | {} |", format::check_outcome(c)));
+    }
+    out
+}
+
+fn thread_counts(threads: &[&Vec<&ReviewComment>], unresolved: &HashSet<u64>) -> String {
+    if threads.is_empty() {
+        return String::new();
+    }
+    let open = threads.iter().filter(|t| unresolved.contains(&t[0].thread_id)).count();
+    format!("{open} open / {} resolved", threads.len() - open)
+}
+
+/// A thread as a heading line plus its comments quoted below
+fn thread(thread: &[&ReviewComment], unresolved: &HashSet<u64>) -> String {
+    let first = thread[0];
+    let state = if unresolved.contains(&first.thread_id) { "open" } else { "resolved" };
+    let outdated = if first.outdated { ", outdated" } else { "" };
+    let line = first.line.map_or("file".to_string(), |l| format!("Line {l}"));
+    let mut out = vec![format!("**{line}** — {state}{outdated}")];
+    for c in thread {
+        out.push(String::new());
+        out.push(format!("> **@{}** ({})", c.author, c.created_at));
+        out.push(">".to_string());
+        out.extend(c.body.lines().map(|l| format!("> {l}").trim_end().to_string()));
+    }
+    out.join("\n")
+}
+
+/// Stable, unique `file-...` anchor ids for each path
+fn file_anchors<'a>(paths: impl Iterator<Item = &'a str>) -> HashMap<&'a str, String> {
+    let mut used: HashSet<String> = HashSet::new();
+    let mut anchors = HashMap::new();
+    for path in paths {
+        let slug: String = path
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+        let base = format!("file-{slug}");
+        let mut anchor = base.clone();
+        let mut n = 2;
+        while !used.insert(anchor.clone()) {
+            anchor = format!("{base}-{n}");
+            n += 1;
+        }
+        anchors.insert(path, anchor);
+    }
+    anchors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::{CheckRun, PrFile};
+
+    const EXPECTED: &str = r#"# o/r #12: Add retries
+
+- **State:** OPEN
+- **Branches:** `main` ← `retries`
+- **Head:** `abc1234def`
+- **Changes:** +7 −2 in 2 files
+- **Review decision:** CHANGES_REQUESTED
+- **Mergeable:** MERGEABLE (BLOCKED)
+
+## Files
+
+| File | Status | + | − | Threads |
+|---|---|---:|---:|---|
+| [src/retry.rs](#file-src-retry-rs) | modified | 5 | 2 | 1 open / 1 resolved |
+| [src/retry_rs](#file-src-retry-rs-2) | added | 2 | 0 |  |
+
+## Smart analysis
+
+| Category | Entity | File |
+|---|---|---|
+| behavioral | `impl Client › function send` | src/retry.rs |
+
+## Checks
+
+**Rollup:** FAILURE · 1 passed, 1 failed, 0 pending
+
+| Check | Result |
+|---|---|
+| [lint](https://ci/1) | pass |
+| test | fail |
+
+## Review threads by file
+
+<a id="file-src-retry-rs"></a>
+### src/retry.rs
+
+**Line 10** — open
+
+> **@alice** (2024-05-01T10:01:00Z)
+>
+> Should this back off?
+>
+> Jitter too.
+
+> **@bob** (2024-05-01T10:02:00Z)
+>
+> Done.
+
+**Line 20** — resolved, outdated
+
+> **@alice** (2024-05-01T10:03:00Z)
+>
+> Typo
+
+<details>
+<summary>Diff</summary>
+
+```diff
+@@ -1,3 +1,4 @@
+ fn send() {
+-    once();
++    retry();
+```
+
+_12 more bytes not shown._
+
+</details>
+
+<a id="file-src-retry-rs-2"></a>
+### src/retry_rs
+
+No review threads.
+"#;
+
+    #[test]
+    fn test_report_snapshot() {
+        let file = |name: &str, status: &str, additions, deletions, patch: Option<&str>| PrFile {
+            filename: name.to_string(),
+            status: status.to_string(),
+            additions,
+            deletions,
+            patch: patch.map(str::to_string),
+            ..Default::default()
+        };
+        let pr = PullRequest {
+            node_id: "PR_1".to_string(),
+            number: 12,
+            title: "Add retries".to_string(),
+            body: None,
+            state: "OPEN".to_string(),
+            additions: 7,
+            deletions: 2,
+            changed_files: 2,
+            is_draft: false,
+            head_ref: "retries".to_string(),
+            base_ref: "main".to_string(),
+            head_sha: "abc1234def".to_string(),
+            base_sha: "def".to_string(),
+            is_cross_repository: false,
+            files: vec![
+                file(
+                    "src/retry.rs",
+                    "modified",
+                    5,
+                    2,
+                    Some("@@ -1,3 +1,4 @@\n fn send() {\n-    once();\n+    retry();\n+    log();\n"),
+                ),
+                file("src/retry_rs", "added", 2, 0, None),
+            ],
+        };
+        let status = PrStatus {
+            number: 12,
+            title: pr.title.clone(),
+            state: "OPEN".to_string(),
+            is_draft: false,
+            mergeable: "MERGEABLE".to_string(),
+            merge_state_status: "BLOCKED".to_string(),
+            review_decision: Some("CHANGES_REQUESTED".to_string()),
+            requested_reviewers: vec![],
+            head_ref: pr.head_ref.clone(),
+            head_sha: pr.head_sha.clone(),
+            is_cross_repository: false,
+            checks_state: Some("FAILURE".to_string()),
+            checks: vec![
+                CheckRun {
+                    name: "lint".to_string(),
+                    status: "completed".to_string(),
+                    conclusion: Some("success".to_string()),
+                    details_url: Some("https://ci/1".to_string()),
+                },
+                CheckRun {
+                    name: "test".to_string(),
+                    status: "completed".to_string(),
+                    conclusion: Some("failure".to_string()),
+                    details_url: None,
+                },
+            ],
+        };
+        let smart = [SmartChange {
+            category: "behavioral".to_string(),
+            change_type: "modified".to_string(),
+            entity_type: "function".to_string(),
+            entity_name: "send".to_string(),
+            parent: Some("impl Client".to_string()),
+            file: "src/retry.rs".to_string(),
+        }];
+        let comment = |id: u64, thread_id: u64, line: u64, author: &str, body: &str, outdated: bool| ReviewComment {
+            id,
+            thread_id,
+            path: "src/retry.rs".to_string(),
+            line: Some(line),
+            start_line: None,
+            commit_id: "abc".to_string(),
+            outdated,
+            author: author.to_string(),
+            created_at: format!("2024-05-01T10:0{id}:00Z"),
+            body: body.to_string(),
+        };
+        let comments = [
+            comment(1, 1, 10, "alice", "Should this back off?\n\nJitter too.", false),
+            comment(2, 1, 10, "bob", "Done.", false),
+            comment(3, 3, 20, "alice", "Typo", true),
+        ];
+        let unresolved = HashSet::from([1]);
+
+        let report = render(&ReportInput {
+            repo: "o/r",
+            pr: &pr,
+            status: &status,
+            smart: Some(&smart),
+            comments: &comments,
+            unresolved: &unresolved,
+            diff_cap: Some(58),
+        });
+        assert_eq!(report, EXPECTED);
+    }
+
+    #[test]
+    fn test_file_anchors_are_unique() {
+        let anchors = file_anchors(["a/b.rs", "a-b.rs", "a_b.rs"].into_iter());
+        assert_eq!(anchors["a/b.rs"], "file-a-b-rs");
+        assert_eq!(anchors["a-b.rs"], "file-a-b-rs-2");
+        assert_eq!(anchors["a_b.rs"], "file-a-b-rs-3");
+    }
+}
@@ -0,0 +1,440 @@
+//! Strict, offline checks of a `pr review` comments file (`--validate-only`),
+//! and the first stage of reading one for posting (`entries`). The file is walked
+//! as a `serde_json::Value` rather than deserialized, so every problem is
+//! collected at once, each under a path like `comments[3].line`.
+
+use crate::severity;
+use serde_json::{Map, Value};
+
+const TOP_LEVEL_FIELDS: &[&str] = &["body", "event", "comments"];
+const COMMENT_FIELDS: &[&str] = &[
+    "path",
+    "line",
+    "body",
+    "start_line",
+    "side",
+    "hunk",
+    "include_context",
+    "suggestion",
+    "severity",
+];
+
+/// What the review does besides commenting
+pub const EVENTS: &[&str] = &["COMMENT", "APPROVE", "REQUEST_CHANGES"];
+/// Which side of the diff a comment's lines are on: LEFT is the base, RIGHT the head
+pub const SIDES: &[&str] = &["LEFT", "RIGHT"];
+
+/// A comments file taken apart for `pr review`: each comment object is returned
+/// with where it sits (`comments[3]`), to be deserialized on its own so problems
+/// are reported per entry. Unknown fields are warnings here, not problems.
+pub struct Entries {
+    pub body: Option<String>,
+    pub event: Option<String>,
+    pub comments: Vec<(String, Value)>,
+    pub warnings: Vec<String>,
+}
+
+/// Split the file into its entries; Err holds every problem that stops that
+pub fn entries(raw: &str) -> Result<Entries, Vec<String>> {
+    let root: Value = serde_json::from_str(raw)
+        .map_err(|e| vec![format!("invalid JSON at line {} column {}: {}", e.line(), e.column(), strip_position(&e))])?;
+    let mut problems = Vec::new();
+    let mut out = Entries {
+        body: None,
+        event: None,
+        comments: vec![],
+        warnings: vec![],
+    };
+    let comments: Vec<(String, Value)> = match root {
+        // A bare array is just the comments, with the default body
+        Value::Array(comments) => comments.into_iter().enumerate().map(|(i, c)| (format!("[{i}]"), c)).collect(),
+        Value::Object(mut root) => {
+            out.warnings.extend(ignored_fields(&root, TOP_LEVEL_FIELDS, ""));
+            if let Some(body) = root.get("body") {
+                out.body = expect_string(body, "body", &mut problems).map(str::to_string);
+            }
+            if let Some(event) = root.get("event") {
+                out.event = expect_one_of(event, EVENTS, "event", &mut problems).map(str::to_string);
+            }
+            match root.remove("comments") {
+                None => {
+                    problems.push("comments: missing".to_string());
+                    vec![]
+                }
+                Some(Value::Array(comments)) => {
+                    comments.into_iter().enumerate().map(|(i, c)| (format!("comments[{i}]"), c)).collect()
+                }
+                Some(other) => {
+                    problems.push(format!("comments: expected array, got {}", describe(&other)));
+                    vec![]
+                }
+            }
+        }
+        other => return Err(vec![format!("(root): expected object or array, got {}", describe(&other))]),
+    };
+    for (at, c) in comments {
+        match c.as_object() {
+            Some(obj) => {
+                out.warnings.extend(ignored_fields(obj, COMMENT_FIELDS, &format!("{at}.")));
+                out.comments.push((at, c));
+            }
+            None => problems.push(format!("{at}: expected object, got {}", describe(&c))),
+        }
+    }
+    if problems.is_empty() {
+        Ok(out)
+    } else {
+        Err(problems)
+    }
+}
+
+fn ignored_fields(obj: &Map<String, Value>, known: &[&str], prefix: &str) -> Vec<String> {
+    obj.keys()
+        .filter(|k| !known.contains(&k.as_str()))
+        .map(|k| format!("{prefix}{k}: unknown field, ignored"))
+        .collect()
+}
+
+/// Path as GitHub expects it: relative to the repo root, without a leading `./`
+pub fn normalize_path(path: &str) -> &str {
+    let mut path = path;
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path
+}
+
+/// Every problem in the file; empty means it would be accepted
+pub fn validate(raw: &str) -> Vec<String> {
+    let root: Value = match serde_json::from_str(raw) {
+        Ok(v) => v,
+        Err(e) => return vec![format!("invalid JSON at line {} column {}: {}", e.line(), e.column(), strip_position(&e))],
+    };
+    // A bare array is just the comments, with the default body
+    if let Some(comments) = root.as_array() {
+        let mut problems = Vec::new();
+        for (i, c) in comments.iter().enumerate() {
+            validate_comment(c, &format!("[{i}]"), &mut problems);
+        }
+        return problems;
+    }
+    let Some(root) = root.as_object() else {
+        return vec![format!("(root): expected object or array, got {}", describe(&root))];
+    };
+
+    let mut problems = unknown_fields(root, TOP_LEVEL_FIELDS, "");
+    if let Some(body) = root.get("body") {
+        expect_string(body, "body", &mut problems);
+    }
+    if let Some(event) = root.get("event") {
+        expect_one_of(event, EVENTS, "event", &mut problems);
+    }
+    match root.get("comments") {
+        None => problems.push("comments: missing".to_string()),
+        Some(Value::Array(comments)) => {
+            for (i, c) in comments.iter().enumerate() {
+                validate_comment(c, &format!("comments[{i}]"), &mut problems);
+            }
+        }
+        Some(other) => problems.push(format!("comments: expected array, got {}", describe(other))),
+    }
+    problems
+}
+
+fn validate_comment(c: &Value, at: &str, problems: &mut Vec<String>) {
+    let Some(c) = c.as_object() else {
+        problems.push(format!("{at}: expected object, got {}", describe(c)));
+        return;
+    };
+    problems.extend(unknown_fields(c, COMMENT_FIELDS, &format!("{at}.")));
+
+    // A suggestion can stand alone; otherwise the body is the comment
+    let has_suggestion = match c.get("suggestion") {
+        None => false,
+        Some(s) => expect_string(s, &format!("{at}.suggestion"), problems).is_some(),
+    };
+    match c.get("body") {
+        None if !has_suggestion => problems.push(format!("{at}.body: missing (needed unless suggestion is given)")),
+        None => {}
+        Some(body) => {
+            let empty = expect_string(body, &format!("{at}.body"), problems).is_some_and(|b| b.trim().is_empty());
+            if empty && !has_suggestion {
+                problems.push(format!("{at}.body: must not be empty"));
+            }
+        }
+    }
+
+    let path = c.get("path").and_then(|p| expect_string(p, &format!("{at}.path"), problems));
+    if let Some(path) = path {
+        let normalized = normalize_path(path);
+        if normalized.is_empty() {
+            problems.push(format!("{at}.path: must not be empty"));
+        } else if normalized.starts_with('/') {
+            problems.push(format!("{at}.path: must be relative to the repo root, got \"{path}\""));
+        }
+    }
+    let hunk = c.get("hunk").and_then(|h| expect_string(h, &format!("{at}.hunk"), problems));
+    let line = c.get("line").and_then(|l| expect_line(l, &format!("{at}.line"), problems));
+    let start_line = c.get("start_line").and_then(|l| expect_line(l, &format!("{at}.start_line"), problems));
+    if let Some(flag) = c.get("include_context").filter(|v| !v.is_boolean()) {
+        problems.push(format!("{at}.include_context: expected boolean, got {}", describe(flag)));
+    }
+    let side = c.get("side").and_then(|s| expect_one_of(s, SIDES, &format!("{at}.side"), problems));
+    if side == Some("LEFT") {
+        if has_suggestion {
+            problems.push(format!("{at}.side: a suggestion replaces head lines, so it can't be on LEFT"));
+        }
+        if c.contains_key("hunk") {
+            problems.push(format!("{at}.side: LEFT needs path and line, not a hunk"));
+        }
+    }
+    if let Some(severity) = c.get("severity") {
+        let name = expect_string(severity, &format!("{at}.severity"), problems);
+        if let Some(name) = name.filter(|n| severity::Severity::parse(n).is_none()) {
+            problems.push(format!("{at}.severity: unknown \"{name}\" (expected one of {})", severity::NAMES.join(", ")));
+        }
+    }
+
+    if hunk.is_none() {
+        if !c.contains_key("path") {
+            problems.push(format!("{at}.path: missing (needed unless hunk is given)"));
+        }
+        if !c.contains_key("line") {
+            problems.push(format!("{at}.line: missing (needed unless hunk is given)"));
+        }
+    }
+    if let (Some(start), Some(line)) = (start_line, line) {
+        if start > line {
+            problems.push(format!("{at}.start_line: {start} is after line {line}"));
+        }
+    }
+    if start_line.is_some() && !c.contains_key("line") {
+        problems.push(format!("{at}.start_line: needs line"));
+    }
+}
+
+fn unknown_fields(obj: &Map<String, Value>, known: &[&str], prefix: &str) -> Vec<String> {
+    obj.keys()
+        .filter(|k| !known.contains(&k.as_str()))
+        .map(|k| format!("{prefix}{k}: unknown field (expected one of {})", known.join(", ")))
+        .collect()
+}
+
+fn expect_string<'a>(v: &'a Value, at: &str, problems: &mut Vec<String>) -> Option<&'a str> {
+    let s = v.as_str();
+    if s.is_none() {
+        problems.push(format!("{at}: expected string, got {}", describe(v)));
+    }
+    s
+}
+
+fn expect_one_of<'a>(v: &'a Value, allowed: &[&str], at: &str, problems: &mut Vec<String>) -> Option<&'a str> {
+    let s = expect_string(v, at, problems)?;
+    if !allowed.contains(&s) {
+        problems.push(format!("{at}: unknown \"{s}\" (expected one of {})", allowed.join(", ")));
+        return None;
+    }
+    Some(s)
+}
+
+/// A 1-based line number
+fn expect_line(v: &Value, at: &str, problems: &mut Vec<String>) -> Option<u64> {
+    match v.as_u64() {
+        Some(0) => {
+            problems.push(format!("{at}: line numbers start at 1, got 0"));
+            None
+        }
+        Some(n) => Some(n),
+        None => {
+            problems.push(format!("{at}: expected integer, got {}", describe(v)));
+            None
+        }
+    }
+}
+
+/// Type and value, e.g. `string "42"` or `number -1`
+fn describe(v: &Value) -> String {
+    match v {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => format!("boolean {b}"),
+        Value::Number(n) => format!("number {n}"),
+        Value::String(s) => format!("string {}", Value::String(s.clone())),
+        Value::Array(_) => "array".to_string(),
+        Value::Object(_) => "object".to_string(),
+    }
+}
+
+/// serde_json appends " at line L column C"; the caller reports it up front
+fn strip_position(e: &serde_json::Error) -> String {
+    let msg = e.to_string();
+    match msg.rfind(" at line ") {
+        Some(i) => msg[..i].to_string(),
+        None => msg,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_inputs() {
+        for raw in [
+            r#"{"comments": []}"#,
+            "[]",
+            r#"[{"path": "a.rs", "line": 3, "body": "nit"}]"#,
+            r#"{"body": "LGTM", "comments": [{"path": "src/a.rs", "line": 3, "body": "nit"}]}"#,
+            r#"{"comments": [{"path": "./src/a.rs", "line": 5, "start_line": 3, "body": "x", "include_context": true}]}"#,
+            r#"{"comments": [{"hunk": "a-7f3a", "body": "x"}]}"#,
+            r#"{"comments": [{"hunk": "a-7f3a", "line": 4, "body": "x"}]}"#,
+            r#"{"comments": [{"path": "a.rs", "line": 4, "suggestion": "let x = 1;"}]}"#,
+            r#"{"comments": [{"path": "a.rs", "line": 4, "start_line": 2, "body": "", "suggestion": ""}]}"#,
+        ] {
+            assert_eq!(validate(raw), Vec::<String>::new(), "{raw}");
+        }
+    }
+
+    #[test]
+    fn test_malformed_inputs() {
+        let cases: &[(&str, &[&str])] = &[
+            (r#"{"comments": [}"#, &["invalid JSON at line 1 column 15: expected value"]),
+            ("42", &["(root): expected object or array, got number 42"]),
+            (r#"[{"path": "a.rs", "body": "x"}]"#, &["[0].line: missing (needed unless hunk is given)"]),
+            ("{}", &["comments: missing"]),
+            (r#"{"comments": {}}"#, &["comments: expected array, got object"]),
+            (r#"{"body": 1, "comments": []}"#, &["body: expected string, got number 1"]),
+            (
+                r#"{"comments": [], "verdict": "APPROVE"}"#,
+                &["verdict: unknown field (expected one of body, event, comments)"],
+            ),
+            (
+                r#"{"comments": [], "event": "MERGE"}"#,
+                &["event: unknown \"MERGE\" (expected one of COMMENT, APPROVE, REQUEST_CHANGES)"],
+            ),
+            (r#"{"comments": ["nit"]}"#, &["comments[0]: expected object, got string \"nit\""]),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": "42", "body": "x"}]}"#,
+                &["comments[0].line: expected integer, got string \"42\""],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 4.5, "body": "x"}]}"#,
+                &["comments[0].line: expected integer, got number 4.5"],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": -1, "body": "x"}]}"#,
+                &["comments[0].line: expected integer, got number -1"],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 0, "body": "x"}]}"#,
+                &["comments[0].line: line numbers start at 1, got 0"],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 1}]}"#,
+                &["comments[0].body: missing (needed unless suggestion is given)"],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 1, "suggestion": ["x"]}]}"#,
+                &[
+                    "comments[0].suggestion: expected string, got array",
+                    "comments[0].body: missing (needed unless suggestion is given)",
+                ],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 1, "body": "  "}]}"#,
+                &["comments[0].body: must not be empty"],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 1, "body": null}]}"#,
+                &["comments[0].body: expected string, got null"],
+            ),
+            (
+                r#"{"comments": [{"body": "x"}]}"#,
+                &[
+                    "comments[0].path: missing (needed unless hunk is given)",
+                    "comments[0].line: missing (needed unless hunk is given)",
+                ],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 3, "start_line": 5, "body": "x"}]}"#,
+                &["comments[0].start_line: 5 is after line 3"],
+            ),
+            (
+                r#"{"comments": [{"hunk": "a-1", "start_line": 5, "body": "x"}]}"#,
+                &["comments[0].start_line: needs line"],
+            ),
+            (
+                r#"{"comments": [{"path": "/src/a.rs", "line": 1, "body": "x"}]}"#,
+                &["comments[0].path: must be relative to the repo root, got \"/src/a.rs\""],
+            ),
+            (r#"{"comments": [{"path": "./", "line": 1, "body": "x"}]}"#, &["comments[0].path: must not be empty"]),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 1, "body": "x", "include_context": "yes"}]}"#,
+                &["comments[0].include_context: expected boolean, got string \"yes\""],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 1, "body": "x", "position": 4}]}"#,
+                &["comments[0].position: unknown field (expected one of path, line, body, start_line, side, hunk, include_context, suggestion, severity)"],
+            ),
+            (
+                r#"{"comments": [{"path": "a.rs", "line": 1, "body": "x", "side": "right"}]}"#,
+                &["comments[0].side: unknown \"right\" (expected one of LEFT, RIGHT)"],
+            ),
+            (
+                r#"{"comments": [{"hunk": "a-1", "side": "LEFT", "suggestion": "x"}]}"#,
+                &[
+                    "comments[0].side: a suggestion replaces head lines, so it can't be on LEFT",
+                    "comments[0].side: LEFT needs path and line, not a hunk",
+                ],
+            ),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(validate(raw), *expected, "{raw}");
+        }
+    }
+
+    #[test]
+    fn test_reports_every_problem_at_once() {
+        let raw = r#"{"comments": [
+            {"path": "a.rs", "line": 1, "body": "fine"},
+            {"path": "a.rs", "line": "2", "body": ""},
+            {"path": 7, "line": 3, "body": "x", "severity": "high"}
+        ]}"#;
+        assert_eq!(
+            validate(raw),
+            [
+                "comments[1].body: must not be empty",
+                "comments[1].line: expected integer, got string \"2\"",
+                "comments[2].path: expected string, got number 7",
+                "comments[2].severity: unknown \"high\" (expected one of blocker, warning, nit, praise)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entries_warn_about_unknown_fields() {
+        let raw = r#"{"event": "APPROVE", "model": "x", "comments": [{"path": "a.rs", "line": 1, "body": "x", "position": 3}]}"#;
+        let parsed = entries(raw).unwrap_or_else(|p| panic!("{p:?}"));
+        assert_eq!(parsed.event.as_deref(), Some("APPROVE"));
+        assert_eq!(parsed.comments[0].0, "comments[0]");
+        assert_eq!(parsed.warnings, ["model: unknown field, ignored", "comments[0].position: unknown field, ignored"]);
+
+        let bare = entries(r#"[{"path": "a.rs"}, 4]"#).err().unwrap();
+        assert_eq!(bare, ["[1]: expected object, got number 4"]);
+        let bad = entries(r#"{"body": 1, "event": "approve", "comments": [[]]}"#).err().unwrap();
+        assert_eq!(
+            bad,
+            [
+                "body: expected string, got number 1",
+                "event: unknown \"approve\" (expected one of COMMENT, APPROVE, REQUEST_CHANGES)",
+                "comments[0]: expected object, got array",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("./src/a.rs"), "src/a.rs");
+        assert_eq!(normalize_path("././a.rs"), "a.rs");
+        assert_eq!(normalize_path("src/./a.rs"), "src/./a.rs");
+    }
+}
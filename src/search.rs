@@ -2,14 +2,30 @@ use anyhow::{Context, Result};
 use ast_grep_core::Pattern;
 use ast_grep_language::{LanguageExt, SupportLang};
 
+use crate::format::paint;
+
 /// Result of a single match
 pub struct SearchMatch {
     pub file: String,
     pub line: usize,     // 1-indexed
     pub column: usize,   // 1-indexed
     pub text: String,     // the matched line (for grep) or matched node text (for ast-grep)
+    /// Indentation standing in for whatever precedes the match on its first line
+    /// (ast-grep only; node text starts at the match)
+    pub lead: String,
     pub context_before: Vec<String>,
     pub context_after: Vec<String>,
+    /// What `--rewrite` turns the match into (ast-grep only)
+    pub rewrite: Option<Rewrite>,
+    /// Bytes of `text` the pattern matched, when that's not all of it (grep)
+    pub highlight: Option<std::ops::Range<usize>>,
+}
+
+/// A match's rewritten text, and the bytes of the file it replaces
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewrite {
+    pub span: std::ops::Range<usize>,
+    pub text: String,
 }
 
 /// Text grep across fetched file contents
@@ -28,16 +44,19 @@ pub fn grep_files(
         let lines: Vec<&str> = content.lines().collect();
         for (i, line) in lines.iter().enumerate() {
             let haystack = if case_sensitive { line.to_string() } else { line.to_lowercase() };
-            if haystack.contains(&pattern_lower) {
+            if let Some(at) = haystack.find(&pattern_lower) {
                 let start = i.saturating_sub(context_lines);
                 let end = (i + context_lines + 1).min(lines.len());
                 matches.push(SearchMatch {
                     file: filepath.clone(),
                     line: i + 1,
-                    column: haystack.find(&pattern_lower).unwrap_or(0) + 1,
+                    column: at + 1,
                     text: line.to_string(),
+                    lead: String::new(),
                     context_before: lines[start..i].iter().map(|s| s.to_string()).collect(),
                     context_after: lines[i+1..end].iter().map(|s| s.to_string()).collect(),
+                    rewrite: None,
+                    highlight: highlight(line, &haystack, at, pattern_lower.len()),
                 });
             }
         }
@@ -45,6 +64,13 @@ pub fn grep_files(
     matches
 }
 
+/// The match at byte `at` of `haystack` (`line`, maybe lowercased) as bytes of
+/// `line`, if lowercasing left the bytes where they were
+pub fn highlight(line: &str, haystack: &str, at: usize, len: usize) -> Option<std::ops::Range<usize>> {
+    let range = at..at + len;
+    (line.len() == haystack.len() && line.get(range.clone()).is_some()).then_some(range)
+}
+
 /// Infer SupportLang from file extension
 pub fn lang_from_path(path: &str) -> Option<SupportLang> {
     let ext = path.rsplit('.').next()?;
@@ -56,10 +82,12 @@ pub fn lang_from_path(path: &str) -> Option<SupportLang> {
 /// files: Vec of (filepath, content)
 /// pattern: ast-grep pattern string like "console.log($$$)"
 /// lang_override: if set, use this lang for all files; otherwise infer from extension
+/// rewrite: fix template like "logger.debug($$$)" applied to every match
 pub fn ast_grep_files(
     files: &[(String, String)],
     pattern: &str,
     lang_override: Option<SupportLang>,
+    rewrite: Option<&str>,
 ) -> Result<Vec<SearchMatch>> {
     let mut matches = Vec::new();
 
@@ -78,21 +106,31 @@ pub fn ast_grep_files(
 
         let root = lang.ast_grep(content);
         let lines: Vec<&str> = content.lines().collect();
-        let _ = &lines; // suppress unused warning if no matches
 
         for node_match in root.root().find_all(&pat) {
             let start = node_match.start_pos();
             let line_num = start.line(); // 0-indexed
             let col = start.column(&*node_match); // 0-indexed
             let matched_text = node_match.text().to_string();
+            let lead = match_lead(lines.get(line_num).copied().unwrap_or_default(), col);
+            let rewrite = rewrite.map(|template| {
+                let edit = node_match.replace_by(template);
+                Rewrite {
+                    span: edit.position..edit.position + edit.deleted_length,
+                    text: String::from_utf8_lossy(&edit.inserted_text).into_owned(),
+                }
+            });
 
             matches.push(SearchMatch {
                 file: filepath.clone(),
                 line: line_num + 1,
                 column: col + 1,
                 text: matched_text,
+                lead,
                 context_before: vec![],
                 context_after: vec![],
+                rewrite,
+                highlight: None,
             });
         }
     }
@@ -100,8 +138,134 @@ pub fn ast_grep_files(
     Ok(matches)
 }
 
-/// Format search matches for terminal output (grep-style)
-pub fn format_matches(matches: &[SearchMatch]) -> String {
+/// The lines the rewrites of `matches` (all in `content`'s file) change, as
+/// `(first, last, rewritten lines)`: one entry per match, or per set of matches
+/// sharing a line. A match overlapping an earlier one, like a call nested in a
+/// matched call, is left as is, and so is a rewrite that changes nothing.
+pub fn rewritten_lines(content: &str, matches: &[&SearchMatch]) -> Vec<(usize, usize, String)> {
+    let mut rewrites: Vec<(&SearchMatch, &Rewrite)> =
+        matches.iter().filter_map(|m| m.rewrite.as_ref().map(|r| (*m, r))).collect();
+    rewrites.sort_by_key(|(_, r)| r.span.start);
+    let mut groups: Vec<(usize, usize, Vec<&Rewrite>)> = Vec::new();
+    let mut taken = 0;
+    for (m, r) in rewrites {
+        if r.span.start < taken || r.span.end > content.len() {
+            continue;
+        }
+        taken = r.span.end;
+        match groups.last_mut() {
+            Some(group) if m.line <= group.1 => {
+                group.1 = group.1.max(end_line(m));
+                group.2.push(r);
+            }
+            _ => groups.push((m.line, end_line(m), vec![r])),
+        }
+    }
+
+    let line_starts: Vec<usize> = std::iter::once(0).chain(content.match_indices('\n').map(|(i, _)| i + 1)).collect();
+    groups
+        .into_iter()
+        .filter_map(|(first, last, rewrites)| {
+            let start = *line_starts.get(first - 1)?;
+            let end = line_starts.get(last).map_or(content.len(), |&next| next - 1);
+            let mut text = String::new();
+            let mut at = start;
+            for r in rewrites {
+                text.push_str(&content[at..r.span.start]);
+                text.push_str(&r.text);
+                at = r.span.end;
+            }
+            text.push_str(&content[at..end]);
+            // Suggestions take `\n` lines, whatever the file's line endings
+            let lines: Vec<&str> = text.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l)).collect();
+            (text != content[start..end]).then(|| (first, last, lines.join("\n")))
+        })
+        .collect()
+}
+
+/// Each match over its `--rewrite` (`-`/`+` lines, dedented like `format_match_block`)
+pub fn format_rewrites(matches: &[SearchMatch]) -> String {
+    if matches.is_empty() {
+        return "No matches found.".to_string();
+    }
+    let mut lines = Vec::new();
+    for m in matches {
+        lines.push(format!("{}:{}-{}:", m.file, m.line, end_line(m)));
+        lines.extend(dedent_match(&m.text, &m.lead).iter().map(|l| format!("  -{l}")));
+        if let Some(r) = &m.rewrite {
+            lines.extend(dedent_match(&r.text, &m.lead).iter().map(|l| format!("  +{l}")));
+        }
+    }
+    let mut files: Vec<&str> = matches.iter().map(|m| m.file.as_str()).collect();
+    files.sort();
+    files.dedup();
+    let plural = |n: usize, what: &str| format!("{n} {what}{}", if n == 1 { "" } else { "s" });
+    lines.push(format!("\n{} across {}", plural(matches.len(), "rewrite"), plural(files.len(), "file")));
+    lines.join("\n")
+}
+
+/// Lines shown of a multi-line match before it is cut off
+pub const DEFAULT_MAX_MATCH_LINES: usize = 20;
+
+/// `SearchMatch::lead` for a match starting at (0-indexed) `column` of `line`:
+/// the line's own indentation, with any code before the match blanked out
+fn match_lead(line: &str, column: usize) -> String {
+    let before: String = line.chars().take(column).collect();
+    if before.chars().all(|c| c == ' ' || c == '\t') {
+        before
+    } else {
+        " ".repeat(before.chars().count())
+    }
+}
+
+/// A multi-line match as it reads in the file, with the leading whitespace all its
+/// non-blank lines share removed. `lead` re-indents the first line (see `SearchMatch::lead`).
+pub fn dedent_match(text: &str, lead: &str) -> Vec<String> {
+    let lines: Vec<String> = text
+        .lines()
+        .enumerate()
+        .map(|(i, l)| if i == 0 { format!("{lead}{l}") } else { l.to_string() })
+        .collect();
+    let indent = crate::patch::common_indent(&lines.join("\n")).len();
+    lines
+        .into_iter()
+        .map(|l| if l.trim().is_empty() { String::new() } else { l[indent..].trim_end().to_string() })
+        .collect()
+}
+
+/// Last line a match covers
+pub fn end_line(m: &SearchMatch) -> usize {
+    m.line + m.text.lines().count().max(1) - 1
+}
+
+/// A match that spans lines, as a `file:line-end:` header over its dedented
+/// text, indented and cut at `max_lines` with a count of what was left out
+pub fn format_match_block(m: &SearchMatch, max_lines: usize) -> Vec<String> {
+    let block = dedent_match(&m.text, &m.lead);
+    let mut out = vec![format!("{}:{}-{}:", m.file, m.line, end_line(m))];
+    out.extend(
+        block
+            .iter()
+            .take(max_lines)
+            .map(|l| if l.is_empty() { String::new() } else { format!("    {l}") }),
+    );
+    if block.len() > max_lines {
+        out.push(format!("    … {} more lines", block.len() - max_lines));
+    }
+    out
+}
+
+/// A one-line match's text with the matched part in bold red (when color is on)
+fn highlight_match(m: &SearchMatch) -> String {
+    match &m.highlight {
+        Some(r) => format!("{}{}{}", &m.text[..r.start], paint("1;31", &m.text[r.clone()]), &m.text[r.end..]),
+        None => paint("1;31", &m.text),
+    }
+}
+
+/// Format search matches for terminal output (grep-style); multi-line matches
+/// become blocks (see `format_match_block`)
+pub fn format_matches(matches: &[SearchMatch], max_match_lines: usize) -> String {
     if matches.is_empty() {
         return "No matches found.".to_string();
     }
@@ -124,7 +288,11 @@ pub fn format_matches(matches: &[SearchMatch]) -> String {
         }
 
         // The match itself
-        lines.push(format!("{}:{}:{}", m.file, m.line, m.text));
+        if m.text.contains('\n') {
+            lines.extend(format_match_block(m, max_match_lines));
+        } else {
+            lines.push(format!("{}:{}:{}", m.file, m.line, highlight_match(m)));
+        }
 
         // Context after
         for (j, ctx) in m.context_after.iter().enumerate() {
@@ -144,3 +312,250 @@ pub fn format_matches(matches: &[SearchMatch]) -> String {
 
     lines.join("\n")
 }
+
+/// Qualifier keys `--search-qualifier` may use. Scope qualifiers (repo, org,
+/// user, fork) are left out: the search is always pinned to the PR's repo.
+pub const SEARCH_QUALIFIER_KEYS: &[&str] = &["path", "language", "filename", "extension", "size", "in"];
+
+/// Narrowing for repo-wide Code Search candidates
+#[derive(Debug, Default)]
+pub struct CodeSearchScope {
+    /// Path prefixes to search under; each one gets its own query
+    pub paths: Vec<String>,
+    pub exclude_paths: Vec<String>,
+    pub language: Option<String>,
+    /// Raw `key:value` or `-key:value` qualifiers
+    pub qualifiers: Vec<String>,
+}
+
+/// Which files grep/ast-grep read, and at which ref
+#[derive(Debug, Default)]
+pub struct SearchTargets {
+    /// Paths from `--files-from`, replacing the PR's changed files
+    pub files_from: Option<Vec<String>>,
+    /// Read files at this ref instead of the PR's head (or base)
+    pub git_ref: Option<String>,
+    /// Read files at the PR's base branch
+    pub base: bool,
+    /// `--exclude` globs: matching paths are left out after every other filter
+    pub exclude: Vec<String>,
+}
+
+/// Paths from a `--files-from` list: newline-separated, or NUL-separated with
+/// `nul`. Blank entries are skipped, a leading `./` is dropped, and repeats
+/// keep their first position.
+pub fn parse_path_list(raw: &str, nul: bool) -> Vec<String> {
+    let entries: Vec<&str> = if nul {
+        raw.split('\0').collect()
+    } else {
+        raw.lines().map(str::trim_end).collect()
+    };
+    let mut seen = std::collections::HashSet::new();
+    entries
+        .into_iter()
+        .map(crate::review_input::normalize_path)
+        .filter(|p| !p.is_empty() && seen.insert(*p))
+        .map(String::from)
+        .collect()
+}
+
+/// Build the Code Search queries for `text` in `repo`: one per include path, since
+/// the API ANDs repeated qualifiers. Unknown qualifier keys are rejected here
+/// rather than surfacing as a 422 from GitHub.
+pub fn code_search_queries(text: &str, repo: &str, scope: &CodeSearchScope) -> Result<Vec<String>> {
+    let mut shared = format!("{text} repo:{repo}");
+    for path in &scope.exclude_paths {
+        shared.push_str(&format!(" -path:{}", quote_qualifier(path)));
+    }
+    if let Some(language) = &scope.language {
+        shared.push_str(&format!(" language:{}", quote_qualifier(language)));
+    }
+    for qualifier in &scope.qualifiers {
+        let (key, value) = qualifier
+            .trim_start_matches('-')
+            .split_once(':')
+            .filter(|(_, value)| !value.is_empty())
+            .with_context(|| format!("Search qualifier must be key:value, got: {qualifier}"))?;
+        if !SEARCH_QUALIFIER_KEYS.contains(&key) {
+            anyhow::bail!(
+                "Unknown search qualifier `{key}` in {qualifier}; allowed: {}",
+                SEARCH_QUALIFIER_KEYS.join(", ")
+            );
+        }
+        let negate = if qualifier.starts_with('-') { "-" } else { "" };
+        shared.push_str(&format!(" {negate}{key}:{}", quote_qualifier(value)));
+    }
+
+    if scope.paths.is_empty() {
+        return Ok(vec![shared]);
+    }
+    Ok(scope
+        .paths
+        .iter()
+        .map(|path| format!("{shared} path:{}", quote_qualifier(path)))
+        .collect())
+}
+
+/// Quote a qualifier value containing whitespace so it stays one term
+fn quote_qualifier(value: &str) -> String {
+    let value = value.trim_matches('"');
+    if value.contains(char::is_whitespace) {
+        format!("\"{value}\"")
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedent_python_keeps_significant_whitespace() {
+        // A method matched inside a class: node text starts at `def`
+        let source = "class A:\n    def f(self, x):\n        if x:\n\n            return 1\n        return 2";
+        let text = &source[source.find("def").unwrap()..];
+        let lead = match_lead("    def f(self, x):", 4);
+        assert_eq!(lead, "    ");
+        assert_eq!(
+            dedent_match(text, &lead),
+            ["def f(self, x):", "    if x:", "", "        return 1", "    return 2"]
+        );
+        // Tabs are kept as they are
+        assert_eq!(dedent_match("if x:\n\t\ty()\n\tz()", "\t"), ["if x:", "\ty()", "z()"]);
+        // A tab and four spaces aren't the same indent, so neither is removed
+        assert_eq!(dedent_match("if x:\n    y()", "\t"), ["\tif x:", "    y()"]);
+        assert_eq!(dedent_match("a\n\u{2003}b", "\u{2002}"), ["\u{2002}a", "\u{2003}b"]);
+    }
+
+    #[test]
+    fn test_dedent_nested_ts_after_code_on_first_line() {
+        let line = "      const cb = () => {";
+        let text = "() => {\n        if (a) {\n          go(1);\n        }\n      }";
+        // "const cb = " before the match counts as indentation only
+        let lead = match_lead(line, line.find("()").unwrap());
+        assert_eq!(lead, " ".repeat(17));
+        assert_eq!(
+            dedent_match(text, &lead),
+            ["           () => {", "  if (a) {", "    go(1);", "  }", "}"]
+        );
+        assert_eq!(dedent_match("x", ""), ["x"]);
+    }
+
+    #[test]
+    fn test_format_match_block() {
+        let m = SearchMatch {
+            file: "a.ts".to_string(),
+            line: 10,
+            column: 3,
+            text: "f({\n    a,\n    b,\n    c,\n  })".to_string(),
+            lead: "  ".to_string(),
+            context_before: vec![],
+            context_after: vec![],
+            rewrite: None,
+            highlight: None,
+        };
+        assert_eq!(
+            format_match_block(&m, 3),
+            ["a.ts:10-14:", "    f({", "      a,", "      b,", "    … 2 more lines"]
+        );
+        assert_eq!(format_match_block(&m, 20).len(), 6);
+        assert_eq!(
+            format_matches(&[m], 2),
+            "a.ts:10-14:\n    f({\n      a,\n    … 3 more lines\n\n1 matches across 1 files"
+        );
+    }
+
+    #[test]
+    fn test_rewritten_lines_and_format_rewrites() {
+        let content = "fn f() {\n    log(a); log(c);\n    log(log(b));\n}";
+        let m_in = |content: &str, text: &str, line: usize, to: &str| {
+            let start = content.find(text).unwrap();
+            SearchMatch {
+                file: "a.rs".to_string(),
+                line,
+                column: 5,
+                text: text.to_string(),
+                lead: "    ".to_string(),
+                context_before: vec![],
+                context_after: vec![],
+                rewrite: Some(Rewrite {
+                    span: start..start + text.len(),
+                    text: to.to_string(),
+                }),
+                highlight: None,
+            }
+        };
+        let m = |text: &str, line: usize, to: &str| m_in(content, text, line, to);
+        let matches = [
+            m("log(a)", 2, "debug(a)"),
+            m("log(c)", 2, "debug(c)"),
+            m("log(log(b))", 3, "debug(log(b))"),
+            m("log(b)", 3, "debug(b)"),
+            m("}", 4, "}"),
+        ];
+        // Two matches on one line make one entry; the call nested in a rewritten one
+        // keeps its text, and the rewrite changing nothing is dropped
+        assert_eq!(
+            rewritten_lines(content, &matches.iter().collect::<Vec<_>>()),
+            [(2, 2, "    debug(a); debug(c);".to_string()), (3, 3, "    debug(log(b));".to_string())]
+        );
+        assert_eq!(
+            format_rewrites(&matches[2..3]),
+            "a.rs:3-3:\n  -log(log(b))\n  +debug(log(b))\n\n1 rewrite across 1 file"
+        );
+        assert!(format_rewrites(&matches[..2]).ends_with("\n2 rewrites across 1 file"));
+
+        // A CRLF file's carriage returns stay out of the rewritten lines
+        let crlf = "a();\r\nlog(\r\n  x);\r\nb();\r\n";
+        let call = m_in(crlf, "log(\r\n  x)", 2, "debug(\r\n  x)");
+        assert_eq!(rewritten_lines(crlf, &[&call]), [(2, 3, "debug(\n  x);".to_string())]);
+    }
+
+    #[test]
+    fn test_parse_path_list() {
+        assert_eq!(
+            parse_path_list("src/a.rs\r\n./src/b.rs\n\nsrc/a.rs\n", false),
+            ["src/a.rs", "src/b.rs"]
+        );
+        assert_eq!(parse_path_list("dir/with\nnewline.rs\0src/c.rs\0", true), ["dir/with\nnewline.rs", "src/c.rs"]);
+    }
+
+    #[test]
+    fn test_code_search_queries() {
+        let scope = CodeSearchScope {
+            paths: vec!["src/".to_string(), "lib".to_string()],
+            exclude_paths: vec!["tests".to_string()],
+            language: Some("go".to_string()),
+            qualifiers: vec!["-filename:mock.go".to_string(), "path:\"my dir\"".to_string()],
+        };
+        assert_eq!(
+            code_search_queries("Retry", "o/r", &scope).unwrap(),
+            [
+                r#"Retry repo:o/r -path:tests language:go -filename:mock.go path:"my dir" path:src/"#,
+                r#"Retry repo:o/r -path:tests language:go -filename:mock.go path:"my dir" path:lib"#,
+            ]
+        );
+        assert_eq!(
+            code_search_queries("Retry", "o/r", &CodeSearchScope::default()).unwrap(),
+            ["Retry repo:o/r"]
+        );
+    }
+
+    #[test]
+    fn test_code_search_rejects_unknown_qualifiers() {
+        let scope = |q: &str| CodeSearchScope {
+            qualifiers: vec![q.to_string()],
+            ..Default::default()
+        };
+        let err = code_search_queries("x", "o/r", &scope("org:evil")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown search qualifier `org` in org:evil; allowed: path, language, filename, extension, size, in"
+        );
+        assert!(code_search_queries("x", "o/r", &scope("-repo:o/other")).is_err());
+        assert!(code_search_queries("x", "o/r", &scope("language")).is_err());
+        assert!(code_search_queries("x", "o/r", &scope("extension:")).is_err());
+        assert!(code_search_queries("x", "o/r", &scope("size:>1000")).is_ok());
+    }
+}
@@ -0,0 +1,213 @@
+//! `pr suggest --sed`: a sed-style `s/PATTERN/REPLACEMENT/FLAGS` substitution,
+//! applied to one line at a time.
+//!
+//! PATTERN is a `regex` crate regex. In REPLACEMENT, `\1`..`\9` insert a capture
+//! group and `&` the whole match; `\&`, `\\` and a backslashed delimiter are
+//! literal. FLAGS: `g` replaces every match on a line instead of the first, `i`
+//! ignores case. Any character other than a letter, digit, backslash or whitespace
+//! can stand in for `/`.
+
+use anyhow::Result;
+use regex::{Captures, Regex, RegexBuilder};
+
+#[derive(Debug)]
+pub struct Substitution {
+    regex: Regex,
+    replacement: Vec<Part>,
+    global: bool,
+}
+
+#[derive(Debug)]
+enum Part {
+    Literal(String),
+    Group(usize),
+}
+
+impl Substitution {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let usage = || anyhow::anyhow!("Invalid --sed {expr:?}: expected s/PATTERN/REPLACEMENT/[FLAGS]");
+        let mut chars = expr.chars();
+        if chars.next() != Some('s') {
+            return Err(usage());
+        }
+        let delim = chars.next().ok_or_else(usage)?;
+        if delim.is_alphanumeric() || delim == '\\' || delim.is_whitespace() {
+            return Err(usage());
+        }
+        let rest = chars.as_str();
+        let (pattern, rest) = split_at_delim(rest, delim).ok_or_else(usage)?;
+        let (replacement, flags) = split_at_delim(rest, delim).ok_or_else(usage)?;
+
+        let mut global = false;
+        let mut builder = RegexBuilder::new(&unescape_delim(pattern, delim));
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                _ => anyhow::bail!("Invalid --sed {expr:?}: unknown flag {flag:?} (expected g or i)"),
+            }
+        }
+        let regex = builder.build().map_err(|e| anyhow::anyhow!("Invalid --sed pattern {pattern:?}: {e}"))?;
+        let replacement = parse_replacement(replacement, delim);
+        let groups = regex.captures_len() - 1;
+        if let Some(n) = replacement.iter().find_map(|p| match p {
+            Part::Group(n) if *n > groups => Some(*n),
+            _ => None,
+        }) {
+            let s = if groups == 1 { "" } else { "s" };
+            anyhow::bail!("Invalid --sed {expr:?}: \\{n} refers to group {n}, but the pattern has {groups} group{s}");
+        }
+        Ok(Substitution {
+            regex,
+            replacement,
+            global,
+        })
+    }
+
+    /// `line` with the substitution made, or None when the pattern doesn't match it
+    pub fn apply(&self, line: &str) -> Option<String> {
+        let mut out = String::new();
+        let mut last = 0;
+        let mut matched = false;
+        for caps in self.regex.captures_iter(line) {
+            let whole = caps.get(0).expect("group 0 is the whole match");
+            out.push_str(&line[last..whole.start()]);
+            self.expand(&caps, &mut out);
+            last = whole.end();
+            matched = true;
+            if !self.global {
+                break;
+            }
+        }
+        if !matched {
+            return None;
+        }
+        out.push_str(&line[last..]);
+        Some(out)
+    }
+
+    fn expand(&self, caps: &Captures, out: &mut String) {
+        for part in &self.replacement {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Group(n) => out.push_str(caps.get(*n).map_or("", |m| m.as_str())),
+            }
+        }
+    }
+}
+
+/// `s` up to the first `delim` not escaped by a backslash, and what follows it
+fn split_at_delim(s: &str, delim: char) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == delim => return Some((&s[..i], &s[i + c.len_utf8()..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A backslashed delimiter in a pattern matches the delimiter itself (escaped again
+/// if it's a regex metacharacter); every other escape is left to the regex
+fn unescape_delim(pattern: &str, delim: char) -> String {
+    let literal = regex::escape(&delim.to_string());
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next)) if next == delim => {
+                out.push_str(&literal);
+                chars.next();
+            }
+            ('\\', Some(next)) => {
+                out.push(c);
+                out.push(next);
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn parse_replacement(s: &str, delim: char) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let group = match c {
+            '&' => Some(0),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => Some(d as usize - '0' as usize),
+                Some(next) if next == delim || next == '&' || next == '\\' => {
+                    literal.push(next);
+                    None
+                }
+                Some(next) => {
+                    literal.push('\\');
+                    literal.push(next);
+                    None
+                }
+                None => {
+                    literal.push('\\');
+                    None
+                }
+            },
+            _ => {
+                literal.push(c);
+                None
+            }
+        };
+        if let Some(n) = group {
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::Group(n));
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitution_groups_and_flags() {
+        let s = Substitution::parse(r"s/(\w+)Bar/\1_bar/").unwrap();
+        assert_eq!(s.apply("fooBar(quxBar)").as_deref(), Some("foo_bar(quxBar)"));
+        assert_eq!(s.apply("nothing here"), None);
+
+        let s = Substitution::parse(r"s/(\w+)bar/\1_bar/gi").unwrap();
+        assert_eq!(s.apply("fooBar(quxBAR)").as_deref(), Some("foo_bar(qux_bar)"));
+
+        let s = Substitution::parse(r"s|a/b|[&] \& \|\\|").unwrap();
+        assert_eq!(s.apply("x a/b y").as_deref(), Some(r"x [a/b] & |\ y"));
+
+        let s = Substitution::parse(r"s/\//::/g").unwrap();
+        assert_eq!(s.apply("a/b/c").as_deref(), Some("a::b::c"));
+    }
+
+    #[test]
+    fn test_substitution_rejects_bad_expressions() {
+        for (expr, msg) in [
+            ("y/a/b/", "expected s/PATTERN/REPLACEMENT/[FLAGS]"),
+            ("s/a/b", "expected s/PATTERN/REPLACEMENT/[FLAGS]"),
+            ("sxaxbx", "expected s/PATTERN/REPLACEMENT/[FLAGS]"),
+            ("s/a/b/q", "unknown flag 'q'"),
+            ("s/(a/b/", "Invalid --sed pattern \"(a\""),
+            (r"s/(a)/\2/", r"\2 refers to group 2, but the pattern has 1 group"),
+        ] {
+            let err = Substitution::parse(expr).unwrap_err().to_string();
+            assert!(err.contains(msg), "{expr}: {err}");
+        }
+    }
+}
@@ -3,6 +3,7 @@ use sem_core::git::types::{FileChange, FileStatus};
 use sem_core::model::change::{ChangeType, SemanticChange};
 use sem_core::parser::differ::{compute_semantic_diff, DiffResult};
 use sem_core::parser::plugins::create_default_registry;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
 // --- Smart analysis types ---
@@ -25,6 +26,21 @@ struct CategorizedChange {
     removed_tokens: Vec<String>,
     added_tokens: Vec<String>,
     value_change: Option<(String, String)>,
+    /// Enclosing class/impl/module header, e.g. "class UserRepository"
+    parent: Option<String>,
+}
+
+/// One entry of the smart review, for `pr view --smart --json` and the
+/// per-revision history `pr smart-diff` compares
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartChange {
+    /// "mechanical", "new_logic" or "behavioral"
+    pub category: String,
+    pub change_type: String,
+    pub entity_type: String,
+    pub entity_name: String,
+    pub parent: Option<String>,
+    pub file: String,
 }
 
 /// Run sem-core directly on pre-fetched file pairs (no git/CLI needed).
@@ -59,7 +75,16 @@ fn run_sem_core_git(base_ref: &str, head_ref: &str) -> Result<DiffResult> {
     use std::path::Path;
 
     let origin_base = format!("origin/{base_ref}");
-    let origin_head = format!("origin/{head_ref}");
+    // Fork heads never reach origin; `pr checkout` leaves them on a local branch
+    let origin_head = [format!("origin/{head_ref}"), head_ref.to_string()]
+        .into_iter()
+        .find(|r| {
+            std::process::Command::new("git")
+                .args(["rev-parse", "--verify", "--quiet", r])
+                .output()
+                .is_ok_and(|o| o.status.success())
+        })
+        .unwrap_or_else(|| format!("origin/{head_ref}"));
 
     let cwd = std::env::current_dir()?;
     let _git = GitBridge::open(Path::new(&cwd))
@@ -72,7 +97,7 @@ fn run_sem_core_git(base_ref: &str, head_ref: &str) -> Result<DiffResult> {
         .map_err(|e| anyhow::anyhow!("Failed to run git merge-base: {e}"))?;
     if !mb_output.status.success() {
         anyhow::bail!(
-            "Cannot find merge base between {} and {}. Try `git fetch origin` first.",
+            "Cannot find merge base between {} and {}. Try `git fetch origin` or `gh-agent pr checkout` first.",
             origin_base, origin_head
         );
     }
@@ -234,7 +259,203 @@ fn categorize_change(c: &SemanticChange) -> CategorizedChange {
         removed_tokens,
         added_tokens,
         value_change,
+        parent: None,
+    }
+}
+
+// --- Parent entities ---
+
+/// Keywords that open a container whose members sem reports individually
+const CONTAINER_KEYWORDS: &[&str] = &["class", "impl", "trait", "interface", "mod", "module", "namespace"];
+
+/// Modifiers that may precede a container keyword
+const CONTAINER_MODIFIERS: &[&str] = &["export", "default", "abstract", "declare", "pub", "pub(crate)", "unsafe"];
+
+fn indent_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// The container header if `line` opens one: "class Foo", "impl Display for Foo", ...
+fn container_header(line: &str) -> Option<String> {
+    let mut rest = line.trim();
+    while let Some(modifier) = CONTAINER_MODIFIERS.iter().find(|m| rest.starts_with(&format!("{m} "))) {
+        rest = rest[modifier.len()..].trim_start();
+    }
+    let keyword = CONTAINER_KEYWORDS.iter().find(|k| {
+        rest.strip_prefix(**k)
+            .is_some_and(|after| after.starts_with([' ', '<']))
+    })?;
+    rest = &rest[keyword.len()..];
+
+    // Skip an impl's generic parameter list, which may itself contain spaces
+    if rest.starts_with('<') {
+        let mut depth = 0;
+        let end = rest.char_indices().find_map(|(i, c)| {
+            match c {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(i + 1)
+        })?;
+        rest = &rest[end..];
+    }
+
+    // Drop the body, Python's trailing colon and base classes, and Rust where clauses
+    let mut name = rest.split('{').next().unwrap_or_default().trim();
+    name = name.strip_suffix(':').unwrap_or(name);
+    if *keyword == "class" {
+        name = name.split('(').next().unwrap_or_default();
+    }
+    for clause in [" where ", " extends ", " implements "] {
+        name = name.split(clause).next().unwrap_or_default();
+    }
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(format!("{keyword} {name}"))
+}
+
+/// Find the class/impl/module enclosing `entity` inside `file`, using indentation.
+/// None when the entity is top-level, can't be located uniquely, or sits inside
+/// something that isn't a recognizable container.
+fn derive_parent(file: &str, entity: &str) -> Option<String> {
+    let entity = entity.trim();
+    if entity.is_empty() {
+        return None;
+    }
+    let mut found = file.match_indices(entity).map(|(i, _)| i);
+    let at = found.next()?;
+    if found.next().is_some() {
+        return None;
+    }
+
+    let line_start = file[..at].rfind('\n').map_or(0, |i| i + 1);
+    let mut above = file[..line_start].lines().rev();
+    let own_indent = indent_width(&file[line_start..]);
+    if own_indent == 0 {
+        return None;
+    }
+
+    let enclosing = above.find(|l| {
+        let t = l.trim();
+        // Decorators, attributes, and the tail of a multi-line Rust header aren't the container
+        let skip = t.is_empty() || t.starts_with('@') || t.starts_with("#[") || t == "{" || t == "where";
+        !skip && indent_width(l) < own_indent
+    })?;
+    container_header(enclosing)
+}
+
+/// Fill in `parent` for each change from the file contents it came from
+fn assign_parents(
+    categorized: &mut [CategorizedChange],
+    changes: &[SemanticChange],
+    file_pairs: &[(String, String, Option<String>, Option<String>)],
+) {
+    let contents: HashMap<&str, (Option<&str>, Option<&str>)> = file_pairs
+        .iter()
+        .map(|(path, _, before, after)| (path.as_str(), (before.as_deref(), after.as_deref())))
+        .collect();
+    for (cat, change) in categorized.iter_mut().zip(changes) {
+        let Some((before, after)) = contents.get(change.file_path.as_str()) else {
+            continue;
+        };
+        cat.parent = match (after, &change.after_content, before, &change.before_content) {
+            (Some(file), Some(entity), _, _) | (_, _, Some(file), Some(entity)) => derive_parent(file, entity),
+            _ => None,
+        };
+    }
+}
+
+/// Render entries, nesting those that share a parent (two or more per file)
+/// under a header line; the rest stay flat
+fn render_grouped(
+    entries: &[&CategorizedChange],
+    flat: impl Fn(&CategorizedChange) -> String,
+    member: impl Fn(&CategorizedChange) -> String,
+) -> Vec<String> {
+    let key = |c: &CategorizedChange| c.parent.clone().map(|p| (c.file_path.clone(), p));
+    let mut sizes: HashMap<(String, String), usize> = HashMap::new();
+    for c in entries {
+        if let Some(k) = key(c) {
+            *sizes.entry(k).or_default() += 1;
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut rendered: HashSet<(String, String)> = HashSet::new();
+    for c in entries {
+        match key(c) {
+            Some(k) if sizes[&k] >= 2 => {
+                if !rendered.insert(k.clone()) {
+                    continue;
+                }
+                let members: Vec<&&CategorizedChange> =
+                    entries.iter().filter(|m| key(m).as_ref() == Some(&k)).collect();
+                let kind = if members.iter().all(|m| m.entity_type == c.entity_type) {
+                    format!("{}s", c.entity_type)
+                } else {
+                    "members".to_string()
+                };
+                lines.push(format!(
+                    "  {:<22} {} ({} {kind} changed)",
+                    short_path(&c.file_path),
+                    k.1,
+                    members.len()
+                ));
+                lines.extend(members.iter().map(|m| member(m)));
+            }
+            _ => lines.push(flat(c)),
+        }
+    }
+    lines
+}
+
+fn token_detail(c: &CategorizedChange) -> String {
+    let mut parts = Vec::new();
+    if !c.removed_tokens.is_empty() {
+        let top: Vec<&str> = c.removed_tokens.iter().take(3).map(|s| s.as_str()).collect();
+        parts.push(format!("-{}", top.join(",")));
     }
+    if !c.added_tokens.is_empty() {
+        let top: Vec<&str> = c.added_tokens.iter().take(3).map(|s| s.as_str()).collect();
+        parts.push(format!("+{}", top.join(",")));
+    }
+    parts.join(" ")
+}
+
+fn behavioral_detail(c: &CategorizedChange) -> String {
+    if let Some((old_val, new_val)) = &c.value_change {
+        format!("{} → {}", old_val, new_val)
+    } else if !c.removed_tokens.is_empty() || !c.added_tokens.is_empty() {
+        token_detail(c)
+    } else {
+        format!("sim {:.0}%", c.similarity * 100.0)
+    }
+}
+
+fn new_logic_lines(categorized: &[CategorizedChange]) -> Vec<String> {
+    let entries: Vec<&CategorizedChange> =
+        categorized.iter().filter(|c| c.category == ChangeCategory::NewLogic).collect();
+    render_grouped(
+        &entries,
+        |c| format!("  ⊕ {:<20} {} — {}", short_path(&c.file_path), c.entity_name, c.entity_type),
+        |c| format!("      ⊕ {} — {}", c.entity_name, c.entity_type),
+    )
+}
+
+fn behavioral_lines(categorized: &[CategorizedChange]) -> Vec<String> {
+    let entries: Vec<&CategorizedChange> =
+        categorized.iter().filter(|c| c.category == ChangeCategory::Behavioral).collect();
+    render_grouped(
+        &entries,
+        |c| format!("  ∆ {:<20} {:<30} {}", short_path(&c.file_path), c.entity_name, behavioral_detail(c)),
+        |c| format!("      ∆ {:<30} {}", c.entity_name, behavioral_detail(c)),
+    )
 }
 
 fn detect_patterns(changes: &[CategorizedChange]) -> Vec<(String, Vec<usize>)> {
@@ -263,8 +484,13 @@ fn short_path(path: &str) -> &str {
     path.rsplit('/').next().unwrap_or(path)
 }
 
-fn format_smart_output(changes: &[SemanticChange], file_count: usize) -> String {
-    let categorized: Vec<CategorizedChange> = changes.iter().map(categorize_change).collect();
+fn format_smart_output(
+    changes: &[SemanticChange],
+    file_count: usize,
+    file_pairs: &[(String, String, Option<String>, Option<String>)],
+) -> String {
+    let mut categorized: Vec<CategorizedChange> = changes.iter().map(categorize_change).collect();
+    assign_parents(&mut categorized, changes, file_pairs);
     let patterns = detect_patterns(&categorized);
 
     let mut grouped_indices: HashSet<usize> = HashSet::new();
@@ -296,18 +522,9 @@ fn format_smart_output(changes: &[SemanticChange], file_count: usize) -> String
         let desc = if c.change_type == "deleted" {
             format!("  {} {} {} — deleted", icon, short_path(&c.file_path), c.entity_name)
         } else if !c.removed_tokens.is_empty() || !c.added_tokens.is_empty() {
-            let mut parts = Vec::new();
-            if !c.removed_tokens.is_empty() {
-                let top: Vec<&str> = c.removed_tokens.iter().take(3).map(|s| s.as_str()).collect();
-                parts.push(format!("-{}", top.join(",")));
-            }
-            if !c.added_tokens.is_empty() {
-                let top: Vec<&str> = c.added_tokens.iter().take(3).map(|s| s.as_str()).collect();
-                parts.push(format!("+{}", top.join(",")));
-            }
             format!(
                 "  {} {:<20} {:<30} ({})",
-                icon, short_path(&c.file_path), c.entity_name, parts.join(" "),
+                icon, short_path(&c.file_path), c.entity_name, token_detail(c),
             )
         } else {
             format!(
@@ -318,39 +535,8 @@ fn format_smart_output(changes: &[SemanticChange], file_count: usize) -> String
         mechanical_lines.push(desc);
     }
 
-    let mut new_logic_lines: Vec<String> = Vec::new();
-    for c in &categorized {
-        if c.category != ChangeCategory::NewLogic { continue; }
-        new_logic_lines.push(format!(
-            "  ⊕ {:<20} {} — {}",
-            short_path(&c.file_path), c.entity_name, c.entity_type,
-        ));
-    }
-
-    let mut behavioral_lines: Vec<String> = Vec::new();
-    for c in &categorized {
-        if c.category != ChangeCategory::Behavioral { continue; }
-        let detail = if let Some((old_val, new_val)) = &c.value_change {
-            format!("{} → {}", old_val, new_val)
-        } else if !c.removed_tokens.is_empty() || !c.added_tokens.is_empty() {
-            let mut parts = Vec::new();
-            if !c.removed_tokens.is_empty() {
-                let top: Vec<&str> = c.removed_tokens.iter().take(3).map(|s| s.as_str()).collect();
-                parts.push(format!("-{}", top.join(",")));
-            }
-            if !c.added_tokens.is_empty() {
-                let top: Vec<&str> = c.added_tokens.iter().take(3).map(|s| s.as_str()).collect();
-                parts.push(format!("+{}", top.join(",")));
-            }
-            parts.join(" ")
-        } else {
-            format!("sim {:.0}%", c.similarity * 100.0)
-        };
-        behavioral_lines.push(format!(
-            "  ∆ {:<20} {:<30} {}",
-            short_path(&c.file_path), c.entity_name, detail,
-        ));
-    }
+    let new_logic_lines = new_logic_lines(&categorized);
+    let behavioral_lines = behavioral_lines(&categorized);
 
     let mut out = Vec::new();
 
@@ -389,21 +575,56 @@ fn format_smart_output(changes: &[SemanticChange], file_count: usize) -> String
     out.join("\n")
 }
 
-/// Smart review from pre-fetched file pairs (no git/CWD needed)
+/// Smart review from pre-fetched file pairs (no git/CWD needed): the rendered
+/// report, plus the same entries as data
 pub fn run_sem_smart_from_pairs(
     file_pairs: &[(String, String, Option<String>, Option<String>)],
-) -> Result<String> {
+) -> Result<(String, Vec<SmartChange>)> {
     if file_pairs.is_empty() {
-        return Ok("No files to analyze.".to_string());
+        return Ok(("No files to analyze.".to_string(), vec![]));
     }
 
     let result = run_sem_core(file_pairs);
 
     if result.changes.is_empty() {
-        return Ok("No semantic changes found.".to_string());
+        return Ok(("No semantic changes found.".to_string(), vec![]));
     }
 
-    Ok(format_smart_output(&result.changes, result.file_count))
+    Ok((
+        format_smart_output(&result.changes, result.file_count, file_pairs),
+        smart_changes(&result.changes, file_pairs),
+    ))
+}
+
+/// Smart review entries as data (categories plus derived parents)
+pub fn run_sem_smart_json(
+    file_pairs: &[(String, String, Option<String>, Option<String>)],
+) -> Vec<SmartChange> {
+    smart_changes(&run_sem_core(file_pairs).changes, file_pairs)
+}
+
+fn smart_changes(
+    changes: &[SemanticChange],
+    file_pairs: &[(String, String, Option<String>, Option<String>)],
+) -> Vec<SmartChange> {
+    let mut categorized: Vec<CategorizedChange> = changes.iter().map(categorize_change).collect();
+    assign_parents(&mut categorized, changes, file_pairs);
+    categorized
+        .into_iter()
+        .map(|c| SmartChange {
+            category: match c.category {
+                ChangeCategory::Mechanical => "mechanical",
+                ChangeCategory::NewLogic => "new_logic",
+                ChangeCategory::Behavioral => "behavioral",
+            }
+            .to_string(),
+            change_type: c.change_type,
+            entity_type: c.entity_type,
+            entity_name: c.entity_name,
+            parent: c.parent,
+            file: c.file_path,
+        })
+        .collect()
 }
 
 /// Returns deduplicated file paths for non-mechanical changes from pre-fetched pairs.
@@ -422,3 +643,120 @@ pub fn get_smart_files_from_pairs(
     files.dedup();
     Some(files)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PYTHON: &str = "\
+class UserRepository(BaseRepository):
+    def save(self, user):
+        self.db.insert(user)
+
+    @cached
+    def load(self, user_id):
+        return self.db.get(user_id)
+
+def helper():
+    def inner():
+        return 1
+    return inner
+";
+
+    const RUST: &str = "\
+impl<T: Clone> fmt::Display for Wrapper<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, \"{:?}\", self.0)
+    }
+}
+
+impl Config {
+    #[must_use]
+    pub fn load() -> Self {
+        Self::default()
+    }
+}
+";
+
+    const TS: &str = "\
+export default class UserService extends Base implements Service {
+  async save(user: User): Promise<void> {
+    await this.repo.insert(user);
+  }
+}
+";
+
+    #[test]
+    fn test_derive_parent_python() {
+        let save = "def save(self, user):\n        self.db.insert(user)";
+        assert_eq!(derive_parent(PYTHON, save).as_deref(), Some("class UserRepository"));
+        let load = "@cached\n    def load(self, user_id):\n        return self.db.get(user_id)";
+        assert_eq!(derive_parent(PYTHON, load).as_deref(), Some("class UserRepository"));
+        // Top-level, and nested in a function rather than a container
+        assert_eq!(derive_parent(PYTHON, "def helper():"), None);
+        assert_eq!(derive_parent(PYTHON, "def inner():\n        return 1"), None);
+    }
+
+    #[test]
+    fn test_derive_parent_rust() {
+        let fmt = "fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {";
+        assert_eq!(derive_parent(RUST, fmt).as_deref(), Some("impl fmt::Display for Wrapper<T>"));
+        let load = "pub fn load() -> Self {\n        Self::default()\n    }";
+        assert_eq!(derive_parent(RUST, load).as_deref(), Some("impl Config"));
+        assert_eq!(
+            container_header("impl<T: Clone> fmt::Display for Wrapper<T> {").as_deref(),
+            Some("impl fmt::Display for Wrapper<T>")
+        );
+        assert_eq!(container_header("pub trait Store: Send {").as_deref(), Some("trait Store: Send"));
+    }
+
+    #[test]
+    fn test_derive_parent_ts() {
+        let save = "async save(user: User): Promise<void> {\n    await this.repo.insert(user);\n  }";
+        assert_eq!(derive_parent(TS, save).as_deref(), Some("class UserService"));
+        // Ambiguous: the same text appears twice
+        assert_eq!(derive_parent(&format!("{TS}{TS}"), save), None);
+    }
+
+    fn change(name: &str, file: &str, parent: Option<&str>, category: ChangeCategory) -> CategorizedChange {
+        CategorizedChange {
+            category,
+            change_type: "modified".to_string(),
+            entity_type: "method".to_string(),
+            entity_name: name.to_string(),
+            file_path: file.to_string(),
+            similarity: 0.6,
+            removed_tokens: vec![],
+            added_tokens: vec![],
+            value_change: None,
+            parent: parent.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_grouping_under_parent() {
+        let repo = Some("class UserRepository");
+        let changes = [
+            change("save", "app/user.py", repo, ChangeCategory::Behavioral),
+            change("render", "app/view.py", None, ChangeCategory::Behavioral),
+            change("load", "app/user.py", repo, ChangeCategory::Behavioral),
+            change("lonely", "app/other.py", Some("class Other"), ChangeCategory::Behavioral),
+            change("create", "app/user.py", repo, ChangeCategory::NewLogic),
+        ];
+        assert_eq!(
+            behavioral_lines(&changes),
+            [
+                "  user.py                class UserRepository (2 methods changed)",
+                "      ∆ save                           sim 60%",
+                "      ∆ load                           sim 60%",
+                "  ∆ view.py              render                         sim 60%",
+                "  ∆ other.py             lonely                         sim 60%",
+            ]
+        );
+        // A single member isn't worth a header
+        assert_eq!(new_logic_lines(&changes), ["  ⊕ user.py              create — method"]);
+    }
+}
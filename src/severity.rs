@@ -0,0 +1,67 @@
+//! `"severity"` on a review comment: how much a finding matters. It's shown as
+//! a prefix on the posted comment, counted in the output, and `--min-severity`
+//! filters on it.
+
+use serde::{Deserialize, Serialize};
+
+/// Declared most severe first, so `a < b` means `a` matters more
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Blocker,
+    Warning,
+    Nit,
+    Praise,
+}
+
+pub const NAMES: &[&str] = &["blocker", "warning", "nit", "praise"];
+
+impl Severity {
+    pub fn parse(name: &str) -> Option<Severity> {
+        match name {
+            "blocker" => Some(Severity::Blocker),
+            "warning" => Some(Severity::Warning),
+            "nit" => Some(Severity::Nit),
+            "praise" => Some(Severity::Praise),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        NAMES[self as usize]
+    }
+
+    /// Put in front of the comment body
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Severity::Blocker => "🛑 blocker:",
+            Severity::Warning => "⚠️ warning:",
+            Severity::Nit => "💡 nit:",
+            Severity::Praise => "👍 praise:",
+        }
+    }
+
+    /// Kept by `--min-severity min`
+    pub fn at_least(self, min: Severity) -> bool {
+        self <= min
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_round_trip_and_order() {
+        for name in NAMES {
+            let severity = Severity::parse(name).unwrap();
+            assert_eq!(severity.name(), *name);
+            assert_eq!(serde_json::to_value(severity).unwrap(), *name);
+        }
+        assert!(Severity::Blocker.at_least(Severity::Warning));
+        assert!(Severity::Warning.at_least(Severity::Warning));
+        assert!(!Severity::Nit.at_least(Severity::Warning));
+        assert!(!Severity::Praise.at_least(Severity::Nit));
+        assert_eq!(Severity::parse("high"), None);
+    }
+}
@@ -0,0 +1,87 @@
+//! Minimal HTTP server for exercising `github::Client` against canned responses.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+pub struct MockServer {
+    pub url: String,
+    hits: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    /// Serve `handler(method, path, body) -> (status, body)` on a background thread
+    pub fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&str, &str, &str) -> (u16, String) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+
+        let counter = hits.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap_or(0);
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let body = String::from_utf8_lossy(&body).into_owned();
+
+                counter.fetch_add(1, Ordering::SeqCst);
+                let (status, response) = handler(&method, &path, &body);
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                    response.len()
+                );
+            }
+        });
+
+        Self { url, hits }
+    }
+
+    /// Number of requests served so far
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+}
+
+/// JSON body for the contents API with `content` base64-encoded
+pub fn contents_response(content: &str) -> String {
+    let encoded = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, content);
+    serde_json::json!({ "content": encoded, "encoding": "base64" }).to_string()
+}
+
+/// GraphQL body for `Client::get_pr`: an open, non-draft PR with head `head_sha` and no files
+pub fn pr_response(number: u64, head_sha: &str) -> String {
+    let pr = serde_json::json!({
+        "id": "PR_node", "number": number, "title": "t", "body": null, "state": "OPEN",
+        "additions": 0, "deletions": 0, "changedFiles": 0, "isDraft": false,
+        "headRefName": "feature", "baseRefName": "main", "headRefOid": head_sha, "baseRefOid": "base",
+        "files": { "pageInfo": { "hasNextPage": false, "endCursor": null }, "nodes": [] },
+    });
+    serde_json::json!({ "data": { "repository": { "pullRequest": pr } } }).to_string()
+}